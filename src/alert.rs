@@ -49,6 +49,7 @@ pub enum AlertDescription {
     UnknownPskIdentity = 115,
     CertificateRequired = 116,
     NoApplicationProtocol = 120,
+    EchRequired = 121,
 }
 
 impl AlertDescription {
@@ -81,6 +82,7 @@ impl AlertDescription {
             115 => Some(AlertDescription::UnknownPskIdentity),
             116 => Some(AlertDescription::CertificateRequired),
             120 => Some(AlertDescription::NoApplicationProtocol),
+            121 => Some(AlertDescription::EchRequired),
             _ => None,
         }
     }
@@ -98,6 +100,77 @@ impl Alert {
         Self { level, description }
     }
 
+    /// Whether the alert ends the connection.
+    ///
+    /// Section 6.  Alert Protocol
+    /// Closure alerts and error alerts end the connection. Other warnings, such as a
+    /// user_canceled alert which is followed by a close_notify, are ignored.
+    pub(crate) fn is_fatal(&self) -> bool {
+        matches!(
+            (self.level, self.description),
+            (AlertLevel::Fatal, _) | (_, AlertDescription::CloseNotify)
+        )
+    }
+
+    /// The alert to send to the server when the connection fails with `error`, if any.
+    ///
+    /// Section 6.2.  Error Alerts
+    /// Whenever an implementation encounters a fatal error condition, it SHOULD send an
+    /// appropriate fatal alert and MUST close the connection without sending or receiving any
+    /// additional data.
+    ///
+    /// No alert is sent when the transport failed or the server ended the connection.
+    pub(crate) fn from_error(error: &TlsError) -> Option<Self> {
+        let description = match error {
+            TlsError::AbortHandshake(level, description) => {
+                return Some(Self::new(*level, *description))
+            }
+            TlsError::InvalidRecord | TlsError::UnknownContentType | TlsError::InvalidHandshake => {
+                AlertDescription::UnexpectedMessage
+            }
+            TlsError::BadRecordMac => AlertDescription::BadRecordMac,
+            TlsError::InvalidCertificate => AlertDescription::BadCertificate,
+            TlsError::InvalidCipherSuite
+            | TlsError::InvalidSignatureScheme
+            | TlsError::InvalidKeyShare => AlertDescription::IllegalParameter,
+            TlsError::DecodeError
+            | TlsError::ParseError(_)
+            | TlsError::InvalidTicketLength
+            | TlsError::InvalidExtensionsLength
+            | TlsError::InvalidSessionIdLength
+            | TlsError::InvalidSupportedVersions
+            | TlsError::InvalidCertificateEntry
+            | TlsError::InvalidCertificateRequest => AlertDescription::DecodeError,
+            TlsError::InvalidSignature => AlertDescription::DecryptError,
+            TlsError::UnknownExtensionType => AlertDescription::UnsupportedExtension,
+            TlsError::EchRejected => AlertDescription::EchRequired,
+            TlsError::Unimplemented
+            | TlsError::InternalError
+            | TlsError::InvalidNonceLength
+            | TlsError::InsufficientSpace
+            | TlsError::InvalidApplicationData
+            | TlsError::InvalidPrivateKey
+            | TlsError::UnableToInitializeCryptoEngine
+            | TlsError::OutOfMemory
+            | TlsError::RngError
+            | TlsError::CryptoError
+            | TlsError::UnknownTime
+            | TlsError::EncodeError => AlertDescription::InternalError,
+            TlsError::ConnectionClosed
+            | TlsError::UnexpectedEof
+            | TlsError::MissingHandshake
+            | TlsError::HandshakeAborted(..)
+            | TlsError::AlertReceived(..)
+            | TlsError::IoError
+            | TlsError::Io(_)
+            | TlsError::PendingData
+            | TlsError::HeartbeatNotAllowed
+            | TlsError::RecordLimitReached
+            | TlsError::SequenceNumberOverflow => return None,
+        };
+        Some(Self::new(AlertLevel::Fatal, description))
+    }
+
     pub fn parse(buf: &mut ParseBuffer<'_>) -> Result<Alert, TlsError> {
         let level = buf.read_u8()?;
         let desc = buf.read_u8()?;
@@ -116,3 +189,38 @@ impl Alert {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_error() {
+        use crate::parse_buffer::ParseError;
+
+        let alert = Alert::from_error(&TlsError::BadRecordMac).unwrap();
+        assert!(matches!(alert.level, AlertLevel::Fatal));
+        assert!(matches!(alert.description, AlertDescription::BadRecordMac));
+
+        let alert = Alert::from_error(&TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::CertificateExpired,
+        ))
+        .unwrap();
+        assert!(matches!(
+            alert.description,
+            AlertDescription::CertificateExpired
+        ));
+
+        let alert = Alert::from_error(&TlsError::ParseError(ParseError::InvalidData)).unwrap();
+        assert!(matches!(alert.description, AlertDescription::DecodeError));
+
+        // The server already knows why the connection ended
+        assert!(Alert::from_error(&TlsError::HandshakeAborted(
+            AlertLevel::Fatal,
+            AlertDescription::HandshakeFailure
+        ))
+        .is_none());
+        assert!(Alert::from_error(&TlsError::UnexpectedEof).is_none());
+    }
+}
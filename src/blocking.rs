@@ -1,23 +1,30 @@
+use crate::alert::Alert;
 use crate::common::decrypted_buffer_info::DecryptedBufferInfo;
 use crate::common::decrypted_read_handler::DecryptedReadHandler;
 use crate::connection::*;
+use crate::crypto_provider::{CryptoProvider, RustCrypto};
+use crate::extensions::CustomExtensions;
 use crate::key_schedule::KeySchedule;
 use crate::key_schedule::{ReadKeySchedule, SharedState, WriteKeySchedule};
 use crate::read_buffer::ReadBuffer;
 use crate::record::{ClientRecord, ClientRecordHeader};
 use crate::record_reader::RecordReader;
 use crate::split::{SplitState, SplitStateContainer};
+use crate::ticket_store::TicketStoreRef;
 use crate::write_buffer::WriteBuffer;
+use core::marker::PhantomData;
 use embedded_io::blocking::BufRead;
 use embedded_io::Error as _;
 use embedded_io::{
     blocking::{Read, Write},
     Io,
 };
-use rand_core::{CryptoRng, RngCore};
 
 pub use crate::config::*;
+pub use crate::connection::{TlsParts, MIN_CLIENT_AUTH_WRITE_BUFFER_LEN};
+pub use crate::exported_authenticator::{encode_authenticator_request, CertificateChain};
 #[cfg(feature = "std")]
+pub use crate::signer::blocking::ExternalSigner;
 pub use crate::split::ManagedSplitState;
 pub use crate::split::SplitConnectionState;
 pub use crate::TlsError;
@@ -25,23 +32,35 @@ pub use crate::TlsError;
 /// Type representing a TLS connection. An instance of this type can
 /// be used to establish a TLS connection, write and read encrypted data over this connection,
 /// and closing to free up the underlying resources.
-pub struct TlsConnection<'a, Socket, CipherSuite>
+///
+/// The key exchange is performed by the [`CryptoProvider`], which defaults to [`RustCrypto`].
+pub struct TlsConnection<'a, Socket, CipherSuite, Provider = RustCrypto>
 where
     Socket: Read + Write + 'a,
     CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     delegate: Socket,
     opened: bool,
+    read_closed: bool,
+    write_closed: bool,
     key_schedule: KeySchedule<CipherSuite>,
     record_reader: RecordReader<'a, CipherSuite>,
     record_write_buf: WriteBuffer<'a>,
     decrypted: DecryptedBufferInfo,
+    ticket_store: Option<TicketStoreRef<'a, CipherSuite>>,
+    alpn_protocol: heapless::Vec<u8, MAX_ALPN_PROTOCOL_LEN>,
+    custom_extensions: CustomExtensions,
+    #[cfg(feature = "ech")]
+    ech_retry_configs: heapless::Vec<u8, crate::ech::MAX_RETRY_CONFIGS_LEN>,
+    provider: PhantomData<Provider>,
 }
 
-impl<'a, Socket, CipherSuite> TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, CipherSuite, Provider> TlsConnection<'a, Socket, CipherSuite, Provider>
 where
     Socket: Read + Write + 'a,
     CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     /// Create a new TLS connection with the provided context and a blocking I/O implementation
     ///
@@ -62,44 +81,134 @@ where
         Self {
             delegate,
             opened: false,
+            read_closed: false,
+            write_closed: false,
             key_schedule: KeySchedule::new(),
             record_reader: RecordReader::new(record_read_buf),
             record_write_buf: WriteBuffer::new(record_write_buf),
             decrypted: DecryptedBufferInfo::default(),
+            ticket_store: None,
+            alpn_protocol: heapless::Vec::new(),
+            custom_extensions: CustomExtensions::default(),
+            #[cfg(feature = "ech")]
+            ech_retry_configs: heapless::Vec::new(),
+            provider: PhantomData,
         }
     }
 
+    /// Resume sessions with tickets from a [`TicketStore`], where `server` identifies the server
+    /// of the connection.
+    ///
+    /// When the connection is opened without a ticket in its configuration, the ticket of the
+    /// server is taken from the store, and removed from it if the server rejects it. The tickets
+    /// received while reading from the connection are put in the store.
+    pub fn with_ticket_store(
+        mut self,
+        server: &'a str,
+        store: &'a mut (dyn TicketStore<CipherSuite> + Send),
+    ) -> Self {
+        self.ticket_store = Some(TicketStoreRef::new(server, store));
+        self
+    }
+
     /// Open a TLS connection, performing the handshake with the configuration provided when
     /// creating the connection instance.
     ///
     /// Returns an error if the handshake does not proceed. If an error occurs, the connection
     /// instance must be recreated.
+    ///
+    /// If the server requests client authentication, the certificate of the configuration is
+    /// sent and the CertificateVerify signature is created by the signer of the context. The
+    /// write record buffer must then be at least [`MIN_CLIENT_AUTH_WRITE_BUFFER_LEN`] bytes.
+    ///
+    /// The early data of the configuration, if any, is sent during the handshake or right after.
     pub fn open<'v, RNG, Verifier>(
         &mut self,
-        context: TlsContext<'v, CipherSuite, RNG>,
+        mut context: TlsContext<'v, CipherSuite, RNG, impl ExternalSigner, Provider::KeyExchange>,
     ) -> Result<(), TlsError>
     where
-        RNG: CryptoRng + RngCore,
+        RNG: TlsRng,
         Verifier: TlsVerifier<'v, CipherSuite>,
     {
-        let mut handshake: Handshake<CipherSuite, Verifier> =
-            Handshake::new(Verifier::new(context.config.server_name));
+        // A stored ticket is only used if the configuration has none, and does not use ECH
+        let stored_ticket = match &mut self.ticket_store {
+            Some(store)
+                if context.config.session_ticket.is_none() && !context.config.offers_ech() =>
+            {
+                store.get()
+            }
+            _ => None,
+        };
+        let resumption_config;
+        let config = match &stored_ticket {
+            Some(ticket) => {
+                resumption_config = context.config.clone().with_session_ticket(ticket);
+                &resumption_config
+            }
+            None => context.config,
+        };
+
+        check_write_buffer(context.config, &self.record_write_buf)?;
+
+        let mut handshake: Handshake<CipherSuite, Provider, Verifier> = Handshake::new(
+            Verifier::new(context.config.server_name),
+            context.key_share.take(),
+        );
+        // The server is authenticated for the public name of the ECHConfig if it rejects ECH
+        #[cfg(feature = "ech")]
+        if let Ok(Some(ech)) = context.config.ech_config() {
+            handshake.set_ech_verifier(Verifier::new(Some(ech.public_name)));
+        }
+        self.key_schedule
+            .write_state()
+            .set_record_limit(context.config.record_limit);
+        self.record_write_buf
+            .set_record_padding(context.config.record_padding);
         let mut state = State::ClientHello;
 
         while state != State::ApplicationData {
-            let next_state = state.process_blocking(
+            let result = state.process_blocking(
                 &mut self.delegate,
                 &mut handshake,
                 &mut self.record_reader,
                 &mut self.record_write_buf,
                 &mut self.key_schedule,
-                context.config,
+                config,
                 context.rng,
-            )?;
+                context.signer.as_deref_mut(),
+            );
+            let next_state = match result {
+                Ok(next_state) => next_state,
+                Err(e) => {
+                    #[cfg(feature = "ech")]
+                    {
+                        self.ech_retry_configs = handshake.take_ech_retry_configs();
+                    }
+                    return Err(self.abort(state, e));
+                }
+            };
             trace!("State {:?} -> {:?}", state, next_state);
             state = next_state;
         }
         self.opened = true;
+        self.alpn_protocol = handshake.alpn_protocol().clone();
+        self.custom_extensions = handshake.custom_extensions().clone();
+        let max_fragment_length = self.key_schedule.read_state().max_fragment_length();
+        self.record_write_buf
+            .set_max_fragment_length(max_fragment_length);
+
+        if let (Some(store), Some(_)) = (&mut self.ticket_store, &stored_ticket) {
+            if !handshake.psk_accepted() {
+                store.remove();
+            }
+        }
+
+        // Early data which was not sent, or rejected by the server, is sent as application data
+        let data = config.remaining_early_data(handshake.early_data_accepted());
+        if !data.is_empty() {
+            self.write_all(data)?;
+            self.flush()?;
+        }
 
         Ok(())
     }
@@ -113,6 +222,9 @@ where
     ///
     /// Returns the number of bytes buffered/written.
     pub fn write(&mut self, buf: &[u8]) -> Result<usize, TlsError> {
+        if self.write_closed {
+            return Err(TlsError::ConnectionClosed);
+        }
         if self.opened {
             if !self
                 .record_write_buf
@@ -146,19 +258,127 @@ where
                 .write_all(slice)
                 .map_err(|e| TlsError::Io(e.kind()))?;
 
-            key_schedule.increment_counter();
+            key_schedule.increment_counter()?;
+            update_key_if_due_blocking(
+                &mut self.delegate,
+                &mut self.record_write_buf,
+                key_schedule,
+            )?;
+
+            self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
+        }
 
+        // A KeyUpdate request left over by split halves
+        if self.key_schedule.read_state().take_key_update_request() && !self.write_closed {
+            send_key_update_blocking(
+                &mut self.delegate,
+                &mut self.record_write_buf,
+                self.key_schedule.write_state(),
+                false,
+            )?;
             self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
         }
 
         Ok(())
     }
 
+    /// Send a KeyUpdate and switch to the next traffic key, for connections which stay open long
+    /// enough to need periodic rekeying. Buffered writes are flushed first.
+    ///
+    /// If `update_requested` is set, the server is asked to update its keys as well. Its
+    /// KeyUpdate is processed while reading from the connection.
+    pub fn request_key_update(&mut self, update_requested: bool) -> Result<(), TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
+        }
+        if self.write_closed {
+            return Err(TlsError::ConnectionClosed);
+        }
+        self.flush()?;
+
+        send_key_update_blocking(
+            &mut self.delegate,
+            &mut self.record_write_buf,
+            self.key_schedule.write_state(),
+            update_requested,
+        )?;
+        self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))
+    }
+
+    /// Send a heartbeat request carrying `payload`, to keep the connection alive without
+    /// application data, see [`TlsConfig::with_heartbeat`]. Buffered writes are flushed first.
+    ///
+    /// The payload is at most [`MAX_HEARTBEAT_PAYLOAD_LEN`] bytes, and only one request may be in
+    /// flight. The response of the server is processed while reading from the connection, see
+    /// [`heartbeat_in_flight`](Self::heartbeat_in_flight).
+    pub fn send_heartbeat(&mut self, payload: &[u8]) -> Result<(), TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
+        }
+        if self.write_closed {
+            return Err(TlsError::ConnectionClosed);
+        }
+        self.flush()?;
+
+        let (write_key_schedule, read_key_schedule) = self.key_schedule.as_split();
+        let request = read_key_schedule.heartbeat().request(payload)?;
+        let mut result = send_heartbeat_blocking(
+            &mut self.delegate,
+            &mut self.record_write_buf,
+            write_key_schedule,
+            request,
+        );
+        if result.is_ok() {
+            result = self.delegate.flush().map_err(|e| TlsError::Io(e.kind()));
+        }
+        if result.is_err() {
+            self.cancel_heartbeat();
+        }
+        result
+    }
+
+    /// Whether a heartbeat request was sent and not answered by the server yet, see
+    /// [`send_heartbeat`](Self::send_heartbeat).
+    pub fn heartbeat_in_flight(&self) -> bool {
+        self.key_schedule.heartbeat_in_flight()
+    }
+
+    /// Give up on the heartbeat request in flight, for example when the server did not answer it
+    /// in time, so that another one can be sent. A late response to it is discarded.
+    ///
+    /// A request which fails to be sent is given up on by
+    /// [`send_heartbeat`](Self::send_heartbeat) already.
+    pub fn cancel_heartbeat(&mut self) {
+        self.key_schedule.read_state().heartbeat().cancel_request();
+    }
+
+    /// Answer a heartbeat request of the server.
+    fn send_heartbeat_response(&mut self, payload: &[u8]) -> Result<(), TlsError> {
+        self.flush()?;
+
+        let (write_key_schedule, read_key_schedule) = self.key_schedule.as_split();
+        let response = read_key_schedule.heartbeat().response(payload);
+        send_heartbeat_blocking(
+            &mut self.delegate,
+            &mut self.record_write_buf,
+            write_key_schedule,
+            response,
+        )?;
+        self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))
+    }
+
     fn create_read_buffer(&mut self) -> ReadBuffer {
         self.decrypted.create_read_buffer(self.record_reader.buf)
     }
 
     /// Read and decrypt data filling the provided slice.
+    ///
+    /// Post-handshake messages of the server, such as session tickets and KeyUpdates, are
+    /// processed while waiting for application data, which is returned once available.
+    ///
+    /// Returns 0 once the server has closed the connection with a close_notify alert. If the
+    /// transport ends without one, [`TlsError::UnexpectedEof`] is returned since the data may
+    /// have been truncated.
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, TlsError> {
         let mut buffer = self.read_buffered()?;
 
@@ -170,18 +390,172 @@ where
 
     /// Reads buffered data. If nothing is in memory, it'll wait for a TLS record and process it.
     pub fn read_buffered(&mut self) -> Result<ReadBuffer, TlsError> {
-        if self.opened {
-            while self.decrypted.is_empty() {
-                self.read_application_data()?;
+        if !self.opened && !self.read_closed {
+            return Err(TlsError::MissingHandshake);
+        }
+        while self.opened && self.decrypted.is_empty() {
+            match self.read_application_data() {
+                Ok(()) => {}
+                Err(TlsError::ConnectionClosed) => self.read_closed = true,
+                Err(e) => return Err(self.abort(State::ApplicationData, e)),
             }
+        }
+
+        Ok(self.create_read_buffer())
+    }
+
+    fn abort(&mut self, state: State, error: TlsError) -> TlsError {
+        if self.write_closed || Alert::from_error(&error).is_none() {
+            return error;
+        }
+        self.opened = false;
+        self.write_closed = true;
+        state.abort_blocking(
+            &mut self.delegate,
+            &mut self.record_write_buf,
+            self.key_schedule.write_state(),
+            error,
+        )
+    }
+
+    /// Take the latest session ticket received from the server, to resume the session in a
+    /// later connection with [`TlsConfig::with_session_ticket`].
+    ///
+    /// The server sends its tickets after the handshake, and they are processed while reading
+    /// from the connection. No ticket is available before data has been read.
+    pub fn take_session_ticket(&mut self) -> Option<SessionTicket<CipherSuite>> {
+        self.key_schedule.read_state().take_session_ticket()
+    }
 
-            Ok(self.create_read_buffer())
+    /// The application protocol selected by the server among the protocols offered with
+    /// [`TlsConfig::with_alpn_protocols`], if any.
+    ///
+    /// Returns `None` before the connection is opened, or if the server did not select a protocol.
+    pub fn negotiated_alpn(&self) -> Option<&[u8]> {
+        if self.alpn_protocol.is_empty() {
+            None
         } else {
-            Err(TlsError::MissingHandshake)
+            Some(&self.alpn_protocol)
+        }
+    }
+
+    /// The retry configs sent by a server which rejected Encrypted Client Hello, after
+    /// [`Self::open`] failed with [`TlsError::EchRejected`]. The connection may be attempted again
+    /// with these configs in [`TlsConfig::with_ech`].
+    ///
+    /// Returns `None` if the server did not send retry configs, in which case the connection may
+    /// be attempted again without ECH.
+    #[cfg(feature = "ech")]
+    pub fn ech_retry_configs(&self) -> Option<&[u8]> {
+        if self.ech_retry_configs.is_empty() {
+            None
+        } else {
+            Some(&self.ech_retry_configs)
+        }
+    }
+
+    /// The data of the extension of the given type which the server echoed among the extensions
+    /// of [`TlsConfig::with_custom_extensions`].
+    ///
+    /// Returns `None` before the connection is opened, or if the server did not echo the extension.
+    pub fn custom_extension(&self, extension_type: u16) -> Option<&[u8]> {
+        self.custom_extensions.get(extension_type)
+    }
+
+    /// The maximum fragment length accepted by the server, see
+    /// [`TlsConfig::with_max_fragment_length`].
+    ///
+    /// Once the connection is opened, the records sent carry at most
+    /// [`MaxFragmentLength::size`] bytes of data, and larger records of the server are rejected.
+    pub fn max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        self.record_write_buf.max_fragment_length()
+    }
+
+    /// The number of session tickets the server expects to send on this connection, if it
+    /// answered the request of [`TlsConfig::with_ticket_request`].
+    pub fn expected_session_tickets(&self) -> Option<u8> {
+        self.key_schedule.expected_session_tickets()
+    }
+
+    /// Fill `out` with keying material exported from the session (RFC 8446, Section 7.5), bound
+    /// to `label` and `context`.
+    ///
+    /// Returns [`TlsError::MissingHandshake`] before the connection is opened, or if TLS 1.2 was
+    /// negotiated.
+    pub fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
         }
+        self.key_schedule
+            .export_keying_material(label, context, out)
+    }
+
+    /// Create the exported authenticator (RFC 9261) answering `request`, the CertificateRequest
+    /// message of an authenticator request received from the server, and write it to `buf`.
+    ///
+    /// The authenticator proves the possession of the key of `identity`, and is empty if there is
+    /// no identity or if its signature scheme is not accepted by the request. Returns the length
+    /// of the authenticator.
+    pub fn create_authenticator(
+        &self,
+        request: &[u8],
+        identity: Option<&ClientIdentity<'_>>,
+        buf: &mut [u8],
+    ) -> Result<usize, TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
+        }
+        crate::exported_authenticator::create_authenticator(
+            &self.key_schedule,
+            request,
+            identity,
+            buf,
+        )
+    }
+
+    /// Validate the exported authenticator (RFC 9261) of the server answering `request`, an
+    /// authenticator request encoded with [`encode_authenticator_request`].
+    ///
+    /// Returns the certificate chain of the server, or `None` if the authenticator is empty. Only
+    /// the possession of the key of the end-entity certificate is verified, and the chain is left
+    /// to the application to validate.
+    pub fn validate_authenticator<'b>(
+        &self,
+        request: &[u8],
+        authenticator: &'b [u8],
+    ) -> Result<Option<CertificateChain<'b>>, TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
+        }
+        crate::exported_authenticator::validate_authenticator(
+            &self.key_schedule,
+            request,
+            authenticator,
+        )
     }
 
     fn read_application_data(&mut self) -> Result<(), TlsError> {
+        self.read_record()?;
+
+        if self.key_schedule.read_state().take_key_update_request() && !self.write_closed {
+            self.request_key_update(false)?;
+        }
+
+        if let Some(payload) = self.key_schedule.read_state().heartbeat().take_response() {
+            if !self.write_closed {
+                self.send_heartbeat_response(&payload)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_record(&mut self) -> Result<(), TlsError> {
         let buf_ptr_range = self.record_reader.buf.as_ptr_range();
         let key_schedule = self.key_schedule.read_state();
         let record = self
@@ -193,40 +567,121 @@ where
             buffer_info: &mut self.decrypted,
             is_open: &mut self.opened,
         };
-        decrypt_record(key_schedule, record, |_key_schedule, record| {
-            handler.handle(record)
+        decrypt_record(key_schedule, record, |key_schedule, record| {
+            handler.handle(key_schedule, record)
         })?;
 
+        if let Some(store) = &mut self.ticket_store {
+            store.stash(self.key_schedule.read_state());
+        }
+
         Ok(())
     }
 
-    fn close_internal(&mut self) -> Result<(), TlsError> {
-        self.flush()?;
+    fn close_internal(&mut self, wait_for_peer: bool) -> Result<(), TlsError> {
+        if !self.write_closed {
+            self.flush()?;
 
-        let (write_key_schedule, read_key_schedule) = self.key_schedule.as_split();
-        let slice = self.record_write_buf.write_record(
-            &ClientRecord::close_notify(self.opened),
-            write_key_schedule,
-            Some(read_key_schedule),
-        )?;
+            let (write_key_schedule, read_key_schedule) = self.key_schedule.as_split();
+            let slice = self.record_write_buf.write_record(
+                &ClientRecord::close_notify(self.opened || self.read_closed),
+                write_key_schedule,
+                Some(read_key_schedule),
+            )?;
 
-        self.delegate
-            .write_all(slice)
-            .map_err(|e| TlsError::Io(e.kind()))?;
+            self.delegate
+                .write_all(slice)
+                .map_err(|e| TlsError::Io(e.kind()))?;
 
-        self.key_schedule.write_state().increment_counter();
+            self.key_schedule.write_state().increment_counter()?;
+            self.write_closed = true;
 
-        self.flush()?;
+            self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
+        }
 
+        if wait_for_peer {
+            while self.opened {
+                match self.read_record() {
+                    Ok(()) | Err(TlsError::ConnectionClosed) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Close the write side of the connection, while the data of the server can still be read,
+    /// for example the response to an HTTP request. Buffered writes are flushed first.
+    ///
+    /// Writing to the connection afterwards returns [`TlsError::ConnectionClosed`], and the
+    /// KeyUpdate requests of the server are not answered. [`Self::close`] must still be called
+    /// to release the I/O provider.
+    pub fn close_write(&mut self) -> Result<(), TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
+        }
+        if !self.write_closed {
+            self.flush()?;
+            send_close_notify_blocking(
+                &mut self.delegate,
+                &mut self.record_write_buf,
+                self.key_schedule.write_state(),
+            )?;
+            self.write_closed = true;
+            self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
+        }
         Ok(())
     }
 
     /// Close a connection instance, returning the ownership of the I/O provider.
-    pub fn close(mut self) -> Result<Socket, (Socket, TlsError)> {
-        match self.close_internal() {
-            Ok(()) => Ok(self.delegate),
-            Err(e) => Err((self.delegate, e)),
+    pub fn close(self) -> Result<Socket, (Socket, TlsError)> {
+        self.shutdown(false)
+            .map(|parts| parts.socket)
+            .map_err(|(parts, e)| (parts.socket, e))
+    }
+
+    /// Close a connection instance by sending a close_notify alert, returning the I/O provider
+    /// and the record buffers so that they can be reused.
+    ///
+    /// If `wait_for_peer` is set, records are read until the close_notify alert of the server,
+    /// so that the transport can be reused right away. Otherwise the server may still send data
+    /// on the transport.
+    pub fn shutdown(
+        mut self,
+        wait_for_peer: bool,
+    ) -> Result<TlsParts<'a, Socket>, (TlsParts<'a, Socket>, TlsError)> {
+        let result = self.close_internal(wait_for_peer);
+        let parts = TlsParts {
+            socket: self.delegate,
+            record_read_buf: self.record_reader.buf,
+            record_write_buf: self.record_write_buf.into_buf(),
+        };
+        match result {
+            Ok(()) => Ok(parts),
+            Err(e) => Err((parts, e)),
+        }
+    }
+
+    /// Hand the established session over to another TLS implementation, such as the offload
+    /// engine of a cellular modem, returning the I/O provider together with the application traffic
+    /// secrets and record sequence numbers.
+    ///
+    /// Buffered writes are flushed first. Returns [`TlsError::PendingData`] if received data has
+    /// not been read yet, since it would be lost in the migration.
+    #[cfg(feature = "secret-export")]
+    pub fn export_secrets(
+        mut self,
+    ) -> Result<(Socket, crate::key_schedule::TrafficSecrets<CipherSuite>), TlsError> {
+        if !self.opened {
+            return Err(TlsError::MissingHandshake);
+        }
+        if !self.decrypted.is_empty() || self.record_reader.has_pending() {
+            return Err(TlsError::PendingData);
         }
+        self.flush()?;
+
+        let secrets = self.key_schedule.export_traffic_secrets()?;
+        Ok((self.delegate, secrets))
     }
 
     #[cfg(feature = "std")]
@@ -256,17 +711,25 @@ where
         let state = state.state();
         state.set_open(self.opened);
 
-        let (shared, wks, rks) = self.key_schedule.split();
+        let (shared, wks, mut rks) = self.key_schedule.split();
+        if rks.take_key_update_request() {
+            state.set_key_update_requested();
+        }
 
         let reader = TlsReader {
             state: state.clone(),
+            read_closed: self.read_closed,
             delegate: self.delegate.clone(),
             key_schedule: rks,
             record_reader: self.record_reader,
             decrypted: self.decrypted,
+            ticket_store: self.ticket_store,
+            alpn_protocol: self.alpn_protocol,
+            custom_extensions: self.custom_extensions,
         };
         let writer = TlsWriter {
             state,
+            write_closed: self.write_closed,
             delegate: self.delegate,
             key_schedule_shared: shared,
             key_schedule: wks,
@@ -286,43 +749,59 @@ where
     {
         debug_assert!(reader.state.same(&writer.state));
 
+        let mut read_key_schedule = reader.key_schedule;
+        if writer.state.take_key_update_request() {
+            read_key_schedule.defer_key_update_request();
+        }
+
         TlsConnection {
             delegate: writer.delegate,
             opened: writer.state.is_open(),
+            read_closed: reader.read_closed,
+            write_closed: writer.write_closed,
             key_schedule: KeySchedule::unsplit(
                 writer.key_schedule_shared,
                 writer.key_schedule,
-                reader.key_schedule,
+                read_key_schedule,
             ),
             record_reader: reader.record_reader,
             record_write_buf: writer.record_write_buf,
             decrypted: reader.decrypted,
+            ticket_store: reader.ticket_store,
+            alpn_protocol: reader.alpn_protocol,
+            custom_extensions: reader.custom_extensions,
+            #[cfg(feature = "ech")]
+            ech_retry_configs: heapless::Vec::new(),
+            provider: PhantomData,
         }
     }
 }
 
-impl<'a, Socket, CipherSuite> Io for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, CipherSuite, Provider> Io for TlsConnection<'a, Socket, CipherSuite, Provider>
 where
     Socket: Read + Write + 'a,
     CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     type Error = TlsError;
 }
 
-impl<'a, Socket, CipherSuite> Read for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, CipherSuite, Provider> Read for TlsConnection<'a, Socket, CipherSuite, Provider>
 where
     Socket: Read + Write + 'a,
     CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         TlsConnection::read(self, buf)
     }
 }
 
-impl<'a, Socket, CipherSuite> BufRead for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, CipherSuite, Provider> BufRead for TlsConnection<'a, Socket, CipherSuite, Provider>
 where
     Socket: Read + Write + 'a,
     CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
         self.read_buffered().map(|mut buf| buf.peek_all())
@@ -333,10 +812,11 @@ where
     }
 }
 
-impl<'a, Socket, CipherSuite> Write for TlsConnection<'a, Socket, CipherSuite>
+impl<'a, Socket, CipherSuite, Provider> Write for TlsConnection<'a, Socket, CipherSuite, Provider>
 where
     Socket: Read + Write + 'a,
     CipherSuite: TlsCipherSuite + 'static,
+    Provider: CryptoProvider,
 {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         TlsConnection::write(self, buf)
@@ -352,10 +832,14 @@ where
     CipherSuite: TlsCipherSuite + 'static,
 {
     state: State,
+    read_closed: bool,
     delegate: Socket,
     key_schedule: ReadKeySchedule<CipherSuite>,
     record_reader: RecordReader<'a, CipherSuite>,
     decrypted: DecryptedBufferInfo,
+    ticket_store: Option<TicketStoreRef<'a, CipherSuite>>,
+    alpn_protocol: heapless::Vec<u8, MAX_ALPN_PROTOCOL_LEN>,
+    custom_extensions: CustomExtensions,
 }
 
 impl<'a, Socket, CipherSuite, State> AsRef<Socket> for TlsReader<'a, Socket, CipherSuite, State>
@@ -379,15 +863,27 @@ where
 
     /// Reads buffered data. If nothing is in memory, it'll wait for a TLS record and process it.
     pub fn read_buffered(&mut self) -> Result<ReadBuffer, TlsError> {
-        if self.state.is_open() {
-            while self.decrypted.is_empty() {
-                self.read_application_data()?;
+        if !self.state.is_open() && !self.read_closed {
+            return Err(TlsError::MissingHandshake);
+        }
+        while self.state.is_open() && self.decrypted.is_empty() {
+            match self.read_application_data() {
+                Ok(()) => {}
+                Err(TlsError::ConnectionClosed) => self.read_closed = true,
+                Err(e) => return Err(e),
             }
-
-            Ok(self.create_read_buffer())
-        } else {
-            Err(TlsError::MissingHandshake)
         }
+
+        Ok(self.create_read_buffer())
+    }
+
+    /// Take the latest session ticket received from the server, to resume the session in a
+    /// later connection with [`TlsConfig::with_session_ticket`].
+    ///
+    /// The server sends its tickets after the handshake, and they are processed while reading
+    /// from the connection. No ticket is available before data has been read.
+    pub fn take_session_ticket(&mut self) -> Option<SessionTicket<CipherSuite>> {
+        self.key_schedule.take_session_ticket()
     }
 
     fn read_application_data(&mut self) -> Result<(), TlsError> {
@@ -402,10 +898,18 @@ where
             buffer_info: &mut self.decrypted,
             is_open: &mut opened,
         };
-        let result = decrypt_record(&mut self.key_schedule, record, |_key_schedule, record| {
-            handler.handle(record)
+        let result = decrypt_record(&mut self.key_schedule, record, |key_schedule, record| {
+            handler.handle(key_schedule, record)
         });
 
+        if let Some(store) = &mut self.ticket_store {
+            store.stash(&mut self.key_schedule);
+        }
+
+        if self.key_schedule.take_key_update_request() {
+            self.state.set_key_update_requested();
+        }
+
         if !opened {
             self.state.set_open(false);
         }
@@ -418,6 +922,7 @@ where
     CipherSuite: TlsCipherSuite + 'static,
 {
     state: State,
+    write_closed: bool,
     delegate: Socket,
     key_schedule_shared: SharedState<CipherSuite>,
     key_schedule: WriteKeySchedule<CipherSuite>,
@@ -433,6 +938,53 @@ where
     }
 }
 
+impl<'a, Socket, CipherSuite, State> TlsWriter<'a, Socket, CipherSuite, State>
+where
+    Socket: Write + 'a,
+    CipherSuite: TlsCipherSuite + 'static,
+    State: SplitState,
+{
+    /// Send a KeyUpdate and switch to the next traffic key, see
+    /// [`TlsConnection::request_key_update`]. The KeyUpdate of the server, if requested, is
+    /// processed by the reader.
+    pub fn request_key_update(&mut self, update_requested: bool) -> Result<(), TlsError> {
+        if !self.state.is_open() {
+            return Err(TlsError::MissingHandshake);
+        }
+        if self.write_closed {
+            return Err(TlsError::ConnectionClosed);
+        }
+        self.flush()?;
+
+        send_key_update_blocking(
+            &mut self.delegate,
+            &mut self.record_write_buf,
+            &mut self.key_schedule,
+            update_requested,
+        )?;
+        self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))
+    }
+
+    /// Close the write side of the connection, while the reader goes on, see
+    /// [`TlsConnection::close_write`].
+    pub fn close_write(&mut self) -> Result<(), TlsError> {
+        if !self.state.is_open() {
+            return Err(TlsError::MissingHandshake);
+        }
+        if !self.write_closed {
+            self.flush()?;
+            send_close_notify_blocking(
+                &mut self.delegate,
+                &mut self.record_write_buf,
+                &mut self.key_schedule,
+            )?;
+            self.write_closed = true;
+            self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
+        }
+        Ok(())
+    }
+}
+
 impl<'a, Socket, CipherSuite, State> Io for TlsWriter<'a, Socket, CipherSuite, State>
 where
     CipherSuite: TlsCipherSuite + 'static,
@@ -485,6 +1037,9 @@ where
     State: SplitState,
 {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if self.write_closed {
+            return Err(TlsError::ConnectionClosed);
+        }
         if self.state.is_open() {
             if !self
                 .record_write_buf
@@ -515,8 +1070,25 @@ where
                 .write_all(slice)
                 .map_err(|e| TlsError::Io(e.kind()))?;
 
-            self.key_schedule.increment_counter();
+            self.key_schedule.increment_counter()?;
+            update_key_if_due_blocking(
+                &mut self.delegate,
+                &mut self.record_write_buf,
+                &mut self.key_schedule,
+            )?;
+
+            self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
+        }
 
+        // The reader records the KeyUpdate requests of the server, which are answered before the
+        // next application data record
+        if self.state.take_key_update_request() && !self.write_closed {
+            send_key_update_blocking(
+                &mut self.delegate,
+                &mut self.record_write_buf,
+                &mut self.key_schedule,
+                false,
+            )?;
             self.delegate.flush().map_err(|e| TlsError::Io(e.kind()))?;
         }
 
@@ -90,6 +90,14 @@ impl<'b> CryptoBuffer<'b> {
         &self.buf[self.offset..self.offset + self.len]
     }
 
+    /// The data of the buffer, and the space left after it, for data which is encoded
+    /// temporarily without being part of the buffer.
+    #[cfg(feature = "ech")]
+    pub(crate) fn split_spare(&mut self) -> (&mut [u8], &mut [u8]) {
+        let (data, spare) = self.buf.split_at_mut(self.offset + self.len);
+        (&mut data[self.offset..], spare)
+    }
+
     fn extend_internal(&mut self, other: &[u8]) -> Result<(), TlsError> {
         if self.space() < other.len() {
             Err(TlsError::InsufficientSpace)
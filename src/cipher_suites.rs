@@ -7,6 +7,9 @@ pub enum CipherSuite {
     TlsAes128CcmSha256 = 0x1304,
     TlsAes128Ccm8Sha256 = 0x1305,
     TlsPskAes128GcmSha256 = 0x00A8,
+    TlsSm4GcmSm3 = 0x00C6,
+    TlsSm4CcmSm3 = 0x00C7,
+    TlsEcdheEcdsaWithAes128GcmSha256 = 0xC02B,
 }
 
 impl CipherSuite {
@@ -18,6 +21,9 @@ impl CipherSuite {
             0x1304 => Some(Self::TlsAes128CcmSha256),
             0x1305 => Some(Self::TlsAes128Ccm8Sha256),
             0x00A8 => Some(Self::TlsPskAes128GcmSha256),
+            0x00C6 => Some(Self::TlsSm4GcmSm3),
+            0x00C7 => Some(Self::TlsSm4CcmSm3),
+            0xC02B => Some(Self::TlsEcdheEcdsaWithAes128GcmSha256),
             _ => None,
         }
     }
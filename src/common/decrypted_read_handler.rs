@@ -1,10 +1,13 @@
 use core::ops::Range;
 
-use generic_array::ArrayLength;
-
 use crate::{
-    alert::AlertDescription, common::decrypted_buffer_info::DecryptedBufferInfo,
-    handshake::ServerHandshake, record::ServerRecord, TlsError,
+    alert::AlertDescription,
+    common::decrypted_buffer_info::DecryptedBufferInfo,
+    config::TlsCipherSuite,
+    handshake::ServerHandshake,
+    key_schedule::{HashOutputSize, ReadKeySchedule},
+    record::ServerRecord,
+    TlsError,
 };
 
 pub struct DecryptedReadHandler<'a> {
@@ -14,9 +17,10 @@ pub struct DecryptedReadHandler<'a> {
 }
 
 impl DecryptedReadHandler<'_> {
-    pub fn handle<N: ArrayLength<u8>>(
+    pub fn handle<CipherSuite: TlsCipherSuite>(
         &mut self,
-        record: ServerRecord<'_, N>,
+        key_schedule: &mut ReadKeySchedule<CipherSuite>,
+        record: ServerRecord<'_, HashOutputSize<CipherSuite>>,
     ) -> Result<(), TlsError> {
         match record {
             ServerRecord::ApplicationData(data) => {
@@ -43,24 +47,140 @@ impl DecryptedReadHandler<'_> {
                 self.buffer_info.consumed = 0;
                 Ok(())
             }
-            ServerRecord::Alert(alert) => {
-                if let AlertDescription::CloseNotify = alert.description {
+            ServerRecord::Alert(alert) => match alert.description {
+                AlertDescription::CloseNotify => {
                     *self.is_open = false;
                     Err(TlsError::ConnectionClosed)
-                } else {
-                    Err(TlsError::InternalError)
                 }
-            }
+                _ if alert.is_fatal() => {
+                    warn!("Received alert: {:?}", alert.description);
+                    *self.is_open = false;
+                    Err(TlsError::AlertReceived(alert.level, alert.description))
+                }
+                _ => {
+                    warn!("Ignoring warning alert: {:?}", alert.description);
+                    Ok(())
+                }
+            },
             ServerRecord::ChangeCipherSpec(_) => Err(TlsError::InternalError),
-            ServerRecord::Handshake(ServerHandshake::NewSessionTicket(_)) => {
-                // TODO: we should validate extensions and abort. We can do this automatically
-                // as long as the connection is unsplit, however, split connections must be aborted
-                // by the user.
+            // Section 4.6.  Post-Handshake Messages
+            // TLS also allows other messages to be sent after the main handshake.
+            //
+            // They are consumed here, so the read goes on with the next record.
+            ServerRecord::Handshake(ServerHandshake::NewSessionTicket(ticket)) => {
+                key_schedule.receive_session_ticket(&ticket)
+            }
+            ServerRecord::Handshake(ServerHandshake::KeyUpdate(key_update)) => {
+                key_schedule.receive_key_update(&key_update);
                 Ok(())
             }
-            _ => {
-                unimplemented!()
+            // Section 4.6.2.  Post-Handshake Authentication
+            // A client that receives a CertificateRequest message without having sent the
+            // "post_handshake_auth" extension MUST send an "unexpected_message" fatal alert.
+            ServerRecord::Handshake(handshake) => {
+                warn!(
+                    "Unexpected post-handshake message: {:?}",
+                    handshake.handshake_type()
+                );
+                Err(TlsError::InvalidHandshake)
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application_data::ApplicationData;
+    use crate::buffer::CryptoBuffer;
+    use crate::config::Aes128GcmSha256;
+    use crate::key_schedule::KeySchedule;
+    use crate::parse_buffer::ParseBuffer;
+    use crate::record::RecordHeader;
+
+    fn handshake(data: &[u8]) -> ServerRecord<'_, HashOutputSize<Aes128GcmSha256>> {
+        ServerRecord::Handshake(ServerHandshake::parse(&mut ParseBuffer::new(data)).unwrap())
+    }
+
+    #[test]
+    fn test_post_handshake_messages() {
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        let mut storage = [0; 16];
+        storage[..4].copy_from_slice(b"ping");
+        let mut buffer_info = DecryptedBufferInfo::default();
+        let mut is_open = true;
+        let mut handler = DecryptedReadHandler {
+            source_buffer: storage.as_ptr_range(),
+            buffer_info: &mut buffer_info,
+            is_open: &mut is_open,
+        };
+
+        // A KeyUpdate leaves nothing to read, so the application data of the next record is
+        // waited for
+        let key_update = handshake(&[24, 0, 0, 1, 1]);
+        handler
+            .handle(key_schedule.read_state(), key_update)
+            .unwrap();
+        assert!(handler.buffer_info.is_empty());
+
+        let header = RecordHeader::decode([23, 3, 3, 0, 4]).unwrap();
+        let data = CryptoBuffer::wrap_with_pos(&mut storage[..4], 4);
+        let record = ServerRecord::ApplicationData(ApplicationData::new(data, header));
+        handler.handle(key_schedule.read_state(), record).unwrap();
+        assert_eq!(4, handler.buffer_info.len());
+
+        // The client does not offer post-handshake authentication
+        let certificate_request = handshake(&[13, 0, 0, 3, 0, 0, 0]);
+        assert!(matches!(
+            handler.handle(key_schedule.read_state(), certificate_request),
+            Err(TlsError::InvalidHandshake)
+        ));
+    }
+
+    #[test]
+    fn test_alerts() {
+        use crate::alert::{Alert, AlertDescription, AlertLevel};
+
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        let storage = [0; 16];
+        let mut buffer_info = DecryptedBufferInfo::default();
+        let mut is_open = true;
+        let mut handler = DecryptedReadHandler {
+            source_buffer: storage.as_ptr_range(),
+            buffer_info: &mut buffer_info,
+            is_open: &mut is_open,
+        };
+
+        // A warning is ignored, the connection stays open
+        let user_canceled = Alert::new(AlertLevel::Warning, AlertDescription::UserCanceled);
+        handler
+            .handle(
+                key_schedule.read_state(),
+                ServerRecord::Alert(user_canceled),
+            )
+            .unwrap();
+        assert!(*handler.is_open);
+
+        let bad_record_mac = Alert::new(AlertLevel::Fatal, AlertDescription::BadRecordMac);
+        assert!(matches!(
+            handler.handle(
+                key_schedule.read_state(),
+                ServerRecord::Alert(bad_record_mac)
+            ),
+            Err(TlsError::AlertReceived(
+                AlertLevel::Fatal,
+                AlertDescription::BadRecordMac
+            ))
+        ));
+        assert!(!*handler.is_open);
+
+        // A close_notify ends the connection whatever its level
+        *handler.is_open = true;
+        let close_notify = Alert::new(AlertLevel::Warning, AlertDescription::CloseNotify);
+        assert!(matches!(
+            handler.handle(key_schedule.read_state(), ServerRecord::Alert(close_notify)),
+            Err(TlsError::ConnectionClosed)
+        ));
+        assert!(!*handler.is_open);
+    }
+}
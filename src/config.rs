@@ -1,28 +1,103 @@
 use crate::cipher_suites::CipherSuite;
-use crate::handshake::certificate::CertificateRef;
-use crate::handshake::certificate_verify::CertificateVerify;
+pub use crate::crypto_provider::{CryptoProvider, KeyExchange, RustCrypto};
+pub use crate::extensions::client::PskKeyExchangeMode;
+pub use crate::extensions::ExtensionType;
+use crate::handshake::certificate::CertificateEntryRef;
+pub use crate::handshake::certificate::CertificateRef;
+pub use crate::handshake::certificate_verify::CertificateVerify;
+pub use crate::handshake::compressed_certificate::CertificateCompressionAlgorithm;
+#[cfg(feature = "zlib")]
+use crate::handshake::compressed_certificate::SUPPORTED_ALGORITHMS;
+pub use crate::handshake::HandshakeType;
+pub use crate::heartbeat::{HeartbeatMode, MAX_HEARTBEAT_PAYLOAD_LEN};
+pub use crate::kdf::{constant_time_eq, HkdfOutput, SimpleHkdf, TlsHkdf};
+pub use crate::key_exchange::{EphemeralSecret, PublicKey, SharedSecret};
+#[cfg(feature = "secret-export")]
+pub use crate::key_schedule::TrafficSecrets;
+pub use crate::key_schedule::{SessionTicket, MAX_SESSION_TICKET_LEN};
 pub use crate::max_fragment_length::MaxFragmentLength;
-use crate::named_groups::NamedGroup;
-use crate::signature_schemes::SignatureScheme;
+pub use crate::named_groups::NamedGroup;
+pub use crate::pinning::{FingerprintStore, PinnedKeyVerifier, TofuVerifier};
+pub use crate::raw_public_key::RawPublicKeyVerifier;
+pub use crate::record_aead::{ciphertext_region, RecordAead};
+#[cfg(feature = "deterministic-rng")]
+pub use crate::rng::FixedRng;
+pub use crate::rng::TlsRng;
+#[cfg(feature = "sct")]
+pub use crate::sct::CtLog;
+pub use crate::signature_schemes::SignatureScheme;
+pub use crate::signer::{encode_ecdsa_signature, NoSigner, PrivateKey, MAX_SIGNATURE_LEN};
+pub use crate::ticket_store::TicketStore;
 use crate::TlsError;
-use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm, KeyInit};
+use aes::Aes128;
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use ccm::Ccm;
 use core::marker::PhantomData;
-use digest::core_api::BlockSizeUser;
 use digest::{Digest, FixedOutput, OutputSizeUser, Reset};
 use generic_array::ArrayLength;
 use heapless::Vec;
-use rand_core::{CryptoRng, RngCore};
 pub use sha2::Sha256;
 pub use sha2::Sha384;
-use typenum::{Sum, U10, U12, U16, U32};
+#[cfg(feature = "sm")]
+pub use sm3::Sm3;
+use typenum::{Sum, U10, U12, U16, U32, U8};
 
-const TLS_RECORD_MAX: usize = 16384;
+pub(crate) const TLS_RECORD_MAX: usize = 16384;
 pub const TLS_RECORD_OVERHEAD: usize = 128;
 
+/// The alignment of the protected data of every record in the read and write record buffers.
+///
+/// The plaintext handed to [`RecordAead::encrypt_in_place`] and the ciphertext handed to
+/// [`RecordAead::decrypt_in_place`] start at an address which is a multiple of this alignment,
+/// so that they can be passed to word-aligned DMA engines directly. Up to
+/// `TLS_RECORD_ALIGNMENT - 1` bytes of each record buffer may be left unused to achieve this.
+pub const TLS_RECORD_ALIGNMENT: usize = 4;
+
+/// The maximum number of key shares sent in the ClientHello, see [`TlsConfig::with_key_shares`].
+pub const MAX_KEY_SHARES: usize = 3;
+
+/// The maximum length of an application protocol selected with ALPN, see
+/// [`TlsConfig::with_alpn_protocols`].
+pub const MAX_ALPN_PROTOCOL_LEN: usize = 32;
+
+/// The maximum number of custom extensions kept from the server, see
+/// [`TlsConfig::with_custom_extensions`].
+pub const MAX_CUSTOM_EXTENSIONS: usize = 4;
+
+/// The maximum length of the data of a custom extension kept from the server, see
+/// [`TlsConfig::with_custom_extensions`].
+pub const MAX_CUSTOM_EXTENSION_LEN: usize = 64;
+
+/// An order of the ClientHello extensions close to the one of Firefox, see
+/// [`TlsConfig::with_extension_order`].
+pub const BROWSER_EXTENSION_ORDER: &[ExtensionType] = &[
+    ExtensionType::ServerName,
+    ExtensionType::ExtendedMasterSecret,
+    ExtensionType::SupportedGroups,
+    ExtensionType::EcPointFormats,
+    ExtensionType::ApplicationLayerProtocolNegotiation,
+    ExtensionType::StatusRequest,
+    ExtensionType::DelegatedCredential,
+    ExtensionType::KeyShare,
+    ExtensionType::EarlyData,
+    ExtensionType::SupportedVersions,
+    ExtensionType::SignatureAlgorithms,
+    ExtensionType::PskKeyExchangeModes,
+    ExtensionType::Cookie,
+    ExtensionType::MaxFragmentLength,
+];
+
+/// The number of bytes from the start of `buf` up to the first address which is aligned to
+/// [`TLS_RECORD_ALIGNMENT`] after skipping `skip` bytes.
+pub(crate) fn align_offset(buf: &[u8], skip: usize) -> usize {
+    let misalignment = (buf.as_ptr() as usize + skip) % TLS_RECORD_ALIGNMENT;
+    skip + (TLS_RECORD_ALIGNMENT - misalignment) % TLS_RECORD_ALIGNMENT
+}
+
 // longest label is 12b -> buf <= 2 + 1 + 6 + longest + 1 + hash_out = hash_out + 22
 type LongestLabel = U12;
 type LabelOverhead = U10;
-type LabelBuffer<CipherSuite> = Sum<
+pub(crate) type LabelBuffer<CipherSuite> = Sum<
     <<CipherSuite as TlsCipherSuite>::Hash as OutputSizeUser>::OutputSize,
     Sum<LongestLabel, LabelOverhead>,
 >;
@@ -30,22 +105,32 @@ type LabelBuffer<CipherSuite> = Sum<
 /// Represents a TLS 1.3 cipher suite
 pub trait TlsCipherSuite {
     const CODE_POINT: u16;
-    type Cipher: KeyInit<KeySize = Self::KeyLen> + AeadInPlace<NonceSize = Self::IvLen>;
+    type Cipher: RecordAead<KeyLen = Self::KeyLen, NonceLen = Self::IvLen>;
     type KeyLen: ArrayLength<u8>;
     type IvLen: ArrayLength<u8>;
 
-    type Hash: Digest + Reset + Clone + OutputSizeUser + BlockSizeUser + FixedOutput;
+    type Hash: Digest + Reset + Clone + OutputSizeUser + FixedOutput;
+    type Hkdf: TlsHkdf<Hash = Self::Hash>;
     type LabelBufferSize: ArrayLength<u8>;
+
+    /// The TLS 1.2 cipher suite with the same record protection and hash, offered along with this
+    /// one by [`TlsConfig::with_tls12`].
+    #[cfg(feature = "tls12")]
+    const TLS12_CODE_POINT: Option<u16> = None;
 }
 
 pub struct Aes128GcmSha256;
 impl TlsCipherSuite for Aes128GcmSha256 {
     const CODE_POINT: u16 = CipherSuite::TlsAes128GcmSha256 as u16;
+    #[cfg(feature = "tls12")]
+    const TLS12_CODE_POINT: Option<u16> =
+        Some(CipherSuite::TlsEcdheEcdsaWithAes128GcmSha256 as u16);
     type Cipher = Aes128Gcm;
     type KeyLen = U16;
     type IvLen = U12;
 
     type Hash = Sha256;
+    type Hkdf = SimpleHkdf<Sha256>;
     type LabelBufferSize = LabelBuffer<Self>;
 }
 
@@ -57,9 +142,94 @@ impl TlsCipherSuite for Aes256GcmSha384 {
     type IvLen = U12;
 
     type Hash = Sha384;
+    type Hkdf = SimpleHkdf<Sha384>;
     type LabelBufferSize = LabelBuffer<Self>;
 }
 
+pub struct Aes128CcmSha256;
+impl TlsCipherSuite for Aes128CcmSha256 {
+    const CODE_POINT: u16 = CipherSuite::TlsAes128CcmSha256 as u16;
+    type Cipher = Ccm<Aes128, U16, U12>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sha256;
+    type Hkdf = SimpleHkdf<Sha256>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+/// AES-128-CCM with a truncated 8 byte tag, saving 8 bytes per record on constrained links.
+pub struct Aes128Ccm8Sha256;
+impl TlsCipherSuite for Aes128Ccm8Sha256 {
+    const CODE_POINT: u16 = CipherSuite::TlsAes128Ccm8Sha256 as u16;
+    type Cipher = Ccm<Aes128, U8, U12>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sha256;
+    type Hkdf = SimpleHkdf<Sha256>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+/// SM4-GCM with the SM3 hash, from [RFC 8998](https://www.rfc-editor.org/rfc/rfc8998).
+#[cfg(feature = "sm")]
+pub struct Sm4GcmSm3;
+#[cfg(feature = "sm")]
+impl TlsCipherSuite for Sm4GcmSm3 {
+    const CODE_POINT: u16 = CipherSuite::TlsSm4GcmSm3 as u16;
+    type Cipher = aes_gcm::AesGcm<sm4::Sm4, U12>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sm3;
+    type Hkdf = SimpleHkdf<Sm3>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+/// SM4-CCM with the SM3 hash, from [RFC 8998](https://www.rfc-editor.org/rfc/rfc8998).
+#[cfg(feature = "sm")]
+pub struct Sm4CcmSm3;
+#[cfg(feature = "sm")]
+impl TlsCipherSuite for Sm4CcmSm3 {
+    const CODE_POINT: u16 = CipherSuite::TlsSm4CcmSm3 as u16;
+    type Cipher = Ccm<sm4::Sm4, U16, U12>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = Sm3;
+    type Hkdf = SimpleHkdf<Sm3>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+/// A cipher suite with records protected by a custom AEAD, for example one that offloads
+/// encryption to a hardware peripheral.
+///
+/// The code point, hash and key schedule of `Suite` are kept, and `Cipher` must implement the same
+/// AEAD algorithm, including its tag length. Since [`RecordAead::new`] takes no context, a
+/// peripheral is typically reached through a global, such as a mutex protected singleton.
+///
+/// ```ignore
+/// type Suite = WithCipher<Aes128GcmSha256, HardwareAes128Gcm>;
+/// let mut tls: TlsConnection<Socket, Suite> = TlsConnection::new(socket, &mut rx, &mut tx);
+/// ```
+pub struct WithCipher<Suite, Cipher>(PhantomData<(Suite, Cipher)>);
+impl<Suite, Cipher> TlsCipherSuite for WithCipher<Suite, Cipher>
+where
+    Suite: TlsCipherSuite,
+    Cipher: RecordAead<KeyLen = Suite::KeyLen, NonceLen = Suite::IvLen>,
+{
+    const CODE_POINT: u16 = Suite::CODE_POINT;
+    #[cfg(feature = "tls12")]
+    const TLS12_CODE_POINT: Option<u16> = Suite::TLS12_CODE_POINT;
+    type Cipher = Cipher;
+    type KeyLen = Suite::KeyLen;
+    type IvLen = Suite::IvLen;
+
+    type Hash = Suite::Hash;
+    type Hkdf = Suite::Hkdf;
+    type LabelBufferSize = Suite::LabelBufferSize;
+}
+
 /// A TLS 1.3 verifier.
 ///
 /// The verifier is responsible for verifying certificates and signatures. Since certificate verification is
@@ -76,6 +246,36 @@ where
     /// Host verification is enabled by passing a server hostname.
     fn new(host: Option<&'a str>) -> Self;
 
+    /// Restricts the signatures of the certificates to `schemes`, the signature_algorithms_cert
+    /// configured with [`TlsConfig::with_signature_algorithms_cert`].
+    ///
+    /// This method is called before every call to [`Self::verify_certificate_with_roots`], with
+    /// an empty list when the certificates are not restricted separately. The default
+    /// implementation ignores the schemes.
+    fn set_certificate_signature_schemes(&mut self, _schemes: &[SignatureScheme]) {}
+
+    /// Sets the current time, in seconds since the Unix epoch, given by the [`TimeProvider`] of
+    /// the configuration, see [`TlsConfig::with_time_provider`], and what to do if the time is
+    /// unknown, see [`TlsConfig::with_missing_time`].
+    ///
+    /// This method is called before every call to [`Self::verify_certificate_with_roots`]. The
+    /// default implementation ignores the time.
+    fn set_time(&mut self, _now: Option<u64>, _missing_time: MissingTime) {}
+
+    /// Sets the policy of the configuration, see [`TlsConfig::with_verification_policy`].
+    ///
+    /// This method is called before every call to [`Self::verify_certificate_with_roots`]. The
+    /// default implementation ignores the policy.
+    fn set_verification_policy(&mut self, _policy: VerificationPolicy) {}
+
+    /// Sets the pins of the configuration, see [`TlsConfig::with_spki_pins`]. The pins are
+    /// checked by the connection for any verifier, so this is only needed by verifiers which
+    /// trust the key of the server for its pin alone.
+    ///
+    /// This method is called before every call to [`Self::verify_certificate_with_roots`]. The
+    /// default implementation ignores the pins.
+    fn set_spki_pins(&mut self, _pins: &[[u8; 32]]) {}
+
     /// Verify a certificate.
     ///
     /// The handshake transcript up to this point and the server certificate is provided
@@ -88,10 +288,65 @@ where
         cert: CertificateRef,
     ) -> Result<(), TlsError>;
 
+    /// Verify a certificate against the root certificates of the configuration, see
+    /// [`TlsConfig::with_root_certificates`], as well as the CA.
+    ///
+    /// This method is the one called during the handshake. The default implementation ignores
+    /// the root certificates and calls [`Self::verify_certificate`].
+    fn verify_certificate_with_roots(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        _roots: &[&[u8]],
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        self.verify_certificate(transcript, ca, cert)
+    }
+
     /// Verify the certificate signature.
     ///
     /// The signature verification uses the transcript and certificate provided earlier to decode the provided signature.
     fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), crate::TlsError>;
+
+    /// Verify the signature of the ServerKeyExchange of a TLS 1.2 handshake.
+    ///
+    /// The signed message is the concatenation of the client and server randoms and the key
+    /// exchange parameters, and the signature is made with the key of the certificate provided
+    /// earlier. Verifiers which do not implement it can not be used with TLS 1.2.
+    #[cfg(feature = "tls12")]
+    fn verify_tls12_signature(
+        &mut self,
+        _message: &[u8],
+        _verify: CertificateVerify,
+    ) -> Result<(), crate::TlsError> {
+        Err(TlsError::Unimplemented)
+    }
+
+    /// Apply a policy to the certificate of the server, given with the full context of the
+    /// handshake, such as a check of the extended key usages or the pinning of a key.
+    ///
+    /// This method is called with every Certificate message of the server, before
+    /// [`Self::verify_certificate_with_roots`]. The handshake is aborted with the error returned,
+    /// and a [`TlsError::AbortHandshake`] sends its alert to the server. The default
+    /// implementation accepts any certificate.
+    fn verify_certificate_context(
+        &mut self,
+        _context: &CertificateContext,
+    ) -> Result<(), TlsError> {
+        Ok(())
+    }
+
+    /// Apply a policy to the signature of the handshake by the server, given with the full
+    /// context of the handshake.
+    ///
+    /// This method is called with the CertificateVerify message of a TLS 1.3 handshake, or the
+    /// ServerKeyExchange message of a TLS 1.2 handshake, after [`Self::verify_signature`] or
+    /// [`Self::verify_tls12_signature`]. The handshake is aborted with the error returned, and a
+    /// [`TlsError::AbortHandshake`] sends its alert to the server. The default implementation
+    /// accepts any signature.
+    fn verify_signature_context(&mut self, _context: &SignatureContext) -> Result<(), TlsError> {
+        Ok(())
+    }
 }
 
 pub struct NoVerify;
@@ -116,6 +371,99 @@ where
     fn verify_signature(&mut self, _verify: CertificateVerify) -> Result<(), crate::TlsError> {
         Ok(())
     }
+
+    #[cfg(feature = "tls12")]
+    fn verify_tls12_signature(
+        &mut self,
+        _message: &[u8],
+        _verify: CertificateVerify,
+    ) -> Result<(), crate::TlsError> {
+        Ok(())
+    }
+}
+
+/// The certificate of the server and the context of the handshake, given to
+/// [`TlsVerifier::verify_certificate_context`].
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CertificateContext<'c> {
+    pub(crate) server_name: Option<&'c str>,
+    pub(crate) entries: &'c [CertificateEntryRef<'c>],
+    pub(crate) ocsp_response: Option<&'c [u8]>,
+    pub(crate) transcript_hash: &'c [u8],
+}
+
+impl<'c> CertificateContext<'c> {
+    /// The name the server is authenticated for, see [`TlsConfig::with_server_name`], or the
+    /// public name of the ECHConfig when the server rejected ECH, see [`TlsConfig::with_ech`].
+    pub fn server_name(&self) -> Option<&'c str> {
+        self.server_name
+    }
+
+    /// The DER encoded certificates sent by the server, starting with the end-entity
+    /// certificate, as received and before any verification.
+    ///
+    /// With raw public keys, see [`Self::is_raw_public_key`], it is the DER encoded
+    /// SubjectPublicKeyInfo of the server.
+    pub fn chain(&self) -> impl Iterator<Item = &'c [u8]> + 'c {
+        self.entries.iter().map(|entry| match *entry {
+            CertificateEntryRef::X509(data) | CertificateEntryRef::RawPublicKey(data) => data,
+        })
+    }
+
+    /// Whether the server sent a raw public key (RFC 7250) instead of X.509 certificates.
+    pub fn is_raw_public_key(&self) -> bool {
+        matches!(
+            self.entries.first(),
+            Some(CertificateEntryRef::RawPublicKey(_))
+        )
+    }
+
+    /// The OCSP response stapled to the end-entity certificate, if any.
+    pub fn ocsp_response(&self) -> Option<&'c [u8]> {
+        self.ocsp_response
+    }
+
+    /// The hash of the handshake transcript, up to and including the Certificate message.
+    pub fn transcript_hash(&self) -> &'c [u8] {
+        self.transcript_hash
+    }
+}
+
+/// The signature of the handshake by the server and its context, given to
+/// [`TlsVerifier::verify_signature_context`].
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SignatureContext<'c> {
+    pub(crate) server_name: Option<&'c str>,
+    pub(crate) signature_scheme: SignatureScheme,
+    pub(crate) signature: &'c [u8],
+    pub(crate) transcript_hash: &'c [u8],
+}
+
+impl<'c> SignatureContext<'c> {
+    /// The name the server is authenticated for, see [`TlsConfig::with_server_name`], or the
+    /// public name of the ECHConfig when the server rejected ECH, see [`TlsConfig::with_ech`].
+    pub fn server_name(&self) -> Option<&'c str> {
+        self.server_name
+    }
+
+    /// The signature scheme selected by the server.
+    pub fn signature_scheme(&self) -> SignatureScheme {
+        self.signature_scheme
+    }
+
+    /// The signature, as sent by the server.
+    pub fn signature(&self) -> &'c [u8] {
+        self.signature
+    }
+
+    /// The hash of the handshake transcript: up to and including the Certificate message in
+    /// TLS 1.3, which is the hash signed in the CertificateVerify, and up to and including the
+    /// ServerKeyExchange message in TLS 1.2.
+    pub fn transcript_hash(&self) -> &'c [u8] {
+        self.transcript_hash
+    }
 }
 
 #[derive(Debug)]
@@ -126,13 +474,179 @@ where
 {
     //pub(crate) cipher_suites: Vec<CipherSuite, U16>,
     pub(crate) server_name: Option<&'a str>,
+    pub(crate) sni: Option<&'a str>,
+    pub(crate) alpn_protocols: &'a [&'a [u8]],
+    pub(crate) custom_extensions: &'a [(u16, &'a [u8])],
+    pub(crate) unknown_extension_handler: Option<UnknownExtensionHandler>,
+    pub(crate) ocsp_verifier: Option<OcspVerifier>,
+    #[cfg(feature = "sct")]
+    pub(crate) ct_logs: &'a [CtLog<'a>],
+    #[cfg(feature = "sct")]
+    pub(crate) required_scts: usize,
+    #[cfg(feature = "zlib")]
+    pub(crate) certificate_compression: bool,
+    #[cfg(feature = "webpki")]
+    pub(crate) delegated_credentials: bool,
+    #[cfg(feature = "ech")]
+    pub(crate) ech: Option<&'a [u8]>,
     pub(crate) psk: Option<(&'a [u8], Vec<&'a [u8], 4>)>,
+    pub(crate) psk_key_exchange_modes: Vec<PskKeyExchangeMode, 2>,
+    pub(crate) session_ticket: Option<&'a SessionTicket<CipherSuite>>,
+    pub(crate) ticket_request: Option<(u8, u8)>,
+    pub(crate) early_data: Option<&'a [u8]>,
     pub(crate) cipher_suite: PhantomData<CipherSuite>,
     pub(crate) signature_schemes: Vec<SignatureScheme, 16>,
+    pub(crate) signature_schemes_cert: Vec<SignatureScheme, 16>,
     pub(crate) named_groups: Vec<NamedGroup, 16>,
+    pub(crate) key_shares: usize,
     pub(crate) max_fragment_length: Option<MaxFragmentLength>,
+    pub(crate) heartbeat: Option<HeartbeatMode>,
     pub(crate) ca: Option<Certificate<'a>>,
+    pub(crate) root_certificates: &'a [&'a [u8]],
+    pub(crate) spki_pins: &'a [[u8; 32]],
+    pub(crate) time_provider: Option<&'a dyn TimeProvider>,
+    pub(crate) missing_time: MissingTime,
+    pub(crate) verification_policy: VerificationPolicy,
+    pub(crate) certificate_authorities: bool,
     pub(crate) cert: Option<Certificate<'a>>,
+    pub(crate) intermediates: &'a [Certificate<'a>],
+    pub(crate) priv_key: Option<PrivateKey<'a>>,
+    pub(crate) identities: &'a [ClientIdentity<'a>],
+    pub(crate) record_limit: RecordLimit,
+    pub(crate) record_padding: Option<RecordPadding>,
+    pub(crate) middlebox_compatibility: bool,
+    pub(crate) grease: bool,
+    pub(crate) padding: Option<usize>,
+    pub(crate) extension_order: &'a [ExtensionType],
+    pub(crate) strict: bool,
+    #[cfg(feature = "tls12")]
+    pub(crate) tls12: bool,
+}
+
+/// The number of records that may be sent under one traffic key, see
+/// [`TlsConfig::with_record_limit`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecordLimit {
+    pub(crate) records: u64,
+    pub(crate) action: RecordLimitAction,
+}
+
+impl RecordLimit {
+    /// Section 5.5.  Limits on Key Usage
+    /// For AES-GCM, up to 2^24.5 full-size records (about 24 million) may be encrypted on a given
+    /// connection while keeping a safety margin of approximately 2^-57 for Authenticated
+    /// Encryption (AE) security.
+    pub const AES_GCM: u64 = 1 << 24;
+
+    pub const fn new(records: u64, action: RecordLimitAction) -> Self {
+        Self { records, action }
+    }
+}
+
+impl Default for RecordLimit {
+    fn default() -> Self {
+        Self::new(Self::AES_GCM, RecordLimitAction::KeyUpdate)
+    }
+}
+
+/// What to do when the record limit of the current traffic key is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecordLimitAction {
+    /// Send a KeyUpdate as the last record under the current key, and continue with a new key.
+    KeyUpdate,
+    /// Fail with [`TlsError::RecordLimitReached`] instead of exceeding the limit.
+    Abort,
+}
+
+/// The padding of the encrypted records, which hides the length of their content.
+///
+/// RFC 8446, Section 5.4.  Record Padding
+/// All encrypted TLS records can be padded to inflate the size of the TLSCiphertext.  This allows
+/// the sender to hide the size of the traffic from an observer.
+#[derive(Debug, Clone, Copy)]
+pub enum RecordPadding {
+    /// Pads the content of each record to a multiple of the block size.
+    Block(usize),
+    /// Returns the number of padding bytes for a record with the given content length.
+    Custom(fn(usize) -> usize),
+}
+
+impl RecordPadding {
+    /// The number of padding bytes for a record with `len` bytes of content.
+    pub(crate) fn len(&self, len: usize) -> usize {
+        match self {
+            Self::Block(0) => 0,
+            Self::Block(size) => (size - len % size) % size,
+            Self::Custom(padding) => padding(len),
+        }
+    }
+}
+
+/// A handler of the extensions of unknown types sent by the server, see
+/// [`TlsConfig::with_unknown_extension_handler`].
+#[derive(Clone, Copy)]
+pub struct UnknownExtensionHandler(fn(HandshakeType, u16, &[u8]) -> bool);
+
+impl UnknownExtensionHandler {
+    /// Whether the extension is accepted.
+    pub(crate) fn accepts(&self, message: HandshakeType, extension_type: u16, data: &[u8]) -> bool {
+        (self.0)(message, extension_type, data)
+    }
+}
+
+impl core::fmt::Debug for UnknownExtensionHandler {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("UnknownExtensionHandler")
+    }
+}
+
+/// A verifier of the OCSP response stapled by the server, see
+/// [`TlsConfig::with_ocsp_stapling`].
+#[derive(Clone, Copy)]
+pub struct OcspVerifier(fn(Option<&[u8]>, &[u8], Option<&[u8]>) -> bool);
+
+impl OcspVerifier {
+    /// Whether the OCSP response, if any, is acceptable for the certificate.
+    pub(crate) fn verify(
+        &self,
+        response: Option<&[u8]>,
+        certificate: &[u8],
+        issuer: Option<&[u8]>,
+    ) -> bool {
+        (self.0)(response, certificate, issuer)
+    }
+}
+
+impl core::fmt::Debug for OcspVerifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("OcspVerifier")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for OcspVerifier {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "OcspVerifier")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for UnknownExtensionHandler {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "UnknownExtensionHandler")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for RecordPadding {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            Self::Block(size) => defmt::write!(f, "Block({})", size),
+            Self::Custom(_) => defmt::write!(f, "Custom"),
+        }
+    }
 }
 
 pub trait TlsClock {
@@ -147,25 +661,150 @@ impl TlsClock for NoClock {
     }
 }
 
+/// A source of the current time, see [`TlsConfig::with_time_provider`].
+///
+/// Unlike a [`TlsClock`], it is an instance, which can hold the time received from an SNTP
+/// server or a GNSS receiver.
+pub trait TimeProvider {
+    /// The current time in seconds since the Unix epoch, or `None` if it is not known yet.
+    fn now(&self) -> Option<u64>;
+}
+
+impl core::fmt::Debug for dyn TimeProvider + '_ {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("TimeProvider")
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for dyn TimeProvider + '_ {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "TimeProvider")
+    }
+}
+
+/// What the verifier does when the current time is unknown, see
+/// [`TlsConfig::with_missing_time`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MissingTime {
+    /// The validity periods of the certificates are not checked.
+    SkipValidity,
+    /// The certificate is rejected with [`TlsError::UnknownTime`].
+    Reject,
+}
+
+/// The checks of the certificate of the server, each of which can be changed independently for
+/// a connection, see [`TlsConfig::with_verification_policy`].
+///
+/// By default, the validity periods and the server name are checked, and a certificate without
+/// the extended key usage extension is accepted. The options prefixed with `insecure_` let an
+/// attacker holding an expired or misissued certificate impersonate the server, and are meant
+/// for tests or for the recovery of devices whose clock or configuration is wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VerificationPolicy {
+    pub(crate) allow_expired: bool,
+    pub(crate) skip_hostname_verification: bool,
+    pub(crate) require_server_auth: bool,
+}
+
+impl VerificationPolicy {
+    /// The default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// **Insecure.** Accepts certificates and delegated credentials outside of their validity
+    /// period, which are then verified as if the current time was unknown.
+    pub fn insecure_allow_expired(mut self) -> Self {
+        self.allow_expired = true;
+        self
+    }
+
+    /// **Insecure.** Accepts a certificate which is not valid for the server name. The name is
+    /// still sent in the server_name extension.
+    pub fn insecure_skip_hostname_verification(mut self) -> Self {
+        self.skip_hostname_verification = true;
+        self
+    }
+
+    /// Requires the extended key usage extension in the end-entity certificate, with the
+    /// id-kp-serverAuth purpose, as described in
+    /// [RFC 5280, Section 4.2.1.12](https://www.rfc-editor.org/rfc/rfc5280#section-4.2.1.12).
+    /// The handshake fails with [`TlsError::InvalidCertificate`] otherwise.
+    pub fn require_server_auth(mut self) -> Self {
+        self.require_server_auth = true;
+        self
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub struct TlsContext<'a, CipherSuite, RNG>
+pub struct TlsContext<'a, CipherSuite, RNG, Signer = NoSigner, KeyShare = EphemeralSecret>
 where
     CipherSuite: TlsCipherSuite,
-    RNG: CryptoRng + RngCore + 'a,
+    RNG: TlsRng + 'a,
 {
     pub(crate) config: &'a TlsConfig<'a, CipherSuite>,
     pub(crate) rng: &'a mut RNG,
+    pub(crate) signer: Option<&'a mut Signer>,
+    pub(crate) key_share: Option<KeyShare>,
 }
 
-impl<'a, CipherSuite, RNG> TlsContext<'a, CipherSuite, RNG>
+impl<'a, CipherSuite, RNG, KeyShare> TlsContext<'a, CipherSuite, RNG, NoSigner, KeyShare>
 where
     CipherSuite: TlsCipherSuite,
-    RNG: CryptoRng + RngCore + 'a,
+    RNG: TlsRng + 'a,
 {
     /// Create a new context with a given config and random number generator reference.
+    ///
+    /// Any [`TlsRng`] can be used, including a fallible hardware TRNG.
     pub fn new(config: &'a TlsConfig<'a, CipherSuite>, rng: &'a mut RNG) -> Self {
-        Self { config, rng }
+        Self {
+            config,
+            rng,
+            signer: None,
+            key_share: None,
+        }
+    }
+}
+
+impl<'a, CipherSuite, RNG, Signer, KeyShare> TlsContext<'a, CipherSuite, RNG, Signer, KeyShare>
+where
+    CipherSuite: TlsCipherSuite,
+    RNG: TlsRng + 'a,
+{
+    /// Sign the client CertificateVerify with an external signer, such as a secure element
+    /// holding the private key of the configured client certificate.
+    pub fn with_signer<S>(
+        self,
+        signer: &'a mut S,
+    ) -> TlsContext<'a, CipherSuite, RNG, S, KeyShare> {
+        TlsContext {
+            config: self.config,
+            rng: self.rng,
+            signer: Some(signer),
+            key_share: self.key_share,
+        }
+    }
+
+    /// Use an ephemeral key generated in advance, for example during idle time before the
+    /// connection is opened, for the ClientHello key share instead of generating one during the
+    /// handshake.
+    ///
+    /// The key must be of the key exchange type of the connection's
+    /// [`CryptoProvider`](crate::CryptoProvider), and for one of the configured named groups.
+    pub fn with_key_share<K>(self, secret: K) -> TlsContext<'a, CipherSuite, RNG, Signer, K>
+    where
+        K: KeyExchange,
+    {
+        TlsContext {
+            config: self.config,
+            rng: self.rng,
+            signer: self.signer,
+            key_share: Some(secret),
+        }
     }
 }
 
@@ -177,18 +816,67 @@ where
         let mut config = Self {
             cipher_suite: PhantomData,
             signature_schemes: Vec::new(),
+            signature_schemes_cert: Vec::new(),
             named_groups: Vec::new(),
+            key_shares: 1,
             max_fragment_length: None,
+            heartbeat: None,
+            certificate_authorities: false,
             psk: None,
+            psk_key_exchange_modes: [PskKeyExchangeMode::PskDheKe].into_iter().collect(),
+            session_ticket: None,
+            ticket_request: None,
+            early_data: None,
             server_name: None,
+            sni: None,
+            alpn_protocols: &[],
+            custom_extensions: &[],
+            unknown_extension_handler: None,
+            ocsp_verifier: None,
+            #[cfg(feature = "sct")]
+            ct_logs: &[],
+            #[cfg(feature = "sct")]
+            required_scts: 0,
+            #[cfg(feature = "zlib")]
+            certificate_compression: false,
+            #[cfg(feature = "webpki")]
+            delegated_credentials: false,
+            #[cfg(feature = "ech")]
+            ech: None,
             ca: None,
+            root_certificates: &[],
+            spki_pins: &[],
+            time_provider: None,
+            missing_time: MissingTime::SkipValidity,
+            verification_policy: VerificationPolicy::new(),
             cert: None,
+            intermediates: &[],
+            priv_key: None,
+            identities: &[],
+            record_limit: RecordLimit::default(),
+            record_padding: None,
+            middlebox_compatibility: false,
+            grease: false,
+            padding: None,
+            extension_order: &[],
+            strict: false,
+            #[cfg(feature = "tls12")]
+            tls12: false,
         };
 
         //config.cipher_suites.push(CipherSuite::TlsAes128GcmSha256);
         //
         if cfg!(feature = "alloc") {
             config = config.enable_rsa_signatures();
+        } else if cfg!(feature = "rsa") {
+            config
+                .signature_schemes
+                .push(SignatureScheme::RsaPssRsaeSha256)
+                .unwrap();
+            config
+                .signature_schemes
+                .push(SignatureScheme::RsaPssRsaeSha384)
+                .unwrap();
         }
 
         config
@@ -199,12 +887,54 @@ where
             .signature_schemes
             .push(SignatureScheme::EcdsaSecp384r1Sha384)
             .unwrap();
+        config
+            .signature_schemes
+            .push(SignatureScheme::EcdsaSecp521r1Sha512)
+            .unwrap();
         config
             .signature_schemes
             .push(SignatureScheme::Ed25519)
             .unwrap();
+        if cfg!(feature = "ed448") {
+            config
+                .signature_schemes
+                .push(SignatureScheme::Ed448)
+                .unwrap();
+        }
+        if cfg!(feature = "sm") {
+            config
+                .signature_schemes
+                .push(SignatureScheme::Sm2sigSm3)
+                .unwrap();
+        }
+        if cfg!(feature = "brainpool") {
+            config
+                .signature_schemes
+                .push(SignatureScheme::EcdsaBrainpoolP256r1tls13Sha256)
+                .unwrap();
+            config
+                .signature_schemes
+                .push(SignatureScheme::EcdsaBrainpoolP384r1tls13Sha384)
+                .unwrap();
+        }
 
         config.named_groups.push(NamedGroup::Secp256r1).unwrap();
+        config.named_groups.push(NamedGroup::X25519).unwrap();
+        config.named_groups.push(NamedGroup::Secp384r1).unwrap();
+        config.named_groups.push(NamedGroup::Secp521r1).unwrap();
+        if cfg!(feature = "sm") {
+            config.named_groups.push(NamedGroup::CurveSm2).unwrap();
+        }
+        if cfg!(feature = "brainpool") {
+            config
+                .named_groups
+                .push(NamedGroup::BrainpoolP256r1tls13)
+                .unwrap();
+            config
+                .named_groups
+                .push(NamedGroup::BrainpoolP384r1tls13)
+                .unwrap();
+        }
 
         config
     }
@@ -232,17 +962,133 @@ where
         self
     }
 
+    /// Replaces the signature schemes offered in the signature_algorithms extension, which the
+    /// server must use to sign the CertificateVerify (or the ServerKeyExchange with TLS 1.2).
+    ///
+    /// Without [`Self::with_signature_algorithms_cert`], the server may also pick its certificate
+    /// chain with these schemes, but the verifier accepts the signatures of the certificates with
+    /// any algorithm it implements: only the signature_algorithms_cert restrict them. Fails with
+    /// [`TlsError::InsufficientSpace`] for more than 16 schemes.
+    pub fn with_signature_algorithms(
+        mut self,
+        schemes: &[SignatureScheme],
+    ) -> Result<Self, TlsError> {
+        self.signature_schemes =
+            Vec::from_slice(schemes).map_err(|_| TlsError::InsufficientSpace)?;
+        Ok(self)
+    }
+
+    /// Offers `schemes` in the signature_algorithms_cert extension, for the signatures of the
+    /// certificates of the server, separately from the signature_algorithms used for the
+    /// CertificateVerify.
+    ///
+    /// This allows, for example, accepting a legacy intermediate signed with
+    /// [`SignatureScheme::RsaPkcs1Sha1`] while never accepting SHA-1 for the handshake itself.
+    /// The schemes are passed to the verifier, see
+    /// [`TlsVerifier::set_certificate_signature_schemes`]; the webpki verifier only accepts the
+    /// ones it implements, so SHA-1 requires a custom verifier. The extension is not sent by
+    /// default. Fails with [`TlsError::InsufficientSpace`] for more than 16 schemes.
+    pub fn with_signature_algorithms_cert(
+        mut self,
+        schemes: &[SignatureScheme],
+    ) -> Result<Self, TlsError> {
+        self.signature_schemes_cert =
+            Vec::from_slice(schemes).map_err(|_| TlsError::InsufficientSpace)?;
+        Ok(self)
+    }
+
+    /// The name of the server, which the certificate of the server is verified against and which
+    /// is sent in the server_name extension (SNI).
+    ///
+    /// The extension is not sent for an IPv4 or IPv6 address, such as `192.0.2.1`, see
+    /// [`Self::with_sni`] and [`Self::without_sni`] to send another name or none at all.
+    ///
+    /// With the `idna` feature, an internationalized name such as `bücher.example` is converted
+    /// to A-labels (`xn--bcher-kva.example`) for both uses, after its labels are lower-cased.
     pub fn with_server_name(mut self, server_name: &'a str) -> Self {
         self.server_name = Some(server_name);
+        self.sni = Some(server_name);
+        self
+    }
+
+    /// Sends `sni` in the server_name extension instead of the name given to
+    /// [`Self::with_server_name`], which is still used to verify the certificate, for example when
+    /// connecting to a server by its address. Must be called after [`Self::with_server_name`].
+    pub fn with_sni(mut self, sni: &'a str) -> Self {
+        self.sni = Some(sni);
+        self
+    }
+
+    /// Does not send the server_name extension, while the certificate is still verified against
+    /// the name given to [`Self::with_server_name`]. Must be called after
+    /// [`Self::with_server_name`].
+    pub fn without_sni(mut self) -> Self {
+        self.sni = None;
+        self
+    }
+
+    /// Offers application protocols in order of preference with ALPN, as described in
+    /// [RFC 7301](https://www.rfc-editor.org/rfc/rfc7301), such as `x-amzn-mqtt-ca` for MQTT
+    /// with AWS IoT Core on port 443, or `h2` and `http/1.1` for HTTP.
+    ///
+    /// The protocol selected by the server is returned by
+    /// [`negotiated_alpn`](crate::blocking::TlsConnection::negotiated_alpn) once the connection
+    /// is opened. The handshake is aborted if the server selects a protocol which was not offered.
+    /// A protocol longer than [`MAX_ALPN_PROTOCOL_LEN`] fails the handshake with
+    /// [`TlsError::InsufficientSpace`](crate::TlsError::InsufficientSpace) when it is selected.
+    pub fn with_alpn_protocols(mut self, protocols: &'a [&'a [u8]]) -> Self {
+        self.alpn_protocols = protocols;
+        self
+    }
+
+    /// Sends extensions which this crate does not implement, such as a private-use extension of
+    /// a vendor, as `(extension_type, extension_data)` pairs at the end of the ClientHello.
+    ///
+    /// The types must not be defined by [`ExtensionType`]. The extensions of these types which
+    /// the server echoes in EncryptedExtensions are returned by
+    /// [`custom_extension`](crate::blocking::TlsConnection::custom_extension) once the connection
+    /// is opened, other extensions of unknown types are left to
+    /// [`TlsConfig::with_unknown_extension_handler`]. Up to [`MAX_CUSTOM_EXTENSIONS`] extensions of at most
+    /// [`MAX_CUSTOM_EXTENSION_LEN`] bytes are kept, larger ones fail the handshake with
+    /// [`TlsError::InsufficientSpace`](crate::TlsError::InsufficientSpace). The extensions of a
+    /// TLS 1.2 ServerHello are not read back.
+    pub fn with_custom_extensions(mut self, extensions: &'a [(u16, &'a [u8])]) -> Self {
+        self.custom_extensions = extensions;
+        self
+    }
+
+    /// Hands the extensions of unknown types sent by the server, such as proprietary extensions,
+    /// to `handler` with the type of the message, the type of the extension and its data.
+    ///
+    /// The handler is called for the ServerHello, HelloRetryRequest, EncryptedExtensions and
+    /// CertificateRequest messages, and returns whether the extension is accepted. Otherwise, the
+    /// handshake is aborted with an unsupported_extension alert. Without a handler, unknown
+    /// extensions are ignored, unless [`TlsConfig::with_strict_validation`] is set: RFC 8446 then
+    /// requires that the extensions of the ServerHello, HelloRetryRequest and
+    /// EncryptedExtensions were requested by the client. The echoes of the
+    /// [custom extensions](TlsConfig::with_custom_extensions) are not handed to the handler, and
+    /// unknown extensions of a certificate or a NewSessionTicket are always ignored.
+    pub fn with_unknown_extension_handler(
+        mut self,
+        handler: fn(HandshakeType, u16, &[u8]) -> bool,
+    ) -> Self {
+        self.unknown_extension_handler = Some(UnknownExtensionHandler(handler));
         self
     }
 
     /// Configures the maximum plaintext fragment size.
     ///
     /// This option may help reduce memory size, as smaller fragment lengths require smaller
-    /// read/write buffers. Note that embedded-tls does not currently use this option to fragment
-    /// writes. Note that the buffers need to include some overhead over the configured fragment
-    /// length.
+    /// read/write buffers. Note that the buffers need to include some overhead over the configured
+    /// fragment length.
+    ///
+    /// Once the server has accepted the length, the application data written to the connection
+    /// is split in records of at most this length, and larger records of the server abort the
+    /// connection with a record_overflow alert. Handshake messages are not fragmented nor
+    /// reassembled, so the certificate messages of the client and the server must fit in one
+    /// record. A server which does not support the
+    /// extension ignores it, see
+    /// [`max_fragment_length`](crate::blocking::TlsConnection::max_fragment_length).
     ///
     /// From [RFC 6066, Section 4.  Maximum Fragment Length Negotiation](https://www.rfc-editor.org/rfc/rfc6066#page-8):
     ///
@@ -266,21 +1112,673 @@ where
         self
     }
 
+    /// Offers the heartbeat extension with `mode`, as described in
+    /// [RFC 6520](https://www.rfc-editor.org/rfc/rfc6520), which keeps the connection alive
+    /// without application data.
+    ///
+    /// If the server accepts the extension, heartbeat requests are sent with
+    /// [`send_heartbeat`](crate::blocking::TlsConnection::send_heartbeat), and the requests of
+    /// the server are answered while application data is read if `mode` is
+    /// [`HeartbeatMode::PeerAllowedToSend`]. The heartbeats of a split connection are only
+    /// handled once it is unsplit.
+    ///
+    /// The extension is not sent when TLS 1.2 is offered, since heartbeats are only implemented
+    /// for TLS 1.3.
+    pub fn with_heartbeat(mut self, mode: HeartbeatMode) -> Self {
+        self.heartbeat = Some(mode);
+        self
+    }
+
+    /// Requests an OCSP response stapled to the certificate of the server with the status_request
+    /// extension, as described in [RFC 6066, Section 8](https://www.rfc-editor.org/rfc/rfc6066#section-8),
+    /// which is the only revocation information of devices which cannot reach OCSP responders.
+    ///
+    /// Once the certificate of the server is received, `verifier` is called with the DER encoded
+    /// OCSPResponse, or `None` if the server did not staple one, the end-entity certificate and
+    /// the certificate which follows it in the chain, if any. The response is not parsed by this
+    /// crate: the verifier checks its signature, validity and the status of the certificate, and
+    /// returns whether the handshake goes on. Otherwise, the handshake is aborted with a
+    /// bad_certificate_status_response alert.
+    ///
+    /// The extension is not sent when TLS 1.2 is offered, since the CertificateStatus message of
+    /// TLS 1.2 is not supported, and the verifier is then called without a response.
+    pub fn with_ocsp_stapling(
+        mut self,
+        verifier: fn(Option<&[u8]>, &[u8], Option<&[u8]>) -> bool,
+    ) -> Self {
+        self.ocsp_verifier = Some(OcspVerifier(verifier));
+        self
+    }
+
+    /// Requests the Signed Certificate Timestamps (SCTs) of the certificate of the server with
+    /// the signed_certificate_timestamp extension, as described in
+    /// [RFC 6962, Section 3.3](https://www.rfc-editor.org/rfc/rfc6962#section-3.3), and requires
+    /// valid SCTs from at least `required` distinct logs of `logs`.
+    ///
+    /// SCTs of other logs are skipped, but an SCT of one of `logs` with an invalid signature
+    /// aborts the handshake with a bad_certificate alert, as does a certificate with too few SCTs.
+    /// Only logs with P-256 keys are supported, and the timestamps are not compared with the
+    /// current time.
+    ///
+    /// The extension is not sent when TLS 1.2 is offered, since the SCTs of a TLS 1.2 ServerHello
+    /// are not supported, and a TLS 1.2 server then has no SCT.
+    #[cfg(feature = "sct")]
+    pub fn with_certificate_transparency(mut self, logs: &'a [CtLog<'a>], required: usize) -> Self {
+        self.ct_logs = logs;
+        self.required_scts = required;
+        self
+    }
+
+    /// Offers zlib compression of the certificate chain of the server in the compress_certificate
+    /// extension, as described in [RFC 8879](https://www.rfc-editor.org/rfc/rfc8879).
+    ///
+    /// A compressed chain fits in a smaller read record buffer. It is decompressed at the end of
+    /// the write record buffer while it is verified, which must then be larger than the
+    /// uncompressed Certificate message, and a chain which does not fit aborts the handshake.
+    #[cfg(feature = "zlib")]
+    pub fn with_certificate_compression(mut self) -> Self {
+        self.certificate_compression = true;
+        self
+    }
+
+    /// Offers delegated credentials in the delegated_credential extension, as described in
+    /// [RFC 9345](https://www.rfc-editor.org/rfc/rfc9345), so that a server fronted by a CDN may
+    /// sign the handshake with a short-lived key delegated by its certificate.
+    ///
+    /// The credential is verified by the [`CertVerifier`](crate::webpki::CertVerifier): its
+    /// validity period, the DelegationUsage extension of the certificate and its signature by the
+    /// certificate, and the CertificateVerify is then verified with the delegated key. An invalid
+    /// credential aborts the handshake with an illegal_parameter alert. Only ECDSA keys over P-256
+    /// and P-384 are supported, and the validity period is not checked without a clock.
+    #[cfg(feature = "webpki")]
+    pub fn with_delegated_credentials(mut self) -> Self {
+        self.delegated_credentials = true;
+        self
+    }
+
+    /// Encrypts the ClientHello with Encrypted Client Hello (ECH), as described in
+    /// [draft-ietf-tls-esni](https://datatracker.ietf.org/doc/draft-ietf-tls-esni/), so that the
+    /// name of the server is hidden from the network. The ALPN protocols, the certificate_authorities
+    /// extension and the custom extensions are only sent in the encrypted ClientHelloInner, while
+    /// the other extensions, such as the key shares and the signature algorithms, are visible in
+    /// the ClientHelloOuter as without ECH.
+    ///
+    /// `config_list` is the ECHConfigList of the server, usually published in the ech parameter of
+    /// its HTTPS DNS record. The first config of the list with the X25519 KEM, HKDF-SHA256 and
+    /// AES-GCM is used, and the handshake fails with [`TlsError::Unimplemented`] if there is none,
+    /// rather than sending the name in the clear. The ClientHelloOuter sent on the wire carries
+    /// the public name of the config in the server_name extension.
+    ///
+    /// If the server rejects ECH, it is authenticated for the public name and the handshake
+    /// then fails with [`TlsError::EchRejected`]. The retry configs sent by the server, if any,
+    /// are available with
+    /// [`ech_retry_configs`](crate::blocking::TlsConnection::ech_retry_configs), to connect again
+    /// with them. ECH cannot be combined with PSKs, session tickets, early data or TLS 1.2, and
+    /// a HelloRetryRequest of the server is not supported. The tickets of a ticket store are not
+    /// used while ECH is configured.
+    ///
+    /// The ClientHelloInner is encoded in the write record buffer after the ClientHelloOuter, so
+    /// the buffer must fit the ClientHello twice.
+    #[cfg(feature = "ech")]
+    pub fn with_ech(mut self, config_list: &'a [u8]) -> Self {
+        self.ech = Some(config_list);
+        self
+    }
+
     pub fn with_ca(mut self, ca: Certificate<'a>) -> Self {
         self.ca = Some(ca);
         self
     }
 
+    /// A root store of DER encoded root certificates, which the certificate chain of the server
+    /// is validated against in addition to the CA given to [`with_ca`](Self::with_ca).
+    ///
+    /// A chain issued by any of the roots is accepted, so that a device can trust both the old
+    /// and the new CA of an operator while the certificates of the servers are rotated.
+    ///
+    /// The root certificates are passed to the verifier, see
+    /// [`TlsVerifier::verify_certificate_with_roots`]. With the webpki verifier, a path is built
+    /// from the certificate of the server through the intermediates sent along with it to one of
+    /// the roots, checking the signatures, validity periods, basic constraints, key usages and
+    /// name constraints of the certificates.
+    pub fn with_root_certificates(mut self, roots: &'a [&'a [u8]]) -> Self {
+        self.root_certificates = roots;
+        self
+    }
+
+    /// Pins the key of the server: the SHA-256 hash of the DER encoded SubjectPublicKeyInfo of its
+    /// end-entity certificate, or of its raw public key, must be one of `pins`. Otherwise, the
+    /// handshake is aborted with a bad_certificate alert.
+    ///
+    /// The pins are checked before the certificate is passed to the verifier, so they are layered
+    /// on the validation of the chain by a verifier such as `CertVerifier`. With
+    /// [`PinnedKeyVerifier`], the pinned key is trusted without a chain. A pin is computed with
+    /// `openssl x509 -pubkey -noout -in cert.pem | openssl pkey -pubin -outform der | sha256sum`,
+    /// and several pins let the key be rotated.
+    pub fn with_spki_pins(mut self, pins: &'a [[u8; 32]]) -> Self {
+        self.spki_pins = pins;
+        self
+    }
+
+    /// The source of the current time the validity periods of the certificates of the server
+    /// are checked against, for devices without a real-time clock which learn the time at
+    /// runtime, for example with SNTP.
+    ///
+    /// The time is passed to the verifier, see [`TlsVerifier::set_time`], and takes precedence
+    /// over the [`TlsClock`] of the webpki verifier.
+    pub fn with_time_provider(mut self, provider: &'a dyn TimeProvider) -> Self {
+        self.time_provider = Some(provider);
+        self
+    }
+
+    /// What the verifier does when the current time is unknown, neither given by the
+    /// [`TimeProvider`] of the configuration nor by the [`TlsClock`] of the verifier.
+    ///
+    /// By default, the validity periods of the certificates are not checked, which leaves
+    /// devices without a clock open to expired certificates. With [`MissingTime::Reject`], the
+    /// handshake fails with [`TlsError::UnknownTime`] until the time is known.
+    pub fn with_missing_time(mut self, missing_time: MissingTime) -> Self {
+        self.missing_time = missing_time;
+        self
+    }
+
+    /// Relaxes or tightens the verification of the certificate of the server for this
+    /// connection, see [`VerificationPolicy`].
+    ///
+    /// The policy is passed to the verifier, see [`TlsVerifier::set_verification_policy`].
+    pub fn with_verification_policy(mut self, policy: VerificationPolicy) -> Self {
+        self.verification_policy = policy;
+        self
+    }
+
+    /// Sends the subjects of the X.509 CA given to [`with_ca`](Self::with_ca) and of the root
+    /// certificates given to [`with_root_certificates`](Self::with_root_certificates) in the
+    /// certificate_authorities extension of the ClientHello, as described in
+    /// [RFC 8446, Section 4.2.4](https://www.rfc-editor.org/rfc/rfc8446#section-4.2.4), so that
+    /// a server hosting several certificates sends a chain issued by one of them.
+    ///
+    /// The extension is not sent without a CA or root certificates, or with a raw public key and
+    /// no root certificates, and the handshake fails with [`TlsError::InvalidCertificate`] if a
+    /// subject cannot be parsed.
+    pub fn with_certificate_authorities(mut self) -> Self {
+        self.certificate_authorities = true;
+        self
+    }
+
     pub fn with_cert(mut self, cert: Certificate<'a>) -> Self {
         self.cert = Some(cert);
         self
     }
 
+    /// Send a certificate chain when the server requests a client certificate, for devices whose
+    /// certificate is issued by an intermediate CA the server does not know.
+    ///
+    /// The chain starts with the client certificate, followed by the intermediate CA
+    /// certificates in order. The root CA may be omitted. A chain of more than 16 certificates
+    /// fails the handshake with [`TlsError::InsufficientSpace`](crate::TlsError::InsufficientSpace).
+    pub fn with_cert_chain(mut self, cert_chain: &'a [Certificate<'a>]) -> Self {
+        if let Some((cert, intermediates)) = cert_chain.split_first() {
+            self.cert = Some(cert.clone());
+            self.intermediates = intermediates;
+        }
+        self
+    }
+
+    /// Authenticate with a certificate chain and its private key when the server requests a
+    /// client certificate, as required by brokers such as AWS IoT Core.
+    ///
+    /// The chain is given as for [`TlsConfig::with_cert_chain`]. The CertificateVerify is signed
+    /// in software with the key, unless an external signer is given with
+    /// [`TlsContext::with_signer`](crate::blocking::TlsContext::with_signer).
+    pub fn with_client_identity(
+        mut self,
+        cert_chain: &'a [Certificate<'a>],
+        key: PrivateKey<'a>,
+    ) -> Self {
+        self.priv_key = Some(key);
+        self.with_cert_chain(cert_chain)
+    }
+
+    /// Provision several client identities, such as a factory and an operator certificate.
+    ///
+    /// When the server requests a client certificate, the first identity with a certificate
+    /// issued by one of the certificate authorities named in the CertificateRequest is used, or
+    /// the first identity if the server does not name any. If no identity matches, the
+    /// certificate configured with [`TlsConfig::with_client_identity`] or
+    /// [`TlsConfig::with_cert_chain`] is sent, if any, and an empty certificate otherwise.
+    ///
+    /// The issuers are compared with the encoded distinguished names, for each certificate of
+    /// the chain of an identity. If the server sends OID filters, such as the clientAuth extended
+    /// key usage, the end-entity certificate of the identity must also match the key usage and
+    /// extended key usage filters, while the filters of other extensions are ignored.
+    pub fn with_client_identities(mut self, identities: &'a [ClientIdentity<'a>]) -> Self {
+        self.identities = identities;
+        self
+    }
+
+    /// Limit the number of records sent under one traffic key, as required by
+    /// [RFC 8446, Section 5.5](https://www.rfc-editor.org/rfc/rfc8446#section-5.5).
+    ///
+    /// By default, a KeyUpdate is sent after [`RecordLimit::AES_GCM`] records. The KeyUpdate is
+    /// itself the last record under the key, so at least 2 records are required with
+    /// [`RecordLimitAction::KeyUpdate`].
+    pub fn with_record_limit(mut self, records: u64, action: RecordLimitAction) -> Self {
+        self.record_limit = RecordLimit::new(records, action);
+        self
+    }
+
+    /// Pads the encrypted records of TLS 1.3, as described in
+    /// [RFC 8446, Section 5.4](https://www.rfc-editor.org/rfc/rfc8446#section-5.4), so that the
+    /// length of the messages, such as telemetry readings, does not leak to an observer.
+    ///
+    /// The padding is appended to the content of every encrypted record from the second flight
+    /// of the handshake on, and is limited so that the record fits in the write record buffer and
+    /// the maximum fragment length. Records of TLS 1.2 are not padded.
+    pub fn with_record_padding(mut self, padding: RecordPadding) -> Self {
+        self.record_padding = Some(padding);
+        self
+    }
+
+    /// Configures the supported key exchange groups, in order of preference.
+    ///
+    /// A key share is only sent for the first group by default, so it should be one the server
+    /// is expected to support. Otherwise, the server asks for a key share of another group with a
+    /// HelloRetryRequest, which costs a round trip, see [`Self::with_key_shares`]. The
+    /// implemented groups are:
+    ///
+    /// * `Secp256r1`, `Secp384r1`, `Secp521r1` and `X25519`
+    /// * `X25519MlKem768` and `Secp256r1MlKem768` with the `mlkem` feature
+    /// * `Ffdhe2048` and `Ffdhe3072` with the `ffdhe` feature
+    /// * `BrainpoolP256r1tls13` and `BrainpoolP384r1tls13` with the `brainpool` feature
+    /// * `CurveSm2` with the `sm` feature
+    pub fn with_named_groups(mut self, named_groups: &[NamedGroup]) -> Self {
+        // TODO: Remove potential panic
+        self.named_groups = Vec::from_slice(named_groups).unwrap();
+        self
+    }
+
+    /// Sends a key share for each of the `count` most preferred named groups, up to
+    /// [`MAX_KEY_SHARES`], so that the server can pick one of them without a HelloRetryRequest.
+    ///
+    /// Every key share costs a key generation during the handshake, and the size of its public
+    /// key in the ClientHello. The default is a single key share.
+    pub fn with_key_shares(mut self, count: usize) -> Self {
+        self.key_shares = count.clamp(1, MAX_KEY_SHARES);
+        self
+    }
+
     pub fn with_psk(mut self, psk: &'a [u8], identities: &[&'a [u8]]) -> Self {
         // TODO: Remove potential panic
         self.psk = Some((psk, Vec::from_slice(identities).unwrap()));
         self
     }
+
+    /// The key exchange modes offered with a PSK or a session ticket, in the
+    /// psk_key_exchange_modes extension. The list must not be empty, the default is
+    /// [`PskKeyExchangeMode::PskDheKe`] only.
+    ///
+    /// Offering only [`PskKeyExchangeMode::PskKe`] skips the generation of the key shares when
+    /// a PSK is offered, at the cost of forward secrecy. A server which declines the PSK then
+    /// asks for a key share with a HelloRetryRequest.
+    pub fn with_psk_key_exchange_modes(mut self, modes: &[PskKeyExchangeMode]) -> Self {
+        // TODO: Remove potential panic
+        self.psk_key_exchange_modes = Vec::from_slice(modes).unwrap();
+        self
+    }
+
+    /// Resume a previous session with a ticket taken from its connection.
+    ///
+    /// The ticket is offered instead of any PSK configured with [`Self::with_psk`]. If the server
+    /// does not accept it, a full handshake is performed. Section C.4 of RFC 8446 recommends to
+    /// use a ticket only once.
+    pub fn with_session_ticket(mut self, ticket: &'a SessionTicket<CipherSuite>) -> Self {
+        self.session_ticket = Some(ticket);
+        self
+    }
+
+    /// Requests `new_session_count` tickets from the server after a full handshake, and
+    /// `resumption_count` tickets after a resumption, with the ticket_request extension of
+    /// [RFC 9149](https://www.rfc-editor.org/rfc/rfc9149). Servers without support for the
+    /// extension send the number of tickets of their choice.
+    ///
+    /// The number of tickets the server expects to send is available once the connection is
+    /// opened, see
+    /// [`expected_session_tickets`](crate::blocking::TlsConnection::expected_session_tickets).
+    /// The connection keeps only the last ticket received, so the tickets should be taken with
+    /// [`take_session_ticket`](crate::blocking::TlsConnection::take_session_ticket) as they arrive,
+    /// or put in a ticket store.
+    pub fn with_ticket_request(mut self, new_session_count: u8, resumption_count: u8) -> Self {
+        self.ticket_request = Some((new_session_count, resumption_count));
+        self
+    }
+
+    /// Data sent by [`open`](crate::blocking::TlsConnection::open) as soon as possible: as
+    /// 0-RTT early data if the session is resumed with a ticket allowing early data, or after the
+    /// handshake otherwise.
+    ///
+    /// The server limits the amount of early data to the
+    /// [`max_early_data_size`](SessionTicket::max_early_data_size) of the ticket. Only the
+    /// beginning of larger data is sent as early data, and the rest after the handshake.
+    ///
+    /// Early data saves a round trip, for example for the CONNECT packet of an MQTT client
+    /// reconnecting after sleep. It is sent again after the handshake if the server rejects it.
+    /// As early data is not protected against replays, it must be safe for the server to process
+    /// it more than once.
+    pub fn with_early_data(mut self, data: &'a [u8]) -> Self {
+        self.early_data = Some(data);
+        self
+    }
+
+    /// Makes the handshake look like a TLS 1.2 session resumption to middleboxes, as described in
+    /// [RFC 8446, Appendix D.4](https://www.rfc-editor.org/rfc/rfc8446#appendix-D.4).
+    ///
+    /// The ClientHello carries a random 32 bytes legacy_session_id, and a dummy
+    /// change_cipher_spec record is sent before the second flight of the client. Some middleboxes
+    /// drop TLS 1.3 handshakes otherwise. The change_cipher_spec records of the server are ignored
+    /// during the handshake in either case.
+    pub fn with_middlebox_compatibility(mut self) -> Self {
+        self.middlebox_compatibility = true;
+        self
+    }
+
+    /// Advertises reserved GREASE values in the ClientHello, as described in
+    /// [RFC 8701](https://www.rfc-editor.org/rfc/rfc8701).
+    ///
+    /// A random cipher suite, named group with its key share, supported version and two
+    /// extensions are added to the ClientHello, like browsers do. Servers must ignore them, so
+    /// this makes the ClientHello look more like the ones of browsers and checks that the server
+    /// tolerates unknown values.
+    pub fn with_grease(mut self) -> Self {
+        self.grease = true;
+        self
+    }
+
+    /// Pads the ClientHello to at least `len` bytes, with the padding extension of
+    /// [RFC 7685](https://www.rfc-editor.org/rfc/rfc7685).
+    ///
+    /// The length is the one of the handshake message, including its 4 bytes header. Padding to
+    /// 512 bytes works around middleboxes, such as some F5 load balancers, which hang on
+    /// ClientHellos of 256 to 511 bytes. Padding to a fixed length also hides which extensions,
+    /// such as a session ticket or a longer server name, a ClientHello carries. A ClientHello
+    /// which is already long enough is not padded, and the write record buffer must have room for
+    /// the padded ClientHello.
+    pub fn with_padding(mut self, len: usize) -> Self {
+        self.padding = Some(len);
+        self
+    }
+
+    /// Sends the extensions of the ClientHello in the given order, such as
+    /// [`BROWSER_EXTENSION_ORDER`], as some middleboxes block ClientHellos by their fingerprint
+    /// (JA3), which includes the order of the extensions.
+    ///
+    /// The extensions which are not in the order follow in the default order, then the custom
+    /// extensions, and the types which are not sent are skipped. The GREASE extensions stay first and last, and they are only
+    /// followed by the padding and pre_shared_key extensions, since RFC 8446 requires the
+    /// pre_shared_key extension to be the last one.
+    pub fn with_extension_order(mut self, order: &'a [ExtensionType]) -> Self {
+        self.extension_order = order;
+        self
+    }
+
+    /// Enforces the rules of RFC 8446 on the presence of the extensions of the server, beyond the
+    /// extensions allowed in each message, and aborts the handshake with the alert given by the
+    /// RFC otherwise:
+    ///
+    /// * A ServerHello or HelloRetryRequest without a supported_versions extension for TLS 1.3
+    /// * A ServerHello without a key_share extension
+    /// * An extension repeated in the same message
+    /// * A server_name or max_fragment_length extension which was not requested
+    /// * An extension of an unknown type in a ServerHello, HelloRetryRequest or
+    ///   EncryptedExtensions, without [`TlsConfig::with_unknown_extension_handler`]
+    /// * A CertificateRequest without a signature_algorithms extension
+    ///
+    /// Servers which do not follow these rules are tolerated by default. A key share for a group
+    /// which was not offered, or a pre-shared key which was not offered, always abort the
+    /// handshake.
+    pub fn with_strict_validation(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Offers TLS 1.2 along with TLS 1.3 in the supported_versions extension, for servers which do
+    /// not support TLS 1.3 yet, as described in
+    /// [RFC 5246](https://www.rfc-editor.org/rfc/rfc5246).
+    ///
+    /// The TLS 1.2 handshake uses the ECDHE-ECDSA-AES128-GCM-SHA256 cipher suite of
+    /// [RFC 5289](https://www.rfc-editor.org/rfc/rfc5289) with the configured groups and signature
+    /// schemes, so the cipher suite of the connection must be [`Aes128GcmSha256`]. Servers which
+    /// support TLS 1.3 still negotiate it, and the handshake is aborted if the downgrade
+    /// protection of TLS 1.3 detects that TLS 1.2 was forced by an attacker.
+    ///
+    /// Early data is not sent when TLS 1.2 is offered. Session resumption, renegotiation and
+    /// client certificates are not supported with TLS 1.2: the client answers a certificate
+    /// request with an empty certificate list.
+    #[cfg(feature = "tls12")]
+    pub fn with_tls12(mut self) -> Self {
+        self.tls12 = true;
+        self
+    }
+
+    /// The current time given by the time provider of [`TlsConfig::with_time_provider`], if any.
+    pub(crate) fn now(&self) -> Option<u64> {
+        self.time_provider.and_then(|provider| provider.now())
+    }
+
+    /// The X.509 CA given to [`TlsConfig::with_ca`], if any.
+    pub(crate) fn x509_ca(&self) -> Option<&'a [u8]> {
+        match self.ca {
+            Some(Certificate::X509(ca)) => Some(ca),
+            _ => None,
+        }
+    }
+
+    /// Whether the subjects of the CA and the root certificates are sent in the
+    /// certificate_authorities extension, see [`TlsConfig::with_certificate_authorities`].
+    pub(crate) fn offers_certificate_authorities(&self) -> bool {
+        self.certificate_authorities
+            && (self.x509_ca().is_some() || !self.root_certificates.is_empty())
+    }
+
+    /// Whether TLS 1.2 is offered in the ClientHello.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn offers_tls12(&self) -> bool {
+        self.tls12 && CipherSuite::TLS12_CODE_POINT.is_some()
+    }
+
+    /// The pinned public key of the server, when raw public keys are offered in the
+    /// server_certificate_type extension, see [`Certificate::RawPublicKey`].
+    pub(crate) fn server_raw_public_key(&self) -> Option<&'a [u8]> {
+        // The server_certificate_type extension of a TLS 1.2 ServerHello is not supported
+        #[cfg(feature = "tls12")]
+        if self.offers_tls12() {
+            return None;
+        }
+        match self.ca {
+            Some(Certificate::RawPublicKey(key)) => Some(key),
+            _ => None,
+        }
+    }
+
+    /// Whether the raw public key of the client is offered in the client_certificate_type
+    /// extension, see [`Certificate::RawPublicKey`].
+    pub(crate) fn offers_client_raw_public_key(&self) -> bool {
+        #[cfg(feature = "tls12")]
+        if self.offers_tls12() {
+            return false;
+        }
+        matches!(self.cert, Some(Certificate::RawPublicKey(_)))
+    }
+
+    /// The algorithms offered in the compress_certificate extension.
+    pub(crate) fn certificate_compression_algorithms(
+        &self,
+    ) -> &'static [CertificateCompressionAlgorithm] {
+        #[cfg(feature = "zlib")]
+        if self.certificate_compression {
+            return SUPPORTED_ALGORITHMS;
+        }
+        &[]
+    }
+
+    /// The signature schemes of the delegated credentials offered in the delegated_credential
+    /// extension.
+    pub(crate) fn delegated_credential_schemes(&self) -> &'static [SignatureScheme] {
+        #[cfg(feature = "webpki")]
+        if self.delegated_credentials {
+            return crate::delegated_credential::SUPPORTED_SCHEMES;
+        }
+        &[]
+    }
+
+    /// Whether ECH is configured, in which case the tickets of a ticket store are not used.
+    pub(crate) fn offers_ech(&self) -> bool {
+        #[cfg(feature = "ech")]
+        if self.ech.is_some() {
+            return true;
+        }
+        false
+    }
+
+    /// The ECHConfig used to encrypt the ClientHello, if ECH is configured.
+    #[cfg(feature = "ech")]
+    pub(crate) fn ech_config(&self) -> Result<Option<crate::ech::EchConfig<'a>>, TlsError> {
+        let Some(config_list) = self.ech else {
+            return Ok(None);
+        };
+        #[cfg(feature = "tls12")]
+        if self.offers_tls12() {
+            warn!("ECH is not supported along with TLS 1.2");
+            return Err(TlsError::Unimplemented);
+        }
+        if self.psk_identities() > 0 {
+            warn!("ECH is not supported along with PSKs");
+            return Err(TlsError::Unimplemented);
+        }
+        crate::ech::EchConfig::select(config_list).map(Some)
+    }
+
+    /// The name sent in the server_name extension, if any.
+    pub(crate) fn sni(&self) -> Option<&'a str> {
+        self.sni.filter(|name| !is_ip_literal(name))
+    }
+
+    /// Whether early data is offered in the ClientHello.
+    pub(crate) fn offers_early_data(&self) -> bool {
+        // A server negotiating TLS 1.2 would fail on the early data records
+        #[cfg(feature = "tls12")]
+        if self.offers_tls12() {
+            return false;
+        }
+        !self.zero_rtt_data().is_empty()
+    }
+
+    /// The part of the early data sent before the handshake, up to the max_early_data_size of
+    /// the session ticket.
+    pub(crate) fn zero_rtt_data(&self) -> &'a [u8] {
+        match (self.session_ticket, self.early_data) {
+            (Some(ticket), Some(data)) => {
+                let len = usize::min(data.len(), ticket.max_early_data_size() as usize);
+                &data[..len]
+            }
+            _ => &[],
+        }
+    }
+
+    /// The part of the early data to send after the handshake, depending on whether the server
+    /// accepted the data sent before.
+    pub(crate) fn remaining_early_data(&self, accepted: bool) -> &'a [u8] {
+        let data = self.early_data.unwrap_or_default();
+        if accepted {
+            &data[self.zero_rtt_data().len()..]
+        } else {
+            data
+        }
+    }
+
+    /// Whether the ClientHello needs key shares, which a PSK offered only for PSK-only key
+    /// establishment does not.
+    pub(crate) fn needs_key_shares(&self) -> bool {
+        self.psk_identities() == 0
+            || self
+                .psk_key_exchange_modes
+                .contains(&PskKeyExchangeMode::PskDheKe)
+    }
+
+    /// The number of PSK identities offered in the ClientHello, each with a binder.
+    pub(crate) fn psk_identities(&self) -> usize {
+        match (self.session_ticket, &self.psk) {
+            (Some(_), _) => 1,
+            (None, Some((_, identities))) => identities.len(),
+            (None, None) => 0,
+        }
+    }
+}
+
+/// RFC 6066, Section 3.  Server Name Indication
+/// Literal IPv4 and IPv6 addresses are not permitted in "HostName".
+///
+/// A host name never contains a colon, and its top-level label is not all-numeric.
+fn is_ip_literal(name: &str) -> bool {
+    name.contains(':') || name.bytes().all(|c| c.is_ascii_digit() || c == b'.')
+}
+
+// Not derived, as the cipher suite itself need not be Clone
+impl<'a, CipherSuite> Clone for TlsConfig<'a, CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn clone(&self) -> Self {
+        Self {
+            server_name: self.server_name,
+            sni: self.sni,
+            alpn_protocols: self.alpn_protocols,
+            custom_extensions: self.custom_extensions,
+            unknown_extension_handler: self.unknown_extension_handler,
+            ocsp_verifier: self.ocsp_verifier,
+            #[cfg(feature = "sct")]
+            ct_logs: self.ct_logs,
+            #[cfg(feature = "sct")]
+            required_scts: self.required_scts,
+            #[cfg(feature = "zlib")]
+            certificate_compression: self.certificate_compression,
+            #[cfg(feature = "webpki")]
+            delegated_credentials: self.delegated_credentials,
+            #[cfg(feature = "ech")]
+            ech: self.ech,
+            psk: self.psk.clone(),
+            psk_key_exchange_modes: self.psk_key_exchange_modes.clone(),
+            session_ticket: self.session_ticket,
+            ticket_request: self.ticket_request,
+            early_data: self.early_data,
+            cipher_suite: PhantomData,
+            signature_schemes: self.signature_schemes.clone(),
+            signature_schemes_cert: self.signature_schemes_cert.clone(),
+            named_groups: self.named_groups.clone(),
+            key_shares: self.key_shares,
+            max_fragment_length: self.max_fragment_length,
+            heartbeat: self.heartbeat,
+            ca: self.ca.clone(),
+            root_certificates: self.root_certificates,
+            spki_pins: self.spki_pins,
+            time_provider: self.time_provider,
+            missing_time: self.missing_time,
+            verification_policy: self.verification_policy,
+            certificate_authorities: self.certificate_authorities,
+            cert: self.cert.clone(),
+            intermediates: self.intermediates,
+            priv_key: self.priv_key.clone(),
+            identities: self.identities,
+            record_limit: self.record_limit,
+            record_padding: self.record_padding,
+            middlebox_compatibility: self.middlebox_compatibility,
+            grease: self.grease,
+            padding: self.padding,
+            extension_order: self.extension_order,
+            strict: self.strict,
+            #[cfg(feature = "tls12")]
+            tls12: self.tls12,
+        }
+    }
 }
 
 impl<'a, CipherSuite> Default for TlsConfig<'a, CipherSuite>
@@ -296,5 +1794,36 @@ where
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Certificate<'a> {
     X509(&'a [u8]),
+    /// A DER encoded SubjectPublicKeyInfo, as described in
+    /// [RFC 7250](https://www.rfc-editor.org/rfc/rfc7250).
+    ///
+    /// Given to [`TlsConfig::with_ca`], it is the pinned key of the server: the raw public key
+    /// certificate type is negotiated in the server_certificate_type extension, and the key sent
+    /// by the server is verified by [`RawPublicKeyVerifier`]. The handshake is aborted with an
+    /// unsupported_certificate alert if the server does not support raw public keys.
+    ///
+    /// Given to [`TlsConfig::with_client_identity`] or [`TlsConfig::with_cert`], it is the key of
+    /// the client, offered in the client_certificate_type extension for devices which carry a key
+    /// pair but no issued certificate. The key is sent if the server requests a client
+    /// certificate and accepted raw public keys, and an empty certificate otherwise.
+    ///
+    /// Raw public keys are not offered when TLS 1.2 is.
     RawPublicKey(&'a [u8]),
 }
+
+/// A client certificate chain along with its private key, see
+/// [`TlsConfig::with_client_identities`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClientIdentity<'a> {
+    pub(crate) cert_chain: &'a [Certificate<'a>],
+    pub(crate) key: PrivateKey<'a>,
+}
+
+impl<'a> ClientIdentity<'a> {
+    /// The chain starts with the client certificate, followed by the intermediate CA
+    /// certificates, if any.
+    pub fn new(cert_chain: &'a [Certificate<'a>], key: PrivateKey<'a>) -> Self {
+        Self { cert_chain, key }
+    }
+}
@@ -1,23 +1,32 @@
-use crate::config::{TlsCipherSuite, TlsConfig, TlsVerifier};
+use crate::config::{
+    Certificate, CertificateContext, ClientIdentity, SignatureContext, TlsCipherSuite, TlsConfig,
+    TlsVerifier, MAX_ALPN_PROTOCOL_LEN, MAX_KEY_SHARES, TLS_RECORD_ALIGNMENT, TLS_RECORD_OVERHEAD,
+};
 use crate::handshake::{ClientHandshake, ServerHandshake};
 use crate::key_schedule::{HashOutputSize, KeySchedule, ReadKeySchedule, WriteKeySchedule};
-use crate::record::{ClientRecord, ServerRecord};
+use crate::record::{ClientRecord, RecordHeader, ServerRecord};
 use crate::record_reader::RecordReader;
 use crate::write_buffer::WriteBuffer;
 use crate::TlsError;
 use crate::{
     alert::*,
-    handshake::{certificate::CertificateRef, certificate_request::CertificateRequest},
+    handshake::{
+        certificate::{CertificateEntryRef, CertificateRef},
+        certificate_request::{CertificateRequest, CertificateRequestRef},
+    },
 };
 use core::fmt::Debug;
 use embedded_io::Error as _;
-use rand_core::{CryptoRng, RngCore};
 
 use embedded_io::blocking::{Read as BlockingRead, Write as BlockingWrite};
 
 #[cfg(feature = "async")]
 use embedded_io::asynch::{Read as AsyncRead, Write as AsyncWrite};
 
+#[cfg(feature = "async")]
+use crate::signer::asynch::ExternalSigner as AsyncSigner;
+use crate::signer::blocking::ExternalSigner as BlockingSigner;
+
 use crate::application_data::ApplicationData;
 // use crate::handshake::certificate_request::CertificateRequest;
 // use crate::handshake::certificate_verify::CertificateVerify;
@@ -26,8 +35,30 @@ use crate::application_data::ApplicationData;
 // use crate::handshake::new_session_ticket::NewSessionTicket;
 // use crate::handshake::server_hello::ServerHello;
 use crate::buffer::CryptoBuffer;
-use digest::generic_array::typenum::Unsigned;
-use p256::ecdh::EphemeralSecret;
+use crate::certificate_types::CertificateType;
+use crate::change_cipher_spec::ChangeCipherSpec;
+use crate::crypto_provider::CryptoProvider;
+#[cfg(feature = "ech")]
+use crate::ech::{EchState, EchStatus, MAX_RETRY_CONFIGS_LEN};
+use crate::extensions::client::PskKeyExchangeMode;
+use crate::extensions::{unknown_extensions, CustomExtensions, ExtensionType, ExtensionTypes};
+use crate::grease::Grease;
+use crate::handshake::certificate_verify::CertificateVerify;
+use crate::handshake::client_hello::ClientHello;
+use crate::handshake::encrypted_extensions::EncryptedExtensions;
+use crate::handshake::key_update::{KeyUpdate, KeyUpdateRequest};
+use crate::handshake::server_hello::{HelloRetryRequest, ServerHello};
+use crate::handshake::{HandshakeType, Random};
+use crate::heartbeat::{HeartbeatMessage, HeartbeatMode};
+#[cfg(feature = "ech")]
+use crate::kdf::constant_time_eq;
+use crate::max_fragment_length::MaxFragmentLength;
+use crate::named_groups::NamedGroup;
+use crate::rng::{CheckedRng, TlsRng};
+use crate::signature_schemes::SignatureScheme;
+use crate::signer::{CLIENT_CONTEXT, MAX_SIGNATURE_LEN};
+use crate::supported_versions::TLS13;
+use rand_core::RngCore;
 use sha2::Digest;
 
 use crate::content_types::ContentType;
@@ -38,7 +69,7 @@ use crate::content_types::ContentType;
 // use crate::handshake::new_session_ticket::NewSessionTicket;
 // use crate::handshake::server_hello::ServerHello;
 use crate::parse_buffer::ParseBuffer;
-use aes_gcm::aead::{AeadCore, AeadInPlace, KeyInit};
+use crate::record_aead::{record_engine, DynRecordAead, RecordAead};
 
 pub(crate) fn decrypt_record<CipherSuite>(
     key_schedule: &mut ReadKeySchedule<CipherSuite>,
@@ -51,37 +82,17 @@ pub(crate) fn decrypt_record<CipherSuite>(
 where
     CipherSuite: TlsCipherSuite,
 {
-    if let ServerRecord::ApplicationData(ApplicationData {
-        header,
-        data: mut app_data,
-    }) = record
-    {
-        let server_key = key_schedule.get_key()?;
-        let nonce = key_schedule.get_nonce()?;
-
-        let crypto = <CipherSuite::Cipher as KeyInit>::new(&server_key);
-        crypto
-            .decrypt_in_place(&nonce, header.data(), &mut app_data)
-            .map_err(|_| TlsError::CryptoError)?;
-
-        let padding = app_data
-            .as_slice()
-            .iter()
-            .enumerate()
-            .rfind(|(_, b)| **b != 0);
-        if let Some((index, _)) = padding {
-            app_data.truncate(index + 1);
-        };
-
-        let content_type =
-            ContentType::of(*app_data.as_slice().last().unwrap()).ok_or(TlsError::InvalidRecord)?;
-
-        trace!("Decrypting: content type = {:?}", content_type);
-
-        // Remove the content type
-        app_data.truncate(app_data.len() - 1);
+    if let ServerRecord::ApplicationData(ApplicationData { header, data }) = record {
+        let (content_type, app_data) = open_protected_record(key_schedule, &header, data)?;
 
         match content_type {
+            // RFC 5246, Section 7.4.1.1.  Hello Request
+            // This message MAY be ignored by the client if it does not wish to renegotiate a
+            // session, or the client may, if it wishes, respond with a no_renegotiation alert.
+            #[cfg(feature = "tls12")]
+            ContentType::Handshake if key_schedule.is_tls12() => {
+                warn!("Ignoring a handshake message, renegotiation is not supported");
+            }
             ContentType::Handshake => {
                 // Decode potentially coaleced handshake messages
                 let data = app_data.as_slice();
@@ -115,9 +126,13 @@ where
                 let alert = Alert::parse(&mut buf)?;
                 cb(key_schedule, ServerRecord::Alert(alert))?;
             }
+            // Heartbeats are handled here, so that the requests of the server are answered
+            // whatever is being read
+            ContentType::Heartbeat => key_schedule.heartbeat().receive(app_data.as_slice())?,
             _ => return Err(TlsError::Unimplemented),
         }
-        key_schedule.increment_counter();
+        key_schedule.increment_counter()?;
+        key_schedule.apply_key_update()?;
     } else {
         trace!("Not decrypting: content_type = {:?}", record.content_type());
         cb(key_schedule, record)?;
@@ -125,6 +140,64 @@ where
     Ok(())
 }
 
+/// Decrypts a protected record of the server in place, and returns its content type along with
+/// the plaintext.
+pub(crate) fn open_protected_record<'a, CipherSuite>(
+    key_schedule: &ReadKeySchedule<CipherSuite>,
+    header: &RecordHeader,
+    mut data: CryptoBuffer<'a>,
+) -> Result<(ContentType, CryptoBuffer<'a>), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    #[cfg(feature = "tls12")]
+    if key_schedule.is_tls12() {
+        let (content_type, data) = crate::tls12::open_record(key_schedule, header, data)?;
+        key_schedule.check_fragment_length(data.len())?;
+        return Ok((content_type, data));
+    }
+
+    let server_key = key_schedule.get_key()?;
+    let nonce = key_schedule.get_nonce()?;
+
+    let crypto = <CipherSuite::Cipher as RecordAead>::new(&server_key);
+    let content_type = open_record(record_engine(&crypto), &nonce, header.data(), &mut data)?;
+    key_schedule.check_fragment_length(data.len())?;
+    Ok((content_type, data))
+}
+
+/// Decrypts a record in place, and strips the padding and the content type.
+fn open_record<Aead>(
+    crypto: &Aead,
+    nonce: &[u8],
+    header: &[u8],
+    app_data: &mut CryptoBuffer<'_>,
+) -> Result<ContentType, TlsError>
+where
+    Aead: DynRecordAead + ?Sized,
+{
+    crypto
+        .open(nonce, header, app_data)
+        .map_err(|_| TlsError::BadRecordMac)?;
+
+    // Section 5.4.  Record Padding
+    // If a receiving implementation does not find a non-zero octet in the cleartext, it MUST
+    // terminate the connection with an "unexpected_message" alert.
+    let (index, content_type) = app_data
+        .as_slice()
+        .iter()
+        .enumerate()
+        .rfind(|(_, b)| **b != 0)
+        .ok_or(TlsError::InvalidRecord)?;
+    let content_type = ContentType::of(*content_type).ok_or(TlsError::InvalidRecord)?;
+
+    trace!("Decrypting: content type = {:?}", content_type);
+
+    // Remove the padding and the content type
+    app_data.truncate(index);
+    Ok(content_type)
+}
+
 pub(crate) fn encrypt<CipherSuite>(
     key_schedule: &mut WriteKeySchedule<CipherSuite>,
     buf: &mut CryptoBuffer<'_>,
@@ -132,14 +205,27 @@ pub(crate) fn encrypt<CipherSuite>(
 where
     CipherSuite: TlsCipherSuite,
 {
+    key_schedule.check_record_limit()?;
     let client_key = key_schedule.get_key()?;
     let nonce = key_schedule.get_nonce()?;
     // trace!("encrypt key {:02x?}", client_key);
     // trace!("encrypt nonce {:02x?}", nonce);
     // trace!("plaintext {} {:02x?}", buf.len(), buf.as_slice(),);
     //let crypto = Aes128Gcm::new_varkey(&self.key_schedule.get_client_key()).unwrap();
-    let crypto = <CipherSuite::Cipher as KeyInit>::new(&client_key);
-    let len = buf.len() + <CipherSuite::Cipher as AeadCore>::TagSize::to_usize();
+    let crypto = <CipherSuite::Cipher as RecordAead>::new(&client_key);
+    seal_record(record_engine(&crypto), &nonce, buf)
+}
+
+/// Encrypts the inner plaintext of a record in place, appending the authentication tag.
+fn seal_record<Aead>(
+    crypto: &Aead,
+    nonce: &[u8],
+    buf: &mut CryptoBuffer<'_>,
+) -> Result<(), TlsError>
+where
+    Aead: DynRecordAead + ?Sized,
+{
+    let len = buf.len() + crypto.tag_len();
 
     if len > buf.capacity() {
         return Err(TlsError::InsufficientSpace);
@@ -156,65 +242,183 @@ where
     ];
 
     crypto
-        .encrypt_in_place(&nonce, &additional_data, buf)
+        .seal(nonce, &additional_data, buf)
         .map_err(|_| TlsError::InvalidApplicationData)
 }
 
-pub struct Handshake<CipherSuite, Verifier>
+/// The I/O provider and the record buffers of a closed connection, to reuse them for another
+/// connection or for plaintext traffic.
+///
+/// The write record buffer is returned without the bytes skipped at its start to align the
+/// records.
+pub struct TlsParts<'a, Socket> {
+    pub socket: Socket,
+    pub record_read_buf: &'a mut [u8],
+    pub record_write_buf: &'a mut [u8],
+}
+
+pub struct Handshake<CipherSuite, Provider, Verifier>
 where
     CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
 {
     traffic_hash: Option<CipherSuite::Hash>,
-    secret: Option<EphemeralSecret>,
+    /// The transcript up to the Certificate of the server, which its CertificateVerify signs.
+    certificate_hash: Option<CipherSuite::Hash>,
+    secrets: heapless::Vec<Provider::KeyExchange, MAX_KEY_SHARES>,
+    random: Random,
+    session_id: Option<Random>,
+    grease: Option<Grease>,
+    hello_retry: Option<HelloRetryRequest>,
     certificate_request: Option<CertificateRequest>,
     verifier: Verifier,
+    psk_accepted: bool,
+    client_raw_public_key: bool,
+    early_data_offered: bool,
+    early_data_accepted: bool,
+    alpn_protocol: heapless::Vec<u8, MAX_ALPN_PROTOCOL_LEN>,
+    custom_extensions: CustomExtensions,
+    change_cipher_spec_sent: bool,
+    #[cfg(feature = "tls12")]
+    tls12: Option<crate::tls12::Tls12Handshake>,
+    #[cfg(feature = "ech")]
+    ech: Option<EchState>,
+    /// The verifier of the public name of the ECHConfig, which replaces the verifier when the
+    /// server rejects ECH.
+    #[cfg(feature = "ech")]
+    ech_verifier: Option<Verifier>,
 }
 
-impl<'v, CipherSuite, Verifier> Handshake<CipherSuite, Verifier>
+impl<'v, CipherSuite, Provider, Verifier> Handshake<CipherSuite, Provider, Verifier>
 where
     CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
     Verifier: TlsVerifier<'v, CipherSuite>,
 {
-    pub fn new(verifier: Verifier) -> Handshake<CipherSuite, Verifier> {
+    /// Create the handshake state, with an optional key share generated in advance.
+    pub fn new(
+        verifier: Verifier,
+        key_share: Option<Provider::KeyExchange>,
+    ) -> Handshake<CipherSuite, Provider, Verifier> {
         Handshake {
             traffic_hash: None,
-            secret: None,
+            certificate_hash: None,
+            secrets: key_share.into_iter().collect(),
+            random: [0; 32],
+            session_id: None,
+            grease: None,
+            hello_retry: None,
             certificate_request: None,
             verifier,
+            psk_accepted: false,
+            client_raw_public_key: false,
+            early_data_offered: false,
+            early_data_accepted: false,
+            alpn_protocol: heapless::Vec::new(),
+            custom_extensions: CustomExtensions::default(),
+            change_cipher_spec_sent: false,
+            #[cfg(feature = "tls12")]
+            tls12: None,
+            #[cfg(feature = "ech")]
+            ech: None,
+            #[cfg(feature = "ech")]
+            ech_verifier: None,
         }
     }
+
+    /// Set the verifier of the public name of the ECHConfig, which authenticates the server when
+    /// it rejects Encrypted Client Hello.
+    #[cfg(feature = "ech")]
+    pub fn set_ech_verifier(&mut self, verifier: Verifier) {
+        self.ech_verifier = Some(verifier);
+    }
+
+    /// Take the retry configs sent by a server which rejected Encrypted Client Hello, empty if
+    /// none were sent.
+    #[cfg(feature = "ech")]
+    pub fn take_ech_retry_configs(&mut self) -> heapless::Vec<u8, MAX_RETRY_CONFIGS_LEN> {
+        self.ech
+            .as_mut()
+            .map(|ech| core::mem::take(&mut ech.retry_configs))
+            .unwrap_or_default()
+    }
+
+    /// Whether the server accepted an offered PSK, such as a session ticket.
+    pub fn psk_accepted(&self) -> bool {
+        self.psk_accepted
+    }
+
+    /// Whether the server accepted the early data of the configuration. Otherwise, the data
+    /// must be sent after the handshake.
+    pub fn early_data_accepted(&self) -> bool {
+        self.early_data_accepted
+    }
+
+    /// The application protocol selected by the server with ALPN, empty if none was selected.
+    pub fn alpn_protocol(&self) -> &heapless::Vec<u8, MAX_ALPN_PROTOCOL_LEN> {
+        &self.alpn_protocol
+    }
+
+    /// The custom extensions echoed by the server.
+    pub fn custom_extensions(&self) -> &CustomExtensions {
+        &self.custom_extensions
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum State {
     ClientHello,
+    EarlyData,
     ServerHello,
     ServerVerify,
+    EndOfEarlyData,
     ClientCert,
+    ClientCertVerify,
     ClientFinished,
     ApplicationData,
+    /// The server messages of a TLS 1.2 handshake, up to the ServerHelloDone.
+    #[cfg(feature = "tls12")]
+    Tls12ServerFlight,
+    /// The client messages of a TLS 1.2 handshake, up to the Finished.
+    #[cfg(feature = "tls12")]
+    Tls12ClientFlight,
+    /// The ChangeCipherSpec and the Finished of the server in a TLS 1.2 handshake.
+    #[cfg(feature = "tls12")]
+    Tls12ServerFinished,
 }
 
 impl<'a> State {
     #[cfg(feature = "async")]
     #[allow(clippy::too_many_arguments)]
-    pub async fn process<'v, Transport, CipherSuite, RNG, Verifier>(
+    pub async fn process<'v, Transport, CipherSuite, Provider, RNG, Verifier, Signer>(
         self,
         transport: &mut Transport,
-        handshake: &mut Handshake<CipherSuite, Verifier>,
+        handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
         record_reader: &mut RecordReader<'_, CipherSuite>,
         tx_buf: &mut WriteBuffer<'_>,
         key_schedule: &mut KeySchedule<CipherSuite>,
         config: &TlsConfig<'a, CipherSuite>,
         rng: &mut RNG,
+        signer: Option<&mut Signer>,
     ) -> Result<State, TlsError>
     where
         Transport: AsyncRead + AsyncWrite + 'a,
-        RNG: CryptoRng + RngCore + 'a,
+        RNG: TlsRng + 'a,
         CipherSuite: TlsCipherSuite,
+        Provider: CryptoProvider,
         Verifier: TlsVerifier<'v, CipherSuite>,
+        Signer: AsyncSigner,
     {
+        // The record is not protected, so it does not count as a record sent with the current
+        // key, and it is flushed along with the next flight.
+        if let Some(tx) = change_cipher_spec(self, handshake, key_schedule, config, tx_buf)? {
+            transport
+                .write_all(tx)
+                .await
+                .map_err(|e| TlsError::Io(e.kind()))?;
+        }
+
         match self {
             State::ClientHello => {
                 let (state, tx) = client_hello(key_schedule, config, rng, tx_buf, handshake)?;
@@ -223,30 +427,74 @@ impl<'a> State {
 
                 Ok(state)
             }
+            State::EarlyData => {
+                let tx = early_data(key_schedule, config, tx_buf)?;
+
+                respond(tx, transport, key_schedule).await?;
+
+                Ok(State::ServerHello)
+            }
             State::ServerHello => {
                 let record = record_reader
                     .read(transport, key_schedule.read_state())
                     .await?;
-                let result = process_server_hello(handshake, key_schedule, record);
-
-                handle_processing_error(result, transport, key_schedule, tx_buf).await
+                process_server_hello(handshake, key_schedule, config, record)
             }
             State::ServerVerify => {
                 let record = record_reader
                     .read(transport, key_schedule.read_state())
                     .await?;
 
-                let result = process_server_verify(handshake, key_schedule, config, record);
+                process_server_verify(handshake, key_schedule, config, tx_buf, record)
+            }
+            State::EndOfEarlyData => {
+                // The message is protected with the early traffic keys, which also count it
+                key_schedule.write_state().swap_early_traffic_keys();
+                let (state, tx) = end_of_early_data(handshake, key_schedule, tx_buf)?;
+
+                respond(tx, transport, key_schedule).await?;
+                key_schedule.write_state().swap_early_traffic_keys();
 
-                handle_processing_error(result, transport, key_schedule, tx_buf).await
+                Ok(state)
             }
             State::ClientCert => {
-                let (state, tx) = client_cert(handshake, key_schedule, config, tx_buf)?;
+                let (state, tx) =
+                    client_cert(handshake, key_schedule, config, tx_buf, signer.is_some())?;
 
                 respond(tx, transport, key_schedule).await?;
 
                 Ok(state)
             }
+            State::ClientCertVerify => {
+                // See MIN_CLIENT_AUTH_WRITE_BUFFER_LEN
+                let (mut tx_buf, scratch) = tx_buf.split_scratch(CERT_VERIFY_SCRATCH_LEN)?;
+                let (message, signature) = scratch.split_at_mut(CERT_VERIFY_MESSAGE_LEN);
+                let message = client_cert_verify_message(key_schedule, message)?;
+                // The key of a selected identity, or else an external signer, takes precedence over
+                // the key of the configuration
+                let (signature_scheme, len) = match (client_identity(handshake, config), signer) {
+                    (Some(identity), _) => (
+                        identity.key.signature_scheme(),
+                        identity.key.sign(message, signature)?,
+                    ),
+                    (None, Some(signer)) => (
+                        signer.signature_scheme(),
+                        signer.sign(message, signature).await?,
+                    ),
+                    (None, None) => {
+                        let key = config.priv_key.as_ref().ok_or(TlsError::InvalidHandshake)?;
+                        (key.signature_scheme(), key.sign(message, signature)?)
+                    }
+                };
+                let signature = signature.get(..len).ok_or(TlsError::InsufficientSpace)?;
+
+                let tx =
+                    client_cert_verify(key_schedule, signature_scheme, signature, &mut tx_buf)?;
+
+                respond(tx, transport, key_schedule).await?;
+
+                Ok(State::ClientFinished)
+            }
             State::ClientFinished => {
                 let tx = client_finished(key_schedule, tx_buf)?;
 
@@ -255,26 +503,67 @@ impl<'a> State {
                 client_finished_finalize(key_schedule, handshake)
             }
             State::ApplicationData => Ok(State::ApplicationData),
+            #[cfg(feature = "tls12")]
+            State::Tls12ServerFlight => {
+                let (header, data) = record_reader.read_raw(transport).await?;
+                process_tls12_server_flight(handshake, key_schedule, config, header, data)
+            }
+            #[cfg(feature = "tls12")]
+            State::Tls12ClientFlight => {
+                if let Some(tx) = tls12_client_certificate(handshake, key_schedule, tx_buf)? {
+                    respond(tx, transport, key_schedule).await?;
+                }
+                let tx = tls12_client_key_exchange(handshake, key_schedule, rng, tx_buf)?;
+                respond(tx, transport, key_schedule).await?;
+
+                // The record is not protected, the sequence number starts over with the keys
+                let tx = tls12_change_cipher_spec(key_schedule, tx_buf)?;
+                transport
+                    .write_all(tx)
+                    .await
+                    .map_err(|e| TlsError::Io(e.kind()))?;
+
+                let tx = tls12_client_finished(handshake, key_schedule, tx_buf)?;
+                respond(tx, transport, key_schedule).await?;
+
+                Ok(State::Tls12ServerFinished)
+            }
+            #[cfg(feature = "tls12")]
+            State::Tls12ServerFinished => {
+                let record = record_reader
+                    .read(transport, key_schedule.read_state())
+                    .await?;
+                process_tls12_server_finished(handshake, key_schedule, record)
+            }
         }
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub fn process_blocking<'v, Transport, CipherSuite, RNG, Verifier>(
+    pub fn process_blocking<'v, Transport, CipherSuite, Provider, RNG, Verifier, Signer>(
         self,
         transport: &mut Transport,
-        handshake: &mut Handshake<CipherSuite, Verifier>,
+        handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
         record_reader: &mut RecordReader<'_, CipherSuite>,
         tx_buf: &mut WriteBuffer,
         key_schedule: &mut KeySchedule<CipherSuite>,
         config: &TlsConfig<'a, CipherSuite>,
         rng: &mut RNG,
+        signer: Option<&mut Signer>,
     ) -> Result<State, TlsError>
     where
         Transport: BlockingRead + BlockingWrite + 'a,
-        RNG: CryptoRng + RngCore,
+        RNG: TlsRng,
         CipherSuite: TlsCipherSuite + 'static,
+        Provider: CryptoProvider,
         Verifier: TlsVerifier<'v, CipherSuite>,
+        Signer: BlockingSigner,
     {
+        if let Some(tx) = change_cipher_spec(self, handshake, key_schedule, config, tx_buf)? {
+            transport
+                .write_all(tx)
+                .map_err(|e| TlsError::Io(e.kind()))?;
+        }
+
         match self {
             State::ClientHello => {
                 let (state, tx) = client_hello(key_schedule, config, rng, tx_buf, handshake)?;
@@ -283,27 +572,70 @@ impl<'a> State {
 
                 Ok(state)
             }
+            State::EarlyData => {
+                let tx = early_data(key_schedule, config, tx_buf)?;
+
+                respond_blocking(tx, transport, key_schedule)?;
+
+                Ok(State::ServerHello)
+            }
             State::ServerHello => {
                 let record = record_reader.read_blocking(transport, key_schedule.read_state())?;
 
-                let result = process_server_hello(handshake, key_schedule, record);
-
-                handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                process_server_hello(handshake, key_schedule, config, record)
             }
             State::ServerVerify => {
                 let record = record_reader.read_blocking(transport, key_schedule.read_state())?;
 
-                let result = process_server_verify(handshake, key_schedule, config, record);
+                process_server_verify(handshake, key_schedule, config, tx_buf, record)
+            }
+            State::EndOfEarlyData => {
+                // The message is protected with the early traffic keys, which also count it
+                key_schedule.write_state().swap_early_traffic_keys();
+                let (state, tx) = end_of_early_data(handshake, key_schedule, tx_buf)?;
+
+                respond_blocking(tx, transport, key_schedule)?;
+                key_schedule.write_state().swap_early_traffic_keys();
 
-                handle_processing_error_blocking(result, transport, key_schedule, tx_buf)
+                Ok(state)
             }
             State::ClientCert => {
-                let (state, tx) = client_cert(handshake, key_schedule, config, tx_buf)?;
+                let (state, tx) =
+                    client_cert(handshake, key_schedule, config, tx_buf, signer.is_some())?;
 
                 respond_blocking(tx, transport, key_schedule)?;
 
                 Ok(state)
             }
+            State::ClientCertVerify => {
+                // See MIN_CLIENT_AUTH_WRITE_BUFFER_LEN
+                let (mut tx_buf, scratch) = tx_buf.split_scratch(CERT_VERIFY_SCRATCH_LEN)?;
+                let (message, signature) = scratch.split_at_mut(CERT_VERIFY_MESSAGE_LEN);
+                let message = client_cert_verify_message(key_schedule, message)?;
+                // The key of a selected identity, or else an external signer, takes precedence over
+                // the key of the configuration
+                let (signature_scheme, len) = match (client_identity(handshake, config), signer) {
+                    (Some(identity), _) => (
+                        identity.key.signature_scheme(),
+                        identity.key.sign(message, signature)?,
+                    ),
+                    (None, Some(signer)) => {
+                        (signer.signature_scheme(), signer.sign(message, signature)?)
+                    }
+                    (None, None) => {
+                        let key = config.priv_key.as_ref().ok_or(TlsError::InvalidHandshake)?;
+                        (key.signature_scheme(), key.sign(message, signature)?)
+                    }
+                };
+                let signature = signature.get(..len).ok_or(TlsError::InsufficientSpace)?;
+
+                let tx =
+                    client_cert_verify(key_schedule, signature_scheme, signature, &mut tx_buf)?;
+
+                respond_blocking(tx, transport, key_schedule)?;
+
+                Ok(State::ClientFinished)
+            }
             State::ClientFinished => {
                 let tx = client_finished(key_schedule, tx_buf)?;
 
@@ -312,31 +644,127 @@ impl<'a> State {
                 client_finished_finalize(key_schedule, handshake)
             }
             State::ApplicationData => Ok(State::ApplicationData),
+            #[cfg(feature = "tls12")]
+            State::Tls12ServerFlight => {
+                let (header, data) = record_reader.read_raw_blocking(transport)?;
+                process_tls12_server_flight(handshake, key_schedule, config, header, data)
+            }
+            #[cfg(feature = "tls12")]
+            State::Tls12ClientFlight => {
+                if let Some(tx) = tls12_client_certificate(handshake, key_schedule, tx_buf)? {
+                    respond_blocking(tx, transport, key_schedule)?;
+                }
+                let tx = tls12_client_key_exchange(handshake, key_schedule, rng, tx_buf)?;
+                respond_blocking(tx, transport, key_schedule)?;
+
+                // The record is not protected, the sequence number starts over with the keys
+                let tx = tls12_change_cipher_spec(key_schedule, tx_buf)?;
+                transport
+                    .write_all(tx)
+                    .map_err(|e| TlsError::Io(e.kind()))?;
+
+                let tx = tls12_client_finished(handshake, key_schedule, tx_buf)?;
+                respond_blocking(tx, transport, key_schedule)?;
+
+                Ok(State::Tls12ServerFinished)
+            }
+            #[cfg(feature = "tls12")]
+            State::Tls12ServerFinished => {
+                let record = record_reader.read_blocking(transport, key_schedule.read_state())?;
+                process_tls12_server_finished(handshake, key_schedule, record)
+            }
         }
     }
 }
 
-fn handle_processing_error_blocking<CipherSuite>(
-    result: Result<State, TlsError>,
-    transport: &mut impl BlockingWrite,
-    key_schedule: &mut KeySchedule<CipherSuite>,
-    tx_buf: &mut WriteBuffer,
-) -> Result<State, TlsError>
+impl State {
+    /// Whether the records of the client are protected in this state.
+    fn is_protected(self) -> bool {
+        match self {
+            State::ClientHello | State::EarlyData | State::ServerHello => false,
+            #[cfg(feature = "tls12")]
+            State::Tls12ServerFlight | State::Tls12ClientFlight => false,
+            _ => true,
+        }
+    }
+
+    /// Send the alert for an error in this state to the server, if any, and return the error.
+    ///
+    /// The alert is sent on a best effort basis, since the connection is closed anyway.
+    #[cfg(feature = "async")]
+    pub(crate) async fn abort<CipherSuite>(
+        self,
+        transport: &mut impl AsyncWrite,
+        tx_buf: &mut WriteBuffer<'_>,
+        key_schedule: &mut WriteKeySchedule<CipherSuite>,
+        error: TlsError,
+    ) -> TlsError
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        if let Some(alert) = Alert::from_error(&error) {
+            let record = ClientRecord::Alert(alert, self.is_protected());
+            if let Err(e) = send_alert(transport, tx_buf, key_schedule, &record).await {
+                warn!("Failed to send alert: {:?}", e);
+            }
+        }
+        error
+    }
+
+    pub(crate) fn abort_blocking<CipherSuite>(
+        self,
+        transport: &mut impl BlockingWrite,
+        tx_buf: &mut WriteBuffer<'_>,
+        key_schedule: &mut WriteKeySchedule<CipherSuite>,
+        error: TlsError,
+    ) -> TlsError
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        if let Some(alert) = Alert::from_error(&error) {
+            let record = ClientRecord::Alert(alert, self.is_protected());
+            if let Err(e) = send_alert_blocking(transport, tx_buf, key_schedule, &record) {
+                warn!("Failed to send alert: {:?}", e);
+            }
+        }
+        error
+    }
+}
+
+#[cfg(feature = "async")]
+async fn send_alert<CipherSuite>(
+    transport: &mut impl AsyncWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    record: &ClientRecord<'_, '_, CipherSuite>,
+) -> Result<(), TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    if let Err(TlsError::AbortHandshake(level, description)) = result {
-        let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
-        let tx = tx_buf.write_record(
-            &ClientRecord::Alert(Alert { level, description }, false),
-            write_key_schedule,
-            Some(read_key_schedule),
-        )?;
-
-        respond_blocking(tx, transport, key_schedule)?;
-    }
+    let tx = tx_buf.write_record(record, key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .await
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()?;
+    transport.flush().await.map_err(|e| TlsError::Io(e.kind()))
+}
 
-    result
+fn send_alert_blocking<CipherSuite>(
+    transport: &mut impl BlockingWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    record: &ClientRecord<'_, '_, CipherSuite>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let tx = tx_buf.write_record(record, key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()?;
+    transport.flush().map_err(|e| TlsError::Io(e.kind()))
 }
 
 fn respond_blocking<CipherSuite>(
@@ -351,37 +779,13 @@ where
         .write_all(tx)
         .map_err(|e| TlsError::Io(e.kind()))?;
 
-    key_schedule.write_state().increment_counter();
+    key_schedule.write_state().increment_counter()?;
 
     transport.flush().map_err(|e| TlsError::Io(e.kind()))?;
 
     Ok(())
 }
 
-#[cfg(feature = "async")]
-async fn handle_processing_error<'a, CipherSuite>(
-    result: Result<State, TlsError>,
-    transport: &mut impl AsyncWrite,
-    key_schedule: &mut KeySchedule<CipherSuite>,
-    tx_buf: &mut WriteBuffer<'a>,
-) -> Result<State, TlsError>
-where
-    CipherSuite: TlsCipherSuite,
-{
-    if let Err(TlsError::AbortHandshake(level, description)) = result {
-        let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
-        let tx = tx_buf.write_record(
-            &ClientRecord::Alert(Alert { level, description }, false),
-            write_key_schedule,
-            Some(read_key_schedule),
-        )?;
-
-        respond(tx, transport, key_schedule).await?;
-    }
-
-    result
-}
-
 #[cfg(feature = "async")]
 async fn respond<CipherSuite>(
     tx: &[u8],
@@ -396,7 +800,7 @@ where
         .await
         .map_err(|e| TlsError::Io(e.kind()))?;
 
-    key_schedule.write_state().increment_counter();
+    key_schedule.write_state().increment_counter()?;
 
     transport
         .flush()
@@ -406,131 +810,1588 @@ where
     Ok(())
 }
 
-fn client_hello<'r, CipherSuite, RNG, Verifier>(
-    key_schedule: &mut KeySchedule<CipherSuite>,
-    config: &TlsConfig<CipherSuite>,
-    rng: &mut RNG,
-    tx_buf: &'r mut WriteBuffer,
-    handshake: &mut Handshake<CipherSuite, Verifier>,
-) -> Result<(State, &'r [u8]), TlsError>
+/// Send a KeyUpdate and switch to the next traffic key when the record limit of the current key
+/// is reached. The write buffer must not contain a pending record.
+#[cfg(feature = "async")]
+pub(crate) async fn update_key_if_due<CipherSuite>(
+    transport: &mut impl AsyncWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+) -> Result<(), TlsError>
 where
-    RNG: CryptoRng + RngCore,
     CipherSuite: TlsCipherSuite,
 {
-    key_schedule.initialize_early_secret(config.psk.as_ref().map(|p| p.0))?;
-    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
-    let client_hello = ClientRecord::client_hello(config, rng);
-    let slice = tx_buf.write_record(&client_hello, write_key_schedule, Some(read_key_schedule))?;
-
-    if let ClientRecord::Handshake(ClientHandshake::ClientHello(client_hello), _) = client_hello {
-        handshake.secret.replace(client_hello.secret);
-        Ok((State::ServerHello, slice))
-    } else {
-        Err(TlsError::EncodeError)
+    if key_schedule.key_update_due() {
+        // The server keeps its keys, its record limit is its own concern
+        send_key_update(transport, tx_buf, key_schedule, false).await?;
     }
+    Ok(())
 }
 
-fn process_server_hello<CipherSuite, Verifier>(
-    handshake: &mut Handshake<CipherSuite, Verifier>,
-    key_schedule: &mut KeySchedule<CipherSuite>,
-    record: ServerRecord<'_, HashOutputSize<CipherSuite>>,
-) -> Result<State, TlsError>
+pub(crate) fn update_key_if_due_blocking<CipherSuite>(
+    transport: &mut impl BlockingWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+) -> Result<(), TlsError>
 where
     CipherSuite: TlsCipherSuite,
 {
-    match record {
-        ServerRecord::Handshake(server_handshake) => match server_handshake {
-            ServerHandshake::ServerHello(server_hello) => {
-                trace!("********* ServerHello");
-                let secret = handshake.secret.take().ok_or(TlsError::InvalidHandshake)?;
-                let shared = server_hello
-                    .calculate_shared_secret(&secret)
-                    .ok_or(TlsError::InvalidKeyShare)?;
-                key_schedule.initialize_handshake_secret(shared.raw_secret_bytes())?;
-                Ok(State::ServerVerify)
-            }
-            _ => Err(TlsError::InvalidHandshake),
-        },
-        ServerRecord::Alert(alert) => {
-            Err(TlsError::HandshakeAborted(alert.level, alert.description))
-        }
-        _ => Err(TlsError::InvalidRecord),
+    if key_schedule.key_update_due() {
+        send_key_update_blocking(transport, tx_buf, key_schedule, false)?;
     }
+    Ok(())
 }
 
-fn process_server_verify<'a, 'v, CipherSuite, Verifier>(
-    handshake: &mut Handshake<CipherSuite, Verifier>,
-    key_schedule: &mut KeySchedule<CipherSuite>,
-    config: &TlsConfig<'a, CipherSuite>,
-    record: ServerRecord<'_, HashOutputSize<CipherSuite>>,
-) -> Result<State, TlsError>
+/// Send a KeyUpdate, asking the server to update its keys as well if `update_requested`, and
+/// switch to the next traffic key. The write buffer must not contain a pending record.
+#[cfg(feature = "async")]
+pub(crate) async fn send_key_update<CipherSuite>(
+    transport: &mut impl AsyncWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    update_requested: bool,
+) -> Result<(), TlsError>
 where
     CipherSuite: TlsCipherSuite,
-    Verifier: TlsVerifier<'v, CipherSuite>,
 {
-    let mut state = State::ServerVerify;
-    decrypt_record(key_schedule.read_state(), record, |key_schedule, record| {
-        match record {
-            ServerRecord::Handshake(server_handshake) => {
-                match server_handshake {
-                    ServerHandshake::EncryptedExtensions(_) => {}
-                    ServerHandshake::Certificate(certificate) => {
-                        let transcript = key_schedule.transcript_hash();
-                        handshake.verifier.verify_certificate(
-                            transcript,
-                            &config.ca,
-                            certificate,
-                        )?;
-                        debug!("Certificate verified!");
-                    }
-                    ServerHandshake::CertificateVerify(verify) => {
-                        handshake.verifier.verify_signature(verify)?;
-                        debug!("Signature verified!");
-                    }
-                    ServerHandshake::CertificateRequest(request) => {
-                        handshake.certificate_request.replace(request.try_into()?);
-                    }
-                    ServerHandshake::Finished(finished) => {
-                        if !key_schedule.verify_server_finished(&finished)? {
-                            warn!("Server signature verification failed");
-                            return Err(TlsError::InvalidSignature);
-                        }
-
-                        // trace!("server verified {}", verified);
-                        state = if handshake.certificate_request.is_some() {
-                            State::ClientCert
-                        } else {
-                            handshake
-                                .traffic_hash
-                                .replace(key_schedule.transcript_hash().clone());
-                            State::ClientFinished
-                        };
-                    }
-                    _ => return Err(TlsError::InvalidHandshake),
-                }
-            }
-            ServerRecord::ChangeCipherSpec(_) => {}
-            _ => return Err(TlsError::InvalidRecord),
-        }
-
-        Ok(())
-    })?;
-    Ok(state)
+    // TLS 1.2 has no KeyUpdate message
+    #[cfg(feature = "tls12")]
+    if key_schedule.is_tls12() {
+        return Err(TlsError::Unimplemented);
+    }
+    let tx = tx_buf.write_record(&key_update(update_requested), key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .await
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()?;
+    key_schedule.update_traffic_secret()
 }
 
-fn client_cert<'r, CipherSuite, Verifier>(
-    handshake: &mut Handshake<CipherSuite, Verifier>,
-    key_schedule: &mut KeySchedule<CipherSuite>,
-    config: &TlsConfig<CipherSuite>,
-    buffer: &'r mut WriteBuffer,
-) -> Result<(State, &'r [u8]), TlsError>
-where
-    CipherSuite: TlsCipherSuite,
+pub(crate) fn send_key_update_blocking<CipherSuite>(
+    transport: &mut impl BlockingWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    update_requested: bool,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    // TLS 1.2 has no KeyUpdate message
+    #[cfg(feature = "tls12")]
+    if key_schedule.is_tls12() {
+        return Err(TlsError::Unimplemented);
+    }
+    let tx = tx_buf.write_record(&key_update(update_requested), key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()?;
+    key_schedule.update_traffic_secret()
+}
+
+/// Send a heartbeat request or response. The write buffer must not contain a pending record.
+#[cfg(feature = "async")]
+pub(crate) async fn send_heartbeat<CipherSuite>(
+    transport: &mut impl AsyncWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    message: HeartbeatMessage<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let tx = tx_buf.write_record(&ClientRecord::Heartbeat(message), key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .await
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()
+}
+
+pub(crate) fn send_heartbeat_blocking<CipherSuite>(
+    transport: &mut impl BlockingWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    message: HeartbeatMessage<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let tx = tx_buf.write_record(&ClientRecord::Heartbeat(message), key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()
+}
+
+/// Send a close_notify alert, closing the write side of the connection. The write buffer must
+/// not contain a pending record.
+#[cfg(feature = "async")]
+pub(crate) async fn send_close_notify<CipherSuite>(
+    transport: &mut impl AsyncWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let tx = tx_buf.write_record(&ClientRecord::close_notify(true), key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .await
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()
+}
+
+pub(crate) fn send_close_notify_blocking<CipherSuite>(
+    transport: &mut impl BlockingWrite,
+    tx_buf: &mut WriteBuffer<'_>,
+    key_schedule: &mut WriteKeySchedule<CipherSuite>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let tx = tx_buf.write_record(&ClientRecord::close_notify(true), key_schedule, None)?;
+    transport
+        .write_all(tx)
+        .map_err(|e| TlsError::Io(e.kind()))?;
+    key_schedule.increment_counter()
+}
+
+fn key_update<'config, 'a, CipherSuite>(
+    update_requested: bool,
+) -> ClientRecord<'config, 'a, CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let request_update = if update_requested {
+        KeyUpdateRequest::UpdateRequested
+    } else {
+        KeyUpdateRequest::UpdateNotRequested
+    };
+    ClientRecord::Handshake(
+        ClientHandshake::KeyUpdate(KeyUpdate { request_update }),
+        true,
+    )
+}
+
+fn client_hello<'r, CipherSuite, Provider, RNG, Verifier>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    rng: &mut RNG,
+    tx_buf: &'r mut WriteBuffer,
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+) -> Result<(State, &'r [u8]), TlsError>
+where
+    RNG: TlsRng,
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    match (config.session_ticket, &config.psk) {
+        (Some(ticket), _) => key_schedule.initialize_resumption_early_secret(ticket.psk())?,
+        (None, psk) => key_schedule.initialize_early_secret(psk.as_ref().map(|p| p.0))?,
+    }
+
+    let mut rng = CheckedRng::new(rng);
+    let retry = handshake.hello_retry.as_ref();
+    let secrets = &mut handshake.secrets;
+    match retry {
+        // Section 4.1.4.  Hello Retry Request
+        // If a "key_share" extension was supplied in the HelloRetryRequest, [the client MUST]
+        // replace the list of shares with a list containing a single KeyShareEntry from the
+        // indicated group.
+        Some(HelloRetryRequest {
+            selected_group: Some(group),
+            ..
+        }) => {
+            secrets.clear();
+            generate_key_share::<Provider, _>(secrets, *group, &mut rng)?;
+        }
+        Some(_) => {}
+        None => {
+            // A key share generated in advance must be for one of the supported groups.
+            if secrets
+                .iter()
+                .any(|secret| !config.named_groups.contains(&secret.group()))
+            {
+                return Err(TlsError::InvalidKeyShare);
+            }
+
+            // The other key shares are generated for the most preferred groups.
+            //
+            // Section 4.2.8.  Key Share
+            // Clients MAY send an empty client_shares vector in order to request group selection
+            // from the server, at the cost of an additional round trip
+            if config.needs_key_shares() {
+                for &group in config.named_groups.iter() {
+                    if secrets.len() >= config.key_shares {
+                        break;
+                    }
+                    if secrets.iter().all(|secret| secret.group() != group) {
+                        generate_key_share::<Provider, _>(secrets, group, &mut rng)?;
+                    }
+                }
+                if secrets.is_empty() {
+                    return Err(TlsError::InvalidKeyShare);
+                }
+            }
+
+            // Section 4.2.8.  Key Share
+            // client_shares:  A list of offered KeyShareEntry values in descending order of
+            // client preference.
+            secrets.sort_unstable_by_key(|secret| {
+                config
+                    .named_groups
+                    .iter()
+                    .position(|&group| group == secret.group())
+            });
+        }
+    }
+
+    // The second ClientHello keeps the random, the legacy_session_id and the GREASE values of the
+    // first one
+    if retry.is_none() {
+        rng.fill_bytes(&mut handshake.random);
+        if config.middlebox_compatibility {
+            // Appendix D.4.  Middlebox Compatibility Mode
+            // The client always provides a non-empty session ID in the ClientHello.
+            let mut session_id = [0; 32];
+            rng.fill_bytes(&mut session_id);
+            handshake.session_id = Some(session_id);
+        }
+        if config.grease {
+            let mut random = [0; 5];
+            rng.fill_bytes(&mut random);
+            handshake.grease = Some(Grease::new(random));
+        }
+        if config.heartbeat.is_some() {
+            let mut seed = [0; 32];
+            rng.fill_bytes(&mut seed);
+            key_schedule.read_state().heartbeat().set_seed(seed);
+        }
+        #[cfg(feature = "ech")]
+        if let Some(ech) = config.ech_config()? {
+            handshake.ech = Some(EchState::new(&ech, &mut rng)?);
+        }
+        rng.check()?;
+    }
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    let client_hello = ClientHello::new(
+        config,
+        secrets,
+        handshake.random,
+        handshake.session_id,
+        handshake.grease,
+        retry,
+    )?;
+    #[cfg(feature = "ech")]
+    let client_hello = match &handshake.ech {
+        Some(ech) => client_hello.with_ech(ech)?,
+        None => client_hello,
+    };
+    let client_hello = ClientRecord::Handshake(ClientHandshake::ClientHello(client_hello), false);
+    let slice = tx_buf.write_record(&client_hello, write_key_schedule, Some(read_key_schedule))?;
+
+    handshake.early_data_offered = retry.is_none() && config.offers_early_data();
+    let state = if handshake.early_data_offered {
+        State::EarlyData
+    } else {
+        State::ServerHello
+    };
+    Ok((state, slice))
+}
+
+fn generate_key_share<Provider, RNG>(
+    secrets: &mut heapless::Vec<Provider::KeyExchange, MAX_KEY_SHARES>,
+    group: NamedGroup,
+    rng: &mut CheckedRng<'_, RNG>,
+) -> Result<(), TlsError>
+where
+    Provider: CryptoProvider,
+    RNG: TlsRng,
+{
+    let secret = Provider::generate_key(group, rng);
+    rng.check()?;
+    secrets.push(secret?).map_err(|_| TlsError::InvalidKeyShare)
+}
+
+/// Appendix D.4.  Middlebox Compatibility Mode
+/// In middlebox compatibility mode, the client sends a dummy change_cipher_spec record
+/// immediately before its second flight: the second ClientHello after a HelloRetryRequest, the
+/// early data, or the encrypted handshake messages.
+fn change_cipher_spec<'r, CipherSuite, Provider, Verifier>(
+    state: State,
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<Option<&'r [u8]>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let second_flight = match state {
+        State::ClientHello => handshake.hello_retry.is_some(),
+        State::EarlyData
+        | State::EndOfEarlyData
+        | State::ClientCert
+        | State::ClientCertVerify
+        | State::ClientFinished => true,
+        State::ServerHello | State::ServerVerify | State::ApplicationData => false,
+        // There is no such dummy record in TLS 1.2
+        #[cfg(feature = "tls12")]
+        State::Tls12ServerFlight | State::Tls12ClientFlight | State::Tls12ServerFinished => false,
+    };
+    if !config.middlebox_compatibility || !second_flight || handshake.change_cipher_spec_sent {
+        return Ok(None);
+    }
+
+    handshake.change_cipher_spec_sent = true;
+    let record = ClientRecord::ChangeCipherSpec(ChangeCipherSpec::new(), false);
+    buffer
+        .write_record(&record, key_schedule.write_state(), None)
+        .map(Some)
+}
+
+/// Section 4.2.10.  Early Data Indication
+/// The client sends its early data right after the ClientHello, protected with the client early
+/// traffic secret.
+fn early_data<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let data = config.zero_rtt_data();
+    key_schedule.initialize_early_traffic_secret()?;
+
+    buffer.write_record(
+        &ClientRecord::ApplicationData(data),
+        key_schedule.write_state(),
+        None,
+    )
+}
+
+fn process_server_hello<'v, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    record: ServerRecord<'_, HashOutputSize<CipherSuite>>,
+) -> Result<State, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+    Verifier: TlsVerifier<'v, CipherSuite>,
+{
+    // RFC 8446, Section 4.2.1.  Supported Versions
+    // If this extension is not present, servers which are compliant with this specification and
+    // which also support TLS 1.2 MUST negotiate TLS 1.2 or prior as specified in [RFC5246]
+    #[cfg(feature = "tls12")]
+    if let ServerRecord::Handshake(ServerHandshake::ServerHello(server_hello)) = &record {
+        if config.offers_tls12() && server_hello.selected_version().is_none() {
+            trace!("********* ServerHello (TLS 1.2)");
+            let mut tls12 =
+                crate::tls12::Tls12Handshake::new::<CipherSuite>(handshake.random, server_hello)?;
+            handle_unknown_extensions(
+                config,
+                HandshakeType::ServerHello,
+                server_hello.extensions(),
+                true,
+            )?;
+            if let Some(protocol) = server_hello.alpn_protocol() {
+                negotiate_alpn(handshake, config, protocol)?;
+            }
+            if let Some(length) = server_hello.max_fragment_length() {
+                negotiate_max_fragment_length(key_schedule.read_state(), config, length)?;
+            }
+            tls12.process_server_flight(
+                &mut handshake.verifier,
+                key_schedule.read_state(),
+                config,
+                server_hello.coalesced(),
+            )?;
+            let state = tls12_server_flight_state(&tls12);
+            handshake.tls12 = Some(tls12);
+            return Ok(state);
+        }
+    }
+
+    match record {
+        ServerRecord::Handshake(server_handshake) => match server_handshake {
+            ServerHandshake::ServerHello(server_hello) => {
+                trace!("********* ServerHello");
+                // Section 4.1.3.  Server Hello
+                // A client which receives a cipher suite that was not offered MUST
+                // abort the handshake with an "illegal_parameter" alert.
+                if server_hello.cipher_suite() as u16 != CipherSuite::CODE_POINT {
+                    warn!("Server selected a cipher suite that was not offered");
+                    return Err(TlsError::AbortHandshake(
+                        AlertLevel::Fatal,
+                        AlertDescription::IllegalParameter,
+                    ));
+                }
+                if config.strict {
+                    check_server_hello(&server_hello)?;
+                }
+                handle_unknown_extensions(
+                    config,
+                    HandshakeType::ServerHello,
+                    server_hello.extensions(),
+                    true,
+                )?;
+                // A client which receives a legacy_session_id_echo field that does not match what
+                // it sent in the ClientHello MUST abort the handshake with an "illegal_parameter"
+                // alert.
+                let session_id = handshake.session_id.as_ref().map_or(&[][..], |id| &id[..]);
+                if server_hello.legacy_session_id_echo() != session_id {
+                    warn!("Server did not echo the legacy_session_id");
+                    return Err(TlsError::AbortHandshake(
+                        AlertLevel::Fatal,
+                        AlertDescription::IllegalParameter,
+                    ));
+                }
+                // Section 4.1.3.  Server Hello
+                // TLS 1.3 clients receiving a ServerHello indicating TLS 1.2 or below MUST check
+                // that the last 8 bytes are not equal to either of these values. [...] If a match
+                // is found, the client MUST abort the handshake with an "illegal_parameter" alert.
+                if server_hello.is_downgrade() {
+                    warn!("Server random has a downgrade protection sentinel");
+                    return Err(TlsError::AbortHandshake(
+                        AlertLevel::Fatal,
+                        AlertDescription::IllegalParameter,
+                    ));
+                }
+                if server_hello.is_hello_retry_request() {
+                    return process_hello_retry_request(handshake, config, &server_hello);
+                }
+                #[cfg(feature = "ech")]
+                process_ech_acceptance(handshake, key_schedule.read_state(), &server_hello)?;
+                // Section 4.2.11.  Pre-Shared Key Extension
+                // Clients MUST verify that the server's selected_identity is within the range
+                // supplied by the client.
+                match server_hello.selected_identity() {
+                    Some(identity) if usize::from(identity) < config.psk_identities() => {
+                        debug!("Server accepted PSK identity {}", identity);
+                        handshake.psk_accepted = true;
+                    }
+                    Some(_) => {
+                        return Err(TlsError::AbortHandshake(
+                            AlertLevel::Fatal,
+                            AlertDescription::IllegalParameter,
+                        ));
+                    }
+                    // The server declined the PSK, so the early secret is derived without it
+                    None if config.psk_identities() > 0 => {
+                        key_schedule.initialize_early_secret(None)?;
+                    }
+                    None => {}
+                }
+
+                // Section 4.2.9.  Pre-Shared Key Exchange Modes
+                // psk_ke:  PSK-only key establishment.  In this mode, the server MUST NOT supply
+                // a "key_share" value.
+                let Some(key_share) = server_hello.key_share() else {
+                    if !handshake.psk_accepted
+                        || !config
+                            .psk_key_exchange_modes
+                            .contains(&PskKeyExchangeMode::PskKe)
+                    {
+                        warn!("Server did not send a key share");
+                        return Err(TlsError::AbortHandshake(
+                            AlertLevel::Fatal,
+                            AlertDescription::MissingExtension,
+                        ));
+                    }
+                    debug!("Server selected PSK-only key establishment");
+                    handshake.secrets.clear();
+                    key_schedule.initialize_psk_only_handshake_secret()?;
+                    return Ok(State::ServerVerify);
+                };
+                // The server selected psk_dhe_ke, which must have been offered
+                if handshake.psk_accepted
+                    && !config
+                        .psk_key_exchange_modes
+                        .contains(&PskKeyExchangeMode::PskDheKe)
+                {
+                    warn!("Server selected a PSK key exchange mode that was not offered");
+                    return Err(illegal_parameter());
+                }
+                // Section 4.2.8.  Key Share
+                // This value MUST be in the same group as the KeyShareEntry value offered by the
+                // client that the server has selected for the negotiated key exchange.
+                let group = key_share.group;
+                let index = handshake
+                    .secrets
+                    .iter()
+                    .position(|secret| secret.group() == group)
+                    .ok_or(TlsError::AbortHandshake(
+                        AlertLevel::Fatal,
+                        AlertDescription::IllegalParameter,
+                    ))?;
+                let secret = handshake.secrets.swap_remove(index);
+                handshake.secrets.clear();
+                let shared = server_hello.calculate_shared_secret(secret)?;
+                key_schedule.initialize_handshake_secret(shared.as_bytes())?;
+                Ok(State::ServerVerify)
+            }
+            _ => Err(TlsError::InvalidHandshake),
+        },
+        // Appendix D.4.  Middlebox Compatibility Mode
+        // The server may send a change_cipher_spec record immediately after a
+        // HelloRetryRequest.
+        ServerRecord::ChangeCipherSpec(_) => Ok(State::ServerHello),
+        ServerRecord::Alert(alert) if alert.is_fatal() => {
+            Err(TlsError::HandshakeAborted(alert.level, alert.description))
+        }
+        ServerRecord::Alert(alert) => {
+            warn!("Ignoring warning alert: {:?}", alert.description);
+            Ok(State::ServerHello)
+        }
+        _ => Err(TlsError::InvalidRecord),
+    }
+}
+
+/// Section 4.1.4.  Hello Retry Request
+/// The transcript has already been restarted by the record reader, the client answers with a
+/// second ClientHello with the changes requested by the server.
+fn process_hello_retry_request<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    config: &TlsConfig<CipherSuite>,
+    server_hello: &ServerHello<'_>,
+) -> Result<State, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    trace!("********* HelloRetryRequest");
+    let retry = HelloRetryRequest::try_from(server_hello)?;
+
+    // If a client receives a second HelloRetryRequest in the same connection (i.e., where the
+    // ClientHello was itself in response to a HelloRetryRequest), it MUST abort the handshake
+    // with an "unexpected_message" alert.
+    if handshake.hello_retry.is_some() {
+        warn!("Server sent a second HelloRetryRequest");
+        return Err(unexpected_message());
+    }
+
+    // The second ClientHello would need the ClientHelloInner to be encrypted again with the same
+    // HPKE context, which is not supported.
+    #[cfg(feature = "ech")]
+    if handshake.ech.is_some() {
+        warn!("HelloRetryRequest is not supported with Encrypted Client Hello");
+        return Err(TlsError::Unimplemented);
+    }
+
+    // Clients MUST abort the handshake with an "illegal_parameter" alert if the HelloRetryRequest
+    // would not result in any change in the ClientHello.
+    //
+    // Upon receipt of this extension in a HelloRetryRequest, the client MUST verify that (1) the
+    // selected_group field corresponds to a group which was provided in the "supported_groups"
+    // extension in the original ClientHello and (2) the selected_group field does not correspond
+    // to a group which was provided in the "key_share" extension in the original ClientHello.
+    let valid = match retry.selected_group {
+        Some(group) => {
+            config.named_groups.contains(&group)
+                && handshake
+                    .secrets
+                    .iter()
+                    .all(|secret| secret.group() != group)
+        }
+        None => !retry.cookie.is_empty(),
+    };
+    if !valid {
+        warn!("Server sent an invalid HelloRetryRequest");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::IllegalParameter,
+        ));
+    }
+
+    handshake.hello_retry = Some(retry);
+    Ok(State::ClientHello)
+}
+
+/// draft-ietf-tls-esni, Section 6.1.4.  Determining ECH Acceptance
+/// If the last 8 bytes of ServerHello.random are equal to accept_confirmation, then ECH was
+/// accepted. Otherwise, the server rejected ECH.
+///
+/// When ECH is accepted, the handshake continues with the ClientHelloInner, whose transcript
+/// replaces the one of the ClientHelloOuter. When it is rejected, the server is authenticated
+/// with the public name of the ECHConfig.
+#[cfg(feature = "ech")]
+fn process_ech_acceptance<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut ReadKeySchedule<CipherSuite>,
+    server_hello: &ServerHello<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let Some(ech) = handshake.ech.as_mut() else {
+        return Ok(());
+    };
+    let inner_transcript = key_schedule
+        .take_inner_transcript_hash()
+        .ok_or(TlsError::InternalError)?;
+
+    let mut transcript = inner_transcript.clone();
+    server_hello.update_transcript(&mut transcript, true)?;
+    let confirmation =
+        KeySchedule::<CipherSuite>::ech_accept_confirmation(&ech.inner_random, &transcript)?;
+    if constant_time_eq(&confirmation, &server_hello.random()[24..]) {
+        debug!("Server accepted ECH");
+        let mut transcript = inner_transcript;
+        server_hello.update_transcript(&mut transcript, false)?;
+        key_schedule.replace_transcript_hash(transcript);
+        handshake.random = ech.inner_random;
+        ech.status = EchStatus::Accepted;
+    } else {
+        debug!("Server rejected ECH");
+        ech.status = EchStatus::Rejected;
+        // Section 6.1.7.  Authenticating for the Public Name
+        // The client MUST verify that the certificate is valid for
+        // ECHConfig.contents.public_name.
+        handshake.verifier = handshake
+            .ech_verifier
+            .take()
+            .ok_or(TlsError::InternalError)?;
+    }
+    Ok(())
+}
+
+/// The name the server is authenticated for, which is the public name of the ECHConfig when the
+/// server rejected ECH.
+fn authenticated_name<'c, CipherSuite, Provider, Verifier>(
+    _handshake: &Handshake<CipherSuite, Provider, Verifier>,
+    config: &TlsConfig<'c, CipherSuite>,
+) -> Result<Option<&'c str>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    // draft-ietf-tls-esni, Section 6.1.7.  Authenticating for the Public Name
+    // The client MUST verify that the certificate is valid for
+    // ECHConfig.contents.public_name.
+    #[cfg(feature = "ech")]
+    if matches!(&_handshake.ech, Some(ech) if ech.status == EchStatus::Rejected) {
+        return Ok(config.ech_config()?.map(|ech| ech.public_name));
+    }
+    Ok(config.server_name)
+}
+
+/// draft-ietf-tls-esni, Section 5.  The "encrypted_client_hello" Extension
+/// The server sends the extension in EncryptedExtensions with its retry_configs only when it
+/// rejects ECH, which are kept to be returned to the application.
+#[cfg(feature = "ech")]
+fn receive_ech_retry_configs<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    retry_configs: &[u8],
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    match handshake.ech.as_mut() {
+        Some(ech) if ech.status == EchStatus::Rejected => ech.set_retry_configs(retry_configs),
+        _ => Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        )),
+    }
+}
+
+fn process_server_verify<'a, 'v, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<'a, CipherSuite>,
+    tx_buf: &mut WriteBuffer<'_>,
+    record: ServerRecord<'_, HashOutputSize<CipherSuite>>,
+) -> Result<State, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+    Verifier: TlsVerifier<'v, CipherSuite>,
+{
+    let mut state = State::ServerVerify;
+    decrypt_record(key_schedule.read_state(), record, |key_schedule, record| {
+        match record {
+            ServerRecord::Handshake(server_handshake) => {
+                match server_handshake {
+                    ServerHandshake::EncryptedExtensions(extensions) => {
+                        if config.strict {
+                            check_encrypted_extensions(&extensions, config)?;
+                        }
+                        if let Some(protocol) = extensions.alpn_protocol {
+                            negotiate_alpn(handshake, config, protocol)?;
+                        }
+                        if let Some(length) = extensions.max_fragment_length {
+                            negotiate_max_fragment_length(key_schedule, config, length)?;
+                        }
+                        if let Some(count) = extensions.expected_tickets {
+                            key_schedule.set_expected_session_tickets(count);
+                        }
+                        if let Some(mode) = extensions.heartbeat_mode {
+                            negotiate_heartbeat(key_schedule, config, mode)?;
+                        }
+                        if let Some(_retry_configs) = extensions.ech_retry_configs {
+                            #[cfg(feature = "ech")]
+                            receive_ech_retry_configs(handshake, _retry_configs)?;
+                            // The extension is only sent in response to an offered ECH
+                            #[cfg(not(feature = "ech"))]
+                            return Err(TlsError::AbortHandshake(
+                                AlertLevel::Fatal,
+                                AlertDescription::UnsupportedExtension,
+                            ));
+                        }
+                        negotiate_client_certificate_type(
+                            handshake,
+                            config,
+                            extensions.client_certificate_type,
+                        )?;
+                        negotiate_server_certificate_type(
+                            config,
+                            extensions.server_certificate_type,
+                        )?;
+                        handle_unknown_extensions(
+                            config,
+                            HandshakeType::EncryptedExtensions,
+                            extensions.extensions(),
+                            true,
+                        )?;
+                        receive_custom_extensions(handshake, config, extensions.extensions())?;
+                        if extensions.early_data {
+                            // Section 4.2.  Extensions
+                            // Upon receiving such an extension, an endpoint MUST abort the
+                            // handshake with an "unsupported_extension" alert.
+                            if !handshake.early_data_offered {
+                                return Err(TlsError::AbortHandshake(
+                                    AlertLevel::Fatal,
+                                    AlertDescription::UnsupportedExtension,
+                                ));
+                            }
+                            // Section 4.2.10.  Early Data Indication
+                            // If the server supplies an "early_data" extension, the client MUST
+                            // verify that the server's selected_identity is 0.
+                            if !handshake.psk_accepted {
+                                return Err(TlsError::AbortHandshake(
+                                    AlertLevel::Fatal,
+                                    AlertDescription::IllegalParameter,
+                                ));
+                            }
+                            debug!("Server accepted early data");
+                            handshake.early_data_accepted = true;
+                        }
+                    }
+                    ServerHandshake::Certificate(certificate) => {
+                        verify_server_certificate(
+                            handshake,
+                            key_schedule.transcript_hash(),
+                            config,
+                            certificate,
+                        )?;
+                    }
+                    ServerHandshake::CompressedCertificate(compressed) => {
+                        // The server compresses its certificate only with an algorithm of the
+                        // compress_certificate extension
+                        let algorithms = config.certificate_compression_algorithms();
+                        if algorithms.is_empty() {
+                            return Err(unexpected_message());
+                        }
+                        let offered = compressed
+                            .algorithm
+                            .map_or(false, |algorithm| algorithms.contains(&algorithm));
+                        if !offered {
+                            warn!(
+                                "Server compressed its certificate with an algorithm not offered"
+                            );
+                            return Err(TlsError::AbortHandshake(
+                                AlertLevel::Fatal,
+                                AlertDescription::IllegalParameter,
+                            ));
+                        }
+                        // The decompressed message is kept at the end of the write buffer, which
+                        // is not used until the client flight
+                        let (_, scratch) =
+                            tx_buf.split_scratch(compressed.uncompressed_length())?;
+                        let certificate = compressed.decompress(scratch)?;
+                        verify_server_certificate(
+                            handshake,
+                            key_schedule.transcript_hash(),
+                            config,
+                            certificate,
+                        )?;
+                    }
+                    ServerHandshake::CertificateVerify(verify) => {
+                        // Section 4.4.3.  Certificate Verify
+                        // If the CertificateVerify message is sent by a server, the signature
+                        // algorithm MUST be one offered in the client's "signature_algorithms"
+                        // extension unless no valid certificate chain can be produced without
+                        // unsupported algorithms.
+                        if !config.signature_schemes.contains(&verify.signature_scheme) {
+                            warn!("Server selected a signature scheme that was not offered");
+                            return Err(TlsError::AbortHandshake(
+                                AlertLevel::Fatal,
+                                AlertDescription::IllegalParameter,
+                            ));
+                        }
+                        let signature_scheme = verify.signature_scheme;
+                        let signature = verify.signature;
+                        handshake.verifier.verify_signature(verify)?;
+                        let transcript_hash = handshake
+                            .certificate_hash
+                            .take()
+                            .ok_or(TlsError::InvalidHandshake)?
+                            .finalize();
+                        let server_name = authenticated_name(handshake, config)?;
+                        handshake
+                            .verifier
+                            .verify_signature_context(&SignatureContext {
+                                server_name,
+                                signature_scheme,
+                                signature,
+                                transcript_hash: &transcript_hash,
+                            })?;
+                        debug!("Signature verified!");
+                    }
+                    ServerHandshake::CertificateRequest(request) => {
+                        if config.strict {
+                            check_certificate_request(&request)?;
+                        }
+                        // Section 4.3.2.  Certificate Request
+                        // Clients MUST ignore unrecognized extensions.
+                        handle_unknown_extensions(
+                            config,
+                            HandshakeType::CertificateRequest,
+                            request.extensions,
+                            false,
+                        )?;
+                        let identity = config
+                            .identities
+                            .iter()
+                            .position(|identity| request.accepts(identity.cert_chain));
+                        let mut request = CertificateRequest::try_from(request)?;
+                        request.identity = identity;
+                        handshake.certificate_request.replace(request);
+                    }
+                    ServerHandshake::Finished(finished) => {
+                        if !key_schedule.verify_server_finished(&finished)? {
+                            warn!("Server signature verification failed");
+                            return Err(TlsError::InvalidSignature);
+                        }
+
+                        // draft-ietf-tls-esni, Section 6.1.6.  Handshaking with
+                        // ClientHelloOuter
+                        // If the server rejected ECH, the client [...] MUST then abort the
+                        // handshake with an "ech_required" alert.
+                        #[cfg(feature = "ech")]
+                        if let Some(EchStatus::Rejected) =
+                            handshake.ech.as_ref().map(|ech| ech.status)
+                        {
+                            warn!("Server rejected ECH");
+                            return Err(TlsError::EchRejected);
+                        }
+
+                        // trace!("server verified {}", verified);
+                        handshake
+                            .traffic_hash
+                            .replace(key_schedule.transcript_hash().clone());
+                        state = if handshake.early_data_accepted {
+                            State::EndOfEarlyData
+                        } else if handshake.certificate_request.is_some() {
+                            State::ClientCert
+                        } else {
+                            State::ClientFinished
+                        };
+                    }
+                    _ => return Err(TlsError::InvalidHandshake),
+                }
+            }
+            ServerRecord::ChangeCipherSpec(_) => {}
+            ServerRecord::Alert(alert) if alert.is_fatal() => {
+                return Err(TlsError::HandshakeAborted(alert.level, alert.description));
+            }
+            ServerRecord::Alert(alert) => {
+                warn!("Ignoring warning alert: {:?}", alert.description);
+            }
+            _ => return Err(TlsError::InvalidRecord),
+        }
+
+        Ok(())
+    })?;
+    Ok(state)
+}
+
+/// The state after a record of the server flight of a TLS 1.2 handshake.
+#[cfg(feature = "tls12")]
+fn tls12_server_flight_state(tls12: &crate::tls12::Tls12Handshake) -> State {
+    if tls12.server_flight_done() {
+        State::Tls12ClientFlight
+    } else {
+        State::Tls12ServerFlight
+    }
+}
+
+/// The messages of the server flight of TLS 1.2 are read as raw records, since they are not
+/// decoded by the records of TLS 1.3.
+#[cfg(feature = "tls12")]
+fn process_tls12_server_flight<'v, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    header: RecordHeader,
+    data: &[u8],
+) -> Result<State, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+    Verifier: TlsVerifier<'v, CipherSuite>,
+{
+    let tls12 = handshake.tls12.as_mut().ok_or(TlsError::InternalError)?;
+    match header.content_type() {
+        ContentType::Handshake => tls12.process_server_flight(
+            &mut handshake.verifier,
+            key_schedule.read_state(),
+            config,
+            data,
+        )?,
+        ContentType::Alert => {
+            let alert = Alert::parse(&mut ParseBuffer::new(data))?;
+            if alert.is_fatal() {
+                return Err(TlsError::HandshakeAborted(alert.level, alert.description));
+            }
+            warn!("Ignoring warning alert: {:?}", alert.description);
+        }
+        _ => return Err(unexpected_message()),
+    }
+    Ok(tls12_server_flight_state(tls12))
+}
+
+/// RFC 5246, Section 7.4.6.  Client Certificate
+/// This is the first message the client can send after receiving a ServerHelloDone message. This
+/// message is only sent if the server requests a certificate.
+#[cfg(feature = "tls12")]
+fn tls12_client_certificate<'r, CipherSuite, Provider, Verifier>(
+    handshake: &Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<Option<&'r [u8]>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let tls12 = handshake.tls12.as_ref().ok_or(TlsError::InternalError)?;
+    if !tls12.certificate_requested() {
+        return Ok(None);
+    }
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    buffer
+        .write_record(
+            &ClientRecord::Handshake(
+                ClientHandshake::Tls12(crate::tls12::ClientMessage::EmptyCertificate),
+                false,
+            ),
+            write_key_schedule,
+            Some(read_key_schedule),
+        )
+        .map(Some)
+}
+
+/// RFC 8422, Section 5.7.  Client Key Exchange
+/// The client generates an ECDHE key pair on the same curve as the server's ephemeral ECDH key
+/// and sends its public part to the server.
+///
+/// A key share of the ClientHello for the same group is reused, and the master secret is derived
+/// once the message has been added to the transcript.
+#[cfg(feature = "tls12")]
+fn tls12_client_key_exchange<'r, CipherSuite, Provider, RNG, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    rng: &mut RNG,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+    RNG: TlsRng,
+{
+    let tls12 = handshake.tls12.as_mut().ok_or(TlsError::InternalError)?;
+    let (group, server_key) = tls12.server_key()?;
+
+    let secrets = &mut handshake.secrets;
+    let secret = match secrets.iter().position(|secret| secret.group() == group) {
+        Some(index) => secrets.swap_remove(index),
+        None => {
+            secrets.clear();
+            generate_key_share::<Provider, _>(secrets, group, &mut CheckedRng::new(rng))?;
+            secrets.pop().ok_or(TlsError::InternalError)?
+        }
+    };
+    secrets.clear();
+
+    let public_key = secret.public_key()?;
+    let shared = secret.diffie_hellman(server_key)?;
+
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    let tx = buffer.write_record(
+        &ClientRecord::Handshake(
+            ClientHandshake::Tls12(crate::tls12::ClientMessage::ClientKeyExchange(&public_key)),
+            false,
+        ),
+        write_key_schedule,
+        Some(&mut *read_key_schedule),
+    )?;
+    tls12.derive_master_secret::<CipherSuite>(
+        shared.as_bytes(),
+        read_key_schedule.transcript_hash(),
+    )?;
+    Ok(tx)
+}
+
+#[cfg(feature = "tls12")]
+fn tls12_change_cipher_spec<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let record = ClientRecord::ChangeCipherSpec(ChangeCipherSpec::new(), false);
+    buffer.write_record(&record, key_schedule.write_state(), None)
+}
+
+/// RFC 5246, Section 7.1.  Change Cipher Spec Protocol
+/// Immediately after sending this message, the sender MUST instruct the record layer to make the
+/// write pending state the write active state.
+///
+/// The client keys are installed before the Finished, which is the first protected record.
+#[cfg(feature = "tls12")]
+fn tls12_client_finished<'r, CipherSuite, Provider, Verifier>(
+    handshake: &Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let tls12 = handshake.tls12.as_ref().ok_or(TlsError::InternalError)?;
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+    tls12.install_client_keys(write_key_schedule)?;
+
+    let verify_data =
+        tls12.client_verify_data::<CipherSuite>(read_key_schedule.transcript_hash())?;
+    buffer.write_record(
+        &ClientRecord::Handshake(
+            ClientHandshake::Tls12(crate::tls12::ClientMessage::Finished(verify_data)),
+            true,
+        ),
+        write_key_schedule,
+        Some(read_key_schedule),
+    )
+}
+
+/// The server answers the client Finished with its ChangeCipherSpec, after which its records
+/// are protected, and its own Finished.
+#[cfg(feature = "tls12")]
+fn process_tls12_server_finished<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    record: ServerRecord<'_, HashOutputSize<CipherSuite>>,
+) -> Result<State, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let tls12 = handshake.tls12.as_ref().ok_or(TlsError::InternalError)?;
+    let key_schedule = key_schedule.read_state();
+    let alert = match record {
+        ServerRecord::ChangeCipherSpec(_) if !key_schedule.is_tls12() => {
+            tls12.install_server_keys(key_schedule)?;
+            return Ok(State::Tls12ServerFinished);
+        }
+        ServerRecord::ApplicationData(ApplicationData { header, data })
+            if key_schedule.is_tls12() =>
+        {
+            let (content_type, data) = open_protected_record(key_schedule, &header, data)?;
+            key_schedule.increment_counter()?;
+            match content_type {
+                ContentType::Handshake => {
+                    tls12.verify_server_finished::<CipherSuite>(
+                        key_schedule.transcript_hash(),
+                        data.as_slice(),
+                    )?;
+                    debug!("Server Finished verified!");
+                    handshake.tls12 = None;
+                    return Ok(State::ApplicationData);
+                }
+                ContentType::Alert => Alert::parse(&mut ParseBuffer::new(data.as_slice()))?,
+                _ => return Err(unexpected_message()),
+            }
+        }
+        ServerRecord::Alert(alert) => alert,
+        _ => return Err(unexpected_message()),
+    };
+
+    if alert.is_fatal() {
+        return Err(TlsError::HandshakeAborted(alert.level, alert.description));
+    }
+    warn!("Ignoring warning alert: {:?}", alert.description);
+    Ok(State::Tls12ServerFinished)
+}
+
+pub(crate) fn illegal_parameter() -> TlsError {
+    TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter)
+}
+
+pub(crate) fn unexpected_message() -> TlsError {
+    TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::UnexpectedMessage)
+}
+
+/// Section 4.2.  Extensions
+/// There MUST NOT be more than one extension of the same type in a given extension block.
+fn check_duplicates(types: ExtensionTypes) -> Result<(), TlsError> {
+    if types.has_duplicates() {
+        warn!("Server repeated an extension");
+        return Err(illegal_parameter());
+    }
+    Ok(())
+}
+
+/// Section 6.2.  Error Alerts
+/// missing_extension:  Sent by endpoints that receive a handshake message not containing an
+/// extension that is mandatory to send for the offered TLS version or other negotiated
+/// parameters.
+fn check_required(types: ExtensionTypes, extension_type: ExtensionType) -> Result<(), TlsError> {
+    if !types.contains(extension_type) {
+        warn!("Server did not send the {:?} extension", extension_type);
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::MissingExtension,
+        ));
+    }
+    Ok(())
+}
+
+/// The strict checks of a ServerHello or HelloRetryRequest, see
+/// [`TlsConfig::with_strict_validation`].
+fn check_server_hello(server_hello: &ServerHello<'_>) -> Result<(), TlsError> {
+    let types = server_hello.extension_types();
+    check_duplicates(types)?;
+
+    // Section 4.2.1.  Supported Versions
+    // If the "supported_versions" extension in the ServerHello contains a version not offered by
+    // the client or contains a version prior to TLS 1.3, the client MUST abort the handshake with
+    // an "illegal_parameter" alert.
+    match server_hello.selected_version() {
+        Some(TLS13) => {}
+        Some(_) => return Err(illegal_parameter()),
+        // Without the extension, the server negotiated TLS 1.2 or below, which is not offered
+        None => {
+            warn!("Server did not select TLS 1.3");
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::ProtocolVersion,
+            ));
+        }
+    }
+
+    // Section 4.2.9.  Pre-Shared Key Exchange Modes
+    // psk_dhe_ke:  PSK with (EC)DHE key establishment. In this mode, the client and server MUST
+    // supply "key_share" values
+    //
+    // Without a PSK, the server must supply one as well, while the PSK-only mode is checked
+    // against the offered modes when the ServerHello is processed.
+    if !server_hello.is_hello_retry_request() && server_hello.selected_identity().is_none() {
+        check_required(types, ExtensionType::KeyShare)?;
+    }
+    Ok(())
+}
+
+/// Records the application protocol selected by the server, see
+/// [`TlsConfig::with_alpn_protocols`].
+fn negotiate_alpn<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    config: &TlsConfig<CipherSuite>,
+    protocol: &[u8],
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    // Section 4.2.  Extensions
+    // Implementations MUST NOT send extension responses if the remote endpoint did not send the
+    // corresponding extension requests [...] Upon receiving such an extension, an endpoint MUST
+    // abort the handshake with an "unsupported_extension" alert.
+    if config.alpn_protocols.is_empty() {
+        warn!("Server selected an application protocol which was not requested");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        ));
+    }
+    // The server must select one of the protocols offered in the ClientHello
+    if !config
+        .alpn_protocols
+        .iter()
+        .any(|offered| *offered == protocol)
+    {
+        warn!("Server selected an application protocol which was not offered");
+        return Err(illegal_parameter());
+    }
+    handshake.alpn_protocol =
+        heapless::Vec::from_slice(protocol).map_err(|_| TlsError::InsufficientSpace)?;
+    Ok(())
+}
+
+/// Verifies the Certificate message of the server, received as is or compressed.
+fn verify_server_certificate<'v, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    transcript: &CipherSuite::Hash,
+    config: &TlsConfig<'_, CipherSuite>,
+    certificate: CertificateRef,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+    Verifier: TlsVerifier<'v, CipherSuite>,
+{
+    let certificate = if config.server_raw_public_key().is_some() {
+        certificate.into_raw_public_keys()
+    } else {
+        certificate
+    };
+    // Section 4.2.  Extensions
+    // Upon receiving such an extension, an endpoint MUST abort the handshake with an
+    // "unsupported_extension" alert.
+    if certificate.delegated_credential.is_some()
+        && config.delegated_credential_schemes().is_empty()
+    {
+        warn!("Server sent a delegated credential which was not offered");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        ));
+    }
+    crate::pinning::verify_spki_pins(config, &certificate)?;
+    verify_ocsp_response(config, &certificate)?;
+    #[cfg(feature = "sct")]
+    verify_scts(config, &certificate)?;
+    let server_name = authenticated_name(handshake, config)?;
+    handshake
+        .verifier
+        .verify_certificate_context(&CertificateContext {
+            server_name,
+            entries: &certificate.entries,
+            ocsp_response: certificate.ocsp_response,
+            transcript_hash: &transcript.clone().finalize(),
+        })?;
+    handshake.certificate_hash.replace(transcript.clone());
+    handshake
+        .verifier
+        .set_certificate_signature_schemes(&config.signature_schemes_cert);
+    handshake
+        .verifier
+        .set_time(config.now(), config.missing_time);
+    handshake
+        .verifier
+        .set_verification_policy(config.verification_policy);
+    handshake.verifier.set_spki_pins(config.spki_pins);
+    handshake.verifier.verify_certificate_with_roots(
+        transcript,
+        &config.ca,
+        config.root_certificates,
+        certificate,
+    )?;
+    debug!("Certificate verified!");
+    Ok(())
+}
+
+/// Hands the OCSP response stapled to the certificate of the server to the verifier of the
+/// configuration, see [`TlsConfig::with_ocsp_stapling`].
+pub(crate) fn verify_ocsp_response<CipherSuite>(
+    config: &TlsConfig<CipherSuite>,
+    certificate: &CertificateRef<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
 {
-    handshake
-        .traffic_hash
-        .replace(key_schedule.transcript_hash().clone());
+    let Some(verifier) = &config.ocsp_verifier else {
+        return Ok(());
+    };
+    let mut chain = certificate.entries.iter().map(|entry| match *entry {
+        CertificateEntryRef::X509(data) | CertificateEntryRef::RawPublicKey(data) => data,
+    });
+    let end_entity = chain.next().ok_or(TlsError::InvalidCertificate)?;
+    if !verifier.verify(certificate.ocsp_response, end_entity, chain.next()) {
+        warn!("The OCSP response of the server was rejected");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::BadCertificateStatusResponse,
+        ));
+    }
+    Ok(())
+}
+
+/// Verifies the SCTs of the certificate of the server with the logs of the configuration, see
+/// [`TlsConfig::with_certificate_transparency`].
+#[cfg(feature = "sct")]
+pub(crate) fn verify_scts<CipherSuite>(
+    config: &TlsConfig<CipherSuite>,
+    certificate: &CertificateRef<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    if config.ct_logs.is_empty() {
+        return Ok(());
+    }
+    let verified = match (certificate.entries.first(), certificate.sct_list) {
+        (Some(CertificateEntryRef::X509(end_entity)), Some(sct_list)) => {
+            crate::sct::verify(sct_list, end_entity, config.ct_logs)?
+        }
+        _ => 0,
+    };
+    if verified < config.required_scts {
+        warn!(
+            "The certificate of the server has SCTs from {} trusted logs, {} are required",
+            verified, config.required_scts
+        );
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::BadCertificate,
+        ));
+    }
+    Ok(())
+}
+
+/// Keeps the custom extensions echoed by the server, see [`TlsConfig::with_custom_extensions`].
+fn receive_custom_extensions<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    config: &TlsConfig<CipherSuite>,
+    extensions: &[u8],
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    for (extension_type, data) in unknown_extensions(extensions) {
+        if is_custom_extension(config, extension_type) {
+            handshake.custom_extensions.push(extension_type, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Hands the extensions of unknown types of a message to the handler of the configuration, see
+/// [`TlsConfig::with_unknown_extension_handler`]. `response` tells whether the extensions of the
+/// message answer the ones of the client.
+fn handle_unknown_extensions<CipherSuite>(
+    config: &TlsConfig<CipherSuite>,
+    message: HandshakeType,
+    extensions: &[u8],
+    response: bool,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    for (extension_type, data) in unknown_extensions(extensions) {
+        if is_custom_extension(config, extension_type) {
+            continue;
+        }
+        let accepted = match &config.unknown_extension_handler {
+            Some(handler) => handler.accepts(message, extension_type, data),
+            // Section 4.2.  Extensions
+            // Implementations MUST NOT send extension responses if the remote endpoint did not
+            // send the corresponding extension requests [...] Upon receiving such an extension,
+            // an endpoint MUST abort the handshake with an "unsupported_extension" alert.
+            None => !(config.strict && response),
+        };
+        if !accepted {
+            warn!(
+                "Server sent an extension of unknown type {}",
+                extension_type
+            );
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the type is the one of a custom extension of the client.
+fn is_custom_extension<CipherSuite>(config: &TlsConfig<CipherSuite>, extension_type: u16) -> bool
+where
+    CipherSuite: TlsCipherSuite,
+{
+    config
+        .custom_extensions
+        .iter()
+        .any(|(offered, _)| *offered == extension_type)
+}
+
+/// Limits the records of the server to the maximum fragment length it accepted, see
+/// [`TlsConfig::with_max_fragment_length`].
+fn negotiate_max_fragment_length<CipherSuite>(
+    key_schedule: &mut ReadKeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    max_fragment_length: MaxFragmentLength,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    // RFC 6066, Section 4.  Maximum Fragment Length Negotiation
+    // Similarly, if a client receives a maximum fragment length negotiation response that differs
+    // from the length it requested, it MUST also abort the handshake with an "illegal_parameter"
+    // alert.
+    if config.max_fragment_length != Some(max_fragment_length) {
+        warn!("Server accepted a maximum fragment length which was not requested");
+        return Err(illegal_parameter());
+    }
+    key_schedule.set_max_fragment_length(max_fragment_length);
+    Ok(())
+}
+
+/// Enables the heartbeats once the server answered the heartbeat extension, see
+/// [`TlsConfig::with_heartbeat`].
+fn negotiate_heartbeat<CipherSuite>(
+    key_schedule: &mut ReadKeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    peer_mode: HeartbeatMode,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    // Section 4.2.  Extensions
+    // Upon receiving such an extension, an endpoint MUST abort the handshake with an
+    // "unsupported_extension" alert.
+    let Some(mode) = config.heartbeat else {
+        warn!("Server sent a heartbeat extension which was not requested");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        ));
+    };
+    key_schedule.heartbeat().negotiate(mode, peer_mode);
+    Ok(())
+}
+
+/// Keeps whether the server accepted the raw public key of the client in EncryptedExtensions,
+/// see [`Certificate::RawPublicKey`].
+fn negotiate_client_certificate_type<CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    config: &TlsConfig<CipherSuite>,
+    selected: Option<CertificateType>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    match selected {
+        Some(_) if !config.offers_client_raw_public_key() => {
+            warn!("Server sent a client_certificate_type extension which was not requested");
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension,
+            ))
+        }
+        // Without the extension, the server expects an X.509 certificate
+        selected => {
+            handshake.client_raw_public_key = selected == Some(CertificateType::RawPublicKey);
+            Ok(())
+        }
+    }
+}
+
+/// Checks the type of the certificate of the server selected in EncryptedExtensions, see
+/// [`Certificate::RawPublicKey`](crate::config::Certificate::RawPublicKey).
+fn negotiate_server_certificate_type<CipherSuite>(
+    config: &TlsConfig<CipherSuite>,
+    selected: Option<CertificateType>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    match (config.server_raw_public_key(), selected) {
+        (Some(_), Some(CertificateType::RawPublicKey)) | (None, None) => Ok(()),
+        // Without the extension, the server sends an X.509 certificate, which cannot be compared
+        // with the pinned key
+        (Some(_), _) => {
+            warn!("Server does not support raw public keys");
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedCertificate,
+            ))
+        }
+        // Section 4.2.  Extensions
+        // Upon receiving such an extension, an endpoint MUST abort the handshake with an
+        // "unsupported_extension" alert.
+        (None, Some(_)) => {
+            warn!("Server sent a server_certificate_type extension which was not requested");
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension,
+            ))
+        }
+    }
+}
+
+/// The strict checks of EncryptedExtensions, see [`TlsConfig::with_strict_validation`].
+fn check_encrypted_extensions<CipherSuite>(
+    extensions: &EncryptedExtensions<'_>,
+    config: &TlsConfig<CipherSuite>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let types = extensions.extension_types();
+    check_duplicates(types)?;
+
+    // Section 4.2.  Extensions
+    // Implementations MUST NOT send extension responses if the remote endpoint did not send the
+    // corresponding extension requests [...] Upon receiving such an extension, an endpoint MUST
+    // abort the handshake with an "unsupported_extension" alert.
+    let unsolicited = (types.contains(ExtensionType::ServerName) && config.sni().is_none())
+        || (types.contains(ExtensionType::MaxFragmentLength)
+            && config.max_fragment_length.is_none())
+        || (types.contains(ExtensionType::TicketRequest) && config.ticket_request.is_none());
+    if unsolicited {
+        warn!("Server sent an extension which was not requested");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::UnsupportedExtension,
+        ));
+    }
+    Ok(())
+}
+
+/// The strict checks of a CertificateRequest, see [`TlsConfig::with_strict_validation`].
+fn check_certificate_request(request: &CertificateRequestRef<'_>) -> Result<(), TlsError> {
+    check_duplicates(request.extension_types)?;
 
+    // Section 4.3.2.  Certificate Request
+    // The "signature_algorithms" extension MUST be specified
+    check_required(request.extension_types, ExtensionType::SignatureAlgorithms)
+}
+
+fn client_cert<'r, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    config: &TlsConfig<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+    has_signer: bool,
+) -> Result<(State, &'r [u8]), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
     let request_context = &handshake
         .certificate_request
         .as_ref()
@@ -538,18 +2399,172 @@ where
         .request_context;
 
     let mut certificate = CertificateRef::with_context(request_context);
-    if let Some(cert) = &config.cert {
-        certificate.add(cert.into())?;
-    }
+    let raw_public_key = handshake.client_raw_public_key;
+    let can_sign = match client_identity(handshake, config) {
+        Some(identity) if !raw_public_key => {
+            for cert in identity.cert_chain {
+                certificate.add(cert.into())?;
+            }
+            !identity.cert_chain.is_empty()
+        }
+        _ => {
+            // A raw public key is only sent once the server accepted it, without intermediates
+            let cert = config
+                .cert
+                .as_ref()
+                .filter(|cert| matches!(cert, Certificate::RawPublicKey(_)) == raw_public_key);
+            if let Some(cert) = cert {
+                certificate.add(cert.into())?;
+                if !raw_public_key {
+                    for cert in config.intermediates {
+                        certificate.add(cert.into())?;
+                    }
+                }
+            }
+            cert.is_some() && (has_signer || config.priv_key.is_some())
+        }
+    };
     let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
 
+    // Section 4.4.3.  Certificate Verify
+    // Clients MUST send this message whenever authenticating via a certificate
+    // (i.e., when the Certificate message is non-empty).
+    let next_state = if can_sign {
+        State::ClientCertVerify
+    } else {
+        State::ClientFinished
+    };
+
     buffer
         .write_record(
             &ClientRecord::Handshake(ClientHandshake::ClientCert(certificate), true),
             write_key_schedule,
             Some(read_key_schedule),
         )
-        .map(|slice| (State::ClientFinished, slice))
+        .map(|slice| (next_state, slice))
+}
+
+/// The identity selected for the CertificateRequest among the identities of the configuration.
+fn client_identity<'c, 'a, CipherSuite, Provider, Verifier>(
+    handshake: &Handshake<CipherSuite, Provider, Verifier>,
+    config: &'c TlsConfig<'a, CipherSuite>,
+) -> Option<&'c ClientIdentity<'a>>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let identity = handshake.certificate_request.as_ref()?.identity?;
+    config.identities.get(identity)
+}
+
+fn end_of_early_data<'r, CipherSuite, Provider, Verifier>(
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buffer: &'r mut WriteBuffer,
+) -> Result<(State, &'r [u8]), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
+{
+    let next_state = if handshake.certificate_request.is_some() {
+        State::ClientCert
+    } else {
+        State::ClientFinished
+    };
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+
+    buffer
+        .write_record(
+            &ClientRecord::Handshake(ClientHandshake::EndOfEarlyData, true),
+            write_key_schedule,
+            Some(read_key_schedule),
+        )
+        .map(|slice| (next_state, slice))
+}
+
+// 64 bytes of padding + 34 bytes of context string + transcript hash (up to 64 bytes)
+const CERT_VERIFY_MESSAGE_LEN: usize = 64 + 34 + 64;
+
+/// The scratch space of the write buffer used while signing the client CertificateVerify.
+const CERT_VERIFY_SCRATCH_LEN: usize = CERT_VERIFY_MESSAGE_LEN + MAX_SIGNATURE_LEN;
+
+/// The minimum length of the write record buffer when the configuration has a client
+/// certificate, see [`TlsConfig::with_client_identity`].
+///
+/// While the client CertificateVerify is signed, the message to sign and the signature are kept
+/// at the end of the write record buffer rather than in the handshake future, which would
+/// otherwise grow by their maximum size for every connection. The rest of the buffer holds the
+/// CertificateVerify record, with a signature of up to [`MAX_SIGNATURE_LEN`] bytes. Opening a
+/// connection with a smaller buffer fails with [`TlsError::InsufficientSpace`] before the
+/// handshake starts.
+pub const MIN_CLIENT_AUTH_WRITE_BUFFER_LEN: usize = (TLS_RECORD_ALIGNMENT - 1)
+    + CERT_VERIFY_SCRATCH_LEN
+    + TLS_RECORD_OVERHEAD
+    // The handshake header, the signature scheme and the length of the signature
+    + 4
+    + 2
+    + 2
+    + MAX_SIGNATURE_LEN;
+
+/// Checks that the write record buffer fits the client CertificateVerify, if the configuration
+/// has a client certificate, see [`MIN_CLIENT_AUTH_WRITE_BUFFER_LEN`].
+pub(crate) fn check_write_buffer<CipherSuite>(
+    config: &TlsConfig<'_, CipherSuite>,
+    tx_buf: &WriteBuffer<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let client_auth = config.cert.is_some() || !config.identities.is_empty();
+    // The buffer has already been aligned
+    if client_auth
+        && tx_buf.capacity() + (TLS_RECORD_ALIGNMENT - 1) < MIN_CLIENT_AUTH_WRITE_BUFFER_LEN
+    {
+        warn!("The write record buffer is too small for client authentication");
+        return Err(TlsError::InsufficientSpace);
+    }
+    Ok(())
+}
+
+/// Write the content covered by the client CertificateVerify signature to the start of `buf`.
+fn client_cert_verify_message<'m, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    buf: &'m mut [u8],
+) -> Result<&'m [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let mut message = CryptoBuffer::wrap(buf);
+    for _ in 0..64 {
+        message.push(0x20)?;
+    }
+    message.extend_from_slice(CLIENT_CONTEXT)?;
+    message.extend_from_slice(&key_schedule.transcript_hash().clone().finalize())?;
+    let (buf, _, len) = message.release();
+    let buf: &'m [u8] = buf;
+    Ok(&buf[..len])
+}
+
+fn client_cert_verify<'r, CipherSuite>(
+    key_schedule: &mut KeySchedule<CipherSuite>,
+    signature_scheme: SignatureScheme,
+    signature: &[u8],
+    buffer: &'r mut WriteBuffer,
+) -> Result<&'r [u8], TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let certificate_verify = CertificateVerify {
+        signature_scheme,
+        signature,
+    };
+    let (write_key_schedule, read_key_schedule) = key_schedule.as_split();
+
+    buffer.write_record(
+        &ClientRecord::Handshake(ClientHandshake::ClientCertVerify(certificate_verify), true),
+        write_key_schedule,
+        Some(read_key_schedule),
+    )
 }
 
 fn client_finished<'r, CipherSuite>(
@@ -572,13 +2587,17 @@ where
     )
 }
 
-fn client_finished_finalize<CipherSuite, Verifier>(
+fn client_finished_finalize<CipherSuite, Provider, Verifier>(
     key_schedule: &mut KeySchedule<CipherSuite>,
-    handshake: &mut Handshake<CipherSuite, Verifier>,
+    handshake: &mut Handshake<CipherSuite, Provider, Verifier>,
 ) -> Result<State, TlsError>
 where
     CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
 {
+    // The application traffic secrets are derived from the transcript up to the server
+    // Finished, the resumption master secret from the transcript up to the client Finished.
+    let transcript_hash = key_schedule.transcript_hash().clone();
     key_schedule.replace_transcript_hash(
         handshake
             .traffic_hash
@@ -586,6 +2605,961 @@ where
             .ok_or(TlsError::InvalidHandshake)?,
     );
     key_schedule.initialize_master_secret()?;
+    key_schedule.initialize_resumption_secret(&transcript_hash)?;
 
     Ok(State::ApplicationData)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Aes128GcmSha256, NoVerify};
+    use crate::crypto_provider::RustCrypto;
+    use aes_gcm::{aead::KeyInit, Aes128Gcm};
+    use rand::rngs::OsRng;
+
+    fn sealed<'b>(storage: &'b mut [u8], plaintext: &[u8]) -> CryptoBuffer<'b> {
+        let aead = <Aes128Gcm as KeyInit>::new(&Default::default());
+        let mut buf = CryptoBuffer::wrap(storage);
+        buf.extend_from_slice(plaintext).unwrap();
+        aead.seal(&[0; 12], b"aad", &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_open_record_strips_padding() {
+        let aead = <Aes128Gcm as KeyInit>::new(&Default::default());
+        let mut storage = [0; 32];
+        let mut buf = sealed(
+            &mut storage,
+            &[0xAA, ContentType::ApplicationData as u8, 0, 0],
+        );
+
+        let content_type = open_record(&aead, &[0; 12], b"aad", &mut buf).unwrap();
+        assert!(matches!(content_type, ContentType::ApplicationData));
+        assert_eq!(&[0xAA], buf.as_slice());
+    }
+
+    #[test]
+    fn test_open_record_without_content_type() {
+        let aead = <Aes128Gcm as KeyInit>::new(&Default::default());
+        for plaintext in [&[][..], &[0, 0, 0][..]] {
+            let mut storage = [0; 32];
+            let mut buf = sealed(&mut storage, plaintext);
+
+            assert!(matches!(
+                open_record(&aead, &[0; 12], b"aad", &mut buf),
+                Err(TlsError::InvalidRecord)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_open_record_with_wrong_nonce() {
+        let aead = <Aes128Gcm as KeyInit>::new(&Default::default());
+        let mut storage = [0; 32];
+        let mut buf = sealed(&mut storage, &[0xAA, ContentType::ApplicationData as u8]);
+
+        assert!(matches!(
+            open_record(&aead, &[1; 12], b"aad", &mut buf),
+            Err(TlsError::BadRecordMac)
+        ));
+    }
+
+    #[test]
+    fn test_record_padding() {
+        use crate::config::RecordPadding;
+        use crate::record::ClientRecordHeader;
+
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        key_schedule.initialize_early_secret(None).unwrap();
+        key_schedule
+            .initialize_handshake_secret(&[0x42; 32])
+            .unwrap();
+        let mut storage = [0; 512];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+
+        // The content and the padding fill a block, followed by the content type and the tag
+        tx_buf.set_record_padding(Some(RecordPadding::Block(64)));
+        tx_buf
+            .start_record(ClientRecordHeader::ApplicationData)
+            .unwrap();
+        tx_buf.append(b"ping");
+        let record = tx_buf.close_record(key_schedule.write_state()).unwrap();
+        assert_eq!(5 + 64 + 1 + 16, record.len());
+
+        // The padding is limited to the space of the buffer
+        tx_buf.set_record_padding(Some(RecordPadding::Custom(|_| usize::MAX)));
+        tx_buf
+            .start_record(ClientRecordHeader::ApplicationData)
+            .unwrap();
+        tx_buf.append(b"ping");
+        let len = tx_buf.space() + 4;
+        let record = tx_buf.close_record(key_schedule.write_state()).unwrap();
+        assert_eq!(5 + len + 1 + 16, record.len());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_abort_sends_alert() {
+        use crate::testing::Pipe;
+        use std::io::Read;
+
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        let mut storage = [0; 256];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (mut client, mut server) = Pipe::new();
+
+        let error = State::ServerHello.abort_blocking(
+            &mut client,
+            &mut tx_buf,
+            key_schedule.write_state(),
+            TlsError::InvalidCipherSuite,
+        );
+        assert!(matches!(error, TlsError::InvalidCipherSuite));
+
+        // A fatal illegal_parameter alert, before the handshake keys are in use
+        let mut alert = [0; 8];
+        assert_eq!(7, server.read(&mut alert).unwrap());
+        assert_eq!(&[21, 0x03, 0x03, 0, 2, 2, 47], &alert[..7]);
+
+        // Nothing is sent when the server went away
+        State::ServerHello.abort_blocking(
+            &mut client,
+            &mut tx_buf,
+            key_schedule.write_state(),
+            TlsError::UnexpectedEof,
+        );
+        drop(client);
+        assert_eq!(0, server.read(&mut alert).unwrap());
+    }
+
+    /// A HelloRetryRequest for TLS_AES_128_GCM_SHA256 with the extension after supported_versions.
+    fn hello_retry_request(extension: &[u8]) -> heapless::Vec<u8, 64> {
+        let mut buffer = heapless::Vec::new();
+        buffer.extend_from_slice(&[0x03, 0x03]).unwrap();
+        buffer
+            .extend_from_slice(&[
+                0xCF, 0x21, 0xAD, 0x74, 0xE5, 0x9A, 0x61, 0x11, 0xBE, 0x1D, 0x8C, 0x02, 0x1E, 0x65,
+                0xB8, 0x91, 0xC2, 0xA2, 0x11, 0x16, 0x7A, 0xBB, 0x8C, 0x5E, 0x07, 0x9E, 0x09, 0xE2,
+                0xC8, 0xA8, 0x33, 0x9C,
+            ])
+            .unwrap();
+        buffer.extend_from_slice(&[0x00, 0x13, 0x01, 0x00]).unwrap();
+        buffer
+            .extend_from_slice(&(6 + extension.len() as u16).to_be_bytes())
+            .unwrap();
+        buffer
+            .extend_from_slice(&[0x00, 0x2b, 0x00, 0x02, 0x03, 0x04])
+            .unwrap();
+        buffer.extend_from_slice(extension).unwrap();
+        buffer
+    }
+
+    fn selected_group(group: NamedGroup) -> [u8; 6] {
+        let [a, b] = (group as u16).to_be_bytes();
+        [0x00, 0x33, 0x00, 0x02, a, b]
+    }
+
+    const COOKIE: [u8; 8] = [0x00, 0x2c, 0x00, 0x04, 0x00, 0x02, 0xAA, 0xBB];
+
+    fn config() -> TlsConfig<'static, Aes128GcmSha256> {
+        TlsConfig::new().with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519])
+    }
+
+    fn retry(
+        handshake: &mut Handshake<Aes128GcmSha256, RustCrypto, NoVerify>,
+        extension: &[u8],
+    ) -> Result<State, TlsError> {
+        let buffer = hello_retry_request(extension);
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        process_hello_retry_request(handshake, &config(), &hello)
+    }
+
+    fn handshake() -> Handshake<Aes128GcmSha256, RustCrypto, NoVerify> {
+        let secret = RustCrypto::generate_key(NamedGroup::Secp256r1, &mut OsRng).unwrap();
+        Handshake::new(NoVerify, Some(secret))
+    }
+
+    #[test]
+    fn test_hello_retry_request() {
+        let mut handshake = handshake();
+        let extension = selected_group(NamedGroup::X25519);
+        assert_eq!(
+            State::ClientHello,
+            retry(&mut handshake, &extension).unwrap()
+        );
+        assert!(matches!(
+            retry(&mut handshake, &extension),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnexpectedMessage
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_hello_retry_request_without_change() {
+        // The offered group, a group that was not offered, and no change at all
+        for extension in [
+            &selected_group(NamedGroup::Secp256r1)[..],
+            &selected_group(NamedGroup::Secp384r1)[..],
+            &[],
+        ] {
+            assert!(matches!(
+                retry(&mut handshake(), extension),
+                Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::IllegalParameter
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_legacy_session_id_echo() {
+        // The HelloRetryRequest echoes an empty legacy_session_id
+        let buffer = hello_retry_request(&COOKIE);
+        let server_hello = || {
+            let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+            ServerRecord::Handshake(ServerHandshake::ServerHello(hello))
+        };
+        let mut key_schedule = KeySchedule::new();
+
+        let mut first = handshake();
+        assert_eq!(
+            State::ClientHello,
+            process_server_hello(&mut first, &mut key_schedule, &config(), server_hello()).unwrap()
+        );
+
+        let mut second = handshake();
+        second.session_id = Some([0xAA; 32]);
+        assert!(matches!(
+            process_server_hello(&mut second, &mut key_schedule, &config(), server_hello()),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_strict_validation() {
+        let mut extensions = heapless::Vec::<u8, 16>::new();
+        extensions.extend_from_slice(&COOKIE).unwrap();
+        extensions.extend_from_slice(&COOKIE).unwrap();
+        let buffer = hello_retry_request(&extensions);
+        let server_hello = || {
+            let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+            ServerRecord::Handshake(ServerHandshake::ServerHello(hello))
+        };
+        let mut key_schedule = KeySchedule::new();
+
+        // The repeated cookie is only rejected in strict mode
+        let mut lenient = handshake();
+        assert_eq!(
+            State::ClientHello,
+            process_server_hello(&mut lenient, &mut key_schedule, &config(), server_hello())
+                .unwrap()
+        );
+
+        let config = config().with_strict_validation();
+        let mut strict = handshake();
+        assert!(matches!(
+            process_server_hello(&mut strict, &mut key_schedule, &config, server_hello()),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+
+        let buffer = hello_retry_request(&COOKIE);
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        assert!(check_server_hello(&hello).is_ok());
+    }
+
+    #[test]
+    fn test_hello_retry_request_echoes_cookie() {
+        let config = config();
+        let mut handshake = handshake();
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, first) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+        let first = heapless::Vec::<u8, 1024>::from_slice(first).unwrap();
+        assert!(!first.windows(COOKIE.len()).any(|w| w == COOKIE));
+
+        assert_eq!(State::ClientHello, retry(&mut handshake, &COOKIE).unwrap());
+
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, second) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+
+        // Record header, handshake header and legacy_version precede the random and the key
+        // share is kept, since the server only asked for the cookie
+        assert_eq!(first[11..43], second[11..43]);
+        assert!(second.windows(COOKIE.len()).any(|w| w == COOKIE));
+        assert_eq!(first.len() + COOKIE.len(), second.len());
+        assert_eq!(1, handshake.secrets.len());
+        assert_eq!(NamedGroup::Secp256r1, handshake.secrets[0].group());
+    }
+
+    #[test]
+    fn test_middlebox_compatibility() {
+        let config = config().with_middlebox_compatibility();
+        let mut handshake = handshake();
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, first) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+        let first = heapless::Vec::<u8, 1024>::from_slice(first).unwrap();
+        let session_id = handshake.session_id.unwrap();
+        assert_eq!(32, first[43]);
+        assert_eq!(session_id, first[44..76]);
+
+        // The change_cipher_spec record precedes the second ClientHello, and is only sent once
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let state = State::ServerHello;
+        assert!(change_cipher_spec(
+            state,
+            &mut handshake,
+            &mut key_schedule,
+            &config,
+            &mut tx_buf
+        )
+        .unwrap()
+        .is_none());
+        assert_eq!(State::ClientHello, retry(&mut handshake, &COOKIE).unwrap());
+        let state = State::ClientHello;
+        assert_eq!(
+            Some(&[20, 3, 3, 0, 1, 1][..]),
+            change_cipher_spec(
+                state,
+                &mut handshake,
+                &mut key_schedule,
+                &config,
+                &mut tx_buf
+            )
+            .unwrap()
+        );
+        let state = State::ClientFinished;
+        assert!(change_cipher_spec(
+            state,
+            &mut handshake,
+            &mut key_schedule,
+            &config,
+            &mut tx_buf
+        )
+        .unwrap()
+        .is_none());
+
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, second) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+        assert_eq!(first[11..76], second[11..76]);
+    }
+
+    #[test]
+    fn test_ocsp_stapling() {
+        let mut certificate = CertificateRef::with_context(&[]);
+        certificate
+            .add(CertificateEntryRef::X509(&[1, 2, 3]))
+            .unwrap();
+        certificate.add(CertificateEntryRef::X509(&[4, 5])).unwrap();
+        let config = config().with_ocsp_stapling(|response, certificate, issuer| {
+            response == Some(&[0xAA][..]) && certificate == [1, 2, 3] && issuer == Some(&[4, 5][..])
+        });
+
+        // A missing response is left to the verifier
+        assert!(matches!(
+            verify_ocsp_response(&config, &certificate),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::BadCertificateStatusResponse
+            ))
+        ));
+        certificate.ocsp_response = Some(&[0xAA]);
+        assert!(verify_ocsp_response(&config, &certificate).is_ok());
+    }
+
+    #[cfg(feature = "sct")]
+    #[test]
+    fn test_certificate_transparency() {
+        use crate::config::CtLog;
+
+        let mut certificate = CertificateRef::with_context(&[]);
+        certificate
+            .add(CertificateEntryRef::X509(&[1, 2, 3]))
+            .unwrap();
+        let logs = [CtLog::new(&[0x30, 0x00])];
+
+        // SCTs are only required when asked for
+        assert!(verify_scts(&config(), &certificate).is_ok());
+        assert!(verify_scts(
+            &config().with_certificate_transparency(&logs, 0),
+            &certificate
+        )
+        .is_ok());
+        assert!(matches!(
+            verify_scts(
+                &config().with_certificate_transparency(&logs, 1),
+                &certificate
+            ),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::BadCertificate
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_client_auth_write_buffer() {
+        let mut storage = [0; MIN_CLIENT_AUTH_WRITE_BUFFER_LEN];
+        let (small, _) = storage.split_at_mut(MIN_CLIENT_AUTH_WRITE_BUFFER_LEN - 64);
+        let small = WriteBuffer::new(small);
+        // Without a client certificate, the CertificateVerify is never sent
+        assert!(check_write_buffer(&config(), &small).is_ok());
+
+        let config = config().with_cert(Certificate::RawPublicKey(&[0x30, 0x00]));
+        assert!(matches!(
+            check_write_buffer(&config, &small),
+            Err(TlsError::InsufficientSpace)
+        ));
+        let tx_buf = WriteBuffer::new(&mut storage);
+        assert!(check_write_buffer(&config, &tx_buf).is_ok());
+    }
+
+    #[test]
+    fn test_client_raw_public_key() {
+        let config = config().with_cert(Certificate::RawPublicKey(&[0x30, 0x00]));
+        let mut handshake = handshake();
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, record) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+        assert!(extension_types(record).contains(&(ExtensionType::ClientCertificateType as u16)));
+
+        // Without the extension, the server expects an X.509 certificate
+        negotiate_client_certificate_type(&mut handshake, &config, None).unwrap();
+        assert!(!handshake.client_raw_public_key);
+        negotiate_client_certificate_type(
+            &mut handshake,
+            &config,
+            Some(CertificateType::RawPublicKey),
+        )
+        .unwrap();
+        assert!(handshake.client_raw_public_key);
+
+        assert!(matches!(
+            negotiate_client_certificate_type(
+                &mut handshake,
+                &config(),
+                Some(CertificateType::RawPublicKey)
+            ),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_ticket_request() {
+        // The hint of the server to send 2 tickets
+        let data = [0x00, 0x05, 0x00, 0x3a, 0x00, 0x01, 0x02];
+        let extensions = EncryptedExtensions::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert_eq!(Some(2), extensions.expected_tickets);
+
+        let config = config().with_strict_validation();
+        assert!(matches!(
+            check_encrypted_extensions(&extensions, &config),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension
+            ))
+        ));
+        let config = config.with_ticket_request(2, 1);
+        assert!(check_encrypted_extensions(&extensions, &config).is_ok());
+    }
+
+    #[test]
+    fn test_certificate_authorities() {
+        let ca = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        let subject = crate::x509::subject(&ca).unwrap();
+        let offers = |config: &TlsConfig<'_, Aes128GcmSha256>| {
+            let mut key_schedule = KeySchedule::new();
+            let mut storage = [0; 1024];
+            let mut tx_buf = WriteBuffer::new(&mut storage);
+            let (_, record) = client_hello(
+                &mut key_schedule,
+                config,
+                &mut OsRng,
+                &mut tx_buf,
+                &mut handshake(),
+            )
+            .unwrap();
+            let offered =
+                extension_types(record).contains(&(ExtensionType::CertificateAuthorities as u16));
+            assert_eq!(offered, record.windows(subject.len()).any(|w| w == subject));
+            offered
+        };
+
+        let config = config().with_ca(Certificate::X509(&ca));
+        assert!(!offers(&config));
+        let config = config.with_certificate_authorities();
+        assert!(offers(&config));
+
+        // A pinned raw public key has no subject
+        let config = config.with_ca(Certificate::RawPublicKey(&[0x30, 0x00]));
+        assert!(!offers(&config));
+
+        // The old and the new CA of a rotation are both named
+        let old = pem_parser::pem_to_der(include_str!("../tests/data/p384/ca-cert.pem"));
+        let roots: [&[u8]; 2] = [&old, &ca];
+        let config = self::config().with_root_certificates(&roots);
+        assert!(!offers(&config));
+        let config = config.with_certificate_authorities();
+        assert!(offers(&config));
+        let mut storage = [0; 1024];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, record) = client_hello(
+            &mut KeySchedule::new(),
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake(),
+        )
+        .unwrap();
+        let old_subject = crate::x509::subject(&old).unwrap();
+        assert!(record.windows(old_subject.len()).any(|w| w == old_subject));
+    }
+
+    #[test]
+    fn test_signature_algorithms_cert() {
+        let offers = |config: &TlsConfig<'_, Aes128GcmSha256>| {
+            let mut key_schedule = KeySchedule::new();
+            let mut storage = [0; 1024];
+            let mut tx_buf = WriteBuffer::new(&mut storage);
+            let (_, record) = client_hello(
+                &mut key_schedule,
+                config,
+                &mut OsRng,
+                &mut tx_buf,
+                &mut handshake(),
+            )
+            .unwrap();
+            extension_types(record).contains(&(ExtensionType::SignatureAlgorithmsCert as u16))
+        };
+
+        let config = config()
+            .with_signature_algorithms(&[SignatureScheme::EcdsaSecp256r1Sha256])
+            .unwrap();
+        assert!(!offers(&config));
+        let config = config
+            .with_signature_algorithms_cert(&[
+                SignatureScheme::EcdsaSecp256r1Sha256,
+                SignatureScheme::RsaPkcs1Sha1,
+            ])
+            .unwrap();
+        assert!(offers(&config));
+        assert!(matches!(
+            TlsConfig::<Aes128GcmSha256>::new()
+                .with_signature_algorithms_cert(&[SignatureScheme::EcdsaSecp256r1Sha256; 17]),
+            Err(TlsError::InsufficientSpace)
+        ));
+        assert_eq!(
+            config.signature_schemes.as_slice(),
+            &[SignatureScheme::EcdsaSecp256r1Sha256]
+        );
+    }
+
+    #[test]
+    fn test_heartbeat() {
+        let config = config().with_heartbeat(HeartbeatMode::PeerAllowedToSend);
+        let mut handshake = handshake();
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, record) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+        assert!(extension_types(record).contains(&(ExtensionType::Heartbeat as u16)));
+
+        // The server does not accept heartbeat requests
+        let data = [0x00, 0x05, 0x00, 0x0f, 0x00, 0x01, 0x02];
+        let extensions = EncryptedExtensions::parse(&mut ParseBuffer::new(&data)).unwrap();
+        let mode = extensions.heartbeat_mode.unwrap();
+        assert_eq!(HeartbeatMode::PeerNotAllowedToSend, mode);
+
+        let key_schedule = key_schedule.read_state();
+        assert!(matches!(
+            key_schedule.heartbeat().request(b"ping"),
+            Err(TlsError::HeartbeatNotAllowed)
+        ));
+        assert!(matches!(
+            negotiate_heartbeat(key_schedule, &config(), mode),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension
+            ))
+        ));
+        negotiate_heartbeat(key_schedule, &config, mode).unwrap();
+        assert!(matches!(
+            key_schedule.heartbeat().request(b"ping"),
+            Err(TlsError::HeartbeatNotAllowed)
+        ));
+
+        let data = [0x00, 0x05, 0x00, 0x0f, 0x00, 0x01, 0x01];
+        let extensions = EncryptedExtensions::parse(&mut ParseBuffer::new(&data)).unwrap();
+        negotiate_heartbeat(key_schedule, &config, extensions.heartbeat_mode.unwrap()).unwrap();
+        assert!(key_schedule.heartbeat().request(b"ping").is_ok());
+
+        // RFC 6520, Section 2.  Heartbeat Hello Extension
+        // Upon reception of an unknown mode, an error Alert message using illegal_parameter as
+        // its AlertDescription MUST be sent in response.
+        let data = [0x00, 0x05, 0x00, 0x0f, 0x00, 0x01, 0x03];
+        assert!(matches!(
+            EncryptedExtensions::parse(&mut ParseBuffer::new(&data)),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_custom_extensions() {
+        let config = config().with_custom_extensions(&[(0xfe00, &b"telemetry"[..])]);
+        let mut handshake = handshake();
+
+        // The echo of the custom extension, and an extension which was not offered
+        let data = [
+            0x00, 0x0a, 0xfe, 0x00, 0x00, 0x02, 0x01, 0x02, 0xfe, 0x01, 0x00, 0x00,
+        ];
+        let extensions = EncryptedExtensions::parse(&mut ParseBuffer::new(&data)).unwrap();
+        receive_custom_extensions(&mut handshake, &config, extensions.extensions()).unwrap();
+        assert_eq!(
+            Some(&[0x01, 0x02][..]),
+            handshake.custom_extensions().get(0xfe00)
+        );
+        assert_eq!(None, handshake.custom_extensions().get(0xfe01));
+
+        // The extension which was not offered is ignored, unless the validation is strict
+        let message = HandshakeType::EncryptedExtensions;
+        assert!(handle_unknown_extensions(&config, message, extensions.extensions(), true).is_ok());
+        let strict = config.clone().with_strict_validation();
+        assert!(matches!(
+            handle_unknown_extensions(&strict, message, extensions.extensions(), true),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnsupportedExtension
+            ))
+        ));
+        assert!(
+            handle_unknown_extensions(&strict, message, extensions.extensions(), false).is_ok()
+        );
+
+        // The handler decides otherwise, and is not called for the custom extension
+        let handler = config.with_unknown_extension_handler(|_, extension_type, data| {
+            assert_eq!(0xfe01, extension_type);
+            data.is_empty()
+        });
+        assert!(
+            handle_unknown_extensions(&handler, message, extensions.extensions(), true).is_ok()
+        );
+        let handler = handler.with_unknown_extension_handler(|_, _, _| false);
+        assert!(
+            handle_unknown_extensions(&handler, message, extensions.extensions(), false).is_err()
+        );
+    }
+
+    /// The types of the extensions of a ClientHello record.
+    fn extension_types(record: &[u8]) -> heapless::Vec<u16, 16> {
+        // Record and handshake headers, legacy_version and random
+        let mut pos = 5 + 4 + 2 + 32;
+        pos += 1 + record[pos] as usize;
+        pos += 2 + u16::from_be_bytes([record[pos], record[pos + 1]]) as usize;
+        pos += 1 + record[pos] as usize;
+        pos += 2;
+
+        let mut types = heapless::Vec::new();
+        while pos < record.len() {
+            types
+                .push(u16::from_be_bytes([record[pos], record[pos + 1]]))
+                .unwrap();
+            pos += 4 + u16::from_be_bytes([record[pos + 2], record[pos + 3]]) as usize;
+        }
+        types
+    }
+
+    #[test]
+    fn test_extension_order() {
+        let order = [
+            ExtensionType::ServerName,
+            ExtensionType::KeyShare,
+            ExtensionType::ServerName,
+            ExtensionType::PreSharedKey,
+        ];
+        let config = config()
+            .with_server_name("example.com")
+            .with_psk(&[0xAA; 32], &[&b"identity"[..]])
+            .with_extension_order(&order);
+        let mut handshake = handshake();
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, record) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+
+        // The configured extensions come first and only once, then the other ones in the default
+        // order, and the pre_shared_key extension stays last
+        assert_eq!(
+            &[
+                ExtensionType::ServerName as u16,
+                ExtensionType::KeyShare as u16,
+                ExtensionType::SupportedVersions as u16,
+                ExtensionType::SignatureAlgorithms as u16,
+                ExtensionType::SupportedGroups as u16,
+                ExtensionType::PskKeyExchangeModes as u16,
+                ExtensionType::PreSharedKey as u16,
+            ],
+            extension_types(record).as_slice()
+        );
+    }
+
+    #[cfg(feature = "ech")]
+    #[test]
+    fn test_encrypted_client_hello() {
+        // An ECHConfigList with a config for X25519, HKDF-SHA256 and AES-128-GCM
+        let mut list = heapless::Vec::<u8, 64>::new();
+        list.extend_from_slice(&[
+            0x00, 0x3e, 0xfe, 0x0d, 0x00, 0x3a, 0x01, 0x00, 0x20, 0x00, 0x20,
+        ])
+        .unwrap();
+        list.extend_from_slice(&[0x09; 32]).unwrap();
+        list.extend_from_slice(&[0x00, 0x04, 0x00, 0x01, 0x00, 0x01, 0x20, 0x0b])
+            .unwrap();
+        list.extend_from_slice(b"public.name").unwrap();
+        list.extend_from_slice(&[0x00, 0x00]).unwrap();
+
+        let protocols = [&b"secret-protocol"[..]];
+        let custom_extensions = [(0xff01, &b"secret-extension"[..])];
+        let config = config()
+            .with_server_name("secret.example")
+            .with_alpn_protocols(&protocols)
+            .with_custom_extensions(&custom_extensions)
+            .with_ech(&list);
+        let mut handshake = handshake();
+        handshake.set_ech_verifier(NoVerify);
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 2048];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        let (_, record) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+
+        // The ClientHelloOuter carries the public name, and the encrypted ClientHelloInner last,
+        // without the extensions revealing the backend server
+        assert!(record.windows(11).any(|w| w == b"public.name"));
+        assert!(!record.windows(6).any(|w| w == b"secret"));
+        let types = extension_types(record);
+        assert!(!types.contains(&(ExtensionType::ApplicationLayerProtocolNegotiation as u16)));
+        assert!(!types.contains(&0xff01));
+        assert_eq!(
+            Some(&(ExtensionType::EncryptedClientHello as u16)),
+            extension_types(record).last()
+        );
+
+        // A ServerHello with the acceptance confirmation in the last 8 bytes of its random
+        let mut buffer =
+            hello_retry_request(&[0x00, 0x33, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x02, 0xAA, 0xBB]);
+        buffer[2..34].fill(0x42);
+        let inner_transcript = key_schedule
+            .read_state()
+            .take_inner_transcript_hash()
+            .unwrap();
+        let inner_random = handshake.ech.as_ref().unwrap().inner_random;
+        let mut transcript = inner_transcript.clone();
+        ServerHello::parse(&mut ParseBuffer::new(&buffer))
+            .unwrap()
+            .update_transcript(&mut transcript, true)
+            .unwrap();
+        let confirmation =
+            KeySchedule::<Aes128GcmSha256>::ech_accept_confirmation(&inner_random, &transcript)
+                .unwrap();
+
+        // Without it, the server rejected ECH and is authenticated for the public name
+        key_schedule
+            .read_state()
+            .set_inner_transcript_hash(inner_transcript.clone());
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        process_ech_acceptance(&mut handshake, key_schedule.read_state(), &hello).unwrap();
+        assert_eq!(EchStatus::Rejected, handshake.ech.as_ref().unwrap().status);
+        assert!(handshake.ech_verifier.is_none());
+        assert_eq!(
+            Some("public.name"),
+            authenticated_name(&handshake, &config).unwrap()
+        );
+
+        handshake.ech.as_mut().unwrap().status = EchStatus::Offered;
+        key_schedule
+            .read_state()
+            .set_inner_transcript_hash(inner_transcript);
+        buffer[26..34].copy_from_slice(&confirmation);
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        process_ech_acceptance(&mut handshake, key_schedule.read_state(), &hello).unwrap();
+        assert_eq!(EchStatus::Accepted, handshake.ech.as_ref().unwrap().status);
+        assert_eq!(inner_random, handshake.random);
+
+        // A HelloRetryRequest would need the ClientHelloInner to be encrypted again
+        assert!(matches!(
+            retry(&mut handshake, &selected_group(NamedGroup::Secp384r1)),
+            Err(TlsError::Unimplemented)
+        ));
+    }
+
+    #[test]
+    fn test_multiple_key_shares() {
+        let config = TlsConfig::new()
+            .with_named_groups(&[
+                NamedGroup::X25519,
+                NamedGroup::Secp384r1,
+                NamedGroup::Secp256r1,
+            ])
+            .with_key_shares(2);
+        // A key share generated in advance takes the place of a less preferred group
+        let mut handshake = handshake();
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+
+        let (_, hello) = client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut handshake,
+        )
+        .unwrap();
+
+        let groups: heapless::Vec<_, MAX_KEY_SHARES> = handshake
+            .secrets
+            .iter()
+            .map(|secret| secret.group())
+            .collect();
+        assert_eq!(
+            [NamedGroup::X25519, NamedGroup::Secp256r1],
+            groups.as_slice()
+        );
+        for secret in handshake.secrets.iter() {
+            let public_key = secret.public_key().unwrap();
+            assert!(hello.windows(public_key.len()).any(|w| w == public_key));
+        }
+
+        // The server must not ask for a group which already has a key share
+        assert!(matches!(
+            retry(&mut handshake, &selected_group(NamedGroup::X25519)),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_psk_key_exchange_modes() {
+        let config = config()
+            .with_psk(&[0xAA; 32], &[b"identity"])
+            .with_psk_key_exchange_modes(&[PskKeyExchangeMode::PskKe]);
+        // No key share is generated for PSK-only key establishment
+        let mut psk_only: Handshake<Aes128GcmSha256, RustCrypto, NoVerify> =
+            Handshake::new(NoVerify, None);
+        let mut key_schedule = KeySchedule::new();
+        let mut storage = [0; 1024];
+        let mut tx_buf = WriteBuffer::new(&mut storage);
+        client_hello(
+            &mut key_schedule,
+            &config,
+            &mut OsRng,
+            &mut tx_buf,
+            &mut psk_only,
+        )
+        .unwrap();
+        assert!(psk_only.secrets.is_empty());
+
+        // The server accepts the PSK without a key share
+        let mut buffer = hello_retry_request(&[0x00, 0x29, 0x00, 0x02, 0x00, 0x00]);
+        buffer[2..34].fill(0x11);
+        let server_hello = || {
+            let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+            ServerRecord::Handshake(ServerHandshake::ServerHello(hello))
+        };
+        assert_eq!(
+            State::ServerVerify,
+            process_server_hello(&mut psk_only, &mut key_schedule, &config, server_hello())
+                .unwrap()
+        );
+
+        // which is only allowed if the mode was offered
+        let config = config.with_psk_key_exchange_modes(&[PskKeyExchangeMode::PskDheKe]);
+        assert!(matches!(
+            process_server_hello(&mut handshake(), &mut key_schedule, &config, server_hello()),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::MissingExtension
+            ))
+        ));
+    }
+}
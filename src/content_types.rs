@@ -6,6 +6,7 @@ pub enum ContentType {
     Alert = 21,
     Handshake = 22,
     ApplicationData = 23,
+    Heartbeat = 24,
 }
 
 impl ContentType {
@@ -16,6 +17,7 @@ impl ContentType {
             21 => Some(Self::Alert),
             22 => Some(Self::Handshake),
             23 => Some(Self::ApplicationData),
+            24 => Some(Self::Heartbeat),
             _ => None,
         }
     }
@@ -0,0 +1,106 @@
+//! A [`CryptoProvider`] using the `p256-cortex-m4` crate for P-256, which is several times faster
+//! than the generic `p256` crate on Cortex-M4 and newer cores.
+use rand_core::{CryptoRng, RngCore};
+
+use crate::crypto_provider::{CryptoProvider, KeyExchange, RustCrypto};
+use crate::key_exchange::{EphemeralSecret, PublicKey, SharedSecret};
+use crate::named_groups::NamedGroup;
+use crate::signature_schemes::SignatureScheme;
+use crate::TlsError;
+
+/// Performs secp256r1 key exchange and ECDSA P-256 verification with `p256-cortex-m4`, and falls
+/// back to [`RustCrypto`] for everything else.
+pub struct CortexM4;
+
+impl CryptoProvider for CortexM4 {
+    type KeyExchange = CortexM4Secret;
+
+    fn generate_key<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self::KeyExchange, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        match group {
+            NamedGroup::Secp256r1 => Ok(CortexM4Secret::Secp256r1(
+                p256_cortex_m4::SecretKey::random(&mut *rng),
+            )),
+            _ => Ok(CortexM4Secret::Other(EphemeralSecret::generate(
+                group, rng,
+            )?)),
+        }
+    }
+
+    #[cfg(feature = "webpki")]
+    fn verify_signature(
+        scheme: SignatureScheme,
+        certificate: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        use crate::x509::{SubjectPublicKeyInfo, ID_EC_PUBLIC_KEY, SECP256R1};
+
+        if scheme != SignatureScheme::EcdsaSecp256r1Sha256 {
+            return RustCrypto::verify_signature(scheme, certificate, message, signature);
+        }
+
+        let spki = SubjectPublicKeyInfo::from_certificate(certificate)?;
+        if spki.algorithm != ID_EC_PUBLIC_KEY || spki.named_curve != Some(SECP256R1) {
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        let key = p256_cortex_m4::PublicKey::from_sec1_bytes(spki.public_key)
+            .map_err(|_| TlsError::InvalidCertificate)?;
+        let signature = p256_cortex_m4::Signature::from_sec1_bytes(signature)
+            .map_err(|_| TlsError::InvalidSignature)?;
+        if key.verify(message, &signature) {
+            Ok(())
+        } else {
+            Err(TlsError::InvalidSignature)
+        }
+    }
+
+    #[cfg(not(feature = "webpki"))]
+    fn verify_signature(
+        scheme: SignatureScheme,
+        certificate: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        RustCrypto::verify_signature(scheme, certificate, message, signature)
+    }
+}
+
+/// The ephemeral key of the [`CortexM4`] provider.
+pub enum CortexM4Secret {
+    Secp256r1(p256_cortex_m4::SecretKey),
+    Other(EphemeralSecret),
+}
+
+impl KeyExchange for CortexM4Secret {
+    fn group(&self) -> NamedGroup {
+        match self {
+            Self::Secp256r1(_) => NamedGroup::Secp256r1,
+            Self::Other(secret) => secret.group(),
+        }
+    }
+
+    fn public_key(&self) -> Result<PublicKey, TlsError> {
+        match self {
+            Self::Secp256r1(secret) => {
+                let point = secret.public_key().to_uncompressed_sec1_bytes();
+                PublicKey::from_slice(&point[..]).map_err(|_| TlsError::EncodeError)
+            }
+            Self::Other(secret) => secret.public_key(),
+        }
+    }
+
+    fn diffie_hellman(self, peer: &[u8]) -> Result<SharedSecret, TlsError> {
+        match self {
+            Self::Secp256r1(secret) => {
+                let peer = p256_cortex_m4::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.agree(&peer);
+                SharedSecret::new(&[&shared.as_bytes()[..]])
+            }
+            Self::Other(secret) => secret.diffie_hellman(peer),
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use crate::application_data::ApplicationData;
+use crate::key_exchange::SharedSecret;
 use crate::named_groups::NamedGroup;
-use p256::ecdh::SharedSecret;
 
+/// The shared secret is wiped when the engine is dropped.
 pub struct CryptoEngine {
     group: NamedGroup,
     shared: SharedSecret,
@@ -0,0 +1,126 @@
+use rand_core::{CryptoRng, RngCore};
+
+use crate::key_exchange::{EphemeralSecret, PublicKey, SharedSecret};
+use crate::named_groups::NamedGroup;
+use crate::signature_schemes::SignatureScheme;
+use crate::TlsError;
+
+/// Supplies the public key primitives used during the handshake: the ephemeral key exchange and
+/// the verification of the server `CertificateVerify` signature.
+///
+/// The provider is a type parameter of the connection, so that these primitives can be delegated
+/// to hardware accelerated or certified implementations without changing the rest of the crate.
+/// It does not cover hashing, HKDF or record protection: those are tied to the negotiated cipher
+/// suite, and are replaced by implementing [`TlsCipherSuite`](crate::TlsCipherSuite) with other
+/// `Hash`, `Hkdf` and `Cipher` types.
+///
+/// [`RustCrypto`] is the default provider, which is backed by the RustCrypto crates.
+pub trait CryptoProvider {
+    type KeyExchange: KeyExchange;
+
+    /// Generate an ephemeral key for the given group.
+    ///
+    /// Returns [`TlsError::Unimplemented`] if the group is not supported by the provider.
+    fn generate_key<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self::KeyExchange, TlsError>
+    where
+        RNG: CryptoRng + RngCore;
+
+    /// Verify a handshake signature with the public key of a DER encoded X.509 certificate.
+    ///
+    /// Certificate verifiers try the provider first, and only verify the signature themselves
+    /// when [`TlsError::InvalidSignatureScheme`] is returned for a scheme the provider does not
+    /// implement.
+    fn verify_signature(
+        scheme: SignatureScheme,
+        certificate: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError>;
+}
+
+/// An ephemeral key exchange of a single handshake.
+pub trait KeyExchange: Sized {
+    fn group(&self) -> NamedGroup;
+
+    /// The public key encoded as the `key_exchange` field of a KeyShareEntry.
+    fn public_key(&self) -> Result<PublicKey, TlsError>;
+
+    /// Compute the shared secret from the encoded public key of the peer.
+    fn diffie_hellman(self, peer: &[u8]) -> Result<SharedSecret, TlsError>;
+}
+
+/// The default provider, backed by the RustCrypto crates.
+pub struct RustCrypto;
+
+impl CryptoProvider for RustCrypto {
+    type KeyExchange = EphemeralSecret;
+
+    fn generate_key<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self::KeyExchange, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        EphemeralSecret::generate(group, rng)
+    }
+
+    #[cfg(feature = "webpki")]
+    fn verify_signature(
+        scheme: SignatureScheme,
+        certificate: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        if !crate::signature::is_supported(scheme) {
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        let spki = crate::x509::SubjectPublicKeyInfo::from_certificate(certificate)?;
+        crate::signature::verify_signature(scheme, &spki, message, signature)
+    }
+
+    #[cfg(not(feature = "webpki"))]
+    fn verify_signature(
+        _scheme: SignatureScheme,
+        _certificate: &[u8],
+        _message: &[u8],
+        _signature: &[u8],
+    ) -> Result<(), TlsError> {
+        Err(TlsError::InvalidSignatureScheme)
+    }
+}
+
+impl KeyExchange for EphemeralSecret {
+    fn group(&self) -> NamedGroup {
+        EphemeralSecret::group(self)
+    }
+
+    fn public_key(&self) -> Result<PublicKey, TlsError> {
+        EphemeralSecret::public_key(self)
+    }
+
+    fn diffie_hellman(self, peer: &[u8]) -> Result<SharedSecret, TlsError> {
+        EphemeralSecret::diffie_hellman(self, peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    fn exchange<Provider: CryptoProvider>(group: NamedGroup) {
+        let client = Provider::generate_key(group, &mut OsRng).unwrap();
+        let server = Provider::generate_key(group, &mut OsRng).unwrap();
+        assert_eq!(group, client.group());
+
+        let client_public = client.public_key().unwrap();
+        let server_public = server.public_key().unwrap();
+        let client_shared = client.diffie_hellman(&server_public).unwrap();
+        let server_shared = server.diffie_hellman(&client_public).unwrap();
+        assert_eq!(client_shared.as_bytes(), server_shared.as_bytes());
+    }
+
+    #[test]
+    fn test_rust_crypto_key_exchange() {
+        exchange::<RustCrypto>(NamedGroup::Secp256r1);
+        exchange::<RustCrypto>(NamedGroup::X25519);
+    }
+}
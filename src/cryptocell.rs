@@ -0,0 +1,442 @@
+//! Hardware acceleration with the Arm CryptoCell of the nRF52840 (CC310) and nRF5340 (CC312).
+//!
+//! The P-256 key exchange, SHA-256 and AES-128-GCM are routed through the CryptoCell, which
+//! roughly halves the handshake time and drops the corresponding software implementations from
+//! flash. The driver is supplied by implementing [`CryptoCellDriver`], for example on top of the
+//! `nrf_cc3xx` platform library of the nRF Connect SDK. The CryptoCell must be enabled and
+//! initialized before the handshake starts.
+use core::marker::PhantomData;
+
+use aes_gcm::aead::{Buffer, Error};
+use digest::core_api::BlockSizeUser;
+use digest::{FixedOutput, HashMarker, Output, OutputSizeUser, Reset, Update};
+use generic_array::GenericArray;
+use rand_core::{CryptoRng, RngCore};
+use typenum::{U12, U16, U32, U64};
+use zeroize::Zeroize;
+
+use crate::cipher_suites::CipherSuite;
+use crate::config::{LabelBuffer, TlsCipherSuite};
+use crate::crypto_provider::{CryptoProvider, KeyExchange, RustCrypto};
+use crate::kdf::SimpleHkdf;
+use crate::key_exchange::{EphemeralSecret, PublicKey, SharedSecret};
+use crate::named_groups::NamedGroup;
+use crate::record_aead::{ciphertext_region, RecordAead};
+use crate::signature_schemes::SignatureScheme;
+use crate::TlsError;
+
+/// The CryptoCell operations used by the handshake and the record layer.
+///
+/// The CryptoCell is a single peripheral, so the operations are associated functions. The driver
+/// is responsible for serializing access if the CryptoCell is shared with other tasks.
+pub trait CryptoCellDriver {
+    /// The context of an ongoing SHA-256 computation, which is cloned to snapshot the transcript.
+    type Sha256Context: Clone;
+
+    fn sha256_init() -> Self::Sha256Context;
+
+    fn sha256_update(context: &mut Self::Sha256Context, data: &[u8]);
+
+    fn sha256_finish(context: Self::Sha256Context, digest: &mut [u8; 32]);
+
+    /// Generate a P-256 key pair with the TRNG of the CryptoCell, writing the private scalar and
+    /// the uncompressed SEC1 public key.
+    fn ecdh_p256_generate(private: &mut [u8; 32], public: &mut [u8; 65]) -> Result<(), TlsError>;
+
+    /// Compute the P-256 shared secret with the SEC1 encoded public key of the peer. The driver
+    /// must reject points which are not on the curve.
+    fn ecdh_p256_compute(
+        private: &[u8; 32],
+        peer: &[u8],
+        shared: &mut [u8; 32],
+    ) -> Result<(), TlsError>;
+
+    /// Encrypt `buffer` in place with AES-128-GCM, writing the authentication tag.
+    fn aes_gcm_encrypt(
+        key: &[u8; 16],
+        nonce: &[u8; 12],
+        additional_data: &[u8],
+        buffer: &mut [u8],
+        tag: &mut [u8; 16],
+    ) -> Result<(), TlsError>;
+
+    /// Verify the authentication tag and decrypt `buffer` in place with AES-128-GCM.
+    fn aes_gcm_decrypt(
+        key: &[u8; 16],
+        nonce: &[u8; 12],
+        additional_data: &[u8],
+        buffer: &mut [u8],
+        tag: &[u8; 16],
+    ) -> Result<(), TlsError>;
+}
+
+/// Performs secp256r1 key exchange on the CryptoCell, and falls back to [`RustCrypto`] for
+/// everything else.
+///
+/// The private key is generated by the TRNG of the CryptoCell rather than the [`TlsRng`] of the
+/// [`TlsContext`](crate::TlsContext).
+///
+/// [`TlsRng`]: crate::TlsRng
+pub struct CryptoCell<D>(PhantomData<D>);
+
+impl<D> CryptoProvider for CryptoCell<D>
+where
+    D: CryptoCellDriver,
+{
+    type KeyExchange = CryptoCellSecret<D>;
+
+    fn generate_key<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self::KeyExchange, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        match group {
+            NamedGroup::Secp256r1 => {
+                let mut key = CryptoCellKey {
+                    private: [0; 32],
+                    public: [0; 65],
+                    _driver: PhantomData,
+                };
+                D::ecdh_p256_generate(&mut key.private, &mut key.public)?;
+                Ok(CryptoCellSecret::Secp256r1(key))
+            }
+            _ => Ok(CryptoCellSecret::Other(EphemeralSecret::generate(
+                group, rng,
+            )?)),
+        }
+    }
+
+    fn verify_signature(
+        scheme: SignatureScheme,
+        certificate: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), TlsError> {
+        RustCrypto::verify_signature(scheme, certificate, message, signature)
+    }
+}
+
+/// The ephemeral key of the [`CryptoCell`] provider.
+pub enum CryptoCellSecret<D> {
+    Secp256r1(CryptoCellKey<D>),
+    Other(EphemeralSecret),
+}
+
+/// A P-256 key pair generated by the CryptoCell.
+pub struct CryptoCellKey<D> {
+    private: [u8; 32],
+    public: [u8; 65],
+    _driver: PhantomData<D>,
+}
+
+impl<D> Drop for CryptoCellKey<D> {
+    fn drop(&mut self) {
+        self.private.zeroize();
+    }
+}
+
+impl<D> KeyExchange for CryptoCellSecret<D>
+where
+    D: CryptoCellDriver,
+{
+    fn group(&self) -> NamedGroup {
+        match self {
+            Self::Secp256r1(_) => NamedGroup::Secp256r1,
+            Self::Other(secret) => secret.group(),
+        }
+    }
+
+    fn public_key(&self) -> Result<PublicKey, TlsError> {
+        match self {
+            Self::Secp256r1(key) => {
+                PublicKey::from_slice(&key.public[..]).map_err(|_| TlsError::EncodeError)
+            }
+            Self::Other(secret) => secret.public_key(),
+        }
+    }
+
+    fn diffie_hellman(self, peer: &[u8]) -> Result<SharedSecret, TlsError> {
+        match self {
+            Self::Secp256r1(key) => {
+                let mut shared = [0; 32];
+                let result = D::ecdh_p256_compute(&key.private, peer, &mut shared)
+                    .map_err(|_| TlsError::InvalidKeyShare)
+                    .and_then(|_| SharedSecret::new(&[&shared[..]]));
+                shared.zeroize();
+                result
+            }
+            Self::Other(secret) => secret.diffie_hellman(peer),
+        }
+    }
+}
+
+/// SHA-256 on the hash engine of the CryptoCell.
+pub struct CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    context: D::Sha256Context,
+}
+
+impl<D> Clone for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    fn clone(&self) -> Self {
+        Self {
+            context: self.context.clone(),
+        }
+    }
+}
+
+impl<D> Default for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    fn default() -> Self {
+        Self {
+            context: D::sha256_init(),
+        }
+    }
+}
+
+impl<D> HashMarker for CryptoCellSha256<D> where D: CryptoCellDriver {}
+
+impl<D> OutputSizeUser for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    type OutputSize = U32;
+}
+
+impl<D> BlockSizeUser for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    type BlockSize = U64;
+}
+
+impl<D> Update for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    fn update(&mut self, data: &[u8]) {
+        D::sha256_update(&mut self.context, data);
+    }
+}
+
+impl<D> FixedOutput for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    fn finalize_into(self, out: &mut Output<Self>) {
+        D::sha256_finish(self.context, out.as_mut());
+    }
+}
+
+impl<D> Reset for CryptoCellSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    fn reset(&mut self) {
+        self.context = D::sha256_init();
+    }
+}
+
+/// AES-128-GCM record protection on the AES engine of the CryptoCell.
+pub struct CryptoCellAes128Gcm<D> {
+    key: [u8; 16],
+    _driver: PhantomData<D>,
+}
+
+impl<D> Drop for CryptoCellAes128Gcm<D> {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl<D> RecordAead for CryptoCellAes128Gcm<D>
+where
+    D: CryptoCellDriver,
+{
+    type KeyLen = U16;
+    type NonceLen = U12;
+    type TagLen = U16;
+
+    fn new(key: &GenericArray<u8, U16>) -> Self {
+        let mut cipher = Self {
+            key: [0; 16],
+            _driver: PhantomData,
+        };
+        cipher.key.copy_from_slice(key);
+        cipher
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, U12>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        let (plaintext, tag) = ciphertext_region(buffer, 16)?;
+        let tag: &mut [u8; 16] = tag.try_into().map_err(|_| Error)?;
+        D::aes_gcm_encrypt(&self.key, nonce.as_ref(), additional_data, plaintext, tag)
+            .map_err(|_| Error)
+    }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, U12>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        let len = buffer.len().checked_sub(16).ok_or(Error)?;
+        let (data, tag) = buffer.as_mut().split_at_mut(len);
+        let tag: &[u8; 16] = (&*tag).try_into().map_err(|_| Error)?;
+        D::aes_gcm_decrypt(&self.key, nonce.as_ref(), additional_data, data, tag)
+            .map_err(|_| Error)?;
+        buffer.truncate(len);
+        Ok(())
+    }
+}
+
+/// TLS_AES_128_GCM_SHA256 with the record protection and all hashing done by the CryptoCell.
+pub struct CryptoCellAes128GcmSha256<D>(PhantomData<D>);
+
+impl<D> TlsCipherSuite for CryptoCellAes128GcmSha256<D>
+where
+    D: CryptoCellDriver,
+{
+    const CODE_POINT: u16 = CipherSuite::TlsAes128GcmSha256 as u16;
+    type Cipher = CryptoCellAes128Gcm<D>;
+    type KeyLen = U16;
+    type IvLen = U12;
+
+    type Hash = CryptoCellSha256<D>;
+    type Hkdf = SimpleHkdf<CryptoCellSha256<D>>;
+    type LabelBufferSize = LabelBuffer<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::CryptoBuffer;
+    use aes_gcm::aead::{AeadInPlace, KeyInit};
+    use aes_gcm::Aes128Gcm;
+    use digest::Digest;
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    /// Stands in for the CryptoCell with the RustCrypto implementations.
+    struct SoftwareCell;
+
+    impl CryptoCellDriver for SoftwareCell {
+        type Sha256Context = Sha256;
+
+        fn sha256_init() -> Sha256 {
+            Sha256::new()
+        }
+
+        fn sha256_update(context: &mut Sha256, data: &[u8]) {
+            Digest::update(context, data);
+        }
+
+        fn sha256_finish(context: Sha256, digest: &mut [u8; 32]) {
+            digest.copy_from_slice(&context.finalize());
+        }
+
+        fn ecdh_p256_generate(
+            private: &mut [u8; 32],
+            public: &mut [u8; 65],
+        ) -> Result<(), TlsError> {
+            let secret = p256::SecretKey::random(&mut OsRng);
+            private.copy_from_slice(&secret.to_be_bytes());
+            public.copy_from_slice(secret.public_key().to_encoded_point(false).as_bytes());
+            Ok(())
+        }
+
+        fn ecdh_p256_compute(
+            private: &[u8; 32],
+            peer: &[u8],
+            shared: &mut [u8; 32],
+        ) -> Result<(), TlsError> {
+            let secret =
+                p256::SecretKey::from_be_bytes(private).map_err(|_| TlsError::CryptoError)?;
+            let peer = p256::PublicKey::from_sec1_bytes(peer).map_err(|_| TlsError::CryptoError)?;
+            let result = p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), peer.as_affine());
+            shared.copy_from_slice(result.raw_secret_bytes());
+            Ok(())
+        }
+
+        fn aes_gcm_encrypt(
+            key: &[u8; 16],
+            nonce: &[u8; 12],
+            additional_data: &[u8],
+            buffer: &mut [u8],
+            tag: &mut [u8; 16],
+        ) -> Result<(), TlsError> {
+            let cipher = Aes128Gcm::new(key.into());
+            let result = cipher
+                .encrypt_in_place_detached(nonce.into(), additional_data, buffer)
+                .map_err(|_| TlsError::CryptoError)?;
+            tag.copy_from_slice(&result);
+            Ok(())
+        }
+
+        fn aes_gcm_decrypt(
+            key: &[u8; 16],
+            nonce: &[u8; 12],
+            additional_data: &[u8],
+            buffer: &mut [u8],
+            tag: &[u8; 16],
+        ) -> Result<(), TlsError> {
+            let cipher = Aes128Gcm::new(key.into());
+            cipher
+                .decrypt_in_place_detached(nonce.into(), additional_data, buffer, tag.into())
+                .map_err(|_| TlsError::CryptoError)
+        }
+    }
+
+    #[test]
+    fn test_sha256() {
+        let mut hash = CryptoCellSha256::<SoftwareCell>::new();
+        Digest::update(&mut hash, b"abc");
+        let snapshot = hash.clone();
+        assert_eq!(Sha256::digest(b"abc"), hash.finalize());
+        assert_eq!(Sha256::digest(b"abc"), snapshot.finalize());
+    }
+
+    #[test]
+    fn test_aes_gcm_interop() {
+        let key = GenericArray::from([7; 16]);
+        let nonce = GenericArray::from([3; 12]);
+
+        let mut data = [0; 64];
+        let mut buf = CryptoBuffer::wrap(&mut data);
+        buf.extend_from_slice(b"ping").unwrap();
+        let cipher = <CryptoCellAes128Gcm<SoftwareCell> as RecordAead>::new(&key);
+        cipher.encrypt_in_place(&nonce, b"aad", &mut buf).unwrap();
+        assert_eq!(20, buf.len());
+
+        let software = <Aes128Gcm as KeyInit>::new(&key);
+        AeadInPlace::decrypt_in_place(&software, &nonce, b"aad", &mut buf).unwrap();
+        assert_eq!(b"ping", buf.as_slice());
+
+        AeadInPlace::encrypt_in_place(&software, &nonce, b"aad", &mut buf).unwrap();
+        buf.as_mut_slice()[0] ^= 1;
+        assert!(cipher.decrypt_in_place(&nonce, b"aad", &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_key_exchange() {
+        let client =
+            CryptoCell::<SoftwareCell>::generate_key(NamedGroup::Secp256r1, &mut OsRng).unwrap();
+        let server = EphemeralSecret::generate(NamedGroup::Secp256r1, &mut OsRng).unwrap();
+
+        let client_public = client.public_key().unwrap();
+        let server_public = server.public_key().unwrap();
+        let client_shared = client.diffie_hellman(&server_public).unwrap();
+        let server_shared = server.diffie_hellman(&client_public).unwrap();
+        assert_eq!(client_shared.as_bytes(), server_shared.as_bytes());
+    }
+}
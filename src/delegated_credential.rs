@@ -0,0 +1,336 @@
+//! RFC 9345.  Delegated Credentials for TLS and DTLS
+//!
+//! A server whose end-entity certificate allows delegation signs the handshake with a short-lived
+//! key, which it sends in the delegated_credential extension of the end-entity CertificateEntry
+//! along with a signature by the key of the certificate. Only ECDSA over P-256 and P-384 is
+//! supported, for both the key of the certificate and the delegated key.
+use heapless::Vec;
+use sha2::{Digest, Sha256, Sha384};
+
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::handshake::certificate_verify::CertificateVerify;
+use crate::parse_buffer::ParseBuffer;
+use crate::raw_public_key::{self, MAX_PUBLIC_KEY_LEN};
+use crate::signature_schemes::SignatureScheme;
+use crate::x509::{self, SubjectPublicKeyInfo, ID_EC_PUBLIC_KEY, SECP256R1, SECP384R1};
+use crate::TlsError;
+
+/// The signature schemes of the CertificateVerify signed with a delegated credential, offered
+/// in the delegated_credential extension.
+pub(crate) const SUPPORTED_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::EcdsaSecp256r1Sha256,
+    SignatureScheme::EcdsaSecp384r1Sha384,
+];
+
+const CONTEXT: &[u8] = b"TLS, server delegated credentials\x00";
+
+/// Section 4.  Delegated Credentials
+/// The maximum validity period is set to 7 days unless an application profile standard specifies
+/// a shorter period.
+const MAX_VALIDITY: u64 = 7 * 24 * 60 * 60;
+
+/// Section 4.  Delegated Credentials
+///     struct {
+///       uint32 valid_time;
+///       SignatureScheme dc_cert_verify_algorithm;
+///       opaque ASN1_subjectPublicKeyInfo<1..2^24-1>;
+///     } Credential;
+///
+///     struct {
+///       Credential cred;
+///       SignatureScheme algorithm;
+///       opaque signature<1..2^16-1>;
+///     } DelegatedCredential;
+pub(crate) struct DelegatedCredential<'a> {
+    /// The encoded Credential, which is signed along with the certificate
+    credential: &'a [u8],
+    valid_time: u32,
+    dc_cert_verify_algorithm: Option<SignatureScheme>,
+    public_key: &'a [u8],
+    algorithm: u16,
+    signature: &'a [u8],
+}
+
+impl<'a> DelegatedCredential<'a> {
+    /// Parses the data of a delegated_credential extension of a certificate entry.
+    pub(crate) fn parse(data: &'a [u8]) -> Result<Self, TlsError> {
+        let mut buf = ParseBuffer::new(data);
+        let valid_time = buf.read_u32()?;
+        let dc_cert_verify_algorithm = SignatureScheme::of(buf.read_u16()?);
+        let public_key_len = buf.read_u24()? as usize;
+        let public_key = buf.slice(public_key_len)?.as_slice();
+        let credential = &data[..4 + 2 + 3 + public_key_len];
+        let algorithm = buf.read_u16()?;
+        let signature_len = buf.read_u16()?;
+        let signature = buf.slice(signature_len as usize)?.as_slice();
+        if public_key.is_empty() || signature.is_empty() || !buf.is_empty() {
+            return Err(TlsError::DecodeError);
+        }
+        Ok(Self {
+            credential,
+            valid_time,
+            dc_cert_verify_algorithm,
+            public_key,
+            algorithm,
+            signature,
+        })
+    }
+
+    /// Validates the credential delegated by the DER encoded end-entity `certificate`, at the
+    /// time `now` when known, and returns the delegated key.
+    ///
+    /// Section 4.1.3.  Validating a Delegated Credential
+    /// If one or more of these checks fail, then the delegated credential is deemed invalid.
+    /// Clients and servers that receive invalid delegated credentials MUST terminate the
+    /// connection with an "illegal_parameter" alert.
+    pub(crate) fn verify(
+        &self,
+        certificate: &[u8],
+        now: Option<u64>,
+    ) -> Result<DelegatedKey, TlsError> {
+        // 1.  Verify that the current time is within the validity interval of the credential.
+        //     This is done by asserting that the current time does not exceed the validity
+        //     time plus the delegation certificate's notBefore value.
+        // 2.  Verify that the delegated credential's remaining validity time is no more than
+        //     the maximum validity period.
+        if let Some(now) = now {
+            let expiry = x509::not_before(certificate)? + u64::from(self.valid_time);
+            if now > expiry || expiry - now > MAX_VALIDITY {
+                warn!("The delegated credential is not valid at this time");
+                return Err(illegal_parameter());
+            }
+        }
+
+        // 3.  Verify that dc_cert_verify_algorithm matches the scheme indicated in the peer's
+        //     CertificateVerify message and that the algorithm is allowed for use with
+        //     delegated credentials.
+        let scheme = self
+            .dc_cert_verify_algorithm
+            .filter(|scheme| SUPPORTED_SCHEMES.contains(scheme))
+            .ok_or_else(|| {
+                warn!("Unsupported signature scheme of the delegated credential");
+                illegal_parameter()
+            })?;
+
+        // 4.  Verify that the end-entity certificate satisfies the conditions in Section 4.2.
+        if !x509::allows_delegation(certificate)? {
+            warn!("The certificate of the server does not allow delegated credentials");
+            return Err(illegal_parameter());
+        }
+
+        // 5.  Use the public key in the peer's end-entity certificate to verify the signature
+        //     of the credential using the algorithm indicated by DelegatedCredential.algorithm.
+        self.verify_signature(
+            &SubjectPublicKeyInfo::from_certificate(certificate)?,
+            certificate,
+        )?;
+
+        Ok(DelegatedKey {
+            scheme,
+            public_key: Vec::from_slice(self.public_key).map_err(|_| illegal_parameter())?,
+        })
+    }
+
+    /// Section 4.  Delegated Credentials
+    /// The signature of the DelegatedCredential is computed over the concatenation of:
+    ///
+    /// 1.  An octet stream that consists of octet 32 (0x20) repeated 64 times.
+    /// 2.  The non-null-terminated context string "TLS, server delegated credentials" for server
+    ///     authentication and "TLS, client delegated credentials" for client authentication.
+    /// 3.  A single octet 0x00, which serves as the separator.
+    /// 4.  The DER-encoded X.509 end-entity certificate used to sign the DelegatedCredential.
+    /// 5.  DelegatedCredential.cred.
+    /// 6.  DelegatedCredential.algorithm.
+    ///
+    /// The message is hashed as it goes, since the certificate does not fit a fixed buffer.
+    fn verify_signature(
+        &self,
+        spki: &SubjectPublicKeyInfo,
+        certificate: &[u8],
+    ) -> Result<(), TlsError> {
+        let parts = [
+            &[0x20; 64][..],
+            CONTEXT,
+            certificate,
+            self.credential,
+            &self.algorithm.to_be_bytes(),
+        ];
+        if spki.algorithm != ID_EC_PUBLIC_KEY {
+            warn!("Only ECDSA certificates may delegate credentials");
+            return Err(illegal_parameter());
+        }
+        let verified = match (SignatureScheme::of(self.algorithm), spki.named_curve) {
+            (Some(SignatureScheme::EcdsaSecp256r1Sha256), Some(curve)) if curve == SECP256R1 => {
+                use p256::ecdsa::{signature::DigestVerifier, Signature, VerifyingKey};
+
+                let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                    .map_err(|_| TlsError::InvalidCertificate)?;
+                let signature =
+                    Signature::from_der(self.signature).map_err(|_| illegal_parameter())?;
+                let mut digest = Sha256::new();
+                parts.iter().for_each(|part| digest.update(part));
+                key.verify_digest(digest, &signature).is_ok()
+            }
+            (Some(SignatureScheme::EcdsaSecp384r1Sha384), Some(curve)) if curve == SECP384R1 => {
+                use p384::ecdsa::{signature::DigestVerifier, Signature, VerifyingKey};
+
+                let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                    .map_err(|_| TlsError::InvalidCertificate)?;
+                let signature =
+                    Signature::from_der(self.signature).map_err(|_| illegal_parameter())?;
+                let mut digest = Sha384::new();
+                parts.iter().for_each(|part| digest.update(part));
+                key.verify_digest(digest, &signature).is_ok()
+            }
+            _ => {
+                warn!("Unsupported signature algorithm of the delegated credential");
+                return Err(illegal_parameter());
+            }
+        };
+        if !verified {
+            warn!("The signature of the delegated credential is invalid");
+            return Err(illegal_parameter());
+        }
+        Ok(())
+    }
+}
+
+/// The key of a valid delegated credential, which signs the CertificateVerify.
+pub(crate) struct DelegatedKey {
+    scheme: SignatureScheme,
+    public_key: Vec<u8, MAX_PUBLIC_KEY_LEN>,
+}
+
+impl DelegatedKey {
+    /// Section 4.1.3.  Validating a Delegated Credential
+    /// 3.  Verify that dc_cert_verify_algorithm matches the scheme indicated in the peer's
+    ///     CertificateVerify message [...]
+    pub(crate) fn verify_signature(
+        &self,
+        message: &[u8],
+        verify: CertificateVerify,
+    ) -> Result<(), TlsError> {
+        if verify.signature_scheme != self.scheme {
+            warn!("The CertificateVerify does not use the scheme of the delegated credential");
+            return Err(illegal_parameter());
+        }
+        raw_public_key::verify_signature(
+            &self.public_key,
+            verify.signature_scheme,
+            message,
+            verify.signature,
+        )
+    }
+}
+
+fn illegal_parameter() -> TlsError {
+    TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+    use p256::ecdsa::{signature::Signer as _, Signature, SigningKey};
+    use std::vec::Vec;
+
+    // Oct 15 04:55:23 2026 GMT
+    const NOT_BEFORE: u64 = 1792040123;
+
+    // SubjectPublicKeyInfo of id-ecPublicKey with prime256v1
+    const SPKI_PREFIX: [u8; 26] = [
+        0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x08,
+        0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+    ];
+
+    /// A delegated credential for the key of `delegated`, signed by the key of the certificate.
+    fn delegated_credential(
+        certificate: &[u8],
+        delegated: &SigningKey,
+        valid_time: u32,
+    ) -> Vec<u8> {
+        let mut public_key = Vec::from(&SPKI_PREFIX[..]);
+        public_key.extend_from_slice(delegated.verifying_key().to_encoded_point(false).as_bytes());
+
+        let mut credential = Vec::from(&valid_time.to_be_bytes()[..]);
+        credential.extend_from_slice(&(SignatureScheme::EcdsaSecp256r1Sha256 as u16).to_be_bytes());
+        credential.extend_from_slice(&(public_key.len() as u32).to_be_bytes()[1..]);
+        credential.extend_from_slice(&public_key);
+        let algorithm = (SignatureScheme::EcdsaSecp256r1Sha256 as u16).to_be_bytes();
+
+        let key =
+            PKey::private_key_from_pem(include_bytes!("../tests/data/delegated/server-key.pem"))
+                .unwrap();
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+        for part in [
+            &[0x20; 64][..],
+            CONTEXT,
+            certificate,
+            &credential,
+            &algorithm,
+        ] {
+            signer.update(part).unwrap();
+        }
+        let signature = signer.sign_to_vec().unwrap();
+
+        let mut data = credential;
+        data.extend_from_slice(&algorithm);
+        data.extend_from_slice(&(signature.len() as u16).to_be_bytes());
+        data.extend_from_slice(&signature);
+        data
+    }
+
+    #[test]
+    fn test_verify() {
+        let certificate =
+            pem_parser::pem_to_der(include_str!("../tests/data/delegated/server-cert.pem"));
+        let delegated = SigningKey::from_bytes(&[0x11; 32]).unwrap();
+        let data = delegated_credential(&certificate, &delegated, 3600);
+        let credential = DelegatedCredential::parse(&data).unwrap();
+        let key = credential
+            .verify(&certificate, Some(NOT_BEFORE + 60))
+            .unwrap();
+
+        let signature: Signature = delegated.sign(b"handshake");
+        let signature = signature.to_der();
+        key.verify_signature(
+            b"handshake",
+            CertificateVerify {
+                signature_scheme: SignatureScheme::EcdsaSecp256r1Sha256,
+                signature: signature.as_bytes(),
+            },
+        )
+        .unwrap();
+        assert!(key
+            .verify_signature(
+                b"handshake",
+                CertificateVerify {
+                    signature_scheme: SignatureScheme::EcdsaSecp384r1Sha384,
+                    signature: signature.as_bytes(),
+                },
+            )
+            .is_err());
+
+        // Expired, and valid for too long
+        assert!(credential
+            .verify(&certificate, Some(NOT_BEFORE + 3601))
+            .is_err());
+        let data = delegated_credential(&certificate, &delegated, 8 * 24 * 3600);
+        let credential = DelegatedCredential::parse(&data).unwrap();
+        assert!(credential.verify(&certificate, Some(NOT_BEFORE)).is_err());
+
+        // The certificate must allow delegation
+        let other = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let data = delegated_credential(&other, &delegated, 3600);
+        let credential = DelegatedCredential::parse(&data).unwrap();
+        assert!(matches!(
+            credential.verify(&other, None),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+    }
+}
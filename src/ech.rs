@@ -0,0 +1,324 @@
+//! draft-ietf-tls-esni.  TLS Encrypted Client Hello
+//!
+//! The ClientHello of the client, the ClientHelloInner, is encrypted with HPKE to the public key
+//! of an ECHConfig of the server, and sent in the encrypted_client_hello extension of the
+//! ClientHelloOuter, which only carries the public name of the config in its server_name
+//! extension.
+use heapless::Vec;
+use rand_core::{CryptoRng, RngCore};
+
+use crate::hpke::{self, SenderContext};
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+
+/// The maximum size of the retry configs kept when the server rejects ECH.
+pub const MAX_RETRY_CONFIGS_LEN: usize = 512;
+
+// Section 4.  ECH Configuration
+const VERSION: u16 = 0xfe0d;
+
+// Section 6.1.1.  Encrypting the ClientHello
+const INFO_LABEL: &[u8] = b"tls ech\0";
+
+/// An ECHConfig supported by the client.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EchConfig<'a> {
+    /// The ECHConfigContents, which are part of the HPKE info.
+    contents: &'a [u8],
+    pub(crate) config_id: u8,
+    public_key: &'a [u8],
+    /// The kdf_id and aead_id of the first supported HpkeSymmetricCipherSuite.
+    pub(crate) cipher_suite: (u16, u16),
+    pub(crate) maximum_name_length: u8,
+    pub(crate) public_name: &'a str,
+}
+
+impl<'a> EchConfig<'a> {
+    /// Section 6.1.  Offering ECH
+    /// The client then parses the ECHConfigList and selects the first supported ECHConfig.
+    ///
+    /// Fails with [`TlsError::Unimplemented`] if the list has no supported config.
+    pub(crate) fn select(config_list: &'a [u8]) -> Result<Self, TlsError> {
+        let mut selected = None;
+        for config in parse_list(config_list)? {
+            if selected.is_none() {
+                selected = config?;
+            } else {
+                config?;
+            }
+        }
+        selected.ok_or_else(|| {
+            warn!("No supported ECHConfig");
+            TlsError::Unimplemented
+        })
+    }
+
+    /// Section 4.  ECH Configuration
+    ///     struct {
+    ///         uint16 version;
+    ///         uint16 length;
+    ///         select (ECHConfig.version) {
+    ///           case 0xfe0d: ECHConfigContents contents;
+    ///         }
+    ///     } ECHConfig;
+    ///
+    /// Returns `None` for a config which is not supported.
+    fn parse(buf: &mut ParseBuffer<'a>) -> Result<Option<Self>, TlsError> {
+        let version = buf.read_u16()?;
+        let len = buf.read_u16()?;
+        let mut contents = buf.slice(len as usize)?;
+        if version != VERSION {
+            return Ok(None);
+        }
+
+        //     struct {
+        //         uint8 config_id;
+        //         HpkeKemId kem_id;
+        //         HpkePublicKey public_key;
+        //         HpkeSymmetricCipherSuite cipher_suites<4..2^16-4>;
+        //     } HpkeKeyConfig;
+        let config_id = contents.read_u8()?;
+        let kem_id = contents.read_u16()?;
+        let public_key_len = contents.read_u16()?;
+        let public_key = contents.slice(public_key_len as usize)?.as_slice();
+        let cipher_suites_len = contents.read_u16()?;
+        if cipher_suites_len < 4 || cipher_suites_len % 4 != 0 {
+            return Err(TlsError::DecodeError);
+        }
+        let mut cipher_suites = contents.slice(cipher_suites_len as usize)?;
+        let mut cipher_suite = None;
+        while !cipher_suites.is_empty() {
+            let kdf = cipher_suites.read_u16()?;
+            let aead = cipher_suites.read_u16()?;
+            if kdf == hpke::KDF_HKDF_SHA256 && hpke::supports_aead(aead) {
+                cipher_suite.get_or_insert((kdf, aead));
+            }
+        }
+
+        //     struct {
+        //         HpkeKeyConfig key_config;
+        //         uint8 maximum_name_length;
+        //         opaque public_name<1..255>;
+        //         ECHConfigExtension extensions<0..2^16-1>;
+        //     } ECHConfigContents;
+        let maximum_name_length = contents.read_u8()?;
+        let public_name_len = contents.read_u8()?;
+        if public_name_len == 0 {
+            return Err(TlsError::DecodeError);
+        }
+        let public_name =
+            core::str::from_utf8(contents.slice(public_name_len as usize)?.as_slice())
+                .map_err(|_| TlsError::DecodeError)?;
+        let extensions_len = contents.read_u16()?;
+        let mut extensions = contents.slice(extensions_len as usize)?;
+        if !contents.is_empty() {
+            return Err(TlsError::DecodeError);
+        }
+
+        // Section 4.2.  Configuration Extensions
+        // ECH configuration extensions with the high order bit set are mandatory, and clients
+        // MUST ignore any ECHConfig which has a mandatory extension they do not support.
+        let mut mandatory = false;
+        while !extensions.is_empty() {
+            let extension_type = extensions.read_u16()?;
+            let len = extensions.read_u16()?;
+            extensions.slice(len as usize)?;
+            mandatory |= extension_type & 0x8000 != 0;
+        }
+
+        let supported = kem_id == hpke::KEM_X25519_HKDF_SHA256
+            && public_key.len() == hpke::ENC_LEN
+            && !mandatory;
+        Ok(match cipher_suite {
+            Some(cipher_suite) if supported => Some(Self {
+                contents: contents.as_slice(),
+                config_id,
+                public_key,
+                cipher_suite,
+                maximum_name_length,
+                public_name,
+            }),
+            _ => None,
+        })
+    }
+}
+
+/// Section 4.  ECH Configuration
+///     ECHConfig ECHConfigList<4..2^16-1>;
+///
+/// The configs of the list, `None` for those which are not supported.
+fn parse_list(
+    config_list: &[u8],
+) -> Result<impl Iterator<Item = Result<Option<EchConfig<'_>>, TlsError>>, TlsError> {
+    let mut buf = ParseBuffer::new(config_list);
+    let len = buf.read_u16()?;
+    let mut list = buf.slice(len as usize)?;
+    if len < 4 || !buf.is_empty() {
+        return Err(TlsError::DecodeError);
+    }
+    Ok(core::iter::from_fn(move || {
+        (!list.is_empty()).then(|| EchConfig::parse(&mut list))
+    }))
+}
+
+/// Whether the server accepted the ECH offered in the ClientHello.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum EchStatus {
+    Offered,
+    Accepted,
+    Rejected,
+}
+
+/// The Encrypted Client Hello offered in the ClientHello, kept by the handshake.
+pub(crate) struct EchState {
+    /// The random of the ClientHelloInner, while the ClientHelloOuter has its own.
+    pub(crate) inner_random: [u8; 32],
+    pub(crate) config_id: u8,
+    pub(crate) cipher_suite: (u16, u16),
+    pub(crate) maximum_name_length: u8,
+    /// The encapsulated key sent in the extension of the ClientHelloOuter.
+    pub(crate) enc: [u8; hpke::ENC_LEN],
+    context: SenderContext,
+    pub(crate) status: EchStatus,
+    /// The ECHConfigList sent by the server in EncryptedExtensions when it rejects ECH.
+    pub(crate) retry_configs: Vec<u8, MAX_RETRY_CONFIGS_LEN>,
+}
+
+impl EchState {
+    /// Section 6.1.1.  Encrypting the ClientHello
+    ///     pkR = DeserializePublicKey(ECHConfig.contents.public_key)
+    ///     enc, context = SetupBaseS(pkR,
+    ///                               "tls ech" || 0x00 || ECHConfig)
+    pub(crate) fn new<RNG>(config: &EchConfig<'_>, rng: &mut RNG) -> Result<Self, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        let mut inner_random = [0; 32];
+        rng.try_fill_bytes(&mut inner_random)
+            .map_err(|_| TlsError::RngError)?;
+
+        let len = u16::try_from(config.contents.len()).map_err(|_| TlsError::EncodeError)?;
+        let (version, len) = (VERSION.to_be_bytes(), len.to_be_bytes());
+        let info = [INFO_LABEL, &version[..], &len[..], config.contents];
+        let (enc, context) =
+            hpke::setup_base_sender(config.public_key, &info, config.cipher_suite.1, rng)?;
+
+        Ok(Self {
+            inner_random,
+            config_id: config.config_id,
+            cipher_suite: config.cipher_suite,
+            maximum_name_length: config.maximum_name_length,
+            enc,
+            context,
+            status: EchStatus::Offered,
+            retry_configs: Vec::new(),
+        })
+    }
+
+    /// Section 6.1.3.  Recommended Padding Scheme
+    /// The padding of the EncodedClientHelloInner of `len` bytes, which hides the length of the
+    /// name of the server up to the maximum_name_length of the config, and the length of the
+    /// rest up to a multiple of 32 bytes.
+    pub(crate) fn padding(&self, server_name: Option<&str>, len: usize) -> usize {
+        let name_padding = match server_name {
+            Some(name) => (self.maximum_name_length as usize).saturating_sub(name.len()),
+            // The size of a server_name extension with a name of maximum_name_length bytes
+            None => self.maximum_name_length as usize + 9,
+        };
+        let len = len + name_padding;
+        name_padding + 31 - ((len + 31) % 32)
+    }
+
+    /// The length of the payload of the extension of the ClientHelloOuter, for an
+    /// EncodedClientHelloInner of `len` bytes with its padding.
+    pub(crate) fn payload_len(len: usize) -> usize {
+        len + hpke::TAG_LEN
+    }
+
+    /// Section 6.1.1.  Encrypting the ClientHello
+    ///     payload = context.Seal(ClientHelloOuterAAD,
+    ///                            EncodedClientHelloInner)
+    ///
+    /// Encrypts the EncodedClientHelloInner in place, with the ClientHelloOuter with a zeroed
+    /// payload as `aad`, and returns the authentication tag.
+    pub(crate) fn seal(
+        &self,
+        aad: &[u8],
+        inner: &mut [u8],
+    ) -> Result<[u8; hpke::TAG_LEN], TlsError> {
+        self.context.seal(0, aad, inner)
+    }
+
+    /// Keeps the retry configs of a server which rejected ECH, validated as an ECHConfigList.
+    pub(crate) fn set_retry_configs(&mut self, config_list: &[u8]) -> Result<(), TlsError> {
+        for config in parse_list(config_list)? {
+            config?;
+        }
+        self.retry_configs = Vec::from_slice(config_list).map_err(|_| {
+            warn!("ECH retry configs too large");
+            TlsError::InsufficientSpace
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ECHConfigList with a config of an unsupported version, a config with a mandatory
+    // extension and a supported config, with AES-256-GCM as its only supported AEAD.
+    const CONFIG_LIST: &[u8] = &[
+        0x00, 0x86, // list
+        0xfe, 0x0c, 0x00, 0x02, 0x00, 0x00, // draft version
+        0xfe, 0x0d, 0x00, 0x37, 0x01, 0x00, 0x20, 0x00, 0x20, // mandatory extension
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01,
+        0x01, 0x01, 0x00, 0x04, 0x00, 0x01, 0x00, 0x01, 0x00, 0x04, b'a', b'.', b'e', b'x', 0x00,
+        0x04, 0x80, 0x01, 0x00, 0x00, //
+        0xfe, 0x0d, 0x00, 0x3e, 0x02, 0x00, 0x20, 0x00, 0x20, // supported
+        0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+        0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02, 0x02,
+        0x02, 0x02, 0x00, 0x08, 0x00, 0x01, 0x00, 0x03, 0x00, 0x01, 0x00, 0x02, 0x20, 0x0b, b'p',
+        b'u', b'b', b'l', b'i', b'c', b'.', b'n', b'a', b'm', b'e', 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_select() {
+        let config = EchConfig::select(CONFIG_LIST).unwrap();
+        assert_eq!(2, config.config_id);
+        assert_eq!(&[0x02; 32], config.public_key);
+        assert_eq!(
+            (hpke::KDF_HKDF_SHA256, hpke::AEAD_AES_256_GCM),
+            config.cipher_suite
+        );
+        assert_eq!(32, config.maximum_name_length);
+        assert_eq!("public.name", config.public_name);
+        assert_eq!(&CONFIG_LIST[CONFIG_LIST.len() - 0x3e..], config.contents);
+    }
+
+    #[test]
+    fn test_select_unsupported() {
+        assert!(EchConfig::select(&CONFIG_LIST[..0x43]).is_err());
+        let mut list = CONFIG_LIST[..0x43].to_vec();
+        list[1] = 0x41;
+        assert!(matches!(
+            EchConfig::select(&list),
+            Err(TlsError::Unimplemented)
+        ));
+    }
+
+    #[test]
+    fn test_padding() {
+        let config = EchConfig::select(CONFIG_LIST).unwrap();
+        let mut rng = rand::rngs::OsRng;
+        let state = EchState::new(&config, &mut rng).unwrap();
+        // 32 - 11 bytes for the name, then up to a multiple of 32
+        assert_eq!(21 + 7, state.padding(Some("example.com"), 100));
+        assert_eq!(
+            0,
+            state.padding(Some("a.longer.name.than.the.maximum.length"), 128)
+        );
+        assert_eq!(41 + 19, state.padding(None, 100));
+    }
+}
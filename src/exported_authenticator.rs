@@ -0,0 +1,444 @@
+//! RFC 9261.  Exported Authenticators in TLS
+//!
+//! Once the handshake is complete, either side may prove the possession of a certificate at the
+//! application layer, for example to attest the identity of a device to a service after the
+//! connection is established. The authenticator requests and the authenticators are opaque
+//! messages carried by the application protocol, bound to the connection with the keying material
+//! of the TLS exporter. Only ECDSA signatures over P-256 and P-384 are supported.
+use heapless::Vec;
+use sha2::Digest;
+use zeroize::Zeroizing;
+
+use crate::buffer::CryptoBuffer;
+use crate::config::{ClientIdentity, TlsCipherSuite};
+use crate::extensions::ExtensionType;
+use crate::handshake::certificate::{CertificateEntryRef, CertificateRef};
+use crate::handshake::certificate_request::CertificateRequestRef;
+use crate::handshake::certificate_verify::CertificateVerify;
+use crate::handshake::HandshakeType;
+use crate::kdf::TlsHkdf;
+use crate::key_schedule::{HashArray, KeySchedule};
+use crate::parse_buffer::ParseBuffer;
+use crate::raw_public_key::verify_spki_signature;
+use crate::signature_schemes::SignatureScheme;
+use crate::signer::MAX_SIGNATURE_LEN;
+use crate::x509::SubjectPublicKeyInfo;
+use crate::TlsError;
+
+// Section 5.1.  Authenticator Keys
+const CLIENT_HANDSHAKE_CONTEXT: &[u8] = b"EXPORTER-client authenticator handshake context";
+const SERVER_HANDSHAKE_CONTEXT: &[u8] = b"EXPORTER-server authenticator handshake context";
+const CLIENT_FINISHED_KEY: &[u8] = b"EXPORTER-client authenticator finished key";
+const SERVER_FINISHED_KEY: &[u8] = b"EXPORTER-server authenticator finished key";
+
+/// Section 5.2.2.  CertificateVerify
+/// The context string for the signature is "Exported Authenticator".
+const CONTEXT: &[u8] = b"Exported Authenticator\x00";
+
+type Hkdf<CipherSuite> = <CipherSuite as TlsCipherSuite>::Hkdf;
+
+/// The DER encoded certificates of an authenticator, starting with the end-entity certificate.
+pub type CertificateChain<'a> = Vec<&'a [u8], 16>;
+
+/// Section 4.  Authenticator Request
+///     struct {
+///         opaque certificate_request_context<0..2^8-1>;
+///         Extension extensions<2..2^16-1>;
+///     } ClientCertificateRequest;
+///
+/// Encodes the request of an authenticator of the server into `buf`, returning its length. The
+/// `context` identifies the request and must be unique within the connection, and the server
+/// must sign with one of `signature_schemes`.
+pub fn encode_authenticator_request(
+    context: &[u8],
+    signature_schemes: &[SignatureScheme],
+    buf: &mut [u8],
+) -> Result<usize, TlsError> {
+    if context.len() > 255 || signature_schemes.is_empty() {
+        return Err(TlsError::EncodeError);
+    }
+    let mut buf = CryptoBuffer::wrap(buf);
+    buf.push(HandshakeType::ClientCertificateRequest as u8)?;
+    buf.with_u24_length(|buf| {
+        buf.with_u8_length(|buf| buf.extend_from_slice(context))?;
+        buf.with_u16_length(|buf| {
+            buf.push_u16(ExtensionType::SignatureAlgorithms as u16)?;
+            buf.with_u16_length(|buf| {
+                buf.with_u16_length(|buf| {
+                    for scheme in signature_schemes {
+                        buf.push_u16(*scheme as u16)?;
+                    }
+                    Ok(())
+                })
+            })
+        })
+    })?;
+    Ok(buf.len())
+}
+
+/// Creates the authenticator of the client answering `request`, a CertificateRequest of the
+/// server. The authenticator is empty without an identity signing with a scheme of the request.
+pub(crate) fn create_authenticator<CipherSuite>(
+    key_schedule: &KeySchedule<CipherSuite>,
+    request: &[u8],
+    identity: Option<&ClientIdentity<'_>>,
+    buf: &mut [u8],
+) -> Result<usize, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let request = Request::parse(request, HandshakeType::CertificateRequest)?;
+    AuthenticatorSecrets::export(key_schedule, CLIENT_HANDSHAKE_CONTEXT, CLIENT_FINISHED_KEY)?
+        .create(&request, identity, buf)
+}
+
+/// Validates the authenticator of the server answering `request`, a ClientCertificateRequest
+/// encoded with [`encode_authenticator_request`].
+pub(crate) fn validate_authenticator<'b, CipherSuite>(
+    key_schedule: &KeySchedule<CipherSuite>,
+    request: &[u8],
+    authenticator: &'b [u8],
+) -> Result<Option<CertificateChain<'b>>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let request = Request::parse(request, HandshakeType::ClientCertificateRequest)?;
+    AuthenticatorSecrets::export(key_schedule, SERVER_HANDSHAKE_CONTEXT, SERVER_FINISHED_KEY)?
+        .validate(&request, authenticator)
+}
+
+/// An authenticator request, whose encoding is part of the authenticator transcript.
+struct Request<'a> {
+    message: &'a [u8],
+    request: CertificateRequestRef<'a>,
+}
+
+impl<'a> Request<'a> {
+    fn parse(message: &'a [u8], expected: HandshakeType) -> Result<Self, TlsError> {
+        let mut buf = ParseBuffer::new(message);
+        let Message { msg_type, body, .. } = Message::read(&mut buf)?;
+        if msg_type != expected as u8 || !buf.is_empty() {
+            return Err(TlsError::InvalidCertificateRequest);
+        }
+        let request = CertificateRequestRef::parse(&mut ParseBuffer::new(body))?;
+
+        // Section 4.  Authenticator Request
+        // The "signature_algorithms" extension MUST be specified.
+        if request.signature_algorithms.is_none() {
+            return Err(TlsError::InvalidCertificateRequest);
+        }
+        Ok(Self { message, request })
+    }
+}
+
+/// A handshake message of an authenticator or of an authenticator request.
+struct Message<'a> {
+    msg_type: u8,
+    /// The whole message, as hashed in the transcript.
+    encoded: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn read(buf: &mut ParseBuffer<'a>) -> Result<Self, TlsError> {
+        // The unread part of the buffer, which starts with this message
+        let encoded = &buf.as_slice()[buf.as_slice().len() - buf.remaining()..];
+        let msg_type = buf.read_u8()?;
+        let len = buf.read_u24()? as usize;
+        let body = buf.slice(len)?.as_slice();
+        Ok(Self {
+            msg_type,
+            encoded: &encoded[..4 + len],
+            body,
+        })
+    }
+}
+
+/// Section 5.1.  Authenticator Keys
+/// Each authenticator is computed using a Handshake Context and Finished MAC Key derived from the
+/// TLS connection.
+struct AuthenticatorSecrets<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    handshake_context: HashArray<CipherSuite>,
+    finished_key: Zeroizing<HashArray<CipherSuite>>,
+}
+
+impl<CipherSuite> AuthenticatorSecrets<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn export(
+        key_schedule: &KeySchedule<CipherSuite>,
+        handshake_context_label: &[u8],
+        finished_key_label: &[u8],
+    ) -> Result<Self, TlsError> {
+        let mut handshake_context = HashArray::<CipherSuite>::default();
+        key_schedule.export_keying_material(
+            handshake_context_label,
+            &[],
+            &mut handshake_context,
+        )?;
+        let mut finished_key = Zeroizing::new(HashArray::<CipherSuite>::default());
+        key_schedule.export_keying_material(finished_key_label, &[], &mut finished_key)?;
+        Ok(Self {
+            handshake_context,
+            finished_key,
+        })
+    }
+
+    fn transcript(&self, request: &Request<'_>) -> CipherSuite::Hash {
+        let mut transcript = <CipherSuite::Hash as Digest>::new();
+        transcript.update(&self.handshake_context);
+        transcript.update(request.message);
+        transcript
+    }
+
+    /// Section 5.  Authenticator
+    /// The authenticator is the concatenation of the Certificate, CertificateVerify and Finished
+    /// handshake messages, or of the Finished message alone for an empty authenticator.
+    fn create(
+        &self,
+        request: &Request<'_>,
+        identity: Option<&ClientIdentity<'_>>,
+        buf: &mut [u8],
+    ) -> Result<usize, TlsError> {
+        let mut transcript = self.transcript(request);
+        let mut out = CryptoBuffer::wrap(buf);
+
+        let identity = identity.filter(|identity| {
+            !identity.cert_chain.is_empty()
+                && request
+                    .request
+                    .accepts_signature_scheme(identity.key.signature_scheme())
+        });
+        if let Some(identity) = identity {
+            let mut certificate = CertificateRef::with_context(request.request.request_context);
+            for cert in identity.cert_chain {
+                certificate.add(cert.into())?;
+            }
+            let start = out.len();
+            out.push(HandshakeType::Certificate as u8)?;
+            out.with_u24_length(|buf| certificate.encode(buf))?;
+            transcript.update(&out.as_slice()[start..]);
+
+            let mut signature = [0; MAX_SIGNATURE_LEN];
+            let len = identity
+                .key
+                .sign(&signed_message(&transcript)?, &mut signature)?;
+            let verify = CertificateVerify {
+                signature_scheme: identity.key.signature_scheme(),
+                signature: &signature[..len],
+            };
+            let start = out.len();
+            out.push(HandshakeType::CertificateVerify as u8)?;
+            out.with_u24_length(|buf| verify.encode(buf))?;
+            transcript.update(&out.as_slice()[start..]);
+        }
+
+        // Section 5.2.3.  Finished
+        // The HMAC with the Finished MAC Key of the hash of the Handshake Context, the
+        // authenticator request and the Certificate and CertificateVerify, if any.
+        let verify_data = Hkdf::<CipherSuite>::hmac(&self.finished_key, &transcript.finalize())?;
+        out.push(HandshakeType::Finished as u8)?;
+        out.with_u24_length(|buf| buf.extend_from_slice(&verify_data))?;
+        Ok(out.len())
+    }
+
+    /// Validates the signature and the Finished of an authenticator, returning its certificate
+    /// chain, or `None` for an empty authenticator. The chain itself is not validated.
+    fn validate<'b>(
+        &self,
+        request: &Request<'_>,
+        authenticator: &'b [u8],
+    ) -> Result<Option<CertificateChain<'b>>, TlsError> {
+        let mut transcript = self.transcript(request);
+        let mut buf = ParseBuffer::new(authenticator);
+        let mut message = Message::read(&mut buf)?;
+
+        let mut chain = None;
+        if message.msg_type == HandshakeType::Certificate as u8 {
+            let certificate = CertificateRef::parse(&mut ParseBuffer::new(message.body))?;
+            // Section 5.2.1.  Certificate
+            // The certificate_request_context echoes the one of the authenticator request.
+            if certificate.request_context != request.request.request_context {
+                warn!("The authenticator does not answer the request");
+                return Err(TlsError::InvalidCertificate);
+            }
+            let Some(CertificateEntryRef::X509(end_entity)) = certificate.entries.first() else {
+                return Err(TlsError::InvalidCertificate);
+            };
+            transcript.update(message.encoded);
+
+            let verify_message = Message::read(&mut buf)?;
+            if verify_message.msg_type != HandshakeType::CertificateVerify as u8 {
+                return Err(TlsError::InvalidHandshake);
+            }
+            let verify = CertificateVerify::parse(&mut ParseBuffer::new(verify_message.body))?;
+            // Section 5.2.2.  CertificateVerify
+            // The signature scheme is one of the signature_algorithms of the request.
+            if !request
+                .request
+                .accepts_signature_scheme(verify.signature_scheme)
+            {
+                return Err(TlsError::InvalidSignatureScheme);
+            }
+            verify_spki_signature(
+                &SubjectPublicKeyInfo::from_certificate(end_entity)?,
+                verify.signature_scheme,
+                &signed_message(&transcript)?,
+                verify.signature,
+            )?;
+            transcript.update(verify_message.encoded);
+
+            chain = Some(
+                certificate
+                    .entries
+                    .iter()
+                    .map(|entry| match *entry {
+                        CertificateEntryRef::X509(data)
+                        | CertificateEntryRef::RawPublicKey(data) => data,
+                    })
+                    .collect(),
+            );
+            message = Message::read(&mut buf)?;
+        }
+
+        if message.msg_type != HandshakeType::Finished as u8 || !buf.is_empty() {
+            return Err(TlsError::InvalidHandshake);
+        }
+        if !Hkdf::<CipherSuite>::verify_hmac(
+            &self.finished_key,
+            &transcript.finalize(),
+            message.body,
+        )? {
+            warn!("The Finished of the authenticator is invalid");
+            return Err(TlsError::InvalidSignature);
+        }
+        Ok(chain)
+    }
+}
+
+/// Section 5.2.2.  CertificateVerify
+/// The hash of the Handshake Context, the authenticator request and the Certificate is signed
+/// with the padding and the context string of the TLS 1.3 CertificateVerify.
+fn signed_message<Hash: Digest + Clone>(
+    transcript: &Hash,
+) -> Result<Vec<u8, { 64 + 23 + 64 }>, TlsError> {
+    let mut message = Vec::new();
+    message
+        .resize(64, 0x20)
+        .map_err(|_| TlsError::EncodeError)?;
+    message
+        .extend_from_slice(CONTEXT)
+        .map_err(|_| TlsError::EncodeError)?;
+    message
+        .extend_from_slice(&transcript.clone().finalize())
+        .map_err(|_| TlsError::EncodeError)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::config::{Aes128GcmSha256, Certificate};
+    use crate::signer::PrivateKey;
+    use openssl::pkey::PKey;
+    use std::vec::Vec;
+
+    fn secrets(seed: u8) -> AuthenticatorSecrets<Aes128GcmSha256> {
+        AuthenticatorSecrets {
+            handshake_context: [seed; 32].into(),
+            finished_key: Zeroizing::new([seed + 1; 32].into()),
+        }
+    }
+
+    /// The request of an authenticator of the client, the same as the request of an
+    /// authenticator of the server apart from the message type.
+    fn certificate_request(schemes: &[SignatureScheme]) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let len = encode_authenticator_request(&[0xaa, 0xbb], schemes, &mut buf).unwrap();
+        let mut request = Vec::from(&buf[..len]);
+        request[0] = HandshakeType::CertificateRequest as u8;
+        request
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let mut buf = [0; 64];
+        let len = encode_authenticator_request(
+            &[0xaa],
+            &[SignatureScheme::EcdsaSecp256r1Sha256],
+            &mut buf,
+        )
+        .unwrap();
+        let request = Request::parse(&buf[..len], HandshakeType::ClientCertificateRequest).unwrap();
+        assert_eq!(&[0xaa], request.request.request_context);
+        assert!(request
+            .request
+            .accepts_signature_scheme(SignatureScheme::EcdsaSecp256r1Sha256));
+
+        // The request of the other side is rejected
+        assert!(matches!(
+            Request::parse(&buf[..len], HandshakeType::CertificateRequest),
+            Err(TlsError::InvalidCertificateRequest)
+        ));
+    }
+
+    #[test]
+    fn test_create_and_validate() {
+        let cert = openssl::x509::X509::from_pem(include_bytes!("../tests/data/client-cert.pem"))
+            .unwrap()
+            .to_der()
+            .unwrap();
+        let key = PKey::private_key_from_pem(include_bytes!("../tests/data/client-key.pem"))
+            .unwrap()
+            .ec_key()
+            .unwrap()
+            .private_key()
+            .to_vec_padded(32)
+            .unwrap();
+        let chain = [Certificate::X509(&cert)];
+        let identity = ClientIdentity::new(&chain, PrivateKey::EcdsaSecp256r1(&key));
+
+        let message = certificate_request(&[SignatureScheme::EcdsaSecp256r1Sha256]);
+        let request = Request::parse(&message, HandshakeType::CertificateRequest).unwrap();
+        let mut authenticator = [0; 2048];
+        let len = secrets(1)
+            .create(&request, Some(&identity), &mut authenticator)
+            .unwrap();
+        let authenticator = &authenticator[..len];
+
+        let chain = secrets(1).validate(&request, authenticator).unwrap();
+        assert_eq!(&[&cert[..]], chain.unwrap().as_slice());
+
+        // The authenticator is bound to the connection and to the request
+        assert!(secrets(2).validate(&request, authenticator).is_err());
+        let message = certificate_request(&[
+            SignatureScheme::EcdsaSecp384r1Sha384,
+            SignatureScheme::EcdsaSecp256r1Sha256,
+        ]);
+        let other = Request::parse(&message, HandshakeType::CertificateRequest).unwrap();
+        assert!(secrets(1).validate(&other, authenticator).is_err());
+    }
+
+    #[test]
+    fn test_empty_authenticator() {
+        let message = certificate_request(&[SignatureScheme::EcdsaSecp384r1Sha384]);
+        let request = Request::parse(&message, HandshakeType::CertificateRequest).unwrap();
+        let identity = ClientIdentity::new(&[], PrivateKey::EcdsaSecp256r1(&[1; 32]));
+
+        let mut authenticator = [0; 64];
+        let len = secrets(1)
+            .create(&request, Some(&identity), &mut authenticator)
+            .unwrap();
+        assert_eq!(4 + 32, len);
+        assert!(secrets(1)
+            .validate(&request, &authenticator[..len])
+            .unwrap()
+            .is_none());
+    }
+}
@@ -1,8 +1,13 @@
-use crate::extensions::common::KeyShare;
+use crate::certificate_types::CertificateType;
+use crate::config::MAX_KEY_SHARES;
+use crate::extensions::common::KeyShareEntry;
 use crate::extensions::ExtensionType;
+use crate::grease::Grease;
+use crate::handshake::compressed_certificate::CertificateCompressionAlgorithm;
 use crate::signature_schemes::SignatureScheme;
 
 use crate::buffer::*;
+use crate::heartbeat::HeartbeatMode;
 use crate::max_fragment_length::MaxFragmentLength;
 use crate::named_groups::NamedGroup;
 use crate::supported_versions::ProtocolVersions;
@@ -13,6 +18,9 @@ pub enum ClientExtension<'a> {
     ServerName {
         server_name: &'a str,
     },
+    ApplicationLayerProtocolNegotiation {
+        protocols: &'a [&'a [u8]],
+    },
     SupportedVersions {
         versions: ProtocolVersions,
     },
@@ -21,10 +29,16 @@ pub enum ClientExtension<'a> {
     },
     SupportedGroups {
         supported_groups: Vec<NamedGroup, 16>,
+        grease: Option<Grease>,
+    },
+    KeyShare {
+        client_shares: Vec<KeyShareEntry<'a>, MAX_KEY_SHARES>,
+        grease: Option<Grease>,
     },
-    KeyShare(KeyShare<'a>),
     PreSharedKey {
         identities: Vec<&'a [u8], 4>,
+        /// Sent for every identity, 0 for external PSKs.
+        obfuscated_ticket_age: u32,
         hash_size: usize,
     },
     PskKeyExchangeModes {
@@ -34,12 +48,53 @@ pub enum ClientExtension<'a> {
         supported_signature_algorithms: Vec<SignatureScheme, 16>,
     },
     MaxFragmentLength(MaxFragmentLength),
+    Heartbeat(HeartbeatMode),
+    StatusRequest,
+    SignedCertificateTimestamp,
+    ClientCertificateType(&'a [CertificateType]),
+    ServerCertificateType(&'a [CertificateType]),
+    CompressCertificate(&'a [CertificateCompressionAlgorithm]),
+    /// The subjects of the trusted CA and root certificates, which are DER encoded.
+    CertificateAuthorities {
+        ca: Option<&'a [u8]>,
+        roots: &'a [&'a [u8]],
+    },
+    DelegatedCredential(&'a [SignatureScheme]),
+    TicketRequest {
+        new_session_count: u8,
+        resumption_count: u8,
+    },
+    EarlyData,
+    Cookie(&'a [u8]),
+    Padding(usize),
+    /// The encrypted_client_hello extension of the ClientHelloOuter, with a zeroed payload which
+    /// is encrypted once the ClientHello is encoded.
+    #[cfg(feature = "ech")]
+    EncryptedClientHelloOuter {
+        /// The kdf_id and aead_id of the HpkeSymmetricCipherSuite.
+        cipher_suite: (u16, u16),
+        config_id: u8,
+        enc: &'a [u8],
+        payload_len: usize,
+    },
+    /// The encrypted_client_hello extension of the ClientHelloInner.
+    #[cfg(feature = "ech")]
+    EncryptedClientHelloInner,
+    #[cfg(feature = "tls12")]
+    EcPointFormats,
+    #[cfg(feature = "tls12")]
+    ExtendedMasterSecret,
 }
 
-#[derive(Clone, Copy)]
+/// Section 4.2.9.  Pre-Shared Key Exchange Modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum PskKeyExchangeMode {
+    /// PSK-only key establishment, without the cost of an (EC)DHE key exchange but also without
+    /// forward secrecy.
     PskKe = 0,
+    /// PSK with (EC)DHE key establishment.
     PskDheKe = 1,
 }
 
@@ -47,6 +102,9 @@ impl ClientExtension<'_> {
     pub fn extension_type(&self) -> ExtensionType {
         match self {
             ClientExtension::ServerName { .. } => ExtensionType::ServerName,
+            ClientExtension::ApplicationLayerProtocolNegotiation { .. } => {
+                ExtensionType::ApplicationLayerProtocolNegotiation
+            }
             ClientExtension::SupportedVersions { .. } => ExtensionType::SupportedVersions,
             ClientExtension::SignatureAlgorithms { .. } => ExtensionType::SignatureAlgorithms,
             ClientExtension::KeyShare { .. } => ExtensionType::KeyShare,
@@ -57,6 +115,27 @@ impl ClientExtension<'_> {
             ClientExtension::PskKeyExchangeModes { .. } => ExtensionType::PskKeyExchangeModes,
             ClientExtension::PreSharedKey { .. } => ExtensionType::PreSharedKey,
             ClientExtension::MaxFragmentLength(_) => ExtensionType::MaxFragmentLength,
+            ClientExtension::Heartbeat(_) => ExtensionType::Heartbeat,
+            ClientExtension::StatusRequest => ExtensionType::StatusRequest,
+            ClientExtension::SignedCertificateTimestamp => {
+                ExtensionType::SignedCertificateTimestamp
+            }
+            ClientExtension::ClientCertificateType(_) => ExtensionType::ClientCertificateType,
+            ClientExtension::ServerCertificateType(_) => ExtensionType::ServerCertificateType,
+            ClientExtension::CompressCertificate(_) => ExtensionType::CompressCertificate,
+            ClientExtension::CertificateAuthorities { .. } => ExtensionType::CertificateAuthorities,
+            ClientExtension::DelegatedCredential(_) => ExtensionType::DelegatedCredential,
+            ClientExtension::TicketRequest { .. } => ExtensionType::TicketRequest,
+            ClientExtension::EarlyData => ExtensionType::EarlyData,
+            ClientExtension::Cookie(_) => ExtensionType::Cookie,
+            ClientExtension::Padding(_) => ExtensionType::Padding,
+            #[cfg(feature = "ech")]
+            ClientExtension::EncryptedClientHelloOuter { .. }
+            | ClientExtension::EncryptedClientHelloInner => ExtensionType::EncryptedClientHello,
+            #[cfg(feature = "tls12")]
+            ClientExtension::EcPointFormats => ExtensionType::EcPointFormats,
+            #[cfg(feature = "tls12")]
+            ClientExtension::ExtendedMasterSecret => ExtensionType::ExtendedMasterSecret,
         }
     }
 
@@ -75,6 +154,20 @@ impl ClientExtension<'_> {
                     buf.with_u16_length(|buf| buf.extend_from_slice(server_name.as_bytes()))
                         .map_err(|_| TlsError::EncodeError)
                 }),
+                // RFC 7301, Section 3.1.  The Application-Layer Protocol Negotiation Extension
+                // opaque ProtocolName<1..2^8-1>;
+                //
+                // struct {
+                //     ProtocolName protocol_name_list<2..2^16-1>
+                // } ProtocolNameList;
+                ClientExtension::ApplicationLayerProtocolNegotiation { protocols } => buf
+                    .with_u16_length(|buf| {
+                        for protocol in protocols.iter() {
+                            buf.with_u8_length(|buf| buf.extend_from_slice(protocol))?;
+                        }
+                        Ok(())
+                    })
+                    .map_err(|_| TlsError::EncodeError),
                 ClientExtension::PskKeyExchangeModes { modes } => buf.with_u8_length(|buf| {
                     for mode in modes {
                         buf.push(*mode as u8).map_err(|_| TlsError::EncodeError)?;
@@ -103,17 +196,34 @@ impl ClientExtension<'_> {
                     }
                     Ok(())
                 }),
-                ClientExtension::SupportedGroups { supported_groups } => {
-                    buf.with_u16_length(|buf| {
-                        for &g in supported_groups {
-                            buf.push_u16(g as u16).map_err(|_| TlsError::EncodeError)?;
-                        }
-                        Ok(())
-                    })
-                }
-                ClientExtension::KeyShare(key_share) => key_share.encode(buf),
+                ClientExtension::SupportedGroups {
+                    supported_groups,
+                    grease,
+                } => buf.with_u16_length(|buf| {
+                    if let Some(grease) = grease {
+                        buf.push_u16(grease.group())
+                            .map_err(|_| TlsError::EncodeError)?;
+                    }
+                    for &g in supported_groups {
+                        buf.push_u16(g as u16).map_err(|_| TlsError::EncodeError)?;
+                    }
+                    Ok(())
+                }),
+                ClientExtension::KeyShare {
+                    client_shares,
+                    grease,
+                } => buf.with_u16_length(|buf| {
+                    if let Some(grease) = grease {
+                        grease.encode_key_share(buf)?;
+                    }
+                    for share in client_shares {
+                        share.encode(buf)?;
+                    }
+                    Ok(())
+                }),
                 ClientExtension::PreSharedKey {
                     identities,
+                    obfuscated_ticket_age,
                     hash_size,
                 } => {
                     buf.with_u16_length(|buf| {
@@ -121,8 +231,8 @@ impl ClientExtension<'_> {
                             buf.with_u16_length(|buf| buf.extend_from_slice(identity))
                                 .map_err(|_| TlsError::EncodeError)?;
 
-                            // NOTE: No support for ticket age, set to 0 as recommended by RFC
-                            buf.push_u32(0).map_err(|_| TlsError::EncodeError)?;
+                            buf.push_u32(*obfuscated_ticket_age)
+                                .map_err(|_| TlsError::EncodeError)?;
                         }
                         Ok(())
                     })
@@ -142,6 +252,184 @@ impl ClientExtension<'_> {
                 ClientExtension::MaxFragmentLength(len) => {
                     buf.push(*len as u8).map_err(|_| TlsError::EncodeError)
                 }
+                // RFC 6520, Section 2.  Heartbeat Hello Extension
+                // struct {
+                //    HeartbeatMode mode;
+                // } HeartbeatExtension;
+                ClientExtension::Heartbeat(mode) => {
+                    buf.push(*mode as u8).map_err(|_| TlsError::EncodeError)
+                }
+                // RFC 6066, Section 8.  Certificate Status Request
+                // struct {
+                //     CertificateStatusType status_type;
+                //     select (status_type) {
+                //         case ocsp: OCSPStatusRequest;
+                //     } request;
+                // } CertificateStatusRequest;
+                //
+                // struct {
+                //     ResponderID responder_id_list<0..2^16-1>;
+                //     Extensions  request_extensions;
+                // } OCSPStatusRequest;
+                ClientExtension::StatusRequest => {
+                    const STATUS_TYPE_OCSP: u8 = 1;
+                    buf.push(STATUS_TYPE_OCSP)
+                        .map_err(|_| TlsError::EncodeError)?;
+                    buf.push_u16(0).map_err(|_| TlsError::EncodeError)?;
+                    buf.push_u16(0).map_err(|_| TlsError::EncodeError)
+                }
+                // RFC 6962, Section 3.3.1.  TLS Extension
+                // Clients that support the extension SHOULD send a ClientHello extension with the
+                // appropriate type and empty "extension_data".
+                ClientExtension::SignedCertificateTimestamp => Ok(()),
+                // RFC 7250, Section 3.  Structure of the Raw Public Key Extension
+                // struct {
+                //         select(ClientOrServerExtension) {
+                //             case client:
+                //               CertificateType client_certificate_types<1..2^8-1>;
+                //             case server:
+                //               CertificateType client_certificate_type;
+                //         }
+                // } ClientCertTypeExtension;
+                //
+                // struct {
+                //         select(ClientOrServerExtension) {
+                //             case client:
+                //               CertificateType server_certificate_types<1..2^8-1>;
+                //             case server:
+                //               CertificateType server_certificate_type;
+                //         }
+                // } ServerCertTypeExtension;
+                ClientExtension::ClientCertificateType(types)
+                | ClientExtension::ServerCertificateType(types) => buf
+                    .with_u8_length(|buf| {
+                        for certificate_type in types.iter() {
+                            buf.push(*certificate_type as u8)?;
+                        }
+                        Ok(())
+                    })
+                    .map_err(|_| TlsError::EncodeError),
+                // RFC 8879, Section 3.  Negotiating Certificate Compression
+                // struct {
+                //     CertificateCompressionAlgorithm algorithms<2..2^8-2>;
+                // } CertificateCompressionAlgorithms;
+                ClientExtension::CompressCertificate(algorithms) => buf
+                    .with_u8_length(|buf| {
+                        for algorithm in algorithms.iter() {
+                            buf.push_u16(*algorithm as u16)?;
+                        }
+                        Ok(())
+                    })
+                    .map_err(|_| TlsError::EncodeError),
+                // RFC 8446, Section 4.2.4.  Certificate Authorities
+                // opaque DistinguishedName<1..2^16-1>;
+                //
+                // struct {
+                //     DistinguishedName authorities<3..2^16-1>;
+                // } CertificateAuthoritiesExtension;
+                ClientExtension::CertificateAuthorities { ca, roots } => {
+                    buf.with_u16_length(|buf| {
+                        for certificate in ca.iter().chain(roots.iter()) {
+                            let name = crate::x509::subject(certificate)?;
+                            buf.with_u16_length(|buf| buf.extend_from_slice(name))
+                                .map_err(|_| TlsError::EncodeError)?;
+                        }
+                        Ok(())
+                    })
+                }
+                // RFC 9345, Section 4.1.1.  Server Authentication
+                // The body of the extension consists of a SignatureSchemeList (defined in
+                // [RFC8446]):
+                //
+                // struct {
+                //   SignatureScheme supported_signature_algorithm<2..2^16-2>;
+                // } SignatureSchemeList;
+                ClientExtension::DelegatedCredential(schemes) => buf
+                    .with_u16_length(|buf| {
+                        for scheme in schemes.iter() {
+                            buf.push_u16(*scheme as u16)?;
+                        }
+                        Ok(())
+                    })
+                    .map_err(|_| TlsError::EncodeError),
+                // RFC 9149, Section 3.  Ticket Requests
+                // struct {
+                //     uint8 new_session_count;
+                //     uint8 resumption_count;
+                // } ClientTicketRequest;
+                ClientExtension::TicketRequest {
+                    new_session_count,
+                    resumption_count,
+                } => {
+                    buf.push(*new_session_count)
+                        .map_err(|_| TlsError::EncodeError)?;
+                    buf.push(*resumption_count)
+                        .map_err(|_| TlsError::EncodeError)
+                }
+                ClientExtension::EarlyData => Ok(()),
+                ClientExtension::Cookie(cookie) => buf
+                    .with_u16_length(|buf| buf.extend_from_slice(cookie))
+                    .map_err(|_| TlsError::EncodeError),
+                // RFC 7685, Section 3.  Padding Extension
+                // The client MUST fill the padding extension completely with zero bytes
+                ClientExtension::Padding(len) => {
+                    for _ in 0..*len {
+                        buf.push(0).map_err(|_| TlsError::EncodeError)?;
+                    }
+                    Ok(())
+                }
+                // draft-ietf-tls-esni, Section 5.  The "encrypted_client_hello" Extension
+                // enum { outer(0), inner(1) } ECHClientHelloType;
+                //
+                // struct {
+                //    ECHClientHelloType type;
+                //    select (ECHClientHello.type) {
+                //        case outer:
+                //            HpkeSymmetricCipherSuite cipher_suite;
+                //            uint8 config_id;
+                //            opaque enc<0..2^16-1>;
+                //            opaque payload<1..2^16-1>;
+                //        case inner:
+                //            Empty;
+                //    };
+                // } ECHClientHello;
+                #[cfg(feature = "ech")]
+                ClientExtension::EncryptedClientHelloOuter {
+                    cipher_suite: (kdf, aead),
+                    config_id,
+                    enc,
+                    payload_len,
+                } => {
+                    buf.push(0).map_err(|_| TlsError::EncodeError)?;
+                    buf.push_u16(*kdf).map_err(|_| TlsError::EncodeError)?;
+                    buf.push_u16(*aead).map_err(|_| TlsError::EncodeError)?;
+                    buf.push(*config_id).map_err(|_| TlsError::EncodeError)?;
+                    buf.with_u16_length(|buf| buf.extend_from_slice(enc))
+                        .map_err(|_| TlsError::EncodeError)?;
+                    buf.with_u16_length(|buf| {
+                        for _ in 0..*payload_len {
+                            buf.push(0)?;
+                        }
+                        Ok(())
+                    })
+                    .map_err(|_| TlsError::EncodeError)
+                }
+                #[cfg(feature = "ech")]
+                ClientExtension::EncryptedClientHelloInner => {
+                    buf.push(1).map_err(|_| TlsError::EncodeError)
+                }
+                // RFC 8422, Section 5.1.2.  Supported Point Formats Extension
+                // enum {
+                //     uncompressed (0),
+                //     deprecated (1..2),
+                //     reserved (248..255)
+                // } ECPointFormat;
+                #[cfg(feature = "tls12")]
+                ClientExtension::EcPointFormats => buf
+                    .with_u8_length(|buf| buf.push(0))
+                    .map_err(|_| TlsError::EncodeError),
+                #[cfg(feature = "tls12")]
+                ClientExtension::ExtendedMasterSecret => Ok(()),
             }
         })
     }
@@ -11,10 +11,6 @@ impl<'a> KeyShare<'a> {
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<KeyShare<'a>, ParseError> {
         Ok(KeyShare(KeyShareEntry::parse(buf)?))
     }
-
-    pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
-        self.0.encode(buf)
-    }
 }
 
 #[derive(Debug)]
@@ -47,13 +43,11 @@ impl<'a> KeyShareEntry<'a> {
     }
 
     pub fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
-        buf.with_u16_length(|buf| {
-            buf.push_u16(self.group as u16)
-                .map_err(|_| TlsError::EncodeError)?;
+        buf.push_u16(self.group as u16)
+            .map_err(|_| TlsError::EncodeError)?;
 
-            buf.with_u16_length(|buf| buf.extend_from_slice(self.opaque))
-                .map_err(|_| TlsError::EncodeError)
-        })
+        buf.with_u16_length(|buf| buf.extend_from_slice(self.opaque))
+            .map_err(|_| TlsError::EncodeError)
     }
 }
 
@@ -1,14 +1,30 @@
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic
+    )
+)]
+
 pub mod client;
 pub mod common;
 pub mod server;
 
-#[derive(Debug, PartialEq)]
+use crate::config::{MAX_CUSTOM_EXTENSIONS, MAX_CUSTOM_EXTENSION_LEN};
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+use heapless::Vec;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ExtensionType {
     ServerName = 0,
     MaxFragmentLength = 1,
     StatusRequest = 5,
     SupportedGroups = 10,
+    EcPointFormats = 11,
     SignatureAlgorithms = 13,
     UseSrtp = 14,
     Heartbeat = 15,
@@ -17,6 +33,9 @@ pub enum ExtensionType {
     ClientCertificateType = 19,
     ServerCertificateType = 20,
     Padding = 21,
+    ExtendedMasterSecret = 23,
+    CompressCertificate = 27,
+    DelegatedCredential = 34,
     PreSharedKey = 41,
     EarlyData = 42,
     SupportedVersions = 43,
@@ -27,6 +46,9 @@ pub enum ExtensionType {
     PostHandshakeAuth = 49,
     SignatureAlgorithmsCert = 50,
     KeyShare = 51,
+    TicketRequest = 58,
+    EncryptedClientHello = 0xfe0d,
+    RenegotiationInfo = 0xff01,
 }
 
 impl ExtensionType {
@@ -37,6 +59,7 @@ impl ExtensionType {
             1 => Some(Self::MaxFragmentLength),
             5 => Some(Self::StatusRequest),
             10 => Some(Self::SupportedGroups),
+            11 => Some(Self::EcPointFormats),
             13 => Some(Self::SignatureAlgorithms),
             14 => Some(Self::UseSrtp),
             15 => Some(Self::Heartbeat),
@@ -45,6 +68,9 @@ impl ExtensionType {
             19 => Some(Self::ClientCertificateType),
             20 => Some(Self::ServerCertificateType),
             21 => Some(Self::Padding),
+            23 => Some(Self::ExtendedMasterSecret),
+            27 => Some(Self::CompressCertificate),
+            34 => Some(Self::DelegatedCredential),
             41 => Some(Self::PreSharedKey),
             42 => Some(Self::EarlyData),
             43 => Some(Self::SupportedVersions),
@@ -55,7 +81,119 @@ impl ExtensionType {
             49 => Some(Self::PostHandshakeAuth),
             50 => Some(Self::SignatureAlgorithmsCert),
             51 => Some(Self::KeyShare),
+            58 => Some(Self::TicketRequest),
+            0xfe0d => Some(Self::EncryptedClientHello),
+            0xff01 => Some(Self::RenegotiationInfo),
             _ => None,
         }
     }
 }
+
+/// The types of the extensions of a message, read back from its validated extensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExtensionTypes {
+    types: u64,
+    duplicate: bool,
+}
+
+impl ExtensionTypes {
+    /// Reads the types of an extensions block without its length prefix, as returned by
+    /// [`server::ServerExtension::parse_each`].
+    pub fn of(extensions: &[u8]) -> Self {
+        let mut result = Self::default();
+        let mut buf = ParseBuffer::new(extensions);
+        while let (Ok(extension_type), Ok(len)) = (buf.read_u16(), buf.read_u16()) {
+            if buf.slice(len as usize).is_err() {
+                break;
+            }
+            let bit = 1u64.checked_shl(extension_type.into()).unwrap_or(0);
+            result.duplicate |= result.types & bit != 0;
+            result.types |= bit;
+        }
+        result
+    }
+
+    pub fn contains(&self, extension_type: ExtensionType) -> bool {
+        // Types beyond the first 64 are not recorded
+        let bit = 1u64.checked_shl(extension_type as u32).unwrap_or(0);
+        self.types & bit != 0
+    }
+
+    /// Whether an extension type appears more than once.
+    pub fn has_duplicates(&self) -> bool {
+        self.duplicate
+    }
+}
+
+/// The extensions of the types which are not defined by [`ExtensionType`] in an extensions block
+/// without its length prefix, as `(extension_type, extension_data)` pairs.
+pub fn unknown_extensions(extensions: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut buf = ParseBuffer::new(extensions);
+    core::iter::from_fn(move || loop {
+        let extension_type = buf.read_u16().ok()?;
+        let len = buf.read_u16().ok()?;
+        let data = buf.slice(len as usize).ok()?;
+        if ExtensionType::of(extension_type).is_none() {
+            return Some((extension_type, data.as_slice()));
+        }
+    })
+}
+
+/// The custom extensions echoed by the server, see [`crate::config::TlsConfig::with_custom_extensions`].
+#[derive(Debug, Clone, Default)]
+pub struct CustomExtensions {
+    extensions: Vec<(u16, Vec<u8, MAX_CUSTOM_EXTENSION_LEN>), MAX_CUSTOM_EXTENSIONS>,
+}
+
+impl CustomExtensions {
+    pub fn push(&mut self, extension_type: u16, data: &[u8]) -> Result<(), TlsError> {
+        let data = Vec::from_slice(data).map_err(|_| TlsError::InsufficientSpace)?;
+        self.extensions
+            .push((extension_type, data))
+            .map_err(|_| TlsError::InsufficientSpace)
+    }
+
+    /// The data of the extension of the given type, if the server sent one.
+    pub fn get(&self, extension_type: u16) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(t, _)| *t == extension_type)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_types() {
+        // supported_versions, key_share and an empty server_name
+        let extensions = [
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, 0x00, 0x33, 0x00, 0x02, 0x00, 0x1d, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let types = ExtensionTypes::of(&extensions);
+        assert!(types.contains(ExtensionType::SupportedVersions));
+        assert!(types.contains(ExtensionType::KeyShare));
+        assert!(types.contains(ExtensionType::ServerName));
+        assert!(!types.contains(ExtensionType::PreSharedKey));
+        assert!(!types.has_duplicates());
+
+        let types = ExtensionTypes::of(&[0x00, 0x2a, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00]);
+        assert!(types.contains(ExtensionType::EarlyData));
+        assert!(types.has_duplicates());
+    }
+
+    #[test]
+    fn test_unknown_extensions() {
+        // An empty server_name, a private-use extension and an empty early_data
+        let extensions = [
+            0x00, 0x00, 0x00, 0x00, 0xfe, 0x00, 0x00, 0x02, 0x01, 0x02, 0x00, 0x2a, 0x00, 0x00,
+        ];
+        let mut unknown = unknown_extensions(&extensions);
+        assert_eq!(Some((0xfe00, &[0x01, 0x02][..])), unknown.next());
+        assert_eq!(None, unknown.next());
+    }
+}
@@ -1,17 +1,54 @@
 use crate::alert::{AlertDescription, AlertLevel};
+use crate::certificate_types::CertificateType;
 use crate::extensions::common::KeyShare;
 use crate::extensions::ExtensionType;
+use crate::heartbeat::HeartbeatMode;
+use crate::max_fragment_length::MaxFragmentLength;
+use crate::named_groups::NamedGroup;
 use crate::parse_buffer::{ParseBuffer, ParseError};
 use crate::supported_versions::ProtocolVersion;
 use crate::TlsError;
-use heapless::Vec;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ServerExtension<'a> {
     SupportedVersion(SupportedVersion),
     KeyShare(KeyShare<'a>),
+    /// The key share of a HelloRetryRequest, with only the group selected by the server.
+    SelectedGroup(NamedGroup),
     PreSharedKey(u16),
+    Cookie(&'a [u8]),
+    /// The encoded signature schemes accepted by the server in a CertificateRequest.
+    SignatureAlgorithms(&'a [u8]),
+    /// The encoded distinguished names of the certificate authorities in a CertificateRequest.
+    CertificateAuthorities(&'a [u8]),
+    /// The encoded OID filters in a CertificateRequest.
+    OidFilters(&'a [u8]),
+    /// The max_early_data_size in a NewSessionTicket, absent in EncryptedExtensions.
+    EarlyData(Option<u32>),
+    /// The application protocol selected by the server.
+    ApplicationLayerProtocolNegotiation(&'a [u8]),
+    /// The maximum fragment length accepted by the server.
+    MaxFragmentLength(MaxFragmentLength),
+    /// The data of a status_request extension, which is a CertificateStatus in a certificate
+    /// entry.
+    StatusRequest(&'a [u8]),
+    /// The data of a signed_certificate_timestamp extension, which is a
+    /// SignedCertificateTimestampList in a certificate entry.
+    SignedCertificateTimestamp(&'a [u8]),
+    /// The DelegatedCredential of a delegated_credential extension in a certificate entry.
+    DelegatedCredential(&'a [u8]),
+    /// The heartbeat mode of the server.
+    Heartbeat(HeartbeatMode),
+    /// The number of tickets the server expects to send, in EncryptedExtensions.
+    TicketRequest(u8),
+    /// The type of the certificate of the client selected in EncryptedExtensions.
+    ClientCertificateType(CertificateType),
+    /// The type of the certificate of the server selected in EncryptedExtensions.
+    ServerCertificateType(CertificateType),
+    /// The retry_configs of a server rejecting Encrypted Client Hello, an ECHConfigList in
+    /// EncryptedExtensions.
+    EncryptedClientHello(&'a [u8]),
 
     SupportedGroups,
 
@@ -24,12 +61,18 @@ pub enum ServerExtension<'a> {
     // server hello.  The "extension_data" field of this extension SHALL be
     // empty.
     ServerName,
+
+    /// RFC 5746, Section 3.2.  Extension Definition
+    /// The "renegotiated_connection" field of the "renegotiation_info" extension of a TLS 1.2
+    /// ServerHello, which is empty for the initial handshake.
+    #[cfg(feature = "tls12")]
+    RenegotiationInfo(&'a [u8]),
 }
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct SupportedVersion {
-    selected_version: ProtocolVersion,
+    pub(crate) selected_version: ProtocolVersion,
 }
 
 impl SupportedVersion {
@@ -67,9 +110,16 @@ impl<'a> ServerExtension<'a> {
         buf: &mut ParseBuffer<'a>,
         allowed: &[ExtensionType],
     ) -> Result<Option<ServerExtension<'a>>, TlsError> {
-        let extension_type =
-            ExtensionType::of(buf.read_u16().map_err(|_| TlsError::UnknownExtensionType)?)
-                .ok_or(TlsError::UnknownExtensionType)?;
+        let code = buf.read_u16().map_err(|_| TlsError::UnknownExtensionType)?;
+        let Some(extension_type) = ExtensionType::of(code) else {
+            // Extensions of unknown types are skipped, and read back from the validated
+            // extensions by the handshake, see `unknown_extensions`
+            let extension_length = buf
+                .read_u16()
+                .map_err(|_| TlsError::InvalidExtensionsLength)?;
+            buf.slice(extension_length as usize)?;
+            return Ok(None);
+        };
 
         trace!("extension type {:?}", extension_type);
 
@@ -99,27 +149,27 @@ impl<'a> ServerExtension<'a> {
         Self::from_type_and_data(extension_type, &mut buf.slice(extension_length as usize)?)
     }
 
-    pub fn parse_vector<const N: usize>(
+    /// Parse an extensions vector, handing each extension to `f` instead of collecting them.
+    ///
+    /// Returns the raw extensions, without the length prefix.
+    pub fn parse_each(
         buf: &mut ParseBuffer<'a>,
         allowed: &[ExtensionType],
-    ) -> Result<Vec<ServerExtension<'a>, N>, TlsError> {
+        mut f: impl FnMut(ServerExtension<'a>) -> Result<(), TlsError>,
+    ) -> Result<&'a [u8], TlsError> {
         let extensions_len = buf
             .read_u16()
             .map_err(|_| TlsError::InvalidExtensionsLength)?;
 
         let mut ext_buf = buf.slice(extensions_len as usize)?;
 
-        let mut extensions = Vec::new();
-
         for extension in ServerExtensionParserIterator::new(&mut ext_buf, allowed) {
             if let Some(extension) = extension? {
-                extensions
-                    .push(extension)
-                    .map_err(|_| TlsError::DecodeError)?;
+                f(extension)?;
             }
         }
 
-        Ok(extensions)
+        Ok(ext_buf.as_slice())
     }
 
     fn from_type_and_data<'b>(
@@ -130,6 +180,16 @@ impl<'a> ServerExtension<'a> {
             ExtensionType::SupportedVersions => ServerExtension::SupportedVersion(
                 SupportedVersion::parse(data).map_err(|_| TlsError::InvalidSupportedVersions)?,
             ),
+            // Section 4.2.8.  Key Share
+            // In a HelloRetryRequest message, the "extension_data" field of this extension
+            // contains a KeyShareHelloRetryRequest value, which is only a NamedGroup.
+            ExtensionType::KeyShare if data.remaining() == 2 => {
+                let group = NamedGroup::of(data.read_u16()?).ok_or(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::IllegalParameter,
+                ))?;
+                ServerExtension::SelectedGroup(group)
+            }
             ExtensionType::KeyShare => ServerExtension::KeyShare(
                 KeyShare::parse(data).map_err(|_| TlsError::InvalidKeyShare)?,
             ),
@@ -138,8 +198,136 @@ impl<'a> ServerExtension<'a> {
 
                 ServerExtension::PreSharedKey(value)
             }
+            // Section 4.2.10.  Early Data Indication
+            // The extension_data field of this extension contains an "EarlyDataIndication"
+            // value, which is empty except in a NewSessionTicket.
+            ExtensionType::EarlyData if data.is_empty() => ServerExtension::EarlyData(None),
+            ExtensionType::EarlyData => ServerExtension::EarlyData(Some(data.read_u32()?)),
+            // Section 4.2.2.  Cookie
+            // struct {
+            //     opaque cookie<1..2^16-1>;
+            // } Cookie;
+            ExtensionType::Cookie => {
+                let len = data.read_u16()?;
+                if len == 0 {
+                    return Err(TlsError::DecodeError);
+                }
+                ServerExtension::Cookie(data.slice(len as usize)?.as_slice())
+            }
+            // Section 4.2.3.  Signature Algorithms
+            // struct {
+            //     SignatureScheme supported_signature_algorithms<2..2^16-2>;
+            // } SignatureSchemeList;
+            ExtensionType::SignatureAlgorithms => {
+                let len = data.read_u16()?;
+                if len < 2 || len % 2 != 0 {
+                    return Err(TlsError::DecodeError);
+                }
+                ServerExtension::SignatureAlgorithms(data.slice(len as usize)?.as_slice())
+            }
+            // Section 4.2.4.  Certificate Authorities
+            // struct {
+            //     DistinguishedName authorities<3..2^16-1>;
+            // } CertificateAuthoritiesExtension;
+            ExtensionType::CertificateAuthorities => {
+                let len = data.read_u16()?;
+                if len < 3 {
+                    return Err(TlsError::DecodeError);
+                }
+                ServerExtension::CertificateAuthorities(data.slice(len as usize)?.as_slice())
+            }
+            // Section 4.2.5.  OID Filters
+            // struct {
+            //     opaque certificate_extension_oid<1..2^8-1>;
+            //     opaque certificate_extension_values<0..2^16-1>;
+            // } OIDFilter;
+            //
+            // struct {
+            //     OIDFilter filters<0..2^16-1>;
+            // } OIDFilterExtension;
+            ExtensionType::OidFilters => {
+                let len = data.read_u16()?;
+                let filters = data.slice(len as usize)?;
+                let mut list = ParseBuffer::new(filters.as_slice());
+                while !list.is_empty() {
+                    let oid_len = list.read_u8()?;
+                    if oid_len == 0 {
+                        return Err(TlsError::DecodeError);
+                    }
+                    list.slice(oid_len as usize)?;
+                    let values_len = list.read_u16()?;
+                    list.slice(values_len as usize)?;
+                }
+                ServerExtension::OidFilters(filters.as_slice())
+            }
+            // RFC 7301, Section 3.1.  The Application-Layer Protocol Negotiation Extension
+            // The "ProtocolNameList" MUST contain exactly one "ProtocolName".
+            ExtensionType::ApplicationLayerProtocolNegotiation => {
+                let len = data.read_u16()?;
+                let mut list = data.slice(len as usize)?;
+                let protocol_len = list.read_u8()?;
+                let protocol = list.slice(protocol_len as usize)?;
+                if protocol.is_empty() || !list.is_empty() {
+                    return Err(TlsError::DecodeError);
+                }
+                ServerExtension::ApplicationLayerProtocolNegotiation(protocol.as_slice())
+            }
+            // RFC 6066, Section 4.  Maximum Fragment Length Negotiation
+            // Similarly, if a client receives a maximum fragment length negotiation response that
+            // differs from the length it requested, it MUST also abort the handshake with an
+            // "illegal_parameter" alert.
+            ExtensionType::MaxFragmentLength => {
+                ServerExtension::MaxFragmentLength(MaxFragmentLength::of(data.read_u8()?).ok_or(
+                    TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter),
+                )?)
+            }
+            // RFC 6520, Section 2.  Heartbeat Hello Extension
+            // Upon reception of an unknown mode, an error Alert message using illegal_parameter
+            // as its AlertDescription MUST be sent in response.
+            ExtensionType::Heartbeat => {
+                ServerExtension::Heartbeat(HeartbeatMode::of(data.read_u8()?).ok_or(
+                    TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::IllegalParameter),
+                )?)
+            }
+            ExtensionType::StatusRequest => ServerExtension::StatusRequest(data.as_slice()),
+            ExtensionType::SignedCertificateTimestamp => {
+                ServerExtension::SignedCertificateTimestamp(data.as_slice())
+            }
+            ExtensionType::DelegatedCredential => {
+                ServerExtension::DelegatedCredential(data.as_slice())
+            }
+            // RFC 9149, Section 3.  Ticket Requests
+            // struct {
+            //     uint8 expected_count;
+            // } ServerTicketRequestHint;
+            ExtensionType::TicketRequest => ServerExtension::TicketRequest(data.read_u8()?),
+            ExtensionType::ClientCertificateType => {
+                ServerExtension::ClientCertificateType(parse_certificate_type(data)?)
+            }
+            ExtensionType::ServerCertificateType => {
+                ServerExtension::ServerCertificateType(parse_certificate_type(data)?)
+            }
+            // draft-ietf-tls-esni, Section 5.  The "encrypted_client_hello" Extension
+            // struct {
+            //    ECHConfigList retry_configs;
+            // } ECHEncryptedExtensions;
+            ExtensionType::EncryptedClientHello => {
+                ServerExtension::EncryptedClientHello(data.as_slice())
+            }
             ExtensionType::SupportedGroups => ServerExtension::SupportedGroups,
             ExtensionType::ServerName => ServerExtension::ServerName,
+            // RFC 5746, Section 3.2.  Extension Definition
+            // struct {
+            //     opaque renegotiated_connection<0..255>;
+            // } RenegotiationInfo;
+            #[cfg(feature = "tls12")]
+            ExtensionType::RenegotiationInfo => {
+                let len = data.read_u8()?;
+                ServerExtension::RenegotiationInfo(data.slice(len as usize)?.as_slice())
+            }
+            // Only the presence of these TLS 1.2 extensions matters
+            #[cfg(feature = "tls12")]
+            ExtensionType::EcPointFormats | ExtensionType::ExtendedMasterSecret => return Ok(None),
             t => {
                 warn!("Unimplemented extension: {:?}", t);
                 return Ok(None);
@@ -149,3 +337,11 @@ impl<'a> ServerExtension<'a> {
         Ok(Some(extension))
     }
 }
+
+/// The certificate type selected by the server, among the types offered by the client.
+fn parse_certificate_type(data: &mut ParseBuffer<'_>) -> Result<CertificateType, TlsError> {
+    CertificateType::of(data.read_u8()?).ok_or(TlsError::AbortHandshake(
+        AlertLevel::Fatal,
+        AlertDescription::IllegalParameter,
+    ))
+}
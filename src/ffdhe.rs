@@ -0,0 +1,138 @@
+//! Finite field Diffie-Hellman groups from [RFC 7919](https://www.rfc-editor.org/rfc/rfc7919).
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::{Encoding, Random, Uint, U2048, U3072};
+use rand_core::{CryptoRng, RngCore};
+use zeroize::Zeroize;
+
+use crate::TlsError;
+
+/// Size in bytes of the largest supported prime (ffdhe3072).
+pub(crate) const MAX_FFDHE_LEN: usize = 384;
+
+/// The ffdhe2048 prime, with generator 2.
+pub(crate) static FFDHE2048: U2048 = U2048::from_be_hex(concat!(
+    "FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695",
+    "A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A",
+    "D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935",
+    "984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A",
+    "BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4",
+    "AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61",
+    "9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005",
+    "C58EF1837D1683B2C6F34A26C1B2EFFA886B423861285C97FFFFFFFFFFFFFFFF",
+));
+
+/// The ffdhe3072 prime, with generator 2.
+pub(crate) static FFDHE3072: U3072 = U3072::from_be_hex(concat!(
+    "FFFFFFFFFFFFFFFFADF85458A2BB4A9AAFDC5620273D3CF1D8B9C583CE2D3695",
+    "A9E13641146433FBCC939DCE249B3EF97D2FE363630C75D8F681B202AEC4617A",
+    "D3DF1ED5D5FD65612433F51F5F066ED0856365553DED1AF3B557135E7F57C935",
+    "984F0C70E0E68B77E2A689DAF3EFE8721DF158A136ADE73530ACCA4F483A797A",
+    "BC0AB182B324FB61D108A94BB2C8E3FBB96ADAB760D7F4681D4F42A3DE394DF4",
+    "AE56EDE76372BB190B07A7C8EE0A6D709E02FCE1CDF7E2ECC03404CD28342F61",
+    "9172FE9CE98583FF8E4F1232EEF28183C3FE3B1B4C6FAD733BB5FCBC2EC22005",
+    "C58EF1837D1683B2C6F34A26C1B2EFFA886B4238611FCFDCDE355B3B6519035B",
+    "BC34F4DEF99C023861B46FC9D6E6C9077AD91D2691F7F7EE598CB0FAC186D91C",
+    "AEFE130985139270B4130C93BC437944F4FD4452E2D74DD364F2E21E71F54BFF",
+    "5CAE82AB9C9DF69EE86D2BC522363A0DABC521979B0DEADA1DBF9A42D5C4484E",
+    "0ABCD06BFA53DDEF3C1B20EE3FD59D7C25E41D2B66C62E37FFFFFFFFFFFFFFFF",
+));
+
+pub type Ffdhe2048Secret = FfdheSecret<{ U2048::LIMBS }>;
+pub type Ffdhe3072Secret = FfdheSecret<{ U3072::LIMBS }>;
+
+/// An ephemeral private exponent for one of the finite field groups.
+pub struct FfdheSecret<const LIMBS: usize>
+where
+    Uint<LIMBS>: Encoding,
+{
+    prime: &'static Uint<LIMBS>,
+    exponent: Uint<LIMBS>,
+}
+
+impl<const LIMBS: usize> FfdheSecret<LIMBS>
+where
+    Uint<LIMBS>: Encoding,
+{
+    pub(crate) fn generate<RNG>(prime: &'static Uint<LIMBS>, rng: &mut RNG) -> Self
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        // The primes have their top 64 bits set, so any value below 2^(b-1) is in range.
+        let exponent = Uint::<LIMBS>::random(rng).shr_vartime(1);
+        Self { prime, exponent }
+    }
+
+    /// The public value g^x mod p, left-padded to the size of the prime.
+    pub(crate) fn public_key(&self) -> <Uint<LIMBS> as Encoding>::Repr {
+        let params = DynResidueParams::new(self.prime);
+        DynResidue::new(&Uint::from_u8(2), params)
+            .pow(&self.exponent)
+            .retrieve()
+            .to_be_bytes()
+    }
+
+    /// The shared secret Z = y^x mod p, left-padded to the size of the prime.
+    pub(crate) fn diffie_hellman(
+        &self,
+        peer: &[u8],
+    ) -> Result<<Uint<LIMBS> as Encoding>::Repr, TlsError> {
+        if peer.len() != Uint::<LIMBS>::BYTES {
+            return Err(TlsError::InvalidKeyShare);
+        }
+        let peer = Uint::<LIMBS>::from_be_slice(peer);
+
+        // Section 4.2.8.1.  Diffie-Hellman Parameters
+        // Peers MUST validate each other's public key Y by ensuring that 1 < Y
+        // < p-1.
+        if peer <= Uint::ONE || peer >= self.prime.wrapping_sub(&Uint::ONE) {
+            return Err(TlsError::InvalidKeyShare);
+        }
+
+        let params = DynResidueParams::new(self.prime);
+        Ok(DynResidue::new(&peer, params)
+            .pow(&self.exponent)
+            .retrieve()
+            .to_be_bytes())
+    }
+}
+
+impl<const LIMBS: usize> Drop for FfdheSecret<LIMBS>
+where
+    Uint<LIMBS>: Encoding,
+{
+    fn drop(&mut self) {
+        self.exponent.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_ffdhe2048_agreement() {
+        let client = FfdheSecret::generate(&FFDHE2048, &mut OsRng);
+        let server = FfdheSecret::generate(&FFDHE2048, &mut OsRng);
+
+        let client_shared = client.diffie_hellman(&server.public_key()).unwrap();
+        let server_shared = server.diffie_hellman(&client.public_key()).unwrap();
+        assert_eq!(client_shared, server_shared);
+        assert_eq!(256, client_shared.len());
+    }
+
+    #[test]
+    fn test_reject_invalid_public_value() {
+        let client = FfdheSecret::generate(&FFDHE2048, &mut OsRng);
+
+        let one = U2048::ONE.to_be_bytes();
+        assert!(client.diffie_hellman(&one).is_err());
+
+        let p_minus_one = FFDHE2048.wrapping_sub(&U2048::ONE).to_be_bytes();
+        assert!(client.diffie_hellman(&p_minus_one).is_err());
+
+        assert!(client.diffie_hellman(&[2; 255]).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Entry points for fuzzing the parsers with arbitrary bytes.
+//!
+//! These functions are stable wrappers around the internal parsers, so that `cargo fuzz` targets
+//! do not depend on private modules. They return an error for malformed input and must never
+//! panic, which is what a fuzz target checks:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//!
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = embedded_tls::fuzzing::parse_server_hello(data);
+//! });
+//! ```
+use core::convert::Infallible;
+
+use embedded_io::blocking::Read;
+use embedded_io::Io;
+
+use crate::config::Aes128GcmSha256;
+use crate::extensions::server::ServerExtension;
+use crate::extensions::ExtensionType;
+use crate::handshake::certificate::CertificateRef;
+use crate::handshake::server_hello::ServerHello;
+use crate::handshake::ServerHandshake;
+use crate::key_schedule::{HashOutputSize, KeySchedule};
+use crate::parse_buffer::ParseBuffer;
+use crate::record_reader::RecordReader;
+use crate::TlsError;
+
+/// Room for a record of maximum size (the header and up to 2^14 + 256 bytes of ciphertext), plus
+/// the read ahead of the record reader.
+const RECORD_BUFFER_SIZE: usize = 2 * (5 + 16384 + 256);
+
+const ALL_EXTENSIONS: &[ExtensionType] = &[
+    ExtensionType::ServerName,
+    ExtensionType::MaxFragmentLength,
+    ExtensionType::StatusRequest,
+    ExtensionType::SupportedGroups,
+    ExtensionType::SignatureAlgorithms,
+    ExtensionType::UseSrtp,
+    ExtensionType::Heartbeat,
+    ExtensionType::ApplicationLayerProtocolNegotiation,
+    ExtensionType::SignedCertificateTimestamp,
+    ExtensionType::ClientCertificateType,
+    ExtensionType::ServerCertificateType,
+    ExtensionType::Padding,
+    ExtensionType::CompressCertificate,
+    ExtensionType::DelegatedCredential,
+    ExtensionType::PreSharedKey,
+    ExtensionType::EarlyData,
+    ExtensionType::SupportedVersions,
+    ExtensionType::Cookie,
+    ExtensionType::PskKeyExchangeModes,
+    ExtensionType::CertificateAuthorities,
+    ExtensionType::OidFilters,
+    ExtensionType::PostHandshakeAuth,
+    ExtensionType::SignatureAlgorithmsCert,
+    ExtensionType::KeyShare,
+    ExtensionType::TicketRequest,
+    ExtensionType::EncryptedClientHello,
+];
+
+/// Reads the records in `data` as received from the server before the handshake keys are known,
+/// in chunks of at most `chunk_size` bytes.
+///
+/// Reaching the end of `data` between or inside a record is not an error.
+pub fn decode_records(data: &[u8], chunk_size: usize) -> Result<(), TlsError> {
+    let mut buffer = [0; RECORD_BUFFER_SIZE];
+    let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buffer);
+    let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+    let mut input = Input {
+        data,
+        chunk_size: usize::max(chunk_size, 1),
+    };
+
+    loop {
+        match reader.read_blocking(&mut input, key_schedule.read_state()) {
+            Ok(_) => {}
+            Err(TlsError::IoError) if input.data.is_empty() => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Parses a decrypted handshake message, including its handshake header.
+pub fn parse_handshake(data: &[u8]) -> Result<(), TlsError> {
+    let mut buf = ParseBuffer::new(data);
+    while !buf.is_empty() {
+        ServerHandshake::<HashOutputSize<Aes128GcmSha256>>::parse(&mut buf)?;
+    }
+    Ok(())
+}
+
+/// Parses the body of a ServerHello message, without the handshake header.
+pub fn parse_server_hello(data: &[u8]) -> Result<(), TlsError> {
+    ServerHello::parse(&mut ParseBuffer::new(data))?;
+    Ok(())
+}
+
+/// Parses a length prefixed vector of server extensions, allowing any extension type.
+pub fn parse_server_extensions(data: &[u8]) -> Result<(), TlsError> {
+    ServerExtension::parse_each(&mut ParseBuffer::new(data), ALL_EXTENSIONS, |_| Ok(()))?;
+    Ok(())
+}
+
+/// Parses the body of a Certificate message, without the handshake header.
+///
+/// With the `webpki` feature, the public key is also read from every X.509 certificate.
+pub fn parse_certificate(data: &[u8]) -> Result<(), TlsError> {
+    let certificate = CertificateRef::parse(&mut ParseBuffer::new(data))?;
+    #[cfg(feature = "webpki")]
+    for entry in certificate.entries.iter() {
+        if let crate::handshake::certificate::CertificateEntryRef::X509(der) = entry {
+            crate::x509::SubjectPublicKeyInfo::from_certificate(der)?;
+        }
+    }
+    #[cfg(not(feature = "webpki"))]
+    let _ = certificate;
+    Ok(())
+}
+
+struct Input<'d> {
+    data: &'d [u8],
+    chunk_size: usize,
+}
+
+impl Io for Input<'_> {
+    type Error = Infallible;
+}
+
+impl Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = usize::min(usize::min(buf.len(), self.chunk_size), self.data.len());
+        let (chunk, rest) = self.data.split_at(len);
+        buf[..len].copy_from_slice(chunk);
+        self.data = rest;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncated_input() {
+        // A ServerHello record, cut short in the middle of the extensions
+        let mut record = [0; 50];
+        record[..9].copy_from_slice(&[0x16, 0x03, 0x03, 0x00, 45, 0x02, 0x00, 0x00, 41]);
+        record[9..11].copy_from_slice(&[0x03, 0x03]);
+        record[43..].copy_from_slice(&[0x00, 0x13, 0x01, 0x00, 0x00, 0x10, 0x00]);
+
+        for len in 0..record.len() {
+            assert!(decode_records(&record[..len], 7).is_ok());
+        }
+        assert!(decode_records(&record, 7).is_err());
+        assert!(parse_server_hello(&record[9..]).is_err());
+        assert!(parse_handshake(&record[5..]).is_err());
+    }
+
+    #[test]
+    fn test_garbage_input() {
+        let inputs: [&[u8]; 4] = [
+            &[],
+            &[0xff; 64],
+            &[0x00; 64],
+            &[0x16, 0x03, 0x03, 0xff, 0xff],
+        ];
+        for input in inputs {
+            let _ = decode_records(input, 3);
+            let _ = parse_handshake(input);
+            let _ = parse_server_hello(input);
+            let _ = parse_server_extensions(input);
+            let _ = parse_certificate(input);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! RFC 8701.  Applying Generate Random Extensions And Sustain Extensibility (GREASE) to TLS
+//! Extensibility
+//!
+//! The ClientHello advertises reserved values among the cipher suites, groups, versions and
+//! extensions, so that servers which do not ignore unknown values are noticed.
+use crate::buffer::CryptoBuffer;
+use crate::TlsError;
+
+/// The GREASE values of a connection.
+///
+/// Section 3.1.  Client-Initiated Extension Points
+/// When sending a ClientHello, a client MAY advertise one or more GREASE values. The values are
+/// drawn at random for each connection, and kept in the second ClientHello after a
+/// HelloRetryRequest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Grease([u8; 5]);
+
+impl Grease {
+    const CIPHER_SUITE: usize = 0;
+    const GROUP: usize = 1;
+    const VERSION: usize = 2;
+    const EXTENSION: usize = 3;
+    const LAST_EXTENSION: usize = 4;
+
+    /// Picks the GREASE values from random bytes.
+    pub(crate) fn new(mut random: [u8; 5]) -> Self {
+        // Extensions of the same type must not be repeated
+        if value(random[Self::EXTENSION]) == value(random[Self::LAST_EXTENSION]) {
+            random[Self::LAST_EXTENSION] ^= 0x10;
+        }
+        Self(random)
+    }
+
+    pub(crate) fn cipher_suite(&self) -> u16 {
+        value(self.0[Self::CIPHER_SUITE])
+    }
+
+    pub(crate) fn group(&self) -> u16 {
+        value(self.0[Self::GROUP])
+    }
+
+    pub(crate) fn version(&self) -> u16 {
+        value(self.0[Self::VERSION])
+    }
+
+    /// An empty extension, sent first.
+    pub(crate) fn encode_extension(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        encode_extension(buf, value(self.0[Self::EXTENSION]), &[])
+    }
+
+    /// An extension with a single zero byte, sent last but for the padding and pre_shared_key
+    /// extensions.
+    pub(crate) fn encode_last_extension(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        encode_extension(buf, value(self.0[Self::LAST_EXTENSION]), &[0])
+    }
+
+    /// A key share entry for the GREASE group, with a single zero byte.
+    pub(crate) fn encode_key_share(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        buf.push_u16(self.group())
+            .map_err(|_| TlsError::EncodeError)?;
+        buf.with_u16_length(|buf| buf.push(0))
+            .map_err(|_| TlsError::EncodeError)
+    }
+}
+
+/// Section 2.  GREASE Values
+/// The following values are reserved as GREASE values for cipher suites and Application-Layer
+/// Protocol Negotiation (ALPN) identifiers: {0x0A,0x0A}, {0x1A,0x1A}, ..., {0xFA,0xFA}
+fn value(random: u8) -> u16 {
+    let byte = (random & 0xF0) | 0x0A;
+    u16::from_be_bytes([byte, byte])
+}
+
+fn encode_extension(
+    buf: &mut CryptoBuffer,
+    extension_type: u16,
+    data: &[u8],
+) -> Result<(), TlsError> {
+    buf.push_u16(extension_type)
+        .map_err(|_| TlsError::EncodeError)?;
+    buf.with_u16_length(|buf| buf.extend_from_slice(data))
+        .map_err(|_| TlsError::EncodeError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values() {
+        let grease = Grease::new([0x00, 0x1F, 0xA5, 0xFF, 0xF0]);
+        assert_eq!(0x0A0A, grease.cipher_suite());
+        assert_eq!(0x1A1A, grease.group());
+        assert_eq!(0xAAAA, grease.version());
+
+        let mut storage = [0; 16];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        grease.encode_extension(&mut buf).unwrap();
+        grease.encode_last_extension(&mut buf).unwrap();
+        assert_eq!(
+            &[0xFA, 0xFA, 0x00, 0x00, 0xEA, 0xEA, 0x00, 0x01, 0x00],
+            buf.as_slice()
+        );
+    }
+}
@@ -27,7 +27,7 @@ impl<N: ArrayLength<u8>> PskBinder<N> {
         let len = self.verify.len() as u8;
         //buf.extend_from_slice(&[len[1], len[2], len[3]]);
         buf.push(len).map_err(|_| TlsError::EncodeError)?;
-        buf.extend_from_slice(&self.verify[..self.verify.len()])
+        buf.extend_from_slice(&self.verify)
             .map_err(|_| TlsError::EncodeError)?;
         Ok(())
     }
@@ -8,18 +8,27 @@ use heapless::Vec;
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CertificateRef<'a> {
-    raw_entries: &'a [u8],
-    request_context: &'a [u8],
+    pub(crate) request_context: &'a [u8],
 
     pub(crate) entries: Vec<CertificateEntryRef<'a>, 16>,
+    /// The OCSP response stapled to the end-entity certificate, if any.
+    pub(crate) ocsp_response: Option<&'a [u8]>,
+    /// The SignedCertificateTimestampList of the end-entity certificate, if any.
+    #[cfg(feature = "sct")]
+    pub(crate) sct_list: Option<&'a [u8]>,
+    /// The delegated credential of the end-entity certificate, if any.
+    pub(crate) delegated_credential: Option<&'a [u8]>,
 }
 
 impl<'a> CertificateRef<'a> {
     pub fn with_context(request_context: &'a [u8]) -> Self {
         Self {
-            raw_entries: &[],
             request_context,
             entries: Vec::new(),
+            ocsp_response: None,
+            #[cfg(feature = "sct")]
+            sct_list: None,
+            delegated_credential: None,
         }
     }
 
@@ -29,6 +38,17 @@ impl<'a> CertificateRef<'a> {
             .map_err(|_| TlsError::InsufficientSpace)
     }
 
+    /// The entries of a Certificate message, once the raw public key certificate type is
+    /// negotiated, hold a SubjectPublicKeyInfo instead of a certificate.
+    pub(crate) fn into_raw_public_keys(mut self) -> Self {
+        for entry in self.entries.iter_mut() {
+            if let CertificateEntryRef::X509(data) = *entry {
+                *entry = CertificateEntryRef::RawPublicKey(data);
+            }
+        }
+        self
+    }
+
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, TlsError> {
         let request_context_len = buf.read_u8().map_err(|_| TlsError::InvalidCertificate)?;
         let request_context = buf
@@ -39,26 +59,57 @@ impl<'a> CertificateRef<'a> {
             .slice(entries_len as usize)
             .map_err(|_| TlsError::InvalidCertificate)?;
 
-        let entries = CertificateEntryRef::parse_vector(&mut raw_entries)?;
+        let mut certificate = CertificateEntryRef::parse_entries(&mut raw_entries)?;
+        certificate.request_context = request_context.as_slice();
+        Ok(certificate)
+    }
+
+    /// RFC 5246, Section 7.4.2.  Server Certificate
+    ///     opaque ASN.1Cert<1..2^24-1>;
+    ///
+    ///     struct {
+    ///         ASN.1Cert certificate_list<0..2^24-1>;
+    ///     } Certificate;
+    #[cfg(feature = "tls12")]
+    pub(crate) fn parse_tls12(buf: &mut ParseBuffer<'a>) -> Result<Self, TlsError> {
+        let entries_len = buf.read_u24().map_err(|_| TlsError::InvalidCertificate)?;
+        let mut raw_entries = buf
+            .slice(entries_len as usize)
+            .map_err(|_| TlsError::InvalidCertificate)?;
+
+        let mut entries = Vec::new();
+        while !raw_entries.is_empty() {
+            let entry_len = raw_entries
+                .read_u24()
+                .map_err(|_| TlsError::InvalidCertificateEntry)?;
+            let cert = raw_entries
+                .slice(entry_len as usize)
+                .map_err(|_| TlsError::InvalidCertificateEntry)?;
+            entries
+                .push(CertificateEntryRef::X509(cert.as_slice()))
+                .map_err(|_| TlsError::DecodeError)?;
+        }
 
         Ok(Self {
-            raw_entries: raw_entries.as_slice(),
-            request_context: request_context.as_slice(),
+            request_context: &[],
             entries,
+            ocsp_response: None,
+            #[cfg(feature = "sct")]
+            sct_list: None,
+            delegated_credential: None,
         })
     }
 
     pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
-        buf.push(self.request_context.len() as u8)
-            .map_err(|_| TlsError::EncodeError)?;
-        buf.extend_from_slice(self.request_context)
+        buf.with_u8_length(|buf| buf.extend_from_slice(self.request_context))
             .map_err(|_| TlsError::EncodeError)?;
 
-        buf.push_u24(self.entries.len() as u32)?;
-        for entry in self.entries.iter() {
-            entry.encode(buf)?;
-        }
-        Ok(())
+        buf.with_u24_length(|buf| {
+            for entry in self.entries.iter() {
+                entry.encode(buf)?;
+            }
+            Ok(())
+        })
     }
 }
 
@@ -70,16 +121,24 @@ pub enum CertificateEntryRef<'a> {
 }
 
 impl<'a> CertificateEntryRef<'a> {
-    // Source: https://www.rfc-editor.org/rfc/rfc8446#section-4.2 table, rows marked with CT
+    // Source: https://www.rfc-editor.org/rfc/rfc8446#section-4.2 table, rows marked with CT, and
+    // the delegated_credential extension of RFC 9345
     const ALLOWED_EXTENSIONS: &[ExtensionType] = &[
         ExtensionType::StatusRequest,
         ExtensionType::SignedCertificateTimestamp,
+        ExtensionType::DelegatedCredential,
     ];
 
     pub fn parse_vector(
         buf: &mut ParseBuffer<'a>,
     ) -> Result<Vec<CertificateEntryRef<'a>, 16>, TlsError> {
-        let mut entries = Vec::new();
+        Ok(Self::parse_entries(buf)?.entries)
+    }
+
+    /// Parses the entries, along with the OCSP response, SCTs and delegated credential of the
+    /// end-entity certificate.
+    fn parse_entries(buf: &mut ParseBuffer<'a>) -> Result<CertificateRef<'a>, TlsError> {
+        let mut certificate = CertificateRef::with_context(&[]);
         loop {
             let entry_len = buf
                 .read_u24()
@@ -92,33 +151,50 @@ impl<'a> CertificateEntryRef<'a> {
             //let cert: Result<Vec<u8, _>, ()> = cert.into();
             // let cert: Result<Vec<u8, _>, ()> = Ok(Vec::new());
 
-            entries
+            certificate
+                .entries
                 .push(CertificateEntryRef::X509(cert.as_slice()))
                 .map_err(|_| TlsError::DecodeError)?;
 
             // Validate extensions
-            ServerExtension::parse_vector::<2>(buf, Self::ALLOWED_EXTENSIONS)?;
+            let first = certificate.entries.len() == 1;
+            ServerExtension::parse_each(buf, Self::ALLOWED_EXTENSIONS, |extension| {
+                match extension {
+                    ServerExtension::StatusRequest(data) => {
+                        let response = parse_certificate_status(data)?;
+                        if first {
+                            certificate.ocsp_response.get_or_insert(response);
+                        }
+                    }
+                    #[cfg(feature = "sct")]
+                    ServerExtension::SignedCertificateTimestamp(data) if first => {
+                        certificate.sct_list.get_or_insert(data);
+                    }
+                    ServerExtension::DelegatedCredential(data) if first => {
+                        certificate.delegated_credential.get_or_insert(data);
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })?;
 
             if buf.is_empty() {
                 break;
             }
         }
-        Ok(entries)
+        Ok(certificate)
     }
 
-    pub(crate) fn encode(&self, _buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
-        todo!("not implemented");
-        /*
-        match self {
-            CertificateEntry::RawPublicKey(key) => {
-                let entry_len = (key.len() as u32).to_be_bytes();
-            }
-            CertificateEntry::X509(cert) => {
-                let entry_len = (cert.len() as u32).to_be_bytes();
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        match *self {
+            CertificateEntryRef::RawPublicKey(data) | CertificateEntryRef::X509(data) => {
+                buf.with_u24_length(|buf| buf.extend_from_slice(data))
+                    .map_err(|_| TlsError::EncodeError)?;
             }
         }
-        Ok(())
-        */
+
+        // No extensions are sent for the entries of the client certificate
+        buf.push_u16(0).map_err(|_| TlsError::EncodeError)
     }
 }
 
@@ -143,7 +219,7 @@ pub struct Certificate<const N: usize> {
 
 impl<const N: usize> Certificate<N> {
     pub fn request_context(&self) -> &[u8] {
-        &self.request_context[..]
+        &self.request_context
     }
 }
 
@@ -154,10 +230,19 @@ impl<'a, const N: usize> TryFrom<CertificateRef<'a>> for Certificate<N> {
         request_context
             .extend_from_slice(cert.request_context)
             .map_err(|_| TlsError::OutOfMemory)?;
+        // The entries are encoded again without their extensions, which are not kept, so that
+        // the certificates of TLS 1.2 are stored the same way
         let mut entries_data = Vec::new();
-        entries_data
-            .extend_from_slice(cert.raw_entries)
-            .map_err(|_| TlsError::OutOfMemory)?;
+        for entry in cert.entries.iter() {
+            let (CertificateEntryRef::X509(data) | CertificateEntryRef::RawPublicKey(data)) =
+                *entry;
+            let [_, len @ ..] = (data.len() as u32).to_be_bytes();
+            for part in [&len[..], data, &[0, 0]] {
+                entries_data
+                    .extend_from_slice(part)
+                    .map_err(|_| TlsError::OutOfMemory)?;
+            }
+        }
 
         Ok(Self {
             request_context,
@@ -171,12 +256,92 @@ impl<'a, const N: usize> TryFrom<&'a Certificate<N>> for CertificateRef<'a> {
     type Error = TlsError;
     fn try_from(cert: &'a Certificate<N>) -> Result<Self, Self::Error> {
         let request_context = cert.request_context();
-        let entries =
-            CertificateEntryRef::parse_vector(&mut ParseBuffer::from(&cert.entries_data[..]))?;
+        let entries = CertificateEntryRef::parse_vector(&mut ParseBuffer::from(
+            cert.entries_data.as_slice(),
+        ))?;
         Ok(Self {
-            raw_entries: &cert.entries_data[..],
             request_context,
             entries,
+            ocsp_response: None,
+            #[cfg(feature = "sct")]
+            sct_list: None,
+            delegated_credential: None,
         })
     }
 }
+
+/// RFC 8446, Section 4.4.2.1.  OCSP Status and SCT Extensions
+/// In TLS 1.3, the server's OCSP information is carried in an extension in the CertificateEntry
+/// containing the associated certificate.
+///
+/// RFC 6066, Section 8.  Certificate Status Request
+///     struct {
+///         CertificateStatusType status_type;
+///         select (status_type) {
+///             case ocsp: OCSPResponse;
+///         } response;
+///     } CertificateStatus;
+///
+///     opaque OCSPResponse<1..2^24-1>;
+fn parse_certificate_status(data: &[u8]) -> Result<&[u8], TlsError> {
+    const STATUS_TYPE_OCSP: u8 = 1;
+    let mut buf = ParseBuffer::new(data);
+    if buf.read_u8()? != STATUS_TYPE_OCSP {
+        return Err(TlsError::DecodeError);
+    }
+    let len = buf.read_u24()?;
+    let response = buf.slice(len as usize)?;
+    if response.is_empty() || !buf.is_empty() {
+        return Err(TlsError::DecodeError);
+    }
+    Ok(response.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ocsp_response() {
+        // The OCSP response of the end-entity certificate, followed by its issuer
+        let data = [
+            0, 0, 0, 25, 0, 0, 3, 1, 2, 3, 0, 10, 0, 5, 0, 6, 1, 0, 0, 2, 0xAA, 0xBB, 0, 0, 2, 4,
+            5, 0, 0,
+        ];
+        let certificate = CertificateRef::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert_eq!(2, certificate.entries.len());
+        assert_eq!(Some(&[0xAA, 0xBB][..]), certificate.ocsp_response);
+
+        // Only OCSP responses of at least one byte are defined
+        assert!(parse_certificate_status(&[1, 0, 0, 0]).is_err());
+        assert!(parse_certificate_status(&[2, 0, 0, 1, 0xAA]).is_err());
+    }
+
+    #[test]
+    fn test_encode_chain() {
+        let mut certificate = CertificateRef::with_context(&[7]);
+        certificate
+            .add(CertificateEntryRef::X509(&[1, 2, 3]))
+            .unwrap();
+        certificate.add(CertificateEntryRef::X509(&[4, 5])).unwrap();
+
+        let mut data = [0; 32];
+        let mut buf = CryptoBuffer::wrap(&mut data);
+        certificate.encode(&mut buf).unwrap();
+        assert_eq!(
+            &[1, 7, 0, 0, 15, 0, 0, 3, 1, 2, 3, 0, 0, 0, 0, 2, 4, 5, 0, 0],
+            buf.as_slice()
+        );
+
+        let len = buf.len();
+        let parsed = CertificateRef::parse(&mut ParseBuffer::new(&data[..len])).unwrap();
+        assert_eq!(&[7], parsed.request_context);
+        assert!(matches!(
+            parsed.entries.as_slice(),
+            [
+                CertificateEntryRef::X509(&[1, 2, 3]),
+                CertificateEntryRef::X509(&[4, 5])
+            ]
+        ));
+    }
+}
@@ -1,6 +1,8 @@
+use crate::config::Certificate;
 use crate::extensions::server::ServerExtension;
-use crate::extensions::ExtensionType;
+use crate::extensions::{ExtensionType, ExtensionTypes};
 use crate::parse_buffer::ParseBuffer;
+use crate::signature_schemes::SignatureScheme;
 use crate::TlsError;
 use heapless::Vec;
 
@@ -8,6 +10,15 @@ use heapless::Vec;
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CertificateRequestRef<'a> {
     pub(crate) request_context: &'a [u8],
+    /// The encoded signature schemes accepted by the server.
+    pub(crate) signature_algorithms: Option<&'a [u8]>,
+    /// The encoded distinguished names of the certificate authorities accepted by the server.
+    certificate_authorities: Option<&'a [u8]>,
+    /// The encoded OID filters the client certificate must match.
+    oid_filters: Option<&'a [u8]>,
+    pub(crate) extension_types: ExtensionTypes,
+    /// The validated extensions, without the length prefix.
+    pub(crate) extensions: &'a [u8],
 }
 
 impl<'a> CertificateRequestRef<'a> {
@@ -16,6 +27,8 @@ impl<'a> CertificateRequestRef<'a> {
         ExtensionType::StatusRequest,
         ExtensionType::SignatureAlgorithms,
         ExtensionType::SignedCertificateTimestamp,
+        ExtensionType::CompressCertificate,
+        ExtensionType::DelegatedCredential,
         ExtensionType::CertificateAuthorities,
         ExtensionType::OidFilters,
         ExtensionType::SignatureAlgorithmsCert,
@@ -29,24 +42,121 @@ impl<'a> CertificateRequestRef<'a> {
             .slice(request_context_len as usize)
             .map_err(|_| TlsError::InvalidCertificateRequest)?;
 
-        let _extensions_length = buf
-            .read_u16()
-            .map_err(|_| TlsError::InvalidExtensionsLength)?;
-        //info!("sh 5 {}", extensions_length);
-
-        // Validate extensions
-        ServerExtension::parse_vector::<6>(buf, Self::ALLOWED_EXTENSIONS)?;
+        let mut signature_algorithms = None;
+        let mut certificate_authorities = None;
+        let mut oid_filters = None;
+        let extensions = ServerExtension::parse_each(buf, Self::ALLOWED_EXTENSIONS, |extension| {
+            // Section 4.3.2.  Certificate Request
+            // Clients MUST ignore unrecognized extensions.
+            match extension {
+                ServerExtension::SignatureAlgorithms(schemes) => {
+                    signature_algorithms = Some(schemes);
+                }
+                ServerExtension::CertificateAuthorities(authorities) => {
+                    certificate_authorities = Some(authorities);
+                }
+                ServerExtension::OidFilters(filters) => {
+                    oid_filters = Some(filters);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
 
         Ok(Self {
             request_context: request_context.as_slice(),
+            signature_algorithms,
+            certificate_authorities,
+            oid_filters,
+            extension_types: ExtensionTypes::of(extensions),
+            extensions,
+        })
+    }
+
+    /// Returns whether the signature_algorithms extension names `scheme`.
+    pub(crate) fn accepts_signature_scheme(&self, scheme: SignatureScheme) -> bool {
+        self.signature_algorithms.map_or(false, |schemes| {
+            schemes
+                .chunks_exact(2)
+                .any(|s| matches!(s, &[a, b] if u16::from_be_bytes([a, b]) == scheme as u16))
+        })
+    }
+
+    /// Returns whether the chain is accepted by the server, see [`Self::accepts_issuer`] and
+    /// [`Self::accepts_extensions`].
+    pub(crate) fn accepts(&self, cert_chain: &[Certificate]) -> bool {
+        self.accepts_issuer(cert_chain) && self.accepts_extensions(cert_chain)
+    }
+
+    /// Returns whether a certificate of the chain is issued by one of the certificate authorities
+    /// accepted by the server, or if the server does not name them.
+    fn accepts_issuer(&self, cert_chain: &[Certificate]) -> bool {
+        let authorities = match self.certificate_authorities {
+            Some(authorities) => authorities,
+            None => return true,
+        };
+
+        cert_chain.iter().any(|cert| match cert {
+            Certificate::X509(der) => match crate::x509::issuer(der) {
+                Ok(issuer) => names(authorities).any(|name| name == issuer),
+                Err(_) => false,
+            },
+            Certificate::RawPublicKey(_) => false,
         })
     }
+
+    /// Returns whether the end-entity certificate of the chain matches the OID filters of the
+    /// server, if any.
+    ///
+    /// Section 4.2.5.  OID Filters
+    /// If the server has included a non-empty filters list, the client certificate included in
+    /// the response MUST contain all of the specified extension OIDs that the client recognizes.
+    fn accepts_extensions(&self, cert_chain: &[Certificate]) -> bool {
+        let mut filters = match self.oid_filters {
+            Some(oid_filters) => filters(oid_filters).peekable(),
+            None => return true,
+        };
+        if filters.peek().is_none() {
+            return true;
+        }
+
+        match cert_chain.first() {
+            Some(Certificate::X509(der)) => filters.all(|(oid, values)| {
+                crate::x509::matches_oid_filter(der, oid, values).unwrap_or(false)
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// Iterates over the distinguished names of a certificate_authorities extension.
+fn names(authorities: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut buf = ParseBuffer::new(authorities);
+    core::iter::from_fn(move || {
+        let len = buf.read_u16().ok()?;
+        buf.slice(len as usize).ok().map(|name| name.as_slice())
+    })
+}
+
+/// Iterates over the certificate_extension_oid and the certificate_extension_values of an
+/// oid_filters extension.
+fn filters(oid_filters: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut buf = ParseBuffer::new(oid_filters);
+    core::iter::from_fn(move || {
+        let oid_len = buf.read_u8().ok()?;
+        let oid = buf.slice(oid_len as usize).ok()?.as_slice();
+        let values_len = buf.read_u16().ok()?;
+        Some((oid, buf.slice(values_len as usize).ok()?.as_slice()))
+    })
 }
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct CertificateRequest {
     pub(crate) request_context: Vec<u8, 256>,
+    /// The index of the client identity to authenticate with, see
+    /// [`TlsConfig::with_client_identities`](crate::config::TlsConfig::with_client_identities).
+    pub(crate) identity: Option<usize>,
 }
 
 impl<'a> TryFrom<CertificateRequestRef<'a>> for CertificateRequest {
@@ -56,6 +166,95 @@ impl<'a> TryFrom<CertificateRequestRef<'a>> for CertificateRequest {
         request_context
             .extend_from_slice(cert.request_context)
             .map_err(|_| TlsError::InsufficientSpace)?;
-        Ok(Self { request_context })
+        Ok(Self {
+            request_context,
+            identity: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn certificate_request(authorities: &[&[u8]]) -> Vec<u8> {
+        let mut names = Vec::new();
+        for name in authorities {
+            names.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            names.extend_from_slice(name);
+        }
+
+        let mut extensions = Vec::new();
+        if !authorities.is_empty() {
+            extensions.extend_from_slice(&[0, 47]);
+            extensions.extend_from_slice(&(names.len() as u16 + 2).to_be_bytes());
+            extensions.extend_from_slice(&(names.len() as u16).to_be_bytes());
+            extensions.extend_from_slice(&names);
+        }
+        // signature_algorithms, with ecdsa_secp256r1_sha256
+        extensions.extend_from_slice(&[0, 13, 0, 4, 0, 2, 4, 3]);
+
+        let mut data = Vec::from([1, 0xaa]);
+        data.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        data.extend_from_slice(&extensions);
+        data
+    }
+
+    #[test]
+    fn test_accepts_certificate_authorities() {
+        let device = pem_parser::pem_to_der(include_str!("../../tests/data/chain/client-cert.pem"));
+        let factory = pem_parser::pem_to_der(include_str!("../../tests/data/client-cert.pem"));
+        let intermediate = crate::x509::issuer(&device).unwrap();
+
+        let data = certificate_request(&[b"unknown", intermediate]);
+        let request = CertificateRequestRef::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert_eq!(&[0xaa], request.request_context);
+        assert!(request.accepts_signature_scheme(SignatureScheme::EcdsaSecp256r1Sha256));
+        assert!(!request.accepts_signature_scheme(SignatureScheme::EcdsaSecp384r1Sha384));
+        assert!(request.accepts(&[Certificate::X509(&device)]));
+        assert!(!request.accepts(&[Certificate::X509(&factory)]));
+        assert!(!request.accepts(&[]));
+
+        let data = certificate_request(&[]);
+        let request = CertificateRequestRef::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert!(request.accepts(&[Certificate::X509(&factory)]));
+    }
+
+    #[test]
+    fn test_accepts_oid_filters() {
+        let device = pem_parser::pem_to_der(include_str!("../../tests/data/chain/client-cert.pem"));
+        let factory = pem_parser::pem_to_der(include_str!("../../tests/data/client-cert.pem"));
+
+        // The id-ce-extKeyUsage filter with clientAuth, and an unrecognized one
+        let mut filters = Vec::from([3, 0x55, 0x1D, 0x25, 0, 12]);
+        filters.extend_from_slice(&[0x30, 0x0A, 0x06, 0x08, 0x2B, 0x06, 0x01, 0x05]);
+        filters.extend_from_slice(&[0x05, 0x07, 0x03, 0x02]);
+        filters.extend_from_slice(&[1, 0x2A, 0, 1, 0xFF]);
+
+        let mut data = Vec::from([0, 0, filters.len() as u8 + 6, 0, 48, 0]);
+        data.push(filters.len() as u8 + 2);
+        data.extend_from_slice(&[0, filters.len() as u8]);
+        data.extend_from_slice(&filters);
+        let request = CertificateRequestRef::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert!(request.accepts(&[Certificate::X509(&device)]));
+        assert!(!request.accepts(&[Certificate::X509(&factory)]));
+        assert!(!request.accepts(&[]));
+
+        // An empty list does not filter the certificates
+        let data = [0, 0, 6, 0, 48, 0, 2, 0, 0];
+        let request = CertificateRequestRef::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert!(request.accepts(&[Certificate::X509(&factory)]));
+
+        let data = [0, 0, 7, 0, 48, 0, 3, 0, 1, 0];
+        assert!(CertificateRequestRef::parse(&mut ParseBuffer::new(&data)).is_err());
+    }
+
+    #[test]
+    fn test_empty_certificate_authorities() {
+        let data = [0, 0, 6, 0, 47, 0, 2, 0, 0];
+        assert!(CertificateRequestRef::parse(&mut ParseBuffer::new(&data)).is_err());
     }
 }
@@ -1,3 +1,4 @@
+use crate::buffer::CryptoBuffer;
 use crate::parse_buffer::ParseBuffer;
 use crate::signature_schemes::SignatureScheme;
 use crate::TlsError;
@@ -27,4 +28,9 @@ impl<'a> CertificateVerify<'a> {
             signature: signature.as_slice(),
         })
     }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.push_u16(self.signature_scheme as u16)?;
+        buf.with_u16_length(|buf| buf.extend_from_slice(self.signature))
+    }
 }
@@ -1,17 +1,79 @@
+#[cfg(feature = "ech")]
+use digest::Digest;
 use digest::OutputSizeUser;
-use heapless::Vec;
-use p256::ecdh::EphemeralSecret;
-use p256::elliptic_curve::rand_core::{CryptoRng, RngCore};
-use p256::EncodedPoint;
 
 use crate::buffer::*;
-use crate::config::{TlsCipherSuite, TlsConfig};
-use crate::extensions::client::{ClientExtension, PskKeyExchangeMode};
-use crate::extensions::common::{KeyShare, KeyShareEntry};
+use crate::certificate_types::CertificateType;
+use crate::config::{TlsCipherSuite, TlsConfig, MAX_KEY_SHARES};
+use crate::crypto_provider::KeyExchange;
+#[cfg(feature = "ech")]
+use crate::ech::EchState;
+use crate::extensions::client::ClientExtension;
+use crate::extensions::common::KeyShareEntry;
+use crate::extensions::ExtensionType;
+use crate::grease::Grease;
+use crate::handshake::server_hello::HelloRetryRequest;
+#[cfg(feature = "ech")]
+use crate::handshake::HandshakeType;
 use crate::handshake::{Random, LEGACY_VERSION};
+use crate::key_exchange::PublicKey;
 use crate::named_groups::NamedGroup;
-use crate::supported_versions::TLS13;
+use crate::supported_versions::{TLS12, TLS13};
 use crate::TlsError;
+use heapless::Vec;
+
+/// The msg_type and length of a handshake message.
+const HANDSHAKE_HEADER_LEN: usize = 4;
+
+/// The extension_type and length of an extension.
+const EXTENSION_HEADER_LEN: usize = 4;
+
+/// The order of the extensions which are not given by [`TlsConfig::with_extension_order`].
+const DEFAULT_EXTENSION_ORDER: [ExtensionType; 22] = [
+    ExtensionType::SupportedVersions,
+    ExtensionType::SignatureAlgorithms,
+    ExtensionType::SignatureAlgorithmsCert,
+    ExtensionType::MaxFragmentLength,
+    ExtensionType::Heartbeat,
+    ExtensionType::StatusRequest,
+    ExtensionType::DelegatedCredential,
+    ExtensionType::SignedCertificateTimestamp,
+    ExtensionType::ClientCertificateType,
+    ExtensionType::ServerCertificateType,
+    ExtensionType::CompressCertificate,
+    ExtensionType::CertificateAuthorities,
+    ExtensionType::SupportedGroups,
+    ExtensionType::EcPointFormats,
+    ExtensionType::ExtendedMasterSecret,
+    ExtensionType::PskKeyExchangeModes,
+    ExtensionType::KeyShare,
+    ExtensionType::ServerName,
+    ExtensionType::ApplicationLayerProtocolNegotiation,
+    ExtensionType::Cookie,
+    ExtensionType::TicketRequest,
+    ExtensionType::EarlyData,
+];
+
+/// The extensions which reveal the application or the backend server, and are only sent in the
+/// ClientHelloInner with ECH, along with the custom extensions of the configuration.
+const INNER_ONLY_EXTENSIONS: [ExtensionType; 2] = [
+    ExtensionType::ApplicationLayerProtocolNegotiation,
+    ExtensionType::CertificateAuthorities,
+];
+
+/// The ClientHello being encoded, which differs with Encrypted Client Hello.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variant {
+    /// The ClientHello sent on the wire, which is the ClientHelloOuter with ECH.
+    Outer,
+    /// The ClientHelloInner, as it appears in the transcript.
+    #[cfg(feature = "ech")]
+    Inner,
+    /// The EncodedClientHelloInner without its padding, which is the ClientHelloInner with an
+    /// empty legacy_session_id.
+    #[cfg(feature = "ech")]
+    EncodedInner,
+}
 
 pub struct ClientHello<'config, CipherSuite>
 where
@@ -19,91 +81,222 @@ where
 {
     pub(crate) config: &'config TlsConfig<'config, CipherSuite>,
     random: Random,
-    pub(crate) secret: EphemeralSecret,
+    session_id: Option<Random>,
+    grease: Option<Grease>,
+    key_shares: Vec<(NamedGroup, PublicKey), MAX_KEY_SHARES>,
+    retry: Option<&'config HelloRetryRequest>,
+    /// The ECH state and the public name of the ECHConfig, when the ClientHello is encrypted.
+    #[cfg(feature = "ech")]
+    ech: Option<(&'config EchState, &'config str)>,
 }
 
 impl<'config, CipherSuite> ClientHello<'config, CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub fn new<RNG>(config: &'config TlsConfig<'config, CipherSuite>, rng: &mut RNG) -> Self
+    /// Creates a ClientHello, or the second ClientHello in response to a HelloRetryRequest,
+    /// which must have the same random, legacy_session_id and GREASE values as the first one.
+    pub fn new<Secret>(
+        config: &'config TlsConfig<'config, CipherSuite>,
+        secrets: &[Secret],
+        random: Random,
+        session_id: Option<Random>,
+        grease: Option<Grease>,
+        retry: Option<&'config HelloRetryRequest>,
+    ) -> Result<Self, TlsError>
     where
-        RNG: CryptoRng + RngCore,
+        Secret: KeyExchange,
     {
-        let mut random = [0; 32];
-        rng.fill_bytes(&mut random);
+        let mut key_shares = Vec::new();
+        for secret in secrets {
+            key_shares
+                .push((secret.group(), secret.public_key()?))
+                .map_err(|_| TlsError::InvalidKeyShare)?;
+        }
 
-        Self {
+        Ok(Self {
             config,
             random,
-            secret: EphemeralSecret::random(rng),
+            session_id,
+            grease,
+            key_shares,
+            retry,
+            #[cfg(feature = "ech")]
+            ech: None,
+        })
+    }
+
+    /// Encrypts this ClientHello as the ClientHelloInner of Encrypted Client Hello, in a
+    /// ClientHelloOuter for the public name of the ECHConfig of the configuration.
+    #[cfg(feature = "ech")]
+    pub(crate) fn with_ech(mut self, ech: &'config EchState) -> Result<Self, TlsError> {
+        let config = self.config.ech_config()?.ok_or(TlsError::InternalError)?;
+        self.ech = Some((ech, config.public_name));
+        Ok(self)
+    }
+
+    /// Whether TLS 1.2 is offered along with TLS 1.3.
+    fn offers_tls12(&self) -> bool {
+        #[cfg(feature = "tls12")]
+        {
+            self.config.offers_tls12()
+        }
+        #[cfg(not(feature = "tls12"))]
+        {
+            false
         }
     }
 
     pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
-        let public_key = EncodedPoint::from(&self.secret.public_key());
-        let public_key = public_key.as_ref();
+        self.encode_variant(buf, Variant::Outer)
+    }
 
+    fn encode_variant(&self, buf: &mut CryptoBuffer<'_>, variant: Variant) -> Result<(), TlsError> {
+        let start = buf.len();
+        let (random, server_name) = self.random_and_server_name(variant);
         buf.push_u16(LEGACY_VERSION)
             .map_err(|_| TlsError::EncodeError)?;
-        buf.extend_from_slice(&self.random)
+        buf.extend_from_slice(random)
             .map_err(|_| TlsError::EncodeError)?;
 
-        // session id (empty)
-        buf.push(0).map_err(|_| TlsError::EncodeError)?;
+        // legacy_session_id, empty unless in middlebox compatibility mode
+        //
+        // draft-ietf-tls-esni, Section 5.1.  Encoding the ClientHelloInner
+        // the legacy_session_id field of the EncodedClientHelloInner is empty, and the server
+        // copies it from the ClientHelloOuter.
+        #[cfg(feature = "ech")]
+        let session_id = self.session_id.filter(|_| variant != Variant::EncodedInner);
+        #[cfg(not(feature = "ech"))]
+        let session_id = self.session_id;
+        buf.with_u8_length(|buf| match &session_id {
+            Some(session_id) => buf.extend_from_slice(session_id),
+            None => Ok(()),
+        })
+        .map_err(|_| TlsError::EncodeError)?;
 
         // cipher suites (2+)
         //buf.extend_from_slice(&((self.config.cipher_suites.len() * 2) as u16).to_be_bytes());
         //for c in self.config.cipher_suites.iter() {
         //buf.extend_from_slice(&(*c as u16).to_be_bytes());
         //}
-        buf.push_u16(2).map_err(|_| TlsError::EncodeError)?;
-        buf.push_u16(CipherSuite::CODE_POINT)
-            .map_err(|_| TlsError::EncodeError)?;
+        buf.with_u16_length(|buf| {
+            if let Some(grease) = &self.grease {
+                buf.push_u16(grease.cipher_suite())?;
+            }
+            buf.push_u16(CipherSuite::CODE_POINT)?;
+
+            #[cfg(feature = "tls12")]
+            if let Some(code_point) = CipherSuite::TLS12_CODE_POINT.filter(|_| self.offers_tls12())
+            {
+                buf.push_u16(code_point)?;
+                // RFC 5746, Section 3.3.  Renegotiation Protection Request Signaling Cipher Suite
+                // Value
+                // This SCSV is not a true cipher suite (it does not correspond to any valid set of
+                // algorithms) and cannot be negotiated.
+                buf.push_u16(crate::tls12::EMPTY_RENEGOTIATION_INFO_SCSV)?;
+            }
+            Ok(())
+        })
+        .map_err(|_| TlsError::EncodeError)?;
 
         // compression methods, 1 byte of 0
         buf.push(1).map_err(|_| TlsError::EncodeError)?;
         buf.push(0).map_err(|_| TlsError::EncodeError)?;
 
+        // The ClientHelloOuter is only meant for the client-facing server, and carries the public
+        // name instead of the name of the server, without the extensions revealing the backend
+        // server or the application.
+        #[cfg(feature = "ech")]
+        let hides_inner = variant == Variant::Outer && self.ech.is_some();
+        #[cfg(not(feature = "ech"))]
+        let hides_inner = false;
+
         // extensions (1+)
         buf.with_u16_length(|buf| {
-            // Section 4.2.1.  Supported Versions
-            // Implementations of this specification MUST send this extension in the
-            // ClientHello containing all versions of TLS which they are prepared to
-            // negotiate
-            ClientExtension::SupportedVersions {
-                versions: Vec::from_slice(&[TLS13]).unwrap(),
+            if let Some(grease) = &self.grease {
+                grease.encode_extension(buf)?;
             }
-            .encode(buf)?;
 
-            ClientExtension::SignatureAlgorithms {
-                supported_signature_algorithms: self.config.signature_schemes.clone(),
+            // The configured order comes first, then the extensions it leaves out
+            let order = self.config.extension_order;
+            let configured = order
+                .iter()
+                .enumerate()
+                .filter(|(i, extension_type)| !order.iter().take(*i).any(|t| t == *extension_type))
+                .map(|(_, extension_type)| extension_type);
+            let remaining = DEFAULT_EXTENSION_ORDER
+                .iter()
+                .filter(|extension_type| !order.contains(extension_type));
+            for extension_type in configured.chain(remaining).filter(|extension_type| {
+                !hides_inner || !INNER_ONLY_EXTENSIONS.contains(extension_type)
+            }) {
+                self.encode_extension(*extension_type, server_name, buf)?;
             }
-            .encode(buf)?;
 
-            if let Some(max_fragment_length) = self.config.max_fragment_length {
-                ClientExtension::MaxFragmentLength(max_fragment_length).encode(buf)?;
+            let custom_extensions = if hides_inner {
+                &[][..]
+            } else {
+                self.config.custom_extensions
+            };
+            for (extension_type, data) in custom_extensions {
+                buf.push_u16(*extension_type)
+                    .map_err(|_| TlsError::EncodeError)?;
+                buf.with_u16_length(|buf| buf.extend_from_slice(data))
+                    .map_err(|_| TlsError::EncodeError)?;
             }
 
-            ClientExtension::SupportedGroups {
-                supported_groups: self.config.named_groups.clone(),
+            if let Some(grease) = &self.grease {
+                grease.encode_last_extension(buf)?;
             }
-            .encode(buf)?;
 
-            ClientExtension::PskKeyExchangeModes {
-                modes: Vec::from_slice(&[PskKeyExchangeMode::PskDheKe]).unwrap(),
-            }
-            .encode(buf)?;
+            let hash_size = <CipherSuite::Hash as OutputSizeUser>::output_size();
+            let pre_shared_key = if let Some(ticket) = self.config.session_ticket {
+                Some(ClientExtension::PreSharedKey {
+                    identities: [ticket.identity()].into_iter().collect(),
+                    obfuscated_ticket_age: ticket.obfuscated_age(),
+                    hash_size,
+                })
+            } else if let Some((_, identities)) = &self.config.psk {
+                // Section 4.2.11.  Pre-Shared Key Extension
+                // For identities established externally, an obfuscated_ticket_age of 0 SHOULD be
+                // used
+                Some(ClientExtension::PreSharedKey {
+                    identities: identities.clone(),
+                    obfuscated_ticket_age: 0,
+                    hash_size,
+                })
+            } else {
+                None
+            };
+
+            #[cfg(feature = "ech")]
+            let encrypted_client_hello = self.encrypted_client_hello(variant, buf)?;
+            #[cfg(not(feature = "ech"))]
+            let encrypted_client_hello = None;
 
-            ClientExtension::KeyShare(KeyShare(KeyShareEntry {
-                group: NamedGroup::Secp256r1,
-                opaque: public_key,
-            }))
-            .encode(buf)?;
+            // The ClientHelloInner is padded in the EncodedClientHelloInner instead
+            if let (Some(target), Variant::Outer) = (self.config.padding, variant) {
+                // The padding extension precedes the encrypted_client_hello and pre_shared_key
+                // extensions, whose length is known before their payload and binders are
+                // computed
+                let len = HANDSHAKE_HEADER_LEN
+                    + (buf.len() - start)
+                    + encrypted_client_hello
+                        .as_ref()
+                        .map_or(0, trailing_extension_len)
+                    + pre_shared_key.as_ref().map_or(0, trailing_extension_len)
+                    + EXTENSION_HEADER_LEN;
+                if len < target {
+                    ClientExtension::Padding(target - len).encode(buf)?;
+                }
+            }
 
-            if let Some(server_name) = self.config.server_name {
-                // TODO Add SNI extension
-                ClientExtension::ServerName { server_name }.encode(buf)?;
+            // draft-ietf-tls-esni, Section 6.1.1.  Encrypting the ClientHello
+            // The payload of the extension of the ClientHelloOuter is encrypted once the
+            // ClientHelloOuter is encoded, so the extension is the last one. ECH is not combined
+            // with the pre_shared_key extension.
+            if let Some(encrypted_client_hello) = encrypted_client_hello {
+                encrypted_client_hello.encode(buf)?;
             }
 
             // Section 4.2
@@ -111,12 +304,8 @@ where
             // extensions MAY appear in any order, with the exception of
             // "pre_shared_key" which MUST be the last extension in
             // the ClientHello.
-            if let Some((_, identities)) = &self.config.psk {
-                ClientExtension::PreSharedKey {
-                    identities: identities.clone(),
-                    hash_size: <CipherSuite::Hash as OutputSizeUser>::output_size(),
-                }
-                .encode(buf)?;
+            if let Some(pre_shared_key) = pre_shared_key {
+                pre_shared_key.encode(buf)?;
             }
 
             Ok(())
@@ -124,4 +313,318 @@ where
 
         Ok(())
     }
+
+    /// The random and the name sent in the server_name extension, which differ in the
+    /// ClientHelloInner and the ClientHelloOuter of Encrypted Client Hello.
+    fn random_and_server_name(&self, variant: Variant) -> (&Random, Option<&'config str>) {
+        match variant {
+            Variant::Outer => {
+                // draft-ietf-tls-esni, Section 6.1.  Offering ECH
+                // The ClientHelloOuter carries the public_name of the ECHConfig in its
+                // server_name extension.
+                #[cfg(feature = "ech")]
+                if let Some((_, public_name)) = self.ech {
+                    return (&self.random, Some(public_name));
+                }
+                (&self.random, self.config.sni())
+            }
+            #[cfg(feature = "ech")]
+            Variant::Inner | Variant::EncodedInner => (
+                self.ech.map_or(&self.random, |(ech, _)| &ech.inner_random),
+                self.config.sni(),
+            ),
+        }
+    }
+
+    /// Encodes the extension of the given type, if it is sent in this ClientHello. The GREASE,
+    /// padding, encrypted_client_hello and pre_shared_key extensions have a fixed position and
+    /// are not encoded here.
+    fn encode_extension(
+        &self,
+        extension_type: ExtensionType,
+        server_name: Option<&str>,
+        buf: &mut CryptoBuffer<'_>,
+    ) -> Result<(), TlsError> {
+        match extension_type {
+            // Section 4.2.1.  Supported Versions
+            // Implementations of this specification MUST send this extension in the
+            // ClientHello containing all versions of TLS which they are prepared to
+            // negotiate
+            ExtensionType::SupportedVersions => ClientExtension::SupportedVersions {
+                versions: self
+                    .grease
+                    .iter()
+                    .map(Grease::version)
+                    .chain([TLS13])
+                    .chain(self.offers_tls12().then_some(TLS12))
+                    .collect(),
+            }
+            .encode(buf),
+            ExtensionType::SignatureAlgorithms => ClientExtension::SignatureAlgorithms {
+                supported_signature_algorithms: self.config.signature_schemes.clone(),
+            }
+            .encode(buf),
+            // Section 4.2.3.  Signature Algorithms
+            // If no "signature_algorithms_cert" extension is present, then the
+            // "signature_algorithms" extension also applies to signatures appearing in
+            // certificates.
+            ExtensionType::SignatureAlgorithmsCert
+                if !self.config.signature_schemes_cert.is_empty() =>
+            {
+                ClientExtension::SignatureAlgorithmsCert {
+                    supported_signature_algorithms: self.config.signature_schemes_cert.clone(),
+                }
+                .encode(buf)
+            }
+            ExtensionType::MaxFragmentLength => match self.config.max_fragment_length {
+                Some(max_fragment_length) => {
+                    ClientExtension::MaxFragmentLength(max_fragment_length).encode(buf)
+                }
+                None => Ok(()),
+            },
+            // The heartbeats are only implemented for TLS 1.3
+            ExtensionType::Heartbeat if !self.offers_tls12() => match self.config.heartbeat {
+                Some(mode) => ClientExtension::Heartbeat(mode).encode(buf),
+                None => Ok(()),
+            },
+            ExtensionType::StatusRequest
+                if self.config.ocsp_verifier.is_some() && !self.offers_tls12() =>
+            {
+                ClientExtension::StatusRequest.encode(buf)
+            }
+            ExtensionType::ClientCertificateType if self.config.offers_client_raw_public_key() => {
+                ClientExtension::ClientCertificateType(&[CertificateType::RawPublicKey]).encode(buf)
+            }
+            ExtensionType::ServerCertificateType
+                if self.config.server_raw_public_key().is_some() =>
+            {
+                ClientExtension::ServerCertificateType(&[CertificateType::RawPublicKey]).encode(buf)
+            }
+            ExtensionType::DelegatedCredential
+                if !self.config.delegated_credential_schemes().is_empty() =>
+            {
+                ClientExtension::DelegatedCredential(self.config.delegated_credential_schemes())
+                    .encode(buf)
+            }
+            ExtensionType::CompressCertificate
+                if !self.config.certificate_compression_algorithms().is_empty() =>
+            {
+                ClientExtension::CompressCertificate(
+                    self.config.certificate_compression_algorithms(),
+                )
+                .encode(buf)
+            }
+            ExtensionType::CertificateAuthorities
+                if self.config.offers_certificate_authorities() =>
+            {
+                ClientExtension::CertificateAuthorities {
+                    ca: self.config.x509_ca(),
+                    roots: self.config.root_certificates,
+                }
+                .encode(buf)
+            }
+            ExtensionType::TicketRequest => match self.config.ticket_request {
+                Some((new_session_count, resumption_count)) => ClientExtension::TicketRequest {
+                    new_session_count,
+                    resumption_count,
+                }
+                .encode(buf),
+                None => Ok(()),
+            },
+            #[cfg(feature = "sct")]
+            ExtensionType::SignedCertificateTimestamp
+                if !self.config.ct_logs.is_empty() && !self.offers_tls12() =>
+            {
+                ClientExtension::SignedCertificateTimestamp.encode(buf)
+            }
+            ExtensionType::SupportedGroups => ClientExtension::SupportedGroups {
+                supported_groups: self.config.named_groups.clone(),
+                grease: self.grease,
+            }
+            .encode(buf),
+            // RFC 8422, Section 5.1.2.  Supported Point Formats Extension
+            // Implementations of this document MUST support the uncompressed format for all
+            // of their supported curves
+            #[cfg(feature = "tls12")]
+            ExtensionType::EcPointFormats if self.offers_tls12() => {
+                ClientExtension::EcPointFormats.encode(buf)
+            }
+            // RFC 7627, Section 5.1.  Extension Negotiation
+            // In its ClientHello message, a client implementing this document MUST send the
+            // "extended_master_secret" extension
+            #[cfg(feature = "tls12")]
+            ExtensionType::ExtendedMasterSecret if self.offers_tls12() => {
+                ClientExtension::ExtendedMasterSecret.encode(buf)
+            }
+            ExtensionType::PskKeyExchangeModes => ClientExtension::PskKeyExchangeModes {
+                modes: self.config.psk_key_exchange_modes.iter().copied().collect(),
+            }
+            .encode(buf),
+            ExtensionType::KeyShare => ClientExtension::KeyShare {
+                client_shares: self
+                    .key_shares
+                    .iter()
+                    .map(|(group, public_key)| KeyShareEntry {
+                        group: *group,
+                        opaque: public_key,
+                    })
+                    .collect(),
+                grease: self.grease,
+            }
+            .encode(buf),
+            ExtensionType::ServerName => match server_name {
+                Some(server_name) => {
+                    #[cfg(feature = "idna")]
+                    let mut idna_buf = [0; crate::idna::MAX_NAME_LEN];
+                    #[cfg(feature = "idna")]
+                    let server_name = crate::idna::to_ascii(server_name, &mut idna_buf)?;
+                    ClientExtension::ServerName { server_name }.encode(buf)
+                }
+                None => Ok(()),
+            },
+            ExtensionType::ApplicationLayerProtocolNegotiation
+                if !self.config.alpn_protocols.is_empty() =>
+            {
+                ClientExtension::ApplicationLayerProtocolNegotiation {
+                    protocols: self.config.alpn_protocols,
+                }
+                .encode(buf)
+            }
+            // Section 4.2.2.  Cookie
+            // When sending the new ClientHello, the client MUST copy the contents of the
+            // extension received in the HelloRetryRequest into a "cookie" extension in the
+            // new ClientHello.
+            ExtensionType::Cookie => match self.retry {
+                Some(retry) if !retry.cookie.is_empty() => {
+                    ClientExtension::Cookie(&retry.cookie).encode(buf)
+                }
+                _ => Ok(()),
+            },
+            // Section 4.2.10.  Early Data Indication
+            // A client MUST NOT include the "early_data" extension in its followup
+            // ClientHello.
+            ExtensionType::EarlyData if self.retry.is_none() && self.config.offers_early_data() => {
+                ClientExtension::EarlyData.encode(buf)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The encrypted_client_hello extension, of the ClientHelloOuter or of the ClientHelloInner.
+    #[cfg(feature = "ech")]
+    fn encrypted_client_hello(
+        &self,
+        variant: Variant,
+        buf: &mut CryptoBuffer<'_>,
+    ) -> Result<Option<ClientExtension<'config>>, TlsError> {
+        let Some((ech, _)) = self.ech else {
+            return Ok(None);
+        };
+        Ok(Some(match variant {
+            Variant::Outer => ClientExtension::EncryptedClientHelloOuter {
+                cipher_suite: ech.cipher_suite,
+                config_id: ech.config_id,
+                enc: &ech.enc,
+                payload_len: EchState::payload_len(self.encode_inner(ech, buf)?.len()),
+            },
+            Variant::Inner | Variant::EncodedInner => ClientExtension::EncryptedClientHelloInner,
+        }))
+    }
+
+    /// Encodes the EncodedClientHelloInner with its padding in the space left after `buf`.
+    #[cfg(feature = "ech")]
+    fn encode_inner<'b>(
+        &self,
+        ech: &EchState,
+        buf: &'b mut CryptoBuffer<'_>,
+    ) -> Result<&'b mut [u8], TlsError> {
+        let (_, spare) = buf.split_spare();
+        let mut inner = CryptoBuffer::wrap(spare);
+        self.encode_variant(&mut inner, Variant::EncodedInner)?;
+
+        // Section 6.1.3.  Recommended Padding Scheme
+        // the client pads the EncodedClientHelloInner with zeros
+        let padding = ech.padding(self.config.sni(), inner.len());
+        for _ in 0..padding {
+            inner.push(0).map_err(|_| TlsError::EncodeError)?;
+        }
+        let (spare, _, len) = inner.release();
+        spare.get_mut(..len).ok_or(TlsError::EncodeError)
+    }
+
+    /// draft-ietf-tls-esni, Section 6.1.1.  Encrypting the ClientHello
+    /// Encrypts the ClientHelloInner into the payload of the encrypted_client_hello extension at
+    /// the end of the ClientHelloOuter `buf`, using the space left after it, and returns the
+    /// transcript of the ClientHelloInner. Returns `None` without ECH.
+    #[cfg(feature = "ech")]
+    pub(crate) fn seal_inner(
+        &self,
+        buf: &mut CryptoBuffer<'_>,
+    ) -> Result<Option<CipherSuite::Hash>, TlsError> {
+        let Some((ech, _)) = self.ech else {
+            return Ok(None);
+        };
+
+        let (outer, spare) = buf.split_spare();
+        let mut spare = CryptoBuffer::wrap(spare);
+
+        // Section 6.1.5.  Handshaking with ClientHelloInner
+        // The transcript starts with the ClientHelloInner when the server accepts ECH.
+        let mut inner = CryptoBuffer::wrap(spare.split_spare().1);
+        inner
+            .push(HandshakeType::ClientHello as u8)
+            .map_err(|_| TlsError::EncodeError)?;
+        inner.with_u24_length(|buf| self.encode_variant(buf, Variant::Inner))?;
+        let transcript = <CipherSuite::Hash as Digest>::new().chain_update(inner.as_slice());
+
+        let inner = self.encode_inner(ech, &mut spare)?;
+        let payload_pos = outer
+            .len()
+            .checked_sub(EchState::payload_len(inner.len()))
+            .ok_or(TlsError::EncodeError)?;
+
+        // Section 5.2.  Authenticating the ClientHelloOuter
+        // The ClientHelloOuterAAD is the ClientHelloOuter with the payload replaced by zeros,
+        // without the handshake header.
+        let aad = outer
+            .get(HANDSHAKE_HEADER_LEN..)
+            .ok_or(TlsError::EncodeError)?;
+        let tag = ech.seal(aad, inner)?;
+        let (ciphertext, tag_out) = outer
+            .get_mut(payload_pos..)
+            .ok_or(TlsError::EncodeError)?
+            .split_at_mut(inner.len());
+        ciphertext.copy_from_slice(inner);
+        tag_out.copy_from_slice(&tag);
+        Ok(Some(transcript))
+    }
+}
+
+/// The encoded length of an extension following the padding extension: a pre_shared_key
+/// extension, binders included, or an encrypted_client_hello extension.
+fn trailing_extension_len(extension: &ClientExtension<'_>) -> usize {
+    match extension {
+        ClientExtension::PreSharedKey {
+            identities,
+            hash_size,
+            ..
+        } => {
+            // Each identity has a 2 bytes length and a 4 bytes obfuscated_ticket_age, and each
+            // binder a 1 byte length
+            let identities_len: usize = identities
+                .iter()
+                .map(|identity| 2 + identity.len() + 4)
+                .sum();
+            let binders_len = identities.len() * (1 + hash_size);
+            EXTENSION_HEADER_LEN + 2 + identities_len + 2 + binders_len
+        }
+        // The type, cipher_suite and config_id, then enc and payload with their lengths
+        #[cfg(feature = "ech")]
+        ClientExtension::EncryptedClientHelloOuter {
+            enc, payload_len, ..
+        } => EXTENSION_HEADER_LEN + 1 + 4 + 1 + 2 + enc.len() + 2 + payload_len,
+        #[cfg(feature = "ech")]
+        ClientExtension::EncryptedClientHelloInner => EXTENSION_HEADER_LEN + 1,
+        _ => 0,
+    }
 }
@@ -0,0 +1,186 @@
+//! RFC 8879.  TLS Certificate Compression
+//!
+//! The server may compress its Certificate message with an algorithm offered by the client in
+//! the compress_certificate extension. Only zlib is supported, behind the `zlib` feature, since
+//! the brotli and zstd decompressors need an allocator.
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::handshake::certificate::CertificateRef;
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+
+/// Section 3.  Negotiating Certificate Compression
+///     enum {
+///         zlib(1),
+///         brotli(2),
+///         zstd(3),
+///         (65535)
+///     } CertificateCompressionAlgorithm;
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CertificateCompressionAlgorithm {
+    Zlib = 1,
+    Brotli = 2,
+    Zstd = 3,
+}
+
+impl CertificateCompressionAlgorithm {
+    pub fn of(num: u16) -> Option<Self> {
+        match num {
+            1 => Some(Self::Zlib),
+            2 => Some(Self::Brotli),
+            3 => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// The algorithms offered when certificate compression is enabled.
+#[cfg(feature = "zlib")]
+pub(crate) const SUPPORTED_ALGORITHMS: &[CertificateCompressionAlgorithm] =
+    &[CertificateCompressionAlgorithm::Zlib];
+
+/// Section 4.  Compressed Certificate Message
+///     struct {
+///          CertificateCompressionAlgorithm algorithm;
+///          uint24 uncompressed_length;
+///          opaque compressed_certificate_message<1..2^24-1>;
+///     } CompressedCertificate;
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CompressedCertificate<'a> {
+    pub(crate) algorithm: Option<CertificateCompressionAlgorithm>,
+    uncompressed_length: u32,
+    #[cfg_attr(not(feature = "zlib"), allow(dead_code))]
+    data: &'a [u8],
+}
+
+impl<'a> CompressedCertificate<'a> {
+    pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<CompressedCertificate<'a>, TlsError> {
+        let algorithm = CertificateCompressionAlgorithm::of(buf.read_u16()?);
+        let uncompressed_length = buf.read_u24()?;
+        let len = buf.read_u24()?;
+        let data = buf.slice(len as usize)?.as_slice();
+        if data.is_empty() {
+            return Err(TlsError::DecodeError);
+        }
+        Ok(Self {
+            algorithm,
+            uncompressed_length,
+            data,
+        })
+    }
+
+    /// The length of the Certificate message once decompressed.
+    pub(crate) fn uncompressed_length(&self) -> usize {
+        self.uncompressed_length as usize
+    }
+
+    /// Decompresses the Certificate message into `buf`, which must hold
+    /// [`uncompressed_length`](Self::uncompressed_length) bytes, and parses it.
+    pub(crate) fn decompress<'b>(&self, buf: &'b mut [u8]) -> Result<CertificateRef<'b>, TlsError> {
+        let buf = buf
+            .get_mut(..self.uncompressed_length())
+            .ok_or(TlsError::InsufficientSpace)?;
+        let len = match self.algorithm {
+            #[cfg(feature = "zlib")]
+            Some(CertificateCompressionAlgorithm::Zlib) => {
+                miniz_oxide::inflate::decompress_slice_iter_to_slice(
+                    buf,
+                    core::iter::once(self.data),
+                    true,
+                    false,
+                )
+                .map_err(|_| bad_certificate())?
+            }
+            _ => {
+                warn!("Unsupported certificate compression algorithm");
+                return Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::IllegalParameter,
+                ));
+            }
+        };
+
+        // Section 4.  Compressed Certificate Message
+        // If the length of the resulting uncompressed certificate differs from the length
+        // indicated by uncompressed_length, the receiver MUST abort the connection with the
+        // "bad_certificate" alert.
+        if len != buf.len() {
+            return Err(bad_certificate());
+        }
+        CertificateRef::parse(&mut ParseBuffer::new(buf))
+    }
+}
+
+/// Section 4.  Compressed Certificate Message
+/// If the received CompressedCertificate message cannot be decompressed, the receiver MUST abort
+/// the connection with the "bad_certificate" alert.
+fn bad_certificate() -> TlsError {
+    warn!("The compressed certificate could not be decompressed");
+    TlsError::AbortHandshake(AlertLevel::Fatal, AlertDescription::BadCertificate)
+}
+
+#[cfg(all(test, feature = "zlib"))]
+mod tests {
+    use super::*;
+    use heapless::Vec;
+
+    // An empty request context and a single entry of 3 bytes without extensions
+    const CERTIFICATE: &[u8] = &[0, 0, 0, 8, 0, 0, 3, 1, 2, 3, 0, 0];
+
+    /// A zlib stream holding `data` in a single stored block.
+    fn zlib_stored(data: &[u8]) -> Vec<u8, 64> {
+        let (mut a, mut b) = (1u32, 0u32);
+        for byte in data {
+            a = (a + *byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        let len = data.len() as u16;
+        let mut stream = Vec::from_slice(&[0x78, 0x01, 0x01]).unwrap();
+        stream.extend_from_slice(&len.to_le_bytes()).unwrap();
+        stream.extend_from_slice(&(!len).to_le_bytes()).unwrap();
+        stream.extend_from_slice(data).unwrap();
+        stream
+            .extend_from_slice(&((b << 16) | a).to_be_bytes())
+            .unwrap();
+        stream
+    }
+
+    fn compressed_message(uncompressed_length: usize, data: &[u8]) -> Vec<u8, 64> {
+        let mut message = Vec::from_slice(&[0, 1]).unwrap();
+        message
+            .extend_from_slice(&(uncompressed_length as u32).to_be_bytes()[1..])
+            .unwrap();
+        message
+            .extend_from_slice(&(data.len() as u32).to_be_bytes()[1..])
+            .unwrap();
+        message.extend_from_slice(data).unwrap();
+        message
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = zlib_stored(CERTIFICATE);
+        let message = compressed_message(CERTIFICATE.len(), &data);
+        let compressed = CompressedCertificate::parse(&mut ParseBuffer::new(&message)).unwrap();
+        assert_eq!(
+            Some(CertificateCompressionAlgorithm::Zlib),
+            compressed.algorithm
+        );
+
+        let mut buf = [0; 32];
+        let certificate = compressed.decompress(&mut buf).unwrap();
+        assert_eq!(1, certificate.entries.len());
+
+        // The announced length must match
+        let message = compressed_message(CERTIFICATE.len() - 1, &data);
+        let compressed = CompressedCertificate::parse(&mut ParseBuffer::new(&message)).unwrap();
+        assert!(matches!(
+            compressed.decompress(&mut buf),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::BadCertificate
+            ))
+        ));
+    }
+}
@@ -1,14 +1,37 @@
+use crate::certificate_types::CertificateType;
 use crate::extensions::server::ServerExtension;
-use crate::extensions::ExtensionType;
+use crate::extensions::{ExtensionType, ExtensionTypes};
 
+use crate::heartbeat::HeartbeatMode;
+use crate::max_fragment_length::MaxFragmentLength;
 use crate::parse_buffer::ParseBuffer;
 use crate::TlsError;
-use heapless::Vec;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct EncryptedExtensions<'a> {
-    extensions: Vec<ServerExtension<'a>, 16>,
+    /// Whether the server accepted the early data of the client.
+    pub(crate) early_data: bool,
+    /// The application protocol selected by the server, if any.
+    pub(crate) alpn_protocol: Option<&'a [u8]>,
+    /// The maximum fragment length accepted by the server, if any.
+    pub(crate) max_fragment_length: Option<MaxFragmentLength>,
+    /// The number of tickets the server expects to send, if the ticket_request extension is
+    /// present.
+    pub(crate) expected_tickets: Option<u8>,
+    /// The heartbeat mode of the server, if the heartbeat extension is present.
+    pub(crate) heartbeat_mode: Option<HeartbeatMode>,
+    /// The type of the certificate of the client, if the client_certificate_type extension is
+    /// present.
+    pub(crate) client_certificate_type: Option<CertificateType>,
+    /// The type of the certificate of the server, if the server_certificate_type extension is
+    /// present.
+    pub(crate) server_certificate_type: Option<CertificateType>,
+    /// The retry configs of a server rejecting Encrypted Client Hello, if the
+    /// encrypted_client_hello extension is present.
+    pub(crate) ech_retry_configs: Option<&'a [u8]>,
+    /// The validated extensions, left in the record buffer.
+    raw_extensions: &'a [u8],
 }
 
 impl<'a> EncryptedExtensions<'a> {
@@ -23,10 +46,68 @@ impl<'a> EncryptedExtensions<'a> {
         ExtensionType::ClientCertificateType,
         ExtensionType::ServerCertificateType,
         ExtensionType::EarlyData,
+        ExtensionType::TicketRequest,
+        ExtensionType::EncryptedClientHello,
     ];
 
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<EncryptedExtensions<'a>, TlsError> {
-        ServerExtension::parse_vector(buf, Self::ALLOWED_EXTENSIONS)
-            .map(|extensions| Self { extensions })
+        let mut early_data = false;
+        let mut alpn_protocol = None;
+        let mut max_fragment_length = None;
+        let mut expected_tickets = None;
+        let mut heartbeat_mode = None;
+        let mut client_certificate_type = None;
+        let mut server_certificate_type = None;
+        let mut ech_retry_configs = None;
+        let raw_extensions =
+            ServerExtension::parse_each(buf, Self::ALLOWED_EXTENSIONS, |extension| {
+                match extension {
+                    ServerExtension::EarlyData(_) => early_data = true,
+                    ServerExtension::ApplicationLayerProtocolNegotiation(protocol) => {
+                        alpn_protocol.get_or_insert(protocol);
+                    }
+                    ServerExtension::MaxFragmentLength(length) => {
+                        max_fragment_length.get_or_insert(length);
+                    }
+                    ServerExtension::TicketRequest(count) => {
+                        expected_tickets.get_or_insert(count);
+                    }
+                    ServerExtension::Heartbeat(mode) => {
+                        heartbeat_mode.get_or_insert(mode);
+                    }
+                    ServerExtension::ClientCertificateType(certificate_type) => {
+                        client_certificate_type.get_or_insert(certificate_type);
+                    }
+                    ServerExtension::ServerCertificateType(certificate_type) => {
+                        server_certificate_type.get_or_insert(certificate_type);
+                    }
+                    ServerExtension::EncryptedClientHello(retry_configs) => {
+                        ech_retry_configs.get_or_insert(retry_configs);
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })?;
+
+        Ok(Self {
+            early_data,
+            alpn_protocol,
+            max_fragment_length,
+            expected_tickets,
+            heartbeat_mode,
+            client_certificate_type,
+            server_certificate_type,
+            ech_retry_configs,
+            raw_extensions,
+        })
+    }
+
+    pub fn extension_types(&self) -> ExtensionTypes {
+        ExtensionTypes::of(self.raw_extensions)
+    }
+
+    /// The validated extensions, without the length prefix.
+    pub fn extensions(&self) -> &'a [u8] {
+        self.raw_extensions
     }
 }
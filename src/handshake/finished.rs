@@ -45,7 +45,7 @@ impl<N: ArrayLength<u8>> Finished<N> {
     pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
         //let len = self.verify.len().to_be_bytes();
         //buf.extend_from_slice(&[len[1], len[2], len[3]]);
-        buf.extend_from_slice(&self.verify[..self.verify.len()])
+        buf.extend_from_slice(&self.verify)
             .map_err(|_| TlsError::EncodeError)?;
         Ok(())
     }
@@ -0,0 +1,55 @@
+use crate::buffer::CryptoBuffer;
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum KeyUpdateRequest {
+    UpdateNotRequested = 0,
+    UpdateRequested = 1,
+}
+
+/// Section 4.6.3.  Key and Initialization Vector Update
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeyUpdate {
+    pub request_update: KeyUpdateRequest,
+}
+
+impl KeyUpdate {
+    pub fn parse(buf: &mut ParseBuffer) -> Result<Self, TlsError> {
+        let request_update = match buf.read_u8().map_err(|_| TlsError::InvalidHandshake)? {
+            0 => KeyUpdateRequest::UpdateNotRequested,
+            1 => KeyUpdateRequest::UpdateRequested,
+            // If an implementation receives any other value, it MUST terminate the connection
+            // with an "illegal_parameter" alert.
+            _ => return Err(TlsError::InvalidHandshake),
+        };
+        Ok(Self { request_update })
+    }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        buf.push(self.request_update as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut data = [0; 1];
+        let mut buf = CryptoBuffer::wrap(&mut data);
+        KeyUpdate {
+            request_update: KeyUpdateRequest::UpdateRequested,
+        }
+        .encode(&mut buf)
+        .unwrap();
+        assert_eq!(&[1], buf.as_slice());
+
+        let update = KeyUpdate::parse(&mut ParseBuffer::new(&data)).unwrap();
+        assert_eq!(KeyUpdateRequest::UpdateRequested, update.request_update);
+        assert!(KeyUpdate::parse(&mut ParseBuffer::new(&[2])).is_err());
+    }
+}
@@ -1,3 +1,13 @@
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic
+    )
+)]
+
 use generic_array::ArrayLength;
 
 //use p256::elliptic_curve::AffinePoint;
@@ -7,8 +17,10 @@ use crate::handshake::certificate::CertificateRef;
 use crate::handshake::certificate_request::CertificateRequestRef;
 use crate::handshake::certificate_verify::CertificateVerify;
 use crate::handshake::client_hello::ClientHello;
+use crate::handshake::compressed_certificate::CompressedCertificate;
 use crate::handshake::encrypted_extensions::EncryptedExtensions;
 use crate::handshake::finished::Finished;
+use crate::handshake::key_update::KeyUpdate;
 use crate::handshake::new_session_ticket::NewSessionTicket;
 use crate::handshake::server_hello::ServerHello;
 use crate::key_schedule::HashOutputSize;
@@ -22,14 +34,16 @@ pub mod certificate;
 pub mod certificate_request;
 pub mod certificate_verify;
 pub mod client_hello;
+pub mod compressed_certificate;
 pub mod encrypted_extensions;
 pub mod finished;
+pub mod key_update;
 pub mod new_session_ticket;
 pub mod server_hello;
 
 const LEGACY_VERSION: u16 = 0x0303;
 
-type Random = [u8; 32];
+pub(crate) type Random = [u8; 32];
 
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -40,10 +54,15 @@ pub enum HandshakeType {
     EndOfEarlyData = 5,
     EncryptedExtensions = 8,
     Certificate = 11,
+    ServerKeyExchange = 12,
     CertificateRequest = 13,
+    ServerHelloDone = 14,
     CertificateVerify = 15,
+    ClientKeyExchange = 16,
+    ClientCertificateRequest = 17,
     Finished = 20,
     KeyUpdate = 24,
+    CompressedCertificate = 25,
     MessageHash = 254,
 }
 
@@ -56,10 +75,15 @@ impl HandshakeType {
             5 => Some(HandshakeType::EndOfEarlyData),
             8 => Some(HandshakeType::EncryptedExtensions),
             11 => Some(HandshakeType::Certificate),
+            12 => Some(HandshakeType::ServerKeyExchange),
             13 => Some(HandshakeType::CertificateRequest),
+            14 => Some(HandshakeType::ServerHelloDone),
             15 => Some(HandshakeType::CertificateVerify),
+            16 => Some(HandshakeType::ClientKeyExchange),
+            17 => Some(HandshakeType::ClientCertificateRequest),
             20 => Some(HandshakeType::Finished),
             24 => Some(HandshakeType::KeyUpdate),
+            25 => Some(HandshakeType::CompressedCertificate),
             254 => Some(HandshakeType::MessageHash),
             _ => None,
         }
@@ -72,8 +96,13 @@ where
     CipherSuite: TlsCipherSuite,
 {
     ClientCert(CertificateRef<'a>),
+    ClientCertVerify(CertificateVerify<'a>),
     ClientHello(ClientHello<'config, CipherSuite>),
+    EndOfEarlyData,
     Finished(Finished<HashOutputSize<CipherSuite>>),
+    KeyUpdate(KeyUpdate),
+    #[cfg(feature = "tls12")]
+    Tls12(crate::tls12::ClientMessage<'a>),
 }
 
 impl<'config, 'a, CipherSuite> ClientHandshake<'config, 'a, CipherSuite>
@@ -83,16 +112,26 @@ where
     fn handshake_type(&self) -> HandshakeType {
         match self {
             ClientHandshake::ClientHello(_) => HandshakeType::ClientHello,
+            ClientHandshake::EndOfEarlyData => HandshakeType::EndOfEarlyData,
             ClientHandshake::Finished(_) => HandshakeType::Finished,
             ClientHandshake::ClientCert(_) => HandshakeType::Certificate,
+            ClientHandshake::ClientCertVerify(_) => HandshakeType::CertificateVerify,
+            ClientHandshake::KeyUpdate(_) => HandshakeType::KeyUpdate,
+            #[cfg(feature = "tls12")]
+            ClientHandshake::Tls12(inner) => inner.handshake_type(),
         }
     }
 
     fn encode_inner(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
         match self {
             ClientHandshake::ClientHello(inner) => inner.encode(buf),
+            ClientHandshake::EndOfEarlyData => Ok(()),
             ClientHandshake::Finished(inner) => inner.encode(buf),
             ClientHandshake::ClientCert(inner) => inner.encode(buf),
+            ClientHandshake::ClientCertVerify(inner) => inner.encode(buf),
+            ClientHandshake::KeyUpdate(inner) => inner.encode(buf),
+            #[cfg(feature = "tls12")]
+            ClientHandshake::Tls12(inner) => inner.encode(buf),
         }
     }
 
@@ -109,9 +148,11 @@ pub enum ServerHandshake<'a, N: ArrayLength<u8>> {
     EncryptedExtensions(EncryptedExtensions<'a>),
     NewSessionTicket(NewSessionTicket<'a>),
     Certificate(CertificateRef<'a>),
+    CompressedCertificate(CompressedCertificate<'a>),
     CertificateRequest(CertificateRequestRef<'a>),
     CertificateVerify(CertificateVerify<'a>),
     Finished(Finished<N>),
+    KeyUpdate(KeyUpdate),
 }
 
 impl<'a, N: ArrayLength<u8>> ServerHandshake<'a, N> {
@@ -121,9 +162,11 @@ impl<'a, N: ArrayLength<u8>> ServerHandshake<'a, N> {
             ServerHandshake::EncryptedExtensions(_) => HandshakeType::EncryptedExtensions,
             ServerHandshake::NewSessionTicket(_) => HandshakeType::NewSessionTicket,
             ServerHandshake::Certificate(_) => HandshakeType::Certificate,
+            ServerHandshake::CompressedCertificate(_) => HandshakeType::CompressedCertificate,
             ServerHandshake::CertificateRequest(_) => HandshakeType::CertificateRequest,
             ServerHandshake::CertificateVerify(_) => HandshakeType::CertificateVerify,
             ServerHandshake::Finished(_) => HandshakeType::Finished,
+            ServerHandshake::KeyUpdate(_) => HandshakeType::KeyUpdate,
         }
     }
 }
@@ -134,10 +177,12 @@ impl<'a, N: ArrayLength<u8>> Debug for ServerHandshake<'a, N> {
             ServerHandshake::ServerHello(inner) => Debug::fmt(inner, f),
             ServerHandshake::EncryptedExtensions(inner) => Debug::fmt(inner, f),
             ServerHandshake::Certificate(inner) => Debug::fmt(inner, f),
+            ServerHandshake::CompressedCertificate(inner) => Debug::fmt(inner, f),
             ServerHandshake::CertificateRequest(inner) => Debug::fmt(inner, f),
             ServerHandshake::CertificateVerify(inner) => Debug::fmt(inner, f),
             ServerHandshake::Finished(inner) => Debug::fmt(inner, f),
             ServerHandshake::NewSessionTicket(inner) => Debug::fmt(inner, f),
+            ServerHandshake::KeyUpdate(inner) => Debug::fmt(inner, f),
         }
     }
 }
@@ -149,10 +194,12 @@ impl<'a, N: ArrayLength<u8>> defmt::Format for ServerHandshake<'a, N> {
             ServerHandshake::ServerHello(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::EncryptedExtensions(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::Certificate(inner) => defmt::write!(f, "{}", inner),
+            ServerHandshake::CompressedCertificate(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::CertificateRequest(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::CertificateVerify(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::Finished(inner) => defmt::write!(f, "{}", inner),
             ServerHandshake::NewSessionTicket(inner) => defmt::write!(f, "{}", inner),
+            ServerHandshake::KeyUpdate(inner) => defmt::write!(f, "{}", inner),
         }
     }
 }
@@ -162,21 +209,26 @@ impl<'a, N: ArrayLength<u8>> ServerHandshake<'a, N> {
         rx_buf: &'a mut [u8],
         digest: &mut D,
     ) -> Result<ServerHandshake<'a, N>, TlsError> {
-        let header = rx_buf.get(0..4).ok_or(TlsError::InvalidHandshake)?;
-        match HandshakeType::of(header[0]) {
+        let (header, body) = rx_buf.split_at(usize::min(4, rx_buf.len()));
+        let [handshake_type, length @ ..]: [u8; 4] =
+            header.try_into().map_err(|_| TlsError::InvalidHandshake)?;
+        match HandshakeType::of(handshake_type) {
             None => Err(TlsError::InvalidHandshake),
             Some(handshake_type) => {
-                let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+                let [a, b, c] = length;
+                let length = u32::from_be_bytes([0, a, b, c]) as usize;
                 match handshake_type {
                     HandshakeType::ServerHello => {
                         // info!("hash [{:x?}]", &header);
-                        digest.update(header);
-                        Ok(ServerHandshake::ServerHello(ServerHello::read(
-                            rx_buf
-                                .get(4..length + 4)
-                                .ok_or(TlsError::InvalidHandshake)?,
-                            digest,
-                        )?))
+                        if body.len() < length {
+                            return Err(TlsError::InvalidHandshake);
+                        }
+                        let (body, _coalesced) = body.split_at(length);
+                        #[allow(unused_mut)]
+                        let mut hello = ServerHello::read(header, body, digest)?;
+                        #[cfg(feature = "tls12")]
+                        hello.set_coalesced(_coalesced);
+                        Ok(ServerHandshake::ServerHello(hello))
                     }
                     _ => Err(TlsError::Unimplemented), /*
                                                        HandshakeType::ClientHello => Err(TlsError::Unimplemented),
@@ -220,6 +272,9 @@ impl<'a, N: ArrayLength<u8>> ServerHandshake<'a, N> {
             HandshakeType::Certificate => {
                 Ok(ServerHandshake::Certificate(CertificateRef::parse(buf)?))
             }
+            HandshakeType::CompressedCertificate => Ok(ServerHandshake::CompressedCertificate(
+                CompressedCertificate::parse(buf)?,
+            )),
 
             HandshakeType::CertificateRequest => Ok(ServerHandshake::CertificateRequest(
                 CertificateRequestRef::parse(buf)?,
@@ -232,7 +287,7 @@ impl<'a, N: ArrayLength<u8>> ServerHandshake<'a, N> {
                 buf,
                 content_len,
             )?)),
-            //HandshakeType::KeyUpdate => {}
+            HandshakeType::KeyUpdate => Ok(ServerHandshake::KeyUpdate(KeyUpdate::parse(buf)?)),
             //HandshakeType::MessageHash => {}
             t => {
                 warn!("Unimplemented handshake type: {:?}", t);
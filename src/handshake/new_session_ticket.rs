@@ -1,5 +1,3 @@
-use heapless::Vec;
-
 use crate::extensions::server::ServerExtension;
 use crate::extensions::ExtensionType;
 use crate::parse_buffer::ParseBuffer;
@@ -8,11 +6,14 @@ use crate::TlsError;
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct NewSessionTicket<'a> {
-    lifetime: u32,
-    age_add: u32,
-    nonce: &'a [u8],
-    ticket: &'a [u8],
-    extensions: Vec<ServerExtension<'a>, 16>,
+    pub(crate) lifetime: u32,
+    pub(crate) age_add: u32,
+    pub(crate) nonce: &'a [u8],
+    pub(crate) ticket: &'a [u8],
+    /// The amount of early data the server accepts when the ticket is used, 0 if none.
+    pub(crate) max_early_data_size: u32,
+    /// The validated extensions, left in the record buffer.
+    raw_extensions: &'a [u8],
 }
 
 impl<'a> NewSessionTicket<'a> {
@@ -33,14 +34,24 @@ impl<'a> NewSessionTicket<'a> {
             .slice(ticket_length as usize)
             .map_err(|_| TlsError::InvalidTicketLength)?;
 
-        let extensions = ServerExtension::parse_vector(buf, Self::ALLOWED_EXTENSIONS)?;
+        let mut max_early_data_size = 0;
+        let raw_extensions =
+            ServerExtension::parse_each(buf, Self::ALLOWED_EXTENSIONS, |extension| {
+                // Section 4.6.1.  New Session Ticket Message
+                // Clients MUST ignore unrecognized extensions.
+                if let ServerExtension::EarlyData(Some(size)) = extension {
+                    max_early_data_size = size;
+                }
+                Ok(())
+            })?;
 
         Ok(Self {
             lifetime,
             age_add,
             nonce: nonce.as_slice(),
             ticket: ticket.as_slice(),
-            extensions,
+            max_early_data_size,
+            raw_extensions,
         })
     }
 }
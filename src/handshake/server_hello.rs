@@ -1,24 +1,71 @@
-use heapless::Vec;
-
 use crate::cipher_suites::CipherSuite;
 use crate::crypto_engine::CryptoEngine;
+use crate::crypto_provider::KeyExchange;
 use crate::extensions::common::KeyShareEntry;
 use crate::extensions::server::ServerExtension;
-use crate::extensions::ExtensionType;
-use crate::handshake::Random;
+use crate::extensions::{ExtensionType, ExtensionTypes};
+use crate::handshake::{HandshakeType, Random};
+use crate::key_exchange::SharedSecret;
+#[cfg(feature = "tls12")]
+use crate::max_fragment_length::MaxFragmentLength;
+use crate::named_groups::NamedGroup;
 use crate::parse_buffer::ParseBuffer;
-use crate::TlsError;
-use p256::ecdh::{EphemeralSecret, SharedSecret};
-use p256::PublicKey;
+use crate::supported_versions::ProtocolVersion;
+use crate::{
+    alert::{AlertDescription, AlertLevel},
+    TlsError,
+};
+use heapless::Vec;
 use sha2::Digest;
 
+// Section 4.1.3.  Server Hello
+// For reasons of backward compatibility with middleboxes (see Appendix D.4), the
+// HelloRetryRequest message uses the same structure as the ServerHello, but with Random set to
+// the special value of the SHA-256 of "HelloRetryRequest"
+const HELLO_RETRY_REQUEST_RANDOM: Random = [
+    0xCF, 0x21, 0xAD, 0x74, 0xE5, 0x9A, 0x61, 0x11, 0xBE, 0x1D, 0x8C, 0x02, 0x1E, 0x65, 0xB8, 0x91,
+    0xC2, 0xA2, 0x11, 0x16, 0x7A, 0xBB, 0x8C, 0x5E, 0x07, 0x9E, 0x09, 0xE2, 0xC8, 0xA8, 0x33, 0x9C,
+];
+
+// Section 4.1.3.  Server Hello
+// If negotiating TLS 1.2, TLS 1.3 servers MUST set the last 8 bytes of their Random value to the
+// bytes: 44 4F 57 4E 47 52 44 01. If negotiating TLS 1.1 or below, TLS 1.3 servers MUST, and
+// TLS 1.2 servers SHOULD, set the last 8 bytes of their ServerHello.Random value to the bytes:
+// 44 4F 57 4E 47 52 44 00
+const DOWNGRADE_SENTINELS: [[u8; 8]; 2] = [
+    [0x44, 0x4F, 0x57, 0x4E, 0x47, 0x52, 0x44, 0x01],
+    [0x44, 0x4F, 0x57, 0x4E, 0x47, 0x52, 0x44, 0x00],
+];
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct ServerHello<'a> {
+    legacy_version: ProtocolVersion,
     random: Random,
     legacy_session_id_echo: &'a [u8],
     cipher_suite: CipherSuite,
-    extensions: Vec<ServerExtension<'a>, 16>,
+    selected_version: Option<ProtocolVersion>,
+    key_share: Option<KeyShareEntry<'a>>,
+    selected_identity: Option<u16>,
+    selected_group: Option<NamedGroup>,
+    cookie: Option<&'a [u8]>,
+    /// The application protocol selected by a TLS 1.2 server, which is sent in the
+    /// EncryptedExtensions with TLS 1.3.
+    #[cfg(feature = "tls12")]
+    alpn_protocol: Option<&'a [u8]>,
+    /// The maximum fragment length accepted by a TLS 1.2 server.
+    #[cfg(feature = "tls12")]
+    max_fragment_length: Option<MaxFragmentLength>,
+    extension_types: ExtensionTypes,
+    /// The validated extensions, without the length prefix.
+    extensions: &'a [u8],
+    /// The handshake messages following the ServerHello in the same record, which are only
+    /// processed by a TLS 1.2 handshake.
+    #[cfg(feature = "tls12")]
+    coalesced: &'a [u8],
+    /// The whole message, without the handshake header.
+    #[cfg(feature = "ech")]
+    raw: &'a [u8],
 }
 
 impl<'a> ServerHello<'a> {
@@ -30,17 +77,64 @@ impl<'a> ServerHello<'a> {
         ExtensionType::PostHandshakeAuth,
     ];
 
-    pub fn read<D: Digest>(buf: &'a [u8], digest: &mut D) -> Result<ServerHello<'a>, TlsError> {
+    // Source: https://www.rfc-editor.org/rfc/rfc8446#section-4.2 table, rows marked with HRR
+    const HELLO_RETRY_REQUEST_EXTENSIONS: &[ExtensionType] = &[
+        ExtensionType::KeyShare,
+        ExtensionType::SupportedVersions,
+        ExtensionType::Cookie,
+    ];
+
+    // RFC 5246, Section 7.4.1.4.  Hello Extensions
+    // An extension type MUST NOT appear in the ServerHello unless the same extension type appeared
+    // in the corresponding ClientHello.
+    const TLS12_EXTENSIONS: &[ExtensionType] = &[
+        ExtensionType::KeyShare,
+        ExtensionType::PreSharedKey,
+        ExtensionType::SupportedVersions,
+        ExtensionType::PostHandshakeAuth,
+        ExtensionType::ServerName,
+        ExtensionType::MaxFragmentLength,
+        ExtensionType::ApplicationLayerProtocolNegotiation,
+        ExtensionType::EcPointFormats,
+        ExtensionType::ExtendedMasterSecret,
+        ExtensionType::RenegotiationInfo,
+    ];
+
+    pub fn read<D: Digest>(
+        header: &[u8],
+        buf: &'a [u8],
+        digest: &mut D,
+    ) -> Result<ServerHello<'a>, TlsError> {
         //trace!("server hello hash [{:x?}]", &buf[..]);
+        let hello = Self::parse(&mut ParseBuffer::new(buf))?;
+
+        if hello.is_hello_retry_request() {
+            // Section 4.4.1.  The Transcript Hash
+            // When the server responds to a ClientHello with a HelloRetryRequest, the value of
+            // ClientHello1 is replaced with a special synthetic handshake message of handshake
+            // type "message_hash" containing Hash(ClientHello1).
+            let client_hello = core::mem::replace(digest, D::new()).finalize();
+            digest.update([
+                HandshakeType::MessageHash as u8,
+                0,
+                0,
+                client_hello.len() as u8,
+            ]);
+            digest.update(client_hello);
+        }
+
+        digest.update(header);
         digest.update(buf);
-        Self::parse(&mut ParseBuffer::new(buf))
+        Ok(hello)
     }
 
     pub fn parse(buf: &mut ParseBuffer<'a>) -> Result<ServerHello<'a>, TlsError> {
         //let mut buf = ParseBuffer::new(&buf[0..content_length]);
         //let mut buf = ParseBuffer::new(&buf);
+        #[cfg(feature = "ech")]
+        let raw = buf.as_slice();
 
-        let _version = buf.read_u16().map_err(|_| TlsError::InvalidHandshake)?;
+        let legacy_version = buf.read_u16().map_err(|_| TlsError::InvalidHandshake)?;
 
         let mut random = [0; 32];
         buf.fill(&mut random)?;
@@ -63,7 +157,86 @@ impl<'a> ServerHello<'a> {
         // skip compression method, it's 0.
         buf.read_u8()?;
 
-        let extensions = ServerExtension::parse_vector(buf, Self::ALLOWED_EXTENSIONS)?;
+        let allowed = if random == HELLO_RETRY_REQUEST_RANDOM {
+            Self::HELLO_RETRY_REQUEST_EXTENSIONS
+        } else if cfg!(feature = "tls12") {
+            // The extensions are checked again once the version is known
+            Self::TLS12_EXTENSIONS
+        } else {
+            Self::ALLOWED_EXTENSIONS
+        };
+
+        // Only the selected version, the key share, the selected PSK and the retry parameters
+        // are kept, rather than a copy of every extension
+        let mut selected_version = None;
+        let mut key_share = None;
+        let mut selected_identity = None;
+        let mut selected_group = None;
+        let mut cookie = None;
+        #[cfg(feature = "tls12")]
+        let mut alpn_protocol = None;
+        #[cfg(feature = "tls12")]
+        let mut max_fragment_length = None;
+        // RFC 5246, Section 7.4.1.4.  Hello Extensions
+        // The presence of extensions can be detected by determining whether there are bytes
+        // following the compression_method field at the end of the ServerHello.
+        let extensions = if cfg!(feature = "tls12") && buf.is_empty() {
+            &[][..]
+        } else {
+            ServerExtension::parse_each(buf, allowed, |extension| {
+                match extension {
+                    ServerExtension::SupportedVersion(version) => {
+                        selected_version.get_or_insert(version.selected_version);
+                    }
+                    ServerExtension::KeyShare(entry) => {
+                        key_share.get_or_insert(entry.0);
+                    }
+                    ServerExtension::PreSharedKey(identity) => {
+                        selected_identity.get_or_insert(identity);
+                    }
+                    ServerExtension::SelectedGroup(group) => {
+                        selected_group.get_or_insert(group);
+                    }
+                    ServerExtension::Cookie(data) => {
+                        cookie.get_or_insert(data);
+                    }
+                    #[cfg(feature = "tls12")]
+                    ServerExtension::ApplicationLayerProtocolNegotiation(protocol) => {
+                        alpn_protocol.get_or_insert(protocol);
+                    }
+                    #[cfg(feature = "tls12")]
+                    ServerExtension::MaxFragmentLength(length) => {
+                        max_fragment_length.get_or_insert(length);
+                    }
+                    // RFC 5746, Section 3.4.  Client Behavior: Initial Handshake
+                    // The client MUST then verify that the length of the "renegotiated_connection"
+                    // field is zero, and if it is not, MUST abort the handshake (by sending a fatal
+                    // handshake_failure alert).
+                    #[cfg(feature = "tls12")]
+                    ServerExtension::RenegotiationInfo(info) if !info.is_empty() => {
+                        return Err(TlsError::AbortHandshake(
+                            AlertLevel::Fatal,
+                            AlertDescription::HandshakeFailure,
+                        ));
+                    }
+                    _ => {}
+                }
+                Ok(())
+            })?
+        };
+
+        // Section 4.2.  Extensions
+        // The extensions which only exist in TLS 1.2 are not allowed when TLS 1.3 is selected
+        #[cfg(feature = "tls12")]
+        if selected_version.is_some() && random != HELLO_RETRY_REQUEST_RANDOM {
+            let mut extensions = ParseBuffer::new(extensions);
+            for extension in crate::extensions::server::ServerExtensionParserIterator::new(
+                &mut extensions,
+                Self::ALLOWED_EXTENSIONS,
+            ) {
+                extension?;
+            }
+        }
 
         // info!("server random {:x?}", random);
         // info!("server session-id {:x?}", session_id.as_slice());
@@ -71,37 +244,332 @@ impl<'a> ServerHello<'a> {
         // info!("server extensions {:?}", extensions);
 
         Ok(Self {
+            legacy_version,
             random,
             legacy_session_id_echo: session_id.as_slice(),
             cipher_suite,
+            selected_version,
+            key_share,
+            selected_identity,
+            selected_group,
+            cookie,
+            #[cfg(feature = "tls12")]
+            alpn_protocol,
+            #[cfg(feature = "tls12")]
+            max_fragment_length,
+            extension_types: ExtensionTypes::of(extensions),
             extensions,
+            #[cfg(feature = "tls12")]
+            coalesced: &[],
+            #[cfg(feature = "ech")]
+            raw,
         })
     }
 
+    /// The version of the ServerHello, which is the negotiated version when the server does not
+    /// send a supported_versions extension.
+    pub fn legacy_version(&self) -> ProtocolVersion {
+        self.legacy_version
+    }
+
+    pub fn random(&self) -> &Random {
+        &self.random
+    }
+
+    /// Whether the message is a HelloRetryRequest rather than a ServerHello.
+    pub fn is_hello_retry_request(&self) -> bool {
+        self.random == HELLO_RETRY_REQUEST_RANDOM
+    }
+
+    /// The legacy_session_id of the ClientHello, echoed by the server.
+    pub fn legacy_session_id_echo(&self) -> &[u8] {
+        self.legacy_session_id_echo
+    }
+
+    /// Whether the random ends with a downgrade protection sentinel, which means that the
+    /// handshake was downgraded to TLS 1.2 or below by an attacker.
+    pub fn is_downgrade(&self) -> bool {
+        DOWNGRADE_SENTINELS
+            .iter()
+            .any(|sentinel| self.random.ends_with(sentinel))
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    /// The version of the supported_versions extension, if any.
+    pub fn selected_version(&self) -> Option<ProtocolVersion> {
+        self.selected_version
+    }
+
+    pub fn extension_types(&self) -> ExtensionTypes {
+        self.extension_types
+    }
+
+    /// The validated extensions, without the length prefix.
+    pub fn extensions(&self) -> &'a [u8] {
+        self.extensions
+    }
+
+    /// The handshake messages which followed the ServerHello in its record.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn coalesced(&self) -> &'a [u8] {
+        self.coalesced
+    }
+
+    /// The application protocol selected by a TLS 1.2 server, if any.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn alpn_protocol(&self) -> Option<&'a [u8]> {
+        self.alpn_protocol
+    }
+
+    /// The maximum fragment length accepted by a TLS 1.2 server, if any.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        self.max_fragment_length
+    }
+
+    #[cfg(feature = "tls12")]
+    pub(crate) fn set_coalesced(&mut self, coalesced: &'a [u8]) {
+        self.coalesced = coalesced;
+    }
+
+    /// Updates `transcript` with the message and its handshake header. With `confirmation`, the
+    /// last 8 bytes of the random are replaced with zeros, as in the transcript of the ECH
+    /// acceptance confirmation.
+    #[cfg(feature = "ech")]
+    pub(crate) fn update_transcript<D: Digest>(
+        &self,
+        transcript: &mut D,
+        confirmation: bool,
+    ) -> Result<(), TlsError> {
+        let [_, a, b, c] = (self.raw.len() as u32).to_be_bytes();
+        transcript.update([HandshakeType::ServerHello as u8, a, b, c]);
+        if confirmation {
+            // The legacy_version and the first 24 bytes of the random, then the rest of the
+            // message after the random
+            let head = self.raw.get(..26).ok_or(TlsError::InvalidHandshake)?;
+            let tail = self.raw.get(34..).ok_or(TlsError::InvalidHandshake)?;
+            transcript.update(head);
+            transcript.update([0; 8]);
+            transcript.update(tail);
+        } else {
+            transcript.update(self.raw);
+        }
+        Ok(())
+    }
+
     pub fn key_share(&self) -> Option<&KeyShareEntry> {
-        self.extensions.iter().find_map(|e| {
-            if let ServerExtension::KeyShare(entry) = e {
-                Some(&entry.0)
-            } else {
-                None
-            }
+        self.key_share.as_ref()
+    }
+
+    /// The index of the PSK identity accepted by the server, if any.
+    pub fn selected_identity(&self) -> Option<u16> {
+        self.selected_identity
+    }
+
+    pub fn calculate_shared_secret<Secret: KeyExchange>(
+        &self,
+        secret: Secret,
+    ) -> Result<SharedSecret, TlsError> {
+        let server_key_share = self.key_share().ok_or(TlsError::InvalidKeyShare)?;
+
+        // Section 4.2.8.  Key Share
+        // If using (EC)DHE key establishment, servers offer exactly one
+        // KeyShareEntry in the ServerHello.  This value MUST be in the same
+        // group as the KeyShareEntry value offered by the client that the
+        // server has selected for the negotiated key exchange.
+        if server_key_share.group != secret.group() {
+            warn!("Server key share group does not match the offered key share");
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter,
+            ));
+        }
+
+        secret.diffie_hellman(server_key_share.opaque)
+    }
+
+    pub fn initialize_crypto_engine<Secret: KeyExchange>(
+        &self,
+        secret: Secret,
+    ) -> Result<CryptoEngine, TlsError> {
+        let group = secret.group();
+        let shared = self.calculate_shared_secret(secret)?;
+
+        Ok(CryptoEngine::new(group, shared))
+    }
+}
+
+/// The parameters of a HelloRetryRequest, kept for the second ClientHello.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HelloRetryRequest {
+    pub(crate) selected_group: Option<NamedGroup>,
+    /// Empty if the server did not send a cookie, which is at least one byte long otherwise.
+    pub(crate) cookie: Vec<u8, 256>,
+}
+
+impl<'a> TryFrom<&ServerHello<'a>> for HelloRetryRequest {
+    type Error = TlsError;
+    fn try_from(hello: &ServerHello<'a>) -> Result<Self, Self::Error> {
+        let mut cookie = Vec::new();
+        cookie
+            .extend_from_slice(hello.cookie.unwrap_or_default())
+            .map_err(|_| TlsError::InsufficientSpace)?;
+        Ok(Self {
+            selected_group: hello.selected_group,
+            cookie,
         })
     }
+}
 
-    pub fn calculate_shared_secret(&self, secret: &EphemeralSecret) -> Option<SharedSecret> {
-        let server_key_share = self.key_share()?;
-        let server_public_key = PublicKey::from_sec1_bytes(server_key_share.opaque).ok()?;
-        Some(secret.diffie_hellman(&server_public_key))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::named_groups::NamedGroup;
+
+    fn server_hello() -> [u8; 56] {
+        let mut buffer = [0; 56];
+        buffer[..2].copy_from_slice(&[0x03, 0x03]);
+        buffer[34..].copy_from_slice(&[
+            0x00, // session id length
+            0x13, 0x01, // TLS_AES_128_GCM_SHA256
+            0x00, // compression method
+            0x00, 0x10, // extensions length
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, // supported versions: TLS 1.3
+            0x00, 0x33, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x02, 0xAA, 0xBB, // key share
+        ]);
+        buffer
     }
 
-    pub fn initialize_crypto_engine(&self, secret: EphemeralSecret) -> Option<CryptoEngine> {
-        let server_key_share = self.key_share()?;
+    #[test]
+    fn test_parse_keeps_key_share() {
+        let buffer = server_hello();
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        let key_share = hello.key_share().unwrap();
 
-        let group = server_key_share.group;
+        assert_eq!(NamedGroup::X25519, key_share.group);
+        assert_eq!([0xAA, 0xBB], key_share.opaque);
+        assert_eq!(None, hello.selected_identity());
+        assert!(!hello.is_hello_retry_request());
+        assert!(!hello.is_downgrade());
+    }
 
-        let server_public_key = PublicKey::from_sec1_bytes(server_key_share.opaque).ok()?;
-        let shared = secret.diffie_hellman(&server_public_key);
+    #[test]
+    fn test_downgrade_sentinels() {
+        for last in [0x00, 0x01] {
+            let mut buffer = server_hello();
+            buffer[26..34].copy_from_slice(&[0x44, 0x4F, 0x57, 0x4E, 0x47, 0x52, 0x44, last]);
+            let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+            assert!(hello.is_downgrade());
+        }
+    }
+
+    fn hello_retry_request() -> [u8; 60] {
+        let mut buffer = [0; 60];
+        buffer[..2].copy_from_slice(&[0x03, 0x03]);
+        buffer[2..34].copy_from_slice(&HELLO_RETRY_REQUEST_RANDOM);
+        buffer[34..].copy_from_slice(&[
+            0x00, // session id length
+            0x13, 0x01, // TLS_AES_128_GCM_SHA256
+            0x00, // compression method
+            0x00, 0x14, // extensions length
+            0x00, 0x2b, 0x00, 0x02, 0x03, 0x04, // supported versions: TLS 1.3
+            0x00, 0x33, 0x00, 0x02, 0x00, 0x1d, // key share: X25519 selected
+            0x00, 0x2c, 0x00, 0x04, 0x00, 0x02, 0xAA, 0xBB, // cookie
+        ]);
+        buffer
+    }
+
+    #[test]
+    fn test_parse_hello_retry_request() {
+        let buffer = hello_retry_request();
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        assert!(hello.is_hello_retry_request());
+        assert!(hello.key_share().is_none());
+
+        let retry = HelloRetryRequest::try_from(&hello).unwrap();
+        assert_eq!(Some(NamedGroup::X25519), retry.selected_group);
+        assert_eq!([0xAA, 0xBB], retry.cookie.as_slice());
+    }
+
+    #[test]
+    fn test_hello_retry_request_restarts_transcript() {
+        use sha2::Sha256;
+
+        let header = [HandshakeType::ServerHello as u8, 0x00, 0x00, 60];
+        let buffer = hello_retry_request();
+        let mut digest = Sha256::new();
+        digest.update(b"client hello");
+        ServerHello::read(&header, &buffer, &mut digest).unwrap();
+
+        let mut expected = Sha256::new();
+        expected.update([HandshakeType::MessageHash as u8, 0x00, 0x00, 32]);
+        expected.update(Sha256::digest(b"client hello"));
+        expected.update(header);
+        expected.update(buffer);
+        assert_eq!(expected.finalize(), digest.finalize());
+    }
+
+    #[test]
+    fn test_empty_cookie() {
+        let mut buffer = hello_retry_request();
+        buffer[39] = 0x12; // extensions length
+        buffer[55] = 0x02; // cookie extension length
+        buffer[57] = 0x00; // cookie length
+
+        assert!(matches!(
+            ServerHello::parse(&mut ParseBuffer::new(&buffer[..58])),
+            Err(TlsError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn test_hello_retry_request_extensions() {
+        // A pre_shared_key extension is not allowed in a HelloRetryRequest
+        let mut buffer = [0; 66];
+        buffer[..60].copy_from_slice(&hello_retry_request());
+        buffer[39] = 0x1a; // extensions length
+        buffer[60..].copy_from_slice(&[0x00, 0x29, 0x00, 0x02, 0x00, 0x00]);
+
+        assert!(matches!(
+            ServerHello::parse(&mut ParseBuffer::new(&buffer)),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::IllegalParameter
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_parse_selected_identity() {
+        let mut buffer = [0; 62];
+        buffer[..56].copy_from_slice(&server_hello());
+        buffer[39] = 0x16; // extensions length
+        buffer[56..].copy_from_slice(&[0x00, 0x29, 0x00, 0x02, 0x00, 0x00]); // pre shared key
+
+        let hello = ServerHello::parse(&mut ParseBuffer::new(&buffer)).unwrap();
+        assert_eq!(Some(0), hello.selected_identity());
+        assert!(hello.key_share().is_some());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let buffer = server_hello();
+        for len in 0..buffer.len() {
+            // A TLS 1.2 ServerHello may end after the compression method
+            if cfg!(feature = "tls12") && len == 38 {
+                continue;
+            }
+            assert!(ServerHello::parse(&mut ParseBuffer::new(&buffer[..len])).is_err());
+        }
+    }
 
-        Some(CryptoEngine::new(group, shared))
+    #[test]
+    fn test_size_is_bounded() {
+        // The parsed message borrows from the record buffer, so it must stay small
+        assert!(core::mem::size_of::<ServerHello>() <= 144);
     }
 }
@@ -0,0 +1,404 @@
+//! RFC 6520.  Transport Layer Security (TLS) and Datagram Transport Layer Security (DTLS)
+//! Heartbeat Extension
+//!
+//! Heartbeats keep a connection alive without application data, once both peers announced their
+//! heartbeat mode in the hello extensions.
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::buffer::CryptoBuffer;
+use crate::parse_buffer::ParseBuffer;
+use crate::TlsError;
+use heapless::Vec;
+use sha2::{Digest, Sha256};
+
+/// The maximum length of the payload of a heartbeat request, which is kept until the response is
+/// received.
+pub const MAX_HEARTBEAT_PAYLOAD_LEN: usize = 64;
+
+/// Section 4.  Heartbeat Request and Response Messages
+/// The padding_length MUST be at least 16.
+pub(crate) const MIN_PADDING_LEN: usize = 16;
+
+/// The message_type and payload_length of a HeartbeatMessage.
+const HEADER_LEN: usize = 3;
+
+/// Whether a peer accepts heartbeat requests.
+///
+/// Section 2.  Heartbeat Hello Extension
+/// ```text
+/// enum {
+///    peer_allowed_to_send(1),
+///    peer_not_allowed_to_send(2),
+///    (255)
+/// } HeartbeatMode;
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HeartbeatMode {
+    /// The peer may send heartbeat requests, which are answered.
+    PeerAllowedToSend = 1,
+    /// The peer must not send heartbeat requests.
+    PeerNotAllowedToSend = 2,
+}
+
+impl HeartbeatMode {
+    pub(crate) fn of(num: u8) -> Option<Self> {
+        match num {
+            1 => Some(Self::PeerAllowedToSend),
+            2 => Some(Self::PeerNotAllowedToSend),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum HeartbeatMessageType {
+    Request = 1,
+    Response = 2,
+}
+
+impl HeartbeatMessageType {
+    fn of(num: u8) -> Option<Self> {
+        match num {
+            1 => Some(Self::Request),
+            2 => Some(Self::Response),
+            _ => None,
+        }
+    }
+}
+
+/// Section 4.  Heartbeat Request and Response Messages
+/// ```text
+/// struct {
+///    HeartbeatMessageType type;
+///    uint16 payload_length;
+///    opaque payload[HeartbeatMessage.payload_length];
+///    opaque padding[padding_length];
+/// } HeartbeatMessage;
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct HeartbeatMessage<'a> {
+    pub(crate) message_type: HeartbeatMessageType,
+    pub(crate) payload: &'a [u8],
+    pub(crate) padding: &'a [u8],
+}
+
+impl<'a> HeartbeatMessage<'a> {
+    /// Parses the HeartbeatMessage of a record, or returns `None` if it must be discarded.
+    ///
+    /// Section 4.  Heartbeat Request and Response Messages
+    /// If the payload_length of a received HeartbeatMessage is too large, the received
+    /// HeartbeatMessage MUST be discarded silently.
+    pub(crate) fn parse(data: &'a [u8]) -> Option<Self> {
+        let mut buf = ParseBuffer::new(data);
+        let message_type = HeartbeatMessageType::of(buf.read_u8().ok()?)?;
+        let payload_length = buf.read_u16().ok()? as usize;
+
+        // The payload_length is checked against the length of the record, so that no more than
+        // the received payload is ever echoed
+        if HEADER_LEN + payload_length + MIN_PADDING_LEN > data.len() {
+            warn!("Discarding a heartbeat message with a payload_length which is too large");
+            return None;
+        }
+
+        let payload = buf.slice(payload_length).ok()?.as_slice();
+        Some(Self {
+            message_type,
+            payload,
+            padding: data.get(HEADER_LEN + payload_length..)?,
+        })
+    }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer) -> Result<(), TlsError> {
+        if self.padding.len() < MIN_PADDING_LEN {
+            return Err(TlsError::EncodeError);
+        }
+        buf.push(self.message_type as u8)
+            .map_err(|_| TlsError::EncodeError)?;
+        buf.with_u16_length(|buf| buf.extend_from_slice(self.payload))
+            .map_err(|_| TlsError::EncodeError)?;
+        buf.extend_from_slice(self.padding)
+            .map_err(|_| TlsError::EncodeError)
+    }
+}
+
+/// The heartbeat state of a connection.
+#[derive(Debug, Default)]
+pub(crate) struct Heartbeat {
+    /// The mode of the client, once the server answered the heartbeat extension.
+    mode: Option<HeartbeatMode>,
+    /// The mode of the server.
+    peer_mode: Option<HeartbeatMode>,
+    /// Section 4.  Heartbeat Request and Response Messages
+    /// The padding_length MUST be at least 16.  The sender of a HeartbeatMessage MUST use a
+    /// random padding of at least padding_length bytes.
+    ///
+    /// The padding of every message is derived from a random seed taken when the connection is
+    /// opened, since the random number generator is not kept by the connection.
+    seed: [u8; 32],
+    /// The number of paddings derived from the seed.
+    counter: u64,
+    /// The padding of the last message.
+    padding: [u8; MIN_PADDING_LEN],
+    /// The payload of the request in flight, if any.
+    request: Option<Vec<u8, MAX_HEARTBEAT_PAYLOAD_LEN>>,
+    /// The payload of a request of the server which has not been answered yet.
+    response: Option<Vec<u8, MAX_HEARTBEAT_PAYLOAD_LEN>>,
+}
+
+impl Heartbeat {
+    pub(crate) fn set_seed(&mut self, seed: [u8; 32]) {
+        self.seed = seed;
+    }
+
+    /// Derives a fresh padding from the seed, for the next message.
+    fn next_padding(&mut self) {
+        let hash = Sha256::new()
+            .chain_update(self.seed)
+            .chain_update(self.counter.to_be_bytes())
+            .finalize();
+        self.counter += 1;
+        self.padding.copy_from_slice(&hash[..MIN_PADDING_LEN]);
+    }
+
+    /// Enables heartbeats once the server answered the extension with `peer_mode`.
+    pub(crate) fn negotiate(&mut self, mode: HeartbeatMode, peer_mode: HeartbeatMode) {
+        self.mode = Some(mode);
+        self.peer_mode = Some(peer_mode);
+    }
+
+    /// Handles a HeartbeatMessage of the server.
+    pub(crate) fn receive(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        let Some(mode) = self.mode else {
+            warn!("Heartbeat message received, but heartbeats were not negotiated");
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnexpectedMessage,
+            ));
+        };
+
+        let Some(message) = HeartbeatMessage::parse(data) else {
+            return Ok(());
+        };
+
+        match message.message_type {
+            // Section 2.  Heartbeat Hello Extension
+            // A peer which indicated peer_not_allowed_to_send does not expect heartbeat requests,
+            // and treats them as unexpected messages.
+            HeartbeatMessageType::Request if mode == HeartbeatMode::PeerNotAllowedToSend => {
+                warn!("Heartbeat request received, but the server is not allowed to send them");
+                Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::UnexpectedMessage,
+                ))
+            }
+            // Section 4.  Heartbeat Request and Response Messages
+            // When a HeartbeatRequest message is received [...], the receiver MUST send a
+            // corresponding HeartbeatResponse message carrying an exact copy of the payload of
+            // the received HeartbeatRequest.
+            HeartbeatMessageType::Request => {
+                match Vec::from_slice(message.payload) {
+                    Ok(payload) => self.response = Some(payload),
+                    Err(_) => warn!("Discarding a heartbeat request which is too large"),
+                }
+                Ok(())
+            }
+            // Section 4.  Heartbeat Request and Response Messages
+            // If a received HeartbeatResponse message does not contain the expected payload, the
+            // message MUST be discarded silently.
+            HeartbeatMessageType::Response => {
+                if self.request.as_deref() == Some(message.payload) {
+                    self.request = None;
+                } else {
+                    trace!("Discarding an unexpected heartbeat response");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Starts a heartbeat request with `payload`, and returns the HeartbeatMessage to send.
+    pub(crate) fn request(&mut self, payload: &[u8]) -> Result<HeartbeatMessage<'_>, TlsError> {
+        if self.peer_mode != Some(HeartbeatMode::PeerAllowedToSend) {
+            return Err(TlsError::HeartbeatNotAllowed);
+        }
+
+        // Section 3.  Heartbeat Protocol
+        // There MUST NOT be more than one HeartbeatRequest message in flight at a time.
+        if self.request.is_some() {
+            return Err(TlsError::HeartbeatNotAllowed);
+        }
+
+        let payload = Vec::from_slice(payload).map_err(|_| TlsError::InsufficientSpace)?;
+        self.next_padding();
+        let request = self.request.insert(payload);
+        Ok(HeartbeatMessage {
+            message_type: HeartbeatMessageType::Request,
+            payload: request,
+            padding: &self.padding,
+        })
+    }
+
+    /// Whether a heartbeat request was sent and not answered yet.
+    pub(crate) fn in_flight(&self) -> bool {
+        self.request.is_some()
+    }
+
+    /// Gives up on the request in flight, if any, which failed to be sent or is not answered.
+    /// A late response to it is then discarded as unexpected.
+    pub(crate) fn cancel_request(&mut self) {
+        self.request = None;
+    }
+
+    /// Takes the payload of a heartbeat request of the server which must be answered.
+    pub(crate) fn take_response(&mut self) -> Option<Vec<u8, MAX_HEARTBEAT_PAYLOAD_LEN>> {
+        self.response.take()
+    }
+
+    /// The HeartbeatMessage answering a request with `payload`.
+    pub(crate) fn response<'a>(&'a mut self, payload: &'a [u8]) -> HeartbeatMessage<'a> {
+        self.next_padding();
+        HeartbeatMessage {
+            message_type: HeartbeatMessageType::Response,
+            payload,
+            padding: &self.padding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(message_type: u8, payload_length: u16, payload: &[u8]) -> std::vec::Vec<u8> {
+        let mut data = std::vec![message_type];
+        data.extend_from_slice(&payload_length.to_be_bytes());
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&[0xAA; MIN_PADDING_LEN]);
+        data
+    }
+
+    fn negotiated(mode: HeartbeatMode) -> Heartbeat {
+        let mut heartbeat = Heartbeat::default();
+        heartbeat.negotiate(mode, HeartbeatMode::PeerAllowedToSend);
+        heartbeat
+    }
+
+    #[test]
+    fn test_parse() {
+        let data = message(1, 3, b"abc");
+        let message = HeartbeatMessage::parse(&data).unwrap();
+        assert_eq!(message.message_type, HeartbeatMessageType::Request);
+        assert_eq!(message.payload, b"abc");
+        assert_eq!(message.padding, &[0xAA; MIN_PADDING_LEN]);
+    }
+
+    #[test]
+    fn test_discard_payload_length_too_large() {
+        // A payload_length beyond the record must not read past the payload
+        assert!(HeartbeatMessage::parse(&message(1, 4, b"abc")).is_none());
+        assert!(HeartbeatMessage::parse(&message(1, 0x4000, b"abc")).is_none());
+
+        // The padding must be at least 16 bytes
+        let mut data = message(1, 3, b"abc");
+        data.pop();
+        assert!(HeartbeatMessage::parse(&data).is_none());
+
+        let mut heartbeat = negotiated(HeartbeatMode::PeerAllowedToSend);
+        heartbeat.receive(&message(1, 4, b"abc")).unwrap();
+        assert!(heartbeat.take_response().is_none());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut storage = [0; 32];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        HeartbeatMessage {
+            message_type: HeartbeatMessageType::Response,
+            payload: b"abc",
+            padding: &[0xAA; MIN_PADDING_LEN],
+        }
+        .encode(&mut buf)
+        .unwrap();
+        assert_eq!(buf.as_slice(), &message(2, 3, b"abc")[..]);
+    }
+
+    #[test]
+    fn test_answer_request() {
+        let mut heartbeat = negotiated(HeartbeatMode::PeerAllowedToSend);
+        heartbeat.receive(&message(1, 3, b"abc")).unwrap();
+        assert_eq!(heartbeat.take_response().as_deref(), Some(&b"abc"[..]));
+        assert!(heartbeat.take_response().is_none());
+
+        let mut heartbeat = negotiated(HeartbeatMode::PeerNotAllowedToSend);
+        assert!(matches!(
+            heartbeat.receive(&message(1, 3, b"abc")),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnexpectedMessage
+            ))
+        ));
+
+        assert!(Heartbeat::default()
+            .receive(&message(2, 3, b"abc"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_request() {
+        let mut heartbeat = negotiated(HeartbeatMode::PeerNotAllowedToSend);
+        let request = heartbeat.request(b"abc").unwrap();
+        assert_eq!(request.message_type, HeartbeatMessageType::Request);
+        assert_eq!(request.payload, b"abc");
+        assert!(matches!(
+            heartbeat.request(b"def"),
+            Err(TlsError::HeartbeatNotAllowed)
+        ));
+
+        // A response with another payload is discarded
+        heartbeat.receive(&message(2, 3, b"abd")).unwrap();
+        assert!(heartbeat.in_flight());
+        heartbeat.receive(&message(2, 3, b"abc")).unwrap();
+        assert!(!heartbeat.in_flight());
+
+        // A request which is given up on lets another one be sent, and its response is discarded
+        heartbeat.request(b"abc").unwrap();
+        heartbeat.cancel_request();
+        assert!(!heartbeat.in_flight());
+        heartbeat.request(b"def").unwrap();
+        heartbeat.receive(&message(2, 3, b"abc")).unwrap();
+        assert!(heartbeat.in_flight());
+
+        let mut heartbeat = Heartbeat::default();
+        heartbeat.negotiate(
+            HeartbeatMode::PeerAllowedToSend,
+            HeartbeatMode::PeerNotAllowedToSend,
+        );
+        assert!(matches!(
+            heartbeat.request(b"abc"),
+            Err(TlsError::HeartbeatNotAllowed)
+        ));
+        assert!(matches!(
+            negotiated(HeartbeatMode::PeerAllowedToSend)
+                .request(&[0; MAX_HEARTBEAT_PAYLOAD_LEN + 1]),
+            Err(TlsError::InsufficientSpace)
+        ));
+    }
+
+    #[test]
+    fn test_padding() {
+        let mut heartbeat = negotiated(HeartbeatMode::PeerAllowedToSend);
+        heartbeat.set_seed([0x42; 32]);
+        let request = heartbeat.request(b"abc").unwrap().padding.to_vec();
+        heartbeat.cancel_request();
+        let other_request = heartbeat.request(b"abc").unwrap().padding.to_vec();
+        let response = heartbeat.response(b"abc").padding.to_vec();
+
+        // Every message has its own padding
+        assert_eq!(MIN_PADDING_LEN, request.len());
+        assert_ne!(request, other_request);
+        assert_ne!(request, response);
+        assert_ne!(other_request, response);
+    }
+}
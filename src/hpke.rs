@@ -0,0 +1,342 @@
+//! RFC 9180.  Hybrid Public Key Encryption
+//!
+//! Only the base mode of DHKEM(X25519, HKDF-SHA256) with HKDF-SHA256 and AES-GCM is implemented,
+//! and only on the sender side, which is what the client needs to encrypt its ClientHelloInner
+//! with Encrypted Client Hello.
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use hkdf::{Hkdf, HkdfExtract};
+use rand_core::{CryptoRng, RngCore};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::TlsError;
+
+// Section 7.1.  Key Encapsulation Mechanisms (KEMs)
+pub(crate) const KEM_X25519_HKDF_SHA256: u16 = 0x0020;
+// Section 7.2.  Key Derivation Functions (KDFs)
+pub(crate) const KDF_HKDF_SHA256: u16 = 0x0001;
+// Section 7.3.  Authenticated Encryption with Associated Data (AEAD) Functions
+pub(crate) const AEAD_AES_128_GCM: u16 = 0x0001;
+pub(crate) const AEAD_AES_256_GCM: u16 = 0x0002;
+
+/// The length of an encapsulated X25519 key, and of a public key of the recipient.
+pub(crate) const ENC_LEN: usize = 32;
+
+/// The length of the authentication tag of AES-GCM.
+pub(crate) const TAG_LEN: usize = 16;
+
+const NONCE_LEN: usize = 12;
+
+// Section 4.  Cryptographic Dependencies
+const VERSION_LABEL: &[u8] = b"HPKE-v1";
+
+// Section 5.1.  Creating the Encryption Context
+const MODE_BASE: u8 = 0x00;
+
+/// Whether the AEAD of the given identifier is supported.
+pub(crate) fn supports_aead(aead: u16) -> bool {
+    matches!(aead, AEAD_AES_128_GCM | AEAD_AES_256_GCM)
+}
+
+/// The encryption context of the sender, see [`setup_base_sender`].
+pub(crate) struct SenderContext {
+    aead: u16,
+    key: Zeroizing<[u8; 32]>,
+    base_nonce: [u8; NONCE_LEN],
+}
+
+impl SenderContext {
+    /// Section 5.2.  Encryption and Decryption
+    ///     def ContextS.Seal(aad, pt):
+    ///       ct = Seal(self.key, self.ComputeNonce(self.seq), aad, pt)
+    ///       self.IncrementSeq()
+    ///       return ct
+    ///
+    /// Encrypts `buf` in place with the nonce of the sequence number `seq`, which the caller
+    /// increments for each message, and returns the authentication tag.
+    pub(crate) fn seal(
+        &self,
+        seq: u64,
+        aad: &[u8],
+        buf: &mut [u8],
+    ) -> Result<[u8; TAG_LEN], TlsError> {
+        let mut nonce = self.base_nonce;
+        for (n, s) in nonce.iter_mut().rev().zip(seq.to_be_bytes().iter().rev()) {
+            *n ^= s;
+        }
+        let tag = match self.aead {
+            AEAD_AES_128_GCM => Aes128Gcm::new_from_slice(&self.key[..16])
+                .map_err(|_| TlsError::InternalError)?
+                .encrypt_in_place_detached(&nonce.into(), aad, buf),
+            AEAD_AES_256_GCM => Aes256Gcm::new_from_slice(&self.key[..])
+                .map_err(|_| TlsError::InternalError)?
+                .encrypt_in_place_detached(&nonce.into(), aad, buf),
+            _ => return Err(TlsError::InternalError),
+        }
+        .map_err(|_| TlsError::CryptoError)?;
+        Ok(tag.into())
+    }
+}
+
+/// Section 5.1.1.  Encryption to a Public Key
+///     def SetupBaseS(pkR, info):
+///       shared_secret, enc = Encap(pkR)
+///       return enc, KeyScheduleS(mode_base, shared_secret, info,
+///                                default_psk, default_psk_id)
+///
+/// Returns the encapsulated key, to send to the recipient, and the encryption context for the
+/// public key `pk_r` of DHKEM(X25519, HKDF-SHA256) and the given AEAD. The `info` is given in
+/// parts, which are concatenated.
+pub(crate) fn setup_base_sender<RNG>(
+    pk_r: &[u8],
+    info: &[&[u8]],
+    aead: u16,
+    rng: &mut RNG,
+) -> Result<([u8; ENC_LEN], SenderContext), TlsError>
+where
+    RNG: CryptoRng + RngCore,
+{
+    if !supports_aead(aead) {
+        return Err(TlsError::InvalidCipherSuite);
+    }
+    let (shared_secret, enc) = encap(pk_r, rng)?;
+    let context = key_schedule(&shared_secret, info, aead)?;
+    Ok((enc, context))
+}
+
+/// Section 4.1.  DH-Based KEM (DHKEM)
+///     def Encap(pkR):
+///       skE, pkE = GenerateKeyPair()
+///       dh = DH(skE, pkR)
+///       enc = SerializePublicKey(pkE)
+///
+///       pkRm = SerializePublicKey(pkR)
+///       kem_context = concat(enc, pkRm)
+///
+///       shared_secret = ExtractAndExpand(dh, kem_context)
+///       return shared_secret, enc
+fn encap<RNG>(pk_r: &[u8], rng: &mut RNG) -> Result<(Zeroizing<[u8; 32]>, [u8; ENC_LEN]), TlsError>
+where
+    RNG: CryptoRng + RngCore,
+{
+    let pk_r: [u8; 32] = pk_r.try_into().map_err(|_| TlsError::InvalidKeyShare)?;
+    let sk_e = x25519_dalek::EphemeralSecret::random_from_rng(rng);
+    let enc = *x25519_dalek::PublicKey::from(&sk_e).as_bytes();
+    let dh = sk_e.diffie_hellman(&x25519_dalek::PublicKey::from(pk_r));
+
+    // Section 7.1.4.  Validation of Inputs and Outputs
+    // For X25519 and X448, public keys and Diffie-Hellman outputs MUST be validated as described
+    // in [RFC7748]. In particular, recipients MUST check whether the Diffie-Hellman shared secret
+    // is the all-zero value and abort if so.
+    if !dh.was_contributory() {
+        return Err(TlsError::InvalidKeyShare);
+    }
+
+    let suite_id = kem_suite_id();
+    let eae_prk = labeled_extract(&suite_id, &[], b"eae_prk", &[&dh.as_bytes()[..]]);
+    let mut shared_secret = Zeroizing::new([0; 32]);
+    labeled_expand(
+        &eae_prk[..],
+        &suite_id,
+        b"shared_secret",
+        &[&enc[..], &pk_r[..]],
+        &mut shared_secret[..],
+    )?;
+    Ok((shared_secret, enc))
+}
+
+/// Section 5.1.  Creating the Encryption Context
+///     def KeySchedule<ROLE>(mode, shared_secret, info, psk, psk_id):
+///       VerifyPSKInputs(mode, psk, psk_id)
+///
+///       psk_id_hash = LabeledExtract("", "psk_id_hash", psk_id)
+///       info_hash = LabeledExtract("", "info_hash", info)
+///       key_schedule_context = concat(mode, psk_id_hash, info_hash)
+///
+///       secret = LabeledExtract(shared_secret, "secret", psk)
+///
+///       key = LabeledExpand(secret, "key", key_schedule_context, Nk)
+///       base_nonce = LabeledExpand(secret, "base_nonce",
+///                                  key_schedule_context, Nn)
+///       exporter_secret = LabeledExpand(secret, "exp",
+///                                       key_schedule_context, Nh)
+///
+///       return Context<ROLE>(key, base_nonce, 0, exporter_secret)
+fn key_schedule(
+    shared_secret: &[u8],
+    info: &[&[u8]],
+    aead: u16,
+) -> Result<SenderContext, TlsError> {
+    let suite_id = hpke_suite_id(aead);
+    let psk_id_hash = labeled_extract(&suite_id, &[], b"psk_id_hash", &[]);
+    let info_hash = labeled_extract(&suite_id, &[], b"info_hash", info);
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", &[]);
+    let context = [&[MODE_BASE][..], &psk_id_hash[..], &info_hash[..]];
+
+    let key_len = if aead == AEAD_AES_128_GCM { 16 } else { 32 };
+    let mut key = Zeroizing::new([0; 32]);
+    labeled_expand(
+        &secret[..],
+        &suite_id,
+        b"key",
+        &context,
+        &mut key[..key_len],
+    )?;
+    let mut base_nonce = [0; NONCE_LEN];
+    labeled_expand(
+        &secret[..],
+        &suite_id,
+        b"base_nonce",
+        &context,
+        &mut base_nonce,
+    )?;
+    Ok(SenderContext {
+        aead,
+        key,
+        base_nonce,
+    })
+}
+
+/// Section 4.1.  DH-Based KEM (DHKEM)
+///     suite_id = concat("KEM", I2OSP(kem_id, 2))
+fn kem_suite_id() -> [u8; 5] {
+    let [a, b] = KEM_X25519_HKDF_SHA256.to_be_bytes();
+    [b'K', b'E', b'M', a, b]
+}
+
+/// Section 5.1.  Creating the Encryption Context
+///     suite_id = concat(
+///       "HPKE",
+///       I2OSP(kem_id, 2),
+///       I2OSP(kdf_id, 2),
+///       I2OSP(aead_id, 2)
+///     )
+fn hpke_suite_id(aead: u16) -> [u8; 10] {
+    let mut suite_id = [0; 10];
+    suite_id[..4].copy_from_slice(b"HPKE");
+    suite_id[4..6].copy_from_slice(&KEM_X25519_HKDF_SHA256.to_be_bytes());
+    suite_id[6..8].copy_from_slice(&KDF_HKDF_SHA256.to_be_bytes());
+    suite_id[8..].copy_from_slice(&aead.to_be_bytes());
+    suite_id
+}
+
+/// Section 4.  Cryptographic Dependencies
+///     def LabeledExtract(salt, label, ikm):
+///       labeled_ikm = concat("HPKE-v1", suite_id, label, ikm)
+///       return Extract(salt, labeled_ikm)
+fn labeled_extract(
+    suite_id: &[u8],
+    salt: &[u8],
+    label: &[u8],
+    ikm: &[&[u8]],
+) -> Zeroizing<[u8; 32]> {
+    let mut extract = HkdfExtract::<Sha256>::new(Some(salt));
+    for part in [VERSION_LABEL, suite_id, label]
+        .into_iter()
+        .chain(ikm.iter().copied())
+    {
+        extract.input_ikm(part);
+    }
+    let (prk, _) = extract.finalize();
+    Zeroizing::new(prk.into())
+}
+
+/// Section 4.  Cryptographic Dependencies
+///     def LabeledExpand(prk, label, info, L):
+///       labeled_info = concat(I2OSP(L, 2), "HPKE-v1", suite_id,
+///                             label, info)
+///       return Expand(prk, labeled_info, L)
+fn labeled_expand(
+    prk: &[u8],
+    suite_id: &[u8],
+    label: &[u8],
+    info: &[&[u8]],
+    okm: &mut [u8],
+) -> Result<(), TlsError> {
+    let len = u16::try_from(okm.len()).map_err(|_| TlsError::InternalError)?;
+    let len = len.to_be_bytes();
+    let mut labeled_info: heapless::Vec<&[u8], 8> = heapless::Vec::new();
+    for part in [&len[..], VERSION_LABEL, suite_id, label]
+        .into_iter()
+        .chain(info.iter().copied())
+    {
+        labeled_info
+            .push(part)
+            .map_err(|_| TlsError::InternalError)?;
+    }
+    Hkdf::<Sha256>::from_prk(prk)
+        .map_err(|_| TlsError::InternalError)?
+        .expand_multi_info(&labeled_info, okm)
+        .map_err(|_| TlsError::CryptoError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays the private key of the ephemeral key pair of a test vector.
+    struct FixedKey([u8; 32]);
+
+    impl RngCore for FixedKey {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            // The key is repeated for requests of any other length
+            for (byte, key) in dest.iter_mut().zip(self.0.iter().cycle()) {
+                *byte = *key;
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for FixedKey {}
+
+    fn hex<const N: usize>(s: &str) -> [u8; N] {
+        let mut out = [0; N];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // Appendix A.1.1.  Base Setup Information
+    #[test]
+    fn test_base_setup() {
+        let pk_r: [u8; 32] =
+            hex("3948cfe0ad1ddb695d780e59077195da6c56506b027329794ab02bca80815c4d");
+        let sk_e = hex("52c4a758a802cd8b936eceea314432798d5baf2d7e9235dc084ab1b9cfa2f736");
+        let info = b"Ode on a Grecian Urn";
+
+        let (enc, context) =
+            setup_base_sender(&pk_r, &[&info[..]], AEAD_AES_128_GCM, &mut FixedKey(sk_e)).unwrap();
+        assert_eq!(
+            hex::<32>("37fda3567bdbd628e88668c3c8d7e97d1d1253b6d4ea6d44c150f741f1bf4431"),
+            enc
+        );
+        assert_eq!(
+            hex::<16>("4531685d41d65f03dc48f6b8302c05b0"),
+            context.key[..16]
+        );
+        assert_eq!(hex::<12>("56d890e5accaaf011cff4b7d"), context.base_nonce);
+
+        // Appendix A.1.1.1.  Encryptions, sequence number 0
+        let mut plaintext = *b"Beauty is truth, truth beauty";
+        let tag = context.seal(0, b"Count-0", &mut plaintext).unwrap();
+        assert_eq!(
+            hex::<29>("f938558b5d72f1a23810b4be2ab4f84331acc02fc97babc53a52ae8218"),
+            plaintext
+        );
+        assert_eq!(hex::<16>("a355a96d8770ac83d07bea87e13c512a"), tag);
+    }
+}
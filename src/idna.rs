@@ -0,0 +1,185 @@
+//! RFC 5891.  Internationalized Domain Names in Applications (IDNA): Protocol
+//!
+//! A host name with non-ASCII labels (U-labels) is converted to A-labels, which are sent in the
+//! server_name extension and compared with the DNS names of the server certificate. Labels are
+//! only lower-cased before being encoded with Punycode, the other mappings and validity checks of
+//! IDNA are left to the application.
+use crate::TlsError;
+
+/// The maximum length of a host name made of A-labels.
+pub(crate) const MAX_NAME_LEN: usize = 253;
+
+/// The prefix of an A-label.
+const ACE_PREFIX: &[u8] = b"xn--";
+
+// RFC 3492, Section 5.  Parameter values for Punycode
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+/// Converts the U-labels of a host name to A-labels in `buf`. A name which is only made of ASCII
+/// is returned as is.
+pub(crate) fn to_ascii<'b>(name: &'b str, buf: &'b mut [u8]) -> Result<&'b str, TlsError> {
+    if name.is_ascii() {
+        return Ok(name);
+    }
+
+    let mut out = Output { buf, len: 0 };
+    // RFC 3490, Section 3.1.  Requirements
+    // Whenever dots are used as label separators, the following characters MUST be recognized as
+    // dots: U+002E (full stop), U+3002 (ideographic full stop), U+FF0E (fullwidth full stop),
+    // U+FF61 (halfwidth ideographic full stop).
+    for (i, label) in name
+        .split(['.', '\u{3002}', '\u{FF0E}', '\u{FF61}'])
+        .enumerate()
+    {
+        if i > 0 {
+            out.push(b'.')?;
+        }
+        if label.is_ascii() {
+            out.extend(label.as_bytes())?;
+        } else {
+            out.extend(ACE_PREFIX)?;
+            encode_label(label, &mut out)?;
+        }
+    }
+
+    let len = out.len;
+    out.buf
+        .get(..len)
+        .and_then(|name| core::str::from_utf8(name).ok())
+        .ok_or(TlsError::EncodeError)
+}
+
+/// RFC 3492, Section 6.3.  Encoding procedure
+fn encode_label(label: &str, out: &mut Output<'_>) -> Result<(), TlsError> {
+    let chars = || label.chars().flat_map(char::to_lowercase).map(u32::from);
+
+    let mut basic = 0;
+    for c in chars().filter(|&c| c < INITIAL_N) {
+        out.push(c as u8)?;
+        basic += 1;
+    }
+    if basic > 0 {
+        out.push(b'-')?;
+    }
+
+    let total = chars().count() as u32;
+    let mut handled = basic;
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    while handled < total {
+        let m = chars()
+            .filter(|&c| c >= n)
+            .min()
+            .ok_or(TlsError::EncodeError)?;
+        delta = (m - n)
+            .checked_mul(handled + 1)
+            .and_then(|d| d.checked_add(delta))
+            .ok_or(TlsError::EncodeError)?;
+        n = m;
+        for c in chars() {
+            if c < n {
+                delta = delta.checked_add(1).ok_or(TlsError::EncodeError)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        TMIN
+                    } else if k >= bias + TMAX {
+                        TMAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    out.push(digit(t + (q - t) % (BASE - t)))?;
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                out.push(digit(q))?;
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    Ok(())
+}
+
+/// RFC 3492, Section 6.1.  Bias adaptation function
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (((BASE - TMIN + 1) * delta) / (delta + SKEW))
+}
+
+/// RFC 3492, Section 5.  Parameter values for Punycode
+/// digit values 0..25 are represented by a..z and 26..35 by 0..9
+fn digit(d: u32) -> u8 {
+    if d < 26 {
+        b'a' + d as u8
+    } else {
+        b'0' + (d - 26) as u8
+    }
+}
+
+struct Output<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl Output<'_> {
+    fn push(&mut self, c: u8) -> Result<(), TlsError> {
+        let slot = self.buf.get_mut(self.len).ok_or(TlsError::EncodeError)?;
+        *slot = c;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn extend(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        data.iter().try_for_each(|&c| self.push(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ascii() {
+        let mut buf = [0; MAX_NAME_LEN];
+        assert_eq!(
+            "broker.iot.example.com",
+            to_ascii("broker.iot.example.com", &mut buf).unwrap()
+        );
+        assert_eq!(
+            "xn--bcher-kva.example",
+            to_ascii("bücher.example", &mut buf).unwrap()
+        );
+        assert_eq!(
+            "xn--mnchen-3ya.example",
+            to_ascii("MÜNCHEN.example", &mut buf).unwrap()
+        );
+        assert_eq!(
+            "xn--r8jz45g.xn--zckzah",
+            to_ascii("例え。テスト", &mut buf).unwrap()
+        );
+        assert!(to_ascii("bücher.example", &mut [0; 8]).is_err());
+    }
+}
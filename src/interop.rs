@@ -0,0 +1,315 @@
+//! Interop smoke tests of a client configuration against in-process servers.
+//!
+//! An [`InteropServer`] runs a rustls, or with the `interop-openssl` feature an OpenSSL, echo
+//! server on a thread. The server is connected to the client through a [`Pipe`], so the tests run
+//! on the host without network access, for example in CI:
+//!
+//! ```ignore
+//! use embedded_tls::blocking::*;
+//! use embedded_tls::interop::{InteropServer, ServerImplementation};
+//!
+//! let server = InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN_PEM, KEY_PEM)
+//!     .with_named_groups(&[NamedGroup::X25519]);
+//! let config = TlsConfig::new().with_server_name("localhost");
+//! server
+//!     .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut rand::rngs::OsRng)
+//!     .unwrap();
+//! ```
+use std::io;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use embedded_io::blocking::{Read, Write};
+
+use crate::blocking::TlsConnection;
+use crate::config::{NamedGroup, TlsCipherSuite, TlsConfig, TlsContext, TlsRng, TlsVerifier};
+use crate::testing::Pipe;
+use crate::TlsError;
+
+const PING: &[u8] = b"ping";
+
+/// The TLS library used by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerImplementation {
+    Rustls,
+    #[cfg(feature = "interop-openssl")]
+    OpenSsl,
+}
+
+#[derive(Debug)]
+pub enum InteropError {
+    /// The server implementation does not support the cipher suite or a named group.
+    Unsupported,
+    /// The server could not be configured, or failed during the connection.
+    Server(String),
+    /// The client failed.
+    Client(TlsError),
+    /// The server did not echo the data sent by the client.
+    UnexpectedData,
+}
+
+impl From<TlsError> for InteropError {
+    fn from(e: TlsError) -> Self {
+        InteropError::Client(e)
+    }
+}
+
+fn server_error(e: impl ToString) -> InteropError {
+    InteropError::Server(e.to_string())
+}
+
+/// An echo server configuration.
+///
+/// The server only negotiates TLS 1.3 and the cipher suite of the client. The certificate chain
+/// and the private key are PEM encoded.
+#[derive(Debug, Clone)]
+pub struct InteropServer<'a> {
+    implementation: ServerImplementation,
+    cert_chain: &'a [u8],
+    private_key: &'a [u8],
+    named_groups: &'a [NamedGroup],
+    max_early_data_size: u32,
+}
+
+impl<'a> InteropServer<'a> {
+    pub fn new(
+        implementation: ServerImplementation,
+        cert_chain: &'a [u8],
+        private_key: &'a [u8],
+    ) -> Self {
+        Self {
+            implementation,
+            cert_chain,
+            private_key,
+            named_groups: &[],
+            max_early_data_size: 0,
+        }
+    }
+
+    /// Restricts the key exchange groups of the server, which uses its defaults otherwise.
+    pub fn with_named_groups(mut self, named_groups: &'a [NamedGroup]) -> Self {
+        self.named_groups = named_groups;
+        self
+    }
+
+    /// Lets the server accept early data.
+    pub fn with_max_early_data_size(mut self, max_early_data_size: u32) -> Self {
+        self.max_early_data_size = max_early_data_size;
+        self
+    }
+
+    /// Starts the server for a single connection, and returns the end of the pipe for the client.
+    ///
+    /// The server echoes the application data until the client closes the connection.
+    pub fn spawn<CipherSuite>(
+        &self,
+    ) -> Result<(Pipe, JoinHandle<Result<(), InteropError>>), InteropError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let (client, server) = Pipe::new();
+        let handle = match self.implementation {
+            ServerImplementation::Rustls => {
+                let config = self.rustls_config(CipherSuite::CODE_POINT)?;
+                std::thread::spawn(move || {
+                    let connection = rustls::ServerConnection::new(config).map_err(server_error)?;
+                    echo(rustls::StreamOwned::new(connection, server))
+                })
+            }
+            #[cfg(feature = "interop-openssl")]
+            ServerImplementation::OpenSsl => {
+                let acceptor = self.openssl_acceptor(CipherSuite::CODE_POINT)?;
+                std::thread::spawn(move || {
+                    use openssl::ssl::HandshakeError;
+
+                    let stream = acceptor.accept(server).map_err(|e| match e {
+                        HandshakeError::SetupFailure(e) => server_error(e),
+                        HandshakeError::Failure(s) => server_error(s.error()),
+                        HandshakeError::WouldBlock(s) => server_error(s.error()),
+                    })?;
+                    echo(stream)
+                })
+            }
+        };
+        Ok((client, handle))
+    }
+
+    /// Opens a connection with the client configuration, exchanges application data and closes
+    /// the connection.
+    pub fn smoke_test<'c, CipherSuite, Verifier, RNG>(
+        &self,
+        config: &'c TlsConfig<'c, CipherSuite>,
+        rng: &'c mut RNG,
+    ) -> Result<(), InteropError>
+    where
+        CipherSuite: TlsCipherSuite + 'static,
+        Verifier: TlsVerifier<'c, CipherSuite>,
+        RNG: TlsRng,
+    {
+        let (pipe, server) = self.spawn::<CipherSuite>()?;
+
+        let mut read_record_buffer = [0; 16640];
+        let mut write_record_buffer = [0; 16640];
+        let mut tls: TlsConnection<Pipe, CipherSuite> =
+            TlsConnection::new(pipe, &mut read_record_buffer, &mut write_record_buffer);
+
+        let result = tls
+            .open::<RNG, Verifier>(TlsContext::new(config, rng))
+            .and_then(|_| ping(&mut tls));
+        let result = match (result, tls.close()) {
+            (Ok(echoed), Ok(_)) => Ok(echoed),
+            (Err(e), _) | (_, Err((_, e))) => Err(InteropError::Client(e)),
+        };
+
+        // A failing client also makes the server fail, so the client error is reported first
+        let server = server
+            .join()
+            .map_err(|_| server_error("server thread panicked"))?;
+        if result? {
+            server
+        } else {
+            Err(InteropError::UnexpectedData)
+        }
+    }
+
+    fn rustls_config(&self, code_point: u16) -> Result<Arc<rustls::ServerConfig>, InteropError> {
+        let suites: Vec<_> = rustls::ALL_CIPHER_SUITES
+            .iter()
+            .filter(|suite| suite.suite().get_u16() == code_point)
+            .copied()
+            .collect();
+        if suites.is_empty() {
+            return Err(InteropError::Unsupported);
+        }
+
+        let groups = if self.named_groups.is_empty() {
+            rustls::ALL_KX_GROUPS.to_vec()
+        } else {
+            self.named_groups
+                .iter()
+                .map(|group| match group {
+                    NamedGroup::X25519 => Ok(&rustls::kx_group::X25519),
+                    NamedGroup::Secp256r1 => Ok(&rustls::kx_group::SECP256R1),
+                    NamedGroup::Secp384r1 => Ok(&rustls::kx_group::SECP384R1),
+                    _ => Err(InteropError::Unsupported),
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        let certs = rustls_pemfile::certs(&mut &*self.cert_chain)
+            .map_err(server_error)?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect();
+        let key = rustls_pemfile::read_all(&mut &*self.private_key)
+            .map_err(server_error)?
+            .into_iter()
+            .find_map(|item| match item {
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+                _ => None,
+            })
+            .ok_or_else(|| server_error("no private key found"))?;
+
+        let mut config = rustls::ServerConfig::builder()
+            .with_cipher_suites(&suites)
+            .with_kx_groups(&groups)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(server_error)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(server_error)?;
+        config.max_early_data_size = self.max_early_data_size;
+        Ok(Arc::new(config))
+    }
+
+    #[cfg(feature = "interop-openssl")]
+    fn openssl_acceptor(&self, code_point: u16) -> Result<openssl::ssl::SslAcceptor, InteropError> {
+        use openssl::pkey::PKey;
+        use openssl::ssl::{SslAcceptor, SslMethod, SslVersion};
+        use openssl::x509::X509;
+
+        let suite = match code_point {
+            0x1301 => "TLS_AES_128_GCM_SHA256",
+            0x1302 => "TLS_AES_256_GCM_SHA384",
+            0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+            0x1304 => "TLS_AES_128_CCM_SHA256",
+            0x1305 => "TLS_AES_128_CCM_8_SHA256",
+            _ => return Err(InteropError::Unsupported),
+        };
+        let groups = self
+            .named_groups
+            .iter()
+            .map(|group| match group {
+                NamedGroup::Secp256r1 => Ok("P-256"),
+                NamedGroup::Secp384r1 => Ok("P-384"),
+                NamedGroup::Secp521r1 => Ok("P-521"),
+                NamedGroup::X25519 => Ok("X25519"),
+                NamedGroup::X448 => Ok("X448"),
+                _ => Err(InteropError::Unsupported),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut chain = X509::stack_from_pem(self.cert_chain).map_err(server_error)?;
+        if chain.is_empty() {
+            return Err(server_error("no certificate found"));
+        }
+        let leaf = chain.remove(0);
+        let key = PKey::private_key_from_pem(self.private_key).map_err(server_error)?;
+
+        let mut builder =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).map_err(server_error)?;
+        builder.set_certificate(&leaf).map_err(server_error)?;
+        for cert in chain {
+            builder.add_extra_chain_cert(cert).map_err(server_error)?;
+        }
+        builder.set_private_key(&key).map_err(server_error)?;
+        builder
+            .set_min_proto_version(Some(SslVersion::TLS1_3))
+            .map_err(server_error)?;
+        builder.set_ciphersuites(suite).map_err(server_error)?;
+        if !groups.is_empty() {
+            builder
+                .set_groups_list(&groups.join(":"))
+                .map_err(server_error)?;
+        }
+        builder
+            .set_max_early_data(self.max_early_data_size)
+            .map_err(server_error)?;
+        Ok(builder.build())
+    }
+}
+
+/// Sends a ping, and returns whether it was echoed.
+fn ping<CipherSuite>(tls: &mut TlsConnection<Pipe, CipherSuite>) -> Result<bool, TlsError>
+where
+    CipherSuite: TlsCipherSuite + 'static,
+{
+    tls.write_all(PING)?;
+    tls.flush()?;
+
+    let mut echoed = [0; PING.len()];
+    let mut pos = 0;
+    while pos < echoed.len() {
+        match tls.read(&mut echoed[pos..])? {
+            0 => return Ok(false),
+            len => pos += len,
+        }
+    }
+    Ok(echoed == PING)
+}
+
+fn echo(mut stream: impl io::Read + io::Write) -> Result<(), InteropError> {
+    let mut buffer = [0; 1024];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(len) => {
+                stream.write_all(&buffer[..len]).map_err(server_error)?;
+                stream.flush().map_err(server_error)?;
+            }
+            Err(e) => return Err(server_error(e)),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use digest::core_api::BlockSizeUser;
+use digest::{Digest, OutputSizeUser};
+use generic_array::GenericArray;
+use hmac::{Mac, SimpleHmac};
+use subtle::ConstantTimeEq;
+
+use crate::TlsError;
+
+/// The HKDF from the `hkdf` crate, using the HMAC of the `hmac` crate over any [`Digest`].
+pub type SimpleHkdf<Hash> = hkdf::Hkdf<Hash, SimpleHmac<Hash>>;
+
+/// The output of the hash function of a [`TlsHkdf`].
+pub type HkdfOutput<Hkdf> =
+    GenericArray<u8, <<Hkdf as TlsHkdf>::Hash as OutputSizeUser>::OutputSize>;
+
+/// The HKDF and HMAC used by the key schedule of a cipher suite.
+///
+/// [`SimpleHkdf`] works with any [`Digest`], so a hash peripheral is used by the key schedule as
+/// soon as it implements `Digest`. Implement this trait directly to also offload the HMAC
+/// computations, for example to a peripheral with native HMAC support.
+pub trait TlsHkdf: Sized {
+    type Hash: OutputSizeUser;
+
+    /// HKDF-Extract, returning the pseudorandom key and an instance for expanding it.
+    fn extract(salt: Option<&[u8]>, ikm: &[u8]) -> (HkdfOutput<Self>, Self);
+
+    /// Create an instance for expanding an existing pseudorandom key.
+    fn from_prk(prk: &[u8]) -> Result<Self, TlsError>;
+
+    /// HKDF-Expand, filling all of `okm`.
+    fn expand(&self, info: &[u8], okm: &mut [u8]) -> Result<(), TlsError>;
+
+    /// Compute the HMAC of `data`.
+    fn hmac(key: &[u8], data: &[u8]) -> Result<HkdfOutput<Self>, TlsError>;
+
+    /// Verify the HMAC `tag` of `data` in constant time.
+    fn verify_hmac(key: &[u8], data: &[u8], tag: &[u8]) -> Result<bool, TlsError> {
+        let expected = Self::hmac(key, data)?;
+        Ok(constant_time_eq(&expected, tag))
+    }
+}
+
+/// Compare two MACs in constant time, so that a mismatch does not reveal how many leading bytes
+/// were correct. Slices of different length are never equal.
+///
+/// Every MAC check of the handshake goes through this function; use it as well when overriding
+/// [`TlsHkdf::verify_hmac`].
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+impl<Hash> TlsHkdf for SimpleHkdf<Hash>
+where
+    Hash: Digest + BlockSizeUser + Clone,
+{
+    type Hash = Hash;
+
+    fn extract(salt: Option<&[u8]>, ikm: &[u8]) -> (HkdfOutput<Self>, Self) {
+        hkdf::Hkdf::<Hash, SimpleHmac<Hash>>::extract(salt, ikm)
+    }
+
+    fn from_prk(prk: &[u8]) -> Result<Self, TlsError> {
+        hkdf::Hkdf::<Hash, SimpleHmac<Hash>>::from_prk(prk).map_err(|_| TlsError::CryptoError)
+    }
+
+    fn expand(&self, info: &[u8], okm: &mut [u8]) -> Result<(), TlsError> {
+        hkdf::Hkdf::<Hash, SimpleHmac<Hash>>::expand(self, info, okm)
+            .map_err(|_| TlsError::CryptoError)
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Result<HkdfOutput<Self>, TlsError> {
+        let mut hmac =
+            SimpleHmac::<Hash>::new_from_slice(key).map_err(|_| TlsError::CryptoError)?;
+        Mac::update(&mut hmac, data);
+        Ok(hmac.finalize().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Sha256;
+
+    // RFC 5869, A.1.  Test Case 1
+    const IKM: [u8; 22] = [0x0b; 22];
+    const SALT: [u8; 13] = [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+    ];
+    const INFO: [u8; 10] = [0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9];
+    const PRK: [u8; 32] = [
+        0x07, 0x77, 0x09, 0x36, 0x2c, 0x2e, 0x32, 0xdf, 0x0d, 0xdc, 0x3f, 0x0d, 0xc4, 0x7b, 0xba,
+        0x63, 0x90, 0xb6, 0xc7, 0x3b, 0xb5, 0x0f, 0x9c, 0x31, 0x22, 0xec, 0x84, 0x4a, 0xd7, 0xc2,
+        0xb3, 0xe5,
+    ];
+    const OKM: [u8; 42] = [
+        0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36, 0x2f,
+        0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56, 0xec, 0xc4,
+        0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
+    ];
+
+    #[test]
+    fn test_simple_hkdf() {
+        let (prk, hkdf) = <SimpleHkdf<Sha256> as TlsHkdf>::extract(Some(&SALT), &IKM);
+        assert_eq!(&PRK[..], prk.as_slice());
+
+        let mut okm = [0; 42];
+        TlsHkdf::expand(&hkdf, &INFO, &mut okm).unwrap();
+        assert_eq!(OKM, okm);
+
+        let hkdf = <SimpleHkdf<Sha256> as TlsHkdf>::from_prk(&PRK).unwrap();
+        let mut okm = [0; 42];
+        TlsHkdf::expand(&hkdf, &INFO, &mut okm).unwrap();
+        assert_eq!(OKM, okm);
+    }
+
+    #[test]
+    fn test_simple_hmac() {
+        let tag = SimpleHkdf::<Sha256>::hmac(&PRK, &INFO).unwrap();
+        assert!(SimpleHkdf::<Sha256>::verify_hmac(&PRK, &INFO, &tag).unwrap());
+        assert!(!SimpleHkdf::<Sha256>::verify_hmac(&PRK, &IKM, &tag).unwrap());
+        assert!(!SimpleHkdf::<Sha256>::verify_hmac(&PRK, &INFO, &tag[..16]).unwrap());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(&PRK, &PRK));
+        assert!(!constant_time_eq(&PRK, &OKM[..32]));
+        assert!(!constant_time_eq(&PRK, &PRK[..31]));
+        assert!(constant_time_eq(&[], &[]));
+    }
+}
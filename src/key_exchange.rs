@@ -0,0 +1,436 @@
+use heapless::Vec;
+use rand_core::{CryptoRng, RngCore};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::named_groups::NamedGroup;
+use crate::TlsError;
+
+#[cfg(feature = "mlkem")]
+use ml_kem::{kem::Decapsulate, EncodedSizeUser, KemCore};
+
+#[cfg(feature = "brainpool")]
+use bp256::elliptic_curve::sec1::ToEncodedPoint;
+#[cfg(feature = "brainpool")]
+use bp256::r1::BrainpoolP256r1;
+#[cfg(feature = "brainpool")]
+use bp384::r1::BrainpoolP384r1;
+
+#[cfg(feature = "ffdhe")]
+use crate::ffdhe::{Ffdhe2048Secret, Ffdhe3072Secret, FfdheSecret, FFDHE2048, FFDHE3072};
+
+/// Size of the largest encoded public key of the supported groups.
+pub(crate) const MAX_PUBLIC_KEY_LEN: usize = max(max(ECDHE_MAX_LEN, HYBRID_MAX_LEN), FFDHE_MAX_LEN);
+
+/// Size of the largest shared secret of the supported groups. The hybrid groups produce two
+/// concatenated 32 byte secrets.
+pub(crate) const MAX_SHARED_SECRET_LEN: usize = max(66, FFDHE_MAX_LEN);
+
+/// Uncompressed secp521r1 point
+const ECDHE_MAX_LEN: usize = 133;
+
+/// SecP256r1MLKEM768 key share
+#[cfg(feature = "mlkem")]
+const HYBRID_MAX_LEN: usize = 65 + MLKEM768_ENCAPSULATION_KEY_LEN;
+#[cfg(not(feature = "mlkem"))]
+const HYBRID_MAX_LEN: usize = 0;
+
+#[cfg(feature = "ffdhe")]
+const FFDHE_MAX_LEN: usize = crate::ffdhe::MAX_FFDHE_LEN;
+#[cfg(not(feature = "ffdhe"))]
+const FFDHE_MAX_LEN: usize = 0;
+
+#[cfg(feature = "mlkem")]
+const MLKEM768_ENCAPSULATION_KEY_LEN: usize = 1184;
+#[cfg(feature = "mlkem")]
+const MLKEM768_CIPHERTEXT_LEN: usize = 1088;
+
+const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+/// An encoded key share public key.
+pub type PublicKey = Vec<u8, MAX_PUBLIC_KEY_LEN>;
+
+/// An ephemeral private key used for the key exchange of a single handshake.
+pub enum EphemeralSecret {
+    Secp256r1(p256::ecdh::EphemeralSecret),
+    Secp384r1(p384::ecdh::EphemeralSecret),
+    Secp521r1(p521::ecdh::EphemeralSecret),
+    X25519(x25519_dalek::EphemeralSecret),
+    #[cfg(feature = "mlkem")]
+    X25519MlKem768(x25519_dalek::EphemeralSecret, MlKem768Secret),
+    #[cfg(feature = "mlkem")]
+    Secp256r1MlKem768(p256::ecdh::EphemeralSecret, MlKem768Secret),
+    #[cfg(feature = "brainpool")]
+    BrainpoolP256r1(bp256::elliptic_curve::ecdh::EphemeralSecret<BrainpoolP256r1>),
+    #[cfg(feature = "brainpool")]
+    BrainpoolP384r1(bp384::elliptic_curve::ecdh::EphemeralSecret<BrainpoolP384r1>),
+    #[cfg(feature = "sm")]
+    CurveSm2(elliptic_curve::ecdh::EphemeralSecret<sm2::Sm2>),
+    #[cfg(feature = "ffdhe")]
+    Ffdhe2048(Ffdhe2048Secret),
+    #[cfg(feature = "ffdhe")]
+    Ffdhe3072(Ffdhe3072Secret),
+}
+
+impl EphemeralSecret {
+    /// Generate a new ephemeral key for the given group.
+    pub fn generate<RNG>(group: NamedGroup, rng: &mut RNG) -> Result<Self, TlsError>
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        match group {
+            NamedGroup::Secp256r1 => Ok(Self::Secp256r1(p256::ecdh::EphemeralSecret::random(rng))),
+            NamedGroup::Secp384r1 => Ok(Self::Secp384r1(p384::ecdh::EphemeralSecret::random(rng))),
+            NamedGroup::Secp521r1 => Ok(Self::Secp521r1(p521::ecdh::EphemeralSecret::random(rng))),
+            NamedGroup::X25519 => Ok(Self::X25519(
+                x25519_dalek::EphemeralSecret::random_from_rng(&mut *rng),
+            )),
+            #[cfg(feature = "mlkem")]
+            NamedGroup::X25519MlKem768 => Ok(Self::X25519MlKem768(
+                x25519_dalek::EphemeralSecret::random_from_rng(&mut *rng),
+                MlKem768Secret::generate(rng),
+            )),
+            #[cfg(feature = "mlkem")]
+            NamedGroup::Secp256r1MlKem768 => Ok(Self::Secp256r1MlKem768(
+                p256::ecdh::EphemeralSecret::random(&mut *rng),
+                MlKem768Secret::generate(rng),
+            )),
+            #[cfg(feature = "brainpool")]
+            NamedGroup::BrainpoolP256r1tls13 => Ok(Self::BrainpoolP256r1(
+                bp256::elliptic_curve::ecdh::EphemeralSecret::random(rng),
+            )),
+            #[cfg(feature = "brainpool")]
+            NamedGroup::BrainpoolP384r1tls13 => Ok(Self::BrainpoolP384r1(
+                bp384::elliptic_curve::ecdh::EphemeralSecret::random(rng),
+            )),
+            #[cfg(feature = "sm")]
+            NamedGroup::CurveSm2 => Ok(Self::CurveSm2(
+                elliptic_curve::ecdh::EphemeralSecret::random(rng),
+            )),
+            #[cfg(feature = "ffdhe")]
+            NamedGroup::Ffdhe2048 => Ok(Self::Ffdhe2048(FfdheSecret::generate(&FFDHE2048, rng))),
+            #[cfg(feature = "ffdhe")]
+            NamedGroup::Ffdhe3072 => Ok(Self::Ffdhe3072(FfdheSecret::generate(&FFDHE3072, rng))),
+            _ => Err(TlsError::Unimplemented),
+        }
+    }
+
+    pub fn group(&self) -> NamedGroup {
+        match self {
+            Self::Secp256r1(_) => NamedGroup::Secp256r1,
+            Self::Secp384r1(_) => NamedGroup::Secp384r1,
+            Self::Secp521r1(_) => NamedGroup::Secp521r1,
+            Self::X25519(_) => NamedGroup::X25519,
+            #[cfg(feature = "mlkem")]
+            Self::X25519MlKem768(..) => NamedGroup::X25519MlKem768,
+            #[cfg(feature = "mlkem")]
+            Self::Secp256r1MlKem768(..) => NamedGroup::Secp256r1MlKem768,
+            #[cfg(feature = "brainpool")]
+            Self::BrainpoolP256r1(_) => NamedGroup::BrainpoolP256r1tls13,
+            #[cfg(feature = "brainpool")]
+            Self::BrainpoolP384r1(_) => NamedGroup::BrainpoolP384r1tls13,
+            #[cfg(feature = "sm")]
+            Self::CurveSm2(_) => NamedGroup::CurveSm2,
+            #[cfg(feature = "ffdhe")]
+            Self::Ffdhe2048(_) => NamedGroup::Ffdhe2048,
+            #[cfg(feature = "ffdhe")]
+            Self::Ffdhe3072(_) => NamedGroup::Ffdhe3072,
+        }
+    }
+
+    /// The public key encoded as the `key_exchange` field of a KeyShareEntry.
+    pub(crate) fn public_key(&self) -> Result<PublicKey, TlsError> {
+        let mut public_key = Vec::new();
+        let result = match self {
+            Self::Secp256r1(secret) => {
+                let point = p256::EncodedPoint::from(&secret.public_key());
+                public_key.extend_from_slice(point.as_ref())
+            }
+            Self::Secp384r1(secret) => {
+                let point = p384::EncodedPoint::from(&secret.public_key());
+                public_key.extend_from_slice(point.as_ref())
+            }
+            Self::Secp521r1(secret) => {
+                let point = p521::EncodedPoint::from(&secret.public_key());
+                public_key.extend_from_slice(point.as_ref())
+            }
+            Self::X25519(secret) => {
+                public_key.extend_from_slice(x25519_dalek::PublicKey::from(secret).as_bytes())
+            }
+            // The ML-KEM encapsulation key comes first, followed by the X25519 public key
+            #[cfg(feature = "mlkem")]
+            Self::X25519MlKem768(x25519, mlkem) => public_key
+                .extend_from_slice(&mlkem.encapsulation_key.as_bytes())
+                .and_then(|_| {
+                    public_key.extend_from_slice(x25519_dalek::PublicKey::from(x25519).as_bytes())
+                }),
+            // The uncompressed P-256 point comes first, followed by the ML-KEM encapsulation key
+            #[cfg(feature = "mlkem")]
+            Self::Secp256r1MlKem768(secp256r1, mlkem) => {
+                let point = p256::EncodedPoint::from(&secp256r1.public_key());
+                public_key
+                    .extend_from_slice(point.as_ref())
+                    .and_then(|_| public_key.extend_from_slice(&mlkem.encapsulation_key.as_bytes()))
+            }
+            #[cfg(feature = "brainpool")]
+            Self::BrainpoolP256r1(secret) => {
+                let point = secret.public_key().to_encoded_point(false);
+                public_key.extend_from_slice(point.as_bytes())
+            }
+            #[cfg(feature = "brainpool")]
+            Self::BrainpoolP384r1(secret) => {
+                let point = secret.public_key().to_encoded_point(false);
+                public_key.extend_from_slice(point.as_bytes())
+            }
+            #[cfg(feature = "sm")]
+            Self::CurveSm2(secret) => {
+                use elliptic_curve::sec1::ToEncodedPoint;
+                let point = secret.public_key().to_encoded_point(false);
+                public_key.extend_from_slice(point.as_bytes())
+            }
+            #[cfg(feature = "ffdhe")]
+            Self::Ffdhe2048(secret) => public_key.extend_from_slice(secret.public_key().as_ref()),
+            #[cfg(feature = "ffdhe")]
+            Self::Ffdhe3072(secret) => public_key.extend_from_slice(secret.public_key().as_ref()),
+        };
+        result.map_err(|_| TlsError::EncodeError)?;
+        Ok(public_key)
+    }
+
+    /// Compute the shared secret from the peer's encoded public key.
+    pub(crate) fn diffie_hellman(self, peer: &[u8]) -> Result<SharedSecret, TlsError> {
+        match self {
+            Self::Secp256r1(secret) => {
+                let peer = p256::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.diffie_hellman(&peer);
+                SharedSecret::new(&[&shared.raw_secret_bytes()[..]])
+            }
+            Self::Secp384r1(secret) => {
+                let peer = p384::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.diffie_hellman(&peer);
+                SharedSecret::new(&[&shared.raw_secret_bytes()[..]])
+            }
+            Self::Secp521r1(secret) => {
+                let peer = p521::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.diffie_hellman(&peer);
+                SharedSecret::new(&[&shared.raw_secret_bytes()[..]])
+            }
+            Self::X25519(secret) => {
+                let shared = x25519(secret, peer)?;
+                SharedSecret::new(&[&shared.as_bytes()[..]])
+            }
+            // The server share is the ML-KEM ciphertext followed by the X25519 public key, and
+            // the shared secret is the ML-KEM shared secret followed by the X25519 shared secret.
+            #[cfg(feature = "mlkem")]
+            Self::X25519MlKem768(x25519_secret, mlkem) => {
+                if peer.len() != MLKEM768_CIPHERTEXT_LEN + 32 {
+                    return Err(TlsError::InvalidKeyShare);
+                }
+                let (ciphertext, peer) = peer.split_at(MLKEM768_CIPHERTEXT_LEN);
+                let mlkem_shared = mlkem.decapsulate(ciphertext)?;
+                let x25519_shared = x25519(x25519_secret, peer)?;
+                SharedSecret::new(&[&mlkem_shared[..], &x25519_shared.as_bytes()[..]])
+            }
+            // The server share is the P-256 point followed by the ML-KEM ciphertext, and the
+            // shared secret is the ECDH shared secret followed by the ML-KEM shared secret.
+            #[cfg(feature = "mlkem")]
+            Self::Secp256r1MlKem768(secp256r1, mlkem) => {
+                if peer.len() != 65 + MLKEM768_CIPHERTEXT_LEN {
+                    return Err(TlsError::InvalidKeyShare);
+                }
+                let (peer, ciphertext) = peer.split_at(65);
+                let peer = p256::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let ecdh_shared = secp256r1.diffie_hellman(&peer);
+                let mlkem_shared = mlkem.decapsulate(ciphertext)?;
+                SharedSecret::new(&[&ecdh_shared.raw_secret_bytes()[..], &mlkem_shared[..]])
+            }
+            #[cfg(feature = "brainpool")]
+            Self::BrainpoolP256r1(secret) => {
+                let peer = bp256::r1::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.diffie_hellman(&peer);
+                SharedSecret::new(&[&shared.raw_secret_bytes()[..]])
+            }
+            #[cfg(feature = "brainpool")]
+            Self::BrainpoolP384r1(secret) => {
+                let peer = bp384::r1::PublicKey::from_sec1_bytes(peer)
+                    .map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.diffie_hellman(&peer);
+                SharedSecret::new(&[&shared.raw_secret_bytes()[..]])
+            }
+            // Section 3.2.1.  SM2 Key Exchange Mode
+            // [...] the SM2 key exchange protocol is not used. Instead, ECDHE is performed
+            // over curveSM2.
+            #[cfg(feature = "sm")]
+            Self::CurveSm2(secret) => {
+                let peer =
+                    sm2::PublicKey::from_sec1_bytes(peer).map_err(|_| TlsError::InvalidKeyShare)?;
+                let shared = secret.diffie_hellman(&peer);
+                SharedSecret::new(&[&shared.raw_secret_bytes()[..]])
+            }
+            #[cfg(feature = "ffdhe")]
+            Self::Ffdhe2048(secret) => SharedSecret::new(&[secret.diffie_hellman(peer)?.as_ref()]),
+            #[cfg(feature = "ffdhe")]
+            Self::Ffdhe3072(secret) => SharedSecret::new(&[secret.diffie_hellman(peer)?.as_ref()]),
+        }
+    }
+}
+
+fn x25519(
+    secret: x25519_dalek::EphemeralSecret,
+    peer: &[u8],
+) -> Result<x25519_dalek::SharedSecret, TlsError> {
+    let peer: [u8; 32] = peer.try_into().map_err(|_| TlsError::InvalidKeyShare)?;
+    let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(peer));
+
+    // Section 7.4.2.  Elliptic Curve Diffie-Hellman
+    // For X25519 and X448, [...] Implementations MUST check whether the
+    // computed Diffie-Hellman shared secret is the all-zero value and
+    // abort if so
+    if !shared.was_contributory() {
+        return Err(TlsError::InvalidKeyShare);
+    }
+    Ok(shared)
+}
+
+/// The ML-KEM-768 half of a hybrid key share.
+#[cfg(feature = "mlkem")]
+pub struct MlKem768Secret {
+    decapsulation_key: <ml_kem::MlKem768 as KemCore>::DecapsulationKey,
+    encapsulation_key: <ml_kem::MlKem768 as KemCore>::EncapsulationKey,
+}
+
+#[cfg(feature = "mlkem")]
+impl MlKem768Secret {
+    fn generate<RNG>(rng: &mut RNG) -> Self
+    where
+        RNG: CryptoRng + RngCore,
+    {
+        let (decapsulation_key, encapsulation_key) = ml_kem::MlKem768::generate(rng);
+        Self {
+            decapsulation_key,
+            encapsulation_key,
+        }
+    }
+
+    fn decapsulate(
+        &self,
+        ciphertext: &[u8],
+    ) -> Result<ml_kem::SharedKey<ml_kem::MlKem768>, TlsError> {
+        let ciphertext: ml_kem::Ciphertext<ml_kem::MlKem768> = ciphertext
+            .try_into()
+            .map_err(|_| TlsError::InvalidKeyShare)?;
+        self.decapsulation_key
+            .decapsulate(&ciphertext)
+            .map_err(|_| TlsError::InvalidKeyShare)
+    }
+}
+
+/// The raw shared secret resulting from the key exchange.
+pub struct SharedSecret(Vec<u8, MAX_SHARED_SECRET_LEN>);
+
+impl SharedSecret {
+    /// Create a shared secret from the concatenation of `parts`.
+    pub fn new(parts: &[&[u8]]) -> Result<Self, TlsError> {
+        let mut secret = Vec::new();
+        for part in parts {
+            secret
+                .extend_from_slice(part)
+                .map_err(|_| TlsError::InternalError)?;
+        }
+        Ok(Self(secret))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SharedSecret {
+    fn drop(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SharedSecret {}
+
+#[cfg(all(test, feature = "mlkem"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use ml_kem::kem::Encapsulate;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_x25519_mlkem768() {
+        let secret = EphemeralSecret::generate(NamedGroup::X25519MlKem768, &mut OsRng).unwrap();
+        let client_share = secret.public_key().unwrap();
+        assert_eq!(MLKEM768_ENCAPSULATION_KEY_LEN + 32, client_share.len());
+
+        // Act as the server: encapsulate to the client key and perform X25519
+        let (encapsulation_key, client_x25519) =
+            client_share.split_at(MLKEM768_ENCAPSULATION_KEY_LEN);
+        let encapsulation_key = <ml_kem::MlKem768 as KemCore>::EncapsulationKey::from_bytes(
+            &encapsulation_key.try_into().unwrap(),
+        );
+        let (ciphertext, mlkem_shared) = encapsulation_key.encapsulate(&mut OsRng).unwrap();
+
+        let server_x25519 = x25519_dalek::EphemeralSecret::random_from_rng(OsRng);
+        let server_public = x25519_dalek::PublicKey::from(&server_x25519);
+        let client_x25519: [u8; 32] = client_x25519.try_into().unwrap();
+        let x25519_shared =
+            server_x25519.diffie_hellman(&x25519_dalek::PublicKey::from(client_x25519));
+
+        let mut server_share: Vec<u8, { MLKEM768_CIPHERTEXT_LEN + 32 }> = Vec::new();
+        server_share.extend_from_slice(&ciphertext).unwrap();
+        server_share
+            .extend_from_slice(server_public.as_bytes())
+            .unwrap();
+
+        let shared = secret.diffie_hellman(&server_share).unwrap();
+        assert_eq!(&mlkem_shared[..], &shared.as_bytes()[..32]);
+        assert_eq!(x25519_shared.as_bytes(), &shared.as_bytes()[32..]);
+    }
+
+    #[test]
+    fn test_secp256r1_mlkem768() {
+        let secret = EphemeralSecret::generate(NamedGroup::Secp256r1MlKem768, &mut OsRng).unwrap();
+        let client_share = secret.public_key().unwrap();
+        assert_eq!(65 + MLKEM768_ENCAPSULATION_KEY_LEN, client_share.len());
+
+        // Act as the server: perform ECDH and encapsulate to the client key
+        let (client_point, encapsulation_key) = client_share.split_at(65);
+        let encapsulation_key = <ml_kem::MlKem768 as KemCore>::EncapsulationKey::from_bytes(
+            &encapsulation_key.try_into().unwrap(),
+        );
+        let (ciphertext, mlkem_shared) = encapsulation_key.encapsulate(&mut OsRng).unwrap();
+
+        let server_secret = p256::ecdh::EphemeralSecret::random(&mut OsRng);
+        let server_point = p256::EncodedPoint::from(&server_secret.public_key());
+        let ecdh_shared =
+            server_secret.diffie_hellman(&p256::PublicKey::from_sec1_bytes(client_point).unwrap());
+
+        let mut server_share: Vec<u8, { 65 + MLKEM768_CIPHERTEXT_LEN }> = Vec::new();
+        server_share
+            .extend_from_slice(server_point.as_ref())
+            .unwrap();
+        server_share.extend_from_slice(&ciphertext).unwrap();
+
+        let shared = secret.diffie_hellman(&server_share).unwrap();
+        assert_eq!(
+            &ecdh_shared.raw_secret_bytes()[..],
+            &shared.as_bytes()[..32]
+        );
+        assert_eq!(&mlkem_shared[..], &shared.as_bytes()[32..]);
+    }
+}
@@ -1,11 +1,21 @@
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::buffer::CryptoBuffer;
+use crate::config::{RecordLimit, RecordLimitAction};
 use crate::handshake::binder::PskBinder;
 use crate::handshake::finished::Finished;
+use crate::handshake::key_update::{KeyUpdate, KeyUpdateRequest};
+use crate::handshake::new_session_ticket::NewSessionTicket;
+use crate::heartbeat::Heartbeat;
+use crate::kdf::TlsHkdf;
+use crate::max_fragment_length::MaxFragmentLength;
+use crate::parse_buffer::ParseBuffer;
 use crate::{config::TlsCipherSuite, TlsError};
+use core::fmt::{Debug, Formatter};
 use digest::generic_array::ArrayLength;
 use digest::OutputSizeUser;
-use hmac::{Mac, SimpleHmac};
 use sha2::digest::generic_array::{typenum::Unsigned, GenericArray};
 use sha2::Digest;
+use zeroize::{Zeroize, Zeroizing};
 
 pub type HashOutputSize<CipherSuite> =
     <<CipherSuite as TlsCipherSuite>::Hash as OutputSizeUser>::OutputSize;
@@ -15,28 +25,29 @@ pub type IvArray<CipherSuite> = GenericArray<u8, <CipherSuite as TlsCipherSuite>
 pub type KeyArray<CipherSuite> = GenericArray<u8, <CipherSuite as TlsCipherSuite>::KeyLen>;
 pub type HashArray<CipherSuite> = GenericArray<u8, HashOutputSize<CipherSuite>>;
 
-type Hkdf<CipherSuite> = hkdf::Hkdf<
-    <CipherSuite as TlsCipherSuite>::Hash,
-    SimpleHmac<<CipherSuite as TlsCipherSuite>::Hash>,
->;
+type Hkdf<CipherSuite> = <CipherSuite as TlsCipherSuite>::Hkdf;
 
+/// A pseudorandom key of the key schedule.
+///
+/// Only the key itself is kept, and an HKDF instance is created for each expansion, so that the
+/// key can be wiped when it is replaced or dropped.
 enum Secret<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
     Uninitialized,
-    Initialized(Hkdf<CipherSuite>),
+    Initialized(HashArray<CipherSuite>),
 }
 
 impl<CipherSuite> Secret<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    fn replace(&mut self, secret: Hkdf<CipherSuite>) {
-        *self = Self::Initialized(secret);
+    fn replace(&mut self, prk: &HashArray<CipherSuite>) {
+        *self = Self::Initialized(prk.clone());
     }
 
-    fn as_ref(&self) -> Result<&Hkdf<CipherSuite>, TlsError> {
+    fn as_ref(&self) -> Result<&HashArray<CipherSuite>, TlsError> {
         match self {
             Secret::Initialized(ref secret) => Ok(secret),
             Secret::Uninitialized => Err(TlsError::InternalError),
@@ -47,7 +58,20 @@ where
         &self,
         label: &[u8],
         context_type: ContextType<CipherSuite>,
-    ) -> Result<GenericArray<u8, N>, TlsError> {
+    ) -> Result<Zeroizing<GenericArray<u8, N>>, TlsError> {
+        match context_type {
+            ContextType::None => self.expand_label(label, &[]),
+            ContextType::Hash(context) => self.expand_label(label, &context),
+        }
+    }
+
+    /// HKDF-Expand-Label with an arbitrary context, which must fit in the label buffer along
+    /// with the label.
+    fn expand_label<N: ArrayLength<u8>>(
+        &self,
+        label: &[u8],
+        context: &[u8],
+    ) -> Result<Zeroizing<GenericArray<u8, N>>, TlsError> {
         //info!("make label {:?} {}", label, len);
         let mut hkdf_label = heapless_typenum::Vec::<u8, LabelBufferSize<CipherSuite>>::new();
         hkdf_label
@@ -65,28 +89,77 @@ where
             .extend_from_slice(label)
             .map_err(|_| TlsError::InternalError)?;
 
-        match context_type {
-            ContextType::None => {
-                hkdf_label.push(0).map_err(|_| TlsError::InternalError)?;
-            }
-            ContextType::Hash(context) => {
-                hkdf_label
-                    .extend_from_slice(&(context.len() as u8).to_be_bytes())
-                    .map_err(|_| TlsError::InternalError)?;
-                hkdf_label
-                    .extend_from_slice(&context)
-                    .map_err(|_| TlsError::InternalError)?;
-            }
-        }
+        hkdf_label
+            .extend_from_slice(&(context.len() as u8).to_be_bytes())
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .extend_from_slice(context)
+            .map_err(|_| TlsError::InternalError)?;
 
-        let mut okm = GenericArray::default();
+        let mut okm = Zeroizing::new(GenericArray::default());
         //info!("label {:x?}", label);
-        self.as_ref()?
+        Hkdf::<CipherSuite>::from_prk(self.as_ref()?)
+            .map_err(|_| TlsError::InternalError)?
             .expand(&hkdf_label, &mut okm)
             .map_err(|_| TlsError::CryptoError)?;
         //info!("expand {:x?}", okm);
         Ok(okm)
     }
+
+    /// HKDF-Expand-Label into an output of any length, for the labels chosen by the application
+    /// which do not fit in the label buffer.
+    fn expand_label_into(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        okm: &mut [u8],
+    ) -> Result<(), TlsError> {
+        // Section 7.1.  Key Schedule
+        //     struct {
+        //         uint16 length = Length;
+        //         opaque label<7..255> = "tls13 " + Label;
+        //         opaque context<0..255> = Context;
+        //     } HkdfLabel;
+        let label_len = u8::try_from(6 + label.len()).map_err(|_| TlsError::EncodeError)?;
+        let context_len = u8::try_from(context.len()).map_err(|_| TlsError::EncodeError)?;
+        let okm_len = u16::try_from(okm.len()).map_err(|_| TlsError::InsufficientSpace)?;
+
+        let mut hkdf_label = heapless::Vec::<u8, { 2 + 1 + 255 + 1 + 255 }>::new();
+        hkdf_label
+            .extend_from_slice(&okm_len.to_be_bytes())
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .push(label_len)
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .extend_from_slice(b"tls13 ")
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .extend_from_slice(label)
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .push(context_len)
+            .map_err(|_| TlsError::InternalError)?;
+        hkdf_label
+            .extend_from_slice(context)
+            .map_err(|_| TlsError::InternalError)?;
+
+        Hkdf::<CipherSuite>::from_prk(self.as_ref()?)
+            .map_err(|_| TlsError::InternalError)?
+            .expand(&hkdf_label, okm)
+            .map_err(|_| TlsError::CryptoError)
+    }
+}
+
+impl<CipherSuite> Drop for Secret<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn drop(&mut self) {
+        if let Secret::Initialized(prk) = self {
+            prk.zeroize();
+        }
+    }
 }
 
 pub struct SharedState<CipherSuite>
@@ -109,32 +182,48 @@ where
     }
 
     fn initialize(&mut self, ikm: &[u8]) {
-        let (secret, hkdf) = Hkdf::<CipherSuite>::extract(Some(self.secret.as_ref()), ikm);
-        self.hkdf.replace(hkdf);
-        self.secret = secret;
+        let (secret, _) = Hkdf::<CipherSuite>::extract(Some(self.secret.as_ref()), ikm);
+        let secret = Zeroizing::new(secret);
+        self.hkdf.replace(&secret);
+        self.secret.copy_from_slice(&secret);
     }
 
     fn derive_secret(
         &mut self,
         label: &[u8],
         context_type: ContextType<CipherSuite>,
-    ) -> Result<HashArray<CipherSuite>, TlsError> {
+    ) -> Result<Zeroizing<HashArray<CipherSuite>>, TlsError> {
         self.hkdf
             .make_expanded_hkdf_label::<HashOutputSize<CipherSuite>>(label, context_type)
     }
 
     fn derived(&mut self) -> Result<(), TlsError> {
-        self.secret = self.derive_secret(b"derived", ContextType::empty_hash())?;
+        let derived = self.derive_secret(b"derived", ContextType::empty_hash())?;
+        self.secret.copy_from_slice(&derived);
         Ok(())
     }
 }
 
+impl<CipherSuite> Drop for SharedState<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
 pub(crate) struct KeyScheduleState<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
     traffic_secret: Secret<CipherSuite>,
     counter: u64,
+    /// The write key and IV of a TLS 1.2 connection, which are derived from the master secret
+    /// rather than from a traffic secret. The IV is the salt followed by zeros, so that the
+    /// nonce is the salt followed by the sequence number.
+    #[cfg(feature = "tls12")]
+    tls12: Option<(Zeroizing<KeyArray<CipherSuite>>, IvArray<CipherSuite>)>,
 }
 
 impl<CipherSuite> KeyScheduleState<CipherSuite>
@@ -145,17 +234,28 @@ where
         Self {
             traffic_secret: Secret::Uninitialized,
             counter: 0,
+            #[cfg(feature = "tls12")]
+            tls12: None,
         }
     }
 
-    pub fn get_key(&self) -> Result<KeyArray<CipherSuite>, TlsError> {
+    pub fn get_key(&self) -> Result<Zeroizing<KeyArray<CipherSuite>>, TlsError> {
+        #[cfg(feature = "tls12")]
+        if let Some((key, _)) = &self.tls12 {
+            return Ok(key.clone());
+        }
         self.traffic_secret
             .make_expanded_hkdf_label(b"key", ContextType::None)
     }
 
     pub fn get_iv(&self) -> Result<IvArray<CipherSuite>, TlsError> {
+        #[cfg(feature = "tls12")]
+        if let Some((_, iv)) = &self.tls12 {
+            return Ok(iv.clone());
+        }
         self.traffic_secret
             .make_expanded_hkdf_label(b"iv", ContextType::None)
+            .map(|iv| (*iv).clone())
     }
 
     pub fn get_nonce(&self) -> Result<IvArray<CipherSuite>, TlsError> {
@@ -170,16 +270,235 @@ where
         transcript_hash: &CipherSuite::Hash,
     ) -> Result<(), TlsError> {
         let secret = shared.derive_secret(label, ContextType::transcript_hash(transcript_hash))?;
-        let traffic_secret =
-            Hkdf::<CipherSuite>::from_prk(&secret).map_err(|_| TlsError::InternalError)?;
 
-        self.traffic_secret.replace(traffic_secret);
+        self.traffic_secret.replace(&secret);
         self.counter = 0;
         Ok(())
     }
 
-    pub fn increment_counter(&mut self) {
-        self.counter = self.counter.checked_add(1).unwrap();
+    /// Section 5.3.  Per-Record Nonce
+    /// Because the size of sequence numbers is 64-bit, they should not wrap.  If a TLS
+    /// implementation would need to wrap a sequence number, it MUST either rekey (Section 4.6.3)
+    /// or terminate the connection.
+    ///
+    /// The write side is rekeyed or aborted by its record limit long before. If the sequence
+    /// number would wrap anyway, the traffic secret is discarded so that no further record can be
+    /// protected with a reused nonce.
+    pub fn increment_counter(&mut self) -> Result<(), TlsError> {
+        match self.counter.checked_add(1) {
+            Some(counter) => {
+                self.counter = counter;
+                Ok(())
+            }
+            None => {
+                self.traffic_secret = Secret::Uninitialized;
+                #[cfg(feature = "tls12")]
+                {
+                    self.tls12 = None;
+                }
+                Err(TlsError::SequenceNumberOverflow)
+            }
+        }
+    }
+
+    /// Section 7.2.  Updating Traffic Secrets
+    fn update_traffic_secret(&mut self) -> Result<(), TlsError> {
+        let secret = self
+            .traffic_secret
+            .make_expanded_hkdf_label::<HashOutputSize<CipherSuite>>(
+                b"traffic upd",
+                ContextType::None,
+            )?;
+        self.traffic_secret.replace(&secret);
+        self.counter = 0;
+        Ok(())
+    }
+
+    /// Installs the write key and the salt of the implicit nonce of TLS 1.2.
+    #[cfg(feature = "tls12")]
+    fn set_tls12_keys(&mut self, key: &[u8], salt: &[u8]) -> Result<(), TlsError> {
+        let mut key_array = Zeroizing::new(KeyArray::<CipherSuite>::default());
+        let mut iv = IvArray::<CipherSuite>::default();
+        if key.len() != key_array.len() || salt.len() > iv.len() {
+            return Err(TlsError::InternalError);
+        }
+        key_array.copy_from_slice(key);
+        iv[..salt.len()].copy_from_slice(salt);
+        self.tls12 = Some((key_array, iv));
+        self.counter = 0;
+        Ok(())
+    }
+}
+
+/// The application traffic secrets and record sequence numbers of an established connection,
+/// for migrating the session to another TLS implementation such as a modem offload engine.
+///
+/// The secrets are wiped when dropped.
+#[cfg(feature = "secret-export")]
+pub struct TrafficSecrets<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    pub client_application_traffic_secret: HashArray<CipherSuite>,
+    pub server_application_traffic_secret: HashArray<CipherSuite>,
+    /// The sequence number of the next record sent by the client.
+    pub client_sequence_number: u64,
+    /// The sequence number of the next record expected from the server.
+    pub server_sequence_number: u64,
+}
+
+#[cfg(feature = "secret-export")]
+impl<CipherSuite> Drop for TrafficSecrets<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn drop(&mut self) {
+        self.client_application_traffic_secret.zeroize();
+        self.server_application_traffic_secret.zeroize();
+    }
+}
+
+/// The largest session ticket which is kept, larger tickets are ignored.
+pub const MAX_SESSION_TICKET_LEN: usize = 512;
+
+/// A session ticket issued by the server in a NewSessionTicket message, along with the
+/// resumption PSK derived from it.
+///
+/// The ticket is offered in the next handshake with
+/// [`TlsConfig::with_session_ticket`](crate::config::TlsConfig::with_session_ticket) to resume
+/// the session with a PSK (EC)DHE key exchange, which skips the transfer and verification of the
+/// server certificate. The resumption PSK is wiped when dropped.
+pub struct SessionTicket<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    ticket: heapless::Vec<u8, MAX_SESSION_TICKET_LEN>,
+    psk: HashArray<CipherSuite>,
+    lifetime: u32,
+    age_add: u32,
+    age: u32,
+    max_early_data_size: u32,
+}
+
+impl<CipherSuite> SessionTicket<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    /// The number of seconds after which the ticket must no longer be used, counting from its
+    /// reception.
+    pub fn lifetime(&self) -> u32 {
+        self.lifetime
+    }
+
+    /// The number of bytes of early data the server accepts in a connection resumed with the
+    /// ticket, 0 if the server does not accept early data.
+    pub fn max_early_data_size(&self) -> u32 {
+        self.max_early_data_size
+    }
+
+    /// Sets the number of milliseconds elapsed since the ticket was received.
+    ///
+    /// The age is sent, obfuscated, to the server, which may use it to detect replays. It is 0 by
+    /// default, for devices without a clock.
+    pub fn set_age(&mut self, age: u32) {
+        self.age = age;
+    }
+
+    pub(crate) fn identity(&self) -> &[u8] {
+        &self.ticket
+    }
+
+    pub(crate) fn psk(&self) -> &[u8] {
+        &self.psk
+    }
+
+    /// Section 4.2.11.1.  Ticket Age
+    pub(crate) fn obfuscated_age(&self) -> u32 {
+        self.age.wrapping_add(self.age_add)
+    }
+
+    /// The number of bytes written by [`Self::encode`].
+    pub fn encoded_len(&self) -> usize {
+        2 + 4 + 4 + 4 + 4 + self.psk.len() + 2 + self.ticket.len()
+    }
+
+    /// Writes the ticket and its resumption PSK to `buf`, for a [`TicketStore`] keeping it in
+    /// non-volatile memory. Returns the number of bytes written.
+    ///
+    /// The encoding contains the secret PSK, so it must be protected like a private key.
+    ///
+    /// [`TicketStore`]: crate::config::TicketStore
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, TlsError> {
+        let mut buf = CryptoBuffer::wrap(buf);
+        buf.push_u16(CipherSuite::CODE_POINT)?;
+        buf.push_u32(self.lifetime)?;
+        buf.push_u32(self.age_add)?;
+        buf.push_u32(self.age)?;
+        buf.push_u32(self.max_early_data_size)?;
+        buf.extend_from_slice(&self.psk)?;
+        buf.push_u16(self.ticket.len() as u16)?;
+        buf.extend_from_slice(&self.ticket)?;
+        Ok(buf.len())
+    }
+
+    /// Reads a ticket written by [`Self::encode`] with the same cipher suite.
+    pub fn decode(buf: &[u8]) -> Result<Self, TlsError> {
+        let mut buf = ParseBuffer::new(buf);
+        if buf.read_u16()? != CipherSuite::CODE_POINT {
+            return Err(TlsError::InvalidCipherSuite);
+        }
+        let lifetime = buf.read_u32()?;
+        let age_add = buf.read_u32()?;
+        let age = buf.read_u32()?;
+        let max_early_data_size = buf.read_u32()?;
+        let mut psk = Zeroizing::new(HashArray::<CipherSuite>::default());
+        buf.fill(&mut psk)?;
+        let ticket_length = buf.read_u16()?;
+        let ticket = buf
+            .slice(ticket_length as usize)
+            .map_err(|_| TlsError::InvalidTicketLength)?;
+
+        Ok(Self {
+            ticket: heapless::Vec::from_slice(ticket.as_slice())
+                .map_err(|_| TlsError::InvalidTicketLength)?,
+            psk: (*psk).clone(),
+            lifetime,
+            age_add,
+            age,
+            max_early_data_size,
+        })
+    }
+}
+
+impl<CipherSuite> Debug for SessionTicket<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SessionTicket")
+            .field("lifetime", &self.lifetime)
+            .field("age", &self.age)
+            .field("max_early_data_size", &self.max_early_data_size)
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<CipherSuite> defmt::Format for SessionTicket<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "lifetime:{} age:{}", self.lifetime, self.age)
+    }
+}
+
+impl<CipherSuite> Drop for SessionTicket<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn drop(&mut self) {
+        self.psk.zeroize();
     }
 }
 
@@ -226,11 +545,23 @@ where
             shared: SharedState::new(),
             client_state: WriteKeySchedule {
                 state: KeyScheduleState::new(),
+                early_state: KeyScheduleState::new(),
                 binder_key: Secret::Uninitialized,
+                record_limit: RecordLimit::default(),
             },
             server_state: ReadKeySchedule {
                 state: KeyScheduleState::new(),
                 transcript_hash: <CipherSuite::Hash as Digest>::new(),
+                resumption_secret: Secret::Uninitialized,
+                exporter_secret: Secret::Uninitialized,
+                session_ticket: None,
+                key_update: None,
+                key_update_requested: false,
+                max_fragment_length: None,
+                expected_session_tickets: None,
+                heartbeat: Heartbeat::default(),
+                #[cfg(feature = "ech")]
+                inner_transcript_hash: None,
             },
         }
     }
@@ -284,6 +615,16 @@ where
         &mut self.server_state
     }
 
+    /// The number of tickets the server expects to send, see
+    /// [`TlsConfig::with_ticket_request`](crate::config::TlsConfig::with_ticket_request).
+    pub(crate) fn expected_session_tickets(&self) -> Option<u8> {
+        self.server_state.expected_session_tickets
+    }
+
+    pub(crate) fn heartbeat_in_flight(&self) -> bool {
+        self.server_state.heartbeat.in_flight()
+    }
+
     pub fn create_client_finished(
         &self,
     ) -> Result<Finished<HashOutputSize<CipherSuite>>, TlsError> {
@@ -296,13 +637,8 @@ where
                 ContextType::None,
             )?;
 
-        let mut hmac = SimpleHmac::<CipherSuite::Hash>::new_from_slice(&key)
-            .map_err(|_| TlsError::CryptoError)?;
-        Mac::update(
-            &mut hmac,
-            &self.server_state.transcript_hash.clone().finalize(),
-        );
-        let verify = hmac.finalize().into_bytes();
+        let verify =
+            Hkdf::<CipherSuite>::hmac(&key, &self.server_state.transcript_hash.clone().finalize())?;
 
         Ok(Finished { verify, hash: None })
     }
@@ -350,6 +686,22 @@ where
 
     // Initializes the early secrets with a callback for any PSK binders
     pub fn initialize_early_secret(&mut self, psk: Option<&[u8]>) -> Result<(), TlsError> {
+        self.initialize_early_secret_with_label(psk, b"ext binder")
+    }
+
+    /// Initializes the early secrets with the PSK of a session ticket, whose binders use a
+    /// different label than those of external PSKs.
+    pub fn initialize_resumption_early_secret(&mut self, psk: &[u8]) -> Result<(), TlsError> {
+        self.initialize_early_secret_with_label(Some(psk), b"res binder")
+    }
+
+    fn initialize_early_secret_with_label(
+        &mut self,
+        psk: Option<&[u8]>,
+        binder_label: &[u8],
+    ) -> Result<(), TlsError> {
+        // The schedule starts over if the server did not accept the offered PSK
+        self.shared = SharedState::new();
         self.shared.initialize(
             #[allow(clippy::or_fun_call)]
             psk.unwrap_or(Self::zero().as_slice()),
@@ -357,20 +709,40 @@ where
 
         let binder_key = self
             .shared
-            .derive_secret(b"ext binder", ContextType::empty_hash())?;
-        self.client_state.binder_key.replace(
-            Hkdf::<CipherSuite>::from_prk(&binder_key).map_err(|_| TlsError::InternalError)?,
-        );
+            .derive_secret(binder_label, ContextType::empty_hash())?;
+        self.client_state.binder_key.replace(&binder_key);
         self.shared.derived()
     }
 
+    /// Derives the client early traffic secret from the early secret, with the transcript up to
+    /// the ClientHello. Must be called before [`Self::initialize_handshake_secret`].
+    pub fn initialize_early_traffic_secret(&mut self) -> Result<(), TlsError> {
+        self.client_state.state.calculate_traffic_secret(
+            b"c e traffic",
+            &mut self.shared,
+            &self.server_state.transcript_hash,
+        )
+    }
+
     pub fn initialize_handshake_secret(&mut self, ikm: &[u8]) -> Result<(), TlsError> {
+        // The early traffic keys, if any, are kept for the EndOfEarlyData message
+        core::mem::swap(
+            &mut self.client_state.state,
+            &mut self.client_state.early_state,
+        );
         self.shared.initialize(ikm);
 
         self.calculate_traffic_secrets(b"c hs traffic", b"s hs traffic")?;
         self.shared.derived()
     }
 
+    // Section 7.1.  Key Schedule
+    // If a given secret is not available, then the 0-value consisting of a string of Hash.length
+    // bytes set to zeros is used.
+    pub fn initialize_psk_only_handshake_secret(&mut self) -> Result<(), TlsError> {
+        self.initialize_handshake_secret(Self::zero().as_slice())
+    }
+
     pub fn initialize_master_secret(&mut self) -> Result<(), TlsError> {
         self.shared.initialize(Self::zero().as_slice());
 
@@ -378,9 +750,106 @@ where
         //info!("Derive keys, hash: {:x?}", context);
 
         self.calculate_traffic_secrets(b"c ap traffic", b"s ap traffic")?;
+        let exporter_secret = self.shared.derive_secret(
+            b"exp master",
+            ContextType::transcript_hash(&self.server_state.transcript_hash),
+        )?;
+        self.server_state.exporter_secret.replace(&exporter_secret);
         self.shared.derived()
     }
 
+    /// Derives the resumption master secret from the master secret, with the transcript up to
+    /// the client Finished. Must be called after [`Self::initialize_master_secret`].
+    pub fn initialize_resumption_secret(
+        &mut self,
+        transcript_hash: &CipherSuite::Hash,
+    ) -> Result<(), TlsError> {
+        let secret = self
+            .shared
+            .derive_secret(b"res master", ContextType::transcript_hash(transcript_hash))?;
+        self.server_state.resumption_secret.replace(&secret);
+        Ok(())
+    }
+
+    /// Section 7.5.  Exporters
+    ///     TLS-Exporter(label, context_value, key_length) =
+    ///         HKDF-Expand-Label(Derive-Secret(Secret, label, ""),
+    ///                           "exporter", Hash(context_value), key_length)
+    ///
+    /// Fills `out` with keying material of the exporter master secret, which is only available
+    /// once a TLS 1.3 handshake is complete.
+    pub(crate) fn export_keying_material(
+        &self,
+        label: &[u8],
+        context: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), TlsError> {
+        if let Secret::Uninitialized = self.server_state.exporter_secret {
+            return Err(TlsError::MissingHandshake);
+        }
+        let empty_hash = <CipherSuite::Hash as Digest>::new().finalize();
+        let mut secret = Zeroizing::new(HashArray::<CipherSuite>::default());
+        self.server_state
+            .exporter_secret
+            .expand_label_into(label, &empty_hash, &mut secret)?;
+
+        let context_hash = <CipherSuite::Hash as Digest>::new()
+            .chain_update(context)
+            .finalize();
+        Secret::<CipherSuite>::Initialized((*secret).clone()).expand_label_into(
+            b"exporter",
+            &context_hash,
+            out,
+        )
+    }
+
+    /// draft-ietf-tls-esni, Section 7.2.  Shared Mode
+    ///     accept_confirmation = HKDF-Expand-Label(
+    ///        HKDF-Extract(0, ClientHelloInner.random),
+    ///        "ech accept confirmation",
+    ///        transcript_ech_conf,
+    ///        8)
+    ///
+    /// Where `transcript` covers the ClientHelloInner and the ServerHello with the last 8 bytes
+    /// of its random set to zero.
+    #[cfg(feature = "ech")]
+    pub(crate) fn ech_accept_confirmation(
+        inner_random: &[u8],
+        transcript: &CipherSuite::Hash,
+    ) -> Result<[u8; 8], TlsError> {
+        let zeros = HashArray::<CipherSuite>::default();
+        let (prk, _) = Hkdf::<CipherSuite>::extract(Some(&zeros), inner_random);
+        let mut confirmation = [0; 8];
+        Secret::<CipherSuite>::Initialized(prk).expand_label_into(
+            b"ech accept confirmation",
+            &transcript.clone().finalize(),
+            &mut confirmation,
+        )?;
+        Ok(confirmation)
+    }
+
+    /// Export the current traffic secrets and sequence numbers. Only meaningful once the
+    /// handshake is complete, when the application traffic secrets are in use.
+    #[cfg(feature = "secret-export")]
+    pub(crate) fn export_traffic_secrets(&self) -> Result<TrafficSecrets<CipherSuite>, TlsError> {
+        Ok(TrafficSecrets {
+            client_application_traffic_secret: self
+                .client_state
+                .state
+                .traffic_secret
+                .as_ref()?
+                .clone(),
+            server_application_traffic_secret: self
+                .server_state
+                .state
+                .traffic_secret
+                .as_ref()?
+                .clone(),
+            client_sequence_number: self.client_state.state.counter,
+            server_sequence_number: self.server_state.state.counter,
+        })
+    }
+
     fn calculate_traffic_secrets(
         &mut self,
         client_label: &[u8],
@@ -416,17 +885,19 @@ where
     CipherSuite: TlsCipherSuite,
 {
     state: KeyScheduleState<CipherSuite>,
+    early_state: KeyScheduleState<CipherSuite>,
     binder_key: Secret<CipherSuite>,
+    record_limit: RecordLimit,
 }
 impl<CipherSuite> WriteKeySchedule<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub(crate) fn increment_counter(&mut self) {
+    pub(crate) fn increment_counter(&mut self) -> Result<(), TlsError> {
         self.state.increment_counter()
     }
 
-    pub(crate) fn get_key(&self) -> Result<KeyArray<CipherSuite>, TlsError> {
+    pub(crate) fn get_key(&self) -> Result<Zeroizing<KeyArray<CipherSuite>>, TlsError> {
         self.state.get_key()
     }
 
@@ -434,6 +905,58 @@ where
         self.state.get_nonce()
     }
 
+    #[cfg(feature = "tls12")]
+    pub(crate) fn set_tls12_keys(&mut self, key: &[u8], salt: &[u8]) -> Result<(), TlsError> {
+        self.state.set_tls12_keys(key, salt)
+    }
+
+    /// Whether records are protected with the keys of TLS 1.2.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn is_tls12(&self) -> bool {
+        self.state.tls12.is_some()
+    }
+
+    /// The sequence number of the next record.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn sequence_number(&self) -> u64 {
+        self.state.counter
+    }
+
+    pub(crate) fn set_record_limit(&mut self, record_limit: RecordLimit) {
+        self.record_limit = record_limit;
+    }
+
+    /// Fails if encrypting another record would exceed the record limit of the current key.
+    pub(crate) fn check_record_limit(&self) -> Result<(), TlsError> {
+        if self.state.counter >= self.record_limit.records {
+            Err(TlsError::RecordLimitReached)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether a KeyUpdate must be sent, as the last record allowed under the current key.
+    pub(crate) fn key_update_due(&self) -> bool {
+        // TLS 1.2 has no KeyUpdate, the record limit aborts instead
+        #[cfg(feature = "tls12")]
+        if self.is_tls12() {
+            return false;
+        }
+        self.record_limit.action == RecordLimitAction::KeyUpdate
+            && self.state.counter.saturating_add(1) >= self.record_limit.records
+    }
+
+    /// Switches between the handshake and the early traffic keys, around the EndOfEarlyData
+    /// message which is the last one protected with the early traffic keys.
+    pub(crate) fn swap_early_traffic_keys(&mut self) {
+        core::mem::swap(&mut self.state, &mut self.early_state);
+    }
+
+    /// Switch to the next traffic secret after sending a KeyUpdate.
+    pub(crate) fn update_traffic_secret(&mut self) -> Result<(), TlsError> {
+        self.state.update_traffic_secret()
+    }
+
     pub fn create_psk_binder(
         &self,
         transcript_hash: &CipherSuite::Hash,
@@ -445,10 +968,7 @@ where
                 ContextType::None,
             )?;
 
-        let mut hmac = SimpleHmac::<CipherSuite::Hash>::new_from_slice(&key)
-            .map_err(|_| TlsError::CryptoError)?;
-        Mac::update(&mut hmac, &transcript_hash.clone().finalize());
-        let verify = hmac.finalize().into_bytes();
+        let verify = Hkdf::<CipherSuite>::hmac(&key, &transcript_hash.clone().finalize())?;
         Ok(PskBinder { verify })
     }
 }
@@ -459,13 +979,30 @@ where
 {
     state: KeyScheduleState<CipherSuite>,
     transcript_hash: CipherSuite::Hash,
+    resumption_secret: Secret<CipherSuite>,
+    exporter_secret: Secret<CipherSuite>,
+    session_ticket: Option<SessionTicket<CipherSuite>>,
+    /// The KeyUpdate of the record being processed, applied after the record.
+    key_update: Option<KeyUpdateRequest>,
+    /// Whether the server requested a KeyUpdate which has not been sent yet.
+    key_update_requested: bool,
+    /// The maximum fragment length negotiated with the server, if any.
+    max_fragment_length: Option<MaxFragmentLength>,
+    /// The number of tickets the server expects to send, if it answered the ticket request.
+    expected_session_tickets: Option<u8>,
+    /// The heartbeats negotiated with the server.
+    heartbeat: Heartbeat,
+    /// The transcript of the ClientHelloInner, until the ServerHello tells whether the server
+    /// accepted Encrypted Client Hello.
+    #[cfg(feature = "ech")]
+    inner_transcript_hash: Option<CipherSuite::Hash>,
 }
 
 impl<CipherSuite> ReadKeySchedule<CipherSuite>
 where
     CipherSuite: TlsCipherSuite,
 {
-    pub(crate) fn increment_counter(&mut self) {
+    pub(crate) fn increment_counter(&mut self) -> Result<(), TlsError> {
         self.state.increment_counter()
     }
 
@@ -473,7 +1010,7 @@ where
         &mut self.transcript_hash
     }
 
-    pub(crate) fn get_key(&self) -> Result<KeyArray<CipherSuite>, TlsError> {
+    pub(crate) fn get_key(&self) -> Result<Zeroizing<KeyArray<CipherSuite>>, TlsError> {
         self.state.get_key()
     }
 
@@ -481,6 +1018,145 @@ where
         self.state.get_nonce()
     }
 
+    #[cfg(feature = "tls12")]
+    pub(crate) fn set_tls12_keys(&mut self, key: &[u8], salt: &[u8]) -> Result<(), TlsError> {
+        self.state.set_tls12_keys(key, salt)
+    }
+
+    /// Whether records are protected with the keys of TLS 1.2.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn is_tls12(&self) -> bool {
+        self.state.tls12.is_some()
+    }
+
+    /// The sequence number of the next record.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn sequence_number(&self) -> u64 {
+        self.state.counter
+    }
+
+    /// Section 4.6.1.  New Session Ticket Message
+    /// The PSK associated with the ticket is computed as:
+    ///
+    ///     HKDF-Expand-Label(resumption_master_secret,
+    ///                       "resumption", ticket_nonce, Hash.length)
+    ///
+    /// The ticket replaces any ticket received before on this connection.
+    pub(crate) fn receive_session_ticket(
+        &mut self,
+        ticket: &NewSessionTicket<'_>,
+    ) -> Result<(), TlsError> {
+        // The value of zero indicates that the ticket should be discarded immediately.
+        if ticket.lifetime == 0 {
+            return Ok(());
+        }
+        if ticket.nonce.len() > HashOutputSize::<CipherSuite>::to_usize() {
+            return Err(TlsError::InvalidNonceLength);
+        }
+        let identity = match heapless::Vec::from_slice(ticket.ticket) {
+            Ok(identity) => identity,
+            Err(_) => {
+                warn!("Ignoring a session ticket of {} bytes", ticket.ticket.len());
+                return Ok(());
+            }
+        };
+
+        let psk = self
+            .resumption_secret
+            .expand_label::<HashOutputSize<CipherSuite>>(b"resumption", ticket.nonce)?;
+        self.session_ticket = Some(SessionTicket {
+            ticket: identity,
+            psk: (*psk).clone(),
+            lifetime: ticket.lifetime,
+            age_add: ticket.age_add,
+            age: 0,
+            max_early_data_size: ticket.max_early_data_size,
+        });
+        Ok(())
+    }
+
+    pub(crate) fn take_session_ticket(&mut self) -> Option<SessionTicket<CipherSuite>> {
+        self.session_ticket.take()
+    }
+
+    /// Section 4.6.3.  Key and Initialization Vector Update
+    /// After sending a KeyUpdate message, the sender SHALL send all its traffic using the
+    /// next generation of keys.
+    ///
+    /// The KeyUpdate is the last message protected with the current key, so the key is only
+    /// updated by [`ReadKeySchedule::apply_key_update`] once its record has been processed.
+    pub(crate) fn receive_key_update(&mut self, key_update: &KeyUpdate) {
+        self.key_update = Some(key_update.request_update);
+    }
+
+    /// Switch to the next traffic secret of the server, if the record just processed contained
+    /// a KeyUpdate.
+    pub(crate) fn apply_key_update(&mut self) -> Result<(), TlsError> {
+        if let Some(request) = self.key_update.take() {
+            self.state.update_traffic_secret()?;
+            if request == KeyUpdateRequest::UpdateRequested {
+                self.key_update_requested = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the server requested a KeyUpdate, which must be sent before the next application
+    /// data.
+    pub(crate) fn take_key_update_request(&mut self) -> bool {
+        core::mem::take(&mut self.key_update_requested)
+    }
+
+    /// Keep a KeyUpdate request of the server which has not been answered yet.
+    pub(crate) fn defer_key_update_request(&mut self) {
+        self.key_update_requested = true;
+    }
+
+    pub(crate) fn set_max_fragment_length(&mut self, max_fragment_length: MaxFragmentLength) {
+        self.max_fragment_length = Some(max_fragment_length);
+    }
+
+    pub(crate) fn max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        self.max_fragment_length
+    }
+
+    pub(crate) fn set_expected_session_tickets(&mut self, count: u8) {
+        self.expected_session_tickets = Some(count);
+    }
+
+    pub(crate) fn heartbeat(&mut self) -> &mut Heartbeat {
+        &mut self.heartbeat
+    }
+
+    #[cfg(feature = "ech")]
+    pub(crate) fn set_inner_transcript_hash(&mut self, hash: CipherSuite::Hash) {
+        self.inner_transcript_hash = Some(hash);
+    }
+
+    #[cfg(feature = "ech")]
+    pub(crate) fn take_inner_transcript_hash(&mut self) -> Option<CipherSuite::Hash> {
+        self.inner_transcript_hash.take()
+    }
+
+    /// Checks the length of the plaintext of a record of the server against the negotiated
+    /// maximum fragment length.
+    ///
+    /// RFC 8446, Section 5.1.  Record Layer
+    /// The length MUST NOT exceed 2^14 bytes.  An endpoint that receives a record that exceeds
+    /// this length MUST terminate the connection with a "record_overflow" alert.
+    pub(crate) fn check_fragment_length(&self, len: usize) -> Result<(), TlsError> {
+        match self.max_fragment_length {
+            Some(max_fragment_length) if len > max_fragment_length.size() => {
+                warn!("Record exceeds the negotiated maximum fragment length");
+                Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::RecordOverflow,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn verify_server_finished(
         &self,
         finished: &Finished<HashOutputSize<CipherSuite>>,
@@ -496,18 +1172,359 @@ where
                 ContextType::None,
             )?;
         // info!("hmac sign key {:x?}", key);
-        let mut hmac = SimpleHmac::<CipherSuite::Hash>::new_from_slice(&key)
-            .map_err(|_| TlsError::InternalError)?;
-        Mac::update(
-            &mut hmac,
-            finished.hash.as_ref().ok_or_else(|| {
-                warn!("No hash in Finished");
-                TlsError::InternalError
-            })?,
-        );
-        //let code = hmac.clone().finalize().into_bytes();
-        Ok(hmac.verify(&finished.verify).is_ok())
+        let hash = finished.hash.as_ref().ok_or_else(|| {
+            warn!("No hash in Finished");
+            TlsError::InternalError
+        })?;
+        Hkdf::<CipherSuite>::verify_hmac(&key, hash, &finished.verify)
         //info!("verified {:?}", verified);
         //unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Aes128GcmSha256, Aes256GcmSha384, Sha256};
+
+    // Runs the schedule up to the server handshake traffic secret, with no PSK, a fixed
+    // (EC)DHE input and an empty transcript.
+    fn handshake_schedule<CipherSuite: TlsCipherSuite>() -> KeySchedule<CipherSuite> {
+        let mut key_schedule = KeySchedule::<CipherSuite>::new();
+        key_schedule.initialize_early_secret(None).unwrap();
+        key_schedule
+            .initialize_handshake_secret(&[0x42; 32])
+            .unwrap();
+        key_schedule
+    }
+
+    #[test]
+    fn test_early_secret_sha256() {
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        key_schedule.initialize_early_secret(None).unwrap();
+        // RFC 8448, Section 3.  Simple 1-RTT Handshake, "derived" secret
+        let expected = [
+            0x6f, 0x26, 0x15, 0xa1, 0x08, 0xc7, 0x02, 0xc5, 0x67, 0x8f, 0x54, 0xfc, 0x9d, 0xba,
+            0xb6, 0x97, 0x16, 0xc0, 0x76, 0x18, 0x9c, 0x48, 0x25, 0x0c, 0xeb, 0xea, 0xc3, 0x57,
+            0x6c, 0x36, 0x11, 0xba,
+        ];
+        assert_eq!(&expected[..], key_schedule.shared.secret.as_slice());
+    }
+
+    #[test]
+    fn test_early_secret_sha384() {
+        let mut key_schedule = KeySchedule::<Aes256GcmSha384>::new();
+        key_schedule.initialize_early_secret(None).unwrap();
+        let expected = [
+            0x15, 0x91, 0xda, 0xc5, 0xcb, 0xbf, 0x03, 0x30, 0xa4, 0xa8, 0x4d, 0xe9, 0xc7, 0x53,
+            0x33, 0x0e, 0x92, 0xd0, 0x1f, 0x0a, 0x88, 0x21, 0x4b, 0x44, 0x64, 0x97, 0x2f, 0xd6,
+            0x68, 0x04, 0x9e, 0x93, 0xe5, 0x2f, 0x2b, 0x16, 0xfa, 0xd9, 0x22, 0xfd, 0xc0, 0x58,
+            0x44, 0x78, 0x42, 0x8f, 0x28, 0x2b,
+        ];
+        assert_eq!(&expected[..], key_schedule.shared.secret.as_slice());
+    }
+
+    #[test]
+    fn test_handshake_traffic_keys_sha256() {
+        let key_schedule = handshake_schedule::<Aes128GcmSha256>();
+        let state = &key_schedule.server_state.state;
+        let key = [
+            0xe3, 0x72, 0x6d, 0x59, 0x64, 0xac, 0x18, 0xdd, 0x9d, 0xc7, 0xeb, 0xd3, 0x21, 0x7e,
+            0x89, 0x0c,
+        ];
+        let iv = [
+            0x17, 0x2c, 0x35, 0xb0, 0x69, 0x46, 0xea, 0x90, 0x7c, 0x86, 0xf9, 0x7c,
+        ];
+        assert_eq!(&key[..], state.get_key().unwrap().as_slice());
+        assert_eq!(&iv[..], state.get_iv().unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_handshake_traffic_keys_sha384() {
+        let key_schedule = handshake_schedule::<Aes256GcmSha384>();
+        let state = &key_schedule.server_state.state;
+        let key = [
+            0xef, 0x47, 0xaf, 0x39, 0x2a, 0x3d, 0xbe, 0x63, 0xac, 0x48, 0x2f, 0x59, 0x60, 0x02,
+            0xd7, 0x6c, 0xb1, 0x44, 0x96, 0xcc, 0x87, 0xe0, 0x14, 0xb6, 0xf7, 0x6e, 0xf2, 0xdd,
+            0xf7, 0x40, 0xe7, 0x64,
+        ];
+        let iv = [
+            0x0a, 0x6c, 0x77, 0xcb, 0xb8, 0x09, 0x14, 0xa7, 0x51, 0x7a, 0x6d, 0x65,
+        ];
+        assert_eq!(&key[..], state.get_key().unwrap().as_slice());
+        assert_eq!(&iv[..], state.get_iv().unwrap().as_slice());
+    }
+
+    #[cfg(feature = "secret-export")]
+    #[test]
+    fn test_export_traffic_secrets() {
+        let mut key_schedule = handshake_schedule::<Aes128GcmSha256>();
+        key_schedule.write_state().increment_counter().unwrap();
+        key_schedule.write_state().increment_counter().unwrap();
+        key_schedule.read_state().increment_counter().unwrap();
+
+        let secrets = key_schedule.export_traffic_secrets().unwrap();
+        assert_eq!(2, secrets.client_sequence_number);
+        assert_eq!(1, secrets.server_sequence_number);
+
+        // The exported secret derives the same traffic key
+        let mut state = KeyScheduleState::<Aes128GcmSha256>::new();
+        state
+            .traffic_secret
+            .replace(&secrets.server_application_traffic_secret);
+        let expected = key_schedule.server_state.state.get_key().unwrap();
+        assert_eq!(expected.as_slice(), state.get_key().unwrap().as_slice());
+    }
+
+    // Runs the schedule up to the resumption master secret, with an empty transcript.
+    fn resumption_schedule() -> KeySchedule<Aes128GcmSha256> {
+        let mut key_schedule = handshake_schedule::<Aes128GcmSha256>();
+        key_schedule.initialize_master_secret().unwrap();
+        let transcript_hash = key_schedule.transcript_hash().clone();
+        key_schedule
+            .initialize_resumption_secret(&transcript_hash)
+            .unwrap();
+        key_schedule
+    }
+
+    fn new_session_ticket<'a>(
+        buf: &'a mut [u8],
+        lifetime: u32,
+        nonce: &[u8],
+        ticket: &[u8],
+    ) -> NewSessionTicket<'a> {
+        let mut message = CryptoBuffer::wrap(buf);
+        message.push_u32(lifetime).unwrap();
+        message.push_u32(0x0102_0304).unwrap();
+        message.push(nonce.len() as u8).unwrap();
+        message.extend_from_slice(nonce).unwrap();
+        message.push_u16(ticket.len() as u16).unwrap();
+        message.extend_from_slice(ticket).unwrap();
+        // An early_data extension with a max_early_data_size of 1024
+        message.push_u16(8).unwrap();
+        message.push_u16(42).unwrap();
+        message.push_u16(4).unwrap();
+        message.push_u32(1024).unwrap();
+        let (buf, _, len) = message.release();
+        let buf: &'a [u8] = buf;
+        NewSessionTicket::parse(&mut ParseBuffer::new(&buf[..len])).unwrap()
+    }
+
+    #[test]
+    fn test_resumption_psk() {
+        let mut key_schedule = resumption_schedule();
+        let mut buf = [0; 64];
+        let message = new_session_ticket(&mut buf, 7200, &[0x00, 0x01], &[0xaa; 16]);
+        key_schedule
+            .read_state()
+            .receive_session_ticket(&message)
+            .unwrap();
+        let ticket = key_schedule.read_state().take_session_ticket().unwrap();
+
+        // HKDF-Expand-Label(resumption_master_secret, "resumption", ticket_nonce, Hash.length)
+        let mut info = [0; 22];
+        info[..3].copy_from_slice(&[0x00, 0x20, 16]);
+        info[3..19].copy_from_slice(b"tls13 resumption");
+        info[19..].copy_from_slice(&[2, 0x00, 0x01]);
+        let prk = key_schedule
+            .server_state
+            .resumption_secret
+            .as_ref()
+            .unwrap();
+        let mut expected = [0; 32];
+        hkdf::Hkdf::<Sha256>::from_prk(prk)
+            .unwrap()
+            .expand(&info, &mut expected)
+            .unwrap();
+
+        assert_eq!(&expected[..], ticket.psk());
+        assert_eq!(&[0xaa; 16], ticket.identity());
+        assert_eq!(7200, ticket.lifetime());
+        assert_eq!(1024, ticket.max_early_data_size());
+        assert!(key_schedule.read_state().take_session_ticket().is_none());
+    }
+
+    #[test]
+    fn test_ticket_age_is_obfuscated() {
+        let mut key_schedule = resumption_schedule();
+        let mut buf = [0; 64];
+        let message = new_session_ticket(&mut buf, 7200, &[0x00], &[0xaa; 16]);
+        key_schedule
+            .read_state()
+            .receive_session_ticket(&message)
+            .unwrap();
+        let mut ticket = key_schedule.read_state().take_session_ticket().unwrap();
+
+        assert_eq!(0x0102_0304, ticket.obfuscated_age());
+        ticket.set_age(u32::MAX);
+        assert_eq!(0x0102_0303, ticket.obfuscated_age());
+    }
+
+    #[test]
+    fn test_encode_session_ticket() {
+        let mut key_schedule = resumption_schedule();
+        let mut buf = [0; 64];
+        let message = new_session_ticket(&mut buf, 7200, &[0x00], &[0xaa; 16]);
+        key_schedule
+            .read_state()
+            .receive_session_ticket(&message)
+            .unwrap();
+        let mut ticket = key_schedule.read_state().take_session_ticket().unwrap();
+        ticket.set_age(1000);
+
+        let mut encoded = [0; 128];
+        let len = ticket.encode(&mut encoded).unwrap();
+        assert_eq!(ticket.encoded_len(), len);
+        assert!(ticket.encode(&mut encoded[..len - 1]).is_err());
+
+        let decoded = SessionTicket::<Aes128GcmSha256>::decode(&encoded[..len]).unwrap();
+        assert_eq!(ticket.identity(), decoded.identity());
+        assert_eq!(ticket.psk(), decoded.psk());
+        assert_eq!(ticket.obfuscated_age(), decoded.obfuscated_age());
+        assert_eq!(7200, decoded.lifetime());
+        assert_eq!(1024, decoded.max_early_data_size());
+
+        assert!(matches!(
+            SessionTicket::<Aes256GcmSha384>::decode(&encoded[..len]),
+            Err(TlsError::InvalidCipherSuite)
+        ));
+        assert!(SessionTicket::<Aes128GcmSha256>::decode(&encoded[..len - 1]).is_err());
+    }
+
+    #[test]
+    fn test_unusable_session_tickets() {
+        let mut key_schedule = resumption_schedule();
+        let mut buf = [0; MAX_SESSION_TICKET_LEN + 64];
+
+        // A lifetime of zero means that the ticket must be discarded
+        let message = new_session_ticket(&mut buf, 0, &[0x00], &[0xaa; 16]);
+        key_schedule
+            .read_state()
+            .receive_session_ticket(&message)
+            .unwrap();
+        assert!(key_schedule.read_state().take_session_ticket().is_none());
+
+        let message =
+            new_session_ticket(&mut buf, 7200, &[0x00], &[0xaa; MAX_SESSION_TICKET_LEN + 1]);
+        key_schedule
+            .read_state()
+            .receive_session_ticket(&message)
+            .unwrap();
+        assert!(key_schedule.read_state().take_session_ticket().is_none());
+
+        let message = new_session_ticket(&mut buf, 7200, &[0x00; 33], &[0xaa; 16]);
+        assert!(matches!(
+            key_schedule.read_state().receive_session_ticket(&message),
+            Err(TlsError::InvalidNonceLength)
+        ));
+    }
+
+    #[test]
+    fn test_early_traffic_keys_are_kept() {
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        key_schedule
+            .initialize_resumption_early_secret(&[0x42; 32])
+            .unwrap();
+        key_schedule.initialize_early_traffic_secret().unwrap();
+        key_schedule.write_state().increment_counter().unwrap();
+        let early_key = key_schedule.write_state().get_key().unwrap();
+        let early_nonce = key_schedule.write_state().get_nonce().unwrap();
+
+        key_schedule
+            .initialize_handshake_secret(&[0x42; 32])
+            .unwrap();
+        assert_ne!(
+            early_key.as_slice(),
+            key_schedule.write_state().get_key().unwrap().as_slice()
+        );
+
+        // The early keys are restored with their sequence number
+        key_schedule.write_state().swap_early_traffic_keys();
+        assert_eq!(
+            early_key.as_slice(),
+            key_schedule.write_state().get_key().unwrap().as_slice()
+        );
+        assert_eq!(early_nonce, key_schedule.write_state().get_nonce().unwrap());
+        key_schedule.write_state().swap_early_traffic_keys();
+        assert_ne!(
+            early_key.as_slice(),
+            key_schedule.write_state().get_key().unwrap().as_slice()
+        );
+    }
+
+    #[test]
+    fn test_sequence_number_overflow() {
+        let mut key_schedule = handshake_schedule::<Aes128GcmSha256>();
+        let state = &mut key_schedule.client_state.state;
+        state.counter = u64::MAX - 1;
+        state.increment_counter().unwrap();
+        assert!(state.get_nonce().is_ok());
+
+        assert!(matches!(
+            state.increment_counter(),
+            Err(TlsError::SequenceNumberOverflow)
+        ));
+        assert!(state.get_key().is_err());
+        assert!(state.get_nonce().is_err());
+    }
+
+    #[test]
+    fn test_receive_key_update() {
+        let mut key_schedule = handshake_schedule::<Aes128GcmSha256>();
+        let read_state = key_schedule.read_state();
+        read_state.increment_counter().unwrap();
+        let key = read_state.get_key().unwrap();
+        let nonce = read_state.get_nonce().unwrap();
+
+        // The key of the record with the KeyUpdate is kept until the record is processed
+        read_state.receive_key_update(&KeyUpdate {
+            request_update: KeyUpdateRequest::UpdateRequested,
+        });
+        assert_eq!(key.as_slice(), read_state.get_key().unwrap().as_slice());
+        read_state.increment_counter().unwrap();
+        read_state.apply_key_update().unwrap();
+
+        assert_ne!(key.as_slice(), read_state.get_key().unwrap().as_slice());
+        assert_eq!(0, read_state.state.counter);
+        assert_ne!(nonce, read_state.get_nonce().unwrap());
+        assert!(read_state.take_key_update_request());
+        assert!(!read_state.take_key_update_request());
+
+        // Records without a KeyUpdate keep the key
+        let key = read_state.get_key().unwrap();
+        read_state.apply_key_update().unwrap();
+        assert_eq!(key.as_slice(), read_state.get_key().unwrap().as_slice());
+    }
+
+    #[test]
+    fn test_export_keying_material() {
+        let mut key_schedule = handshake_schedule::<Aes128GcmSha256>();
+        let mut out = [0; 48];
+        assert!(matches!(
+            key_schedule.export_keying_material(b"EXPORTER-test", &[], &mut out),
+            Err(TlsError::MissingHandshake)
+        ));
+
+        key_schedule.initialize_master_secret().unwrap();
+        key_schedule
+            .export_keying_material(b"EXPORTER-test", b"context", &mut out)
+            .unwrap();
+        assert_ne!([0; 48], out);
+
+        // The output is bound to the label and the context
+        let mut same = [0; 48];
+        key_schedule
+            .export_keying_material(b"EXPORTER-test", b"context", &mut same)
+            .unwrap();
+        assert_eq!(out, same);
+        let mut other = [0; 48];
+        key_schedule
+            .export_keying_material(b"EXPORTER-other", b"context", &mut other)
+            .unwrap();
+        assert_ne!(out, other);
+        key_schedule
+            .export_keying_material(b"EXPORTER-test", b"", &mut other)
+            .unwrap();
+        assert_ne!(out, other);
+    }
+}
@@ -69,23 +69,66 @@ mod common;
 mod config;
 mod connection;
 mod content_types;
+#[cfg(feature = "p256-cortex-m4")]
+pub mod cortex_m4;
 mod crypto_engine;
+mod crypto_provider;
+#[cfg(feature = "cryptocell")]
+pub mod cryptocell;
+#[cfg(feature = "webpki")]
+mod delegated_credential;
+#[cfg(feature = "ech")]
+mod ech;
+mod exported_authenticator;
 mod extensions;
+#[cfg(feature = "ffdhe")]
+mod ffdhe;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod grease;
 mod handshake;
+mod heartbeat;
+#[cfg(feature = "ech")]
+mod hpke;
+#[cfg(feature = "idna")]
+mod idna;
+#[cfg(feature = "interop")]
+pub mod interop;
+mod kdf;
+mod key_exchange;
 mod key_schedule;
 mod max_fragment_length;
 mod named_groups;
 mod parse_buffer;
+#[cfg(feature = "pem")]
+pub mod pem;
+mod pinning;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+mod raw_public_key;
 pub mod read_buffer;
 mod record;
+mod record_aead;
 mod record_reader;
+mod rng;
+#[cfg(feature = "sct")]
+mod sct;
 mod signature_schemes;
+mod signer;
 mod split;
 mod supported_versions;
+#[cfg(feature = "std")]
+pub mod testing;
+mod ticket_store;
+#[cfg(feature = "tls12")]
+mod tls12;
 mod write_buffer;
 
+#[cfg(feature = "webpki")]
+mod signature;
 #[cfg(feature = "webpki")]
 pub mod webpki;
+mod x509;
 
 #[cfg(feature = "async")]
 mod asynch;
@@ -93,13 +136,20 @@ mod asynch;
 #[cfg(feature = "async")]
 pub use asynch::*;
 
+/// The AEAD traits needed to implement a custom `RecordAead`.
+pub use aes_gcm::aead;
+
 #[derive(Debug, Copy, Clone)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum TlsError {
     ConnectionClosed,
+    /// The transport ended without a close_notify alert, so the data may have been truncated.
+    UnexpectedEof,
     Unimplemented,
     MissingHandshake,
     HandshakeAborted(alert::AlertLevel, alert::AlertDescription),
+    /// The server ended the connection with a fatal alert after the handshake.
+    AlertReceived(alert::AlertLevel, alert::AlertDescription),
     AbortHandshake(alert::AlertLevel, alert::AlertDescription),
     IoError,
     InternalError,
@@ -121,10 +171,26 @@ pub enum TlsError {
     InvalidCertificate,
     InvalidCertificateEntry,
     InvalidCertificateRequest,
+    InvalidPrivateKey,
     UnableToInitializeCryptoEngine,
     ParseError(ParseError),
     OutOfMemory,
+    RngError,
+    PendingData,
+    RecordLimitReached,
+    SequenceNumberOverflow,
     CryptoError,
+    /// A record of the server could not be decrypted.
+    BadRecordMac,
+    /// The server rejected Encrypted Client Hello, see
+    /// [`TlsConfig::with_ech`](crate::config::TlsConfig::with_ech).
+    EchRejected,
+    /// Heartbeats were not negotiated, the server is not allowed to answer them, or a request is
+    /// already in flight, see [`TlsConfig::with_heartbeat`](crate::config::TlsConfig::with_heartbeat).
+    HeartbeatNotAllowed,
+    /// The current time is unknown, so the validity of the certificate of the server cannot be
+    /// checked, see [`TlsConfig::with_missing_time`](crate::config::TlsConfig::with_missing_time).
+    UnknownTime,
     EncodeError,
     DecodeError,
     Io(embedded_io::ErrorKind),
@@ -1,5 +1,5 @@
 /// Maximum plaintext fragment length
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum MaxFragmentLength {
     /// 512 bytes
@@ -11,3 +11,20 @@ pub enum MaxFragmentLength {
     /// 4096 bytes
     Bits12 = 4,
 }
+
+impl MaxFragmentLength {
+    pub(crate) fn of(num: u8) -> Option<Self> {
+        match num {
+            1 => Some(Self::Bits9),
+            2 => Some(Self::Bits10),
+            3 => Some(Self::Bits11),
+            4 => Some(Self::Bits12),
+            _ => None,
+        }
+    }
+
+    /// The maximum length of the plaintext of a record, in bytes.
+    pub fn size(self) -> usize {
+        1 << (self as usize + 8)
+    }
+}
@@ -8,12 +8,24 @@ pub enum NamedGroup {
     X25519 = 0x001D,
     X448 = 0x001E,
 
+    /* Brainpool curves for TLS 1.3 (RFC 8734) */
+    BrainpoolP256r1tls13 = 0x001F,
+    BrainpoolP384r1tls13 = 0x0020,
+    BrainpoolP512r1tls13 = 0x0021,
+
+    /* ShangMi curve (RFC 8998) */
+    CurveSm2 = 0x0029,
+
     /* Finite Field Groups (DHE) */
     Ffdhe2048 = 0x0100,
     Ffdhe3072 = 0x0101,
     Ffdhe4096 = 0x0102,
     Ffdhe6144 = 0x0103,
     Ffdhe8192 = 0x0104,
+
+    /* Hybrid post-quantum groups (draft-ietf-tls-ecdhe-mlkem) */
+    Secp256r1MlKem768 = 0x11EB,
+    X25519MlKem768 = 0x11EC,
 }
 
 impl NamedGroup {
@@ -24,11 +36,17 @@ impl NamedGroup {
             0x0019 => Some(Self::Secp521r1),
             0x001D => Some(Self::X25519),
             0x001E => Some(Self::X448),
+            0x001F => Some(Self::BrainpoolP256r1tls13),
+            0x0020 => Some(Self::BrainpoolP384r1tls13),
+            0x0021 => Some(Self::BrainpoolP512r1tls13),
+            0x0029 => Some(Self::CurveSm2),
             0x0100 => Some(Self::Ffdhe2048),
             0x0101 => Some(Self::Ffdhe3072),
             0x0102 => Some(Self::Ffdhe4096),
             0x0103 => Some(Self::Ffdhe6144),
             0x0104 => Some(Self::Ffdhe8192),
+            0x11EB => Some(Self::Secp256r1MlKem768),
+            0x11EC => Some(Self::X25519MlKem768),
             _ => None,
         }
     }
@@ -1,3 +1,13 @@
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic
+    )
+)]
+
 use crate::TlsError;
 use heapless::Vec;
 
@@ -22,7 +32,7 @@ impl<'b> From<&'b [u8]> for ParseBuffer<'b> {
 
 impl<'b, const N: usize> From<ParseBuffer<'b>> for Result<Vec<u8, N>, ()> {
     fn from(val: ParseBuffer<'b>) -> Self {
-        Vec::from_slice(&val.buffer[val.pos..])
+        Vec::from_slice(val.buffer.get(val.pos..).ok_or(())?)
     }
 }
 
@@ -36,7 +46,7 @@ impl<'b> ParseBuffer<'b> {
     }
 
     pub fn remaining(&self) -> usize {
-        self.buffer.len() - self.pos
+        self.buffer.len().saturating_sub(self.pos)
     }
 
     pub fn as_slice(&self) -> &'b [u8] {
@@ -44,75 +54,32 @@ impl<'b> ParseBuffer<'b> {
     }
 
     pub fn slice(&mut self, len: usize) -> Result<ParseBuffer<'b>, ParseError> {
-        if self.pos + len <= self.buffer.len() {
-            let slice = ParseBuffer::new(&self.buffer[self.pos..self.pos + len]);
-            self.pos += len;
-            Ok(slice)
-        } else {
-            Err(ParseError::InsufficientBytes)
-        }
+        self.take(len).map(ParseBuffer::new)
     }
 
     pub fn read_u8(&mut self) -> Result<u8, ParseError> {
-        if self.pos < self.buffer.len() {
-            let value = self.buffer[self.pos];
-            self.pos += 1;
-            Ok(value)
-        } else {
-            Err(ParseError::InsufficientBytes)
-        }
+        let [value] = self.read_array()?;
+        Ok(value)
     }
 
     pub fn read_u16(&mut self) -> Result<u16, ParseError> {
         //info!("pos={} len={}", self.pos, self.buffer.len());
-        if self.pos + 2 <= self.buffer.len() {
-            let value = u16::from_be_bytes([self.buffer[self.pos], self.buffer[self.pos + 1]]);
-            self.pos += 2;
-            Ok(value)
-        } else {
-            Err(ParseError::InsufficientBytes)
-        }
+        Ok(u16::from_be_bytes(self.read_array()?))
     }
 
     pub fn read_u24(&mut self) -> Result<u32, ParseError> {
-        if self.pos + 3 <= self.buffer.len() {
-            let value = u32::from_be_bytes([
-                0,
-                self.buffer[self.pos],
-                self.buffer[self.pos + 1],
-                self.buffer[self.pos + 2],
-            ]);
-            self.pos += 3;
-            Ok(value)
-        } else {
-            Err(ParseError::InsufficientBytes)
-        }
+        let [a, b, c] = self.read_array()?;
+        Ok(u32::from_be_bytes([0, a, b, c]))
     }
 
     pub fn read_u32(&mut self) -> Result<u32, ParseError> {
-        if self.pos + 4 <= self.buffer.len() {
-            let value = u32::from_be_bytes([
-                self.buffer[self.pos],
-                self.buffer[self.pos + 1],
-                self.buffer[self.pos + 2],
-                self.buffer[self.pos + 3],
-            ]);
-            self.pos += 4;
-            Ok(value)
-        } else {
-            Err(ParseError::InsufficientBytes)
-        }
+        Ok(u32::from_be_bytes(self.read_array()?))
     }
 
     pub fn fill(&mut self, dest: &mut [u8]) -> Result<(), ParseError> {
-        if self.pos + dest.len() <= self.buffer.len() {
-            dest.copy_from_slice(&self.buffer[self.pos..self.pos + dest.len()]);
-            self.pos += dest.len();
-            // info!("Copied {} bytes", dest.len());
-            Ok(())
-        } else {
-            Err(ParseError::InsufficientBytes)
-        }
+        dest.copy_from_slice(self.take(dest.len())?);
+        // info!("Copied {} bytes", dest.len());
+        Ok(())
     }
 
     pub fn copy<const N: usize>(
@@ -121,15 +88,31 @@ impl<'b> ParseBuffer<'b> {
         num_bytes: usize,
     ) -> Result<(), ParseError> {
         if (dest.capacity() - dest.len()) < num_bytes {
-            Err(ParseError::InsufficientSpace)
-        } else if self.pos + num_bytes <= self.buffer.len() {
-            dest.extend_from_slice(&self.buffer[self.pos..self.pos + num_bytes])
-                .map_err(|_| ParseError::InsufficientSpace)?;
-            self.pos += num_bytes;
-            Ok(())
-        } else {
-            Err(ParseError::InsufficientBytes)
+            return Err(ParseError::InsufficientSpace);
         }
+        dest.extend_from_slice(self.take(num_bytes)?)
+            .map_err(|_| ParseError::InsufficientSpace)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ParseError> {
+        let mut value = [0; N];
+        self.fill(&mut value)?;
+        Ok(value)
+    }
+
+    /// Consumes the next `len` bytes. The position is left untouched if there are fewer bytes
+    /// remaining.
+    fn take(&mut self, len: usize) -> Result<&'b [u8], ParseError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or(ParseError::InsufficientBytes)?;
+        let slice = self
+            .buffer
+            .get(self.pos..end)
+            .ok_or(ParseError::InsufficientBytes)?;
+        self.pos = end;
+        Ok(slice)
     }
 }
 
@@ -138,3 +121,39 @@ impl From<ParseError> for TlsError {
         TlsError::ParseError(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_reads_do_not_advance() {
+        let mut buf = ParseBuffer::new(&[1, 2, 3]);
+        assert!(buf.read_u32().is_err());
+        assert!(buf.slice(usize::MAX).is_err());
+        assert!(buf.fill(&mut [0; 4]).is_err());
+        assert_eq!(3, buf.remaining());
+
+        assert_eq!(0x010203, buf.read_u24().unwrap());
+        assert!(buf.read_u8().is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_copy() {
+        let mut buf = ParseBuffer::new(&[1, 2, 3]);
+        let mut small: Vec<u8, 2> = Vec::new();
+        assert!(matches!(
+            buf.copy(&mut small, 3),
+            Err(ParseError::InsufficientSpace)
+        ));
+
+        let mut dest: Vec<u8, 4> = Vec::new();
+        assert!(matches!(
+            buf.copy(&mut dest, 4),
+            Err(ParseError::InsufficientBytes)
+        ));
+        buf.copy(&mut dest, 3).unwrap();
+        assert_eq!(&[1, 2, 3], dest.as_slice());
+    }
+}
@@ -0,0 +1,260 @@
+//! RFC 7468.  Textual Encodings of PKIX, PKCS, and CMS Structures
+//!
+//! Certificates and keys provisioned at runtime, for example in a payload received over MQTT,
+//! are usually PEM encoded. The blocks of a PEM document are decoded to DER into a buffer of the
+//! application, without an allocator.
+use crate::config::{Certificate, PrivateKey};
+use crate::TlsError;
+use heapless::Vec;
+
+const BEGIN: &str = "-----BEGIN ";
+const END: &str = "-----END ";
+const DASHES: &str = "-----";
+
+/// A block of a PEM document, between its encapsulation boundaries.
+#[derive(Debug, Clone, Copy)]
+pub struct PemBlock<'p> {
+    label: &'p str,
+    base64: &'p str,
+}
+
+impl<'p> PemBlock<'p> {
+    /// The label of the block, such as `CERTIFICATE` or `PRIVATE KEY`.
+    pub fn label(&self) -> &'p str {
+        self.label
+    }
+
+    /// Decodes the content of the block into `buf`, returning the DER encoded structure.
+    ///
+    /// The DER encoding takes three quarters of the length of the base64 text.
+    pub fn decode<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], TlsError> {
+        let len = decode_base64(self.base64, buf)?;
+        Ok(&buf[..len])
+    }
+}
+
+/// The blocks of a PEM document, in order. The text around the blocks is ignored.
+pub fn blocks(pem: &str) -> impl Iterator<Item = Result<PemBlock<'_>, TlsError>> {
+    let mut rest = pem;
+    core::iter::from_fn(move || {
+        let start = rest.find(BEGIN)?;
+        match parse_block(&rest[start..]) {
+            Ok((block, remaining)) => {
+                rest = remaining;
+                Some(Ok(block))
+            }
+            Err(e) => {
+                rest = "";
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// Decodes the `CERTIFICATE` blocks of a PEM document into `buf`, one after the other, for
+/// example a certificate chain to give to
+/// [`TlsConfig::with_client_identity`](crate::config::TlsConfig::with_client_identity).
+pub fn certificates<'b, const N: usize>(
+    pem: &str,
+    mut buf: &'b mut [u8],
+) -> Result<Vec<Certificate<'b>, N>, TlsError> {
+    let mut certificates = Vec::new();
+    for block in blocks(pem) {
+        let block = block?;
+        if block.label != "CERTIFICATE" {
+            continue;
+        }
+        let len = decode_base64(block.base64, buf)?;
+        let (der, rest) = core::mem::take(&mut buf).split_at_mut(len);
+        buf = rest;
+        certificates
+            .push(Certificate::X509(der))
+            .map_err(|_| TlsError::InsufficientSpace)?;
+    }
+    Ok(certificates)
+}
+
+impl<'a> Certificate<'a> {
+    /// Decodes the first `CERTIFICATE` block of a PEM document into `buf`.
+    pub fn from_pem(pem: &str, buf: &'a mut [u8]) -> Result<Self, TlsError> {
+        let block = find(pem, &["CERTIFICATE"])?;
+        Ok(Certificate::X509(block.decode(buf)?))
+    }
+}
+
+impl<'a> PrivateKey<'a> {
+    /// Decodes the first EC private key of a PEM document into `buf`, from either a SEC1
+    /// `EC PRIVATE KEY` block or an unencrypted PKCS #8 `PRIVATE KEY` block, as with
+    /// [`PrivateKey::from_der`].
+    pub fn from_pem(pem: &str, buf: &'a mut [u8]) -> Result<Self, TlsError> {
+        let block = find(pem, &["EC PRIVATE KEY", "PRIVATE KEY"])?;
+        Self::from_der(block.decode(buf)?)
+    }
+}
+
+/// The first block of a PEM document with one of the `labels`.
+fn find<'p>(pem: &'p str, labels: &[&str]) -> Result<PemBlock<'p>, TlsError> {
+    for block in blocks(pem) {
+        let block = block?;
+        if labels.contains(&block.label) {
+            return Ok(block);
+        }
+    }
+    warn!("No {} block in PEM document", labels[0]);
+    Err(TlsError::DecodeError)
+}
+
+/// Section 2.  General Considerations
+/// The label [...] is surrounded by "-----BEGIN " and "-----" as the pre-encapsulation boundary,
+/// and by "-----END " and "-----" as the post-encapsulation boundary, with the same label.
+fn parse_block(pem: &str) -> Result<(PemBlock<'_>, &str), TlsError> {
+    let (label, rest) = pem[BEGIN.len()..]
+        .split_once(DASHES)
+        .ok_or(TlsError::DecodeError)?;
+    if label.contains(['\r', '\n']) {
+        return Err(TlsError::DecodeError);
+    }
+    let (base64, rest) = rest.split_once(END).ok_or(TlsError::DecodeError)?;
+    let rest = rest
+        .strip_prefix(label)
+        .and_then(|rest| rest.strip_prefix(DASHES))
+        .ok_or(TlsError::DecodeError)?;
+    Ok((PemBlock { label, base64 }, rest))
+}
+
+/// Decodes base64 text into `buf`, skipping whitespace, and returns the length of the data.
+///
+/// Headers, such as those of the legacy encrypted private keys, are not supported.
+fn decode_base64(text: &str, buf: &mut [u8]) -> Result<usize, TlsError> {
+    let mut bits: u32 = 0;
+    let mut bit_count = 0;
+    let mut char_count = 0;
+    let mut padding = 0;
+    let mut len = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            _ => return Err(TlsError::DecodeError),
+        };
+        // The padding ends the text
+        if padding > 0 {
+            return Err(TlsError::DecodeError);
+        }
+        char_count += 1;
+        bits = (bits << 6 | u32::from(value)) & 0xFFF;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            *buf.get_mut(len).ok_or(TlsError::InsufficientSpace)? = (bits >> bit_count) as u8;
+            len += 1;
+        }
+    }
+    // A single character of a group does not make a byte, and the padding completes the group
+    if char_count % 4 == 1 || padding > 2 || (padding > 0 && (char_count + padding) % 4 != 0) {
+        return Err(TlsError::DecodeError);
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+    #[test]
+    fn test_decode_base64() {
+        let mut buf = [0; 8];
+        for (text, data) in [
+            ("", &b""[..]),
+            ("Zg==", &b"f"[..]),
+            ("Zm8=", &b"fo"[..]),
+            ("Zm9v", &b"foo"[..]),
+            ("Zm9v\r\nYmFy", &b"foobar"[..]),
+        ] {
+            let len = decode_base64(text, &mut buf).unwrap();
+            assert_eq!(data, &buf[..len]);
+        }
+        for text in ["Z", "Zg=", "Zg===", "Zg==Zg==", "Zm9v!"] {
+            assert!(matches!(
+                decode_base64(text, &mut buf),
+                Err(TlsError::DecodeError)
+            ));
+        }
+        assert!(matches!(
+            decode_base64("Zm9vYmFyYmF6", &mut buf),
+            Err(TlsError::InsufficientSpace)
+        ));
+    }
+
+    #[test]
+    fn test_certificates() {
+        let pem = include_str!("../tests/data/server-cert.pem");
+        let der = pem_parser::pem_to_der(pem);
+        let mut buf = [0; 2048];
+        let Certificate::X509(certificate) = Certificate::from_pem(pem, &mut buf).unwrap() else {
+            panic!("not an X.509 certificate");
+        };
+        assert_eq!(der, certificate);
+
+        // A chain of two certificates, with text around the blocks
+        let chain = std::format!(
+            "server\n{}intermediate\n{}",
+            include_str!("../tests/data/chain/server-cert.pem"),
+            include_str!("../tests/data/chain/intermediate-cert.pem")
+        );
+        let certificates: Vec<_, 4> = certificates(&chain, &mut buf).unwrap();
+        assert_eq!(2, certificates.len());
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        assert!(matches!(certificates[1], Certificate::X509(der) if der == intermediate));
+
+        assert!(matches!(
+            Certificate::from_pem(include_str!("../tests/data/server-key.pem"), &mut buf),
+            Err(TlsError::DecodeError)
+        ));
+    }
+
+    #[test]
+    fn test_private_key() {
+        let mut buf = [0; 256];
+        for (key, certificate) in [
+            // PKCS #8
+            (
+                include_str!("../tests/data/server-key.pem"),
+                include_str!("../tests/data/server-cert.pem"),
+            ),
+            // SEC1
+            (
+                include_str!("../tests/data/chain/server-key.pem"),
+                include_str!("../tests/data/chain/server-cert.pem"),
+            ),
+        ] {
+            let PrivateKey::EcdsaSecp256r1(scalar) = PrivateKey::from_pem(key, &mut buf).unwrap()
+            else {
+                panic!("not a P-256 key");
+            };
+            // The public key of the certificate matches the private key
+            let public_key = p256::SecretKey::from_be_bytes(scalar)
+                .unwrap()
+                .public_key()
+                .to_encoded_point(false);
+            let certificate = pem_parser::pem_to_der(certificate);
+            let spki = crate::x509::SubjectPublicKeyInfo::from_certificate(&certificate).unwrap();
+            assert_eq!(spki.public_key, public_key.as_bytes());
+        }
+
+        let key = PrivateKey::from_pem(include_str!("../tests/data/p384/server-key.pem"), &mut buf);
+        assert!(matches!(key, Ok(PrivateKey::EcdsaSecp384r1(scalar)) if scalar.len() == 48));
+    }
+}
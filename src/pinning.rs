@@ -0,0 +1,308 @@
+//! Pinning of the key of the server by the SHA-256 hash of its SubjectPublicKeyInfo, as the
+//! pin-sha256 of HTTP Public Key Pinning (RFC 7469).
+//!
+//! Endpoints such as firmware update servers are trusted for their key rather than for the CA
+//! which issued their certificate, so that a misissued certificate or a compromised CA does not
+//! let another server in. The pins are checked along with any verifier, or alone with
+//! [`PinnedKeyVerifier`].
+//!
+//! Equipment with self-signed certificates, on a local network, is trusted on first use by a
+//! [`TofuVerifier`], which records the hash of the key of each server in a [`FingerprintStore`]
+//! the first time it is seen.
+use core::marker::PhantomData;
+use heapless::Vec;
+use sha2::{Digest, Sha256};
+
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::config::{Certificate, TlsCipherSuite, TlsConfig, TlsVerifier};
+use crate::handshake::certificate::{CertificateEntryRef, CertificateRef};
+use crate::handshake::certificate_verify::CertificateVerify;
+use crate::raw_public_key::{verify_certificate_verify, MAX_PUBLIC_KEY_LEN};
+use crate::TlsError;
+
+/// The DER encoded SubjectPublicKeyInfo of the end-entity certificate, or the raw public key, of
+/// the server.
+fn server_key<'a>(certificate: &CertificateRef<'a>) -> Result<&'a [u8], TlsError> {
+    match certificate.entries.first() {
+        Some(&CertificateEntryRef::X509(certificate)) => {
+            crate::x509::subject_public_key_info(certificate)
+        }
+        Some(&CertificateEntryRef::RawPublicKey(key)) => Ok(key),
+        None => Err(TlsError::InvalidCertificate),
+    }
+}
+
+/// The SHA-256 hash of the key of the server, as pinned by [`TlsConfig::with_spki_pins`].
+fn fingerprint(certificate: &CertificateRef<'_>) -> Result<[u8; 32], TlsError> {
+    let mut fingerprint = [0; 32];
+    fingerprint.copy_from_slice(&Sha256::digest(server_key(certificate)?));
+    Ok(fingerprint)
+}
+
+/// Checks the key of the server against the pins of the configuration, if any, see
+/// [`TlsConfig::with_spki_pins`].
+///
+/// RFC 7469, Section 2.6.  Validating Pinned Connections
+/// If the connection passes the pin validation [...] the UA continues with the connection;
+/// otherwise, the UA MUST treat this Pin Failure as a non-recoverable error.
+pub(crate) fn verify_spki_pins<CipherSuite>(
+    config: &TlsConfig<CipherSuite>,
+    certificate: &CertificateRef<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    if config.spki_pins.is_empty() {
+        return Ok(());
+    }
+    let fingerprint = fingerprint(certificate)?;
+    if !config.spki_pins.contains(&fingerprint) {
+        warn!("The key of the server is not pinned");
+        return Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::BadCertificate,
+        ));
+    }
+    Ok(())
+}
+
+/// A verifier which trusts the key of the server for its pin, see
+/// [`TlsConfig::with_spki_pins`], without validating its certificate chain.
+///
+/// The signature of the handshake is verified with the key of the end-entity certificate, which
+/// must be an ECDSA key over P-256 or P-384. Without pins, the server is rejected rather than
+/// trusted for any key. To check the pins on top of the validation of the chain, give them along
+/// with a verifier such as [`CertVerifier`](crate::webpki::CertVerifier) instead.
+pub struct PinnedKeyVerifier<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    pinned: bool,
+    public_key: Vec<u8, MAX_PUBLIC_KEY_LEN>,
+    certificate_transcript: Option<CipherSuite::Hash>,
+}
+
+impl<'a, CipherSuite> TlsVerifier<'a, CipherSuite> for PinnedKeyVerifier<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn new(_host: Option<&'a str>) -> Self {
+        Self {
+            pinned: false,
+            public_key: Vec::new(),
+            certificate_transcript: None,
+        }
+    }
+
+    fn set_spki_pins(&mut self, pins: &[[u8; 32]]) {
+        // The pins themselves are checked by the connection
+        self.pinned = !pins.is_empty();
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        _ca: &Option<Certificate>,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        if !self.pinned {
+            warn!("No pins to trust the key of the server for");
+            return Err(TlsError::InvalidCertificate);
+        }
+        self.public_key = Vec::from_slice(server_key(&cert)?).map_err(|_| {
+            warn!("The key of the server is not supported");
+            TlsError::InvalidCertificate
+        })?;
+        self.certificate_transcript.replace(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
+        let transcript = self
+            .certificate_transcript
+            .take()
+            .ok_or(TlsError::InvalidHandshake)?;
+        verify_certificate_verify(&self.public_key, transcript, verify)
+    }
+
+    #[cfg(feature = "tls12")]
+    fn verify_tls12_signature(
+        &mut self,
+        message: &[u8],
+        verify: CertificateVerify,
+    ) -> Result<(), TlsError> {
+        crate::raw_public_key::verify_signature(
+            &self.public_key,
+            verify.signature_scheme,
+            message,
+            verify.signature,
+        )
+    }
+}
+
+/// Storage of the fingerprints of the servers trusted by a [`TofuVerifier`], keyed by server
+/// name. A fingerprint is the SHA-256 hash of the DER encoded SubjectPublicKeyInfo of the
+/// server, as a pin of [`TlsConfig::with_spki_pins`].
+///
+/// Like a [`TlsClock`](crate::config::TlsClock), the store is a type rather than an instance,
+/// since the verifier is created by the connection. Implementations keep the fingerprints in a
+/// global, in RAM or in flash, so that they outlive the connections.
+pub trait FingerprintStore {
+    /// Returns the fingerprint recorded for `server`, if any.
+    fn get(server: &str) -> Option<[u8; 32]>;
+
+    /// Records the fingerprint of `server`, the first time it is contacted. A fingerprint which
+    /// is not recorded leaves the next key of the server to be trusted on first use again.
+    fn put(server: &str, fingerprint: [u8; 32]);
+}
+
+/// A verifier which trusts the key of a server the first time it is seen, and only this key on
+/// the following connections, for equipment with self-signed certificates.
+///
+/// The key is looked up in the `Store` with the server name given to
+/// [`TlsConfig::with_server_name`], which is required. A server without a recorded fingerprint
+/// is trusted, and its fingerprint is recorded once it has proven the possession of the key by
+/// signing the handshake. A server with another key is rejected with a bad_certificate alert,
+/// until its fingerprint is removed from the store. The chain of the certificate is not
+/// validated, and the key must be an ECDSA key over P-256 or P-384.
+pub struct TofuVerifier<'a, CipherSuite, Store>
+where
+    CipherSuite: TlsCipherSuite,
+    Store: FingerprintStore,
+{
+    host: Option<&'a str>,
+    key: PinnedKeyVerifier<CipherSuite>,
+    first_contact: Option<[u8; 32]>,
+    _store: PhantomData<Store>,
+}
+
+impl<'a, CipherSuite, Store> TofuVerifier<'a, CipherSuite, Store>
+where
+    CipherSuite: TlsCipherSuite,
+    Store: FingerprintStore,
+{
+    /// Records the fingerprint of a server contacted for the first time, once the handshake is
+    /// signed with its key.
+    fn record(&mut self) {
+        if let (Some(host), Some(fingerprint)) = (self.host, self.first_contact.take()) {
+            info!("Trusting the key of {} on first use", host);
+            Store::put(host, fingerprint);
+        }
+    }
+}
+
+impl<'a, CipherSuite, Store> TlsVerifier<'a, CipherSuite> for TofuVerifier<'a, CipherSuite, Store>
+where
+    CipherSuite: TlsCipherSuite,
+    Store: FingerprintStore,
+{
+    fn new(host: Option<&'a str>) -> Self {
+        Self {
+            host,
+            key: PinnedKeyVerifier::new(host),
+            first_contact: None,
+            _store: PhantomData,
+        }
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        let Some(host) = self.host else {
+            warn!("No server name to trust the key of the server for");
+            return Err(TlsError::InvalidCertificate);
+        };
+        let fingerprint = fingerprint(&cert)?;
+        match Store::get(host) {
+            Some(recorded) if recorded == fingerprint => {}
+            Some(_) => {
+                warn!("The key of {} is not the one trusted on first use", host);
+                return Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::BadCertificate,
+                ));
+            }
+            None => self.first_contact = Some(fingerprint),
+        }
+        // The key is pinned to the recorded fingerprint, or to the one seen on first contact
+        self.key.set_spki_pins(&[fingerprint]);
+        self.key.verify_certificate(transcript, ca, cert)
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
+        self.key.verify_signature(verify)?;
+        self.record();
+        Ok(())
+    }
+
+    #[cfg(feature = "tls12")]
+    fn verify_tls12_signature(
+        &mut self,
+        message: &[u8],
+        verify: CertificateVerify,
+    ) -> Result<(), TlsError> {
+        self.key.verify_tls12_signature(message, verify)?;
+        self.record();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Aes128GcmSha256;
+
+    #[test]
+    fn test_verify_spki_pins() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let spki = crate::x509::subject_public_key_info(&der).unwrap();
+        let mut pin = [0; 32];
+        pin.copy_from_slice(&Sha256::digest(spki));
+        let other_pin = [0; 32];
+
+        let mut certificate = CertificateRef::with_context(&[]);
+        certificate.add(CertificateEntryRef::X509(&der)).unwrap();
+        let mut raw_public_key = CertificateRef::with_context(&[]);
+        raw_public_key
+            .add(CertificateEntryRef::RawPublicKey(spki))
+            .unwrap();
+
+        let verify = |pins, certificate| {
+            let config = TlsConfig::<Aes128GcmSha256>::new().with_spki_pins(pins);
+            verify_spki_pins(&config, certificate)
+        };
+        // Any of the pins matches
+        let pin_sets: [&[[u8; 32]]; 2] = [&[pin], &[other_pin, pin]];
+        for pins in pin_sets {
+            assert!(verify(pins, &certificate).is_ok());
+            assert!(verify(pins, &raw_public_key).is_ok());
+        }
+        assert!(matches!(
+            verify(&[other_pin][..], &certificate),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::BadCertificate
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_pinned_key_verifier_without_pins() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let transcript = Sha256::new();
+        let verify = |pins: &[[u8; 32]]| {
+            let mut certificate = CertificateRef::with_context(&[]);
+            certificate.add(CertificateEntryRef::X509(&der)).unwrap();
+            let mut verifier = PinnedKeyVerifier::<Aes128GcmSha256>::new(Some("localhost"));
+            verifier.set_spki_pins(pins);
+            verifier.verify_certificate(&transcript, &None, certificate)
+        };
+        // The connection has checked the key against the pins, if any
+        assert!(verify(&[[0; 32]]).is_ok());
+        // Without pins, the key is not trusted
+        assert!(matches!(verify(&[]), Err(TlsError::InvalidCertificate)));
+    }
+}
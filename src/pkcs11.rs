@@ -0,0 +1,128 @@
+//! An [`ExternalSigner`](crate::blocking::ExternalSigner) for client identity keys stored as
+//! PKCS#11 objects, for example in a smart card, HSM or a software token on a Linux gateway.
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsPssParams};
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::ObjectHandle;
+use cryptoki::session::Session;
+
+use crate::signature_schemes::SignatureScheme;
+use crate::signer::encode_ecdsa_signature;
+use crate::TlsError;
+
+/// Signs the client CertificateVerify with a private key object of an open PKCS#11 session.
+///
+/// The session must be logged in if the token requires it. Supported schemes are ECDSA with
+/// P-256 and P-384, and RSASSA-PSS with SHA-256, SHA-384 and SHA-512.
+pub struct Pkcs11Signer {
+    session: Session,
+    key: ObjectHandle,
+    scheme: SignatureScheme,
+}
+
+impl Pkcs11Signer {
+    /// Create a signer for the private `key` of `session`, producing signatures with `scheme`.
+    pub fn new(
+        session: Session,
+        key: ObjectHandle,
+        scheme: SignatureScheme,
+    ) -> Result<Self, TlsError> {
+        mechanism(scheme)?;
+        Ok(Self {
+            session,
+            key,
+            scheme,
+        })
+    }
+
+    /// Release the session.
+    pub fn into_session(self) -> Session {
+        self.session
+    }
+
+    fn sign_message(&self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        let mechanism = mechanism(self.scheme)?;
+        let output = self
+            .session
+            .sign(&mechanism, self.key, message)
+            .map_err(|_e| {
+                warn!("PKCS#11 signing failed: {:?}", _e);
+                TlsError::CryptoError
+            })?;
+
+        if is_ecdsa(self.scheme) {
+            // PKCS#11 produces ECDSA signatures as the concatenation of `r` and `s`
+            encode_ecdsa_signature(&output, signature)
+        } else {
+            let signature = signature
+                .get_mut(..output.len())
+                .ok_or(TlsError::InsufficientSpace)?;
+            signature.copy_from_slice(&output);
+            Ok(output.len())
+        }
+    }
+}
+
+fn is_ecdsa(scheme: SignatureScheme) -> bool {
+    matches!(
+        scheme,
+        SignatureScheme::EcdsaSecp256r1Sha256 | SignatureScheme::EcdsaSecp384r1Sha384
+    )
+}
+
+fn mechanism(scheme: SignatureScheme) -> Result<Mechanism<'static>, TlsError> {
+    match scheme {
+        SignatureScheme::EcdsaSecp256r1Sha256 => Ok(Mechanism::EcdsaSha256),
+        SignatureScheme::EcdsaSecp384r1Sha384 => Ok(Mechanism::EcdsaSha384),
+        SignatureScheme::RsaPssRsaeSha256 => Ok(Mechanism::Sha256RsaPkcsPss(PkcsPssParams {
+            hash_alg: MechanismType::SHA256,
+            mgf: PkcsMgfType::MGF1_SHA256,
+            s_len: 32.into(),
+        })),
+        SignatureScheme::RsaPssRsaeSha384 => Ok(Mechanism::Sha384RsaPkcsPss(PkcsPssParams {
+            hash_alg: MechanismType::SHA384,
+            mgf: PkcsMgfType::MGF1_SHA384,
+            s_len: 48.into(),
+        })),
+        SignatureScheme::RsaPssRsaeSha512 => Ok(Mechanism::Sha512RsaPkcsPss(PkcsPssParams {
+            hash_alg: MechanismType::SHA512,
+            mgf: PkcsMgfType::MGF1_SHA512,
+            s_len: 64.into(),
+        })),
+        _ => Err(TlsError::InvalidSignatureScheme),
+    }
+}
+
+impl crate::signer::blocking::ExternalSigner for Pkcs11Signer {
+    fn signature_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+
+    fn sign(&mut self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        self.sign_message(message, signature)
+    }
+}
+
+#[cfg(feature = "async")]
+impl crate::signer::asynch::ExternalSigner for Pkcs11Signer {
+    fn signature_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+
+    async fn sign(&mut self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        self.sign_message(message, signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_scheme() {
+        assert!(matches!(
+            mechanism(SignatureScheme::Ed25519),
+            Err(TlsError::InvalidSignatureScheme)
+        ));
+        assert!(mechanism(SignatureScheme::RsaPssRsaeSha256).is_ok());
+    }
+}
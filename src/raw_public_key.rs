@@ -0,0 +1,225 @@
+//! RFC 7250.  Using Raw Public Keys in Transport Layer Security (TLS) and Datagram Transport
+//! Layer Security (DTLS)
+//!
+//! The server authenticates with a bare SubjectPublicKeyInfo instead of an X.509 certificate,
+//! which saves the bytes of the certificate chain. The key is compared with the key pinned by the
+//! application, and only ECDSA keys over P-256 and P-384 are supported.
+use heapless::Vec;
+use sha2::Digest;
+
+use crate::config::{Certificate, TlsCipherSuite, TlsVerifier};
+use crate::handshake::certificate::{CertificateEntryRef, CertificateRef};
+use crate::handshake::certificate_verify::CertificateVerify;
+use crate::signature_schemes::SignatureScheme;
+use crate::x509::{SubjectPublicKeyInfo, ID_EC_PUBLIC_KEY, SECP256R1, SECP384R1};
+use crate::TlsError;
+
+/// The length of the SubjectPublicKeyInfo of a P-384 key, the longest supported key.
+pub(crate) const MAX_PUBLIC_KEY_LEN: usize = 120;
+
+const SERVER_CONTEXT: &[u8] = b"TLS 1.3, server CertificateVerify\x00";
+
+/// A verifier of the raw public key of the server, which must be the key given to
+/// [`TlsConfig::with_ca`](crate::config::TlsConfig::with_ca) as a [`Certificate::RawPublicKey`].
+pub struct RawPublicKeyVerifier<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    public_key: Vec<u8, MAX_PUBLIC_KEY_LEN>,
+    certificate_transcript: Option<CipherSuite::Hash>,
+}
+
+impl<'a, CipherSuite> TlsVerifier<'a, CipherSuite> for RawPublicKeyVerifier<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    fn new(_host: Option<&'a str>) -> Self {
+        Self {
+            public_key: Vec::new(),
+            certificate_transcript: None,
+        }
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        cert: CertificateRef,
+    ) -> Result<(), TlsError> {
+        let Some(Certificate::RawPublicKey(pinned)) = ca else {
+            warn!("No raw public key of the server is pinned");
+            return Err(TlsError::InvalidCertificate);
+        };
+        // RFC 8446, Section 4.4.2.  Certificate
+        // If the RawPublicKey certificate type was negotiated, then the certificate_list MUST
+        // contain no more than one CertificateEntry, which contains an ASN1_subjectPublicKeyInfo
+        // value as defined in [RFC7250], Section 3.
+        match cert.entries.as_slice() {
+            [CertificateEntryRef::RawPublicKey(key)] if key == pinned => {}
+            _ => {
+                warn!("The public key of the server is not the pinned key");
+                return Err(TlsError::InvalidCertificate);
+            }
+        }
+        self.public_key = Vec::from_slice(pinned).map_err(|_| TlsError::InvalidCertificate)?;
+        self.certificate_transcript.replace(transcript.clone());
+        Ok(())
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
+        let transcript = self
+            .certificate_transcript
+            .take()
+            .ok_or(TlsError::InvalidHandshake)?;
+        verify_certificate_verify(&self.public_key, transcript, verify)
+    }
+}
+
+/// Verifies the CertificateVerify of the server with a DER encoded SubjectPublicKeyInfo, given
+/// the transcript up to the Certificate message.
+pub(crate) fn verify_certificate_verify<Hash: Digest>(
+    public_key: &[u8],
+    transcript: Hash,
+    verify: CertificateVerify,
+) -> Result<(), TlsError> {
+    // 64 bytes of padding + 34 bytes of context string + transcript hash (up to 64 bytes)
+    let mut message: Vec<u8, { 64 + 34 + 64 }> = Vec::new();
+    message
+        .resize(64, 0x20)
+        .map_err(|_| TlsError::EncodeError)?;
+    message
+        .extend_from_slice(SERVER_CONTEXT)
+        .map_err(|_| TlsError::EncodeError)?;
+    message
+        .extend_from_slice(&transcript.finalize())
+        .map_err(|_| TlsError::EncodeError)?;
+
+    verify_signature(
+        public_key,
+        verify.signature_scheme,
+        &message,
+        verify.signature,
+    )
+}
+
+/// Verifies a handshake signature with a DER encoded SubjectPublicKeyInfo.
+pub(crate) fn verify_signature(
+    public_key: &[u8],
+    scheme: SignatureScheme,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), TlsError> {
+    let spki = SubjectPublicKeyInfo::from_der(public_key)?;
+    verify_spki_signature(&spki, scheme, message, signature)
+}
+
+/// Verifies a handshake signature with a parsed SubjectPublicKeyInfo, for example the one of an
+/// X.509 certificate.
+pub(crate) fn verify_spki_signature(
+    spki: &SubjectPublicKeyInfo,
+    scheme: SignatureScheme,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), TlsError> {
+    if spki.algorithm != ID_EC_PUBLIC_KEY {
+        return Err(TlsError::InvalidSignatureScheme);
+    }
+    match (scheme, spki.named_curve) {
+        (SignatureScheme::EcdsaSecp256r1Sha256, Some(curve)) if curve == SECP256R1 => {
+            use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let signature =
+                Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify(message, &signature)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        (SignatureScheme::EcdsaSecp384r1Sha384, Some(curve)) if curve == SECP384R1 => {
+            use p384::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let signature =
+                Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify(message, &signature)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        _ => {
+            warn!(
+                "Signature scheme {:?} does not match the raw public key",
+                scheme
+            );
+            Err(TlsError::InvalidSignatureScheme)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Aes128GcmSha256;
+    use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use sha2::Sha256;
+
+    // SubjectPublicKeyInfo of id-ecPublicKey with prime256v1
+    const SPKI_PREFIX: [u8; 26] = [
+        0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x08,
+        0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+    ];
+
+    fn public_key(signing_key: &SigningKey) -> Vec<u8, MAX_PUBLIC_KEY_LEN> {
+        let mut key = Vec::from_slice(&SPKI_PREFIX).unwrap();
+        key.extend_from_slice(
+            signing_key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes(),
+        )
+        .unwrap();
+        key
+    }
+
+    #[test]
+    fn test_verify_pinned_key() {
+        let signing_key = SigningKey::from_bytes(&[0x11; 32]).unwrap();
+        let key = public_key(&signing_key);
+        let ca = Some(Certificate::RawPublicKey(&key));
+        let mut transcript = Sha256::new();
+        transcript.update(b"handshake");
+
+        let mut certificate = CertificateRef::with_context(&[]);
+        certificate
+            .add(CertificateEntryRef::RawPublicKey(&key))
+            .unwrap();
+        let mut verifier =
+            <RawPublicKeyVerifier<Aes128GcmSha256> as TlsVerifier<Aes128GcmSha256>>::new(None);
+        verifier
+            .verify_certificate(&transcript, &ca, certificate)
+            .unwrap();
+
+        let mut message: Vec<u8, 130> = Vec::new();
+        message.resize(64, 0x20).unwrap();
+        message.extend_from_slice(SERVER_CONTEXT).unwrap();
+        message.extend_from_slice(&transcript.finalize()).unwrap();
+        let signature: Signature = signing_key.sign(&message);
+        let signature = signature.to_der();
+        verifier
+            .verify_signature(CertificateVerify {
+                signature_scheme: SignatureScheme::EcdsaSecp256r1Sha256,
+                signature: signature.as_bytes(),
+            })
+            .unwrap();
+
+        // Another key is rejected
+        let other_key = public_key(&SigningKey::from_bytes(&[0x22; 32]).unwrap());
+        let mut certificate = CertificateRef::with_context(&[]);
+        certificate
+            .add(CertificateEntryRef::RawPublicKey(&other_key))
+            .unwrap();
+        assert!(matches!(
+            verifier.verify_certificate(&Sha256::new(), &ca, certificate),
+            Err(TlsError::InvalidCertificate)
+        ));
+    }
+}
@@ -1,16 +1,25 @@
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic
+    )
+)]
+
 use crate::application_data::ApplicationData;
 use crate::buffer::*;
 use crate::change_cipher_spec::ChangeCipherSpec;
-use crate::config::{TlsCipherSuite, TlsConfig};
+use crate::config::TlsCipherSuite;
 use crate::content_types::ContentType;
-use crate::handshake::client_hello::ClientHello;
 use crate::handshake::{ClientHandshake, ServerHandshake};
+use crate::heartbeat::HeartbeatMessage;
 use crate::key_schedule::{HashOutputSize, ReadKeySchedule, WriteKeySchedule};
 use crate::TlsError;
 use crate::{alert::*, parse_buffer::ParseBuffer};
 use core::fmt::Debug;
 use generic_array::ArrayLength;
-use rand_core::{CryptoRng, RngCore};
 use sha2::Digest;
 use typenum::Unsigned;
 
@@ -26,6 +35,7 @@ where
     ChangeCipherSpec(ChangeCipherSpec, Encrypted),
     Alert(Alert, Encrypted),
     ApplicationData(&'a [u8]),
+    Heartbeat(HeartbeatMessage<'a>),
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -35,6 +45,7 @@ pub enum ClientRecordHeader {
     ChangeCipherSpec(Encrypted),
     Alert(Encrypted),
     ApplicationData,
+    Heartbeat,
 }
 
 impl ClientRecordHeader {
@@ -43,28 +54,29 @@ impl ClientRecordHeader {
             ClientRecordHeader::Handshake(encrypted)
             | ClientRecordHeader::ChangeCipherSpec(encrypted)
             | ClientRecordHeader::Alert(encrypted) => *encrypted,
-            ClientRecordHeader::ApplicationData => true,
+            ClientRecordHeader::ApplicationData | ClientRecordHeader::Heartbeat => true,
         }
     }
 
     pub fn header_content_type(&self) -> ContentType {
         match self {
             Self::Handshake(false) => ContentType::Handshake,
-            Self::Alert(false) => ContentType::ChangeCipherSpec,
+            Self::Alert(false) => ContentType::Alert,
             Self::ChangeCipherSpec(false) => ContentType::ChangeCipherSpec,
             Self::Handshake(true) => ContentType::ApplicationData,
             Self::Alert(true) => ContentType::ApplicationData,
             Self::ChangeCipherSpec(true) => ContentType::ApplicationData,
-            Self::ApplicationData => ContentType::ApplicationData,
+            Self::ApplicationData | Self::Heartbeat => ContentType::ApplicationData,
         }
     }
 
     pub fn trailer_content_type(&self) -> ContentType {
         match self {
             Self::Handshake(_) => ContentType::Handshake,
-            Self::Alert(_) => ContentType::ChangeCipherSpec,
+            Self::Alert(_) => ContentType::Alert,
             Self::ChangeCipherSpec(_) => ContentType::ChangeCipherSpec,
             Self::ApplicationData => ContentType::ApplicationData,
+            Self::Heartbeat => ContentType::Heartbeat,
         }
     }
 
@@ -73,10 +85,10 @@ impl ClientRecordHeader {
             Self::Handshake(true) => [0x03, 0x03],
             Self::Handshake(false) => [0x03, 0x01],
             Self::ChangeCipherSpec(true) => [0x03, 0x03],
-            Self::ChangeCipherSpec(false) => [0x03, 0x01],
+            Self::ChangeCipherSpec(false) => [0x03, 0x03],
             Self::Alert(true) => [0x03, 0x03],
-            Self::Alert(false) => [0x03, 0x01],
-            Self::ApplicationData => [0x03, 0x03],
+            Self::Alert(false) => [0x03, 0x03],
+            Self::ApplicationData | Self::Heartbeat => [0x03, 0x03],
         }
     }
 
@@ -103,22 +115,10 @@ where
             }
             ClientRecord::Alert(_, encrypted) => ClientRecordHeader::Alert(*encrypted),
             ClientRecord::ApplicationData(_) => ClientRecordHeader::ApplicationData,
+            ClientRecord::Heartbeat(_) => ClientRecordHeader::Heartbeat,
         }
     }
 
-    pub fn client_hello<RNG>(
-        config: &'config TlsConfig<'config, CipherSuite>,
-        rng: &mut RNG,
-    ) -> Self
-    where
-        RNG: CryptoRng + RngCore,
-    {
-        ClientRecord::Handshake(
-            ClientHandshake::ClientHello(ClientHello::new(config, rng)),
-            false,
-        )
-    }
-
     pub fn close_notify(opened: bool) -> Self {
         ClientRecord::Alert(
             Alert::new(AlertLevel::Warning, AlertDescription::CloseNotify),
@@ -137,6 +137,7 @@ where
             ClientRecord::ApplicationData(data) => buf
                 .extend_from_slice(data)
                 .map_err(|_| TlsError::EncodeError)?,
+            ClientRecord::Heartbeat(message) => message.encode(buf)?,
         };
 
         Ok(buf.len() - record_length_marker)
@@ -149,11 +150,29 @@ where
         write_key_schedule: &mut WriteKeySchedule<CipherSuite>,
     ) -> Result<(), TlsError> {
         match self {
+            // The encrypted records of TLS 1.2 have no inner content type
+            #[cfg(feature = "tls12")]
+            ClientRecord::Handshake(ClientHandshake::Tls12(_), _) => {
+                read_key_schedule
+                    .ok_or(TlsError::InternalError)?
+                    .transcript_hash()
+                    .update(buf.as_slice());
+            }
             ClientRecord::Handshake(handshake, false) => {
+                let read_key_schedule = read_key_schedule.ok_or(TlsError::InternalError)?;
+
+                // draft-ietf-tls-esni, Section 6.1.1.  Encrypting the ClientHello
+                // The ClientHelloInner is encrypted into the ClientHelloOuter before the
+                // ClientHelloOuter is added to the transcript.
+                #[cfg(feature = "ech")]
+                if let ClientHandshake::ClientHello(hello) = handshake {
+                    if let Some(inner_transcript) = hello.seal_inner(buf)? {
+                        read_key_schedule.set_inner_transcript_hash(inner_transcript);
+                    }
+                }
+
                 let enc_buf = &mut buf.as_mut_slice();
-                let transcript = read_key_schedule
-                    .ok_or(TlsError::InternalError)?
-                    .transcript_hash();
+                let transcript = read_key_schedule.transcript_hash();
 
                 if let ClientHandshake::ClientHello(hello) = handshake {
                     // Special case for PSK which needs to:
@@ -164,24 +183,42 @@ where
                     //
                     // This causes a few issues since lengths must be correctly inside the payload,
                     // but won't actually be added to the record buffer until the end.
-                    if let Some((_, identities)) = &hello.config.psk {
+                    let identities = hello.config.psk_identities();
+                    if identities > 0 {
                         let binders_len =
-                            identities.len() * (1 + HashOutputSize::<CipherSuite>::to_usize());
+                            identities * (1 + HashOutputSize::<CipherSuite>::to_usize());
 
-                        let binders_pos = enc_buf.len() - binders_len;
+                        let binders_pos = enc_buf
+                            .len()
+                            .checked_sub(binders_len)
+                            .ok_or(TlsError::EncodeError)?;
+                        let binders_len_pos =
+                            binders_pos.checked_sub(2).ok_or(TlsError::EncodeError)?;
 
                         // NOTE: Exclude the binders_len itself from the digest
-                        transcript.update(&enc_buf[0..binders_pos - 2]);
+                        transcript.update(
+                            enc_buf
+                                .get(..binders_len_pos)
+                                .ok_or(TlsError::EncodeError)?,
+                        );
 
                         // Append after the client hello data. Sizes have already been set.
-                        let mut buf = CryptoBuffer::wrap(&mut enc_buf[binders_pos..]);
+                        let mut buf = CryptoBuffer::wrap(
+                            enc_buf
+                                .get_mut(binders_pos..)
+                                .ok_or(TlsError::EncodeError)?,
+                        );
                         // Create a binder and encode for each identity
-                        for _id in identities {
+                        for _ in 0..identities {
                             let binder = write_key_schedule.create_psk_binder(transcript)?;
                             binder.encode(&mut buf)?;
                         }
 
-                        transcript.update(&enc_buf[binders_pos - 2..]);
+                        transcript.update(
+                            enc_buf
+                                .get(binders_len_pos..)
+                                .ok_or(TlsError::EncodeError)?,
+                        );
                     } else {
                         transcript.update(enc_buf);
                     }
@@ -189,14 +226,16 @@ where
                     transcript.update(enc_buf);
                 }
             }
+            // Post-handshake messages are not part of the transcript
+            ClientRecord::Handshake(ClientHandshake::KeyUpdate(_), true) => {}
             ClientRecord::Handshake(_, true) => {
                 let transcript = read_key_schedule
                     .ok_or(TlsError::InternalError)?
                     .transcript_hash();
 
-                let end = buf.len();
                 // Don't include the content type in the slice
-                transcript.update(&buf.as_slice()[0..end - 1]);
+                let (_, handshake) = buf.as_slice().split_last().ok_or(TlsError::EncodeError)?;
+                transcript.update(handshake);
             }
             _ => {}
         };
@@ -221,8 +260,8 @@ pub struct RecordHeader {
 
 impl RecordHeader {
     pub fn content_type(&self) -> ContentType {
-        // Content type already validated in read
-        ContentType::of(self.header[0]).unwrap()
+        // Content type already validated in decode
+        ContentType::of(self.header[0]).unwrap_or(ContentType::Invalid)
     }
 
     pub fn content_length(&self) -> usize {
@@ -243,6 +282,21 @@ impl RecordHeader {
 }
 
 impl<'a, N: ArrayLength<u8>> ServerRecord<'a, N> {
+    /// Wraps a record which is protected whatever its content type, as in TLS 1.2.
+    #[cfg(feature = "tls12")]
+    pub(crate) fn protected(
+        header: RecordHeader,
+        data: &'a mut [u8],
+    ) -> Result<ServerRecord<'a, N>, TlsError> {
+        if header.content_length() != data.len() {
+            return Err(TlsError::InvalidRecord);
+        }
+        let buf = CryptoBuffer::wrap_with_pos(data, data.len());
+        Ok(ServerRecord::ApplicationData(ApplicationData::new(
+            buf, header,
+        )))
+    }
+
     pub fn content_type(&self) -> ContentType {
         match self {
             ServerRecord::Handshake(_) => ContentType::Handshake,
@@ -260,7 +314,9 @@ impl<'a, N: ArrayLength<u8>> ServerRecord<'a, N> {
     where
         D: Digest,
     {
-        assert_eq!(header.content_length(), data.len());
+        if header.content_length() != data.len() {
+            return Err(TlsError::InvalidRecord);
+        }
         match header.content_type() {
             ContentType::Invalid => Err(TlsError::Unimplemented),
             ContentType::ChangeCipherSpec => Ok(ServerRecord::ChangeCipherSpec(
@@ -280,6 +336,8 @@ impl<'a, N: ArrayLength<u8>> ServerRecord<'a, N> {
                     buf, header,
                 )))
             }
+            // Heartbeats are only negotiated with TLS 1.3, which protects them
+            ContentType::Heartbeat => Err(TlsError::InvalidRecord),
         }
     }
 
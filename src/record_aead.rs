@@ -0,0 +1,223 @@
+use aes_gcm::aead::{AeadCore, AeadInPlace, Buffer, Error, KeyInit};
+use generic_array::{ArrayLength, GenericArray};
+use typenum::Unsigned;
+
+/// The AEAD used to protect TLS records.
+///
+/// The record layer only needs to know the key, nonce and tag lengths of the AEAD, plus a way to
+/// seal and open a record in place. This is implemented for every `KeyInit + AeadInPlace` cipher
+/// from the RustCrypto `aead` crates (AES-GCM, AES-CCM, AES-CCM-8, ...), and can be implemented
+/// directly for any other AEAD.
+pub trait RecordAead: Sized {
+    type KeyLen: ArrayLength<u8>;
+    type NonceLen: ArrayLength<u8>;
+    type TagLen: Unsigned;
+
+    fn new(key: &GenericArray<u8, Self::KeyLen>) -> Self;
+
+    /// Encrypts `buffer` in place and appends the `TagLen` byte authentication tag.
+    fn encrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, Self::NonceLen>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error>;
+
+    /// Verifies and strips the trailing authentication tag, then decrypts `buffer` in place.
+    fn decrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, Self::NonceLen>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error>;
+
+    fn tag_len() -> usize {
+        Self::TagLen::to_usize()
+    }
+}
+
+impl<T> RecordAead for T
+where
+    T: KeyInit + AeadInPlace,
+{
+    type KeyLen = T::KeySize;
+    type NonceLen = T::NonceSize;
+    type TagLen = <T as AeadCore>::TagSize;
+
+    fn new(key: &GenericArray<u8, Self::KeyLen>) -> Self {
+        <T as KeyInit>::new(key)
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, Self::NonceLen>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        AeadInPlace::encrypt_in_place(self, nonce, additional_data, buffer)
+    }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, Self::NonceLen>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        AeadInPlace::decrypt_in_place(self, nonce, additional_data, buffer)
+    }
+}
+
+/// An object safe view of a [`RecordAead`] for the record layer.
+///
+/// With the `dynamic-suites` feature, the record layer protects records through
+/// `&dyn DynRecordAead`, so that the record framing is compiled once rather than once for every
+/// cipher suite used by the application.
+pub(crate) trait DynRecordAead {
+    fn tag_len(&self) -> usize;
+
+    fn seal(
+        &self,
+        nonce: &[u8],
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error>;
+
+    fn open(
+        &self,
+        nonce: &[u8],
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error>;
+}
+
+impl<T> DynRecordAead for T
+where
+    T: RecordAead,
+{
+    fn tag_len(&self) -> usize {
+        T::tag_len()
+    }
+
+    fn seal(
+        &self,
+        nonce: &[u8],
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        if nonce.len() != T::NonceLen::to_usize() {
+            return Err(Error);
+        }
+        self.encrypt_in_place(GenericArray::from_slice(nonce), additional_data, buffer)
+    }
+
+    fn open(
+        &self,
+        nonce: &[u8],
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        if nonce.len() != T::NonceLen::to_usize() {
+            return Err(Error);
+        }
+        self.decrypt_in_place(GenericArray::from_slice(nonce), additional_data, buffer)
+    }
+}
+
+/// The record AEAD as used by the record layer, type erased with the `dynamic-suites` feature.
+#[cfg(feature = "dynamic-suites")]
+pub(crate) fn record_engine<A: RecordAead>(aead: &A) -> &dyn DynRecordAead {
+    aead
+}
+
+#[cfg(not(feature = "dynamic-suites"))]
+pub(crate) fn record_engine<A: RecordAead>(aead: &A) -> &A {
+    aead
+}
+
+/// Appends room for a `tag_len` byte authentication tag to `buffer`, and returns the plaintext
+/// and the tag region.
+///
+/// The regions are contiguous, and the plaintext of a record starts at a
+/// [`TLS_RECORD_ALIGNMENT`](crate::TLS_RECORD_ALIGNMENT) aligned address. A DMA-based
+/// [`RecordAead::encrypt_in_place`] can thus encrypt the plaintext in place and write the tag
+/// directly behind it.
+pub fn ciphertext_region(
+    buffer: &mut dyn Buffer,
+    tag_len: usize,
+) -> Result<(&mut [u8], &mut [u8]), Error> {
+    let len = buffer.len();
+    for _ in 0..tag_len {
+        buffer.extend_from_slice(&[0])?;
+    }
+    Ok(buffer.as_mut().split_at_mut(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::CryptoBuffer;
+    use aes::Aes128;
+    use aes_gcm::Aes128Gcm;
+    use ccm::Ccm;
+    use typenum::{U12, U8};
+
+    fn roundtrip<A: RecordAead>(expected_tag_len: usize) {
+        let key = GenericArray::default();
+        let nonce = GenericArray::default();
+        let aead = A::new(&key);
+
+        let mut storage = [0; 32];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        buf.extend_from_slice(b"ping").unwrap();
+
+        aead.encrypt_in_place(&nonce, b"aad", &mut buf).unwrap();
+        assert_eq!(4 + expected_tag_len, buf.len());
+        assert_eq!(expected_tag_len, A::tag_len());
+
+        aead.decrypt_in_place(&nonce, b"aad", &mut buf).unwrap();
+        assert_eq!(b"ping", buf.as_slice());
+    }
+
+    #[test]
+    fn test_gcm_tag_len() {
+        roundtrip::<Aes128Gcm>(16);
+    }
+
+    #[test]
+    fn test_ccm8_tag_len() {
+        roundtrip::<Ccm<Aes128, U8, U12>>(8);
+    }
+
+    #[test]
+    fn test_dyn_record_aead() {
+        let key = GenericArray::default();
+        let aead = <Aes128Gcm as KeyInit>::new(&key);
+        let engine: &dyn DynRecordAead = &aead;
+        assert_eq!(16, engine.tag_len());
+
+        let mut storage = [0; 32];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        buf.extend_from_slice(b"ping").unwrap();
+        engine.seal(&[0; 12], b"aad", &mut buf).unwrap();
+        assert!(engine.open(&[0; 8], b"aad", &mut buf).is_err());
+        engine.open(&[0; 12], b"aad", &mut buf).unwrap();
+        assert_eq!(b"ping", buf.as_slice());
+    }
+
+    #[test]
+    fn test_ciphertext_region() {
+        let mut storage = [0; 32];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        buf.extend_from_slice(b"ping").unwrap();
+
+        let (plaintext, tag) = ciphertext_region(&mut buf, 16).unwrap();
+        assert_eq!(b"ping", plaintext);
+        assert_eq!(16, tag.len());
+        assert_eq!(20, buf.len());
+
+        let mut storage = [0; 8];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        buf.extend_from_slice(b"ping").unwrap();
+        assert!(ciphertext_region(&mut buf, 16).is_err());
+    }
+}
@@ -1,3 +1,13 @@
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::indexing_slicing,
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic
+    )
+)]
+
 use core::marker::PhantomData;
 
 use crate::key_schedule::{HashOutputSize, ReadKeySchedule};
@@ -7,7 +17,7 @@ use embedded_io::{blocking::Read as BlockingRead, Error};
 use embedded_io::asynch::Read as AsyncRead;
 
 use crate::{
-    config::TlsCipherSuite,
+    config::{align_offset, TlsCipherSuite},
     record::{RecordHeader, ServerRecord},
     TlsError,
 };
@@ -37,6 +47,11 @@ where
         }
     }
 
+    /// Whether bytes of the next record have already been read from the transport.
+    pub(crate) fn has_pending(&self) -> bool {
+        self.pending > 0
+    }
+
     #[cfg(feature = "async")]
     pub async fn read<'m>(
         &'m mut self,
@@ -46,12 +61,24 @@ where
     where
         CipherSuite: TlsCipherSuite + 'static,
     {
+        let (header, data) = self.read_raw(transport).await?;
+        decode(header, data, key_schedule)
+    }
+
+    /// Reads a record without decoding its content.
+    #[cfg(feature = "async")]
+    pub(crate) async fn read_raw<'m>(
+        &'m mut self,
+        transport: &mut impl AsyncRead,
+    ) -> Result<(RecordHeader, &'m mut [u8]), TlsError> {
         let header = self.advance(transport, 5).await?;
-        let header = RecordHeader::decode(header.try_into().unwrap())?;
+        let header: [u8; 5] = header.try_into().map_err(|_| TlsError::InternalError)?;
+        let header = RecordHeader::decode(header)?;
 
         let content_length = header.content_length();
+        self.ensure_aligned(content_length)?;
         let data = self.advance(transport, content_length).await?;
-        ServerRecord::decode(header, data, key_schedule.transcript_hash())
+        Ok((header, data))
     }
 
     #[cfg(feature = "async")]
@@ -64,16 +91,19 @@ where
 
         while self.pending < amount {
             let read = transport
-                .read(&mut self.buf[self.decoded + self.pending..])
+                .read(self.free_space()?)
                 .await
                 .map_err(|e| TlsError::Io(e.kind()))?;
             if read == 0 {
-                return Err(TlsError::IoError);
+                return Err(TlsError::UnexpectedEof);
             }
             self.pending += read;
         }
 
-        let slice = &mut self.buf[self.decoded..self.decoded + amount];
+        let slice = self
+            .buf
+            .get_mut(self.decoded..self.decoded + amount)
+            .ok_or(TlsError::InternalError)?;
         self.decoded += amount;
         self.pending -= amount;
         Ok(slice)
@@ -87,12 +117,22 @@ where
     where
         CipherSuite: TlsCipherSuite + 'static,
     {
+        let (header, data) = self.read_raw_blocking(transport)?;
+        decode(header, data, key_schedule)
+    }
+
+    pub(crate) fn read_raw_blocking<'m>(
+        &'m mut self,
+        transport: &mut impl BlockingRead,
+    ) -> Result<(RecordHeader, &'m mut [u8]), TlsError> {
         let header = self.advance_blocking(transport, 5)?;
-        let header = RecordHeader::decode(header.try_into().unwrap())?;
+        let header: [u8; 5] = header.try_into().map_err(|_| TlsError::InternalError)?;
+        let header = RecordHeader::decode(header)?;
 
         let content_length = header.content_length();
+        self.ensure_aligned(content_length)?;
         let data = self.advance_blocking(transport, content_length)?;
-        ServerRecord::decode(header, data, key_schedule.transcript_hash())
+        Ok((header, data))
     }
 
     fn advance_blocking<'m>(
@@ -104,20 +144,30 @@ where
 
         while self.pending < amount {
             let read = transport
-                .read(&mut self.buf[self.decoded + self.pending..])
+                .read(self.free_space()?)
                 .map_err(|e| TlsError::Io(e.kind()))?;
             if read == 0 {
-                return Err(TlsError::IoError);
+                return Err(TlsError::UnexpectedEof);
             }
             self.pending += read;
         }
 
-        let slice = &mut self.buf[self.decoded..self.decoded + amount];
+        let slice = self
+            .buf
+            .get_mut(self.decoded..self.decoded + amount)
+            .ok_or(TlsError::InternalError)?;
         self.decoded += amount;
         self.pending -= amount;
         Ok(slice)
     }
 
+    /// The part of the buffer after the read but not yet decoded bytes.
+    fn free_space(&mut self) -> Result<&mut [u8], TlsError> {
+        self.buf
+            .get_mut(self.decoded + self.pending..)
+            .ok_or(TlsError::InternalError)
+    }
+
     fn ensure_contiguous(&mut self, len: usize) -> Result<(), TlsError> {
         if self.decoded + len > self.buf.len() {
             if len > self.buf.len() {
@@ -130,6 +180,47 @@ where
 
         Ok(())
     }
+
+    /// Moves the read but not yet decoded bytes so that the next `len` bytes start at an aligned
+    /// address, either in place or at the start of the buffer.
+    fn ensure_aligned(&mut self, len: usize) -> Result<(), TlsError> {
+        let mut target = align_offset(self.buf, self.decoded);
+        if target + usize::max(len, self.pending) > self.buf.len() {
+            // The header has already been decoded, so the pending bytes always fit at the start
+            target = align_offset(self.buf, 0);
+            if target + len > self.buf.len() {
+                return Err(TlsError::InsufficientSpace);
+            }
+        }
+        if target != self.decoded {
+            self.buf
+                .copy_within(self.decoded..self.decoded + self.pending, target);
+            self.decoded = target;
+        }
+        Ok(())
+    }
+}
+
+fn decode<'m, CipherSuite>(
+    header: RecordHeader,
+    data: &'m mut [u8],
+    key_schedule: &mut ReadKeySchedule<CipherSuite>,
+) -> Result<ServerRecord<'m, HashOutputSize<CipherSuite>>, TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    // Once the keys of TLS 1.2 are installed, the records of every content type but
+    // change_cipher_spec are protected, without hiding their content type
+    #[cfg(feature = "tls12")]
+    if key_schedule.is_tls12()
+        && !matches!(
+            header.content_type(),
+            crate::content_types::ContentType::ChangeCipherSpec
+        )
+    {
+        return ServerRecord::protected(header, data);
+    }
+    ServerRecord::decode(header, data, key_schedule.transcript_hash())
 }
 
 #[cfg(test)]
@@ -141,6 +232,9 @@ mod tests {
 
     struct ChunkRead<'a>(&'a [u8], usize);
 
+    #[repr(align(4))]
+    struct Aligned<const N: usize>([u8; N]);
+
     impl embedded_io::Io for ChunkRead<'_> {
         type Error = Infallible;
     }
@@ -202,8 +296,8 @@ mod tests {
             chunk_size,
         );
 
-        let mut buf = [0; 32];
-        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf);
+        let mut buf = Aligned([0; 32]);
+        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf.0);
         let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
 
         {
@@ -216,7 +310,8 @@ mod tests {
                 panic!("Wrong server record");
             }
 
-            assert_eq!(9, reader.decoded);
+            // The data is moved behind the header to the next aligned position
+            assert_eq!(12, reader.decoded);
             assert_eq!(expected_pending, reader.pending);
         }
 
@@ -230,7 +325,7 @@ mod tests {
                 panic!("Wrong server record");
             }
 
-            assert_eq!(16, reader.decoded);
+            assert_eq!(22, reader.decoded);
             assert_eq!(0, reader.pending);
         }
     }
@@ -261,8 +356,8 @@ mod tests {
         ]
         .as_slice();
 
-        let mut buf = [0; 5]; // This buffer is so small that it cannot contain both the header and data
-        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf);
+        let mut buf = Aligned([0; 5]); // This buffer is so small that it cannot contain both the header and data
+        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf.0);
         let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
 
         {
@@ -313,8 +408,8 @@ mod tests {
         ]
         .as_slice();
 
-        let mut buf = [0; 32];
-        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf);
+        let mut buf = Aligned([0; 32]);
+        let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf.0);
         let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
 
         {
@@ -327,7 +422,7 @@ mod tests {
                 panic!("Wrong server record");
             }
 
-            assert_eq!(5, reader.decoded);
+            assert_eq!(8, reader.decoded);
             assert_eq!(5, reader.pending);
         }
 
@@ -341,8 +436,42 @@ mod tests {
                 panic!("Wrong server record");
             }
 
-            assert_eq!(10, reader.decoded);
+            assert_eq!(16, reader.decoded);
             assert_eq!(0, reader.pending);
         }
     }
+
+    #[test]
+    fn record_data_is_aligned() {
+        for offset in 0..4 {
+            let mut transport = [
+                // Header
+                ContentType::ApplicationData as u8,
+                0x03,
+                0x03,
+                0x00,
+                0x04,
+                // Data
+                0xde,
+                0xad,
+                0xbe,
+                0xef,
+            ]
+            .as_slice();
+
+            let mut buf = Aligned([0; 32]);
+            let mut reader = RecordReader::<Aes128GcmSha256>::new(&mut buf.0[offset..]);
+            let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+
+            if let ServerRecord::ApplicationData(data) = reader
+                .read_blocking(&mut transport, key_schedule.read_state())
+                .unwrap()
+            {
+                assert_eq!([0xde, 0xad, 0xbe, 0xef], data.data.as_slice());
+                assert_eq!(0, data.data.as_slice().as_ptr() as usize % 4);
+            } else {
+                panic!("Wrong server record");
+            }
+        }
+    }
 }
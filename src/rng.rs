@@ -0,0 +1,161 @@
+use core::num::NonZeroU32;
+
+use rand_core::{CryptoRng, RngCore};
+
+use crate::TlsError;
+
+/// A cryptographically secure random number generator which may fail, for example a hardware
+/// TRNG which runs out of entropy.
+///
+/// The ClientHello random and the ephemeral keys of the handshake are drawn from this generator,
+/// and the handshake is aborted with the returned error if it fails. It is implemented for all
+/// [`CryptoRng`] + [`RngCore`] generators, using [`RngCore::try_fill_bytes`].
+pub trait TlsRng {
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), TlsError>;
+}
+
+impl<RNG> TlsRng for RNG
+where
+    RNG: CryptoRng + RngCore,
+{
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), TlsError> {
+        RngCore::try_fill_bytes(self, dest).map_err(|_| TlsError::RngError)
+    }
+}
+
+/// Adapts a [`TlsRng`] to the infallible [`RngCore`] interface of the key generation crates,
+/// recording the first failure so the caller can discard the output.
+pub(crate) struct CheckedRng<'a, RNG> {
+    rng: &'a mut RNG,
+    error: Option<TlsError>,
+}
+
+impl<'a, RNG> CheckedRng<'a, RNG>
+where
+    RNG: TlsRng,
+{
+    pub fn new(rng: &'a mut RNG) -> Self {
+        Self { rng, error: None }
+    }
+
+    /// Returns the first error of the generator, if any.
+    pub fn check(&self) -> Result<(), TlsError> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a, RNG> RngCore for CheckedRng<'a, RNG>
+where
+    RNG: TlsRng,
+{
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        if let Err(e) = self.rng.try_fill_bytes(dest) {
+            // Key generation uses rejection sampling, which would not terminate with an all zero
+            // or all one output. The output is discarded after checking the error.
+            dest.fill(0x01);
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.rng.try_fill_bytes(dest).map_err(|e| {
+            self.error.get_or_insert(e);
+            NonZeroU32::new(rand_core::Error::CUSTOM_START)
+                .unwrap()
+                .into()
+        })
+    }
+}
+
+impl<'a, RNG> CryptoRng for CheckedRng<'a, RNG> where RNG: TlsRng {}
+
+/// Replays fixed bytes instead of randomness, for reproducible handshakes in known-answer tests
+/// such as the traces of RFC 8448.
+///
+/// The handshake draws the private key of the ephemeral key share first, followed by the 32
+/// bytes of the ClientHello random. For X25519, `data` is thus the private key followed by the
+/// random. Fails with [`TlsError::RngError`] once the data is exhausted.
+///
+/// This generator is not random and MUST NOT be used outside of tests.
+#[cfg(feature = "deterministic-rng")]
+pub struct FixedRng<'a> {
+    data: &'a [u8],
+}
+
+#[cfg(feature = "deterministic-rng")]
+impl<'a> FixedRng<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+#[cfg(feature = "deterministic-rng")]
+impl<'a> TlsRng for FixedRng<'a> {
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), TlsError> {
+        if dest.len() > self.data.len() {
+            return Err(TlsError::RngError);
+        }
+        let (bytes, rest) = self.data.split_at(dest.len());
+        dest.copy_from_slice(bytes);
+        self.data = rest;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_exchange::EphemeralSecret;
+    use crate::named_groups::NamedGroup;
+    use rand::rngs::OsRng;
+
+    struct ExhaustedRng;
+
+    impl TlsRng for ExhaustedRng {
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), TlsError> {
+            Err(TlsError::RngError)
+        }
+    }
+
+    #[test]
+    fn test_checked_rng() {
+        let mut rng = OsRng;
+        let mut checked = CheckedRng::new(&mut rng);
+        EphemeralSecret::generate(NamedGroup::Secp256r1, &mut checked).unwrap();
+        assert!(checked.check().is_ok());
+    }
+
+    #[test]
+    fn test_checked_rng_failure() {
+        let mut rng = ExhaustedRng;
+        let mut checked = CheckedRng::new(&mut rng);
+        for group in [NamedGroup::Secp256r1, NamedGroup::X25519] {
+            EphemeralSecret::generate(group, &mut checked).unwrap();
+        }
+        assert!(matches!(checked.check(), Err(TlsError::RngError)));
+    }
+
+    #[cfg(feature = "deterministic-rng")]
+    #[test]
+    fn test_fixed_rng() {
+        let mut rng = FixedRng::new(&[1, 2, 3]);
+        let mut buf = [0; 2];
+        rng.try_fill_bytes(&mut buf).unwrap();
+        assert_eq!([1, 2], buf);
+        assert!(matches!(
+            rng.try_fill_bytes(&mut buf),
+            Err(TlsError::RngError)
+        ));
+    }
+}
@@ -0,0 +1,268 @@
+//! RFC 6962.  Certificate Transparency
+//!
+//! The server sends Signed Certificate Timestamps (SCTs) in the signed_certificate_timestamp
+//! extension of the end-entity CertificateEntry, each of them being the promise of a log to
+//! publish the certificate. The SCTs are verified with the keys of the logs trusted by the
+//! application, and the SCTs of other logs are skipped. Only logs signing with ECDSA over P-256
+//! and SHA-256 are supported, and SCTs embedded in the certificate itself are not considered.
+use p256::ecdsa::{signature::DigestVerifier, Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::parse_buffer::ParseBuffer;
+use crate::x509::{SubjectPublicKeyInfo, ID_EC_PUBLIC_KEY, SECP256R1};
+use crate::TlsError;
+
+// Section 3.2.  Structure of the Signed Certificate Timestamp
+const SCT_VERSION_V1: u8 = 0;
+const SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP: u8 = 0;
+const ENTRY_TYPE_X509: u16 = 0;
+
+// RFC 5246, Section 7.4.1.4.1.  Signature Algorithms
+const HASH_SHA256: u8 = 4;
+const SIGNATURE_ECDSA: u8 = 3;
+
+/// A Certificate Transparency log trusted by the application, see
+/// [`TlsConfig::with_certificate_transparency`](crate::config::TlsConfig::with_certificate_transparency).
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CtLog<'a> {
+    key: &'a [u8],
+}
+
+impl<'a> CtLog<'a> {
+    /// A log with its DER encoded SubjectPublicKeyInfo, as published in the log lists.
+    pub const fn new(key: &'a [u8]) -> Self {
+        Self { key }
+    }
+
+    /// Section 3.2.  Structure of the Signed Certificate Timestamp
+    /// "id" is the log's SHA-256 hash of the log's public key, calculated over the DER encoding
+    /// of the key represented as SubjectPublicKeyInfo.
+    fn id(&self) -> [u8; 32] {
+        Sha256::digest(self.key).into()
+    }
+
+    fn verifying_key(&self) -> Result<VerifyingKey, TlsError> {
+        let spki = SubjectPublicKeyInfo::from_der(self.key)?;
+        if spki.algorithm != ID_EC_PUBLIC_KEY || spki.named_curve != Some(SECP256R1) {
+            warn!("Only Certificate Transparency logs with P-256 keys are supported");
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        VerifyingKey::from_sec1_bytes(spki.public_key).map_err(|_| TlsError::InvalidCertificate)
+    }
+}
+
+/// Counts the trusted logs which issued a valid SCT for `certificate` in `sct_list`, the data of
+/// a signed_certificate_timestamp extension.
+///
+/// An SCT of a trusted log with an invalid signature fails the verification.
+pub(crate) fn verify(
+    sct_list: &[u8],
+    certificate: &[u8],
+    logs: &[CtLog<'_>],
+) -> Result<usize, TlsError> {
+    let mut verified = 0;
+    for log in logs {
+        let id = log.id();
+        let mut found = false;
+        let mut list = parse_list(sct_list)?;
+        while !list.is_empty() {
+            match Sct::parse(&mut list)? {
+                Some(sct) if sct.log_id == id => {
+                    sct.verify(log, certificate)?;
+                    found = true;
+                }
+                _ => {}
+            }
+        }
+        verified += usize::from(found);
+    }
+    Ok(verified)
+}
+
+/// Section 3.3.  Including the Signed Certificate Timestamp in the TLS Handshake
+///     opaque SerializedSCT<1..2^16-1>;
+///
+///     struct {
+///         SerializedSCT sct_list <1..2^16-1>;
+///     } SignedCertificateTimestampList;
+fn parse_list(sct_list: &[u8]) -> Result<ParseBuffer<'_>, TlsError> {
+    let mut buf = ParseBuffer::new(sct_list);
+    let len = buf.read_u16()?;
+    let list = buf.slice(len as usize)?;
+    if list.is_empty() || !buf.is_empty() {
+        return Err(TlsError::DecodeError);
+    }
+    Ok(list)
+}
+
+/// Section 3.2.  Structure of the Signed Certificate Timestamp
+///     struct {
+///         Version sct_version;
+///         LogID id;
+///         uint64 timestamp;
+///         CtExtensions extensions;
+///         digitally-signed struct {
+///             ...
+///         };
+///     } SignedCertificateTimestamp;
+struct Sct<'a> {
+    log_id: &'a [u8],
+    timestamp: &'a [u8],
+    extensions: &'a [u8],
+    algorithm: [u8; 2],
+    signature: &'a [u8],
+}
+
+impl<'a> Sct<'a> {
+    /// Parses the next serialized SCT of a list, which is skipped if its version is unknown.
+    fn parse(list: &mut ParseBuffer<'a>) -> Result<Option<Self>, TlsError> {
+        let len = list.read_u16()?;
+        let mut buf = list.slice(len as usize)?;
+        if buf.is_empty() {
+            return Err(TlsError::DecodeError);
+        }
+        if buf.read_u8()? != SCT_VERSION_V1 {
+            return Ok(None);
+        }
+        let log_id = buf.slice(32)?.as_slice();
+        let timestamp = buf.slice(8)?.as_slice();
+        let extensions_len = buf.read_u16()?;
+        let extensions = buf.slice(extensions_len as usize)?.as_slice();
+        let algorithm = [buf.read_u8()?, buf.read_u8()?];
+        let signature_len = buf.read_u16()?;
+        let signature = buf.slice(signature_len as usize)?.as_slice();
+        if !buf.is_empty() {
+            return Err(TlsError::DecodeError);
+        }
+        Ok(Some(Self {
+            log_id,
+            timestamp,
+            extensions,
+            algorithm,
+            signature,
+        }))
+    }
+
+    /// Section 3.2.  Structure of the Signed Certificate Timestamp
+    ///     digitally-signed struct {
+    ///         Version sct_version;
+    ///         SignatureType signature_type = certificate_timestamp;
+    ///         uint64 timestamp;
+    ///         LogEntryType entry_type;
+    ///         select(entry_type) {
+    ///             case x509_entry: ASN.1Cert;
+    ///             case precert_entry: PreCert;
+    ///         } signed_entry;
+    ///        CtExtensions extensions;
+    ///     };
+    fn verify(&self, log: &CtLog<'_>, certificate: &[u8]) -> Result<(), TlsError> {
+        if self.algorithm != [HASH_SHA256, SIGNATURE_ECDSA] {
+            warn!(
+                "Unsupported signature algorithm of SCT: {:?}",
+                self.algorithm
+            );
+            return Err(TlsError::InvalidSignatureScheme);
+        }
+        let key = log.verifying_key()?;
+        let signature =
+            Signature::from_der(self.signature).map_err(|_| TlsError::InvalidCertificate)?;
+
+        let certificate_len = u32::try_from(certificate.len())
+            .ok()
+            .filter(|&len| len < 1 << 24)
+            .ok_or(TlsError::InvalidCertificate)?;
+        let mut digest = Sha256::new();
+        digest.update([SCT_VERSION_V1, SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP]);
+        digest.update(self.timestamp);
+        digest.update(ENTRY_TYPE_X509.to_be_bytes());
+        digest.update(&certificate_len.to_be_bytes()[1..]);
+        digest.update(certificate);
+        digest.update((self.extensions.len() as u16).to_be_bytes());
+        digest.update(self.extensions);
+
+        key.verify_digest(digest, &signature).map_err(|_| {
+            warn!("The signature of an SCT is invalid");
+            TlsError::InvalidCertificate
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use p256::ecdsa::{signature::Signer, SigningKey};
+    use std::vec::Vec;
+
+    const CERTIFICATE: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x01];
+
+    fn log_key(signing_key: &SigningKey) -> Vec<u8> {
+        // SubjectPublicKeyInfo of id-ecPublicKey with prime256v1
+        let mut key = Vec::from(
+            &[
+                0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06,
+                0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+            ][..],
+        );
+        key.extend_from_slice(
+            signing_key
+                .verifying_key()
+                .to_encoded_point(false)
+                .as_bytes(),
+        );
+        key
+    }
+
+    fn sct_list(signing_key: &SigningKey, log_id: &[u8], certificate: &[u8]) -> Vec<u8> {
+        let timestamp = 1_700_000_000_000u64.to_be_bytes();
+        let mut signed = Vec::from(&[SCT_VERSION_V1, SIGNATURE_TYPE_CERTIFICATE_TIMESTAMP][..]);
+        signed.extend_from_slice(&timestamp);
+        signed.extend_from_slice(&ENTRY_TYPE_X509.to_be_bytes());
+        signed.extend_from_slice(&(certificate.len() as u32).to_be_bytes()[1..]);
+        signed.extend_from_slice(certificate);
+        signed.extend_from_slice(&[0, 0]);
+        let signature: Signature = signing_key.sign(&signed);
+        let signature = signature.to_der();
+
+        let mut sct = Vec::from(&[SCT_VERSION_V1][..]);
+        sct.extend_from_slice(log_id);
+        sct.extend_from_slice(&timestamp);
+        sct.extend_from_slice(&[0, 0, HASH_SHA256, SIGNATURE_ECDSA]);
+        sct.extend_from_slice(&(signature.as_bytes().len() as u16).to_be_bytes());
+        sct.extend_from_slice(signature.as_bytes());
+
+        let mut list = Vec::new();
+        list.extend_from_slice(&(sct.len() as u16 + 2).to_be_bytes());
+        list.extend_from_slice(&(sct.len() as u16).to_be_bytes());
+        list.extend_from_slice(&sct);
+        list
+    }
+
+    #[test]
+    fn test_verify() {
+        let signing_key = SigningKey::from_bytes(&[0x11; 32]).unwrap();
+        let key = log_key(&signing_key);
+        let log = CtLog::new(&key);
+        let list = sct_list(&signing_key, &log.id(), CERTIFICATE);
+
+        assert_eq!(1, verify(&list, CERTIFICATE, &[log]).unwrap());
+
+        // The SCT of an unknown log is skipped
+        let other_key = log_key(&SigningKey::from_bytes(&[0x22; 32]).unwrap());
+        assert_eq!(
+            0,
+            verify(&list, CERTIFICATE, &[CtLog::new(&other_key)]).unwrap()
+        );
+
+        // The SCT was issued for another certificate
+        assert!(matches!(
+            verify(&list, &[0x30, 0x00], &[log]),
+            Err(TlsError::InvalidCertificate)
+        ));
+
+        // An empty list is not allowed
+        assert!(verify(&[0, 0], CERTIFICATE, &[log]).is_err());
+    }
+}
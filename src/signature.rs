@@ -0,0 +1,155 @@
+use crate::signature_schemes::SignatureScheme;
+#[cfg(feature = "ed448")]
+use crate::x509::ID_ED448;
+#[cfg(feature = "rsa")]
+use crate::x509::RSA_ENCRYPTION;
+#[cfg(feature = "sm")]
+use crate::x509::SM2;
+use crate::x509::{SubjectPublicKeyInfo, ID_EC_PUBLIC_KEY, SECP521R1};
+#[cfg(feature = "brainpool")]
+use crate::x509::{BRAINPOOL_P256R1, BRAINPOOL_P384R1};
+use crate::TlsError;
+
+/// Whether `scheme` is verified by [`verify_signature`] rather than the webpki backend.
+pub(crate) fn is_supported(scheme: SignatureScheme) -> bool {
+    match scheme {
+        SignatureScheme::EcdsaSecp521r1Sha512 => true,
+        #[cfg(feature = "brainpool")]
+        SignatureScheme::EcdsaBrainpoolP256r1tls13Sha256
+        | SignatureScheme::EcdsaBrainpoolP384r1tls13Sha384 => true,
+        #[cfg(feature = "sm")]
+        SignatureScheme::Sm2sigSm3 => true,
+        #[cfg(feature = "ed448")]
+        SignatureScheme::Ed448 => true,
+        #[cfg(feature = "rsa")]
+        SignatureScheme::RsaPssRsaeSha256 | SignatureScheme::RsaPssRsaeSha384 => true,
+        _ => false,
+    }
+}
+
+/// Verify a signature made with one of the schemes not covered by the webpki backend,
+/// using the public key of a certificate.
+pub(crate) fn verify_signature(
+    scheme: SignatureScheme,
+    spki: &SubjectPublicKeyInfo,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), TlsError> {
+    match scheme {
+        SignatureScheme::EcdsaSecp521r1Sha512 => {
+            use p521::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            if spki.algorithm != ID_EC_PUBLIC_KEY || spki.named_curve != Some(SECP521R1) {
+                return Err(TlsError::InvalidSignatureScheme);
+            }
+            let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let signature =
+                Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify(message, &signature)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        #[cfg(feature = "brainpool")]
+        SignatureScheme::EcdsaBrainpoolP256r1tls13Sha256 => {
+            use bp256::r1::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            if spki.algorithm != ID_EC_PUBLIC_KEY || spki.named_curve != Some(BRAINPOOL_P256R1) {
+                return Err(TlsError::InvalidSignatureScheme);
+            }
+            let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let signature =
+                Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify(message, &signature)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        #[cfg(feature = "brainpool")]
+        SignatureScheme::EcdsaBrainpoolP384r1tls13Sha384 => {
+            use bp384::r1::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+
+            if spki.algorithm != ID_EC_PUBLIC_KEY || spki.named_curve != Some(BRAINPOOL_P384R1) {
+                return Err(TlsError::InvalidSignatureScheme);
+            }
+            let key = VerifyingKey::from_sec1_bytes(spki.public_key)
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let signature =
+                Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify(message, &signature)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        #[cfg(feature = "sm")]
+        SignatureScheme::Sm2sigSm3 => {
+            use sm2::dsa::{signature::Verifier, Signature, VerifyingKey};
+
+            if spki.algorithm != ID_EC_PUBLIC_KEY || spki.named_curve != Some(SM2) {
+                return Err(TlsError::InvalidSignatureScheme);
+            }
+            // Section 3.2.1.  SM2 Signature Scheme
+            // [...] the distinguishing identifier used in the SM2 signature
+            // computation is the ASCII string "TLSv1.3"
+            let key = VerifyingKey::from_sec1_bytes("TLSv1.3", spki.public_key)
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let signature =
+                Signature::from_der(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify(message, &signature)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        #[cfg(feature = "ed448")]
+        SignatureScheme::Ed448 => {
+            use ed448_goldilocks_plus::{Signature, VerifyingKey};
+
+            if spki.algorithm != ID_ED448 {
+                return Err(TlsError::InvalidSignatureScheme);
+            }
+            let key: &[u8; 57] = spki
+                .public_key
+                .try_into()
+                .map_err(|_| TlsError::InvalidCertificate)?;
+            let key = VerifyingKey::from_bytes(key).map_err(|_| TlsError::InvalidCertificate)?;
+            let signature: &[u8; 114] = signature
+                .try_into()
+                .map_err(|_| TlsError::InvalidSignature)?;
+            let signature =
+                Signature::from_bytes(signature).map_err(|_| TlsError::InvalidSignature)?;
+            key.verify_raw(&signature, message)
+                .map_err(|_| TlsError::InvalidSignature)
+        }
+        #[cfg(feature = "rsa")]
+        SignatureScheme::RsaPssRsaeSha256 => {
+            verify_rsa_pss::<sha2::Sha256>(spki, message, signature)
+        }
+        #[cfg(feature = "rsa")]
+        SignatureScheme::RsaPssRsaeSha384 => {
+            verify_rsa_pss::<sha2::Sha384>(spki, message, signature)
+        }
+        _ => Err(TlsError::InvalidSignatureScheme),
+    }
+}
+
+#[cfg(feature = "rsa")]
+fn verify_rsa_pss<D>(
+    spki: &SubjectPublicKeyInfo,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), TlsError>
+where
+    D: digest::Digest + digest::FixedOutputReset,
+{
+    use rsa::pkcs1::DecodeRsaPublicKey;
+    use rsa::pss::{Signature, VerifyingKey};
+    use rsa::signature::Verifier;
+
+    if spki.algorithm != RSA_ENCRYPTION {
+        return Err(TlsError::InvalidSignatureScheme);
+    }
+    let key = rsa::RsaPublicKey::from_pkcs1_der(spki.public_key)
+        .map_err(|_| TlsError::InvalidCertificate)?;
+
+    // Section 4.2.3.  Signature Algorithms
+    // RSASSA-PSS RSAE algorithms: [...] The length of the Salt MUST be equal to
+    // the length of the output of the digest algorithm.
+    let key = VerifyingKey::<D>::new(key);
+    let signature = Signature::try_from(signature).map_err(|_| TlsError::InvalidSignature)?;
+    key.verify(message, &signature)
+        .map_err(|_| TlsError::InvalidSignature)
+}
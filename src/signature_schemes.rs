@@ -1,7 +1,7 @@
 #![allow(unused_imports)]
 use crate::TlsError;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SignatureScheme {
     /* RSASSA-PKCS1-v1_5 algorithms */
@@ -23,6 +23,14 @@ pub enum SignatureScheme {
     Ed25519 = 0x0807,
     Ed448 = 0x0808,
 
+    /* ECDSA algorithms with Brainpool curves (RFC 8734) */
+    EcdsaBrainpoolP256r1tls13Sha256 = 0x081A,
+    EcdsaBrainpoolP384r1tls13Sha384 = 0x081B,
+    EcdsaBrainpoolP512r1tls13Sha512 = 0x081C,
+
+    /* ShangMi algorithms (RFC 8998) */
+    Sm2sigSm3 = 0x0708,
+
     /* RSASSA-PSS algorithms with public key OID RSASSA-PSS */
     RsaPssPssSha256 = 0x0809,
     RsaPssPssSha384 = 0x080a,
@@ -54,6 +62,12 @@ impl SignatureScheme {
             0x0807 => Some(Self::Ed25519),
             0x0808 => Some(Self::Ed448),
 
+            0x081A => Some(Self::EcdsaBrainpoolP256r1tls13Sha256),
+            0x081B => Some(Self::EcdsaBrainpoolP384r1tls13Sha384),
+            0x081C => Some(Self::EcdsaBrainpoolP512r1tls13Sha512),
+
+            0x0708 => Some(Self::Sm2sigSm3),
+
             0x0809 => Some(Self::RsaPssPssSha256),
             0x080a => Some(Self::RsaPssPssSha384),
             0x080b => Some(Self::RsaPssPssSha512),
@@ -63,6 +77,14 @@ impl SignatureScheme {
             _ => None,
         }
     }
+
+    /// Whether this is an RSASSA-PKCS1-v1_5 scheme, which TLS 1.3 only permits in certificates.
+    pub fn is_rsa_pkcs1(&self) -> bool {
+        matches!(
+            self,
+            Self::RsaPkcs1Sha256 | Self::RsaPkcs1Sha384 | Self::RsaPkcs1Sha512 | Self::RsaPkcs1Sha1
+        )
+    }
 }
 
 #[cfg(all(not(feature = "alloc"), feature = "webpki"))]
@@ -89,6 +111,20 @@ impl TryInto<&'static webpki::SignatureAlgorithm> for SignatureScheme {
             SignatureScheme::Ed25519 => Ok(&webpki::ED25519),
             SignatureScheme::Ed448 => Err(TlsError::InvalidSignatureScheme),
 
+            /* ECDSA algorithms with Brainpool curves */
+            SignatureScheme::EcdsaBrainpoolP256r1tls13Sha256 => {
+                Err(TlsError::InvalidSignatureScheme)
+            }
+            SignatureScheme::EcdsaBrainpoolP384r1tls13Sha384 => {
+                Err(TlsError::InvalidSignatureScheme)
+            }
+            SignatureScheme::EcdsaBrainpoolP512r1tls13Sha512 => {
+                Err(TlsError::InvalidSignatureScheme)
+            }
+
+            /* ShangMi algorithms */
+            SignatureScheme::Sm2sigSm3 => Err(TlsError::InvalidSignatureScheme),
+
             /* RSASSA-PSS algorithms with public key OID RSASSA-PSS */
             SignatureScheme::RsaPssPssSha256 => Err(TlsError::InvalidSignatureScheme),
             SignatureScheme::RsaPssPssSha384 => Err(TlsError::InvalidSignatureScheme),
@@ -124,6 +160,20 @@ impl TryInto<&'static webpki::SignatureAlgorithm> for SignatureScheme {
             SignatureScheme::Ed25519 => Ok(&webpki::ED25519),
             SignatureScheme::Ed448 => Err(TlsError::InvalidSignatureScheme),
 
+            /* ECDSA algorithms with Brainpool curves */
+            SignatureScheme::EcdsaBrainpoolP256r1tls13Sha256 => {
+                Err(TlsError::InvalidSignatureScheme)
+            }
+            SignatureScheme::EcdsaBrainpoolP384r1tls13Sha384 => {
+                Err(TlsError::InvalidSignatureScheme)
+            }
+            SignatureScheme::EcdsaBrainpoolP512r1tls13Sha512 => {
+                Err(TlsError::InvalidSignatureScheme)
+            }
+
+            /* ShangMi algorithms */
+            SignatureScheme::Sm2sigSm3 => Err(TlsError::InvalidSignatureScheme),
+
             /* RSASSA-PSS algorithms with public key OID RSASSA-PSS */
             SignatureScheme::RsaPssPssSha256 => Err(TlsError::InvalidSignatureScheme),
             SignatureScheme::RsaPssPssSha384 => Err(TlsError::InvalidSignatureScheme),
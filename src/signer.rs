@@ -0,0 +1,296 @@
+//! Client authentication with a private key held outside of the crate, for example in a secure
+//! element such as the ATECC608 or a TPM 2.0, which never exposes the key, or with a
+//! [`PrivateKey`] given to the configuration.
+use crate::signature_schemes::SignatureScheme;
+use crate::x509::{SECP256R1, SECP384R1};
+use crate::TlsError;
+use core::fmt::{Debug, Formatter};
+
+/// Size of the buffer the signature is written to, large enough for RSA-4096 signatures.
+pub const MAX_SIGNATURE_LEN: usize = 512;
+
+/// Context string of the client CertificateVerify signature.
+pub(crate) const CLIENT_CONTEXT: &[u8] = b"TLS 1.3, client CertificateVerify\x00";
+
+/// The signer of contexts without an external signer. It cannot be instantiated.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NoSigner {}
+
+/// The private key of a client certificate, which signs the CertificateVerify in software.
+///
+/// ECDSA keys are given as the big-endian secret scalar, 32 bytes for P-256 and 48 bytes for
+/// P-384, or parsed from the DER encoding exported by tools such as openssl or step-ca with
+/// [`PrivateKey::from_der`]. The nonces are derived deterministically as specified by RFC 6979, so no random number
+/// generator is needed.
+#[derive(Clone)]
+pub enum PrivateKey<'a> {
+    EcdsaSecp256r1(&'a [u8]),
+    EcdsaSecp384r1(&'a [u8]),
+}
+
+impl<'a> PrivateKey<'a> {
+    /// Parse a DER encoded EC private key, either a PKCS #8 PrivateKeyInfo (RFC 5208) or a SEC1
+    /// ECPrivateKey (RFC 5915), which are told apart by their version. The secret scalar is
+    /// borrowed from `der`.
+    ///
+    /// Only keys over P-256 and P-384 are supported, other keys are rejected with
+    /// [`TlsError::InvalidPrivateKey`].
+    pub fn from_der(der: &'a [u8]) -> Result<Self, TlsError> {
+        let (named_curve, scalar) = crate::x509::parse_ec_private_key(der)?;
+        // The curve is given by the length of the scalar if the key has no parameters
+        match (named_curve, scalar.len()) {
+            (Some(SECP256R1), 32) | (None, 32) => Ok(Self::EcdsaSecp256r1(scalar)),
+            (Some(SECP384R1), 48) | (None, 48) => Ok(Self::EcdsaSecp384r1(scalar)),
+            _ => {
+                warn!("Unsupported private key");
+                Err(TlsError::InvalidPrivateKey)
+            }
+        }
+    }
+
+    pub(crate) fn signature_scheme(&self) -> SignatureScheme {
+        match self {
+            Self::EcdsaSecp256r1(_) => SignatureScheme::EcdsaSecp256r1Sha256,
+            Self::EcdsaSecp384r1(_) => SignatureScheme::EcdsaSecp384r1Sha384,
+        }
+    }
+
+    /// Sign `message` and write the DER encoded signature to `signature`, returning its length.
+    pub(crate) fn sign(&self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        use p256::ecdsa::signature::Signer;
+
+        match self {
+            Self::EcdsaSecp256r1(key) => {
+                let key = p256::ecdsa::SigningKey::from_bytes(key)
+                    .map_err(|_| TlsError::InvalidPrivateKey)?;
+                let raw: p256::ecdsa::Signature =
+                    key.try_sign(message).map_err(|_| TlsError::CryptoError)?;
+                encode_ecdsa_signature(raw.as_ref(), signature)
+            }
+            Self::EcdsaSecp384r1(key) => {
+                let key = p384::ecdsa::SigningKey::from_bytes(key)
+                    .map_err(|_| TlsError::InvalidPrivateKey)?;
+                let raw: p384::ecdsa::Signature =
+                    key.try_sign(message).map_err(|_| TlsError::CryptoError)?;
+                encode_ecdsa_signature(raw.as_ref(), signature)
+            }
+        }
+    }
+}
+
+impl Debug for PrivateKey<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("PrivateKey")
+            .field(&self.signature_scheme())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for PrivateKey<'_> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "PrivateKey({})", self.signature_scheme())
+    }
+}
+
+/// Encode an ECDSA signature given as the concatenation of `r` and `s`, as produced by most secure
+/// elements, TPMs and PKCS#11 tokens, into the DER encoded `Ecdsa-Sig-Value` required by TLS.
+///
+/// Returns the length of the encoding written to `signature`.
+pub fn encode_ecdsa_signature(raw: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+    if raw.is_empty() || raw.len() % 2 != 0 || raw.len() > 2 * 66 {
+        return Err(TlsError::InvalidSignature);
+    }
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let (r, s) = (trim_integer(r), trim_integer(s));
+
+    let r_len = integer_len(r);
+    let s_len = integer_len(s);
+    let content_len = 2 + r_len + 2 + s_len;
+
+    let mut der: heapless::Vec<u8, 142> = heapless::Vec::new();
+    der.push(0x30).map_err(|_| TlsError::EncodeError)?;
+    if content_len >= 0x80 {
+        der.push(0x81).map_err(|_| TlsError::EncodeError)?;
+    }
+    der.push(content_len as u8)
+        .map_err(|_| TlsError::EncodeError)?;
+    for integer in [r, s] {
+        der.push(0x02).map_err(|_| TlsError::EncodeError)?;
+        der.push(integer_len(integer) as u8)
+            .map_err(|_| TlsError::EncodeError)?;
+        if integer[0] & 0x80 != 0 {
+            der.push(0).map_err(|_| TlsError::EncodeError)?;
+        }
+        der.extend_from_slice(integer)
+            .map_err(|_| TlsError::EncodeError)?;
+    }
+
+    let signature = signature
+        .get_mut(..der.len())
+        .ok_or(TlsError::InsufficientSpace)?;
+    signature.copy_from_slice(&der);
+    Ok(der.len())
+}
+
+/// Strip leading zeroes, keeping at least one byte.
+fn trim_integer(integer: &[u8]) -> &[u8] {
+    let zeroes = integer.iter().take_while(|b| **b == 0).count();
+    &integer[zeroes.min(integer.len() - 1)..]
+}
+
+/// Length of the DER INTEGER content, with a zero byte to keep it positive.
+fn integer_len(integer: &[u8]) -> usize {
+    integer.len() + usize::from(integer[0] & 0x80 != 0)
+}
+
+#[cfg(feature = "async")]
+pub mod asynch {
+    use super::*;
+
+    /// Produces the client CertificateVerify signature.
+    ///
+    /// The signer is invoked once per handshake, when the server requests client authentication
+    /// and a client certificate is configured.
+    pub trait ExternalSigner {
+        /// The scheme of the produced signatures, matching the client certificate key.
+        fn signature_scheme(&self) -> SignatureScheme;
+
+        /// Sign `message` and write the encoded signature to `signature`, returning its length.
+        ///
+        /// `message` is the complete content covered by the signature, as defined in RFC 8446
+        /// section 4.4.3: 64 spaces, the context string and the transcript hash. It is signed as
+        /// is with the hash of the signature scheme, which allows TPM restricted keys to hash it
+        /// on the TPM. ECDSA signatures are DER encoded, see [`encode_ecdsa_signature`].
+        async fn sign(&mut self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError>;
+    }
+
+    impl ExternalSigner for NoSigner {
+        fn signature_scheme(&self) -> SignatureScheme {
+            match *self {}
+        }
+
+        async fn sign(&mut self, _: &[u8], _: &mut [u8]) -> Result<usize, TlsError> {
+            match *self {}
+        }
+    }
+}
+
+pub mod blocking {
+    use super::*;
+
+    /// Produces the client CertificateVerify signature.
+    ///
+    /// The signer is invoked once per handshake, when the server requests client authentication
+    /// and a client certificate is configured.
+    pub trait ExternalSigner {
+        /// The scheme of the produced signatures, matching the client certificate key.
+        fn signature_scheme(&self) -> SignatureScheme;
+
+        /// Sign `message` and write the encoded signature to `signature`, returning its length.
+        ///
+        /// `message` is the complete content covered by the signature, as defined in RFC 8446
+        /// section 4.4.3: 64 spaces, the context string and the transcript hash. It is signed as
+        /// is with the hash of the signature scheme, which allows TPM restricted keys to hash it
+        /// on the TPM. ECDSA signatures are DER encoded, see [`encode_ecdsa_signature`].
+        fn sign(&mut self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError>;
+    }
+
+    impl ExternalSigner for NoSigner {
+        fn signature_scheme(&self) -> SignatureScheme {
+            match *self {}
+        }
+
+        fn sign(&mut self, _: &[u8], _: &mut [u8]) -> Result<usize, TlsError> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_ecdsa_signature() {
+        let mut raw = [0; 64];
+        raw[0] = 0x80;
+        raw[31] = 0x01;
+        raw[62] = 0x7f;
+        raw[63] = 0x02;
+
+        let mut signature = [0; 128];
+        let len = encode_ecdsa_signature(&raw, &mut signature).unwrap();
+
+        let mut r = [0; 33];
+        r[1] = 0x80;
+        r[32] = 0x01;
+        let mut expected = std::vec![0x30, 39, 0x02, 33];
+        expected.extend_from_slice(&r);
+        expected.extend_from_slice(&[0x02, 2, 0x7f, 0x02]);
+        assert_eq!(&expected[..], &signature[..len]);
+    }
+
+    #[test]
+    fn test_encode_ecdsa_signature_long_form() {
+        let raw = [0xff; 132];
+        let mut signature = [0; 142];
+        let len = encode_ecdsa_signature(&raw, &mut signature).unwrap();
+
+        assert_eq!(141, len);
+        assert_eq!(&[0x30, 0x81, 138, 0x02, 67, 0x00, 0xff], &signature[..7]);
+    }
+
+    #[test]
+    fn test_private_key_signature() {
+        use p256::ecdsa::signature::Verifier;
+
+        let scalar = [0x11; 32];
+        let mut signature = [0; MAX_SIGNATURE_LEN];
+        let len = PrivateKey::EcdsaSecp256r1(&scalar)
+            .sign(b"message", &mut signature)
+            .unwrap();
+
+        let verifying_key = p256::ecdsa::SigningKey::from_bytes(&scalar)
+            .unwrap()
+            .verifying_key();
+        let decoded = p256::ecdsa::Signature::from_der(&signature[..len]).unwrap();
+        assert!(verifying_key.verify(b"message", &decoded).is_ok());
+
+        assert!(matches!(
+            PrivateKey::EcdsaSecp256r1(&[0; 32]).sign(b"message", &mut signature),
+            Err(TlsError::InvalidPrivateKey)
+        ));
+    }
+
+    #[test]
+    fn test_private_key_from_der() {
+        // PKCS #8, wrapping a SEC1 key with the curve in the algorithm parameters
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-key.pem"));
+        let key = PrivateKey::from_der(&der).unwrap();
+        assert!(matches!(key, PrivateKey::EcdsaSecp256r1(scalar) if scalar.len() == 32));
+
+        // SEC1
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/chain/server-key.pem"));
+        let PrivateKey::EcdsaSecp256r1(scalar) = PrivateKey::from_der(&der).unwrap() else {
+            panic!("not a P-256 key");
+        };
+        assert_eq!(&der[7..39], scalar);
+
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/p384/server-key.pem"));
+        let key = PrivateKey::from_der(&der).unwrap();
+        assert!(matches!(key, PrivateKey::EcdsaSecp384r1(scalar) if scalar.len() == 48));
+
+        // Ed25519 and raw scalars are rejected
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/ed25519/server-key.pem"));
+        assert!(matches!(
+            PrivateKey::from_der(&der),
+            Err(TlsError::InvalidPrivateKey)
+        ));
+        assert!(matches!(
+            PrivateKey::from_der(&[0x11; 32]),
+            Err(TlsError::InvalidPrivateKey)
+        ));
+    }
+}
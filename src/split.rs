@@ -4,6 +4,18 @@ pub trait SplitState: Clone {
     fn same(&self, other: &Self) -> bool;
     fn is_open(&self) -> bool;
     fn set_open(&self, open: bool);
+
+    /// Records that the server requested a KeyUpdate, which the reader received and the writer
+    /// must send before its next application data record.
+    ///
+    /// Section 4.6.3.  Key and Initialization Vector Update
+    /// This mechanism allows either side to force an update to the entire connection, but
+    /// causes an implementation which receives multiple KeyUpdates while it is silent to
+    /// respond with a single update.
+    fn set_key_update_requested(&self);
+
+    /// Returns whether a KeyUpdate was requested since the last call, and clears the request.
+    fn take_key_update_request(&self) -> bool;
 }
 
 pub trait SplitStateContainer {
@@ -14,6 +26,7 @@ pub trait SplitStateContainer {
 
 pub struct SplitConnectionState {
     is_open: AtomicBool,
+    key_update_requested: AtomicBool,
 }
 
 impl Default for SplitConnectionState {
@@ -21,6 +34,7 @@ impl Default for SplitConnectionState {
     fn default() -> Self {
         Self {
             is_open: AtomicBool::new(true),
+            key_update_requested: AtomicBool::new(false),
         }
     }
 }
@@ -42,6 +56,14 @@ impl SplitState for &SplitConnectionState {
         self.is_open.store(open, Ordering::Release);
     }
 
+    fn set_key_update_requested(&self) {
+        self.key_update_requested.store(true, Ordering::Release);
+    }
+
+    fn take_key_update_request(&self) -> bool {
+        self.key_update_requested.swap(false, Ordering::AcqRel)
+    }
+
     fn same(&self, other: &Self) -> bool {
         core::ptr::eq(*self, *other)
     }
@@ -80,8 +102,34 @@ mod stdlib {
             self.0.as_ref().set_open(open)
         }
 
+        fn set_key_update_requested(&self) {
+            self.0.as_ref().set_key_update_requested()
+        }
+
+        fn take_key_update_request(&self) -> bool {
+            self.0.as_ref().take_key_update_request()
+        }
+
         fn same(&self, other: &Self) -> bool {
             Arc::ptr_eq(&self.0, &other.0)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_update_requests_are_coalesced() {
+        let mut container = SplitConnectionState::default();
+        let reader = (&mut container).state();
+        let writer = reader.clone();
+        assert!(!writer.take_key_update_request());
+
+        reader.set_key_update_requested();
+        reader.set_key_update_requested();
+        assert!(writer.take_key_update_request());
+        assert!(!writer.take_key_update_request());
+    }
+}
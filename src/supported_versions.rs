@@ -3,4 +3,5 @@ use heapless::Vec;
 pub type ProtocolVersion = u16;
 pub type ProtocolVersions = Vec<ProtocolVersion, 16>;
 
+pub const TLS12: ProtocolVersion = 0x0303;
 pub const TLS13: ProtocolVersion = 0x0304;
@@ -0,0 +1,239 @@
+//! Utilities for testing applications without a network.
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+
+use embedded_io::Io;
+
+/// One end of an in-memory, bidirectional byte stream.
+///
+/// A pipe is created as a pair of connected ends with [`Pipe::new`]: the bytes written to one end
+/// are read from the other. Each end implements the async and blocking I/O traits of
+/// `embedded-io`, as well as [`std::io::Read`] and [`std::io::Write`] so that a peer can be
+/// implemented with a std based library. A `TlsConnection` can thus be tested against a fake or
+/// real peer running on another thread or task, without sockets.
+///
+/// Reads wait until the peer has written some bytes. Once an end is dropped, the peer reads the
+/// remaining bytes and then end of stream, and writing to the peer fails with a broken pipe error.
+pub struct Pipe {
+    rx: Arc<Channel>,
+    tx: Arc<Channel>,
+}
+
+#[derive(Default)]
+struct Channel {
+    state: Mutex<ChannelState>,
+    readable: Condvar,
+}
+
+#[derive(Default)]
+struct ChannelState {
+    data: VecDeque<u8>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl Pipe {
+    /// Creates the two connected ends of a pipe.
+    pub fn new() -> (Pipe, Pipe) {
+        let a = Arc::new(Channel::default());
+        let b = Arc::new(Channel::default());
+        (
+            Pipe {
+                rx: a.clone(),
+                tx: b.clone(),
+            },
+            Pipe { rx: b, tx: a },
+        )
+    }
+
+    fn read_blocking(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.rx.lock();
+        loop {
+            if let Some(len) = state.try_read(buf) {
+                return Ok(len);
+            }
+            state = self
+                .rx
+                .readable
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn read_async(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        std::future::poll_fn(|cx| {
+            let mut state = self.rx.lock();
+            match state.try_read(buf) {
+                Some(len) => Poll::Ready(Ok(len)),
+                None => {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    fn write_inner(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.tx.lock();
+        if state.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        state.data.extend(buf);
+        state.wake();
+        self.tx.readable.notify_all();
+        Ok(buf.len())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        for channel in [&self.rx, &self.tx] {
+            let mut state = channel.lock();
+            state.closed = true;
+            state.wake();
+            channel.readable.notify_all();
+        }
+    }
+}
+
+impl Channel {
+    fn lock(&self) -> MutexGuard<'_, ChannelState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ChannelState {
+    /// Reads the available bytes, or returns `None` if the reader must wait for the peer.
+    fn try_read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.data.is_empty() && !self.closed && !buf.is_empty() {
+            return None;
+        }
+        let len = usize::min(buf.len(), self.data.len());
+        for (dest, byte) in buf.iter_mut().zip(self.data.drain(..len)) {
+            *dest = byte;
+        }
+        Some(len)
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Io for Pipe {
+    type Error = io::Error;
+}
+
+impl embedded_io::blocking::Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_blocking(buf)
+    }
+}
+
+impl embedded_io::blocking::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_inner(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io::asynch::Read for Pipe {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_async(buf).await
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_io::asynch::Write for Pipe {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_inner(buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl io::Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_blocking(buf)
+    }
+}
+
+impl io::Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_inner(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_blocking_roundtrip() {
+        let (mut client, mut server) = Pipe::new();
+        let peer = std::thread::spawn(move || {
+            let mut buf = [0; 4];
+            server.read_exact(&mut buf).unwrap();
+            server.write_all(&buf).unwrap();
+        });
+
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(b"ping", &buf);
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn test_close() {
+        let (mut client, server) = Pipe::new();
+        let (mut other, mut peer) = Pipe::new();
+        peer.write_all(b"bye").unwrap();
+        drop(peer);
+        drop(server);
+
+        let mut buf = [0; 8];
+        assert_eq!(3, other.read(&mut buf).unwrap());
+        assert_eq!(0, other.read(&mut buf).unwrap());
+        assert_eq!(
+            io::ErrorKind::BrokenPipe,
+            client.write(b"ping").unwrap_err().kind()
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        use embedded_io::asynch::{Read, Write};
+
+        let (mut client, mut server) = Pipe::new();
+        let peer = tokio::spawn(async move {
+            let mut buf = [0; 4];
+            let len = server.read(&mut buf).await.unwrap();
+            assert_eq!(len, server.write(&buf[..len]).await.unwrap());
+        });
+
+        assert_eq!(4, client.write(b"ping").await.unwrap());
+        let mut buf = [0; 4];
+        let len = client.read(&mut buf).await.unwrap();
+        assert_eq!(b"ping", &buf[..len]);
+        peer.await.unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use crate::config::TlsCipherSuite;
+use crate::key_schedule::{ReadKeySchedule, SessionTicket};
+
+/// Storage for session tickets, keyed by server identity.
+///
+/// A connection with a ticket store looks up a ticket for its server when it is opened, and puts
+/// the tickets received from the server in the store, so that sessions can be resumed after the
+/// device has been reset or in deep sleep. Implementations may keep the tickets in RAM, or in
+/// flash or FRAM using [`SessionTicket::encode`] and [`SessionTicket::decode`].
+///
+/// The server identity is chosen by the application, see
+/// [`TlsConnection::with_ticket_store`](crate::blocking::TlsConnection::with_ticket_store). Failing
+/// to store or retrieve a ticket is not an error, the next connection performs a full handshake.
+pub trait TicketStore<CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    /// Returns the ticket for `server`, if any.
+    ///
+    /// The age of the ticket should be set with [`SessionTicket::set_age`] if the time it was put
+    /// in the store is known.
+    fn get(&mut self, server: &str) -> Option<SessionTicket<CipherSuite>>;
+
+    /// Stores a ticket for `server`, replacing the previous one.
+    fn put(&mut self, server: &str, ticket: SessionTicket<CipherSuite>);
+
+    /// Removes the ticket for `server`, after it has been rejected by the server.
+    fn remove(&mut self, server: &str);
+}
+
+/// A ticket store along with the identity of the server of a connection.
+///
+/// The store must be `Send`, so that the connection can be moved to another task or thread.
+pub(crate) struct TicketStoreRef<'a, CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    server: &'a str,
+    store: &'a mut (dyn TicketStore<CipherSuite> + Send),
+}
+
+impl<'a, CipherSuite> TicketStoreRef<'a, CipherSuite>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    pub(crate) fn new(
+        server: &'a str,
+        store: &'a mut (dyn TicketStore<CipherSuite> + Send),
+    ) -> Self {
+        Self { server, store }
+    }
+
+    pub(crate) fn get(&mut self) -> Option<SessionTicket<CipherSuite>> {
+        self.store.get(self.server)
+    }
+
+    pub(crate) fn remove(&mut self) {
+        self.store.remove(self.server)
+    }
+
+    /// Moves the last ticket received on the connection, if any, to the store.
+    pub(crate) fn stash(&mut self, key_schedule: &mut ReadKeySchedule<CipherSuite>) {
+        if let Some(ticket) = key_schedule.take_session_ticket() {
+            self.store.put(self.server, ticket);
+        }
+    }
+}
@@ -0,0 +1,712 @@
+//! RFC 5246.  The Transport Layer Security (TLS) Protocol Version 1.2
+//!
+//! A client fallback for servers which do not support TLS 1.3. Only the ECDHE_ECDSA key exchange
+//! with AES-GCM (RFC 5289) is supported, along with the extended master secret (RFC 7627).
+//! Handshake messages split across records are not reassembled.
+use heapless::Vec;
+use sha2::Digest;
+use typenum::Unsigned;
+use zeroize::Zeroizing;
+
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::buffer::CryptoBuffer;
+use crate::config::{CertificateContext, SignatureContext, TlsCipherSuite, TlsConfig, TlsVerifier};
+use crate::connection::{illegal_parameter, unexpected_message};
+use crate::content_types::ContentType;
+use crate::extensions::ExtensionType;
+use crate::handshake::certificate::CertificateRef;
+use crate::handshake::certificate_verify::CertificateVerify;
+use crate::handshake::server_hello::ServerHello;
+use crate::handshake::{HandshakeType, Random};
+use crate::kdf::{constant_time_eq, TlsHkdf};
+use crate::key_schedule::{ReadKeySchedule, WriteKeySchedule};
+use crate::named_groups::NamedGroup;
+use crate::parse_buffer::ParseBuffer;
+use crate::record::RecordHeader;
+use crate::record_aead::{record_engine, DynRecordAead, RecordAead};
+use crate::supported_versions::TLS12;
+use crate::TlsError;
+
+/// RFC 5746, Section 3.3.  Renegotiation Protection Request Signaling Cipher Suite Value
+/// This SCSV is not a true cipher suite (it does not correspond to any valid set of algorithms)
+/// and cannot be negotiated.
+pub(crate) const EMPTY_RENEGOTIATION_INFO_SCSV: u16 = 0x00FF;
+
+const MASTER_SECRET_LEN: usize = 48;
+const VERIFY_DATA_LEN: usize = 12;
+const SALT_LEN: usize = 4;
+const EXPLICIT_NONCE_LEN: usize = 8;
+/// An uncompressed point of secp521r1.
+const MAX_POINT_LEN: usize = 133;
+/// The "extended master secret" label followed by a hash of up to 64 bytes.
+const MAX_LABEL_SEED_LEN: usize = 86;
+const MAX_HASH_LEN: usize = 64;
+/// The keys and salts of both sides with a 256 bit key.
+const MAX_KEY_BLOCK_LEN: usize = 72;
+
+/// RFC 8422, Section 5.4.  Server Key Exchange
+/// named_curve:  Indicates that a named curve is used.
+const NAMED_CURVE: u8 = 3;
+
+/// Section 5.  HMAC and the Pseudorandom Function
+///     PRF(secret, label, seed) = P_<hash>(secret, label + seed)
+///
+///     P_hash(secret, seed) = HMAC_hash(secret, A(1) + seed) +
+///                            HMAC_hash(secret, A(2) + seed) + ...
+///
+///     A(0) = seed
+///     A(i) = HMAC_hash(secret, A(i-1))
+fn prf<Hkdf>(secret: &[u8], label: &[u8], seed: &[&[u8]], out: &mut [u8]) -> Result<(), TlsError>
+where
+    Hkdf: TlsHkdf,
+{
+    let mut label_seed: Vec<u8, MAX_LABEL_SEED_LEN> = Vec::new();
+    for part in [label].iter().chain(seed) {
+        label_seed
+            .extend_from_slice(part)
+            .map_err(|_| TlsError::InternalError)?;
+    }
+
+    let mut a = Hkdf::hmac(secret, &label_seed)?;
+    let mut input: Vec<u8, { MAX_HASH_LEN + MAX_LABEL_SEED_LEN }> = Vec::new();
+    for chunk in out.chunks_mut(a.len()) {
+        input.clear();
+        input
+            .extend_from_slice(&a)
+            .and_then(|_| input.extend_from_slice(&label_seed))
+            .map_err(|_| TlsError::InternalError)?;
+        let block = Hkdf::hmac(secret, &input)?;
+        chunk.copy_from_slice(&block[..chunk.len()]);
+        a = Hkdf::hmac(secret, &a)?;
+    }
+    Ok(())
+}
+
+/// The state of a TLS 1.2 handshake, from the ServerHello to the server Finished.
+pub(crate) struct Tls12Handshake {
+    client_random: Random,
+    server_random: Random,
+    extended_master_secret: bool,
+    last_message: HandshakeType,
+    server_key: Option<(NamedGroup, Vec<u8, MAX_POINT_LEN>)>,
+    certificate_requested: bool,
+    master_secret: Zeroizing<[u8; MASTER_SECRET_LEN]>,
+}
+
+impl Tls12Handshake {
+    pub(crate) fn new<CipherSuite>(
+        client_random: Random,
+        server_hello: &ServerHello<'_>,
+    ) -> Result<Self, TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        // Section 7.4.1.3.  Server Hello
+        // server_version:  This field will contain the lower of that suggested by the client in
+        // the client hello and the highest supported by the server.
+        if server_hello.legacy_version() != TLS12 {
+            warn!("Server selected an unsupported version");
+            return Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::ProtocolVersion,
+            ));
+        }
+        // cipher_suite:  The single cipher suite selected by the server from the list in
+        // ClientHello.cipher_suites.
+        if Some(server_hello.cipher_suite() as u16) != CipherSuite::TLS12_CODE_POINT {
+            warn!("Server selected a cipher suite that was not offered");
+            return Err(illegal_parameter());
+        }
+        // RFC 8446, Section 4.1.3.  Server Hello
+        // TLS 1.3 clients receiving a ServerHello indicating TLS 1.2 or below MUST check that the
+        // last 8 bytes are not equal to either of these values.
+        if server_hello.is_downgrade() {
+            warn!("Server random has a downgrade protection sentinel");
+            return Err(illegal_parameter());
+        }
+
+        Ok(Self {
+            client_random,
+            server_random: *server_hello.random(),
+            extended_master_secret: server_hello
+                .extension_types()
+                .contains(ExtensionType::ExtendedMasterSecret),
+            last_message: HandshakeType::ServerHello,
+            server_key: None,
+            certificate_requested: false,
+            master_secret: Zeroizing::new([0; MASTER_SECRET_LEN]),
+        })
+    }
+
+    /// Processes the handshake messages of a record of the server flight, adding them to the
+    /// transcript.
+    pub(crate) fn process_server_flight<'v, CipherSuite, Verifier>(
+        &mut self,
+        verifier: &mut Verifier,
+        key_schedule: &mut ReadKeySchedule<CipherSuite>,
+        config: &TlsConfig<CipherSuite>,
+        mut data: &[u8],
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+        Verifier: TlsVerifier<'v, CipherSuite>,
+    {
+        while !data.is_empty() {
+            let mut buf = ParseBuffer::new(data);
+            let handshake_type = buf.read_u8().map_err(|_| TlsError::InvalidHandshake)?;
+            let len = buf.read_u24().map_err(|_| TlsError::InvalidHandshake)? as usize;
+            let body = buf
+                .slice(len)
+                .map_err(|_| TlsError::InvalidHandshake)?
+                .as_slice();
+            let (message, rest) = data.split_at(4 + len);
+            data = rest;
+
+            let handshake_type =
+                HandshakeType::of(handshake_type).ok_or_else(unexpected_message)?;
+            if !follows(self.last_message, handshake_type) {
+                warn!("Unexpected handshake message: {:?}", handshake_type);
+                return Err(unexpected_message());
+            }
+            key_schedule.transcript_hash().update(message);
+
+            match handshake_type {
+                // Section 7.4.2.  Server Certificate
+                HandshakeType::Certificate => {
+                    let certificate = CertificateRef::parse_tls12(&mut ParseBuffer::new(body))?;
+                    crate::pinning::verify_spki_pins(config, &certificate)?;
+                    crate::connection::verify_ocsp_response(config, &certificate)?;
+                    #[cfg(feature = "sct")]
+                    crate::connection::verify_scts(config, &certificate)?;
+                    verifier.verify_certificate_context(&CertificateContext {
+                        server_name: config.server_name,
+                        entries: &certificate.entries,
+                        ocsp_response: certificate.ocsp_response,
+                        transcript_hash: &key_schedule.transcript_hash().clone().finalize(),
+                    })?;
+                    verifier.set_certificate_signature_schemes(&config.signature_schemes_cert);
+                    verifier.set_time(config.now(), config.missing_time);
+                    verifier.set_verification_policy(config.verification_policy);
+                    verifier.set_spki_pins(config.spki_pins);
+                    verifier.verify_certificate_with_roots(
+                        key_schedule.transcript_hash(),
+                        &config.ca,
+                        config.root_certificates,
+                        certificate,
+                    )?;
+                    debug!("Certificate verified!");
+                }
+                HandshakeType::ServerKeyExchange => {
+                    let transcript = key_schedule.transcript_hash();
+                    self.process_server_key_exchange(verifier, config, transcript, body)?;
+                    debug!("Signature verified!");
+                }
+                // Section 7.4.4.  Certificate Request
+                // The client answers with an empty certificate list.
+                HandshakeType::CertificateRequest => self.certificate_requested = true,
+                // Section 7.4.5.  Server Hello Done
+                //     struct { } ServerHelloDone;
+                HandshakeType::ServerHelloDone if body.is_empty() => {}
+                _ => return Err(TlsError::InvalidHandshake),
+            }
+            self.last_message = handshake_type;
+        }
+        Ok(())
+    }
+
+    /// Whether the ServerHelloDone has been received.
+    pub(crate) fn server_flight_done(&self) -> bool {
+        matches!(self.last_message, HandshakeType::ServerHelloDone)
+    }
+
+    pub(crate) fn certificate_requested(&self) -> bool {
+        self.certificate_requested
+    }
+
+    /// The key exchange group and the ephemeral public key of the server.
+    pub(crate) fn server_key(&self) -> Result<(NamedGroup, &[u8]), TlsError> {
+        self.server_key
+            .as_ref()
+            .map(|(group, point)| (*group, &point[..]))
+            .ok_or(TlsError::InvalidHandshake)
+    }
+
+    /// RFC 8422, Section 5.4.  Server Key Exchange
+    ///     struct {
+    ///         ECParameters    curve_params;
+    ///         ECPoint         public;
+    ///     } ServerECDHParams;
+    ///
+    ///     signed_params:  A hash of the params, with the signature appropriate to that hash
+    ///     applied.  The private key corresponding to the certified public key in the server's
+    ///     Certificate message is used for signing.
+    fn process_server_key_exchange<'v, CipherSuite, Verifier>(
+        &mut self,
+        verifier: &mut Verifier,
+        config: &TlsConfig<CipherSuite>,
+        transcript: &CipherSuite::Hash,
+        body: &[u8],
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+        Verifier: TlsVerifier<'v, CipherSuite>,
+    {
+        let mut buf = ParseBuffer::new(body);
+        let curve_type = buf.read_u8().map_err(|_| TlsError::InvalidHandshake)?;
+        let group = buf.read_u16().map_err(|_| TlsError::InvalidHandshake)?;
+        let point_len = buf.read_u8().map_err(|_| TlsError::InvalidHandshake)? as usize;
+        let point = buf
+            .slice(point_len)
+            .map_err(|_| TlsError::InvalidHandshake)?
+            .as_slice();
+        let params = body
+            .get(..4 + point_len)
+            .ok_or(TlsError::InvalidHandshake)?;
+        let verify = CertificateVerify::parse(&mut buf)?;
+        if !buf.is_empty() {
+            return Err(TlsError::InvalidHandshake);
+        }
+
+        // The server must select one of the groups and signature algorithms offered by the client
+        let group = Some(group)
+            .filter(|_| curve_type == NAMED_CURVE)
+            .and_then(NamedGroup::of)
+            .filter(|group| config.named_groups.contains(group))
+            .ok_or_else(illegal_parameter)?;
+        if !config
+            .signature_schemes
+            .iter()
+            .any(|scheme| *scheme as u16 == verify.signature_scheme as u16)
+        {
+            warn!("Server selected a signature scheme that was not offered");
+            return Err(illegal_parameter());
+        }
+
+        let mut message: Vec<u8, { 2 * 32 + 4 + MAX_POINT_LEN }> = Vec::new();
+        for part in [&self.client_random[..], &self.server_random[..], params] {
+            message
+                .extend_from_slice(part)
+                .map_err(|_| illegal_parameter())?;
+        }
+        let context = SignatureContext {
+            server_name: config.server_name,
+            signature_scheme: verify.signature_scheme,
+            signature: verify.signature,
+            transcript_hash: &transcript.clone().finalize(),
+        };
+        verifier.verify_tls12_signature(&message, verify)?;
+        verifier.verify_signature_context(&context)?;
+
+        let point = Vec::from_slice(point).map_err(|_| illegal_parameter())?;
+        self.server_key = Some((group, point));
+        Ok(())
+    }
+
+    /// Derives the master secret from the premaster secret, with the transcript up to the
+    /// ClientKeyExchange.
+    pub(crate) fn derive_master_secret<CipherSuite>(
+        &mut self,
+        premaster_secret: &[u8],
+        transcript: &CipherSuite::Hash,
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        if self.extended_master_secret {
+            // RFC 7627, Section 4.  The Extended Master Secret
+            //     master_secret = PRF(pre_master_secret, "extended master secret",
+            //                         session_hash)
+            //                         [0..47];
+            let session_hash = transcript.clone().finalize();
+            prf::<CipherSuite::Hkdf>(
+                premaster_secret,
+                b"extended master secret",
+                &[&session_hash[..]],
+                &mut self.master_secret[..],
+            )
+        } else {
+            // Section 8.1.  Computing the Master Secret
+            //     master_secret = PRF(pre_master_secret, "master secret",
+            //                         ClientHello.random + ServerHello.random)
+            //                         [0..47];
+            prf::<CipherSuite::Hkdf>(
+                premaster_secret,
+                b"master secret",
+                &[&self.client_random[..], &self.server_random[..]],
+                &mut self.master_secret[..],
+            )
+        }
+    }
+
+    /// Section 6.3.  Key Calculation
+    ///     key_block = PRF(SecurityParameters.master_secret,
+    ///                     "key expansion",
+    ///                     SecurityParameters.server_random +
+    ///                     SecurityParameters.client_random);
+    ///
+    /// The AEAD ciphers have no MAC key, the block holds the write keys of the client and the
+    /// server, followed by their implicit nonces.
+    fn key_block<CipherSuite>(&self) -> Result<Zeroizing<[u8; MAX_KEY_BLOCK_LEN]>, TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let len = 2 * (CipherSuite::KeyLen::to_usize() + SALT_LEN);
+        let mut key_block = Zeroizing::new([0; MAX_KEY_BLOCK_LEN]);
+        prf::<CipherSuite::Hkdf>(
+            &self.master_secret[..],
+            b"key expansion",
+            &[&self.server_random[..], &self.client_random[..]],
+            key_block.get_mut(..len).ok_or(TlsError::InternalError)?,
+        )?;
+        Ok(key_block)
+    }
+
+    /// Protects the next records of the client, after its ChangeCipherSpec.
+    pub(crate) fn install_client_keys<CipherSuite>(
+        &self,
+        key_schedule: &mut WriteKeySchedule<CipherSuite>,
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let key_block = self.key_block::<CipherSuite>()?;
+        let key_len = CipherSuite::KeyLen::to_usize();
+        let salt = 2 * key_len;
+        key_schedule.set_tls12_keys(&key_block[..key_len], &key_block[salt..salt + SALT_LEN])
+    }
+
+    /// Opens the next records of the server, after its ChangeCipherSpec.
+    pub(crate) fn install_server_keys<CipherSuite>(
+        &self,
+        key_schedule: &mut ReadKeySchedule<CipherSuite>,
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let key_block = self.key_block::<CipherSuite>()?;
+        let key_len = CipherSuite::KeyLen::to_usize();
+        let salt = 2 * key_len + SALT_LEN;
+        key_schedule.set_tls12_keys(
+            &key_block[key_len..2 * key_len],
+            &key_block[salt..salt + SALT_LEN],
+        )
+    }
+
+    /// The verify_data of the client Finished, with the transcript up to the ClientKeyExchange.
+    pub(crate) fn client_verify_data<CipherSuite>(
+        &self,
+        transcript: &CipherSuite::Hash,
+    ) -> Result<[u8; VERIFY_DATA_LEN], TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        self.verify_data::<CipherSuite>(b"client finished", transcript)
+    }
+
+    /// Section 7.4.9.  Finished
+    /// Recipients of Finished messages MUST verify that the contents are correct.
+    ///
+    /// The message is the whole server Finished, which must be the only message of its record.
+    pub(crate) fn verify_server_finished<CipherSuite>(
+        &self,
+        transcript: &CipherSuite::Hash,
+        message: &[u8],
+    ) -> Result<(), TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let verify_data = match message.split_at(usize::min(4, message.len())) {
+            ([handshake_type, 0, 0, len], verify_data)
+                if *handshake_type == HandshakeType::Finished as u8
+                    && usize::from(*len) == verify_data.len() =>
+            {
+                verify_data
+            }
+            _ => return Err(unexpected_message()),
+        };
+        let expected = self.verify_data::<CipherSuite>(b"server finished", transcript)?;
+        if !constant_time_eq(&expected, verify_data) {
+            warn!("Server signature verification failed");
+            return Err(TlsError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    /// Section 7.4.9.  Finished
+    ///     verify_data
+    ///        PRF(master_secret, finished_label, Hash(handshake_messages))
+    ///           [0..verify_data_length-1];
+    fn verify_data<CipherSuite>(
+        &self,
+        label: &[u8],
+        transcript: &CipherSuite::Hash,
+    ) -> Result<[u8; VERIFY_DATA_LEN], TlsError>
+    where
+        CipherSuite: TlsCipherSuite,
+    {
+        let mut verify_data = [0; VERIFY_DATA_LEN];
+        prf::<CipherSuite::Hkdf>(
+            &self.master_secret[..],
+            label,
+            &[&transcript.clone().finalize()[..]],
+            &mut verify_data,
+        )?;
+        Ok(verify_data)
+    }
+}
+
+/// The order of the messages of the server flight, but for the Certificate which must follow the
+/// ServerHello, since only the ECDHE_ECDSA key exchange is offered.
+fn follows(last: HandshakeType, next: HandshakeType) -> bool {
+    matches!(
+        (last, next),
+        (HandshakeType::ServerHello, HandshakeType::Certificate)
+            | (HandshakeType::Certificate, HandshakeType::ServerKeyExchange)
+            | (
+                HandshakeType::ServerKeyExchange,
+                HandshakeType::CertificateRequest
+            )
+            | (
+                HandshakeType::ServerKeyExchange | HandshakeType::CertificateRequest,
+                HandshakeType::ServerHelloDone
+            )
+    )
+}
+
+/// The handshake messages sent by the client in a TLS 1.2 handshake.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) enum ClientMessage<'a> {
+    /// Section 7.4.6.  Client Certificate
+    /// If no suitable certificate is available, the client MUST send a certificate message
+    /// containing no certificates.
+    EmptyCertificate,
+    /// RFC 8422, Section 5.7.  Client Key Exchange
+    /// The ephemeral public key of the client, encoded as an ECPoint.
+    ClientKeyExchange(&'a [u8]),
+    Finished([u8; VERIFY_DATA_LEN]),
+}
+
+impl ClientMessage<'_> {
+    pub(crate) fn handshake_type(&self) -> HandshakeType {
+        match self {
+            ClientMessage::EmptyCertificate => HandshakeType::Certificate,
+            ClientMessage::ClientKeyExchange(_) => HandshakeType::ClientKeyExchange,
+            ClientMessage::Finished(_) => HandshakeType::Finished,
+        }
+    }
+
+    pub(crate) fn encode(&self, buf: &mut CryptoBuffer<'_>) -> Result<(), TlsError> {
+        match self {
+            ClientMessage::EmptyCertificate => buf.push_u24(0),
+            ClientMessage::ClientKeyExchange(public_key) => {
+                buf.with_u8_length(|buf| buf.extend_from_slice(public_key))
+            }
+            ClientMessage::Finished(verify_data) => buf.extend_from_slice(verify_data),
+        }
+        .map_err(|_| TlsError::EncodeError)
+    }
+}
+
+/// Section 6.2.3.3.  AEAD Ciphers
+///     additional_data = seq_num + TLSCompressed.type +
+///                       TLSCompressed.version + TLSCompressed.length;
+fn additional_data(sequence_number: u64, content_type: ContentType, len: usize) -> [u8; 13] {
+    let mut additional_data = [0; 13];
+    let [upper, lower] = (len as u16).to_be_bytes();
+    additional_data[..8].copy_from_slice(&sequence_number.to_be_bytes());
+    additional_data[8..].copy_from_slice(&[content_type as u8, 0x03, 0x03, upper, lower]);
+    additional_data
+}
+
+/// Encrypts the plaintext of a record in place, prepending the explicit nonce and appending the
+/// authentication tag.
+///
+/// RFC 5288, Section 3.  AES-GCM Cipher Suites
+/// The nonce_explicit MAY be the 64-bit sequence number.
+pub(crate) fn encrypt<CipherSuite>(
+    key_schedule: &WriteKeySchedule<CipherSuite>,
+    content_type: ContentType,
+    buf: &mut CryptoBuffer<'_>,
+) -> Result<(), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    key_schedule.check_record_limit()?;
+    let client_key = key_schedule.get_key()?;
+    // The implicit nonce is followed by the sequence number
+    let nonce = key_schedule.get_nonce()?;
+    let crypto = <CipherSuite::Cipher as RecordAead>::new(&client_key);
+    seal(
+        record_engine(&crypto),
+        &nonce,
+        key_schedule.sequence_number(),
+        content_type,
+        buf,
+    )
+}
+
+fn seal<Aead>(
+    crypto: &Aead,
+    nonce: &[u8],
+    sequence_number: u64,
+    content_type: ContentType,
+    buf: &mut CryptoBuffer<'_>,
+) -> Result<(), TlsError>
+where
+    Aead: DynRecordAead + ?Sized,
+{
+    let len = buf.len();
+    if len + EXPLICIT_NONCE_LEN + crypto.tag_len() > buf.capacity() {
+        return Err(TlsError::InsufficientSpace);
+    }
+
+    crypto
+        .seal(
+            nonce,
+            &additional_data(sequence_number, content_type, len),
+            buf,
+        )
+        .map_err(|_| TlsError::InvalidApplicationData)?;
+
+    let sealed = buf.len();
+    buf.extend_from_slice(&[0; EXPLICIT_NONCE_LEN])?;
+    let data = buf.as_mut_slice();
+    data.copy_within(..sealed, EXPLICIT_NONCE_LEN);
+    data[..EXPLICIT_NONCE_LEN].copy_from_slice(&sequence_number.to_be_bytes());
+    Ok(())
+}
+
+/// Decrypts a record of the server in place, and returns its content type along with the
+/// plaintext.
+pub(crate) fn open_record<'a, CipherSuite>(
+    key_schedule: &ReadKeySchedule<CipherSuite>,
+    header: &RecordHeader,
+    data: CryptoBuffer<'a>,
+) -> Result<(ContentType, CryptoBuffer<'a>), TlsError>
+where
+    CipherSuite: TlsCipherSuite,
+{
+    let server_key = key_schedule.get_key()?;
+    let mut nonce = key_schedule.get_nonce()?;
+    let explicit_nonce = data
+        .as_slice()
+        .get(..EXPLICIT_NONCE_LEN)
+        .ok_or(TlsError::BadRecordMac)?;
+    nonce[SALT_LEN..].copy_from_slice(explicit_nonce);
+
+    let crypto = <CipherSuite::Cipher as RecordAead>::new(&server_key);
+    let data = open(
+        record_engine(&crypto),
+        &nonce,
+        key_schedule.sequence_number(),
+        header.content_type(),
+        data,
+    )?;
+    Ok((header.content_type(), data))
+}
+
+fn open<'a, Aead>(
+    crypto: &Aead,
+    nonce: &[u8],
+    sequence_number: u64,
+    content_type: ContentType,
+    data: CryptoBuffer<'a>,
+) -> Result<CryptoBuffer<'a>, TlsError>
+where
+    Aead: DynRecordAead + ?Sized,
+{
+    let len = data
+        .len()
+        .checked_sub(EXPLICIT_NONCE_LEN + crypto.tag_len())
+        .ok_or(TlsError::BadRecordMac)?;
+    let mut data = data.offset(EXPLICIT_NONCE_LEN);
+    crypto
+        .open(
+            nonce,
+            &additional_data(sequence_number, content_type, len),
+            &mut data,
+        )
+        .map_err(|_| TlsError::BadRecordMac)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdf::SimpleHkdf;
+    use crate::key_schedule::KeySchedule;
+    use crate::Aes128GcmSha256;
+    use sha2::Sha256;
+
+    #[test]
+    fn test_prf_sha256() {
+        // The test vector of P_SHA256 published on the TLS working group list
+        let secret = [
+            0x9b, 0xbe, 0x43, 0x6b, 0xa9, 0x40, 0xf0, 0x17, 0xb1, 0x76, 0x52, 0x84, 0x9a, 0x71,
+            0xdb, 0x35,
+        ];
+        let seed = [
+            0xa0, 0xba, 0x9f, 0x93, 0x6c, 0xda, 0x31, 0x18, 0x27, 0xa6, 0xf7, 0x96, 0xff, 0xd5,
+            0x19, 0x8c,
+        ];
+        let expected = [
+            0xe3, 0xf2, 0x29, 0xba, 0x72, 0x7b, 0xe1, 0x7b, 0x8d, 0x12, 0x26, 0x20, 0x55, 0x7c,
+            0xd4, 0x53, 0xc2, 0xaa, 0xb2, 0x1d, 0x07, 0xc3, 0xd4, 0x95, 0x32, 0x9b, 0x52, 0xd4,
+            0xe6, 0x1e, 0xdb, 0x5a, 0x6b, 0x30, 0x17, 0x91, 0xe9, 0x0d, 0x35, 0xc9, 0xc9, 0xa4,
+            0x6b, 0x4e, 0x14, 0xba, 0xf9, 0xaf, 0x0f, 0xa0, 0x22, 0xf7, 0x07, 0x7d, 0xef, 0x17,
+            0xab, 0xfd, 0x37, 0x97, 0xc0, 0x56, 0x4b, 0xab, 0x4f, 0xbc, 0x91, 0x66, 0x6e, 0x9d,
+            0xef, 0x9b, 0x97, 0xfc, 0xe3, 0x4f, 0x79, 0x67, 0x89, 0xba, 0xa4, 0x80, 0x82, 0xd1,
+            0x22, 0xee, 0x42, 0xc5, 0xa7, 0x2e, 0x5a, 0x51, 0x10, 0xff, 0xf7, 0x01, 0x87, 0x34,
+            0x7b, 0x66,
+        ];
+
+        let mut out = [0; 100];
+        prf::<SimpleHkdf<Sha256>>(&secret, b"test label", &[&seed[..]], &mut out).unwrap();
+        assert_eq!(expected, out);
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        let (write, read) = key_schedule.as_split();
+        write.set_tls12_keys(&[1; 16], &[2; 4]).unwrap();
+        read.set_tls12_keys(&[1; 16], &[2; 4]).unwrap();
+        write.increment_counter().unwrap();
+        read.increment_counter().unwrap();
+
+        let mut storage = [0; 64];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        buf.extend_from_slice(b"ping").unwrap();
+        encrypt(write, ContentType::ApplicationData, &mut buf).unwrap();
+        assert_eq!(8 + 4 + 16, buf.len());
+        assert_eq!(&1u64.to_be_bytes(), &buf.as_slice()[..8]);
+
+        let len = buf.len() as u16;
+        let [upper, lower] = len.to_be_bytes();
+        let header = RecordHeader::decode([23, 3, 3, upper, lower]).unwrap();
+        let (content_type, plaintext) = open_record(read, &header, buf).unwrap();
+        assert!(matches!(content_type, ContentType::ApplicationData));
+        assert_eq!(b"ping", plaintext.as_slice());
+    }
+
+    #[test]
+    fn test_open_record_rejects_another_content_type() {
+        let mut key_schedule = KeySchedule::<Aes128GcmSha256>::new();
+        let (write, read) = key_schedule.as_split();
+        write.set_tls12_keys(&[1; 16], &[2; 4]).unwrap();
+        read.set_tls12_keys(&[1; 16], &[2; 4]).unwrap();
+
+        let mut storage = [0; 64];
+        let mut buf = CryptoBuffer::wrap(&mut storage);
+        buf.extend_from_slice(b"ping").unwrap();
+        encrypt(write, ContentType::ApplicationData, &mut buf).unwrap();
+
+        let [upper, lower] = (buf.len() as u16).to_be_bytes();
+        let header = RecordHeader::decode([22, 3, 3, upper, lower]).unwrap();
+        assert!(matches!(
+            open_record(read, &header, buf),
+            Err(TlsError::BadRecordMac)
+        ));
+    }
+}
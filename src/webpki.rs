@@ -1,16 +1,22 @@
-use crate::config::{Certificate, TlsCipherSuite, TlsClock, TlsVerifier};
+use crate::alert::{AlertDescription, AlertLevel};
+use crate::config::{
+    Certificate, MissingTime, TlsCipherSuite, TlsClock, TlsVerifier, VerificationPolicy,
+};
+use crate::crypto_provider::{CryptoProvider, RustCrypto};
+use crate::delegated_credential::{DelegatedCredential, DelegatedKey};
 use crate::handshake::{
     certificate::{
         Certificate as OwnedCertificate, CertificateEntryRef, CertificateRef as ServerCertificate,
     },
     certificate_verify::CertificateVerify,
 };
+use crate::signature_schemes::SignatureScheme;
 use crate::TlsError;
 use core::marker::PhantomData;
 use digest::Digest;
 use heapless::Vec;
-use webpki::DnsNameRef;
 
+#[cfg(not(feature = "alloc"))]
 static ALL_SIGALGS: &[&webpki::SignatureAlgorithm] = &[
     &webpki::ECDSA_P256_SHA256,
     &webpki::ECDSA_P256_SHA384,
@@ -19,65 +25,305 @@ static ALL_SIGALGS: &[&webpki::SignatureAlgorithm] = &[
     &webpki::ED25519,
 ];
 
-pub struct CertVerifier<'a, CipherSuite, Clock, const CERT_SIZE: usize>
+// Section 4.2.3.  Signature Algorithms
+// RSASSA-PKCS1-v1_5 algorithms:  [...] These values refer solely to
+// signatures which appear in certificates (see Section 4.4.2.2) and are
+// not defined for use in signed TLS handshake messages
+#[cfg(feature = "alloc")]
+static ALL_SIGALGS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+];
+
+/// A verifier backed by webpki.
+///
+/// Signature schemes that webpki does not implement are verified using the `Provider`. A
+/// delegated credential sent with the certificate, see
+/// [`TlsConfig::with_delegated_credentials`](crate::config::TlsConfig::with_delegated_credentials),
+/// is validated along with the certificate, and its key then verifies the CertificateVerify.
+pub struct CertVerifier<'a, CipherSuite, Clock, const CERT_SIZE: usize, Provider = RustCrypto>
 where
     Clock: TlsClock,
     CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider,
 {
     host: Option<&'a str>,
+    certificate_schemes: Vec<SignatureScheme, 16>,
+    now: Option<u64>,
+    missing_time: MissingTime,
+    policy: VerificationPolicy,
     certificate_transcript: Option<CipherSuite::Hash>,
     certificate: Option<OwnedCertificate<CERT_SIZE>>,
+    delegated_key: Option<DelegatedKey>,
     _clock: PhantomData<Clock>,
+    _provider: PhantomData<Provider>,
 }
 
-impl<'a, CipherSuite, Clock, const CERT_SIZE: usize> TlsVerifier<'a, CipherSuite>
-    for CertVerifier<'a, CipherSuite, Clock, CERT_SIZE>
+impl<'a, CipherSuite, Clock, const CERT_SIZE: usize, Provider> TlsVerifier<'a, CipherSuite>
+    for CertVerifier<'a, CipherSuite, Clock, CERT_SIZE, Provider>
 where
     CipherSuite: TlsCipherSuite,
     Clock: TlsClock,
+    Provider: CryptoProvider,
 {
     fn new(host: Option<&'a str>) -> Self {
         Self {
             host,
+            certificate_schemes: Vec::new(),
+            now: None,
+            missing_time: MissingTime::SkipValidity,
+            policy: VerificationPolicy::new(),
             certificate_transcript: None,
             certificate: None,
+            delegated_key: None,
             _clock: PhantomData,
+            _provider: PhantomData,
         }
     }
 
+    fn set_certificate_signature_schemes(&mut self, schemes: &[SignatureScheme]) {
+        // Schemes beyond the capacity are not implemented by webpki anyway
+        self.certificate_schemes = schemes.iter().copied().take(16).collect();
+    }
+
+    fn set_time(&mut self, now: Option<u64>, missing_time: MissingTime) {
+        self.now = now;
+        self.missing_time = missing_time;
+    }
+
+    fn set_verification_policy(&mut self, policy: VerificationPolicy) {
+        self.policy = policy;
+    }
+
     fn verify_certificate(
         &mut self,
         transcript: &CipherSuite::Hash,
         ca: &Option<Certificate>,
         cert: ServerCertificate,
     ) -> Result<(), TlsError> {
-        verify_certificate(self.host.clone(), ca, &cert, Clock::now())?;
-        self.certificate.replace(cert.try_into()?);
-        self.certificate_transcript.replace(transcript.clone());
-        Ok(())
+        self.verify_certificate_with_roots(transcript, ca, &[], cert)
+    }
+
+    fn verify_certificate_with_roots(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        roots: &[&[u8]],
+        cert: ServerCertificate,
+    ) -> Result<(), TlsError> {
+        self.verify_with_anchors(transcript, ca, roots, core::iter::empty(), cert)
     }
 
     fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
         let handshake_hash = self.certificate_transcript.take().unwrap();
         let ctx_str = b"TLS 1.3, server CertificateVerify\x00";
-        let mut msg: Vec<u8, 130> = Vec::new();
+        // 64 bytes of padding + 34 bytes of context string + transcript hash (up to 64 bytes)
+        let mut msg: Vec<u8, { 64 + 34 + 64 }> = Vec::new();
         msg.resize(64, 0x20).map_err(|_| TlsError::EncodeError)?;
         msg.extend_from_slice(ctx_str)
             .map_err(|_| TlsError::EncodeError)?;
         msg.extend_from_slice(&handshake_hash.finalize())
             .map_err(|_| TlsError::EncodeError)?;
 
+        if let Some(key) = self.delegated_key.take() {
+            return key.verify_signature(&msg[..], verify);
+        }
         let certificate = self.certificate.as_ref().unwrap().try_into()?;
-        verify_signature(&msg[..], certificate, verify)?;
+        verify_signature::<Provider>(&msg[..], certificate, verify)?;
         Ok(())
     }
+
+    #[cfg(feature = "tls12")]
+    fn verify_tls12_signature(
+        &mut self,
+        message: &[u8],
+        verify: CertificateVerify,
+    ) -> Result<(), TlsError> {
+        let certificate = self
+            .certificate
+            .as_ref()
+            .ok_or(TlsError::InvalidCertificate)?
+            .try_into()?;
+        verify_signature::<Provider>(message, certificate, verify)
+    }
 }
 
-fn verify_signature(
+impl<'a, CipherSuite, Clock, const CERT_SIZE: usize, Provider>
+    CertVerifier<'a, CipherSuite, Clock, CERT_SIZE, Provider>
+where
+    CipherSuite: TlsCipherSuite,
+    Clock: TlsClock,
+    Provider: CryptoProvider,
+{
+    /// Verifies the certificate with the CA and the root certificates of the configuration, and
+    /// the `builtin` trust anchors of the verifier.
+    fn verify_with_anchors<'r>(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate<'r>>,
+        roots: &[&'r [u8]],
+        builtin: impl Iterator<Item = webpki::TrustAnchor<'r>>,
+        cert: ServerCertificate,
+    ) -> Result<(), TlsError> {
+        // The time of the configuration takes precedence over the clock of the verifier, and
+        // expired certificates are verified as if the time was unknown
+        let now = if self.policy.allow_expired {
+            None
+        } else {
+            let now = self.now.or_else(Clock::now);
+            if now.is_none() && self.missing_time == MissingTime::Reject {
+                warn!("The current time is unknown");
+                return Err(TlsError::UnknownTime);
+            }
+            now
+        };
+        let host = if self.policy.skip_hostname_verification {
+            None
+        } else {
+            self.host
+        };
+        let sigalgs = certificate_sigalgs(&self.certificate_schemes);
+        verify_certificate(host, ca, roots, builtin, &cert, &sigalgs, now)?;
+        if self.policy.require_server_auth {
+            if let Some(CertificateEntryRef::X509(end_entity)) = cert.entries.first() {
+                if !crate::x509::has_extended_key_usage(end_entity, crate::x509::ID_KP_SERVER_AUTH)?
+                {
+                    warn!("Certificate does not allow server authentication");
+                    return Err(TlsError::InvalidCertificate);
+                }
+            }
+        }
+        self.delegated_key = match (cert.delegated_credential, cert.entries.first()) {
+            (Some(data), Some(CertificateEntryRef::X509(certificate))) => {
+                Some(DelegatedCredential::parse(data)?.verify(certificate, now)?)
+            }
+            _ => None,
+        };
+        self.certificate.replace(cert.try_into()?);
+        self.certificate_transcript.replace(transcript.clone());
+        Ok(())
+    }
+}
+
+/// A [`CertVerifier`] which also trusts the root certificates of the Mozilla root program, as
+/// bundled by the webpki-roots crate, for hosts such as Linux gateways which connect to servers
+/// of the public web PKI.
+///
+/// The CA and the root certificates of the configuration are tried first.
+#[cfg(feature = "webpki-roots")]
+pub struct WebPkiRootsVerifier<
+    'a,
+    CipherSuite,
+    Clock,
+    const CERT_SIZE: usize,
+    Provider = RustCrypto,
+>(CertVerifier<'a, CipherSuite, Clock, CERT_SIZE, Provider>)
+where
+    Clock: TlsClock,
+    CipherSuite: TlsCipherSuite,
+    Provider: CryptoProvider;
+
+#[cfg(feature = "webpki-roots")]
+impl<'a, CipherSuite, Clock, const CERT_SIZE: usize, Provider> TlsVerifier<'a, CipherSuite>
+    for WebPkiRootsVerifier<'a, CipherSuite, Clock, CERT_SIZE, Provider>
+where
+    CipherSuite: TlsCipherSuite,
+    Clock: TlsClock,
+    Provider: CryptoProvider,
+{
+    fn new(host: Option<&'a str>) -> Self {
+        Self(CertVerifier::new(host))
+    }
+
+    fn set_certificate_signature_schemes(&mut self, schemes: &[SignatureScheme]) {
+        self.0.set_certificate_signature_schemes(schemes);
+    }
+
+    fn set_time(&mut self, now: Option<u64>, missing_time: MissingTime) {
+        self.0.set_time(now, missing_time);
+    }
+
+    fn set_verification_policy(&mut self, policy: VerificationPolicy) {
+        self.0.set_verification_policy(policy);
+    }
+
+    fn verify_certificate(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        cert: ServerCertificate,
+    ) -> Result<(), TlsError> {
+        self.verify_certificate_with_roots(transcript, ca, &[], cert)
+    }
+
+    fn verify_certificate_with_roots(
+        &mut self,
+        transcript: &CipherSuite::Hash,
+        ca: &Option<Certificate>,
+        roots: &[&[u8]],
+        cert: ServerCertificate,
+    ) -> Result<(), TlsError> {
+        let builtin = webpki_roots::TLS_SERVER_ROOTS
+            .iter()
+            .map(|anchor| webpki::TrustAnchor {
+                subject: anchor.subject,
+                spki: anchor.spki,
+                name_constraints: anchor.name_constraints,
+            });
+        self.0
+            .verify_with_anchors(transcript, ca, roots, builtin, cert)
+    }
+
+    fn verify_signature(&mut self, verify: CertificateVerify) -> Result<(), TlsError> {
+        self.0.verify_signature(verify)
+    }
+
+    #[cfg(feature = "tls12")]
+    fn verify_tls12_signature(
+        &mut self,
+        message: &[u8],
+        verify: CertificateVerify,
+    ) -> Result<(), TlsError> {
+        self.0.verify_tls12_signature(message, verify)
+    }
+}
+
+/// The algorithms of webpki for the signatures of the certificates, restricted to `schemes`
+/// unless it is empty.
+fn certificate_sigalgs(
+    schemes: &[SignatureScheme],
+) -> Vec<&'static webpki::SignatureAlgorithm, 11> {
+    ALL_SIGALGS
+        .iter()
+        .copied()
+        .filter(|alg| {
+            schemes.is_empty()
+                || schemes.iter().any(|scheme| {
+                    let pkisig: Result<&'static webpki::SignatureAlgorithm, TlsError> =
+                        (*scheme).try_into();
+                    pkisig.map_or(false, |pkisig| core::ptr::eq(pkisig, *alg))
+                })
+        })
+        .collect()
+}
+
+fn verify_signature<Provider>(
     message: &[u8],
     certificate: ServerCertificate,
     verify: CertificateVerify,
-) -> Result<(), TlsError> {
+) -> Result<(), TlsError>
+where
+    Provider: CryptoProvider,
+{
     let mut verified = false;
     if !certificate.entries.is_empty() {
         // TODO: Support intermediates...
@@ -91,12 +337,39 @@ fn verify_signature(
                 "Verifying with signature scheme {:?}",
                 verify.signature_scheme
             );
+            // Section 4.4.3.  Certificate Verify
+            // RSA signatures MUST use an RSASSA-PSS algorithm, regardless of whether
+            // RSASSA-PKCS1-v1_5 algorithms appear in "signature_algorithms".
+            if verify.signature_scheme.is_rsa_pkcs1() {
+                warn!("CertificateVerify must not use RSASSA-PKCS1-v1_5");
+                return Err(TlsError::InvalidSignatureScheme);
+            }
             info!("Signature: {:x?}", verify.signature);
-            let pkisig = verify.signature_scheme.try_into()?;
-            match cert.verify_signature(pkisig, message, verify.signature) {
+            // Schemes implemented by the provider take precedence, so that accelerated
+            // implementations are preferred over webpki.
+            match Provider::verify_signature(
+                verify.signature_scheme,
+                certificate,
+                message,
+                verify.signature,
+            ) {
                 Ok(_) => {
                     verified = true;
                 }
+                Err(TlsError::InvalidSignatureScheme) => {
+                    let pkisig: Result<&'static webpki::SignatureAlgorithm, TlsError> =
+                        verify.signature_scheme.try_into();
+                    if let Ok(pkisig) = pkisig {
+                        match cert.verify_signature(pkisig, message, verify.signature) {
+                            Ok(_) => {
+                                verified = true;
+                            }
+                            Err(e) => {
+                                info!("Error verifying signature: {:?}", e);
+                            }
+                        }
+                    }
+                }
                 Err(e) => {
                     info!("Error verifying signature: {:?}", e);
                 }
@@ -109,70 +382,327 @@ fn verify_signature(
     Ok(())
 }
 
-fn verify_certificate(
+/// Builds a path from the end-entity certificate through the intermediates sent by the server
+/// to the CA, one of the root certificates or one of the `builtin` trust anchors, and verifies
+/// the host name.
+fn verify_certificate<'r>(
     verify_host: Option<&str>,
-    ca: &Option<Certificate>,
+    ca: &Option<Certificate<'r>>,
+    roots: &[&'r [u8]],
+    builtin: impl Iterator<Item = webpki::TrustAnchor<'r>>,
     certificate: &ServerCertificate,
+    sigalgs: &[&webpki::SignatureAlgorithm],
     now: Option<u64>,
 ) -> Result<(), TlsError> {
-    let mut verified = false;
-    let mut host_verified = false;
-    if let Some(Certificate::X509(ca)) = ca {
-        let trust = webpki::TrustAnchor::try_from_cert_der(ca).map_err(|e| {
-            warn!("Error loading CA: {:?}", e);
-            TlsError::DecodeError
-        })?;
-        let anchors = &[trust];
-        let anchors = webpki::TLSServerTrustAnchors(anchors);
+    let ca = match ca {
+        Some(Certificate::X509(ca)) => Some(*ca),
+        _ => None,
+    };
+    let mut anchors = ca
+        .into_iter()
+        .chain(roots.iter().copied())
+        .map(|root| {
+            webpki::TrustAnchor::try_from_cert_der(root).map_err(|e| {
+                warn!("Error loading CA: {:?}", e);
+                TlsError::DecodeError
+            })
+        })
+        .chain(builtin.map(Ok))
+        .peekable();
+    if anchors.peek().is_none() {
+        warn!("No CA or root certificates to verify the certificate with");
+        return Err(TlsError::InvalidCertificate);
+    }
 
-        trace!("We got {} certificate entries", certificate.entries.len());
-
-        if !certificate.entries.is_empty() {
-            // TODO: Support intermediates...
-            if let CertificateEntryRef::X509(certificate) = certificate.entries[0] {
-                let cert = webpki::EndEntityCert::try_from(certificate).map_err(|e| {
-                    warn!("Error loading cert: {:?}", e);
-                    TlsError::DecodeError
-                })?;
-
-                let time = if let Some(now) = now {
-                    webpki::Time::from_seconds_since_unix_epoch(now)
-                } else {
-                    // If no clock is provided, use certificate notAfter as the timestamp, if available
-                    if let Ok(validity) = cert.validity() {
-                        validity.not_after
-                    } else {
-                        webpki::Time::from_seconds_since_unix_epoch(0)
-                    }
-                };
-                info!("Certificate is loaded!");
-                match cert.verify_is_valid_tls_server_cert(ALL_SIGALGS, &anchors, &[], time) {
-                    Ok(_) => verified = true,
-                    Err(e) => {
-                        warn!("Error verifying certificate: {:?}", e);
-                    }
-                }
+    trace!("We got {} certificate entries", certificate.entries.len());
+    let Some(CertificateEntryRef::X509(end_entity)) = certificate.entries.first() else {
+        return Err(TlsError::InvalidCertificate);
+    };
+    // The certificates after the end-entity one are intermediates, which webpki builds the path
+    // with in any order
+    let intermediates: Vec<&[u8], 15> = certificate.entries[1..]
+        .iter()
+        .filter_map(|entry| match entry {
+            CertificateEntryRef::X509(der) => Some(*der),
+            CertificateEntryRef::RawPublicKey(_) => None,
+        })
+        .collect();
 
-                if let Some(server_name) = verify_host {
-                    match cert.verify_is_valid_for_dns_name(
-                        DnsNameRef::try_from_ascii_str(server_name).unwrap(),
-                    ) {
-                        Ok(_) => host_verified = true,
-                        Err(e) => {
-                            warn!("Error verifying host: {:?}", e);
-                        }
-                    }
-                }
-            }
+    let cert = webpki::EndEntityCert::try_from(*end_entity).map_err(|e| {
+        warn!("Error loading cert: {:?}", e);
+        TlsError::DecodeError
+    })?;
+    info!("Certificate is loaded!");
+
+    let time = if let Some(now) = now {
+        webpki::Time::from_seconds_since_unix_epoch(now)
+    } else {
+        // If no clock is provided, use certificate notAfter as the timestamp, if available
+        if let Ok(validity) = cert.validity() {
+            validity.not_after
+        } else {
+            webpki::Time::from_seconds_since_unix_epoch(0)
         }
-    }
+    };
 
-    if !verified {
+    // Section 4.4.2.2.  Server Certificate Selection
+    // The certificate MUST allow the key to be used for signing (i.e., the digitalSignature bit
+    // MUST be set if the Key Usage extension is present)
+    //
+    // RFC 5280, Section 6.1.4.  Preparation for Certificate i+1
+    // If a key usage extension is present, verify that the keyCertSign bit is set.
+    //
+    // webpki does not check the KeyUsage extension, so it is checked for the end-entity
+    // certificate and every intermediate sent by the server.
+    if !crate::x509::allows_key_usage(end_entity, crate::x509::DIGITAL_SIGNATURE)? {
+        warn!("Certificate does not allow digital signatures");
         return Err(TlsError::InvalidCertificate);
     }
+    for intermediate in intermediates.iter() {
+        if !crate::x509::allows_key_usage(intermediate, crate::x509::KEY_CERT_SIGN)? {
+            warn!("Intermediate certificate does not allow certificate signing");
+            return Err(TlsError::InvalidCertificate);
+        }
+    }
 
-    if !host_verified && verify_host.is_some() {
-        return Err(TlsError::InvalidCertificate);
+    // The path is built to each trust anchor in turn, so that no memory is needed for a root
+    // store of any size. An error other than an unknown issuer is reported over it, as the path
+    // did reach that anchor.
+    let mut result = Err(webpki::Error::UnknownIssuer);
+    for trust in anchors {
+        let anchors = &[trust?];
+        let anchors = webpki::TLSServerTrustAnchors(anchors);
+        match cert.verify_is_valid_tls_server_cert(sigalgs, &anchors, &intermediates, time) {
+            Ok(_) => {
+                result = Ok(());
+                break;
+            }
+            Err(webpki::Error::UnknownIssuer) => {}
+            Err(e) => result = Err(e),
+        }
+    }
+    match result {
+        Ok(_) => {}
+        Err(e @ (webpki::Error::CertExpired | webpki::Error::CertNotValidYet)) => {
+            warn!("Error verifying certificate: {:?}", e);
+            return Err(abort(AlertDescription::CertificateExpired));
+        }
+        Err(e @ webpki::Error::UnknownIssuer) => {
+            warn!("Error verifying certificate: {:?}", e);
+            return Err(abort(AlertDescription::UnknownCa));
+        }
+        Err(e) => {
+            warn!("Error verifying certificate: {:?}", e);
+            return Err(TlsError::InvalidCertificate);
+        }
+    }
+
+    // Only DNS names are verified, the certificate of a server given by its address is rejected
+    if let Some(server_name) = verify_host {
+        if !crate::x509::is_valid_for_dns_name(end_entity, server_name)? {
+            warn!("Certificate is not valid for {}", server_name);
+            return Err(TlsError::InvalidCertificate);
+        }
     }
     Ok(())
 }
+
+/// Section 6.2.  Error Alerts
+/// certificate_expired:  A certificate has expired or is not currently valid.
+///
+/// unknown_ca:  A valid certificate chain or partial chain was received, but the certificate
+/// was not accepted because the CA certificate could not be located or could not be matched
+/// with a known trust anchor.
+fn abort(description: AlertDescription) -> TlsError {
+    TlsError::AbortHandshake(AlertLevel::Fatal, description)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    fn chain<'a>(entries: &[&'a [u8]]) -> ServerCertificate<'a> {
+        let mut certificate = ServerCertificate::with_context(&[]);
+        for entry in entries {
+            certificate.add(CertificateEntryRef::X509(entry)).unwrap();
+        }
+        certificate
+    }
+
+    fn verify(
+        host: Option<&str>,
+        ca: &Option<Certificate>,
+        roots: &[&[u8]],
+        certificate: &ServerCertificate,
+    ) -> Result<(), TlsError> {
+        verify_certificate(
+            host,
+            ca,
+            roots,
+            core::iter::empty(),
+            certificate,
+            ALL_SIGALGS,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_verify_certificate_chain() {
+        let root = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        let other_root = pem_parser::pem_to_der(include_str!("../tests/data/p384/ca-cert.pem"));
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        let server = pem_parser::pem_to_der(include_str!("../tests/data/chain/server-cert.pem"));
+        let device = pem_parser::pem_to_der(include_str!("../tests/data/chain/client-cert.pem"));
+        let roots: [&[u8]; 2] = [&other_root, &root];
+
+        // The path is built through the intermediate to the second root
+        let certificate = chain(&[&server, &intermediate]);
+        assert!(verify(Some("localhost"), &None, &roots, &certificate).is_ok());
+        assert!(matches!(
+            verify(Some("example.com"), &None, &roots, &certificate),
+            Err(TlsError::InvalidCertificate)
+        ));
+        // The CA is a trust anchor as well
+        let ca = Some(Certificate::X509(&root));
+        assert!(verify(Some("localhost"), &ca, &[], &certificate).is_ok());
+
+        // Without the intermediate or the right root, the issuer is unknown
+        for (certificate, roots) in [
+            (chain(&[&server]), &roots[..]),
+            (chain(&[&server, &intermediate]), &roots[..1]),
+        ] {
+            assert!(matches!(
+                verify(Some("localhost"), &None, roots, &certificate),
+                Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::UnknownCa
+                ))
+            ));
+        }
+
+        // An intermediate must allow certificate signing
+        let certificate = chain(&[&server, &device, &intermediate]);
+        assert!(matches!(
+            verify(Some("localhost"), &None, &roots, &certificate),
+            Err(TlsError::InvalidCertificate)
+        ));
+    }
+
+    #[test]
+    fn test_time() {
+        type Verifier<'a> =
+            CertVerifier<'a, crate::config::Aes128GcmSha256, crate::config::NoClock, 4096>;
+        let root = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        let server = pem_parser::pem_to_der(include_str!("../tests/data/chain/server-cert.pem"));
+        let ca = Some(Certificate::X509(&root));
+        let verify = |now, missing_time| {
+            let mut verifier = Verifier::new(Some("localhost"));
+            verifier.set_time(now, missing_time);
+            verifier.verify_certificate(&sha2::Sha256::new(), &ca, chain(&[&server, &intermediate]))
+        };
+
+        // Without a clock, the validity periods are skipped unless the time is required
+        assert!(verify(None, MissingTime::SkipValidity).is_ok());
+        assert!(matches!(
+            verify(None, MissingTime::Reject),
+            Err(TlsError::UnknownTime)
+        ));
+
+        // The certificate of the server is valid from October 2026 to January 2035
+        assert!(verify(Some(1_800_000_000), MissingTime::Reject).is_ok());
+        for now in [1_700_000_000, 2_100_000_000] {
+            assert!(matches!(
+                verify(Some(now), MissingTime::Reject),
+                Err(TlsError::AbortHandshake(
+                    AlertLevel::Fatal,
+                    AlertDescription::CertificateExpired
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verification_policy() {
+        type Verifier<'a> =
+            CertVerifier<'a, crate::config::Aes128GcmSha256, crate::config::NoClock, 4096>;
+        let root = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        let server = pem_parser::pem_to_der(include_str!("../tests/data/chain/server-cert.pem"));
+        // Issued by the root, without the extended key usage extension
+        let other_server = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let ca = Some(Certificate::X509(&root));
+        let verify = |host, now, policy, certificate| {
+            let mut verifier = Verifier::new(Some(host));
+            verifier.set_time(Some(now), MissingTime::Reject);
+            verifier.set_verification_policy(policy);
+            verifier.verify_certificate(&sha2::Sha256::new(), &ca, certificate)
+        };
+        let expired = 2_100_000_000;
+        let valid = 1_800_000_000;
+
+        let policy = VerificationPolicy::new();
+        let certificate = || chain(&[&server, &intermediate]);
+        assert!(verify("localhost", valid, policy, certificate()).is_ok());
+        assert!(verify("localhost", expired, policy, certificate()).is_err());
+        assert!(verify("example.com", valid, policy, certificate()).is_err());
+
+        // Each option only relaxes its own check
+        let policy = VerificationPolicy::new().insecure_allow_expired();
+        assert!(verify("localhost", expired, policy, certificate()).is_ok());
+        assert!(verify("example.com", expired, policy, certificate()).is_err());
+        let policy = VerificationPolicy::new().insecure_skip_hostname_verification();
+        assert!(verify("example.com", valid, policy, certificate()).is_ok());
+        assert!(verify("example.com", expired, policy, certificate()).is_err());
+
+        let policy = VerificationPolicy::new().require_server_auth();
+        assert!(verify("localhost", valid, policy, certificate()).is_ok());
+        assert!(verify(
+            "localhost",
+            valid,
+            VerificationPolicy::new(),
+            chain(&[&other_server])
+        )
+        .is_ok());
+        assert!(matches!(
+            verify("localhost", valid, policy, chain(&[&other_server])),
+            Err(TlsError::InvalidCertificate)
+        ));
+    }
+
+    #[cfg(all(feature = "webpki-roots", feature = "std"))]
+    #[test]
+    fn test_webpki_roots() {
+        type Verifier<'a> =
+            WebPkiRootsVerifier<'a, crate::config::Aes128GcmSha256, std::time::SystemTime, 4096>;
+        let root = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        let server = pem_parser::pem_to_der(include_str!("../tests/data/chain/server-cert.pem"));
+
+        // The test CA is not part of the Mozilla root program
+        let mut verifier = Verifier::new(Some("localhost"));
+        assert!(matches!(
+            verifier.verify_certificate(
+                &sha2::Sha256::new(),
+                &None,
+                chain(&[&server, &intermediate])
+            ),
+            Err(TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::UnknownCa
+            ))
+        ));
+
+        let mut verifier = Verifier::new(Some("localhost"));
+        let ca = Some(Certificate::X509(&root));
+        assert!(verifier
+            .verify_certificate(&sha2::Sha256::new(), &ca, chain(&[&server, &intermediate]))
+            .is_ok());
+    }
+}
@@ -1,8 +1,9 @@
 use crate::{
     buffer::CryptoBuffer,
-    config::{TlsCipherSuite, TLS_RECORD_OVERHEAD},
+    config::{align_offset, RecordPadding, TlsCipherSuite, TLS_RECORD_MAX, TLS_RECORD_OVERHEAD},
     connection::encrypt,
     key_schedule::{ReadKeySchedule, WriteKeySchedule},
+    max_fragment_length::MaxFragmentLength,
     record::{ClientRecord, ClientRecordHeader},
     TlsError,
 };
@@ -11,10 +12,17 @@ pub struct WriteBuffer<'a> {
     buffer: &'a mut [u8],
     pos: usize,
     current_header: Option<ClientRecordHeader>,
+    max_fragment_length: Option<MaxFragmentLength>,
+    record_padding: Option<RecordPadding>,
 }
 
+const HEADER_SIZE: usize = 5;
+
 impl<'a> WriteBuffer<'a> {
     pub fn new(buffer: &'a mut [u8]) -> Self {
+        // Records are placed so that the data following the header is aligned
+        let offset = align_offset(buffer, HEADER_SIZE) - HEADER_SIZE;
+        let buffer = &mut buffer[offset..];
         debug_assert!(
             buffer.len() > TLS_RECORD_OVERHEAD,
             "The write buffer must be sufficiently large to include the tls record overhead"
@@ -23,11 +31,61 @@ impl<'a> WriteBuffer<'a> {
             buffer,
             pos: 0,
             current_header: None,
+            max_fragment_length: None,
+            record_padding: None,
+        }
+    }
+
+    /// The length of the buffer, from the first aligned record.
+    pub(crate) fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the buffer, from the first aligned record.
+    pub(crate) fn into_buf(self) -> &'a mut [u8] {
+        self.buffer
+    }
+
+    /// Limits the data of the records to the maximum fragment length negotiated with the server.
+    pub(crate) fn set_max_fragment_length(
+        &mut self,
+        max_fragment_length: Option<MaxFragmentLength>,
+    ) {
+        self.max_fragment_length = max_fragment_length;
+    }
+
+    pub(crate) fn max_fragment_length(&self) -> Option<MaxFragmentLength> {
+        self.max_fragment_length
+    }
+
+    /// Pads the content of the TLS 1.3 records which are encrypted.
+    pub(crate) fn set_record_padding(&mut self, record_padding: Option<RecordPadding>) {
+        self.record_padding = record_padding;
+    }
+
+    /// The number of zero bytes which follow the content type of a record with `len` bytes of
+    /// content, limited so that the padded record still fits in the buffer.
+    fn padding_len(&self, len: usize) -> usize {
+        let max_len = usize::min(self.max_block_size() - HEADER_SIZE, TLS_RECORD_MAX);
+        match &self.record_padding {
+            Some(padding) => usize::min(padding.len(len), max_len.saturating_sub(len)),
+            None => 0,
         }
     }
 
     fn max_block_size(&self) -> usize {
-        self.buffer.len() - TLS_RECORD_OVERHEAD
+        let max_block_size = self.buffer.len() - TLS_RECORD_OVERHEAD;
+        match self.max_fragment_length {
+            // RFC 6066, Section 4.  Maximum Fragment Length Negotiation
+            // Once a maximum fragment length other than 2^14 has been successfully negotiated,
+            // the client and server MUST immediately begin fragmenting messages (including
+            // handshake messages) to ensure that no fragment larger than the negotiated length is
+            // sent.
+            // Only the application data records are limited, handshake messages are not
+            // fragmented.
+            Some(length) => usize::min(max_block_size, HEADER_SIZE + length.size()),
+            None => max_block_size,
+        }
     }
 
     pub fn is_full(&self) -> bool {
@@ -55,6 +113,34 @@ impl<'a> WriteBuffer<'a> {
         self.max_block_size() - self.pos
     }
 
+    /// Split off the last `len` bytes as scratch space, returning a shorter buffer for the next
+    /// record. This keeps temporary data of the handshake out of the stack and the futures.
+    pub(crate) fn split_scratch(
+        &mut self,
+        len: usize,
+    ) -> Result<(WriteBuffer<'_>, &mut [u8]), TlsError> {
+        if self.current_header.is_some() {
+            return Err(TlsError::InternalError);
+        }
+        let at = self
+            .buffer
+            .len()
+            .checked_sub(len)
+            .filter(|at| *at > TLS_RECORD_OVERHEAD)
+            .ok_or(TlsError::InsufficientSpace)?;
+        let (buffer, scratch) = self.buffer.split_at_mut(at);
+        Ok((
+            WriteBuffer {
+                buffer,
+                pos: 0,
+                current_header: None,
+                max_fragment_length: self.max_fragment_length,
+                record_padding: self.record_padding,
+            },
+            scratch,
+        ))
+    }
+
     pub fn contains(&self, header: ClientRecordHeader) -> bool {
         self.current_header == Some(header)
     }
@@ -94,16 +180,28 @@ impl<'a> WriteBuffer<'a> {
     where
         CipherSuite: TlsCipherSuite,
     {
-        const HEADER_SIZE: usize = 5;
-
         let header = self.current_header.take().unwrap();
+        let padding_len = self.padding_len(self.pos - HEADER_SIZE);
         self.with_buffer(|mut buf| {
             if !header.is_encrypted() {
                 return Ok(buf);
             }
 
+            #[cfg(feature = "tls12")]
+            if write_key_schedule.is_tls12() {
+                let mut buf = buf.offset(HEADER_SIZE);
+                crate::tls12::encrypt(write_key_schedule, header.trailer_content_type(), &mut buf)?;
+                return Ok(buf.rewind());
+            }
+
             buf.push(header.trailer_content_type() as u8)
                 .map_err(|_| TlsError::EncodeError)?;
+            // RFC 8446, Section 5.2.  Record Payload Protection
+            // zeros:  An arbitrary-length run of zero-valued bytes may appear in the cleartext
+            // after the type field.
+            for _ in 0..padding_len {
+                buf.push(0).map_err(|_| TlsError::EncodeError)?;
+            }
 
             let mut buf = buf.offset(HEADER_SIZE);
             encrypt(write_key_schedule, &mut buf)?;
@@ -114,6 +212,12 @@ impl<'a> WriteBuffer<'a> {
         self.buffer[3] = upper;
         self.buffer[4] = lower;
 
+        // The content type of TLS 1.2 records is not hidden
+        #[cfg(feature = "tls12")]
+        if header.is_encrypted() && write_key_schedule.is_tls12() {
+            self.buffer[0] = header.trailer_content_type() as u8;
+        }
+
         let slice = &self.buffer[..self.pos];
 
         self.pos = 0;
@@ -0,0 +1,786 @@
+//! Minimal DER reader for the parts of X.509 certificates that the certificate
+//! verifier does not expose, for the issuer of the client certificates, for the
+//! DNS names the server certificate is matched against, and for delegated credentials.
+#![cfg_attr(not(feature = "webpki"), allow(dead_code))]
+use crate::parse_buffer::{ParseBuffer, ParseError};
+use crate::TlsError;
+
+const SEQUENCE: u8 = 0x30;
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const EXPLICIT_0: u8 = 0xA0;
+const EXPLICIT_3: u8 = 0xA3;
+/// dNSName [2] IA5String of a GeneralName
+const DNS_NAME: u8 = 0x82;
+
+/// id-ce-subjectAltName (2.5.29.17)
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1D, 0x11];
+
+/// id-ce-keyUsage (2.5.29.15)
+const KEY_USAGE: &[u8] = &[0x55, 0x1D, 0x0F];
+
+/// id-ce-extKeyUsage (2.5.29.37)
+const EXT_KEY_USAGE: &[u8] = &[0x55, 0x1D, 0x25];
+
+/// id-kp-serverAuth (1.3.6.1.5.5.7.3.1), the purpose of TLS server certificates
+pub(crate) const ID_KP_SERVER_AUTH: &[u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01];
+
+/// The digitalSignature bit of the KeyUsage extension
+pub(crate) const DIGITAL_SIGNATURE: u8 = 0;
+
+/// The keyCertSign bit of the KeyUsage extension
+pub(crate) const KEY_CERT_SIGN: u8 = 5;
+
+/// id-pe-delegationUsage (1.3.6.1.4.1.44363.44) of RFC 9345
+const DELEGATION_USAGE: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xDA, 0x4B, 0x2C];
+
+/// id-ecPublicKey (1.2.840.10045.2.1)
+pub(crate) const ID_EC_PUBLIC_KEY: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+
+/// secp256r1 (1.2.840.10045.3.1.7)
+pub(crate) const SECP256R1: &[u8] = &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+
+/// secp384r1 (1.3.132.0.34)
+pub(crate) const SECP384R1: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x22];
+
+/// secp521r1 (1.3.132.0.35)
+pub(crate) const SECP521R1: &[u8] = &[0x2B, 0x81, 0x04, 0x00, 0x23];
+
+/// brainpoolP256r1 (1.3.36.3.3.2.8.1.1.7)
+#[cfg(feature = "brainpool")]
+pub(crate) const BRAINPOOL_P256R1: &[u8] = &[0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x07];
+
+/// brainpoolP384r1 (1.3.36.3.3.2.8.1.1.11)
+#[cfg(feature = "brainpool")]
+pub(crate) const BRAINPOOL_P384R1: &[u8] = &[0x2B, 0x24, 0x03, 0x03, 0x02, 0x08, 0x01, 0x01, 0x0B];
+
+/// sm2 (1.2.156.10197.1.301)
+#[cfg(feature = "sm")]
+pub(crate) const SM2: &[u8] = &[0x2A, 0x81, 0x1C, 0xCF, 0x55, 0x01, 0x82, 0x2D];
+
+/// rsaEncryption (1.2.840.113549.1.1.1)
+#[cfg(feature = "rsa")]
+pub(crate) const RSA_ENCRYPTION: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01];
+
+/// id-Ed448 (1.3.101.113)
+#[cfg(feature = "ed448")]
+pub(crate) const ID_ED448: &[u8] = &[0x2B, 0x65, 0x71];
+
+#[derive(Debug)]
+pub(crate) struct SubjectPublicKeyInfo<'a> {
+    /// Content octets of the algorithm OID
+    pub(crate) algorithm: &'a [u8],
+    /// Content octets of the named curve OID, for EC keys
+    pub(crate) named_curve: Option<&'a [u8]>,
+    /// Content of the subjectPublicKey BIT STRING, without the unused-bits octet
+    pub(crate) public_key: &'a [u8],
+}
+
+impl<'a> SubjectPublicKeyInfo<'a> {
+    /// Locate the SubjectPublicKeyInfo of a DER encoded certificate.
+    pub(crate) fn from_certificate(certificate: &'a [u8]) -> Result<Self, TlsError> {
+        Self::parse_certificate(&mut ParseBuffer::new(certificate)).map_err(|e| {
+            warn!("Error parsing certificate: {:?}", e);
+            TlsError::InvalidCertificate
+        })
+    }
+
+    /// Parse a DER encoded SubjectPublicKeyInfo.
+    pub(crate) fn from_der(spki: &'a [u8]) -> Result<Self, TlsError> {
+        Self::parse(&mut read_expected(&mut ParseBuffer::new(spki), SEQUENCE)?).map_err(|e| {
+            warn!("Error parsing public key: {:?}", e);
+            TlsError::InvalidCertificate
+        })
+    }
+
+    fn parse_certificate(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
+        // Certificate ::= SEQUENCE { tbsCertificate, signatureAlgorithm, signatureValue }
+        let mut certificate = read_expected(buf, SEQUENCE)?;
+        let mut tbs = read_expected(&mut certificate, SEQUENCE)?;
+
+        // TBSCertificate ::= SEQUENCE { [0] version OPTIONAL, serialNumber, signature,
+        //                                 issuer, validity, subject, subjectPublicKeyInfo, ... }
+        let (tag, _) = read(&mut tbs)?;
+        if tag == EXPLICIT_0 {
+            // serialNumber
+            read(&mut tbs)?;
+        }
+        // signature, issuer, validity and subject
+        for _ in 0..4 {
+            read_expected(&mut tbs, SEQUENCE)?;
+        }
+
+        Self::parse(&mut read_expected(&mut tbs, SEQUENCE)?)
+    }
+
+    fn parse(buf: &mut ParseBuffer<'a>) -> Result<Self, ParseError> {
+        // AlgorithmIdentifier ::= SEQUENCE { algorithm OBJECT IDENTIFIER, parameters ANY OPTIONAL }
+        let mut algorithm = read_expected(buf, SEQUENCE)?;
+        let oid = read_expected(&mut algorithm, OBJECT_IDENTIFIER)?;
+        let named_curve = if algorithm.is_empty() {
+            None
+        } else {
+            match read(&mut algorithm)? {
+                (OBJECT_IDENTIFIER, curve) => Some(curve.as_slice()),
+                _ => None,
+            }
+        };
+
+        let mut public_key = read_expected(buf, BIT_STRING)?;
+        if public_key.read_u8()? != 0 {
+            return Err(ParseError::InvalidData);
+        }
+        let public_key = public_key.slice(public_key.remaining())?;
+
+        Ok(Self {
+            algorithm: oid.as_slice(),
+            named_curve,
+            public_key: public_key.as_slice(),
+        })
+    }
+}
+
+/// Locate the DER encoded issuer Name of a certificate, including its tag and length, as found
+/// in the certificate_authorities of a CertificateRequest.
+pub(crate) fn issuer(certificate: &[u8]) -> Result<&[u8], TlsError> {
+    // signature
+    tbs_field(certificate, 1)
+}
+
+/// Locate the DER encoded subject Name of a certificate, including its tag and length, as sent
+/// in the certificate_authorities extension of a ClientHello.
+pub(crate) fn subject(certificate: &[u8]) -> Result<&[u8], TlsError> {
+    // signature, issuer and validity
+    tbs_field(certificate, 3)
+}
+
+/// Locate the DER encoded SubjectPublicKeyInfo of a certificate, including its tag and length,
+/// as hashed by a key pin.
+pub(crate) fn subject_public_key_info(certificate: &[u8]) -> Result<&[u8], TlsError> {
+    // signature, issuer, validity and subject
+    tbs_field(certificate, 4)
+}
+
+/// Locate the SEQUENCE of the TBSCertificate of a certificate which follows the serialNumber
+/// and `skip` other SEQUENCEs.
+fn tbs_field(certificate: &[u8], skip: usize) -> Result<&[u8], TlsError> {
+    parse_tbs_field(&mut ParseBuffer::new(certificate), skip).map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })
+}
+
+fn parse_tbs_field<'a>(buf: &mut ParseBuffer<'a>, skip: usize) -> Result<&'a [u8], ParseError> {
+    let mut certificate = read_expected(buf, SEQUENCE)?;
+    let mut tbs = read_expected(&mut certificate, SEQUENCE)?;
+
+    let (tag, _) = read(&mut tbs)?;
+    if tag == EXPLICIT_0 {
+        // serialNumber
+        read(&mut tbs)?;
+    }
+    for _ in 0..skip {
+        read_expected(&mut tbs, SEQUENCE)?;
+    }
+
+    // The field is returned with its tag and length, which are skipped by `read`
+    let start = tbs.as_slice().len() - tbs.remaining();
+    read_expected(&mut tbs, SEQUENCE)?;
+    let end = tbs.as_slice().len() - tbs.remaining();
+    tbs.as_slice()
+        .get(start..end)
+        .ok_or(ParseError::InvalidData)
+}
+
+/// Whether `name` matches one of the DNS names in the subjectAltName extension of a certificate,
+/// see [`dns_name_matches`].
+pub(crate) fn is_valid_for_dns_name(certificate: &[u8], name: &str) -> Result<bool, TlsError> {
+    #[cfg(feature = "idna")]
+    let mut idna_buf = [0; crate::idna::MAX_NAME_LEN];
+    #[cfg(feature = "idna")]
+    let name = crate::idna::to_ascii(name, &mut idna_buf)?;
+
+    parse_dns_names(&mut ParseBuffer::new(certificate), |presented| {
+        dns_name_matches(presented, name)
+    })
+    .map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })
+}
+
+fn parse_dns_names(
+    buf: &mut ParseBuffer<'_>,
+    mut f: impl FnMut(&[u8]) -> bool,
+) -> Result<bool, ParseError> {
+    parse_extensions(buf, |oid, mut value| {
+        if oid != SUBJECT_ALT_NAME {
+            return Ok(false);
+        }
+        // GeneralNames ::= SEQUENCE SIZE (1..MAX) OF GeneralName
+        let mut names = read_expected(&mut value, SEQUENCE)?;
+        while !names.is_empty() {
+            match read(&mut names)? {
+                (DNS_NAME, name) if f(name.as_slice()) => return Ok(true),
+                _ => {}
+            }
+        }
+        Ok(false)
+    })
+}
+
+/// Whether a certificate may issue delegated credentials.
+///
+/// RFC 9345, Section 4.2.  Certificate Requirements
+/// The client MUST NOT accept a delegated credential unless the server's end-entity certificate
+/// satisfies the following criteria:
+///
+/// *  It has the DelegationUsage extension.
+/// *  It has the digitalSignature KeyUsage (see the KeyUsage extension defined in [RFC5280]).
+pub(crate) fn allows_delegation(certificate: &[u8]) -> Result<bool, TlsError> {
+    let mut delegation_usage = false;
+    let mut digital_signature = false;
+    parse_extensions(&mut ParseBuffer::new(certificate), |oid, mut value| {
+        if oid == DELEGATION_USAGE {
+            delegation_usage = true;
+        } else if oid == KEY_USAGE {
+            // KeyUsage ::= BIT STRING { digitalSignature (0), ... }
+            let mut usage = read_expected(&mut value, BIT_STRING)?;
+            let _unused_bits = usage.read_u8()?;
+            digital_signature = usage.read_u8()? & 0x80 != 0;
+        }
+        Ok(false)
+    })
+    .map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })?;
+    Ok(delegation_usage && digital_signature)
+}
+
+/// Whether the KeyUsage extension of a certificate, if any, has the bit `usage` set, such as
+/// [`DIGITAL_SIGNATURE`] or [`KEY_CERT_SIGN`].
+pub(crate) fn allows_key_usage(certificate: &[u8], usage: u8) -> Result<bool, TlsError> {
+    let mut allowed = true;
+    parse_extensions(&mut ParseBuffer::new(certificate), |oid, mut value| {
+        if oid != KEY_USAGE {
+            return Ok(false);
+        }
+        // KeyUsage ::= BIT STRING { digitalSignature (0), ..., keyCertSign (5), ... }
+        let usage_bits = read_expected(&mut value, BIT_STRING)?;
+        let byte = usage_bits
+            .as_slice()
+            .get(1 + usize::from(usage / 8))
+            .copied()
+            .unwrap_or(0);
+        allowed = byte & (0x80 >> (usage % 8)) != 0;
+        Ok(true)
+    })
+    .map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })?;
+    Ok(allowed)
+}
+
+/// Whether a certificate has the ExtendedKeyUsage extension with the `purpose`, such as
+/// [`ID_KP_SERVER_AUTH`]. A certificate without the extension has no extended key usage.
+pub(crate) fn has_extended_key_usage(certificate: &[u8], purpose: &[u8]) -> Result<bool, TlsError> {
+    parse_extensions(&mut ParseBuffer::new(certificate), |oid, mut value| {
+        if oid != EXT_KEY_USAGE {
+            return Ok(false);
+        }
+        // ExtKeyUsageSyntax ::= SEQUENCE SIZE (1..MAX) OF KeyPurposeId
+        let mut usages = read_expected(&mut value, SEQUENCE)?;
+        while !usages.is_empty() {
+            if read_expected(&mut usages, OBJECT_IDENTIFIER)?.as_slice() == purpose {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    })
+    .map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })
+}
+
+/// Whether a certificate matches a filter of the oid_filters extension of a CertificateRequest.
+///
+/// RFC 8446, Section 4.2.5.  OID Filters
+/// For each extension OID recognized by the client, all of the specified values MUST be present
+/// in the client certificate (but the certificate MAY have other values as well).  However, the
+/// client MUST ignore and skip any unrecognized certificate extension OIDs.
+///
+/// The key usage and the extended key usage extensions are recognized, and the OID of the filter
+/// is compared with or without its tag and length.
+pub(crate) fn matches_oid_filter(
+    certificate: &[u8],
+    oid: &[u8],
+    values: &[u8],
+) -> Result<bool, TlsError> {
+    let oid = match oid {
+        [OBJECT_IDENTIFIER, len, oid @ ..] if *len as usize == oid.len() => oid,
+        oid => oid,
+    };
+    if oid != KEY_USAGE && oid != EXT_KEY_USAGE {
+        return Ok(true);
+    }
+
+    parse_extensions(
+        &mut ParseBuffer::new(certificate),
+        |extension_oid, mut value| {
+            if extension_oid != oid {
+                return Ok(false);
+            }
+            let mut filter = ParseBuffer::new(values);
+            if oid == KEY_USAGE {
+                // KeyUsage ::= BIT STRING, where the usages of the filter must all be set
+                let mut usage = read_expected(&mut value, BIT_STRING)?;
+                let mut required = read_expected(&mut filter, BIT_STRING)?;
+                let _unused_bits = (usage.read_u8()?, required.read_u8()?);
+                while !required.is_empty() {
+                    let bits = required.read_u8()?;
+                    let set = if usage.is_empty() {
+                        0
+                    } else {
+                        usage.read_u8()?
+                    };
+                    if bits & !set != 0 {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            } else {
+                // ExtKeyUsageSyntax ::= SEQUENCE SIZE (1..MAX) OF KeyPurposeId
+                let usages = read_expected(&mut value, SEQUENCE)?.as_slice();
+                let mut required = read_expected(&mut filter, SEQUENCE)?;
+                while !required.is_empty() {
+                    let purpose = read_expected(&mut required, OBJECT_IDENTIFIER)?;
+                    let mut usages = ParseBuffer::new(usages);
+                    let mut found = false;
+                    while !found && !usages.is_empty() {
+                        found = read_expected(&mut usages, OBJECT_IDENTIFIER)?.as_slice()
+                            == purpose.as_slice();
+                    }
+                    if !found {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+        },
+    )
+    .map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })
+}
+
+/// Locate the secret scalar of a DER encoded EC private key, along with its named curve if
+/// present, in a SEC1 ECPrivateKey (RFC 5915) or a PKCS #8 PrivateKeyInfo (RFC 5208).
+pub(crate) fn parse_ec_private_key(der: &[u8]) -> Result<(Option<&[u8]>, &[u8]), TlsError> {
+    parse_private_key(&mut ParseBuffer::new(der)).map_err(|e| {
+        warn!("Error parsing private key: {:?}", e);
+        TlsError::InvalidPrivateKey
+    })
+}
+
+fn parse_private_key<'a>(
+    buf: &mut ParseBuffer<'a>,
+) -> Result<(Option<&'a [u8]>, &'a [u8]), ParseError> {
+    let mut key = read_expected(buf, SEQUENCE)?;
+    let version = read_expected(&mut key, INTEGER)?;
+    match version.as_slice() {
+        // PrivateKeyInfo ::= SEQUENCE { version 0, privateKeyAlgorithm AlgorithmIdentifier,
+        //                               privateKey OCTET STRING, attributes [0] OPTIONAL }
+        [0] => {
+            let mut algorithm = read_expected(&mut key, SEQUENCE)?;
+            if read_expected(&mut algorithm, OBJECT_IDENTIFIER)?.as_slice() != ID_EC_PUBLIC_KEY {
+                return Err(ParseError::InvalidData);
+            }
+            let curve = read_expected(&mut algorithm, OBJECT_IDENTIFIER)?.as_slice();
+            let (named_curve, scalar) =
+                parse_private_key(&mut read_expected(&mut key, OCTET_STRING)?)?;
+            Ok((named_curve.or(Some(curve)), scalar))
+        }
+        // ECPrivateKey ::= SEQUENCE { version 1, privateKey OCTET STRING,
+        //                             parameters [0] ECParameters OPTIONAL,
+        //                             publicKey [1] BIT STRING OPTIONAL }
+        [1] => {
+            let scalar = read_expected(&mut key, OCTET_STRING)?.as_slice();
+            let named_curve = if key.is_empty() {
+                None
+            } else {
+                match read(&mut key)? {
+                    (EXPLICIT_0, mut parameters) => {
+                        Some(read_expected(&mut parameters, OBJECT_IDENTIFIER)?.as_slice())
+                    }
+                    _ => None,
+                }
+            };
+            Ok((named_curve, scalar))
+        }
+        _ => Err(ParseError::InvalidData),
+    }
+}
+
+/// Calls `f` with the extnID and the extnValue of the extensions of a certificate, until it
+/// returns true.
+fn parse_extensions<'a>(
+    buf: &mut ParseBuffer<'a>,
+    mut f: impl FnMut(&[u8], ParseBuffer<'a>) -> Result<bool, ParseError>,
+) -> Result<bool, ParseError> {
+    let mut certificate = read_expected(buf, SEQUENCE)?;
+    let mut tbs = read_expected(&mut certificate, SEQUENCE)?;
+
+    let (tag, _) = read(&mut tbs)?;
+    if tag == EXPLICIT_0 {
+        // serialNumber
+        read(&mut tbs)?;
+    }
+    // signature, issuer, validity, subject and subjectPublicKeyInfo
+    for _ in 0..5 {
+        read_expected(&mut tbs, SEQUENCE)?;
+    }
+
+    // The optional issuerUniqueID [1] and subjectUniqueID [2] are followed by extensions [3]
+    while !tbs.is_empty() {
+        let (tag, mut extensions) = read(&mut tbs)?;
+        if tag != EXPLICIT_3 {
+            continue;
+        }
+        let mut extensions = read_expected(&mut extensions, SEQUENCE)?;
+        while !extensions.is_empty() {
+            // Extension ::= SEQUENCE { extnID OBJECT IDENTIFIER, critical BOOLEAN DEFAULT FALSE,
+            //                          extnValue OCTET STRING }
+            let mut extension = read_expected(&mut extensions, SEQUENCE)?;
+            let oid = read_expected(&mut extension, OBJECT_IDENTIFIER)?;
+            let value = match read(&mut extension)? {
+                (BOOLEAN, _) => read_expected(&mut extension, OCTET_STRING)?,
+                (OCTET_STRING, value) => value,
+                _ => return Err(ParseError::InvalidData),
+            };
+            if f(oid.as_slice(), value)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// The notBefore time of a certificate, in seconds since the Unix epoch.
+pub(crate) fn not_before(certificate: &[u8]) -> Result<u64, TlsError> {
+    parse_not_before(&mut ParseBuffer::new(certificate)).map_err(|e| {
+        warn!("Error parsing certificate: {:?}", e);
+        TlsError::InvalidCertificate
+    })
+}
+
+fn parse_not_before(buf: &mut ParseBuffer<'_>) -> Result<u64, ParseError> {
+    let mut certificate = read_expected(buf, SEQUENCE)?;
+    let mut tbs = read_expected(&mut certificate, SEQUENCE)?;
+
+    let (tag, _) = read(&mut tbs)?;
+    if tag == EXPLICIT_0 {
+        // serialNumber
+        read(&mut tbs)?;
+    }
+    // signature and issuer
+    for _ in 0..2 {
+        read_expected(&mut tbs, SEQUENCE)?;
+    }
+
+    // Validity ::= SEQUENCE { notBefore Time, notAfter Time }
+    let mut validity = read_expected(&mut tbs, SEQUENCE)?;
+    let (tag, time) = read(&mut validity)?;
+    parse_time(tag, time.as_slice())
+}
+
+/// RFC 5280, Section 4.1.2.5.  Validity
+/// Time ::= CHOICE { utcTime UTCTime, generalTime GeneralizedTime }
+///
+/// Both are expressed in Greenwich Mean Time (Zulu), as YYMMDDHHMMSSZ for UTCTime, where YY is
+/// interpreted as 19YY when greater than or equal to 50 and as 20YY otherwise, and as
+/// YYYYMMDDHHMMSSZ for GeneralizedTime.
+fn parse_time(tag: u8, time: &[u8]) -> Result<u64, ParseError> {
+    let digits = |range: core::ops::Range<usize>| {
+        time.get(range)
+            .filter(|digits| digits.iter().all(u8::is_ascii_digit))
+            .map(|digits| {
+                digits
+                    .iter()
+                    .fold(0, |value, digit| value * 10 + u64::from(digit - b'0'))
+            })
+            .ok_or(ParseError::InvalidData)
+    };
+    let (year, start) = match (tag, time.len()) {
+        (UTC_TIME, 13) => match digits(0..2)? {
+            year if year >= 50 => (1900 + year, 2),
+            year => (2000 + year, 2),
+        },
+        (GENERALIZED_TIME, 15) => (digits(0..4)?, 4),
+        _ => return Err(ParseError::InvalidData),
+    };
+    if time[start + 10] != b'Z' {
+        return Err(ParseError::InvalidData);
+    }
+    let month = digits(start..start + 2)?;
+    let day = digits(start + 2..start + 4)?;
+    let seconds = digits(start + 4..start + 6)? * 3600
+        + digits(start + 6..start + 8)? * 60
+        + digits(start + 8..start + 10)?;
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(ParseError::InvalidData);
+    }
+
+    // The days from the civil date, counting the years from March so that the leap day is last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Ok(days * 86400 + seconds)
+}
+
+/// Compares a DNS name presented by a certificate with the name of the server, ignoring the case
+/// and a trailing dot.
+///
+/// RFC 6125, Section 6.4.3.  Checking of Wildcard Certificates
+/// 1.  The client SHOULD NOT attempt to match a presented identifier in which the wildcard
+///     character comprises a label other than the left-most label (e.g., do not match
+///     bar.*.example.net).
+///
+/// 2.  If the wildcard character is the only character of the left-most label in the presented
+///     identifier, the client SHOULD NOT compare against anything but the left-most label of the
+///     reference identifier (e.g., *.example.com would match foo.example.com but not
+///     bar.foo.example.com or example.com).
+///
+/// Partial wildcards such as `f*.example.com` are not matched, nor is a wildcard directly below
+/// a top-level domain.
+fn dns_name_matches(presented: &[u8], reference: &str) -> bool {
+    let presented = presented.strip_suffix(b".").unwrap_or(presented);
+    let reference = reference.strip_suffix('.').unwrap_or(reference).as_bytes();
+    if reference.contains(&b'*') {
+        return false;
+    }
+
+    match presented.strip_prefix(b"*.") {
+        Some(suffix) if suffix.contains(&b'.') && !suffix.contains(&b'*') => {
+            match reference.iter().position(|&c| c == b'.') {
+                Some(dot) if dot > 0 => reference
+                    .get(dot + 1..)
+                    .map_or(false, |rest| rest.eq_ignore_ascii_case(suffix)),
+                _ => false,
+            }
+        }
+        Some(_) => false,
+        None => !presented.contains(&b'*') && presented.eq_ignore_ascii_case(reference),
+    }
+}
+
+fn read<'a>(buf: &mut ParseBuffer<'a>) -> Result<(u8, ParseBuffer<'a>), ParseError> {
+    let tag = buf.read_u8()?;
+    let len = match buf.read_u8()? {
+        len if len < 0x80 => len as usize,
+        0x81 => buf.read_u8()? as usize,
+        0x82 => buf.read_u16()? as usize,
+        0x83 => buf.read_u24()? as usize,
+        _ => return Err(ParseError::InvalidData),
+    };
+    Ok((tag, buf.slice(len)?))
+}
+
+fn read_expected<'a>(buf: &mut ParseBuffer<'a>, tag: u8) -> Result<ParseBuffer<'a>, ParseError> {
+    match read(buf)? {
+        (t, value) if t == tag => Ok(value),
+        _ => Err(ParseError::InvalidData),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_ec_public_key() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let spki = SubjectPublicKeyInfo::from_certificate(&der).unwrap();
+
+        assert_eq!(ID_EC_PUBLIC_KEY, spki.algorithm);
+        // prime256v1 (1.2.840.10045.3.1.7)
+        assert_eq!(
+            Some(&[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07][..]),
+            spki.named_curve
+        );
+        assert_eq!(65, spki.public_key.len());
+        assert_eq!(0x04, spki.public_key[0]);
+    }
+
+    #[test]
+    fn test_issuer() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/chain/client-cert.pem"));
+        let issuer = issuer(&der).unwrap();
+        assert_eq!(SEQUENCE, issuer[0]);
+        assert_eq!(issuer.len(), issuer[1] as usize + 2);
+        // The common name of the intermediate CA
+        assert!(issuer.ends_with(b"intermediate"));
+
+        assert!(super::issuer(&der[..20]).is_err());
+    }
+
+    #[test]
+    fn test_allows_key_usage() {
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        let device = pem_parser::pem_to_der(include_str!("../tests/data/chain/client-cert.pem"));
+        assert!(allows_key_usage(&intermediate, KEY_CERT_SIGN).unwrap());
+        assert!(!allows_key_usage(&intermediate, DIGITAL_SIGNATURE).unwrap());
+        assert!(allows_key_usage(&device, DIGITAL_SIGNATURE).unwrap());
+        assert!(!allows_key_usage(&device, KEY_CERT_SIGN).unwrap());
+
+        // Any usage is allowed without the extension
+        let ca = pem_parser::pem_to_der(include_str!("../tests/data/ca-cert.pem"));
+        assert!(allows_key_usage(&ca, KEY_CERT_SIGN).unwrap());
+    }
+
+    #[test]
+    fn test_subject() {
+        // The subject of the intermediate CA is the issuer of the certificates it signs
+        let intermediate =
+            pem_parser::pem_to_der(include_str!("../tests/data/chain/intermediate-cert.pem"));
+        let device = pem_parser::pem_to_der(include_str!("../tests/data/chain/client-cert.pem"));
+        assert_eq!(issuer(&device).unwrap(), subject(&intermediate).unwrap());
+
+        assert!(subject(&intermediate[..20]).is_err());
+    }
+
+    #[test]
+    fn test_subject_public_key_info() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        let spki = subject_public_key_info(&der).unwrap();
+        assert_eq!(SEQUENCE, spki[0]);
+        assert_eq!(spki.len(), spki[1] as usize + 2);
+        assert_eq!(
+            SubjectPublicKeyInfo::from_certificate(&der)
+                .unwrap()
+                .public_key,
+            SubjectPublicKeyInfo::from_der(spki).unwrap().public_key
+        );
+    }
+
+    #[test]
+    fn test_is_valid_for_dns_name() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        assert!(is_valid_for_dns_name(&der, "localhost").unwrap());
+        assert!(is_valid_for_dns_name(&der, "LOCALHOST.").unwrap());
+        assert!(!is_valid_for_dns_name(&der, "example.com").unwrap());
+        assert!(is_valid_for_dns_name(&der[..der.len() / 2], "localhost").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_dns_name() {
+        assert!(dns_name_matches(
+            b"*.iot.example.com",
+            "broker.iot.example.com"
+        ));
+        assert!(dns_name_matches(
+            b"*.iot.example.com",
+            "Broker.IOT.example.com."
+        ));
+        assert!(!dns_name_matches(b"*.iot.example.com", "iot.example.com"));
+        assert!(!dns_name_matches(b"*.iot.example.com", ".iot.example.com"));
+        assert!(!dns_name_matches(
+            b"*.iot.example.com",
+            "a.broker.iot.example.com"
+        ));
+        assert!(!dns_name_matches(b"*.iot.example.com", "*.iot.example.com"));
+        assert!(!dns_name_matches(
+            b"broker.*.example.com",
+            "broker.iot.example.com"
+        ));
+        assert!(!dns_name_matches(
+            b"b*.iot.example.com",
+            "broker.iot.example.com"
+        ));
+        assert!(!dns_name_matches(b"*.com", "example.com"));
+        assert!(dns_name_matches(
+            b"broker.iot.example.com",
+            "broker.iot.example.com"
+        ));
+    }
+
+    #[test]
+    fn test_not_before() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        // Oct 13 08:20:42 2021 GMT
+        assert_eq!(1634113242, not_before(&der).unwrap());
+
+        assert_eq!(0, parse_time(UTC_TIME, b"700101000000Z").unwrap());
+        assert_eq!(2524607999, parse_time(UTC_TIME, b"491231235959Z").unwrap());
+        assert_eq!(
+            951868800,
+            parse_time(GENERALIZED_TIME, b"20000301000000Z").unwrap()
+        );
+        assert!(parse_time(UTC_TIME, b"691231235959Z").is_err());
+        assert!(parse_time(UTC_TIME, b"701301000000Z").is_err());
+        assert!(parse_time(GENERALIZED_TIME, b"700101000000Z").is_err());
+    }
+
+    #[test]
+    fn test_allows_delegation() {
+        // The certificate has the digitalSignature key usage, but no DelegationUsage extension
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        assert!(!allows_delegation(&der).unwrap());
+    }
+
+    #[test]
+    fn test_matches_oid_filter() {
+        // The certificate has the digitalSignature key usage and the clientAuth extended key
+        // usage
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/chain/client-cert.pem"));
+        let client_auth = [
+            0x30, 0x0A, 0x06, 0x08, 0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02,
+        ];
+        let server_auth = [
+            0x30, 0x0A, 0x06, 0x08, 0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01,
+        ];
+        assert!(matches_oid_filter(&der, EXT_KEY_USAGE, &client_auth).unwrap());
+        assert!(!matches_oid_filter(&der, EXT_KEY_USAGE, &server_auth).unwrap());
+        assert!(matches_oid_filter(&der, &[0x06, 0x03, 0x55, 0x1D, 0x25], &client_auth).unwrap());
+
+        // digitalSignature, then keyEncipherment
+        assert!(matches_oid_filter(&der, KEY_USAGE, &[0x03, 0x02, 0x07, 0x80]).unwrap());
+        assert!(!matches_oid_filter(&der, KEY_USAGE, &[0x03, 0x02, 0x05, 0x20]).unwrap());
+
+        // Unrecognized extensions are ignored, but the recognized ones must be present
+        assert!(matches_oid_filter(&der, SUBJECT_ALT_NAME, &[]).unwrap());
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/client-cert.pem"));
+        assert!(!matches_oid_filter(&der, EXT_KEY_USAGE, &client_auth).unwrap());
+    }
+
+    #[test]
+    fn test_has_extended_key_usage() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/chain/server-cert.pem"));
+        assert!(has_extended_key_usage(&der, ID_KP_SERVER_AUTH).unwrap());
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/chain/client-cert.pem"));
+        assert!(!has_extended_key_usage(&der, ID_KP_SERVER_AUTH).unwrap());
+        // No ExtendedKeyUsage extension
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        assert!(!has_extended_key_usage(&der, ID_KP_SERVER_AUTH).unwrap());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let der = pem_parser::pem_to_der(include_str!("../tests/data/server-cert.pem"));
+        assert!(SubjectPublicKeyInfo::from_certificate(&der[..der.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use aes_gcm::Aes128Gcm;
+use embedded_io::adapters::FromStd;
+use embedded_io::blocking::{Read as _, Write as _};
+use embedded_tls::aead::consts::{U12, U16};
+use embedded_tls::aead::{generic_array::GenericArray, AeadInPlace, Buffer, Error, KeyInit};
+use embedded_tls::blocking::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread::JoinHandle;
+
+static INIT: Once = Once::new();
+
+static ENCRYPTED: AtomicUsize = AtomicUsize::new(0);
+static DECRYPTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Stands in for an AES-GCM peripheral, counting the records it protects.
+struct OffloadAes128Gcm(Aes128Gcm);
+
+impl RecordAead for OffloadAes128Gcm {
+    type KeyLen = U16;
+    type NonceLen = U12;
+    type TagLen = U16;
+
+    fn new(key: &GenericArray<u8, U16>) -> Self {
+        Self(Aes128Gcm::new(key))
+    }
+
+    fn encrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, U12>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        ENCRYPTED.fetch_add(1, Ordering::Relaxed);
+        AeadInPlace::encrypt_in_place(&self.0, nonce, additional_data, buffer)
+    }
+
+    fn decrypt_in_place(
+        &self,
+        nonce: &GenericArray<u8, U12>,
+        additional_data: &[u8],
+        buffer: &mut dyn Buffer,
+    ) -> Result<(), Error> {
+        DECRYPTED.fetch_add(1, Ordering::Relaxed);
+        AeadInPlace::decrypt_in_place(&self.0, nonce, additional_data, buffer)
+    }
+}
+
+fn setup() -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+    });
+    (addr, h)
+}
+
+#[test]
+fn test_offloaded_aead() {
+    let (addr, h) = setup();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new().with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, WithCipher<Aes128GcmSha256, OffloadAes128Gcm>> =
+        TlsConnection::new(
+            FromStd::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    tls.read_exact(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf);
+
+    assert!(ENCRYPTED.load(Ordering::Relaxed) > 0);
+    assert!(DECRYPTED.load(Ordering::Relaxed) > 0);
+    h.join().unwrap();
+}
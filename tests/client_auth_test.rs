@@ -0,0 +1,337 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::adapters::FromStd;
+use embedded_io::blocking::{Read as _, Write as _};
+use embedded_tls::blocking::*;
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::{nid, ssl, x509};
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Once;
+use std::thread::JoinHandle;
+
+static INIT: Once = Once::new();
+
+fn setup() -> (SocketAddr, JoinHandle<String>) {
+    setup_with(|_| {})
+}
+
+/// Starts a server requiring a client certificate, and returns the common name of the client.
+fn setup_with(
+    configure: impl FnOnce(&mut ssl::SslAcceptorBuilder),
+) -> (SocketAddr, JoinHandle<String>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder.set_ca_file("tests/data/ca-cert.pem").unwrap();
+    builder.set_verify(ssl::SslVerifyMode::PEER | ssl::SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    configure(&mut builder);
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let peer = conn.ssl().peer_certificate().unwrap();
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+
+        let name = peer.subject_name().entries_by_nid(nid::Nid::COMMONNAME);
+        name.last().unwrap().data().as_utf8().unwrap().to_string()
+    });
+    (addr, h)
+}
+
+/// Stands in for a secure element holding the client key.
+struct KeyStoreSigner {
+    key: PKey<Private>,
+    signatures: usize,
+}
+
+impl ExternalSigner for KeyStoreSigner {
+    fn signature_scheme(&self) -> SignatureScheme {
+        SignatureScheme::EcdsaSecp256r1Sha256
+    }
+
+    fn sign(&mut self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+        signer.update(message).unwrap();
+        let der = signer.sign_to_vec().unwrap();
+        signature[..der.len()].copy_from_slice(&der);
+        self.signatures += 1;
+        Ok(der.len())
+    }
+}
+
+#[test]
+fn test_client_certificate_external_signer() {
+    let (addr, h) = setup();
+    let pem = include_bytes!("data/client-cert.pem");
+    let der = x509::X509::from_pem(pem).unwrap().to_der().unwrap();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut signer = KeyStoreSigner {
+        key: PKey::private_key_from_pem(include_bytes!("data/client-key.pem")).unwrap(),
+        signatures: 0,
+    };
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_cert(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_signer(&mut signer))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    tls.read_exact(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf);
+
+    h.join().unwrap();
+    assert_eq!(1, signer.signatures);
+}
+
+/// Converts a PEM encoded EC private key to the secret scalar.
+fn private_key(pem: &[u8]) -> Vec<u8> {
+    let key = PKey::private_key_from_pem(pem).unwrap();
+    key.ec_key()
+        .unwrap()
+        .private_key()
+        .to_vec_padded(32)
+        .unwrap()
+}
+
+#[test]
+fn test_client_identity() {
+    let (addr, h) = setup();
+    let pem = include_bytes!("data/client-cert.pem");
+    let der = x509::X509::from_pem(pem).unwrap().to_der().unwrap();
+    let key = private_key(include_bytes!("data/client-key.pem"));
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let chain = [Certificate::X509(&der[..])];
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_client_identity(&chain, PrivateKey::EcdsaSecp256r1(&key))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    tls.read_exact(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf);
+
+    h.join().unwrap();
+}
+
+#[test]
+fn test_client_certificate_chain() {
+    let (addr, h) = setup();
+    let leaf = x509::X509::from_pem(include_bytes!("data/chain/client-cert.pem")).unwrap();
+    let leaf = leaf.to_der().unwrap();
+    let intermediate =
+        x509::X509::from_pem(include_bytes!("data/chain/intermediate-cert.pem")).unwrap();
+    let intermediate = intermediate.to_der().unwrap();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut signer = KeyStoreSigner {
+        key: PKey::private_key_from_pem(include_bytes!("data/chain/client-key.pem")).unwrap(),
+        signatures: 0,
+    };
+
+    // The server only trusts the root CA, so the intermediate must be sent
+    let chain = [
+        Certificate::X509(&leaf[..]),
+        Certificate::X509(&intermediate[..]),
+    ];
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_cert_chain(&chain)
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_signer(&mut signer))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    tls.read_exact(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf);
+
+    h.join().unwrap();
+    assert_eq!(1, signer.signatures);
+}
+
+#[test]
+fn test_client_identity_selection() {
+    // The server only names the intermediate CA, which did not issue the factory certificate
+    let intermediate =
+        x509::X509::from_pem(include_bytes!("data/chain/intermediate-cert.pem")).unwrap();
+    let (addr, h) = setup_with(|builder| {
+        builder.add_client_ca(&intermediate).unwrap();
+    });
+
+    let factory = x509::X509::from_pem(include_bytes!("data/client-cert.pem")).unwrap();
+    let factory = factory.to_der().unwrap();
+    let factory_key = private_key(include_bytes!("data/client-key.pem"));
+    let device = x509::X509::from_pem(include_bytes!("data/chain/client-cert.pem")).unwrap();
+    let device = device.to_der().unwrap();
+    let device_key = private_key(include_bytes!("data/chain/client-key.pem"));
+    let intermediate = intermediate.to_der().unwrap();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let factory_chain = [Certificate::X509(&factory[..])];
+    let device_chain = [
+        Certificate::X509(&device[..]),
+        Certificate::X509(&intermediate[..]),
+    ];
+    let identities = [
+        ClientIdentity::new(&factory_chain, PrivateKey::EcdsaSecp256r1(&factory_key)),
+        ClientIdentity::new(&device_chain, PrivateKey::EcdsaSecp256r1(&device_key)),
+    ];
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_client_identities(&identities)
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    tls.read_exact(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf);
+
+    assert_eq!("device", h.join().unwrap());
+}
+
+/// Stands in for a TPM, which signs asynchronously and returns the raw `r` and `s` values.
+struct TpmSigner {
+    key: PKey<Private>,
+    signatures: usize,
+}
+
+impl embedded_tls::ExternalSigner for TpmSigner {
+    fn signature_scheme(&self) -> SignatureScheme {
+        SignatureScheme::EcdsaSecp256r1Sha256
+    }
+
+    async fn sign(&mut self, message: &[u8], signature: &mut [u8]) -> Result<usize, TlsError> {
+        tokio::task::yield_now().await;
+        let digest = openssl::sha::sha256(message);
+        let sig = EcdsaSig::sign(&digest, &self.key.ec_key().unwrap()).unwrap();
+        let mut raw = sig.r().to_vec_padded(32).unwrap();
+        raw.extend_from_slice(&sig.s().to_vec_padded(32).unwrap());
+        self.signatures += 1;
+        encode_ecdsa_signature(&raw, signature)
+    }
+}
+
+#[tokio::test]
+async fn test_client_certificate_async_signer() {
+    use embedded_io::adapters::FromTokio;
+    use embedded_tls::*;
+
+    let (addr, h) = setup();
+    let pem = include_bytes!("data/client-cert.pem");
+    let der = x509::X509::from_pem(pem).unwrap().to_der().unwrap();
+    let stream = tokio::net::TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut signer = TpmSigner {
+        key: PKey::private_key_from_pem(include_bytes!("data/client-key.pem")).unwrap(),
+        signatures: 0,
+    };
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_cert(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromTokio<tokio::net::TcpStream>, Aes128GcmSha256> =
+        TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_signer(&mut signer))
+        .await
+        .expect("error establishing TLS connection");
+
+    tls.write(b"ping").await.expect("error writing data");
+    tls.flush().await.expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    let len = tls.read(&mut rx_buf).await.expect("error reading data");
+    assert_eq!(b"ping", &rx_buf[..len]);
+
+    h.join().unwrap();
+    assert_eq!(1, signer.signatures);
+}
@@ -336,3 +336,313 @@ fn test_blocking_ping_nocopy_bufread() {
         .map_err(|(_, e)| e)
         .expect("error closing session");
 }
+
+#[test]
+fn test_blocking_ping_aes256() {
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+
+    let addr = setup();
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    log::info!("Connected");
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes256GcmSha384> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    log::info!("Established");
+
+    tls.write(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4096];
+    let sz = tls.read(&mut rx_buf).expect("error reading data");
+    assert_eq!(4, sz);
+    assert_eq!(b"ping", &rx_buf[..sz]);
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+}
+
+#[test]
+fn test_blocking_ping_x25519() {
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+
+    let addr = setup();
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    log::info!("Connected");
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_named_groups(&[NamedGroup::X25519])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    log::info!("Established");
+
+    tls.write(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4096];
+    let sz = tls.read(&mut rx_buf).expect("error reading data");
+    assert_eq!(4, sz);
+    assert_eq!(b"ping", &rx_buf[..sz]);
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+}
+
+/// A verifier which pins the certificate of the server, either the one the test server sends or
+/// another one.
+struct PinningVerifier<const PIN_SERVER: bool>;
+
+impl<'a, CipherSuite, const PIN_SERVER: bool> embedded_tls::blocking::TlsVerifier<'a, CipherSuite>
+    for PinningVerifier<PIN_SERVER>
+where
+    CipherSuite: embedded_tls::blocking::TlsCipherSuite,
+{
+    fn new(host: Option<&'a str>) -> Self {
+        assert_eq!(Some("localhost"), host);
+        Self
+    }
+
+    fn verify_certificate(
+        &mut self,
+        _transcript: &CipherSuite::Hash,
+        _ca: &Option<embedded_tls::blocking::Certificate>,
+        _cert: embedded_tls::blocking::CertificateRef,
+    ) -> Result<(), embedded_tls::blocking::TlsError> {
+        Ok(())
+    }
+
+    fn verify_signature(
+        &mut self,
+        _verify: embedded_tls::blocking::CertificateVerify,
+    ) -> Result<(), embedded_tls::blocking::TlsError> {
+        Ok(())
+    }
+
+    fn verify_certificate_context(
+        &mut self,
+        context: &embedded_tls::blocking::CertificateContext,
+    ) -> Result<(), embedded_tls::blocking::TlsError> {
+        use embedded_tls::alert::{AlertDescription, AlertLevel};
+
+        let pinned = if PIN_SERVER {
+            pem_parser::pem_to_der(include_str!("data/server-cert.pem"))
+        } else {
+            pem_parser::pem_to_der(include_str!("data/p384/server-cert.pem"))
+        };
+        assert_eq!(Some("localhost"), context.server_name());
+        assert!(!context.is_raw_public_key());
+        assert_eq!(32, context.transcript_hash().len());
+        if context.chain().next() != Some(&pinned[..]) {
+            return Err(embedded_tls::blocking::TlsError::AbortHandshake(
+                AlertLevel::Fatal,
+                AlertDescription::BadCertificate,
+            ));
+        }
+        Ok(())
+    }
+
+    fn verify_signature_context(
+        &mut self,
+        context: &embedded_tls::blocking::SignatureContext,
+    ) -> Result<(), embedded_tls::blocking::TlsError> {
+        assert_eq!(
+            embedded_tls::blocking::SignatureScheme::EcdsaSecp256r1Sha256,
+            context.signature_scheme()
+        );
+        assert!(!context.signature().is_empty());
+        assert_eq!(32, context.transcript_hash().len());
+        Ok(())
+    }
+}
+
+#[test]
+fn test_blocking_verifier_context() {
+    use embedded_tls::alert::{AlertDescription, AlertLevel};
+    use embedded_tls::blocking::blocking::*;
+    use std::net::TcpStream;
+
+    let addr = setup();
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new().with_server_name("localhost");
+
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+    tls.open::<OsRng, PinningVerifier<true>>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+
+    // The verifier rejects the certificate with its own alert
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+    let result = tls.open::<OsRng, PinningVerifier<false>>(TlsContext::new(&config, &mut OsRng));
+    assert!(matches!(
+        result,
+        Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::BadCertificate
+        ))
+    ));
+}
+
+#[test]
+fn test_blocking_spki_pins() {
+    use embedded_tls::alert::{AlertDescription, AlertLevel};
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+
+    // SHA-256 of the SubjectPublicKeyInfo of data/server-cert.pem
+    const SERVER_PIN: [u8; 32] = [
+        0x24, 0xa9, 0x6e, 0x60, 0xc2, 0xe2, 0x70, 0x89, 0x48, 0x17, 0x92, 0x6f, 0x04, 0xf7, 0xa6,
+        0x2a, 0x47, 0xfd, 0xff, 0x21, 0x4d, 0x98, 0x91, 0x66, 0xca, 0x44, 0x53, 0xc8, 0x19, 0x59,
+        0xc8, 0xa5,
+    ];
+
+    let addr = setup();
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+
+    // The pinned key is trusted without a CA
+    let pins = [[0; 32], SERVER_PIN];
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_spki_pins(&pins);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+    tls.open::<OsRng, PinnedKeyVerifier<Aes128GcmSha256>>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    tls.write(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+    let mut rx_buf = [0; 4096];
+    let sz = tls.read(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf[..sz]);
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+
+    // Any other key is rejected, whatever the verifier
+    let pins = [[0; 32]];
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_spki_pins(&pins);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+    let result = tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng));
+    assert!(matches!(
+        result,
+        Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::BadCertificate
+        ))
+    ));
+}
+
+#[test]
+fn test_blocking_tofu() {
+    use embedded_tls::alert::{AlertDescription, AlertLevel};
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+    use std::sync::Mutex;
+
+    static FINGERPRINTS: Mutex<Vec<(String, [u8; 32])>> = Mutex::new(Vec::new());
+
+    struct Store;
+    impl FingerprintStore for Store {
+        fn get(server: &str) -> Option<[u8; 32]> {
+            let fingerprints = FINGERPRINTS.lock().unwrap();
+            fingerprints
+                .iter()
+                .find(|(name, _)| name == server)
+                .map(|(_, fingerprint)| *fingerprint)
+        }
+
+        fn put(server: &str, fingerprint: [u8; 32]) {
+            let mut fingerprints = FINGERPRINTS.lock().unwrap();
+            fingerprints.retain(|(name, _)| name != server);
+            fingerprints.push((server.to_string(), fingerprint));
+        }
+    }
+
+    let addr = setup();
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new().with_server_name("localhost");
+    let mut open = || {
+        let stream = TcpStream::connect(addr).expect("error connecting to server");
+        let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+            FromStd::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+        tls.open::<OsRng, TofuVerifier<Aes128GcmSha256, Store>>(TlsContext::new(
+            &config, &mut OsRng,
+        ))?;
+        tls.close().map_err(|(_, e)| e)
+    };
+
+    // The key is recorded on first contact, and trusted on the following ones
+    open().expect("error on first contact");
+    let recorded = Store::get("localhost").expect("no fingerprint recorded");
+    open().expect("error on second contact");
+    assert_eq!(Some(recorded), Store::get("localhost"));
+
+    // Another key is rejected, and the recorded fingerprint is kept
+    Store::put("localhost", [0; 32]);
+    let result = open();
+    assert!(matches!(
+        result,
+        Err(TlsError::AbortHandshake(
+            AlertLevel::Fatal,
+            AlertDescription::BadCertificate
+        ))
+    ));
+    assert_eq!(Some([0; 32]), Store::get("localhost"));
+}
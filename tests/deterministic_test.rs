@@ -0,0 +1,90 @@
+#![cfg(feature = "deterministic-rng")]
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::adapters::FromStd;
+use embedded_tls::blocking::*;
+use std::io::{Read, Write};
+
+/// RFC 8448 Section 3: the ephemeral X25519 private key of the client, followed by the
+/// ClientHello random.
+const RFC8448_CLIENT_RANDOMNESS: [u8; 64] = [
+    0x49, 0xaf, 0x42, 0xba, 0x7f, 0x79, 0x94, 0x85, 0x2d, 0x71, 0x3e, 0xf2, 0x78, 0x4b, 0xcb, 0xca,
+    0xa7, 0x91, 0x1d, 0xe2, 0x6a, 0xdc, 0x56, 0x42, 0xcb, 0x63, 0x45, 0x40, 0xe7, 0xea, 0x50, 0x05,
+    0xcb, 0x34, 0xec, 0xb1, 0xe7, 0x81, 0x63, 0xba, 0x1c, 0x38, 0xc6, 0xda, 0xcb, 0x19, 0x6a, 0x6d,
+    0xff, 0xa2, 0x1a, 0x8d, 0x99, 0x12, 0xec, 0x18, 0xa2, 0xef, 0x62, 0x83, 0x02, 0x4d, 0xec, 0xe7,
+];
+
+/// RFC 8448 Section 3: the ephemeral X25519 public key of the client.
+const RFC8448_CLIENT_PUBLIC_KEY: [u8; 32] = [
+    0x99, 0x38, 0x1d, 0xe5, 0x60, 0xe4, 0xbd, 0x43, 0xd2, 0x3d, 0x8e, 0x43, 0x5a, 0x7d, 0xba, 0xfe,
+    0xb3, 0xc0, 0x6e, 0x51, 0xc1, 0x3c, 0xae, 0x4d, 0x54, 0x13, 0x69, 0x1e, 0x52, 0x9a, 0xaf, 0x2c,
+];
+
+/// Records the flight of the client, and ends the connection before the server responds.
+#[derive(Default)]
+struct Transcript {
+    written: Vec<u8>,
+}
+
+impl Read for Transcript {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Transcript {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn client_hello(randomness: &[u8]) -> Result<Vec<u8>, TlsError> {
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_named_groups(&[NamedGroup::X25519])
+        .with_server_name("server");
+
+    let mut transcript = Transcript::default();
+    let mut tls: TlsConnection<FromStd<&mut Transcript>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(&mut transcript),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    let mut rng = FixedRng::new(randomness);
+    let result = tls.open::<FixedRng, NoVerify>(TlsContext::new(&config, &mut rng));
+    drop(tls);
+    match result {
+        Err(TlsError::RngError) => Err(TlsError::RngError),
+        _ => Ok(transcript.written),
+    }
+}
+
+#[test]
+fn test_reproducible_client_hello() {
+    let first = client_hello(&RFC8448_CLIENT_RANDOMNESS).unwrap();
+    let second = client_hello(&RFC8448_CLIENT_RANDOMNESS).unwrap();
+    assert_eq!(first, second);
+
+    // Record header, handshake header and legacy_version precede the random
+    assert_eq!(&RFC8448_CLIENT_RANDOMNESS[32..], &first[11..43]);
+    assert!(first
+        .windows(RFC8448_CLIENT_PUBLIC_KEY.len())
+        .any(|w| w == RFC8448_CLIENT_PUBLIC_KEY));
+}
+
+#[test]
+fn test_exhausted_randomness() {
+    assert!(matches!(
+        client_hello(&RFC8448_CLIENT_RANDOMNESS[..40]),
+        Err(TlsError::RngError)
+    ));
+}
@@ -0,0 +1,134 @@
+#![cfg(feature = "interop")]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_tls::blocking::*;
+use embedded_tls::interop::{InteropError, InteropServer, ServerImplementation};
+use rand::rngs::OsRng;
+
+const CERT_CHAIN: &[u8] = include_bytes!("data/server-cert.pem");
+const PRIVATE_KEY: &[u8] = include_bytes!("data/server-key.pem");
+
+#[test]
+fn test_rustls_smoke_test() {
+    let config = TlsConfig::new().with_server_name("localhost");
+    InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN, PRIVATE_KEY)
+        .with_named_groups(&[NamedGroup::Secp256r1])
+        .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+        .expect("smoke test failed");
+}
+
+#[test]
+fn test_rustls_unsupported_suite() {
+    let config = TlsConfig::new().with_server_name("localhost");
+    let result = InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN, PRIVATE_KEY)
+        .smoke_test::<Aes128CcmSha256, NoVerify, _>(&config, &mut OsRng);
+    assert!(matches!(result, Err(InteropError::Unsupported)));
+}
+
+#[test]
+fn test_rustls_hello_retry_request() {
+    // The key share is sent for P-256 only, so the server asks for another one
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519]);
+    InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN, PRIVATE_KEY)
+        .with_named_groups(&[NamedGroup::X25519])
+        .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+        .expect("smoke test failed");
+}
+
+#[test]
+fn test_rustls_multiple_key_shares() {
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::X25519, NamedGroup::Secp256r1])
+        .with_key_shares(2);
+    for group in [NamedGroup::X25519, NamedGroup::Secp256r1] {
+        InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN, PRIVATE_KEY)
+            .with_named_groups(&[group])
+            .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+            .expect("smoke test failed");
+    }
+}
+
+#[test]
+fn test_rustls_middlebox_compatibility() {
+    // The server answers the session ID with change_cipher_spec records, also with a
+    // HelloRetryRequest
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519])
+        .with_middlebox_compatibility();
+    for group in [NamedGroup::Secp256r1, NamedGroup::X25519] {
+        InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN, PRIVATE_KEY)
+            .with_named_groups(&[group])
+            .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+            .expect("smoke test failed");
+    }
+}
+
+#[test]
+fn test_rustls_grease() {
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519])
+        .with_grease();
+    for group in [NamedGroup::Secp256r1, NamedGroup::X25519] {
+        InteropServer::new(ServerImplementation::Rustls, CERT_CHAIN, PRIVATE_KEY)
+            .with_named_groups(&[group])
+            .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+            .expect("smoke test failed");
+    }
+}
+
+#[cfg(feature = "interop-openssl")]
+#[test]
+fn test_openssl_smoke_test() {
+    let config = TlsConfig::new().with_server_name("localhost");
+    InteropServer::new(ServerImplementation::OpenSsl, CERT_CHAIN, PRIVATE_KEY)
+        .smoke_test::<Aes256GcmSha384, NoVerify, _>(&config, &mut OsRng)
+        .expect("smoke test failed");
+}
+
+#[cfg(feature = "interop-openssl")]
+#[test]
+fn test_openssl_hello_retry_request() {
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519]);
+    InteropServer::new(ServerImplementation::OpenSsl, CERT_CHAIN, PRIVATE_KEY)
+        .with_named_groups(&[NamedGroup::X25519])
+        .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+        .expect("smoke test failed");
+}
+
+#[cfg(feature = "interop-openssl")]
+#[test]
+fn test_openssl_middlebox_compatibility() {
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519])
+        .with_middlebox_compatibility();
+    for group in [NamedGroup::Secp256r1, NamedGroup::X25519] {
+        InteropServer::new(ServerImplementation::OpenSsl, CERT_CHAIN, PRIVATE_KEY)
+            .with_named_groups(&[group])
+            .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+            .expect("smoke test failed");
+    }
+}
+
+#[cfg(feature = "interop-openssl")]
+#[test]
+fn test_openssl_grease() {
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_named_groups(&[NamedGroup::Secp256r1, NamedGroup::X25519])
+        .with_grease();
+    for group in [NamedGroup::Secp256r1, NamedGroup::X25519] {
+        InteropServer::new(ServerImplementation::OpenSsl, CERT_CHAIN, PRIVATE_KEY)
+            .with_named_groups(&[group])
+            .smoke_test::<Aes128GcmSha256, NoVerify, _>(&config, &mut OsRng)
+            .expect("smoke test failed");
+    }
+}
@@ -0,0 +1,211 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::adapters::FromStd;
+use embedded_io::blocking::{Read as _, Write as _};
+use embedded_tls::blocking::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Once;
+use std::thread::JoinHandle;
+
+static INIT: Once = Once::new();
+
+fn setup() -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites("TLS_AES_128_GCM_SHA256").unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; 64];
+        loop {
+            match conn.read(&mut buf[..]) {
+                Ok(0) | Err(_) => break,
+                Ok(len) => conn.write_all(&buf[..len]).unwrap(),
+            }
+        }
+    });
+    (addr, h)
+}
+
+#[test]
+fn test_key_update_at_record_limit() {
+    let (addr, h) = setup();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_record_limit(3, RecordLimitAction::KeyUpdate)
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    // Every second application record is followed by a KeyUpdate
+    for _ in 0..10 {
+        tls.write_all(b"ping").expect("error writing data");
+        tls.flush().expect("error flushing data");
+
+        let mut rx_buf = [0; 4];
+        tls.read_exact(&mut rx_buf).expect("error reading data");
+        assert_eq!(b"ping", &rx_buf);
+    }
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+    h.join().unwrap();
+}
+
+#[test]
+fn test_abort_at_record_limit() {
+    let (addr, h) = setup();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_record_limit(2, RecordLimitAction::Abort)
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    for _ in 0..2 {
+        tls.write_all(b"ping").expect("error writing data");
+        tls.flush().expect("error flushing data");
+    }
+
+    tls.write_all(b"ping").expect("error writing data");
+    assert!(matches!(tls.flush(), Err(TlsError::RecordLimitReached)));
+
+    drop(tls);
+    h.join().unwrap();
+}
+
+#[test]
+fn test_request_key_update() {
+    let (addr, h) = setup();
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new().with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    // The server updates its keys as well when requested, before echoing the next ping
+    for update_requested in [false, true, true, false] {
+        tls.write_all(b"ping").expect("error writing data");
+        tls.request_key_update(update_requested)
+            .expect("error updating keys");
+        tls.write_all(b"pong").expect("error writing data");
+        tls.flush().expect("error flushing data");
+
+        let mut rx_buf = [0; 8];
+        tls.read_exact(&mut rx_buf).expect("error reading data");
+        assert_eq!(b"pingpong", &rx_buf);
+    }
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+    h.join().unwrap();
+}
+
+#[tokio::test]
+async fn test_request_key_update_async() {
+    use embedded_io::adapters::FromTokio;
+    use embedded_tls::*;
+
+    let (addr, h) = setup();
+    let stream = tokio::net::TcpStream::connect(addr)
+        .await
+        .expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new().with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromTokio<tokio::net::TcpStream>, Aes128GcmSha256> =
+        TlsConnection::new(
+            FromTokio::new(stream),
+            &mut read_record_buffer,
+            &mut write_record_buffer,
+        );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .await
+        .expect("error establishing TLS connection");
+
+    for _ in 0..3 {
+        tls.request_key_update(true)
+            .await
+            .expect("error updating keys");
+        tls.write(b"ping").await.expect("error writing data");
+        tls.flush().await.expect("error flushing data");
+
+        let mut rx_buf = [0; 4];
+        let mut len = 0;
+        while len < rx_buf.len() {
+            len += tls
+                .read(&mut rx_buf[len..])
+                .await
+                .expect("error reading data");
+        }
+        assert_eq!(b"ping", &rx_buf);
+    }
+
+    tls.close()
+        .await
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+    h.join().unwrap();
+}
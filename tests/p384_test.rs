@@ -0,0 +1,181 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::adapters::FromStd;
+use embedded_io::blocking::{Read as _, Write as _};
+use embedded_tls::blocking::*;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Once;
+use std::thread::JoinHandle;
+
+static INIT: Once = Once::new();
+
+fn setup(groups: &str) -> (SocketAddr, JoinHandle<()>) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/p384/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/p384/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_ciphersuites("TLS_AES_256_GCM_SHA384").unwrap();
+    builder.set_groups_list(groups).unwrap();
+    builder.set_sigalgs_list("ECDSA+SHA384").unwrap();
+    let acceptor = builder.build();
+
+    let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+    let listener = TcpListener::bind(addr).expect("cannot listen on port");
+    let addr = listener
+        .local_addr()
+        .expect("error retrieving socket address");
+
+    let h = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = acceptor.accept(stream).unwrap();
+        let mut buf = [0; 64];
+        let len = conn.read(&mut buf[..]).unwrap();
+        conn.write_all(&buf[..len]).unwrap();
+    });
+    (addr, h)
+}
+
+fn ping(tls: &mut TlsConnection<FromStd<TcpStream>, Aes256GcmSha384>) {
+    tls.write_all(b"ping").expect("error writing data");
+    tls.flush().expect("error flushing data");
+
+    let mut rx_buf = [0; 4];
+    tls.read_exact(&mut rx_buf).expect("error reading data");
+    assert_eq!(b"ping", &rx_buf);
+}
+
+#[test]
+fn test_secp384r1_key_exchange() {
+    let (addr, h) = setup("P-384");
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_named_groups(&[NamedGroup::Secp384r1])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes256GcmSha384> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    ping(&mut tls);
+    h.join().unwrap();
+}
+
+#[test]
+fn test_secp384r1_precomputed_key_share() {
+    // Generated before the connection is opened
+    let secret = EphemeralSecret::generate(NamedGroup::Secp384r1, &mut OsRng).unwrap();
+
+    let (addr, h) = setup("P-384");
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_named_groups(&[NamedGroup::X25519, NamedGroup::Secp384r1])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes256GcmSha384> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng).with_key_share(secret))
+        .expect("error establishing TLS connection");
+
+    ping(&mut tls);
+    h.join().unwrap();
+}
+
+#[cfg(feature = "webpki")]
+#[test]
+fn test_secp384r1_certificate_verify() {
+    use embedded_tls::webpki::CertVerifier;
+    use std::time::SystemTime;
+
+    let (addr, h) = setup("P-384");
+    let pem = include_str!("data/p384/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_named_groups(&[NamedGroup::Secp384r1])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes256GcmSha384> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, CertVerifier<Aes256GcmSha384, SystemTime, 4096>>(TlsContext::new(
+        &config, &mut OsRng,
+    ))
+    .expect("error establishing TLS connection");
+
+    ping(&mut tls);
+    h.join().unwrap();
+}
+
+// The CertificateVerify signature scheme is independent of the key exchange group
+#[cfg(feature = "webpki")]
+#[test]
+fn test_secp384r1_certificate_with_secp256r1_key_exchange() {
+    use embedded_tls::webpki::CertVerifier;
+    use std::time::SystemTime;
+
+    let (addr, h) = setup("P-256");
+    let pem = include_str!("data/p384/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_named_groups(&[NamedGroup::Secp256r1])
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<FromStd<TcpStream>, Aes256GcmSha384> = TlsConnection::new(
+        FromStd::new(stream),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, CertVerifier<Aes256GcmSha384, SystemTime, 4096>>(TlsContext::new(
+        &config, &mut OsRng,
+    ))
+    .expect("error establishing TLS connection");
+
+    ping(&mut tls);
+    h.join().unwrap();
+}
@@ -0,0 +1,384 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::blocking::{Read, Write};
+use embedded_tls::testing::Pipe;
+use rand_core::OsRng;
+use std::sync::Arc;
+
+#[allow(dead_code)]
+mod tlsserver;
+
+type ServerStream = rustls::StreamOwned<rustls::ServerConnection, Pipe>;
+
+/// Runs a rustls server on one end of the pipe.
+fn serve(pipe: Pipe, session: fn(&mut ServerStream)) -> std::thread::JoinHandle<()> {
+    serve_with_config(pipe, server_config(), session)
+}
+
+fn serve_with_config(
+    pipe: Pipe,
+    config: rustls::ServerConfig,
+    session: fn(&mut ServerStream),
+) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let connection = rustls::ServerConnection::new(Arc::new(config)).unwrap();
+        session(&mut rustls::StreamOwned::new(connection, pipe));
+    })
+}
+
+fn server_config() -> rustls::ServerConfig {
+    use tlsserver::*;
+
+    let versions = &[&rustls::version::TLS13];
+
+    let test_dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests");
+
+    let certs = load_certs(&test_dir.join("data").join("server-cert.pem"));
+    let privkey = load_private_key(&test_dir.join("data").join("server-key.pem"));
+
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, privkey)
+        .unwrap()
+}
+
+fn echo(tls: &mut ServerStream) {
+    use std::io::{Read, Write};
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).unwrap();
+    tls.write_all(&buffer).unwrap();
+    tls.flush().unwrap();
+}
+
+/// Answers the close_notify alert of the client with its own.
+fn close(tls: &mut ServerStream) {
+    use std::io::Read;
+
+    assert_eq!(0, tls.read(&mut [0; 4]).unwrap());
+    tls.conn.send_close_notify();
+    tls.conn.complete_io(&mut tls.sock).unwrap();
+}
+
+#[test]
+fn test_blocking_ping_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    let server = serve(server, echo);
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_blocking_shutdown_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    let server = serve(server, |tls| {
+        echo(tls);
+        close(tls);
+    });
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    // The parts are returned once the server has closed its side of the connection
+    let parts = tls
+        .shutdown(true)
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+    assert_eq!(16384, parts.record_read_buf.len());
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_blocking_half_close_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    // The request is read until the client closes its write side
+    let server = serve(server, |tls| {
+        use std::io::{Read, Write};
+
+        let mut request = Vec::new();
+        tls.read_to_end(&mut request).unwrap();
+        tls.write_all(&request).unwrap();
+        tls.conn.send_close_notify();
+        tls.conn.complete_io(&mut tls.sock).unwrap();
+    });
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.close_write().expect("Failed to close the write side");
+    assert!(matches!(
+        tls.write(b"ping"),
+        Err(TlsError::ConnectionClosed)
+    ));
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+    assert_eq!(
+        0,
+        tls.read(&mut buffer).expect("Failed to read close_notify")
+    );
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+    server.join().unwrap();
+}
+
+#[test]
+fn test_blocking_truncation_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    // The pipe is dropped without a close_notify alert
+    let server = serve(server, echo);
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+    server.join().unwrap();
+
+    assert!(matches!(
+        tls.read(&mut buffer),
+        Err(TlsError::UnexpectedEof)
+    ));
+}
+
+#[test]
+fn test_blocking_alpn_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    let mut server_config = server_config();
+    server_config.alpn_protocols = vec![b"mqtt".to_vec(), b"x-amzn-mqtt-ca".to_vec()];
+    let server = serve_with_config(server, server_config, |tls| {
+        echo(tls);
+        assert_eq!(Some(&b"x-amzn-mqtt-ca"[..]), tls.conn.alpn_protocol());
+    });
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost")
+        .with_alpn_protocols(&[b"x-amzn-mqtt-ca", b"http/1.1"]);
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+    assert_eq!(None, tls.negotiated_alpn());
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+    assert_eq!(Some(&b"x-amzn-mqtt-ca"[..]), tls.negotiated_alpn());
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_blocking_ip_address_without_sni_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    // The server_name extension is not sent for an address
+    let server = serve(server, |tls| {
+        echo(tls);
+        assert_eq!(None, tls.conn.server_name());
+    });
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new().with_server_name("127.0.0.1");
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_blocking_sni_override_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, server) = Pipe::new();
+    let server = serve(server, |tls| {
+        echo(tls);
+        assert_eq!(Some("device.example.com"), tls.conn.server_name());
+    });
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost")
+        .with_sni("device.example.com");
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    server.join().unwrap();
+}
+
+#[test]
+fn test_blocking_padding_over_pipe() {
+    use embedded_tls::blocking::*;
+
+    let (client, mut server) = Pipe::new();
+    // The ClientHello record is read before it is handed over to rustls
+    let server = std::thread::spawn(move || {
+        use std::io::Read;
+
+        let mut record = vec![0; 5];
+        server.read_exact(&mut record).unwrap();
+        let len = u16::from_be_bytes([record[3], record[4]]) as usize;
+        assert_eq!(512, len);
+        record.resize(5 + len, 0);
+        server.read_exact(&mut record[5..]).unwrap();
+
+        let mut connection = rustls::ServerConnection::new(Arc::new(server_config())).unwrap();
+        connection.read_tls(&mut &record[..]).unwrap();
+        connection.process_new_packets().unwrap();
+        echo(&mut rustls::StreamOwned::new(connection, server));
+    });
+
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost")
+        .with_padding(512);
+
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(client, &mut read_record_buffer, &mut write_record_buffer);
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    server.join().unwrap();
+}
@@ -0,0 +1,186 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::blocking::{Read, Write};
+use embedded_tls::blocking::*;
+use embedded_tls::testing::Pipe;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+/// Keeps the encoded tickets, and records whether a ticket was removed.
+#[derive(Default)]
+struct MemoryStore {
+    tickets: HashMap<String, Vec<u8>>,
+    removed: bool,
+}
+
+impl TicketStore<Aes128GcmSha256> for MemoryStore {
+    fn get(&mut self, server: &str) -> Option<SessionTicket<Aes128GcmSha256>> {
+        let encoded = self.tickets.get(server)?;
+        Some(SessionTicket::decode(encoded).unwrap())
+    }
+
+    fn put(&mut self, server: &str, ticket: SessionTicket<Aes128GcmSha256>) {
+        let mut encoded = vec![0; ticket.encoded_len()];
+        ticket.encode(&mut encoded).unwrap();
+        self.tickets.insert(server.to_string(), encoded);
+    }
+
+    fn remove(&mut self, server: &str) {
+        self.tickets.remove(server);
+        self.removed = true;
+    }
+}
+
+/// Runs an OpenSSL echo server for each pipe received, reporting whether the session of the
+/// connection was resumed.
+fn serve(pipes: mpsc::Receiver<Pipe>, resumed: mpsc::Sender<bool>) -> std::thread::JoinHandle<()> {
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    let acceptor = builder.build();
+
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+
+        for pipe in pipes {
+            let mut conn = acceptor.accept(pipe).unwrap();
+            let mut buf = [0; 4];
+            conn.read_exact(&mut buf).unwrap();
+            conn.write_all(&buf).unwrap();
+            resumed.send(conn.ssl().session_reused()).unwrap();
+        }
+    })
+}
+
+fn ping(
+    pipe: Pipe,
+    config: &TlsConfig<Aes128GcmSha256>,
+    store: Option<&mut MemoryStore>,
+) -> Option<SessionTicket<Aes128GcmSha256>> {
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(pipe, &mut read_record_buffer, &mut write_record_buffer);
+    if let Some(store) = store {
+        tls = tls.with_ticket_store("localhost", store);
+    }
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    tls.take_session_ticket()
+}
+
+#[test]
+fn test_resume_with_session_ticket() {
+    let (pipes, rx) = mpsc::channel();
+    let (tx, resumed) = mpsc::channel();
+    let server = serve(rx, tx);
+
+    let config = TlsConfig::new().with_server_name("localhost");
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let ticket = ping(client, &config, None).expect("no session ticket received");
+    assert!(!resumed.recv().unwrap());
+
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_session_ticket(&ticket);
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    ping(client, &config, None);
+    assert!(resumed.recv().unwrap());
+
+    drop(pipes);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_full_handshake_if_ticket_is_rejected() {
+    let (pipes, rx) = mpsc::channel();
+    let (tx, resumed) = mpsc::channel();
+    let server = serve(rx, tx);
+
+    let config = TlsConfig::new().with_server_name("localhost");
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let ticket = ping(client, &config, None).expect("no session ticket received");
+    assert!(!resumed.recv().unwrap());
+    drop(pipes);
+    server.join().unwrap();
+
+    // A new server does not know the ticket key of the previous one
+    let (pipes, rx) = mpsc::channel();
+    let (tx, resumed) = mpsc::channel();
+    let server = serve(rx, tx);
+
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_session_ticket(&ticket);
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    ping(client, &config, None);
+    assert!(!resumed.recv().unwrap());
+
+    drop(pipes);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_resume_with_ticket_store() {
+    let (pipes, rx) = mpsc::channel();
+    let (tx, resumed) = mpsc::channel();
+    let server = serve(rx, tx);
+
+    let config = TlsConfig::new().with_server_name("localhost");
+    let mut store = MemoryStore::default();
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    assert!(ping(client, &config, Some(&mut store)).is_none());
+    assert!(!resumed.recv().unwrap());
+    assert!(store.tickets.contains_key("localhost"));
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    ping(client, &config, Some(&mut store));
+    assert!(resumed.recv().unwrap());
+    assert!(!store.removed);
+
+    drop(pipes);
+    server.join().unwrap();
+
+    // The ticket rejected by a new server is removed, then replaced by a new one
+    let (pipes, rx) = mpsc::channel();
+    let (tx, resumed) = mpsc::channel();
+    let server = serve(rx, tx);
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    ping(client, &config, Some(&mut store));
+    assert!(!resumed.recv().unwrap());
+    assert!(store.removed);
+    assert!(store.tickets.contains_key("localhost"));
+
+    drop(pipes);
+    server.join().unwrap();
+}
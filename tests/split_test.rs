@@ -157,3 +157,63 @@ fn test_blocking_managed() {
         .map_err(|(_, e)| e)
         .expect("error closing session");
 }
+
+#[test]
+fn test_blocking_key_update() {
+    use embedded_tls::blocking::*;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    let addr = setup();
+    let pem = include_str!("data/ca-cert.pem");
+    let der = pem_parser::pem_to_der(pem);
+
+    let stream = TcpStream::connect(addr).expect("error connecting to server");
+
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let config = TlsConfig::new()
+        .with_ca(Certificate::X509(&der[..]))
+        .with_server_name("localhost");
+
+    let mut tls: TlsConnection<Clonable<TcpStream>, Aes128GcmSha256> = TlsConnection::new(
+        Clonable(Arc::new(stream)),
+        &mut read_record_buffer,
+        &mut write_record_buffer,
+    );
+
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    let (mut reader, mut writer) = tls.split();
+
+    // The reader rolls the read keys on the KeyUpdate of the server, while the writer keeps
+    // writing with its own keys
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut buffer = [0; 12];
+            reader.read_exact(&mut buffer).expect("Failed to read data");
+            assert_eq!(b"pingpingping", &buffer);
+        });
+        scope.spawn(|| {
+            for _ in 0..3 {
+                writer
+                    .request_key_update(true)
+                    .expect("Failed to update keys");
+                writer.write(b"ping").expect("Failed to write data");
+                writer.flush().expect("Failed to flush");
+            }
+        });
+    });
+
+    let mut tls = TlsConnection::unsplit(reader, writer);
+
+    tls.write_all(b"ping").expect("Failed to write data");
+    tls.flush().expect("Failed to flush");
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    tls.close()
+        .map_err(|(_, e)| e)
+        .expect("error closing session");
+}
@@ -0,0 +1,197 @@
+#![macro_use]
+#![allow(incomplete_features)]
+#![feature(async_fn_in_trait)]
+#![feature(impl_trait_projections)]
+use embedded_io::blocking::Read;
+use embedded_tls::blocking::*;
+use embedded_tls::testing::Pipe;
+use openssl::ssl;
+use rand::rngs::OsRng;
+use std::sync::mpsc;
+
+/// What the server saw of a connection.
+#[derive(Debug, PartialEq)]
+struct Received {
+    early_data: Vec<u8>,
+    resumed: bool,
+}
+
+/// Runs an OpenSSL server accepting up to `max_early_data` bytes of early data for each pipe
+/// received. The server echoes the first 4 bytes of the connection, whether they were sent as
+/// early data or not.
+fn serve(
+    max_early_data: u32,
+    pipes: mpsc::Receiver<Pipe>,
+    received: mpsc::Sender<Received>,
+) -> std::thread::JoinHandle<()> {
+    let mut builder =
+        ssl::SslAcceptor::mozilla_intermediate_v5(ssl::SslMethod::tls_server()).unwrap();
+    builder
+        .set_private_key_file("tests/data/server-key.pem", ssl::SslFiletype::PEM)
+        .unwrap();
+    builder
+        .set_certificate_chain_file("tests/data/server-cert.pem")
+        .unwrap();
+    builder
+        .set_min_proto_version(Some(ssl::SslVersion::TLS1_3))
+        .unwrap();
+    builder.set_max_early_data(max_early_data).unwrap();
+    let acceptor = builder.build();
+
+    std::thread::spawn(move || {
+        use std::io::{Read, Write};
+
+        for pipe in pipes {
+            let ssl = ssl::Ssl::new(acceptor.context()).unwrap();
+            let mut conn = ssl::SslStream::new(ssl, pipe).unwrap();
+
+            let mut early_data = vec![0; 1024];
+            let mut len = 0;
+            loop {
+                match conn.read_early_data(&mut early_data[len..]).unwrap() {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            early_data.truncate(len);
+            conn.accept().unwrap();
+
+            let mut buf = [0; 4];
+            let len = usize::min(early_data.len(), buf.len());
+            buf[..len].copy_from_slice(&early_data[..len]);
+            conn.read_exact(&mut buf[len..]).unwrap();
+            conn.write_all(&buf).unwrap();
+            received
+                .send(Received {
+                    early_data,
+                    resumed: conn.ssl().session_reused(),
+                })
+                .unwrap();
+        }
+    })
+}
+
+/// Opens a connection with `ping` as early data, and returns the ticket received.
+fn ping(pipe: Pipe, config: TlsConfig<Aes128GcmSha256>) -> Option<SessionTicket<Aes128GcmSha256>> {
+    let mut read_record_buffer = [0; 16384];
+    let mut write_record_buffer = [0; 16384];
+    let mut tls: TlsConnection<Pipe, Aes128GcmSha256> =
+        TlsConnection::new(pipe, &mut read_record_buffer, &mut write_record_buffer);
+
+    let config = config.with_early_data(b"ping");
+    tls.open::<OsRng, NoVerify>(TlsContext::new(&config, &mut OsRng))
+        .expect("error establishing TLS connection");
+
+    let mut buffer = [0; 4];
+    tls.read_exact(&mut buffer).expect("Failed to read data");
+    assert_eq!(b"ping", &buffer);
+
+    tls.take_session_ticket()
+}
+
+#[test]
+fn test_early_data() {
+    let (pipes, rx) = mpsc::channel();
+    let (tx, received) = mpsc::channel();
+    let server = serve(1024, rx, tx);
+
+    // Without a ticket, the data is sent after the handshake
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let config = TlsConfig::new().with_server_name("localhost");
+    let ticket = ping(client, config).expect("no session ticket received");
+    assert_eq!(
+        Received {
+            early_data: vec![],
+            resumed: false
+        },
+        received.recv().unwrap()
+    );
+    assert_eq!(1024, ticket.max_early_data_size());
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_session_ticket(&ticket);
+    ping(client, config);
+    assert_eq!(
+        Received {
+            early_data: b"ping".to_vec(),
+            resumed: true
+        },
+        received.recv().unwrap()
+    );
+
+    drop(pipes);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_early_data_sent_again_if_rejected() {
+    let (pipes, rx) = mpsc::channel();
+    let (tx, received) = mpsc::channel();
+    let server = serve(1024, rx, tx);
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let config = TlsConfig::new().with_server_name("localhost");
+    let ticket = ping(client, config).expect("no session ticket received");
+    received.recv().unwrap();
+    drop(pipes);
+    server.join().unwrap();
+
+    // A new server does not know the ticket key of the previous one
+    let (pipes, rx) = mpsc::channel();
+    let (tx, received) = mpsc::channel();
+    let server = serve(1024, rx, tx);
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_session_ticket(&ticket);
+    ping(client, config);
+    assert_eq!(
+        Received {
+            early_data: vec![],
+            resumed: false
+        },
+        received.recv().unwrap()
+    );
+
+    drop(pipes);
+    server.join().unwrap();
+}
+
+#[test]
+fn test_early_data_capped_by_ticket() {
+    let (pipes, rx) = mpsc::channel();
+    let (tx, received) = mpsc::channel();
+    let server = serve(2, rx, tx);
+
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let config = TlsConfig::new().with_server_name("localhost");
+    let ticket = ping(client, config).expect("no session ticket received");
+    received.recv().unwrap();
+    assert_eq!(2, ticket.max_early_data_size());
+
+    // Only the beginning of the data is sent as early data, the rest after the handshake
+    let (client, server_end) = Pipe::new();
+    pipes.send(server_end).unwrap();
+    let config = TlsConfig::new()
+        .with_server_name("localhost")
+        .with_session_ticket(&ticket);
+    ping(client, config);
+    assert_eq!(
+        Received {
+            early_data: b"pi".to_vec(),
+            resumed: true
+        },
+        received.recv().unwrap()
+    );
+
+    drop(pipes);
+    server.join().unwrap();
+}